@@ -0,0 +1,8 @@
+// The embed-ui feature builds in the ui from UI_BUNDLE_DIR, which has the same layout as the
+// directory the server otherwise serves from: index.html, embed.html, assets/ and chess-ui.wasm.
+fn main() {
+    println!("cargo:rerun-if-env-changed=UI_BUNDLE_DIR");
+    if std::env::var("UI_BUNDLE_DIR").is_err() {
+        println!("cargo:rustc-env=UI_BUNDLE_DIR=/srv/chess");
+    }
+}
@@ -0,0 +1,443 @@
+// Cloud analysis: the browser ui can only search a few moves deep without freezing the page, so it
+// can ask the server to analyze a position instead. The server runs the desktop ui's engine
+// (ANALYSIS_ENGINE, over UCI with `chess-ui --uci`, see ui/src/uci.rs) as a separate process for
+// each request, at most ANALYSIS_WORKERS at once. Requests wait for a free worker in turn, and once
+// ANALYSIS_QUEUE of them are waiting or running, new ones are turned away.
+//
+// POST /api/analyze with {"fen": "...", "depth": 8, "time_ms": 5000}, where the limits are
+// optional and capped at ANALYSIS_MAX_DEPTH and ANALYSIS_MAX_SECS, answers with the analysis' ID and
+// the limits it got: {"id": "...", "depth": 8, "time_ms": 5000}. The results are then streamed over
+// the websocket /api/analyze/<id>, as a message for each depth the engine completes:
+//   {"info": {"depth": 4, "seldepth": 9, "nodes": 5120, "nps": 20480, "time_ms": 250,
+//             "score": {"cp": 35}, "pv": ["e2e4", "e7e5"]}}
+// where the score is from the point of view of the player to move, either in centipawns or
+// {"mate": n} in n moves, negative if they're mated. The last message is the engine's move, as
+// {"bestmove": "e2e4"}, or {"error": "..."} if the analysis failed. Results not picked up within
+// RESULT_TTL are dropped.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{ChildStdout, Command},
+    sync::{mpsc, Semaphore},
+};
+use tracing::{info, warn, Instrument};
+use uuid::Uuid;
+use warp::{http, ws::Message, Filter, Reply};
+
+use crate::Server;
+
+const DEFAULT_WORKERS: usize = 2;
+const DEFAULT_QUEUE: usize = 16;
+// The engine's own limit.
+const DEFAULT_MAX_DEPTH: u32 = 8;
+const DEFAULT_MAX_SECS: u64 = 10;
+// How long results wait for their websocket once the analysis is done.
+const RESULT_TTL: Duration = Duration::from_secs(60);
+// How much longer than its time limit the engine gets before it's killed.
+const GRACE: Duration = Duration::from_secs(5);
+// Longer than any FEN.
+const MAX_FEN_LEN: usize = 128;
+
+pub struct CloudAnalysis {
+    // The desktop ui binary.
+    engine: PathBuf,
+    max_depth: u32,
+    max_time: Duration,
+    max_jobs: usize,
+    // A permit for each engine that can run at once.
+    workers: Semaphore,
+    // Analyses waiting or running.
+    jobs: AtomicUsize,
+    // Each analysis' results, until its websocket takes them.
+    results: Mutex<HashMap<Uuid, mpsc::UnboundedReceiver<Value>>>,
+}
+
+// A validated request.
+struct Job {
+    fen: String,
+    depth: u32,
+    time: Duration,
+}
+
+impl CloudAnalysis {
+    pub fn new(
+        engine: PathBuf,
+        workers: usize,
+        max_jobs: usize,
+        max_depth: u32,
+        max_time: Duration,
+    ) -> Self {
+        CloudAnalysis {
+            engine,
+            max_depth,
+            max_time,
+            max_jobs,
+            workers: Semaphore::new(workers),
+            jobs: AtomicUsize::new(0),
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Runs ANALYSIS_ENGINE, with the limits in ANALYSIS_WORKERS (default 2), ANALYSIS_QUEUE
+    // (default 16), ANALYSIS_MAX_DEPTH (default 8) and ANALYSIS_MAX_SECS (default 10). None if
+    // ANALYSIS_ENGINE isn't set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let engine = match std::env::var("ANALYSIS_ENGINE") {
+            Ok(e) => PathBuf::from(e),
+            Err(_) => return Ok(None),
+        };
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+            match std::env::var(name) {
+                Ok(v) => v.parse().map_err(|_| format!("invalid {}: {}", name, v)),
+                Err(_) => Ok(default),
+            }
+        }
+        Ok(Some(CloudAnalysis::new(
+            engine,
+            var("ANALYSIS_WORKERS", DEFAULT_WORKERS)?,
+            var("ANALYSIS_QUEUE", DEFAULT_QUEUE)?,
+            var("ANALYSIS_MAX_DEPTH", DEFAULT_MAX_DEPTH)?,
+            Duration::from_secs(var("ANALYSIS_MAX_SECS", DEFAULT_MAX_SECS)?),
+        )))
+    }
+
+    fn job(&self, request: &Value) -> Result<Job, String> {
+        let fen = request["fen"]
+            .as_str()
+            .map(str::trim)
+            .filter(|f| !f.is_empty() && f.len() <= MAX_FEN_LEN)
+            .filter(|f| f.chars().all(|c| c.is_ascii_graphic() || c == ' '))
+            .ok_or("Invalid FEN")?;
+        let limit = |name: &str, max: u64| match &request[name] {
+            Value::Null => Ok(max),
+            v => v
+                .as_u64()
+                .filter(|&n| n > 0)
+                .map(|n| n.min(max))
+                .ok_or_else(|| format!("Invalid {}", name)),
+        };
+        Ok(Job {
+            fen: fen.to_string(),
+            depth: limit("depth", self.max_depth as u64)? as u32,
+            time: Duration::from_millis(limit("time_ms", self.max_time.as_millis() as u64)?),
+        })
+    }
+
+    // Queues the analysis the request asks for, answering as the API does.
+    fn submit(self: &Arc<Self>, request: &Value) -> Result<Value, (http::StatusCode, String)> {
+        let job = self
+            .job(request)
+            .map_err(|e| (http::StatusCode::BAD_REQUEST, e))?;
+        let queued = self
+            .jobs
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < self.max_jobs).then_some(n + 1)
+            });
+        if queued.is_err() {
+            return Err((
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                "Too many analyses, try again later".to_string(),
+            ));
+        }
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.results.lock().unwrap().insert(id, rx);
+        let answer = json!({"id": id.to_string(), "depth": job.depth,
+                            "time_ms": job.time.as_millis() as u64});
+        info!(fen = job.fen, depth = job.depth, "analysis queued");
+        let cloud = self.clone();
+        tokio::spawn(
+            async move {
+                {
+                    let _permit = cloud.workers.acquire().await.unwrap();
+                    let result = tokio::time::timeout(job.time + GRACE, cloud.run(&job, &tx))
+                        .await
+                        .unwrap_or_else(|_| Err("The engine took too long".to_string()));
+                    if let Err(e) = result {
+                        warn!("analysis failed: {}", e);
+                        let _ = tx.send(json!({ "error": e }));
+                    }
+                }
+                cloud.jobs.fetch_sub(1, Ordering::SeqCst);
+                drop(tx);
+                tokio::time::sleep(RESULT_TTL).await;
+                cloud.results.lock().unwrap().remove(&id);
+            }
+            .instrument(tracing::info_span!("analysis", %id)),
+        );
+        Ok(answer)
+    }
+
+    // Analyzes the position with the engine, sending what it finds to tx.
+    async fn run(&self, job: &Job, tx: &mpsc::UnboundedSender<Value>) -> Result<(), String> {
+        let mut child = Command::new(&self.engine)
+            .arg("--uci")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("couldn't start the engine: {}", e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let io = |e: std::io::Error| e.to_string();
+        let position = format!("position fen {}\nisready\n", job.fen);
+        stdin.write_all(position.as_bytes()).await.map_err(io)?;
+        // The engine says what's wrong with the position before it's ready.
+        loop {
+            let line = next_line(&mut lines).await?;
+            if let Some(e) = line.strip_prefix("info string ") {
+                return Err(e.to_string());
+            }
+            if line == "readyok" {
+                break;
+            }
+        }
+        let go = format!("go depth {} movetime {}\n", job.depth, job.time.as_millis());
+        stdin.write_all(go.as_bytes()).await.map_err(io)?;
+        loop {
+            let line = next_line(&mut lines).await?;
+            let message = if let Some(m) = line.strip_prefix("bestmove ") {
+                json!({"bestmove": m.trim()})
+            } else if let Some(info) = parse_info(&line) {
+                json!({ "info": info })
+            } else {
+                continue;
+            };
+            let done = message["bestmove"].is_string();
+            // Nobody's listening anymore, so stop.
+            if tx.send(message).is_err() || done {
+                break;
+            }
+        }
+        let _ = stdin.write_all(b"quit\n").await;
+        Ok(())
+    }
+
+    // The results of the analysis, if they're still there and nobody's taken them yet.
+    fn take_results(&self, id: Uuid) -> Option<mpsc::UnboundedReceiver<Value>> {
+        self.results.lock().unwrap().remove(&id)
+    }
+}
+
+async fn next_line(lines: &mut Lines<BufReader<ChildStdout>>) -> Result<String, String> {
+    match lines.next_line().await {
+        Ok(Some(line)) => Ok(line),
+        Ok(None) => Err("the engine quit".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// A UCI info line with a score, as in the API. None for other info lines, e.g. strings.
+fn parse_info(line: &str) -> Option<Value> {
+    let mut words = line.strip_prefix("info ")?.split_whitespace();
+    let mut info = json!({});
+    while let Some(word) = words.next() {
+        match word {
+            "depth" | "seldepth" | "nodes" | "nps" => {
+                info[word] = json!(words.next()?.parse::<u64>().ok()?);
+            }
+            "time" => info["time_ms"] = json!(words.next()?.parse::<u64>().ok()?),
+            "score" => {
+                let kind = words.next().filter(|&k| k == "cp" || k == "mate")?;
+                info["score"] = json!({ kind: words.next()?.parse::<i64>().ok()? });
+            }
+            "pv" => {
+                info["pv"] = json!(words.by_ref().collect::<Vec<_>>());
+            }
+            _ => {}
+        }
+    }
+    info["score"].is_object().then_some(info)
+}
+
+// POST /api/analyze and the websocket /api/analyze/<id>, as described above.
+pub fn routes(
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let cloud = warp::any().map(move || server.cloud_analysis.clone());
+    let off = || {
+        warp::reply::with_status("Cloud analysis is off", http::StatusCode::NOT_FOUND)
+            .into_response()
+    };
+    let submit = warp::path!("api" / "analyze")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .and(cloud.clone())
+        .map(move |request: Value, cloud: Option<Arc<CloudAnalysis>>| {
+            let Some(cloud) = cloud else {
+                return off();
+            };
+            match cloud.submit(&request) {
+                Ok(answer) => {
+                    warp::reply::with_status(warp::reply::json(&answer), http::StatusCode::ACCEPTED)
+                        .into_response()
+                }
+                Err((status, e)) => warp::reply::with_status(e, status).into_response(),
+            }
+        });
+    let results = warp::path!("api" / "analyze" / Uuid)
+        .and(warp::ws())
+        .and(cloud)
+        .map(
+            move |id: Uuid, ws: warp::ws::Ws, cloud: Option<Arc<CloudAnalysis>>| {
+                let Some(cloud) = cloud else {
+                    return off();
+                };
+                match cloud.take_results(id) {
+                    Some(rx) => ws
+                        .on_upgrade(move |websocket| stream_results(websocket, rx))
+                        .into_response(),
+                    None => {
+                        warp::reply::with_status("No such analysis", http::StatusCode::NOT_FOUND)
+                            .into_response()
+                    }
+                }
+            },
+        );
+    submit.or(results).unify()
+}
+
+async fn stream_results(ws: warp::ws::WebSocket, mut rx: mpsc::UnboundedReceiver<Value>) {
+    let (mut ws_tx, _) = ws.split();
+    while let Some(message) = rx.recv().await {
+        if ws_tx
+            .send(Message::text(message.to_string()))
+            .await
+            .is_err()
+        {
+            // Dropping rx tells the analysis to stop.
+            return;
+        }
+    }
+    let _ = ws_tx.send(Message::close()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, os::unix::fs::PermissionsExt};
+
+    use tokio::time::timeout;
+    use tokio_tungstenite::connect_async;
+
+    use super::*;
+
+    // Stands in for chess-ui --uci. A FEN starting with "slow" takes a while.
+    const ENGINE: &str = r#"#!/bin/sh
+while read line; do
+    case "$line" in
+        "position fen bad"*) echo "info string Invalid FEN: bad" ;;
+        "position fen slow"*) sleep 1 ;;
+        isready) echo readyok ;;
+        go*)
+            echo "info string searching"
+            echo "info depth 1 seldepth 2 nodes 20 nps 2000 time 10 score cp 35 pv e2e4 e7e5"
+            echo "info depth 2 seldepth 3 nodes 90 nps 3000 time 30 score mate -1 pv d2d4"
+            echo "bestmove e2e4" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    async fn post(
+        routes: &(impl Filter<Extract = (warp::reply::Response,)> + Clone + Send + Sync + 'static),
+        request: Value,
+    ) -> (u16, Value) {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/analyze")
+            .json(&request)
+            .reply(routes)
+            .await;
+        let body = serde_json::from_slice(res.body()).unwrap_or(Value::Null);
+        (res.status().as_u16(), body)
+    }
+
+    // Everything the websocket for the analysis sends.
+    async fn results(addr: SocketAddr, id: &Value) -> Vec<Value> {
+        let url = format!("ws://{}/api/analyze/{}", addr, id.as_str().unwrap());
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        let mut messages = Vec::new();
+        while let Some(Ok(m)) = timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+            if m.is_text() {
+                messages.push(serde_json::from_str(m.to_text().unwrap()).unwrap());
+            }
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_cloud_analysis() {
+        let dir = std::env::temp_dir().join(format!("chess-cloud-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = dir.join("engine.sh");
+        std::fs::write(&engine, ENGINE).unwrap();
+        std::fs::set_permissions(&engine, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let cloud = CloudAnalysis::new(engine, 1, 1, 8, Duration::from_secs(10));
+        let server = Server {
+            cloud_analysis: Some(Arc::new(cloud)),
+            ..Default::default()
+        };
+        let routes = routes(server);
+        let (addr, serve) = warp::serve(routes.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(serve);
+
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let (status, answer) =
+            post(&routes, json!({"fen": fen, "depth": 20, "time_ms": 500})).await;
+        assert_eq!(status, 202);
+        assert_eq!(
+            (answer["depth"].as_u64(), answer["time_ms"].as_u64()),
+            (Some(8), Some(500))
+        );
+        assert_eq!(
+            results(addr, &answer["id"]).await,
+            [
+                json!({"info": {"depth": 1, "seldepth": 2, "nodes": 20, "nps": 2000, "time_ms": 10,
+                                "score": {"cp": 35}, "pv": ["e2e4", "e7e5"]}}),
+                json!({"info": {"depth": 2, "seldepth": 3, "nodes": 90, "nps": 3000, "time_ms": 30,
+                                "score": {"mate": -1}, "pv": ["d2d4"]}}),
+                json!({"bestmove": "e2e4"}),
+            ]
+        );
+        // The results are gone once taken.
+        let url = format!(
+            "ws://{}/api/analyze/{}",
+            addr,
+            answer["id"].as_str().unwrap()
+        );
+        assert!(connect_async(url).await.is_err());
+
+        let (_, answer) = post(&routes, json!({"fen": "bad"})).await;
+        assert_eq!(
+            results(addr, &answer["id"]).await,
+            [json!({"error": "Invalid FEN: bad"})]
+        );
+        assert_eq!(post(&routes, json!({"fen": "x\ny"})).await.0, 400);
+        assert_eq!(post(&routes, json!({"fen": fen, "depth": 0})).await.0, 400);
+
+        // One analysis at a time.
+        let (status, slow) = post(&routes, json!({"fen": "slow"})).await;
+        assert_eq!(status, 202);
+        assert_eq!(post(&routes, json!({"fen": fen})).await.0, 503);
+        assert_eq!(results(addr, &slow["id"]).await.len(), 3);
+
+        let off = super::routes(Server::default());
+        assert_eq!(post(&off, json!({"fen": fen})).await.0, 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+// The opening explorer: what was played in each position of the finished games in the move log
+// (see movelog.rs), and how those games ended. The server doesn't know the rules, so positions are
+// told apart by the hash each player sent with their move (see ui/src/protocol.rs): a move was
+// played in the position the previous move's hash describes, and the first move in the starting
+// position, which has the key "". Only games of standard chess, without a rule set, are counted,
+// and only once their result is in. Aborted games and moves sent without a hash are left out.
+//
+// GET /api/explorer?hash=<key> answers with the moves played in that position, most played first:
+//   {"hash": "...", "games": 12, "moves": [{"uci": "e2e4", "games": 8, "white": 4, "draws": 2,
+//    "black": 2}, ...]}
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::RwLock,
+};
+
+use serde_json::{json, Value};
+use tracing::info;
+use uuid::Uuid;
+use warp::{http, Filter, Reply};
+
+use crate::{movelog::MoveLog, Server};
+
+// How the games a move was played in ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tally {
+    white: u32,
+    draws: u32,
+    black: u32,
+}
+
+impl Tally {
+    fn games(&self) -> u32 {
+        self.white + self.draws + self.black
+    }
+}
+
+#[derive(Default)]
+pub struct Explorer {
+    // Position key to the moves played there, in long algebraic notation.
+    positions: RwLock<HashMap<String, HashMap<String, Tally>>>,
+}
+
+impl Explorer {
+    // Indexes every finished game in the log.
+    pub fn load(log: &MoveLog) -> io::Result<Self> {
+        let explorer = Explorer::default();
+        let mut games = 0;
+        for entry in std::fs::read_dir(log.dir())? {
+            let path = entry?.path();
+            let game_id = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".ndjson"))
+                .and_then(|id| Uuid::parse_str(id).ok());
+            if let Some(game_id) = game_id {
+                if explorer.add(&log.read(game_id)?) {
+                    games += 1;
+                }
+            }
+        }
+        info!(games, "indexed games for the opening explorer");
+        Ok(explorer)
+    }
+
+    // Call once the game's result has been logged.
+    pub fn add_game(&self, log: &MoveLog, game_id: Uuid) -> io::Result<()> {
+        self.add(&log.read(game_id)?);
+        Ok(())
+    }
+
+    // Counts the game in log, if it's a finished game of standard chess. Returns whether it was.
+    fn add(&self, log: &str) -> bool {
+        let Some((moves, result)) = game_moves(log) else {
+            return false;
+        };
+        let mut positions = self.positions.write().unwrap();
+        // A position can come up more than once in a game, but the game only counts once.
+        for (key, m) in moves.into_iter().collect::<HashSet<_>>() {
+            let tally = positions.entry(key).or_default().entry(m).or_default();
+            match result {
+                "1-0" => tally.white += 1,
+                "0-1" => tally.black += 1,
+                _ => tally.draws += 1,
+            }
+        }
+        true
+    }
+
+    // The moves played in the position with this key, as in the API.
+    pub fn lookup(&self, key: &str) -> Value {
+        let positions = self.positions.read().unwrap();
+        let mut moves: Vec<(&String, &Tally)> = positions
+            .get(key)
+            .map(|moves| moves.iter().collect())
+            .unwrap_or_default();
+        moves.sort_by(|a, b| b.1.games().cmp(&a.1.games()).then(a.0.cmp(b.0)));
+        let games: u32 = moves.iter().map(|(_, t)| t.games()).sum();
+        let moves: Vec<Value> = moves
+            .into_iter()
+            .map(|(m, t)| {
+                json!({"uci": m, "games": t.games(), "white": t.white, "draws": t.draws,
+                       "black": t.black})
+            })
+            .collect();
+        json!({"hash": key, "games": games, "moves": moves})
+    }
+}
+
+// The moves of a game as (position key, move), with its result, if it's a finished game of
+// standard chess.
+fn game_moves(log: &str) -> Option<(Vec<(String, String)>, &'static str)> {
+    // Each move, with the hash of the position after it if the player sent one.
+    let mut moves: Vec<(String, Option<String>)> = Vec::new();
+    let mut result = None;
+    for line in log.lines() {
+        let Ok(data) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if data["game_id"].is_string() && !data["ruleset"].is_null() {
+            return None;
+        }
+        if let Some(r) = data["result"].as_str() {
+            result = ["1-0", "0-1", "1/2-1/2"]
+                .into_iter()
+                .find(|&known| known == r);
+        } else if let Some(ply) = data["takeback"].as_u64() {
+            moves.truncate(ply.saturating_sub(1) as usize);
+        } else if let Some(m) = long_algebraic(&data) {
+            moves.push((m, data["hash"].as_str().map(str::to_string)));
+        }
+    }
+    let mut played = Vec::new();
+    let mut key = Some(String::new());
+    for (m, hash) in moves {
+        if let Some(key) = key {
+            played.push((key, m));
+        }
+        key = hash;
+    }
+    Some((played, result?))
+}
+
+// E.g. "e2e4", or "e7e8q" for a promotion.
+fn long_algebraic(m: &Value) -> Option<String> {
+    let square = |row: &str, col: &str| -> Option<String> {
+        let (r, c) = (
+            m[row].as_u64()?,
+            m[col].as_u64().filter(|c| (1..=8).contains(c))?,
+        );
+        Some(format!("{}{}", (b'a' + c as u8 - 1) as char, r))
+    };
+    let promotion = m["promotion"].as_str().unwrap_or_default().to_lowercase();
+    Some(format!(
+        "{}{}{}",
+        square("src_row", "src_col")?,
+        square("dst_row", "dst_col")?,
+        promotion
+    ))
+}
+
+// GET /api/explorer?hash=<key>, where a missing or empty key is the starting position.
+pub fn routes(
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path!("api" / "explorer")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |query: HashMap<String, String>| match &server.explorer {
+                Some(explorer) => {
+                    let key = query.get("hash").map_or("", String::as_str);
+                    warp::reply::json(&explorer.lookup(key)).into_response()
+                }
+                None => warp::reply::with_status(
+                    "The opening explorer needs the move log",
+                    http::StatusCode::NOT_FOUND,
+                )
+                .into_response(),
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_explorer() {
+        let dir = std::env::temp_dir().join(format!("chess-explorer-{}", Uuid::new_v4()));
+        let log = MoveLog::new(dir.clone(), 1 << 20).unwrap();
+        let mv = |src: (u8, u8), dst: (u8, u8), hash: &str| {
+            json!({"src_row": src.0, "src_col": src.1, "dst_row": dst.0, "dst_col": dst.1,
+                   "hash": hash})
+        };
+        let e4 = mv((2, 5), (4, 5), "e4");
+        let d4 = mv((2, 4), (4, 4), "d4");
+        let e5 = mv((7, 5), (5, 5), "e4e5");
+        let play = |ruleset: Option<&str>, moves: &[&Value], result: Option<&str>| {
+            let game_id = Uuid::new_v4();
+            log.start_game(game_id, ruleset).unwrap();
+            for &m in moves {
+                log.record(game_id, Uuid::new_v4(), m.clone()).unwrap();
+            }
+            if let Some(result) = result {
+                let result = json!({"result": result, "reason": "checkmate"});
+                log.record(game_id, Uuid::new_v4(), result).unwrap();
+            }
+            game_id
+        };
+        play(None, &[&e4, &e5], Some("1-0"));
+        // d4 was taken back.
+        let takeback = json!({"takeback": 1});
+        play(None, &[&d4, &takeback, &e4, &e5], Some("1/2-1/2"));
+        play(None, &[&d4], Some("0-1"));
+        // Not counted: unfinished, aborted, and another game's rules.
+        play(None, &[&d4], None);
+        play(None, &[&d4], Some("*"));
+        play(Some(r#"{"name": "Test"}"#), &[&d4], Some("1-0"));
+
+        let explorer = Explorer::load(&log).unwrap();
+        assert_eq!(
+            explorer.lookup(""),
+            json!({"hash": "", "games": 3, "moves": [
+                {"uci": "e2e4", "games": 2, "white": 1, "draws": 1, "black": 0},
+                {"uci": "d2d4", "games": 1, "white": 0, "draws": 0, "black": 1},
+            ]})
+        );
+        assert_eq!(explorer.lookup("e4")["moves"][0]["uci"], "e7e5");
+        assert_eq!(explorer.lookup("e4e5")["games"], 0);
+
+        let game_id = play(None, &[&e4], Some("0-1"));
+        explorer.add_game(&log, game_id).unwrap();
+        assert_eq!(explorer.lookup("")["moves"][0]["black"], 1);
+
+        let server = Server {
+            explorer: Some(Arc::new(explorer)),
+            ..Default::default()
+        };
+        let on = routes(server);
+        let res = warp::test::request()
+            .path("/api/explorer?hash=e4")
+            .reply(&on)
+            .await;
+        assert_eq!(res.status(), 200);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["moves"][0]["games"], 2);
+        let off = routes(Server::default());
+        let res = warp::test::request()
+            .path("/api/explorer")
+            .reply(&off)
+            .await;
+        assert_eq!(res.status(), 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+// Fair play checks of finished games, with the server built with the fair-play feature. Once a
+// game's result is in, its move log (see movelog.rs) is run through the desktop ui's analysis
+// (chess-ui --fair-play, see ui/src/fairplay.rs), which compares each player's moves with the
+// engine's and looks at how long they took. The report is kept next to the log, as
+// <game_id>.fairplay.json, and GET /admin/fair-play lists the players flagged as suspicious in
+// any of them. Like changing the log filter, that's only allowed from the server's machine.
+
+use std::{
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use uuid::Uuid;
+use warp::{http, Filter, Reply};
+
+use crate::{listen, Server};
+
+const DEFAULT_DEPTH: u32 = 3;
+
+pub struct FairPlay {
+    // The desktop ui binary.
+    analyzer: PathBuf,
+    // How deep the engine searches each position. Deeper is slower but catches stronger engines.
+    depth: u32,
+    // Where the move logs are, and the reports go.
+    dir: PathBuf,
+}
+
+impl FairPlay {
+    pub fn new(analyzer: PathBuf, depth: u32, dir: PathBuf) -> Self {
+        FairPlay {
+            analyzer,
+            depth,
+            dir,
+        }
+    }
+
+    // Runs FAIR_PLAY_ANALYZER at FAIR_PLAY_DEPTH (default 3) on the move logs in dir. None if
+    // FAIR_PLAY_ANALYZER isn't set.
+    pub fn from_env(dir: &Path) -> Result<Option<Self>, String> {
+        let analyzer = match std::env::var("FAIR_PLAY_ANALYZER") {
+            Ok(a) => PathBuf::from(a),
+            Err(_) => return Ok(None),
+        };
+        let depth = match std::env::var("FAIR_PLAY_DEPTH") {
+            Ok(d) => d
+                .parse()
+                .map_err(|_| format!("invalid FAIR_PLAY_DEPTH: {}", d))?,
+            Err(_) => DEFAULT_DEPTH,
+        };
+        Ok(Some(FairPlay::new(analyzer, depth, dir.to_path_buf())))
+    }
+
+    fn report_path(&self, game_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.fairplay.json", game_id))
+    }
+
+    // Analyzes the game's move log and saves the report, returning it. The engine takes a while,
+    // so it runs on a blocking thread.
+    pub async fn check(&self, game_id: Uuid) -> io::Result<Value> {
+        let log = self.dir.join(format!("{}.ndjson", game_id));
+        let mut command = Command::new(&self.analyzer);
+        command
+            .arg("--fair-play")
+            .arg(log)
+            .arg("--depth")
+            .arg(self.depth.to_string());
+        let output = tokio::task::spawn_blocking(move || command.output()).await??;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "analyzer failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let report: Value = serde_json::from_slice(&output.stdout)?;
+        std::fs::write(self.report_path(game_id), report.to_string())?;
+        let flagged = suspicious(&report).count();
+        if flagged > 0 {
+            warn!(flagged, "suspicious play");
+        } else {
+            info!("fair play check passed");
+        }
+        Ok(report)
+    }
+
+    // The suspicious players in every report, each with the game it's from.
+    fn flagged(&self) -> io::Result<Vec<Value>> {
+        let mut flagged = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let game_id = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => match name.strip_suffix(".fairplay.json") {
+                    Some(game_id) => game_id.to_string(),
+                    None => continue,
+                },
+                None => continue,
+            };
+            let report: Value = match std::fs::read(&path).map(|r| serde_json::from_slice(&r)) {
+                Ok(Ok(report)) => report,
+                _ => {
+                    warn!("couldn't read {}", path.display());
+                    continue;
+                }
+            };
+            for player in suspicious(&report) {
+                let mut player = player.clone();
+                player["game_id"] = json!(game_id);
+                flagged.push(player);
+            }
+        }
+        Ok(flagged)
+    }
+}
+
+fn suspicious(report: &Value) -> impl Iterator<Item = &Value> {
+    report["players"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["suspicious"] == true)
+}
+
+// GET /admin/fair-play: the flagged players, as a JSON array. Only allowed from the machine the
+// server runs on.
+pub fn routes(
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "fair-play")
+        .and(warp::get())
+        .and(listen::client(server.trust_proxy))
+        .map(move |client: Option<IpAddr>| {
+            if !listen::is_local(client) {
+                return warp::reply::with_status("Forbidden", http::StatusCode::FORBIDDEN)
+                    .into_response();
+            }
+            let fair_play = match &server.fair_play {
+                Some(f) => f,
+                None => {
+                    return warp::reply::with_status(
+                        "Fair play checks are off",
+                        http::StatusCode::NOT_FOUND,
+                    )
+                    .into_response()
+                }
+            };
+            match fair_play.flagged() {
+                Ok(flagged) => warp::reply::json(&flagged).into_response(),
+                Err(e) => {
+                    warp::reply::with_status(e.to_string(), http::StatusCode::INTERNAL_SERVER_ERROR)
+                        .into_response()
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, os::unix::fs::PermissionsExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_and_report() {
+        let dir = std::env::temp_dir().join(format!("chess-fair-play-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Stands in for chess-ui, checking it's asked about the right log.
+        let analyzer = dir.join("analyzer.sh");
+        let report = json!({"players": [
+            {"player_id": "a", "match_rate": 0.95, "suspicious": true},
+            {"player_id": "b", "match_rate": 0.4, "suspicious": false},
+        ]});
+        std::fs::write(
+            &analyzer,
+            format!(
+                "#!/bin/sh\n[ \"$1 $3 $4\" = '--fair-play --depth 2' ] && [ -f \"$2\" ] && echo '{}'\n",
+                report
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&analyzer, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let fair_play = FairPlay::new(analyzer, 2, dir.clone());
+        let game_id = Uuid::new_v4();
+        assert!(fair_play.check(game_id).await.is_err());
+        std::fs::write(dir.join(format!("{}.ndjson", game_id)), "").unwrap();
+        assert_eq!(fair_play.check(game_id).await.unwrap(), report);
+
+        let server = Server {
+            fair_play: Some(std::sync::Arc::new(fair_play)),
+            ..Default::default()
+        };
+        let routes = routes(server);
+        let get = |ip: [u8; 4]| {
+            warp::test::request()
+                .path("/admin/fair-play")
+                .remote_addr(SocketAddr::from((ip, 40000)))
+                .reply(&routes)
+        };
+        let res = get([127, 0, 0, 1]).await;
+        assert_eq!(res.status(), 200);
+        let flagged: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            flagged,
+            json!([{"player_id": "a", "match_rate": 0.95, "suspicious": true, "game_id": game_id.to_string()}])
+        );
+        assert_eq!(get([192, 168, 0, 2]).await.status(), 403);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+// Where the server listens, and who's on the other end. LISTEN is a comma-separated list of
+// addresses to serve on, 0.0.0.0:58597 by default:
+//   LISTEN=0.0.0.0:58597,[::1]:58597   IPv4 and IPv6
+//   LISTEN=unix:/run/chess.sock        a Unix socket, e.g. for a reverse proxy on the same machine
+// Behind a reverse proxy, every request seems to come from the proxy. With TRUST_PROXY set, the
+// server takes the client's address from the last X-Forwarded-For entry, the one the proxy added,
+// and whether they used https from X-Forwarded-Proto. That's what's logged, and what the endpoints
+// only allowed from the server's machine check. Only set it if clients can't reach the server
+// without going through the proxy, since anyone else can send those headers too.
+
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+};
+
+use futures_util::future;
+use tokio::{net::UnixListener, sync::watch};
+use tokio_stream::wrappers::UnixListenerStream;
+use tracing::info;
+use warp::{http::HeaderMap, Filter, Reply};
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:58597";
+
+#[derive(Debug, PartialEq)]
+pub enum Listener {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Listener {
+    fn parse(s: &str) -> Result<Listener, String> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("missing Unix socket path".to_string()),
+            Some(path) => Ok(Listener::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(Listener::Tcp)
+                .map_err(|_| format!("invalid address: {}", s)),
+        }
+    }
+}
+
+// The addresses in LISTEN.
+pub fn from_env() -> Result<Vec<Listener>, String> {
+    let listen = std::env::var("LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.to_string());
+    let listeners = listen
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Listener::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid LISTEN: {}", e))?;
+    if listeners.is_empty() {
+        return Err("LISTEN has no addresses".to_string());
+    }
+    Ok(listeners)
+}
+
+// Serves the routes on every listener until signal completes, then waits for their connections to
+// close. Fails without serving any if one can't be bound.
+pub async fn serve<F>(
+    routes: F,
+    listeners: &[Listener],
+    signal: impl Future<Output = ()>,
+) -> Result<(), String>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let (stop, stopped) = watch::channel(());
+    let mut servers = Vec::new();
+    for listener in listeners {
+        let mut stopped = stopped.clone();
+        let signal = async move {
+            let _ = stopped.changed().await;
+        };
+        let server = warp::serve(routes.clone());
+        match listener {
+            Listener::Tcp(addr) => {
+                let (addr, serve) = server
+                    .try_bind_with_graceful_shutdown(*addr, signal)
+                    .map_err(|e| format!("couldn't listen on {}: {}", addr, e))?;
+                info!(%addr, "listening");
+                servers.push(future::Either::Left(serve));
+            }
+            Listener::Unix(path) => {
+                // A socket left over from a previous run would fail the bind.
+                if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)
+                        .map_err(|e| format!("couldn't remove {}: {}", path.display(), e))?;
+                }
+                let socket = UnixListener::bind(path)
+                    .map_err(|e| format!("couldn't listen on {}: {}", path.display(), e))?;
+                info!(path = %path.display(), "listening");
+                let incoming = UnixListenerStream::new(socket);
+                servers.push(future::Either::Right(
+                    server.serve_incoming_with_graceful_shutdown(incoming, signal),
+                ));
+            }
+        }
+    }
+    let serving = future::join_all(servers);
+    tokio::pin!(serving);
+    tokio::select! {
+        _ = &mut serving => {}
+        _ = signal => {
+            let _ = stop.send(());
+            serving.await;
+        }
+    }
+    for listener in listeners {
+        if let Listener::Unix(path) = listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    Ok(())
+}
+
+// The client's address: the connection's, or the proxy's word for it if it's trusted. None for a
+// Unix socket without a trusted X-Forwarded-For.
+pub fn client_ip(
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trust_proxy: bool,
+) -> Option<IpAddr> {
+    if trust_proxy {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    remote.map(|a| a.ip())
+}
+
+// "https" or "http", as the client connected to the proxy if it's trusted. The server itself only
+// speaks http.
+pub fn scheme(headers: &HeaderMap, trust_proxy: bool) -> &str {
+    let forwarded = headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok());
+    match forwarded {
+        Some(proto) if trust_proxy => proto,
+        _ => "http",
+    }
+}
+
+// The client's IP address, as client_ip finds it.
+pub fn client(
+    trust_proxy: bool,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(move |remote, headers: HeaderMap| client_ip(remote, &headers, trust_proxy))
+}
+
+// Whether the request comes from the server's own machine.
+pub fn is_local(ip: Option<IpAddr>) -> bool {
+    ip.is_some_and(|ip| ip.is_loopback())
+}
+
+// Logs each request, like warp::log, with the client's address and scheme.
+pub fn access_log(trust_proxy: bool) -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(move |info| {
+        let headers = info.request_headers();
+        let ip = client_ip(info.remote_addr(), headers, trust_proxy);
+        info!(
+            target: "server",
+            "{} {} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
+            ip.map_or("-".to_string(), |ip| ip.to_string()),
+            scheme(headers, trust_proxy),
+            info.method(),
+            info.path(),
+            info.version(),
+            info.status().as_u16(),
+            info.referer().unwrap_or("-"),
+            info.user_agent().unwrap_or("-"),
+            info.elapsed(),
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listeners() {
+        assert_eq!(
+            Listener::parse("[::]:58597"),
+            Ok(Listener::Tcp(SocketAddr::from(([0; 16], 58597))))
+        );
+        assert_eq!(
+            Listener::parse("unix:/run/chess.sock"),
+            Ok(Listener::Unix(PathBuf::from("/run/chess.sock")))
+        );
+        assert!(Listener::parse("unix:").is_err());
+        assert!(Listener::parse("localhost").is_err());
+
+        let proxy = Some(SocketAddr::from(([127, 0, 0, 1], 40000)));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "127.0.0.1, 203.0.113.7".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let client: IpAddr = [203, 0, 113, 7].into();
+        assert_eq!(client_ip(proxy, &headers, true), Some(client));
+        assert_eq!(scheme(&headers, true), "https");
+        // Someone claiming to be local doesn't get past the proxy's entry.
+        assert!(!is_local(client_ip(proxy, &headers, true)));
+        assert!(is_local(client_ip(proxy, &headers, false)));
+        assert_eq!(scheme(&headers, false), "http");
+        assert_eq!(client_ip(None, &HeaderMap::new(), true), None);
+    }
+}
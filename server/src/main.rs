@@ -1,166 +1,1921 @@
 // Derived from https://github.com/seanmonstar/warp/blob/master/examples/websockets_chat.rs
 
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{mpsc, RwLock};
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
 use uuid::Uuid;
 use warp::ws::{Message, WebSocket};
-use warp::{http, http::Uri, Filter, Reply};
+use warp::{filters::BoxedFilter, http, http::Uri, Filter, Reply};
+
+mod cloud;
+mod explorer;
+#[cfg(feature = "fair-play")]
+mod fairplay;
+mod listen;
+mod movelog;
+mod outbox;
+mod prefs;
+mod puzzles;
+mod sse;
+mod webhooks;
+
+use cloud::CloudAnalysis;
+use explorer::Explorer;
+#[cfg(feature = "fair-play")]
+use fairplay::FairPlay;
+use movelog::MoveLog;
+use prefs::Prefs;
+use puzzles::Puzzles;
+use webhooks::Webhook;
 
 // Need to add player color
-type Player = mpsc::UnboundedSender<Message>;
-type Game = HashMap<Uuid, Player>;
+type Player = outbox::Sender;
 type Games = Arc<RwLock<HashMap<Uuid, Game>>>;
+type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+// Moves can be sent as binary frames instead of JSON (see ui/src/protocol.rs). They're the only
+// binary messages relayed, so anything without the tag and length of a move frame is dropped.
+const MOVE_FRAME_TAG: u8 = b'M';
+const MOVE_FRAME_LEN: usize = 17;
+// A move in several legs has a byte for each square it stops on, up to this many.
+const MOVE_MAX_VIA: usize = 12;
+
+fn is_move_frame(frame: &[u8]) -> bool {
+    (MOVE_FRAME_LEN..=MOVE_FRAME_LEN + MOVE_MAX_VIA).contains(&frame.len())
+        && frame[0] == MOVE_FRAME_TAG
+}
+
+// {"abort": true} asks the server to abort the game. It answers {"aborted": true} to everyone if
+// the player can (see Game::abortable), and {"abortable": false} to them otherwise.
+fn is_abort(msg: &Message) -> bool {
+    msg.to_str()
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        .is_some_and(|data| data["abort"] == true)
+}
+
+// {"color": "white"} or {"color": "black"} tells the other player their color. The creator's client
+// sends it whenever a seat is taken. Whether the sender is white, if msg is one.
+fn sender_is_white(msg: &Message) -> Option<bool> {
+    let data: serde_json::Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+    match data["color"].as_str()? {
+        "white" => Some(false),
+        "black" => Some(true),
+        _ => None,
+    }
+}
+
+// How long shutting down waits for the websockets to finish sending.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How many players a game has. Anyone who joins while this many are connected is a spectator.
+const SEATS: usize = 2;
+
+#[derive(Default)]
+struct Game {
+    // Everyone connected to the game, spectators included, since they all get the moves.
+    players: HashMap<Uuid, Player>,
+    // Those in players who are only watching.
+    spectators: HashSet<Uuid>,
+    // JSON rule set (see ui/src/ruleset.rs) the creator chose. It's sent to players when they join.
+    ruleset: Option<String>,
+    // When a player left the seat that's still empty, if the game is waiting for them to come back
+    // before it's adjudicated.
+    vacated: Option<Instant>,
+    // How the game ended, as the first player to say so reported it: {"result", "reason"}.
+    result: Option<serde_json::Value>,
+    // Who made the first move, if anyone has, and whether the other player has moved since. The
+    // game can be aborted until both have (see abortable).
+    first_mover: Option<Uuid>,
+    both_moved: bool,
+    // Set once the other player has taken longer than FIRST_MOVE_SECS to make their first move.
+    first_move_overdue: bool,
+    // The seated player playing white, as the creator's client last assigned colors (see
+    // sender_is_white), so an abandoned game can be adjudicated without asking either client.
+    white: Option<Uuid>,
+    // Private games: only those who join with the passcode get a seat, and no_spectators turns
+    // everyone else away rather than letting them watch.
+    passcode: Option<String>,
+    no_spectators: bool,
+    // Where the game's events go, if the creator asked (see webhooks.rs), and whether it's started,
+    // with both players in it.
+    webhook: Option<Webhook>,
+    started: bool,
+    // Rated games are played without the ui's assists, e.g. its check for blunders, so the players
+    // are told as they take their seats.
+    rated: bool,
+}
+
+impl Game {
+    // The players who aren't spectators.
+    fn seated(&self) -> impl Iterator<Item = &Player> {
+        self.players
+            .iter()
+            .filter(|(pid, _)| !self.spectators.contains(pid))
+            .map(|(_, tx)| tx)
+    }
+
+    fn seated_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.players
+            .keys()
+            .copied()
+            .filter(|pid| !self.spectators.contains(pid))
+    }
+
+    // Tells everyone how many players and spectators are connected, e.g. so a player can see
+    // their opponent has dropped.
+    fn send_presence(&self) {
+        let msg = format!(
+            r#"{{"presence": {{"players": {}, "spectators": {}}}}}"#,
+            self.players.len() - self.spectators.len(),
+            self.spectators.len()
+        );
+        for tx in self.players.values() {
+            if let Err(_disconnected) = tx.send_latest("presence", Message::text(msg.clone())) {}
+        }
+    }
+
+    // Whether someone joining with passcode can take a seat, if there's one free.
+    fn may_play(&self, passcode: Option<&str>) -> bool {
+        self.passcode.is_none() || self.passcode.as_deref() == passcode
+    }
+
+    // Whether player_id can abort the game, which isn't rated: before both players have moved, as
+    // long as they haven't moved themselves or the other player is taking too long to.
+    fn abortable(&self, player_id: Uuid) -> bool {
+        self.result.is_none()
+            && !self.both_moved
+            && !self.spectators.contains(&player_id)
+            && (self.first_mover != Some(player_id) || self.first_move_overdue)
+    }
+}
+
+// Passcodes go in game links and URLs, so they're kept to characters that don't need escaping.
+const MAX_PASSCODE_LEN: usize = 64;
+
+fn is_valid_passcode(passcode: &str) -> bool {
+    (1..=MAX_PASSCODE_LEN).contains(&passcode.len())
+        && passcode
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// What every connection shares, besides the games.
+#[derive(Clone, Default)]
+struct Server {
+    // Set once the server starts shutting down.
+    shutting_down: Arc<AtomicBool>,
+    // Websockets still open, so shutting down can wait for them.
+    open_sockets: Arc<AtomicUsize>,
+    // Where moves are logged, if anywhere (see movelog.rs).
+    move_log: Option<Arc<MoveLog>>,
+    // How long a player who drops out of a game has to come back before it's adjudicated, if it
+    // ever is.
+    abandon_grace: Option<Duration>,
+    // What the remaining player gets then: "win", "draw" or "abort". The server records it (see
+    // abandoned_result), and their client applies it too, e.g. drawing when neither side can mate.
+    abandon_result: &'static str,
+    // How long the player who made the first move waits for the other player's before they can
+    // abort the game, if they ever can.
+    first_move_timeout: Option<Duration>,
+    // What was played in each position of the logged games, if the move log is on (see
+    // explorer.rs).
+    explorer: Option<Arc<Explorer>>,
+    // Runs the native engine for browsers that want deeper analysis, if ANALYSIS_ENGINE is set
+    // (see cloud.rs).
+    cloud_analysis: Option<Arc<CloudAnalysis>>,
+    // Checks finished games for engine use, if the move log is on (see fairplay.rs).
+    #[cfg(feature = "fair-play")]
+    fair_play: Option<Arc<FairPlay>>,
+    // The puzzles found in the logged games, if the move log is on (see puzzles.rs).
+    puzzles: Option<Arc<Puzzles>>,
+    // Logged-in players' preferences (see prefs.rs).
+    prefs: Arc<Prefs>,
+    // Set by WEBHOOK_ALLOW_HTTP, to allow webhooks that aren't https.
+    allow_http_webhooks: bool,
+    // Set by WEBHOOK_ALLOW_PRIVATE, to allow webhooks to the server's own network (see
+    // webhooks.rs).
+    allow_private_webhooks: bool,
+    // The players connected by server-sent events rather than websockets (see sse.rs).
+    sse: Arc<sse::Sessions>,
+    // Set by TRUST_PROXY, to take clients' addresses from a reverse proxy's headers (see
+    // listen.rs).
+    trust_proxy: bool,
+}
+
+// ABANDON_GRACE_SECS (default 60, 0 never adjudicates) and ABANDON_RESULT (default "win").
+fn abandon_settings() -> Result<(Option<Duration>, &'static str), String> {
+    let grace = match std::env::var("ABANDON_GRACE_SECS") {
+        Ok(s) => s
+            .parse()
+            .map_err(|_| format!("invalid ABANDON_GRACE_SECS: {}", s))?,
+        Err(_) => 60,
+    };
+    let result = match std::env::var("ABANDON_RESULT").as_deref() {
+        Err(_) | Ok("win") => "win",
+        Ok("draw") => "draw",
+        Ok("abort") => "abort",
+        Ok(s) => return Err(format!("invalid ABANDON_RESULT: {}", s)),
+    };
+    Ok(((grace > 0).then(|| Duration::from_secs(grace)), result))
+}
+
+// FIRST_MOVE_SECS (default 30, 0 never lets the first mover abort).
+fn first_move_timeout() -> Result<Option<Duration>, String> {
+    let secs = match std::env::var("FIRST_MOVE_SECS") {
+        Ok(s) => s
+            .parse()
+            .map_err(|_| format!("invalid FIRST_MOVE_SECS: {}", s))?,
+        Err(_) => 30,
+    };
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    let log_filter = init_logging();
 
+    // Games in progress are saved here on shutdown and restored on startup, so players can rejoin
+    // them after a restart.
+    let games_file = std::env::var("GAMES_FILE").ok().map(PathBuf::from);
+    let allow_private_webhooks = std::env::var_os("WEBHOOK_ALLOW_PRIVATE").is_some();
     let games = Games::default();
-    let games = warp::any().map(move || games.clone());
+    if let Some(path) = &games_file {
+        match load_games(path, allow_private_webhooks) {
+            Ok(g) => {
+                info!(games = g.len(), "restored games");
+                *games.write().await = g;
+            }
+            Err(e) => warn!("couldn't restore games from {}: {}", path.display(), e),
+        }
+    }
+    let (abandon_grace, abandon_result) = abandon_settings().unwrap();
+    let move_log = MoveLog::from_env().expect("couldn't set up the move log");
+    let explorer = move_log
+        .as_ref()
+        .map(|log| Explorer::load(log).expect("couldn't index the move log"));
+    let puzzles = move_log
+        .as_ref()
+        .map(|log| Puzzles::from_env(log.dir()).expect("couldn't load the puzzles"));
+    let server = Server {
+        move_log: move_log.map(Arc::new),
+        explorer: explorer.map(Arc::new),
+        puzzles,
+        cloud_analysis: CloudAnalysis::from_env().unwrap().map(Arc::new),
+        abandon_grace,
+        abandon_result,
+        first_move_timeout: first_move_timeout().unwrap(),
+        prefs: Prefs::from_env().unwrap(),
+        allow_http_webhooks: std::env::var_os("WEBHOOK_ALLOW_HTTP").is_some(),
+        allow_private_webhooks,
+        trust_proxy: std::env::var_os("TRUST_PROXY").is_some(),
+        ..Default::default()
+    };
+    #[cfg(feature = "fair-play")]
+    let server = Server {
+        fair_play: match &server.move_log {
+            Some(log) => FairPlay::from_env(log.dir()).unwrap().map(Arc::new),
+            None => None,
+        },
+        ..server
+    };
 
-    // Create a game
-    let create =
-        warp::path("create")
-            .and(warp::ws())
-            .and(games.clone())
-            .map(|ws: warp::ws::Ws, games| {
-                ws.on_upgrade(move |websocket| create_game(websocket, games))
-            });
+    let root = warp::path::end().map(|| warp::redirect(Uri::from_static("/ui/")));
+    let routes = root
+        .or(ui_routes(std::env::var("UI_DIR").ok()))
+        .or(health_routes(server.clone()))
+        .or(game_routes(games.clone(), server.clone()))
+        .or(sse::routes(games.clone(), server.clone()))
+        .or(explorer::routes(server.clone()))
+        .or(puzzles::routes(server.clone()))
+        .or(cloud::routes(server.clone()))
+        .or(log_routes(log_filter, server.trust_proxy));
+    #[cfg(feature = "fair-play")]
+    let routes = routes.or(fairplay::routes(server.clone()));
+    let routes = routes.with(listen::access_log(server.trust_proxy));
+    let listeners = listen::from_env().unwrap();
+    listen::serve(routes, &listeners, async move {
+        shutdown_signal().await;
+        shutdown(&games, &server, games_file.as_deref()).await;
+    })
+    .await
+    .unwrap();
+}
 
-    // Join a game
-    let join = warp::path!("join" / String).and(warp::ws()).and(games).map(
-        |game_id: String, ws: warp::ws::Ws, games| {
-            if let Ok(game_id) = Uuid::parse_str(&game_id) {
-                ws.on_upgrade(move |websocket| join_game(websocket, game_id, games))
-                    .into_response()
-            } else {
-                eprintln!("invalid join ID: {}", game_id);
-                warp::reply::with_status("Invalid game ID", http::StatusCode::BAD_REQUEST)
-                    .into_response()
+async fn shutdown_signal() {
+    let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("couldn't listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+}
+
+// Stops taking new games, saves the ones in progress to games_file, tells their players and closes
+// their websockets, then waits (up to DRAIN_TIMEOUT) for the websockets to finish sending.
+async fn shutdown(games: &Games, server: &Server, games_file: Option<&Path>) {
+    info!("shutting down");
+    server.shutting_down.store(true, Ordering::SeqCst);
+    {
+        let mut w = games.write().await;
+        if let Some(path) = games_file {
+            match save_games(path, &w) {
+                Ok(n) => info!(games = n, "saved games"),
+                Err(e) => warn!("couldn't save games to {}: {}", path.display(), e),
             }
-        },
+        }
+        let msg = Message::text(r#"{"shutdown": "The server is shutting down"}"#);
+        for game in w.values() {
+            for tx in game.players.values() {
+                let _ = tx.send(msg.clone());
+                let _ = tx.send(Message::close());
+            }
+        }
+        // Dropping the players' senders ends their websockets once everything's sent.
+        w.clear();
+    }
+    // In case the last changes haven't been saved yet.
+    if let Err(e) = server.prefs.save().await {
+        warn!("{}", e);
+    }
+    if let Some(puzzles) = &server.puzzles {
+        if let Err(e) = puzzles.save_streaks().await {
+            warn!("{}", e);
+        }
+    }
+    let drained = tokio::time::timeout(DRAIN_TIMEOUT, async {
+        while server.open_sockets.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            open = server.open_sockets.load(Ordering::SeqCst),
+            "gave up waiting for websockets to close"
+        );
+    }
+}
+
+// Saves the unfinished games with players in them: their IDs, rule sets, whether they've started,
+// who can join them and their webhooks. Returns how many were saved.
+fn save_games(path: &Path, games: &HashMap<Uuid, Game>) -> std::io::Result<usize> {
+    let saved: Vec<serde_json::Value> = games
+        .iter()
+        .filter(|(_, game)| !game.players.is_empty() && game.result.is_none())
+        .map(|(game_id, game)| {
+            let ruleset = game
+                .ruleset
+                .as_deref()
+                .and_then(|r| serde_json::from_str::<serde_json::Value>(r).ok());
+            serde_json::json!({
+                "game_id": game_id.to_string(),
+                "ruleset": ruleset,
+                "moved": game.first_mover.is_some(),
+                "passcode": game.passcode,
+                "no_spectators": game.no_spectators,
+                "webhook": game.webhook.as_ref().map(Webhook::url),
+                "rated": game.rated,
+            })
+        })
+        .collect();
+    let n = saved.len();
+    std::fs::write(path, serde_json::Value::from(saved).to_string())?;
+    Ok(n)
+}
+
+// The games saved by save_games, waiting for their players to rejoin. A missing file means there
+// aren't any. allow_private_webhooks is as in Server.
+fn load_games(path: &Path, allow_private_webhooks: bool) -> Result<HashMap<Uuid, Game>, String> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let saved: Vec<serde_json::Value> = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+    let mut games = HashMap::new();
+    for g in saved {
+        let game_id = g["game_id"]
+            .as_str()
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| format!("invalid game ID: {}", g["game_id"]))?;
+        let ruleset = Some(&g["ruleset"])
+            .filter(|r| r.is_object())
+            .map(|r| r.to_string());
+        games.insert(
+            game_id,
+            Game {
+                ruleset,
+                // Players get new IDs when they rejoin, so whoever moved first is forgotten, and
+                // a game that has started can't be aborted anymore.
+                both_moved: g["moved"].as_bool() == Some(true),
+                passcode: g["passcode"].as_str().map(str::to_string),
+                no_spectators: g["no_spectators"].as_bool() == Some(true),
+                webhook: g["webhook"]
+                    .as_str()
+                    .map(|url| Webhook::start(url.to_string(), game_id, allow_private_webhooks)),
+                // Whether or not both players were in it, it isn't new anymore.
+                started: true,
+                rated: g["rated"].as_bool() == Some(true),
+                ..Default::default()
+            },
+        );
+    }
+    Ok(games)
+}
+
+// For orchestrators: /healthz is OK as long as the server is up, and /readyz as long as it's
+// taking new games.
+fn health_routes(
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .map(|| warp::reply::with_status("OK", http::StatusCode::OK).into_response());
+    let readyz = warp::path("readyz").and(warp::path::end()).map(move || {
+        if server.shutting_down.load(Ordering::SeqCst) {
+            warp::reply::with_status("Shutting down", http::StatusCode::SERVICE_UNAVAILABLE)
+                .into_response()
+        } else {
+            warp::reply::with_status("OK", http::StatusCode::OK).into_response()
+        }
+    });
+    healthz.or(readyz).unify()
+}
+
+// Serves the ui under /ui/ from dir, or /srv/chess if it's None. With the embed-ui feature, None
+// serves the copy built into the binary instead.
+fn ui_routes(dir: Option<String>) -> BoxedFilter<(warp::reply::Response,)> {
+    #[cfg(feature = "embed-ui")]
+    if dir.is_none() {
+        return warp::path("ui")
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                embedded_file(tail.as_str()).ok_or_else(warp::reject::not_found)
+            })
+            .boxed();
+    }
+    let dir = dir.unwrap_or_else(|| "/srv/chess".to_string());
+    warp::path("ui")
+        .and(warp::fs::dir(dir))
+        .map(Reply::into_response)
+        .boxed()
+}
+
+#[cfg(feature = "embed-ui")]
+static UI: include_dir::Dir = include_dir::include_dir!("$UI_BUNDLE_DIR");
+
+#[cfg(feature = "embed-ui")]
+fn embedded_file(path: &str) -> Option<warp::reply::Response> {
+    let path = if path.is_empty() || path.ends_with('/') {
+        format!("{}index.html", path)
+    } else {
+        path.to_string()
+    };
+    let file = UI.get_file(&path)?;
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    let mut res = warp::reply::Response::new(file.contents().into());
+    res.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_str(mime.as_ref()).ok()?,
     );
+    Some(res)
+}
 
-    let ui = warp::path("ui").and(warp::fs::dir("/srv/chess"));
+// Logs go to stderr, filtered by RUST_LOG (e.g. "server=debug,warp=info"), as JSON lines when
+// LOG_FORMAT=json. The returned handle changes the filter while the server runs.
+fn init_logging() -> LogFilter {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter);
+    if std::env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        registry.with(fmt::layer().json()).init();
+    } else {
+        registry.with(fmt::layer()).init();
+    }
+    handle
+}
 
-    let root = warp::path::end().map(|| warp::redirect(Uri::from_static("/ui/")));
-    let routes = root.or(ui).or(create).or(join);
-    warp::serve(routes.with(warp::log("server")))
-        .run(([0, 0, 0, 0], 58597))
-        .await;
+// Changes the log filter, e.g. `curl -X PUT 'localhost:58597/log?filter=server=debug'`. Only
+// allowed from the machine the server runs on.
+fn log_routes(
+    log_filter: LogFilter,
+    trust_proxy: bool,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path("log")
+        .and(warp::put())
+        .and(listen::client(trust_proxy))
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |client: Option<IpAddr>, query: HashMap<String, String>| {
+                if !listen::is_local(client) {
+                    return warp::reply::with_status("Forbidden", http::StatusCode::FORBIDDEN)
+                        .into_response();
+                }
+                let filter = query.get("filter").map_or("", String::as_str);
+                match EnvFilter::try_new(filter).map(|f| log_filter.reload(f)) {
+                    Ok(Ok(())) => {
+                        info!(filter, "log filter changed");
+                        warp::reply::with_status("OK", http::StatusCode::OK).into_response()
+                    }
+                    Ok(Err(e)) => warp::reply::with_status(
+                        e.to_string(),
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                    Err(e) => {
+                        warp::reply::with_status(e.to_string(), http::StatusCode::BAD_REQUEST)
+                            .into_response()
+                    }
+                }
+            },
+        )
+}
+
+// The websocket endpoints for creating and joining games. They're unavailable while shutting down.
+fn game_routes(
+    games: Games,
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let games = warp::any().map(move || games.clone());
+    let server = warp::any().map(move || server.clone());
+    let shutting_down = || {
+        warp::reply::with_status("Shutting down", http::StatusCode::SERVICE_UNAVAILABLE)
+            .into_response()
+    };
+
+    // Create a game, optionally with a rule set, privately, with a webhook and rated:
+    // /create?ruleset=<json>&passcode=<secret>&spectators=false&webhook=<url>&rated=true
+    let create = warp::path("create")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::ws())
+        .and(games.clone())
+        .and(server.clone())
+        .then(
+            move |query: HashMap<String, String>, ws: warp::ws::Ws, games, server: Server| async move {
+                if server.shutting_down.load(Ordering::SeqCst) {
+                    return shutting_down();
+                }
+                let (game, webhook) = match new_game(query, &server).await {
+                    Ok(g) => g,
+                    Err(e) => {
+                        return warp::reply::with_status(e, http::StatusCode::BAD_REQUEST)
+                            .into_response()
+                    }
+                };
+                ws.on_upgrade(move |websocket| create_game(websocket, game, webhook, games, server))
+                    .into_response()
+            },
+        );
+
+    // Join a game, with its passcode if it's private: /join/<game ID>?passcode=<secret>. With
+    // watch=true, only to watch it, leaving a free seat for a player.
+    let join = warp::path!("join" / String)
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::ws())
+        .and(games)
+        .and(server)
+        .map(
+            move |game_id: String,
+                  mut query: HashMap<String, String>,
+                  ws: warp::ws::Ws,
+                  games,
+                  server: Server| {
+                if server.shutting_down.load(Ordering::SeqCst) {
+                    return shutting_down();
+                }
+                let passcode = query.remove("passcode");
+                let watch = query.get("watch").map(String::as_str) == Some("true");
+                if let Ok(game_id) = Uuid::parse_str(&game_id) {
+                    ws.on_upgrade(move |websocket| {
+                        join_game(websocket, game_id, passcode, watch, games, server)
+                    })
+                    .into_response()
+                } else {
+                    warn!("invalid join ID: {}", game_id);
+                    warp::reply::with_status("Invalid game ID", http::StatusCode::BAD_REQUEST)
+                        .into_response()
+                }
+            },
+        );
+
+    create.or(join).unify()
+}
+
+// The game a creator asked for with the query of /create, and its webhook, or what's wrong with it.
+async fn new_game(
+    mut query: HashMap<String, String>,
+    server: &Server,
+) -> Result<(Game, Option<String>), &'static str> {
+    let ruleset = query.remove("ruleset");
+    if let Some(r) = &ruleset {
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(r) {
+            warn!("invalid ruleset: {}", e);
+            return Err("Invalid ruleset");
+        }
+    }
+    let passcode = query.remove("passcode");
+    if passcode.as_deref().is_some_and(|p| !is_valid_passcode(p)) {
+        warn!("invalid passcode");
+        return Err("Invalid passcode");
+    }
+    let webhook = query.remove("webhook");
+    if let Some(url) = &webhook {
+        let (http, private) = (server.allow_http_webhooks, server.allow_private_webhooks);
+        if let Err(e) = webhooks::check_url(url, http, private).await {
+            warn!("{}", e);
+            return Err("Invalid webhook");
+        }
+    }
+    let game = Game {
+        ruleset,
+        passcode,
+        no_spectators: query.get("spectators").map(String::as_str) == Some("false"),
+        rated: query.get("rated").map(String::as_str) == Some("true"),
+        ..Default::default()
+    };
+    Ok((game, webhook))
 }
 
-async fn create_game(ws: WebSocket, games: Games) {
+async fn create_game(
+    ws: WebSocket,
+    game: Game,
+    webhook: Option<String>,
+    games: Games,
+    server: Server,
+) {
+    // The creator always gets a seat.
+    let passcode = game.passcode.clone();
+    let game_id = add_game(game, webhook, &games, &server).await;
+    join_game(ws, game_id, passcode, false, games, server).await;
+}
+
+// Starts the game, returning its ID.
+async fn add_game(mut game: Game, webhook: Option<String>, games: &Games, server: &Server) -> Uuid {
     let game_id = Uuid::new_v4();
-    let game = HashMap::new();
+    game.webhook = webhook.map(|url| Webhook::start(url, game_id, server.allow_private_webhooks));
+    if let Some(log) = &server.move_log {
+        if let Err(e) = log.start_game(game_id, game.ruleset.as_deref()) {
+            warn!(%game_id, "couldn't log game: {}", e);
+        }
+    }
     games.write().await.insert(game_id, game);
-    join_game(ws, game_id, games).await;
+    info!(%game_id, "game created");
+    game_id
+}
+
+async fn join_game(
+    ws: WebSocket,
+    game_id: Uuid,
+    passcode: Option<String>,
+    watch: bool,
+    games: Games,
+    server: Server,
+) {
+    let player_id = Uuid::new_v4();
+    // Everything logged while handling this player is tagged with the game and player.
+    let span = info_span!("player", %game_id, %player_id);
+    play(ws, game_id, player_id, passcode, watch, games, server)
+        .instrument(span)
+        .await;
 }
 
-async fn join_game(ws: WebSocket, game_id: Uuid, games: Games) {
+async fn play(
+    ws: WebSocket,
+    game_id: Uuid,
+    player_id: Uuid,
+    passcode: Option<String>,
+    watch: bool,
+    games: Games,
+    server: Server,
+) {
     let (mut ws_tx, mut ws_rx) = ws.split();
-    let (tx, rx) = mpsc::unbounded_channel();
-    let mut rx = UnboundedReceiverStream::new(rx);
+    let (tx, rx) = outbox::channel();
+    // Who the player logged in as, if they did.
+    let mut user = None;
 
-    let player_id = Uuid::new_v4();
-    {
-        let mut w = games.write().await;
-        if let Some(game) = w.get_mut(&game_id) {
-            if game.is_empty() {
-                // First player, send them the game ID
-                let game_info = format!(r#"{{"game_id": "{}"}}"#, game_id);
-                if let Err(_) = tx.send(Message::text(game_info)) {
-                    // This should get handled below by player_disconnected.
-                }
-            } else {
-                let msg = format!(r#"{{"joined": "{}"}}"#, player_id);
-                for (&pid, tx) in game.iter() {
-                    if pid != player_id {
-                        if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
-                    }
-                }
+    match enter(game_id, player_id, passcode.as_deref(), watch, tx, &games).await {
+        Ok(()) => {}
+        Err(Refused::Private) => {
+            if let Err(e) = ws_tx.send(Message::text(DENIED)).await {
+                warn!("websocket send error: {}", e);
             }
-            game.insert(player_id, tx);
-        } else {
-            eprintln!("non-existant game ID: {}", game_id);
+            let _ = ws_tx.close().await;
             return;
         }
+        Err(Refused::NoGame) => return,
     }
 
     // Backgroud task that sends messages back to the client.
-    tokio::task::spawn(async move {
-        while let Some(message) = rx.next().await {
-            ws_tx
-                .send(message)
-                .unwrap_or_else(|e| {
-                    eprintln!("websocket send error: {}", e);
-                })
-                .await;
+    // It runs until the player's sender is dropped, when they leave or the server shuts down, or
+    // until they're disconnected for being too slow (see outbox.rs).
+    let open_sockets = server.open_sockets.clone();
+    open_sockets.fetch_add(1, Ordering::SeqCst);
+    let mut sending = tokio::task::spawn(
+        async move {
+            while let Some(message) = rx.recv().await {
+                ws_tx
+                    .send(message)
+                    .unwrap_or_else(|e| {
+                        warn!("websocket send error: {}", e);
+                    })
+                    .await;
+            }
+            open_sockets.fetch_sub(1, Ordering::SeqCst);
         }
-    });
+        .in_current_span(),
+    );
 
-    // Receive messages from the client and forward them to other players.
-    while let Some(result) = ws_rx.next().await {
+    // Receive messages from the client and forward them to other players, until the connection's
+    // closed at either end.
+    loop {
+        let result = tokio::select! {
+            result = ws_rx.next() => result,
+            _ = &mut sending => break,
+        };
+        let Some(result) = result else { break };
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
-                eprintln!(
-                    "websocket error(game_id={}, player_id={}): {}",
-                    game_id, player_id, e
-                );
+                warn!("websocket error: {}", e);
                 break;
             }
         };
-        process_message(game_id, player_id, msg, &games).await;
+        receive(game_id, player_id, msg, &mut user, &games, &server).await;
     }
 
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
-    player_disconnected(game_id, player_id, &games).await;
+    player_disconnected(game_id, player_id, &games, &server).await;
+}
+
+// Why someone couldn't join a game.
+#[derive(Debug, PartialEq)]
+enum Refused {
+    NoGame,
+    // Without the passcode of a private game that doesn't allow spectators.
+    Private,
+}
+
+// What's sent instead of anything else to those turned away from private games.
+const DENIED: &str = r#"{"denied": "This game is private"}"#;
+
+// What spectators are told as they join, so their board doesn't let them move.
+const SPECTATING: &str = r#"{"role": "spectator"}"#;
+
+// What players are told as they take a seat in a rated game.
+const RATED: &str = r#"{"rated": true}"#;
+
+// Adds the player to the game, in a seat if there's one free and they have the passcode the game
+// needs, if any, and otherwise as a spectator, as they are if they only want to watch. tx gets the
+// messages for them.
+async fn enter(
+    game_id: Uuid,
+    player_id: Uuid,
+    passcode: Option<&str>,
+    watch: bool,
+    tx: Player,
+    games: &Games,
+) -> Result<(), Refused> {
+    let mut w = games.write().await;
+    let Some(game) = w.get_mut(&game_id) else {
+        warn!("non-existant game ID");
+        return Err(Refused::NoGame);
+    };
+    let spectator =
+        watch || game.players.len() - game.spectators.len() >= SEATS || !game.may_play(passcode);
+    if spectator && game.no_spectators {
+        info!("turned away from private game");
+        return Err(Refused::Private);
+    }
+    if game.players.is_empty() {
+        // First player, send them the game ID
+        let game_info = format!(r#"{{"game_id": "{}"}}"#, game_id);
+        if let Err(_) = tx.send(Message::text(game_info)) {
+            // This should get handled below by player_disconnected.
+        }
+    } else {
+        if let Some(ruleset) = &game.ruleset {
+            let msg = format!(r#"{{"ruleset": {}}}"#, ruleset);
+            if let Err(_disconnected) = tx.send(Message::text(msg)) {}
+        }
+        if !spectator {
+            let msg = format!(r#"{{"joined": "{}"}}"#, player_id);
+            for tx in game.seated() {
+                if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
+            }
+        }
+    }
+    if spectator {
+        if let Err(_disconnected) = tx.send(Message::text(SPECTATING)) {}
+    } else if game.rated {
+        if let Err(_disconnected) = tx.send(Message::text(RATED)) {}
+    }
+    if !spectator && game.first_move_overdue && game.abortable(player_id) {
+        if let Err(_disconnected) = tx.send(Message::text(r#"{"abortable": true}"#)) {}
+    }
+    game.players.insert(player_id, tx);
+    if spectator {
+        game.spectators.insert(player_id);
+    } else {
+        game.vacated = None;
+    }
+    game.send_presence();
+    info!(players = game.players.len(), spectator, "player joined");
+    if !game.started && game.seated().count() == SEATS {
+        game.started = true;
+        if let Some(hook) = &game.webhook {
+            let ruleset = game
+                .ruleset
+                .as_deref()
+                .and_then(|r| serde_json::from_str::<serde_json::Value>(r).ok());
+            hook.send("start", serde_json::json!({ "ruleset": ruleset }));
+        }
+    }
+    Ok(())
 }
 
-async fn process_message(game_id: Uuid, player_id: Uuid, msg: Message, games: &Games) {
-    // Skip any non-Text messages...
-    let msg = if let Ok(s) = msg.to_str() {
-        s
+// Handles a message from the player, logged in as user if they did.
+async fn receive(
+    game_id: Uuid,
+    player_id: Uuid,
+    msg: Message,
+    user: &mut Option<Uuid>,
+    games: &Games,
+    server: &Server,
+) {
+    match prefs::parse(&msg) {
+        Some(request) => {
+            let r = games.read().await;
+            let reply = r.get(&game_id).and_then(|g| g.players.get(&player_id));
+            update_prefs(request, user, reply, server);
+        }
+        None => process_message(game_id, player_id, msg, games, server).await,
+    }
+}
+
+async fn process_message(
+    game_id: Uuid,
+    player_id: Uuid,
+    msg: Message,
+    games: &Games,
+    server: &Server,
+) {
+    if let Ok(s) = msg.to_str() {
+        debug!("websocket message: {}", s);
+    } else if msg.is_binary() {
+        let frame = msg.as_bytes();
+        if !is_move_frame(frame) {
+            warn!("dropping binary message that isn't a move: {:?}", frame);
+            return;
+        }
+        debug!(
+            src = frame[1],
+            dst = frame[2],
+            promotion = frame[3],
+            "move frame"
+        );
     } else {
+        // Pings and such.
         return;
-    };
+    }
+
+    // Spectators only watch. Nothing they send is logged or relayed, so whatever their client does,
+    // they can't move, take back, offer a draw, or abort or end the game.
+    let spectator = games
+        .read()
+        .await
+        .get(&game_id)
+        .is_some_and(|g| g.spectators.contains(&player_id));
+    if spectator {
+        debug!("dropping a spectator's message");
+        return;
+    }
+
+    if let Some(result) = movelog::parse_result(&msg) {
+        let mut w = games.write().await;
+        if let Some(game) = w.get_mut(&game_id).filter(|g| g.result.is_none()) {
+            record_result(game_id, player_id, game, result, server);
+        }
+    }
+
+    if is_abort(&msg) {
+        let mut w = games.write().await;
+        if let Some(game) = w.get_mut(&game_id) {
+            if game.abortable(player_id) {
+                let result = serde_json::json!({"result": "*", "reason": "aborted"});
+                record_result(game_id, player_id, game, result, server);
+                for tx in game.players.values() {
+                    if let Err(_disconnected) = tx.send(Message::text(r#"{"aborted": true}"#)) {}
+                }
+            } else if let Some(tx) = game.players.get(&player_id) {
+                if let Err(_disconnected) = tx.send(Message::text(r#"{"abortable": false}"#)) {}
+            }
+        }
+        // Everyone's been told, the player who asked included.
+        return;
+    }
+
+    if let Some(sender_white) = sender_is_white(&msg) {
+        let mut w = games.write().await;
+        if let Some(game) = w.get_mut(&game_id) {
+            game.white = if sender_white {
+                Some(player_id)
+            } else {
+                game.seated_ids().find(|&pid| pid != player_id)
+            };
+        }
+    }
+
+    if movelog::parse_move(&msg).is_some() {
+        let mut w = games.write().await;
+        if let Some(game) = w.get_mut(&game_id).filter(|g| !g.both_moved) {
+            match game.first_mover {
+                None => {
+                    game.first_mover = Some(player_id);
+                    if let Some(timeout) = server.first_move_timeout {
+                        tokio::spawn(
+                            first_move_overdue(game_id, player_id, timeout, games.clone())
+                                .in_current_span(),
+                        );
+                    }
+                }
+                Some(pid) if pid != player_id => game.both_moved = true,
+                Some(_) => {}
+            }
+        }
+    }
 
-    eprintln!(
-        "websocket message(game_id={}, player_id={}): {}",
-        game_id, player_id, msg
-    );
     {
         let r = games.read().await;
         if let Some(game) = r.get(&game_id) {
-            for (&pid, tx) in game.iter() {
+            // Logged while the game is locked, so the moves are in the order they were relayed.
+            if let (Some(log), Some(entry)) = (&server.move_log, movelog::log_entry(&msg)) {
+                if let Err(e) = log.record(game_id, player_id, entry) {
+                    warn!("couldn't log move: {}", e);
+                }
+            }
+            if let Some(hook) = game.webhook.as_ref().filter(|_| game.result.is_none()) {
+                if let Some(m) = movelog::parse_move(&msg) {
+                    hook.send("move", serde_json::json!({ "move": m }));
+                }
+            }
+            for (&pid, tx) in game.players.iter() {
                 if pid != player_id {
-                    if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
+                    if let Err(_disconnected) = tx.send(msg.clone()) {}
                 }
             }
         }
     }
 }
 
-async fn player_disconnected(game_id: Uuid, player_id: Uuid, games: &Games) {
-    eprintln!("player disconnected(game_id={}): {}", game_id, player_id);
+// Logs the player in as a user, sending them the user's preferences, or saves the logged-in user's
+// preferences (see prefs.rs).
+fn update_prefs(
+    request: Result<prefs::Request, String>,
+    user: &mut Option<Uuid>,
+    reply: Option<&Player>,
+    server: &Server,
+) {
+    match (request, *user) {
+        (Ok(prefs::Request::Login(u)), _) => {
+            *user = Some(u);
+            info!(user = %u, "logged in");
+            if let Some(tx) = reply {
+                if let Err(_disconnected) = tx.send(prefs::message(&server.prefs.get(u))) {}
+            }
+        }
+        (Ok(prefs::Request::Save(p)), Some(u)) => {
+            if let Err(e) = server.prefs.set(u, p) {
+                warn!(user = %u, "couldn't save preferences: {}", e);
+            }
+        }
+        (Ok(prefs::Request::Save(_)), None) => warn!("preferences from a player not logged in"),
+        (Err(e), _) => warn!("{}", e),
+    }
+}
+
+// Records how the game ended, as player_id reported it or the server adjudicated it for them.
+fn record_result(
+    game_id: Uuid,
+    player_id: Uuid,
+    game: &mut Game,
+    result: serde_json::Value,
+    server: &Server,
+) {
+    info!(%result, "game over");
+    if let Some(log) = &server.move_log {
+        if let Err(e) = log.record(game_id, player_id, result.clone()) {
+            warn!("couldn't log result: {}", e);
+        } else if let Some(explorer) = &server.explorer {
+            if let Err(e) = explorer.add_game(log, game_id) {
+                warn!("couldn't add the game to the opening explorer: {}", e);
+            }
+        }
+    }
+    if let Some(hook) = &game.webhook {
+        hook.send("end", result.clone());
+    }
+    game.result = Some(result);
+    game.vacated = None;
+    if let Some(puzzles) = server.puzzles.clone().filter(|p| p.generates()) {
+        tokio::spawn(
+            async move {
+                match puzzles.generate(game_id).await {
+                    Ok(found) => info!(found, "looked for puzzles"),
+                    Err(e) => warn!("couldn't look for puzzles: {}", e),
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    #[cfg(feature = "fair-play")]
+    if let Some(fair_play) = server.fair_play.clone() {
+        tokio::spawn(
+            async move {
+                if let Err(e) = fair_play.check(game_id).await {
+                    warn!("couldn't check fair play: {}", e);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+// Once timeout has passed since first_mover made the first move, lets them abort the game too if
+// the other player still hasn't moved.
+async fn first_move_overdue(game_id: Uuid, first_mover: Uuid, timeout: Duration, games: Games) {
+    tokio::time::sleep(timeout).await;
+    let mut w = games.write().await;
+    if let Some(game) = w
+        .get_mut(&game_id)
+        .filter(|g| g.first_mover == Some(first_mover) && !g.both_moved && g.result.is_none())
+    {
+        info!("first move overdue");
+        game.first_move_overdue = true;
+        for tx in game.seated() {
+            if let Err(_disconnected) = tx.send(Message::text(r#"{"abortable": true}"#)) {}
+        }
+    }
+}
+
+async fn player_disconnected(game_id: Uuid, player_id: Uuid, games: &Games, server: &Server) {
+    info!("player disconnected");
 
     {
         let mut w = games.write().await;
         if let Some(game) = w.get_mut(&game_id) {
-            game.remove(&player_id);
-            if game.is_empty() {
-                eprintln!("all players left game: {}", game_id);
+            game.players.remove(&player_id);
+            let spectator = game.spectators.remove(&player_id);
+            if game.players.is_empty() {
+                info!("all players left game");
                 w.remove(&game_id);
             } else {
-                let msg = format!(r#"{{"disconnected": "{}"}}"#, player_id);
-                for (_, tx) in game.iter() {
-                    if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
+                if !spectator {
+                    let msg = format!(r#"{{"disconnected": "{}"}}"#, player_id);
+                    for tx in game.seated() {
+                        if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
+                    }
+                    let waiting = game.seated().next().is_some() && game.result.is_none();
+                    if let (Some(grace), true) = (server.abandon_grace, waiting) {
+                        let vacated = Instant::now();
+                        game.vacated = Some(vacated);
+                        tokio::spawn(
+                            adjudicate(game_id, vacated, grace, games.clone(), server.clone())
+                                .in_current_span(),
+                        );
+                    }
                 }
+                game.send_presence();
             }
         }
     }
 }
+
+// Once grace has passed, tells the players still in the game that it was abandoned and records the
+// result, unless the seat vacated then has been taken again or the game has ended since.
+async fn adjudicate(
+    game_id: Uuid,
+    vacated: Instant,
+    grace: Duration,
+    games: Games,
+    server: Server,
+) {
+    tokio::time::sleep(grace).await;
+    let mut w = games.write().await;
+    if let Some(game) = w.get_mut(&game_id).filter(|g| g.vacated == Some(vacated)) {
+        info!(result = server.abandon_result, "game abandoned");
+        game.vacated = None;
+        let msg = format!(r#"{{"abandoned": "{}"}}"#, server.abandon_result);
+        for tx in game.seated() {
+            if let Err(_disconnected) = tx.send(Message::text(msg.clone())) {}
+        }
+        let Some(remaining) = game.seated_ids().next() else {
+            return;
+        };
+        match abandoned_result(game, remaining, server.abandon_result) {
+            Some(result) => record_result(game_id, remaining, game, result, &server),
+            // Left to the remaining player's client to report.
+            None => warn!("no color for the remaining player, can't adjudicate"),
+        }
+    }
+}
+
+// The result of a game abandoned by everyone but remaining: aborted before both players have
+// moved, and otherwise as ABANDON_RESULT says. Unlike the client, the server doesn't know the
+// position, so it doesn't draw when neither side can mate. None for a win if no one has told
+// remaining their color.
+fn abandoned_result(game: &Game, remaining: Uuid, policy: &str) -> Option<serde_json::Value> {
+    let result = match policy {
+        _ if !game.both_moved => "*",
+        "draw" => "1/2-1/2",
+        "abort" => "*",
+        _ if game.white? == remaining => "1-0",
+        _ => "0-1",
+    };
+    Some(serde_json::json!({"result": result, "reason": "abandoned"}))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use serde_json::{json, Value};
+    use tokio::{net::TcpStream, sync::mpsc, time::timeout};
+    use tokio_tungstenite::{
+        connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+    };
+
+    use super::*;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    // Serves the game routes, with their server-sent events fallback, on an ephemeral port.
+    fn start() -> (SocketAddr, Games) {
+        start_with(Server::default())
+    }
+
+    fn start_with(server: Server) -> (SocketAddr, Games) {
+        let games = Games::default();
+        let routes =
+            game_routes(games.clone(), server.clone()).or(sse::routes(games.clone(), server));
+        let (addr, serve) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(serve);
+        (addr, games)
+    }
+
+    async fn connect(addr: SocketAddr, path: &str) -> Client {
+        let url = format!("ws://{}{}", addr, path);
+        connect_async(url).await.expect("couldn't connect").0
+    }
+
+    // Creates a game, returning the creator and the game ID.
+    async fn create(addr: SocketAddr, path: &str) -> (Client, String) {
+        let mut creator = connect(addr, path).await;
+        let msg = recv(&mut creator).await;
+        let game_id = msg["game_id"].as_str().expect("no game ID").to_string();
+        assert_presence(&mut creator, 1, 0).await;
+        (creator, game_id)
+    }
+
+    async fn recv(client: &mut Client) -> Value {
+        let msg = timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("connection closed")
+            .expect("websocket error");
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    async fn send(client: &mut Client, v: Value) {
+        client.send(WsMessage::text(v.to_string())).await.unwrap();
+    }
+
+    async fn assert_presence(client: &mut Client, players: u64, spectators: u64) {
+        assert_eq!(
+            recv(client).await,
+            json!({"presence": {"players": players, "spectators": spectators}})
+        );
+    }
+
+    // Waits for the server to have read everything the client sent, by logging in and waiting for
+    // the answer, skipping whatever else comes first.
+    async fn sync(client: &mut Client) {
+        send(client, json!({ "login": Uuid::new_v4().to_string() })).await;
+        while recv(client).await.get("prefs").is_none() {}
+    }
+
+    async fn assert_silent(client: &mut Client) {
+        let next = timeout(Duration::from_millis(200), client.next()).await;
+        assert!(next.is_err(), "unexpected message: {:?}", next);
+    }
+
+    // The server closes the connection without sending anything.
+    async fn assert_closed(client: &mut Client) {
+        let next = timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("connection still open");
+        assert!(
+            matches!(next, None | Some(Ok(WsMessage::Close(_))) | Some(Err(_))),
+            "unexpected message: {:?}",
+            next
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_and_join() {
+        let (addr, games) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        assert!(games
+            .read()
+            .await
+            .contains_key(&Uuid::parse_str(&game_id).unwrap()));
+
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 0).await;
+        // Without a rule set, all the joiner needs to know is who's there.
+        assert_presence(&mut joiner, 2, 0).await;
+        assert_silent(&mut joiner).await;
+    }
+
+    #[tokio::test]
+    async fn test_ruleset_sent_to_joiner() {
+        let (addr, _) = start();
+        let ruleset = json!({"version": 1, "name": "No knights", "rules": {"knight": false}});
+        let path = format!(
+            "/create?ruleset={}",
+            ruleset.to_string().replace('"', "%22").replace(' ', "%20")
+        );
+        let (_creator, game_id) = create(addr, &path).await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        assert_eq!(recv(&mut joiner).await, json!({ "ruleset": ruleset }));
+    }
+
+    // Preferences saved in one game are sent on login in another, and the other player doesn't see
+    // either.
+    #[tokio::test]
+    async fn test_prefs() {
+        let (addr, _) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+        let user = Uuid::new_v4().to_string();
+        send(&mut creator, json!({ "prefs": {"theme": "gray"} })).await;
+        send(&mut creator, json!({ "login": user })).await;
+        assert_eq!(recv(&mut creator).await, json!({"prefs": {}}));
+        let prefs = json!({"theme": "wood", "sounds": false});
+        send(&mut creator, json!({ "prefs": prefs })).await;
+        assert_silent(&mut joiner).await;
+
+        let (mut other, _) = create(addr, "/create").await;
+        send(&mut other, json!({ "login": user })).await;
+        assert_eq!(recv(&mut other).await, json!({ "prefs": prefs }));
+    }
+
+    // Colors are picked by the creator's client, and moves are checked by the clients, so the
+    // server just passes messages on to the other players.
+    #[tokio::test]
+    async fn test_relay() {
+        let (addr, _) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+
+        send(&mut creator, json!({"color": "black"})).await;
+        assert_eq!(recv(&mut joiner).await, json!({"color": "black"}));
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        send(&mut joiner, m.clone()).await;
+        assert_eq!(recv(&mut creator).await, m);
+        // Messages aren't echoed back to the sender.
+        assert_silent(&mut joiner).await;
+
+        // Binary messages are relayed if they're move frames.
+        let mut frame = vec![b'M', 0x72, 0x81, b'q', 1, 0, 0, 0, 0];
+        frame.extend_from_slice(&0x0123_4567_89ab_cdefu64.to_le_bytes());
+        creator
+            .send(WsMessage::binary(frame.clone()))
+            .await
+            .unwrap();
+        let relayed = timeout(Duration::from_secs(5), joiner.next())
+            .await
+            .expect("timed out")
+            .unwrap()
+            .unwrap();
+        assert_eq!(relayed, WsMessage::binary(frame));
+        creator
+            .send(WsMessage::binary(vec![1, 2, 3]))
+            .await
+            .unwrap();
+        assert_silent(&mut joiner).await;
+    }
+
+    #[tokio::test]
+    async fn test_move_log() {
+        let dir = std::env::temp_dir().join(format!("chess-moves-{}", Uuid::new_v4()));
+        let log = Arc::new(MoveLog::new(dir.clone(), 1 << 20).unwrap());
+        let (addr, _) = start_with(Server {
+            move_log: Some(log.clone()),
+            ..Default::default()
+        });
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+
+        send(&mut creator, json!({"color": "black"})).await;
+        recv(&mut joiner).await;
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5, "hash": "00000000000000ff"});
+        send(&mut joiner, m.clone()).await;
+        recv(&mut creator).await;
+        let mut frame = vec![b'M', 0x75, 0x55, 0, 0, 0, 0, 0, 0];
+        frame.extend_from_slice(&0xeeu64.to_le_bytes());
+        creator.send(WsMessage::binary(frame)).await.unwrap();
+        joiner.next().await.unwrap().unwrap();
+        send(&mut creator, json!({"takeback": "request", "ply": 2})).await;
+        recv(&mut joiner).await;
+        send(&mut joiner, json!({"takeback": "accept", "ply": 2})).await;
+        recv(&mut creator).await;
+
+        let game_id = Uuid::parse_str(&game_id).unwrap();
+        let lines: Vec<Value> = std::fs::read_to_string(log.path(game_id))
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["game_id"], game_id.to_string());
+        assert!(lines[0]["ruleset"].is_null());
+        assert_eq!(lines[1]["src_row"], 2);
+        assert_eq!(lines[1]["hash"], "00000000000000ff");
+        assert_eq!(lines[2]["src_row"], 7);
+        assert_eq!(lines[2]["hash"], "00000000000000ee");
+        assert_ne!(lines[1]["player_id"], lines[2]["player_id"]);
+        assert_eq!(lines[3]["takeback"], 2);
+    }
+
+    // Anyone joining a game that already has two players is a spectator, and is told so. The
+    // players aren't told they joined, since the creator's client would pick colors again, but
+    // everyone gets the new head count. Nothing a spectator sends is relayed.
+    #[tokio::test]
+    async fn test_spectators() {
+        let (addr, _) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let join = format!("/join/{}", game_id);
+        let mut second = connect(addr, &join).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut second, 2, 0).await;
+        let mut third = connect(addr, &join).await;
+        assert_eq!(recv(&mut third).await, json!({"role": "spectator"}));
+        for client in [&mut creator, &mut second, &mut third] {
+            assert_presence(client, 2, 1).await;
+        }
+
+        let m = json!({"src_row": 6, "src_col": 4, "dst_row": 4, "dst_col": 4});
+        send(&mut second, m.clone()).await;
+        assert_eq!(recv(&mut creator).await, m);
+        assert_eq!(recv(&mut third).await, m);
+        assert_silent(&mut second).await;
+        // The spectator's board is read-only, and the server doesn't pass on their moves either.
+        send(&mut third, m.clone()).await;
+        assert_silent(&mut creator).await;
+        assert_silent(&mut second).await;
+
+        third.close(None).await.unwrap();
+        for client in [&mut creator, &mut second] {
+            assert_presence(client, 2, 0).await;
+        }
+        // A player leaving frees their seat for whoever joins next.
+        second.close(None).await.unwrap();
+        assert!(recv(&mut creator).await["disconnected"].is_string());
+        assert_presence(&mut creator, 1, 0).await;
+        let mut fourth = connect(addr, &join).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut fourth, 2, 0).await;
+
+        // Joining only to watch leaves a free seat free.
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut watcher = connect(addr, &format!("/join/{}?watch=true", game_id)).await;
+        assert_eq!(recv(&mut watcher).await, json!({"role": "spectator"}));
+        assert_presence(&mut creator, 1, 1).await;
+        assert_presence(&mut watcher, 1, 1).await;
+        let _player = connect(addr, &format!("/join/{}", game_id)).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+    }
+
+    // Only players with the passcode get a seat in a private game. Anyone else watches, unless the
+    // creator turned spectators away too.
+    #[tokio::test]
+    async fn test_private_games() {
+        let (addr, games) = start();
+        let (mut creator, game_id) = create(addr, "/create?passcode=s3cret").await;
+        let mut watcher = connect(addr, &format!("/join/{}?passcode=guess", game_id)).await;
+        assert_eq!(recv(&mut watcher).await["role"], "spectator");
+        assert_presence(&mut creator, 1, 1).await;
+        assert_presence(&mut watcher, 1, 1).await;
+        let mut player = connect(addr, &format!("/join/{}?passcode=s3cret", game_id)).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 1).await;
+        assert_presence(&mut player, 2, 1).await;
+        // Without the passcode, the watcher can't play either.
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        send(&mut watcher, m).await;
+        send(&mut watcher, json!({"takeback": "accept", "ply": 1})).await;
+        assert_silent(&mut creator).await;
+        assert_silent(&mut player).await;
+
+        let (mut creator, game_id) = create(addr, "/create?passcode=s3cret&spectators=false").await;
+        let mut turned_away = connect(addr, &format!("/join/{}", game_id)).await;
+        assert_eq!(
+            recv(&mut turned_away).await,
+            json!({"denied": "This game is private"})
+        );
+        assert_closed(&mut turned_away).await;
+        assert_silent(&mut creator).await;
+
+        // Still private after a restart.
+        let path = std::env::temp_dir().join(format!("chess-games-{}.json", Uuid::new_v4()));
+        save_games(&path, &*games.read().await).unwrap();
+        let restored = load_games(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let game = &restored[&Uuid::parse_str(&game_id).unwrap()];
+        assert_eq!(game.passcode.as_deref(), Some("s3cret"));
+        assert!(game.no_spectators);
+
+        for bad in ["", "two%20words", &"x".repeat(MAX_PASSCODE_LEN + 1)] {
+            let url = format!("ws://{}/create?passcode={}", addr, bad);
+            assert!(connect_async(url).await.is_err(), "{}", bad);
+        }
+    }
+
+    // Both players in a rated game are told so, and spectators aren't.
+    #[tokio::test]
+    async fn test_rated_games() {
+        let (addr, games) = start();
+        let mut creator = connect(addr, "/create?rated=true").await;
+        let game_id = recv(&mut creator).await["game_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(recv(&mut creator).await, json!({"rated": true}));
+        assert_presence(&mut creator, 1, 0).await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        assert_eq!(recv(&mut joiner).await, json!({"rated": true}));
+        let mut watcher = connect(addr, &format!("/join/{}", game_id)).await;
+        assert_eq!(recv(&mut watcher).await["role"], "spectator");
+        assert_presence(&mut watcher, 2, 1).await;
+
+        let path = std::env::temp_dir().join(format!("chess-games-{}.json", Uuid::new_v4()));
+        save_games(&path, &*games.read().await).unwrap();
+        let restored = load_games(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(restored[&Uuid::parse_str(&game_id).unwrap()].rated);
+    }
+
+    // The creator's webhook hears about the game starting, each move and the result, in order.
+    #[tokio::test]
+    async fn test_webhooks() {
+        let (hook_tx, mut hook_rx) = mpsc::unbounded_channel();
+        let hook = warp::path("hook")
+            .and(warp::body::json())
+            .map(move |event: Value| {
+                hook_tx.send(event).unwrap();
+                "OK"
+            });
+        let (hook_addr, serve) = warp::serve(hook).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(serve);
+        async fn next_event(hook_rx: &mut mpsc::UnboundedReceiver<Value>) -> Value {
+            let event = timeout(Duration::from_secs(5), hook_rx.recv());
+            event.await.expect("no webhook call").unwrap()
+        }
+
+        let path = format!("/create?webhook=http://{}/hook", hook_addr);
+        let (addr, _) = start();
+        assert!(connect_async(format!("ws://{}{}", addr, path))
+            .await
+            .is_err());
+        let (addr, games) = start_with(Server {
+            allow_http_webhooks: true,
+            allow_private_webhooks: true,
+            ..Default::default()
+        });
+        let (mut creator, game_id) = create(addr, &path).await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_eq!(
+            next_event(&mut hook_rx).await,
+            json!({"event": "start", "game_id": game_id, "ruleset": null})
+        );
+        let mut watcher = connect(addr, &format!("/join/{}", game_id)).await;
+        assert_eq!(recv(&mut watcher).await["role"], "spectator");
+
+        send(&mut joiner, json!({"color": "white"})).await;
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        send(&mut creator, m.clone()).await;
+        // A spectator can't end the game.
+        let over = json!({"game_over": {"result": "1-0", "reason": "resignation"}});
+        send(&mut watcher, over).await;
+        sync(&mut watcher).await;
+        let id = Uuid::parse_str(&game_id).unwrap();
+        assert_eq!(games.read().await[&id].result, None);
+        send(
+            &mut joiner,
+            json!({"game_over": {"result": "0-1", "reason": "resignation"}}),
+        )
+        .await;
+        assert_eq!(
+            next_event(&mut hook_rx).await,
+            json!({"event": "move", "game_id": game_id, "move": m})
+        );
+        assert_eq!(
+            next_event(&mut hook_rx).await,
+            json!({"event": "end", "game_id": game_id, "result": "0-1", "reason": "resignation"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_and_rejoin() {
+        let (addr, games) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let join = format!("/join/{}", game_id);
+        let mut joiner = connect(addr, &join).await;
+        let joined = recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+
+        joiner.close(None).await.unwrap();
+        assert_eq!(
+            recv(&mut creator).await,
+            json!({ "disconnected": joined["joined"] })
+        );
+        assert_presence(&mut creator, 1, 0).await;
+
+        // The game stays open while anyone is in it, so the player can come back.
+        let mut rejoined = connect(addr, &join).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 0).await;
+        send(&mut rejoined, json!({"src_row": 7})).await;
+        assert_eq!(recv(&mut creator).await, json!({"src_row": 7}));
+
+        // Once everyone has left, the game is gone.
+        creator.close(None).await.unwrap();
+        rejoined.close(None).await.unwrap();
+        timeout(Duration::from_secs(5), async {
+            while !games.read().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("game wasn't removed");
+        let mut late = connect(addr, &join).await;
+        assert_closed(&mut late).await;
+    }
+
+    // A player who drops out has the grace period to come back. After that, the other player is
+    // told the game was abandoned, and the server records the result without waiting for them.
+    #[tokio::test]
+    async fn test_abandoned() {
+        let grace = Duration::from_millis(300);
+        let (addr, games) = start_with(Server {
+            abandon_grace: Some(grace),
+            abandon_result: "win",
+            ..Default::default()
+        });
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let join = format!("/join/{}", game_id);
+        let mut joiner = connect(addr, &join).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+
+        // Back in time.
+        joiner.close(None).await.unwrap();
+        assert!(recv(&mut creator).await["disconnected"].is_string());
+        assert_presence(&mut creator, 1, 0).await;
+        let mut joiner = connect(addr, &join).await;
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+        tokio::time::sleep(grace).await;
+        assert_silent(&mut creator).await;
+
+        // The creator plays white, and both players move.
+        send(&mut creator, json!({"color": "black"})).await;
+        assert_eq!(recv(&mut joiner).await, json!({"color": "black"}));
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        send(&mut creator, m.clone()).await;
+        assert_eq!(recv(&mut joiner).await, m);
+        send(&mut joiner, m.clone()).await;
+        assert_eq!(recv(&mut creator).await, m);
+
+        // Gone for good.
+        joiner.close(None).await.unwrap();
+        assert!(recv(&mut creator).await["disconnected"].is_string());
+        assert_presence(&mut creator, 1, 0).await;
+        assert_eq!(recv(&mut creator).await, json!({"abandoned": "win"}));
+        let game_id = Uuid::parse_str(&game_id).unwrap();
+        let won = Some(json!({"result": "1-0", "reason": "abandoned"}));
+        assert_eq!(games.read().await[&game_id].result, won);
+
+        // What the player reports afterwards doesn't change it.
+        let over = json!({"game_over": {"result": "1/2-1/2", "reason": "abandoned"}});
+        send(&mut creator, over).await;
+        sync(&mut creator).await;
+        assert_eq!(games.read().await[&game_id].result, won);
+        // Finished games aren't saved for after a restart.
+        let path = std::env::temp_dir().join(format!("chess-games-{}.json", Uuid::new_v4()));
+        assert_eq!(save_games(&path, &*games.read().await).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+
+        // Before both players have moved, it's aborted.
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        drop(joiner);
+        assert!(recv(&mut creator).await["disconnected"].is_string());
+        assert_presence(&mut creator, 1, 0).await;
+        assert_eq!(recv(&mut creator).await, json!({"abandoned": "win"}));
+        let game_id = Uuid::parse_str(&game_id).unwrap();
+        assert_eq!(
+            games.read().await[&game_id].result,
+            Some(json!({"result": "*", "reason": "abandoned"}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort() {
+        let overdue = Duration::from_millis(300);
+        let (addr, games) = start_with(Server {
+            first_move_timeout: Some(overdue),
+            ..Default::default()
+        });
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+
+        // Having made the first move, the creator has to wait for the joiner's.
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        send(&mut creator, m.clone()).await;
+        assert_eq!(recv(&mut joiner).await, m);
+        send(&mut creator, json!({"abort": true})).await;
+        assert_eq!(recv(&mut creator).await, json!({"abortable": false}));
+        assert_silent(&mut joiner).await;
+        assert_eq!(recv(&mut creator).await, json!({"abortable": true}));
+        assert_eq!(recv(&mut joiner).await, json!({"abortable": true}));
+
+        send(&mut creator, json!({"abort": true})).await;
+        assert_eq!(recv(&mut creator).await, json!({"aborted": true}));
+        assert_eq!(recv(&mut joiner).await, json!({"aborted": true}));
+        let game_id = Uuid::parse_str(&game_id).unwrap();
+        assert_eq!(
+            games.read().await[&game_id].result,
+            Some(json!({"result": "*", "reason": "aborted"}))
+        );
+
+        // Once both players have moved, the game has to be played out.
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+        send(&mut creator, m.clone()).await;
+        assert_eq!(recv(&mut joiner).await, m);
+        send(&mut joiner, m.clone()).await;
+        assert_eq!(recv(&mut creator).await, m);
+        send(&mut joiner, json!({"abort": true})).await;
+        assert_eq!(recv(&mut joiner).await, json!({"abortable": false}));
+        tokio::time::sleep(overdue).await;
+        assert_silent(&mut creator).await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let server = Server::default();
+        let (addr, games) = start_with(server.clone());
+        let ruleset = json!({"name": "Test"});
+        let (mut creator, game_id) = create(
+            addr,
+            &format!(
+                "/create?ruleset={}",
+                ruleset.to_string().replace('"', "%22")
+            ),
+        )
+        .await;
+        let mut joiner = connect(addr, &format!("/join/{}", game_id)).await;
+        recv(&mut creator).await;
+        recv(&mut joiner).await;
+        assert_presence(&mut creator, 2, 0).await;
+        assert_presence(&mut joiner, 2, 0).await;
+        // A game nobody's in anymore isn't saved.
+        games.write().await.insert(Uuid::new_v4(), Game::default());
+
+        let path = std::env::temp_dir().join(format!("chess-games-{}.json", Uuid::new_v4()));
+        let health = health_routes(server.clone());
+        let readyz = || warp::test::request().path("/readyz").reply(&health);
+        assert_eq!(readyz().await.status(), 200);
+        shutdown(&games, &server, Some(&path)).await;
+        assert_eq!(readyz().await.status(), 503);
+        let healthz = warp::test::request().path("/healthz").reply(&health);
+        assert_eq!(healthz.await.status(), 200);
+
+        for client in [&mut creator, &mut joiner] {
+            assert_eq!(
+                recv(client).await,
+                json!({"shutdown": "The server is shutting down"})
+            );
+            assert_closed(client).await;
+        }
+        assert_eq!(server.open_sockets.load(Ordering::SeqCst), 0);
+        // No new games while shutting down.
+        let url = format!("ws://{}/create", addr);
+        assert!(connect_async(url).await.is_err());
+
+        let restored = load_games(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let game_id = Uuid::parse_str(&game_id).unwrap();
+        assert_eq!(restored.len(), 1);
+        let ruleset = restored[&game_id].ruleset.as_deref().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(ruleset).unwrap(),
+            json!({"name": "Test"})
+        );
+        assert!(restored[&game_id].players.is_empty());
+        assert!(load_games(&path, false).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ui() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../ui");
+        let routes = ui_routes(Some(dir.to_string()));
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+        let res = get("/ui/").await;
+        assert_eq!(res.status(), 200);
+        assert!(String::from_utf8_lossy(res.body()).contains("<canvas"));
+        assert_eq!(get("/ui/assets/js/mem.js").await.status(), 200);
+        assert_eq!(get("/ui/nothing.js").await.status(), 404);
+    }
+
+    // Build with UI_BUNDLE_DIR pointing at a directory with an index.html, e.g. ui/.
+    #[cfg(feature = "embed-ui")]
+    #[tokio::test]
+    async fn test_embedded_ui() {
+        let routes = ui_routes(None);
+        let res = warp::test::request().path("/ui/").reply(&routes).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "text/html");
+        let res = warp::test::request()
+            .path("/ui/nothing.js")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_log_filter() {
+        // The handle only works while the layer is around.
+        let (_layer, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("info"));
+        let routes = log_routes(handle, true);
+        let put = |ip: [u8; 4], filter: &str| {
+            warp::test::request()
+                .method("PUT")
+                .path(&format!("/log?filter={}", filter))
+                .remote_addr(SocketAddr::from((ip, 40000)))
+        };
+        let status = |req: warp::test::RequestBuilder| async { req.reply(&routes).await.status() };
+        assert_eq!(status(put([127, 0, 0, 1], "server=debug")).await, 200);
+        assert_eq!(status(put([127, 0, 0, 1], "server=loud")).await, 400);
+        assert_eq!(status(put([192, 168, 0, 2], "server=debug")).await, 403);
+        // Through a reverse proxy on the same machine.
+        let proxied = put([127, 0, 0, 1], "server=debug").header("x-forwarded-for", "192.168.0.2");
+        assert_eq!(status(proxied).await, 403);
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests() {
+        let (addr, _) = start();
+        assert!(connect_async(format!("ws://{}/join/not-a-game", addr))
+            .await
+            .is_err());
+        assert!(
+            connect_async(format!("ws://{}/create?ruleset=%7Bnope", addr))
+                .await
+                .is_err()
+        );
+        let mut unknown = connect(addr, &format!("/join/{}", Uuid::new_v4())).await;
+        assert_closed(&mut unknown).await;
+    }
+
+    // Reads a stream of server-sent events.
+    struct Events {
+        response: reqwest::Response,
+        buffer: String,
+    }
+
+    impl Events {
+        async fn open(url: String) -> Events {
+            let response = reqwest::get(url).await.unwrap().error_for_status().unwrap();
+            Events {
+                response,
+                buffer: String::new(),
+            }
+        }
+
+        // The next event's name, if it has one, and data.
+        async fn next(&mut self) -> (Option<String>, String) {
+            loop {
+                if let Some((event, rest)) = self.buffer.split_once("\n\n") {
+                    let (event, rest) = (event.to_string(), rest.to_string());
+                    self.buffer = rest;
+                    let field = |name: &str| {
+                        event
+                            .lines()
+                            .find_map(|l| l.strip_prefix(name))
+                            .map(str::to_string)
+                    };
+                    if let Some(data) = field("data:") {
+                        return (field("event:"), data);
+                    }
+                    continue;
+                }
+                let chunk = timeout(Duration::from_secs(5), self.response.chunk())
+                    .await
+                    .expect("timed out waiting for an event")
+                    .unwrap()
+                    .expect("stream closed");
+                self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+        }
+
+        async fn message(&mut self) -> Value {
+            serde_json::from_str(&self.next().await.1).unwrap()
+        }
+    }
+
+    // A player whose network blocks websockets plays with one who has them.
+    #[tokio::test]
+    async fn test_sse() {
+        let (addr, _) = start();
+        let (mut creator, game_id) = create(addr, "/create").await;
+        let mut events = Events::open(format!("http://{}/sse/join/{}", addr, game_id)).await;
+        let (name, session) = events.next().await;
+        assert_eq!(name.as_deref(), Some("session"));
+        assert_eq!(
+            events.message().await,
+            json!({"presence": {"players": 2, "spectators": 0}})
+        );
+        assert!(recv(&mut creator).await["joined"].is_string());
+        assert_presence(&mut creator, 2, 0).await;
+
+        // Moves come as JSON, however they were sent.
+        let frame = [b'M', 0x14, 0x34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        creator
+            .send(WsMessage::binary(frame.to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(
+            events.message().await,
+            json!({"src_row": 1, "src_col": 4, "dst_row": 3, "dst_col": 4})
+        );
+        let client = reqwest::Client::new();
+        let post = |body: &str| {
+            client
+                .post(format!("http://{}/sse/send/{}", addr, session))
+                .body(body.to_string())
+                .send()
+        };
+        let m = json!({"src_row": 6, "src_col": 4, "dst_row": 4, "dst_col": 4});
+        assert_eq!(post(&m.to_string()).await.unwrap().status(), 200);
+        assert_eq!(recv(&mut creator).await, m);
+        let unknown = client
+            .post(format!("http://{}/sse/send/{}", addr, Uuid::new_v4()))
+            .body("{}")
+            .send();
+        assert_eq!(unknown.await.unwrap().status(), 404);
+
+        // Closing the stream leaves the game.
+        drop(events);
+        assert!(recv(&mut creator).await["disconnected"].is_string());
+        assert_presence(&mut creator, 1, 0).await;
+        assert_eq!(post("{}").await.unwrap().status(), 404);
+    }
+}
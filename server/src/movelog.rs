@@ -0,0 +1,329 @@
+// An audit trail of the moves relayed in each game, for checking what happened in a game later or
+// importing games elsewhere. Each game's moves are appended to <dir>/<game_id>.ndjson, one JSON
+// object per line. The first line describes the game:
+//   {"game_id": "...", "ruleset": {...} or null, "time_ms": 1700000000000}
+// and each move after that has the fields of a JSON move message (see ui/src/protocol.rs),
+// whichever encoding it was sent in, plus who sent it and when:
+//   {"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5, "hash": "...", "player_id": "...", ...}
+// When the players agree to take back moves, the player who accepted logs the ply they went back
+// to (see ui/src/protocol.rs):
+//   {"takeback": 3, "player_id": "...", "time_ms": ...}
+// The game's result is logged once, as the first player to report it did:
+//   {"result": "1-0", "reason": "abandoned", "player_id": "...", "time_ms": ...}
+// Once a file would grow past max_bytes, it's renamed to <game_id>.ndjson.1 (then .2, and so on)
+// and a new one is started. `chess-ui --replay <file>` reads the files back and checks the moves.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+use warp::ws::Message;
+
+use crate::{is_move_frame, MOVE_FRAME_LEN};
+
+const DEFAULT_MAX_BYTES: u64 = 1 << 20;
+
+pub struct MoveLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    // Held while appending, so one player's move can't land in the middle of a rotation.
+    lock: Mutex<()>,
+}
+
+impl MoveLog {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(MoveLog {
+            dir,
+            max_bytes,
+            lock: Mutex::new(()),
+        })
+    }
+
+    // Logs to MOVE_LOG_DIR, rotating at MOVE_LOG_MAX_BYTES (default 1 MiB). None if MOVE_LOG_DIR
+    // isn't set.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let dir = match std::env::var("MOVE_LOG_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => return Ok(None),
+        };
+        let max_bytes = match std::env::var("MOVE_LOG_MAX_BYTES") {
+            Ok(n) => n.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid MOVE_LOG_MAX_BYTES: {}", n),
+                )
+            })?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        MoveLog::new(dir, max_bytes).map(Some)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Where the game's moves are being logged. Earlier ones may be in rotated files next to it.
+    pub fn path(&self, game_id: Uuid) -> PathBuf {
+        self.dir().join(format!("{}.ndjson", game_id))
+    }
+
+    // Everything logged for the game, the rotated files first.
+    pub fn read(&self, game_id: Uuid) -> io::Result<String> {
+        let path = self.path(game_id);
+        let mut log = String::new();
+        for n in 1.. {
+            match fs::read_to_string(format!("{}.{}", path.display(), n)) {
+                Ok(s) => log.push_str(&s),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e),
+            }
+        }
+        log.push_str(&fs::read_to_string(&path)?);
+        Ok(log)
+    }
+
+    pub fn start_game(&self, game_id: Uuid, ruleset: Option<&str>) -> io::Result<()> {
+        let ruleset = ruleset.and_then(|r| serde_json::from_str::<Value>(r).ok());
+        self.append(
+            game_id,
+            json!({"game_id": game_id.to_string(), "ruleset": ruleset, "time_ms": now_ms()}),
+        )
+    }
+
+    // entry is from log_entry.
+    pub fn record(&self, game_id: Uuid, player_id: Uuid, mut entry: Value) -> io::Result<()> {
+        entry["player_id"] = json!(player_id.to_string());
+        entry["time_ms"] = json!(now_ms());
+        self.append(game_id, entry)
+    }
+
+    fn append(&self, game_id: Uuid, entry: Value) -> io::Result<()> {
+        let mut line = entry.to_string();
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap();
+        let path = self.path(game_id);
+        let size = match fs::metadata(&path) {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            rotate(&path)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+// Renames the file to the first of <path>.1, <path>.2, ... that isn't taken.
+fn rotate(path: &Path) -> io::Result<()> {
+    let rotated = (1..)
+        .map(|n| PathBuf::from(format!("{}.{}", path.display(), n)))
+        .find(|p| !p.exists())
+        .unwrap();
+    fs::rename(path, rotated)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// What to log for msg: a move, or an accepted takeback. None for anything else.
+pub fn log_entry(msg: &Message) -> Option<Value> {
+    parse_move(msg).or_else(|| parse_takeback(msg))
+}
+
+// {"takeback": ply} if msg accepts a takeback.
+fn parse_takeback(msg: &Message) -> Option<Value> {
+    let data: Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+    match (data["takeback"].as_str(), data["ply"].as_u64()) {
+        (Some("accept"), Some(ply)) if ply >= 1 => Some(json!({ "takeback": ply })),
+        _ => None,
+    }
+}
+
+// {"result", "reason"} if msg says the game is over (see ui/src/protocol.rs).
+pub fn parse_result(msg: &Message) -> Option<Value> {
+    let data: Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+    match (
+        data["game_over"]["result"].as_str(),
+        data["game_over"]["reason"].as_str(),
+    ) {
+        (Some(result), Some(reason)) => Some(json!({"result": result, "reason": reason})),
+        _ => None,
+    }
+}
+
+// The move in msg as the fields of a JSON move message, leaving out the ones that are unset, like
+// the ui does. None if msg isn't a move, or its squares aren't on the board. Whether the move is
+// legal is up to the players.
+pub fn parse_move(msg: &Message) -> Option<Value> {
+    // TODO: get board size from the rule set
+    let on_board = |n: u64| (1..=8).contains(&n);
+    let m = if msg.is_binary() {
+        let f = msg.as_bytes();
+        if !is_move_frame(f) {
+            return None;
+        }
+        let mut m = json!({
+            "src_row": f[1] >> 4,
+            "src_col": f[1] & 0xf,
+            "dst_row": f[2] >> 4,
+            "dst_col": f[2] & 0xf,
+        });
+        if f[3] != 0 {
+            m["promotion"] = json!((f[3] as char).to_string());
+        }
+        if f[4] != 0 {
+            m["flags"] = json!(f[4]);
+        }
+        let clock_ms = u32::from_le_bytes(f[5..9].try_into().unwrap());
+        if clock_ms != 0 {
+            m["clock_ms"] = json!(clock_ms);
+        }
+        let hash = u64::from_le_bytes(f[9..17].try_into().unwrap());
+        if hash != 0 {
+            m["hash"] = json!(format!("{:016x}", hash));
+        }
+        if f.len() > MOVE_FRAME_LEN {
+            let via: Vec<[u8; 2]> = f[MOVE_FRAME_LEN..]
+                .iter()
+                .map(|b| [b >> 4, b & 0xf])
+                .collect();
+            m["via"] = json!(via);
+        }
+        m
+    } else {
+        let data: Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+        let fields = [
+            "src_row",
+            "src_col",
+            "dst_row",
+            "dst_col",
+            "promotion",
+            "flags",
+            "clock_ms",
+            "hash",
+            "via",
+        ];
+        let m: serde_json::Map<String, Value> = fields
+            .iter()
+            .filter(|&&k| !data[k].is_null())
+            .map(|&k| (k.to_string(), data[k].clone()))
+            .collect();
+        Value::Object(m)
+    };
+    let squares = ["src_row", "src_col", "dst_row", "dst_col"];
+    let via_on_board = match &m["via"] {
+        Value::Null => true,
+        Value::Array(via) => via
+            .iter()
+            .flat_map(|s| [&s[0], &s[1]])
+            .all(|n| n.as_u64().is_some_and(on_board)),
+        _ => false,
+    };
+    if via_on_board && squares.iter().all(|&k| m[k].as_u64().is_some_and(on_board)) {
+        Some(m)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_move() {
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        assert_eq!(parse_move(&Message::text(m.to_string())), Some(m));
+
+        let mut frame = vec![b'M', 0x72, 0x81, b'q', 1, 0xe8, 0x03, 0, 0];
+        frame.extend_from_slice(&0x0123_4567_89ab_cdefu64.to_le_bytes());
+        let expected = json!({
+            "src_row": 7, "src_col": 2, "dst_row": 8, "dst_col": 1, "promotion": "q", "flags": 1,
+            "clock_ms": 1000, "hash": "0123456789abcdef",
+        });
+        assert_eq!(parse_move(&Message::binary(frame.clone())), Some(expected));
+        // A chain of jumps.
+        let mut chain = frame.clone();
+        chain.extend([0x54, 0x76]);
+        assert_eq!(
+            parse_move(&Message::binary(chain)).unwrap().get("via"),
+            Some(&json!([[5, 4], [7, 6]]))
+        );
+        frame[1] = 0x92;
+        assert_eq!(parse_move(&Message::binary(frame)), None);
+        let bad_via =
+            json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5, "via": [[9, 1]]});
+        assert_eq!(parse_move(&Message::text(bad_via.to_string())), None);
+
+        assert_eq!(parse_move(&Message::text(r#"{"src_row": 7}"#)), None);
+        assert_eq!(parse_move(&Message::text(r#"{"color": "black"}"#)), None);
+        assert_eq!(parse_move(&Message::binary(vec![1, 2, 3])), None);
+
+        let accept = Message::text(r#"{"takeback": "accept", "ply": 3}"#);
+        assert_eq!(log_entry(&accept), Some(json!({"takeback": 3})));
+        let request = Message::text(r#"{"takeback": "request", "ply": 3}"#);
+        assert_eq!(log_entry(&request), None);
+
+        let over = Message::text(r#"{"game_over": {"result": "*", "reason": "abandoned"}}"#);
+        assert_eq!(
+            parse_result(&over),
+            Some(json!({"result": "*", "reason": "abandoned"}))
+        );
+        assert_eq!(parse_result(&accept), None);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("chess-moves-{}", Uuid::new_v4()));
+        let log = MoveLog::new(dir.clone(), 200).unwrap();
+        let game_id = Uuid::new_v4();
+        log.start_game(game_id, Some(r#"{"name": "Test"}"#))
+            .unwrap();
+        let m = json!({"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5});
+        for _ in 0..3 {
+            log.record(game_id, Uuid::new_v4(), m.clone()).unwrap();
+        }
+
+        let path = log.path(game_id);
+        assert_eq!(log.read(game_id).unwrap().lines().count(), 4);
+        let read = |p: PathBuf| -> Vec<Value> {
+            fs::read_to_string(p)
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        };
+        let rotated = |n| PathBuf::from(format!("{}.{}", path.display(), n));
+        let first = read(rotated(1));
+        assert_eq!(first[0]["ruleset"], json!({"name": "Test"}));
+        let rest: Vec<Value> = (2..)
+            .map(rotated)
+            .take_while(|p| p.exists())
+            .chain([path.clone()])
+            .flat_map(read)
+            .collect();
+        let moves: Vec<&Value> = first[1..].iter().chain(&rest).collect();
+        assert_eq!(moves.len(), 3);
+        for mv in moves {
+            assert_eq!(mv["src_row"], 2);
+            assert!(mv["player_id"].is_string());
+            assert!(mv["time_ms"].as_u64().unwrap() > 0);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
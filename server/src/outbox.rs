@@ -0,0 +1,156 @@
+// The messages waiting to be sent to a player. A player whose connection can't keep up, e.g. a
+// spectator on a bad network, mustn't make the server hold on to everything sent to them, so at
+// most OUTBOX_LEN messages wait. Messages that only say how things are now, like presence, replace
+// the one of their kind still waiting rather than adding to the queue, and a player who falls
+// further behind than that is disconnected: they get a close frame instead of the rest, and can
+// rejoin the game with its link.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{stream, Stream};
+use tokio::sync::Notify;
+use tracing::{warn, Span};
+use warp::ws::Message;
+
+// Far more than a game sends in the time a connection that's keeping up takes to send it.
+const OUTBOX_LEN: usize = 256;
+
+#[derive(Default)]
+struct Queue {
+    // Each message with its kind, if a later one of the same kind replaces it.
+    messages: VecDeque<(Option<&'static str>, Message)>,
+    // Nothing more will be queued: the sender is gone or the player was too slow.
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Notify,
+    // The player's, for logging that they were disconnected from wherever it happens.
+    span: Span,
+}
+
+// The game's end, which queues messages for the player.
+pub struct Sender(Arc<Shared>);
+
+// The end that sends the messages over the player's connection.
+pub struct Receiver(Arc<Shared>);
+
+// The player's connection has closed, or they were disconnected for being too slow.
+#[derive(Debug)]
+pub struct Disconnected;
+
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::default(),
+        ready: Notify::new(),
+        span: Span::current(),
+    });
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+impl Sender {
+    pub fn send(&self, msg: Message) -> Result<(), Disconnected> {
+        self.push(None, msg)
+    }
+
+    // Queues a message that replaces any of the same kind that's still waiting, e.g. "presence".
+    pub fn send_latest(&self, kind: &'static str, msg: Message) -> Result<(), Disconnected> {
+        self.push(Some(kind), msg)
+    }
+
+    fn push(&self, kind: Option<&'static str>, msg: Message) -> Result<(), Disconnected> {
+        let mut queue = self.0.queue.lock().unwrap();
+        if queue.closed {
+            return Err(Disconnected);
+        }
+        if kind.is_some() {
+            queue.messages.retain(|(k, _)| *k != kind);
+        }
+        if queue.messages.len() >= OUTBOX_LEN {
+            self.0
+                .span
+                .in_scope(|| warn!("player too slow, disconnecting"));
+            queue.messages.clear();
+            queue.messages.push_back((None, Message::close()));
+            queue.closed = true;
+            self.0.ready.notify_one();
+            return Err(Disconnected);
+        }
+        queue.messages.push_back((kind, msg));
+        self.0.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().closed = true;
+        self.0.ready.notify_one();
+    }
+}
+
+impl Receiver {
+    // The next message, or None once everything queued has been sent and no more will be.
+    pub async fn recv(&self) -> Option<Message> {
+        loop {
+            {
+                let mut queue = self.0.queue.lock().unwrap();
+                if let Some((_, msg)) = queue.messages.pop_front() {
+                    return Some(msg);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            self.0.ready.notified().await;
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Message> {
+        stream::unfold(
+            self,
+            |rx| async move { rx.recv().await.map(|msg| (msg, rx)) },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let (tx, rx) = channel();
+        tx.send(Message::text("joined")).unwrap();
+        tx.send_latest("presence", Message::text("2 players"))
+            .unwrap();
+        tx.send(Message::text("move")).unwrap();
+        tx.send_latest("presence", Message::text("1 player"))
+            .unwrap();
+        assert_eq!(rx.recv().await, Some(Message::text("joined")));
+        assert_eq!(rx.recv().await, Some(Message::text("move")));
+        assert_eq!(rx.recv().await, Some(Message::text("1 player")));
+
+        // Presence alone never fills the queue.
+        for _ in 0..OUTBOX_LEN * 2 {
+            tx.send_latest("presence", Message::text("presence"))
+                .unwrap();
+        }
+        for _ in 1..OUTBOX_LEN {
+            tx.send(Message::text("move")).unwrap();
+        }
+        assert!(tx.send(Message::text("move")).is_err());
+        assert_eq!(rx.recv().await, Some(Message::close()));
+        assert!(tx.send(Message::text("move")).is_err());
+
+        let (tx, rx) = channel();
+        tx.send(Message::text("shutdown")).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(Message::text("shutdown")));
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,218 @@
+// Players' preferences, e.g. their theme, sounds and auto-queen, kept by the server so they follow a
+// player from device to device. There are no accounts: a player's user ID is a UUID their client
+// makes up once and keeps, like a password, and they log in with it on a game's websocket:
+//   {"login": "<user>"}
+// The server answers with what it has for them, or {} if nothing yet, in a UserPrefs message (see
+// ui/src/protocol.rs):
+//   {"prefs": {"theme": "wood", "sounds": false, ...}}
+// and a logged-in player sends the same message whenever they change them. Neither is relayed to
+// the other players. The server doesn't look inside the preferences, which are the ui's settings
+// (see ui/src/settings.rs), but keeps at most MAX_PREFS_LEN bytes of them per player, for the
+// MAX_USERS players who most recently logged in or saved. If PREFS_FILE is set, they're saved
+// there after every change, by a task of their own so players don't wait for the disk, and loaded
+// on startup.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+use tracing::{warn, Instrument};
+use uuid::Uuid;
+use warp::ws::Message;
+
+// Plenty for the ui's settings.
+const MAX_PREFS_LEN: usize = 4096;
+// Anyone can make up a user ID, so this many users is all the server keeps, about 40 MB at most.
+// A new one makes way for whoever logged in or saved least recently.
+const MAX_USERS: usize = 10_000;
+
+#[derive(Default)]
+pub struct Prefs {
+    path: Option<PathBuf>,
+    // Each user's preferences, and when they were last used, counted in uses.
+    users: Mutex<HashMap<Uuid, (u64, Value)>>,
+    // Counts logins and saves. Those loaded on startup count as used before any.
+    uses: AtomicU64,
+    // Wakes the task saving them to path (see save_on_change).
+    changed: Notify,
+    // Held while saving, so saves don't overlap and the last one has the latest changes.
+    saving: tokio::sync::Mutex<()>,
+}
+
+// A message from a player about their preferences.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Login(Uuid),
+    Save(Value),
+}
+
+impl Prefs {
+    // Kept in PREFS_FILE, if it's set, and otherwise only until the server stops. A missing file
+    // means no one has saved any yet.
+    pub fn from_env() -> Result<Arc<Prefs>, String> {
+        let Ok(path) = std::env::var("PREFS_FILE").map(PathBuf::from) else {
+            return Ok(Arc::default());
+        };
+        let users = match std::fs::read_to_string(&path) {
+            Ok(data) => {
+                let saved: HashMap<String, Value> = serde_json::from_str(&data)
+                    .map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+                saved
+                    .into_iter()
+                    .map(|(user, prefs)| {
+                        Uuid::parse_str(&user)
+                            .map(|user| (user, (0, prefs)))
+                            .map_err(|_| format!("invalid user ID in {}: {}", path.display(), user))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+        };
+        let prefs = Arc::new(Prefs {
+            path: Some(path),
+            users: Mutex::new(users),
+            ..Default::default()
+        });
+        tokio::spawn(save_on_change(prefs.clone()).in_current_span());
+        Ok(prefs)
+    }
+
+    // The user's preferences, or an empty object if they haven't saved any.
+    pub fn get(&self, user: Uuid) -> Value {
+        let mut users = self.users.lock().unwrap();
+        match users.get_mut(&user) {
+            Some((used, prefs)) => {
+                *used = self.uses.fetch_add(1, Ordering::Relaxed) + 1;
+                prefs.clone()
+            }
+            None => json!({}),
+        }
+    }
+
+    // Replaces the user's preferences, which must be a JSON object.
+    pub fn set(&self, user: Uuid, prefs: Value) -> Result<(), String> {
+        if !prefs.is_object() {
+            return Err(format!("preferences must be an object: {}", prefs));
+        }
+        if prefs.to_string().len() > MAX_PREFS_LEN {
+            return Err("preferences too long".to_string());
+        }
+        let mut users = self.users.lock().unwrap();
+        if users.len() >= MAX_USERS && !users.contains_key(&user) {
+            let least_recent = users.iter().min_by_key(|(_, (used, _))| *used);
+            if let Some(&least_recent) = least_recent.map(|(u, _)| u) {
+                users.remove(&least_recent);
+            }
+        }
+        let used = self.uses.fetch_add(1, Ordering::Relaxed) + 1;
+        users.insert(user, (used, prefs));
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    // Writes everyone's preferences to PREFS_FILE, if it's set.
+    pub async fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _saving = self.saving.lock().await;
+        let saved: serde_json::Map<String, Value> = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(user, (_, prefs))| (user.to_string(), prefs.clone()))
+            .collect();
+        tokio::fs::write(path, Value::Object(saved).to_string())
+            .await
+            .map_err(|e| format!("couldn't save preferences to {}: {}", path.display(), e))
+    }
+}
+
+// Saves the preferences after each change. Changes made while they're being saved are saved
+// together next.
+async fn save_on_change(prefs: Arc<Prefs>) {
+    loop {
+        prefs.changed.notified().await;
+        if let Err(e) = prefs.save().await {
+            warn!("{}", e);
+        }
+    }
+}
+
+// The player's login or preferences, if that's what the message is, or an error if it's one of
+// them but invalid, e.g. a user ID that isn't a UUID.
+pub fn parse(msg: &Message) -> Option<Result<Request, String>> {
+    let data: Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+    if let Some(user) = data.get("login") {
+        return Some(
+            user.as_str()
+                .and_then(|u| Uuid::parse_str(u).ok())
+                .map(Request::Login)
+                .ok_or_else(|| format!("invalid user ID: {}", user)),
+        );
+    }
+    data.get("prefs")
+        .map(|prefs| Ok(Request::Save(prefs.clone())))
+}
+
+// The UserPrefs message telling a player what their preferences are.
+pub fn message(prefs: &Value) -> Message {
+    Message::text(json!({ "prefs": prefs }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prefs() {
+        let user = Uuid::new_v4();
+        let login = Message::text(format!(r#"{{"login": "{}"}}"#, user));
+        assert_eq!(parse(&login), Some(Ok(Request::Login(user))));
+        assert!(matches!(
+            parse(&Message::text(r#"{"login": "me"}"#)),
+            Some(Err(_))
+        ));
+        assert_eq!(parse(&Message::text(r#"{"abort": true}"#)), None);
+
+        let path = std::env::temp_dir().join(format!("prefs-{}.json", Uuid::new_v4()));
+        let prefs = Prefs {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(prefs.get(user), json!({}));
+        let theme = json!({"theme": "wood", "sounds": false});
+        let save = Message::text(json!({ "prefs": theme }).to_string());
+        assert_eq!(parse(&save), Some(Ok(Request::Save(theme.clone()))));
+        prefs.set(user, theme.clone()).unwrap();
+        assert!(prefs.set(user, json!("wood")).is_err());
+        assert!(prefs
+            .set(user, json!({ "theme": "x".repeat(MAX_PREFS_LEN) }))
+            .is_err());
+        assert_eq!(prefs.get(user), theme);
+
+        // Once there are too many users, a new one makes way for the least recently used.
+        let idle = (1..MAX_USERS).map(|_| (Uuid::new_v4(), (0, json!({}))));
+        prefs.users.lock().unwrap().extend(idle);
+        let newcomer = Uuid::new_v4();
+        prefs.set(newcomer, theme.clone()).unwrap();
+        assert_eq!(prefs.users.lock().unwrap().len(), MAX_USERS);
+        assert_eq!(prefs.get(newcomer), theme);
+        assert_eq!(prefs.get(user), theme);
+
+        // Saved for the next run.
+        prefs.save().await.unwrap();
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[user.to_string()], theme);
+        std::fs::remove_file(path).unwrap();
+    }
+}
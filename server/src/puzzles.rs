@@ -0,0 +1,486 @@
+// Puzzles for the puzzle mode, found in the games in the move log (see movelog.rs). They're kept
+// in puzzles.jsonl next to the logs, one JSON object per line, and read back on startup. With
+// PUZZLE_GENERATOR pointing at the desktop ui's binary, each game is run through
+// chess-ui --puzzles (see ui/src/puzzles.rs) once its result is in, searching PUZZLE_DEPTH plies
+// deep (3 by default), and the puzzles it finds are added. Games logged before can be added by
+// running chess-ui --puzzles on their logs and appending what it prints to the file.
+//
+// GET /api/puzzles/random answers with one of them, or 404 if there are none yet:
+//   {"id": "<game ID>-<ply>", "game_id": "...", "fen": "...", "moves": ["d5c7", "e8d7", "c7a8"]}
+//
+// GET /api/puzzles/daily?user=<user> answers with the puzzle of the day, the same for everyone
+// until midnight UTC, with the day's number since 1970 and the user's streak: how many days in a
+// row up to today or yesterday they've solved it.
+//   {"id": "...", ..., "day": 20742, "streak": 3}
+// A player who solves it without a wrong move says so with POST /api/puzzles/daily/solved, which
+// answers with their streak:
+//   {"user": "<user>", "id": "<puzzle ID>"} -> {"streak": 4}
+// User IDs are the ones the ui makes up for preferences (see prefs.rs). Streaks are saved in
+// puzzle_streaks.json, next to the puzzles, by a task of their own after each solve. Since anyone
+// can make up a user ID, at most MAX_STREAKS are kept, and broken ones make way for new ones.
+
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+use tracing::{info, warn, Instrument};
+use uuid::Uuid;
+use warp::{http, Filter, Reply};
+
+use crate::Server;
+
+const DEFAULT_DEPTH: u32 = 3;
+// About 6 MB of streaks at most.
+const MAX_STREAKS: usize = 100_000;
+
+pub struct Puzzles {
+    // The desktop ui binary, if puzzles are looked for in new games.
+    generator: Option<PathBuf>,
+    // How deep the engine searches each position.
+    depth: u32,
+    // Where the move logs are, and puzzles.jsonl.
+    dir: PathBuf,
+    puzzles: RwLock<Vec<Value>>,
+    // The day's puzzle, once picked: puzzles added during the day don't change it.
+    daily: Mutex<Option<(u64, usize)>>,
+    // The day each user last solved the daily puzzle, and how many days in a row up to it.
+    streaks: Mutex<HashMap<Uuid, (u64, u64)>>,
+    // Wakes the task saving the streaks, and is held while saving, as for preferences (see
+    // prefs.rs).
+    streaks_changed: Notify,
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Puzzles {
+    // Reads the puzzles found so far in dir, skipping lines that aren't JSON.
+    pub fn load(generator: Option<PathBuf>, depth: u32, dir: &Path) -> io::Result<Self> {
+        let path = dir.join("puzzles.jsonl");
+        let puzzles = match std::fs::read_to_string(&path) {
+            Ok(s) => s
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .filter_map(|l| match serde_json::from_str(l) {
+                    Ok(puzzle) => Some(puzzle),
+                    Err(e) => {
+                        warn!("skipping a puzzle in {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        info!(puzzles = puzzles.len(), "loaded puzzles");
+        Ok(Puzzles {
+            generator,
+            depth,
+            dir: dir.to_path_buf(),
+            puzzles: RwLock::new(puzzles),
+            daily: Mutex::new(None),
+            streaks: Mutex::new(load_streaks(&dir.join("puzzle_streaks.json"))?),
+            streaks_changed: Notify::new(),
+            saving: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    // The puzzles in dir, with PUZZLE_GENERATOR looking for more at PUZZLE_DEPTH if it's set, and
+    // the streaks saved as they change.
+    pub fn from_env(dir: &Path) -> Result<Arc<Self>, String> {
+        let generator = std::env::var("PUZZLE_GENERATOR").ok().map(PathBuf::from);
+        let depth = match std::env::var("PUZZLE_DEPTH") {
+            Ok(d) => d
+                .parse()
+                .map_err(|_| format!("invalid PUZZLE_DEPTH: {}", d))?,
+            Err(_) => DEFAULT_DEPTH,
+        };
+        let puzzles = Arc::new(Puzzles::load(generator, depth, dir).map_err(|e| e.to_string())?);
+        tokio::spawn(save_on_change(puzzles.clone()).in_current_span());
+        Ok(puzzles)
+    }
+
+    pub fn generates(&self) -> bool {
+        self.generator.is_some()
+    }
+
+    // Looks for puzzles in the game's move log and adds the new ones, returning how many there
+    // were. The engine takes a while, so it runs on a blocking thread.
+    pub async fn generate(&self, game_id: Uuid) -> io::Result<usize> {
+        let Some(generator) = &self.generator else {
+            return Ok(0);
+        };
+        let log = self.dir.join(format!("{}.ndjson", game_id));
+        let mut command = Command::new(generator);
+        command
+            .arg("--puzzles")
+            .arg(log)
+            .arg("--depth")
+            .arg(self.depth.to_string());
+        let output = tokio::task::spawn_blocking(move || command.output()).await??;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "generator failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let found = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        self.add(found)
+    }
+
+    // Saves the puzzles that aren't in the store yet, and returns how many there were.
+    fn add(&self, found: Vec<Value>) -> io::Result<usize> {
+        let mut puzzles = self.puzzles.write().unwrap();
+        let new: Vec<Value> = found
+            .into_iter()
+            .filter(|p| !puzzles.iter().any(|q| q["id"] == p["id"]))
+            .collect();
+        if new.is_empty() {
+            return Ok(0);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("puzzles.jsonl"))?;
+        let mut lines = String::new();
+        for p in &new {
+            lines.push_str(&format!("{}\n", p));
+        }
+        file.write_all(lines.as_bytes())?;
+        let count = new.len();
+        puzzles.extend(new);
+        Ok(count)
+    }
+
+    // One of the puzzles, picked at random.
+    fn random(&self) -> Option<Value> {
+        let puzzles = self.puzzles.read().unwrap();
+        if puzzles.is_empty() {
+            return None;
+        }
+        let i = Uuid::new_v4().as_u128() % puzzles.len() as u128;
+        Some(puzzles[i as usize].clone())
+    }
+
+    // The puzzle of the day, picked by hashing the day so it doesn't follow the order they were
+    // found in.
+    fn daily(&self, day: u64) -> Option<Value> {
+        let puzzles = self.puzzles.read().unwrap();
+        if puzzles.is_empty() {
+            return None;
+        }
+        let mut daily = self.daily.lock().unwrap();
+        let i = match *daily {
+            Some((d, i)) if d == day => i,
+            _ => {
+                let i = (day.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % puzzles.len();
+                *daily = Some((day, i));
+                i
+            }
+        };
+        Some(puzzles[i].clone())
+    }
+
+    // The user's streak on the day: 0 if they didn't solve the puzzle that day or the one before.
+    fn streak(&self, user: Uuid, day: u64) -> u64 {
+        match self.streaks.lock().unwrap().get(&user) {
+            Some(&(last, count)) if last + 1 >= day => count,
+            _ => 0,
+        }
+    }
+
+    // Counts the day's puzzle as solved by the user, and returns their streak.
+    fn solved(&self, user: Uuid, id: &str, day: u64) -> Result<u64, String> {
+        if self.daily(day).is_none_or(|p| p["id"] != id) {
+            return Err(format!("{} isn't the puzzle of the day", id));
+        }
+        let mut streaks = self.streaks.lock().unwrap();
+        let count = match streaks.get(&user) {
+            Some(&(last, count)) if last == day => return Ok(count),
+            Some(&(last, count)) if last + 1 == day => count + 1,
+            None if streaks.len() >= MAX_STREAKS => {
+                streaks.retain(|_, &mut (last, _)| last + 1 >= day);
+                if streaks.len() >= MAX_STREAKS {
+                    return Err("too many streaks".to_string());
+                }
+                1
+            }
+            _ => 1,
+        };
+        streaks.insert(user, (day, count));
+        self.streaks_changed.notify_one();
+        Ok(count)
+    }
+
+    // Writes the streaks to puzzle_streaks.json.
+    pub async fn save_streaks(&self) -> Result<(), String> {
+        let _saving = self.saving.lock().await;
+        let saved: serde_json::Map<String, Value> = self
+            .streaks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(user, &(day, count))| (user.to_string(), json!({"day": day, "count": count})))
+            .collect();
+        let path = self.dir.join("puzzle_streaks.json");
+        tokio::fs::write(&path, Value::Object(saved).to_string())
+            .await
+            .map_err(|e| format!("couldn't save streaks to {}: {}", path.display(), e))
+    }
+}
+
+// Saves the streaks after each solve. Solves while they're being saved are saved together next.
+async fn save_on_change(puzzles: Arc<Puzzles>) {
+    loop {
+        puzzles.streaks_changed.notified().await;
+        if let Err(e) = puzzles.save_streaks().await {
+            warn!("{}", e);
+        }
+    }
+}
+
+// The streaks saved in path, if there are any yet.
+fn load_streaks(path: &Path) -> io::Result<HashMap<Uuid, (u64, u64)>> {
+    let saved: HashMap<String, Value> = match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(saved
+        .into_iter()
+        .filter_map(|(user, streak)| {
+            let user = Uuid::parse_str(&user).ok()?;
+            Some((user, (streak["day"].as_u64()?, streak["count"].as_u64()?)))
+        })
+        .collect())
+}
+
+// Today, in days since 1970, changing at midnight UTC.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400
+}
+
+// GET /api/puzzles/random, GET /api/puzzles/daily and POST /api/puzzles/daily/solved, as
+// described above.
+pub fn routes(
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let puzzles = warp::any().map(move || server.puzzles.clone());
+    let none =
+        || warp::reply::with_status("No puzzles yet", http::StatusCode::NOT_FOUND).into_response();
+    let random = warp::path!("api" / "puzzles" / "random")
+        .and(warp::get())
+        .and(puzzles.clone())
+        .map(
+            move |puzzles: Option<Arc<Puzzles>>| match puzzles.and_then(|p| p.random()) {
+                Some(puzzle) => warp::reply::json(&puzzle).into_response(),
+                None => none(),
+            },
+        );
+    let daily = warp::path!("api" / "puzzles" / "daily")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(puzzles.clone())
+        .map(
+            move |query: HashMap<String, String>, puzzles: Option<Arc<Puzzles>>| {
+                let day = today();
+                let Some((puzzles, mut puzzle)) =
+                    puzzles.and_then(|p| p.daily(day).map(|puzzle| (p, puzzle)))
+                else {
+                    return none();
+                };
+                let user = query.get("user").and_then(|u| Uuid::parse_str(u).ok());
+                puzzle["day"] = json!(day);
+                puzzle["streak"] = json!(user.map_or(0, |u| puzzles.streak(u, day)));
+                warp::reply::json(&puzzle).into_response()
+            },
+        );
+    let solved = warp::path!("api" / "puzzles" / "daily" / "solved")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .and(puzzles)
+        .map(move |request: Value, puzzles: Option<Arc<Puzzles>>| {
+            let Some(puzzles) = puzzles else {
+                return none();
+            };
+            let Some(user) = request["user"]
+                .as_str()
+                .and_then(|u| Uuid::parse_str(u).ok())
+            else {
+                return warp::reply::with_status("Invalid user ID", http::StatusCode::BAD_REQUEST)
+                    .into_response();
+            };
+            let id = request["id"].as_str().unwrap_or_default();
+            match puzzles.solved(user, id, today()) {
+                Ok(streak) => warp::reply::json(&json!({ "streak": streak })).into_response(),
+                Err(e) => {
+                    warn!("{}", e);
+                    warp::reply::with_status(e, http::StatusCode::BAD_REQUEST).into_response()
+                }
+            }
+        });
+    random.or(daily).unify().or(solved).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_puzzles() {
+        let dir = std::env::temp_dir().join(format!("chess-puzzles-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let game_id = Uuid::new_v4();
+        let fork = json!({"id": format!("{}-39", game_id), "game_id": game_id.to_string(),
+                          "fen": "q3k3/8/8/3N4/8/8/8/4K3 w - - 0 20",
+                          "moves": ["d5c7", "e8d7", "c7a8"]});
+        // Stands in for chess-ui, checking it's asked about the right log.
+        let generator = dir.join("generator.sh");
+        std::fs::write(
+            &generator,
+            format!(
+                "#!/bin/sh\n[ \"$1 $3 $4\" = '--puzzles --depth 2' ] && [ -f \"$2\" ] && echo '{}'\n",
+                fork
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&generator, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let puzzles = Puzzles::load(Some(generator), 2, &dir).unwrap();
+        assert!(puzzles.random().is_none());
+        assert!(puzzles.generate(game_id).await.is_err());
+        std::fs::write(dir.join(format!("{}.ndjson", game_id)), "").unwrap();
+        assert_eq!(puzzles.generate(game_id).await.unwrap(), 1);
+        // Found again, it's not added twice.
+        assert_eq!(puzzles.generate(game_id).await.unwrap(), 0);
+        assert_eq!(puzzles.random(), Some(fork.clone()));
+
+        // They're read back, without the generator.
+        let puzzles = Puzzles::load(None, 2, &dir).unwrap();
+        assert_eq!(puzzles.generate(game_id).await.unwrap(), 0);
+        let server = Server {
+            puzzles: Some(Arc::new(puzzles)),
+            ..Default::default()
+        };
+        let res = warp::test::request()
+            .path("/api/puzzles/random")
+            .reply(&routes(server))
+            .await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), fork);
+        let none = warp::test::request()
+            .path("/api/puzzles/random")
+            .reply(&routes(Server::default()))
+            .await;
+        assert_eq!(none.status(), 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daily() {
+        let dir = std::env::temp_dir().join(format!("chess-daily-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lines: String = (0..5)
+            .map(|i| format!("{}\n", json!({"id": format!("g-{}", i), "moves": ["d5c7"]})))
+            .collect();
+        std::fs::write(dir.join("puzzles.jsonl"), lines).unwrap();
+        let puzzles = Puzzles::load(None, 3, &dir).unwrap();
+        let day = 20000;
+        let daily = puzzles.daily(day).unwrap();
+        // It's the same all day, even with more puzzles.
+        puzzles.add(vec![json!({"id": "h-0"})]).unwrap();
+        assert_eq!(puzzles.daily(day), Some(daily.clone()));
+
+        let user = Uuid::new_v4();
+        assert_eq!(puzzles.streak(user, day), 0);
+        let id = daily["id"].as_str().unwrap();
+        let other = if id == "g-0" { "g-1" } else { "g-0" };
+        assert!(puzzles.solved(user, other, day).is_err());
+        assert_eq!(puzzles.solved(user, id, day), Ok(1));
+        // Solving it twice doesn't count.
+        assert_eq!(puzzles.solved(user, id, day), Ok(1));
+        let next = puzzles.daily(day + 1).unwrap();
+        assert_eq!(puzzles.streak(user, day + 1), 1);
+        assert_eq!(
+            puzzles.solved(user, next["id"].as_str().unwrap(), day + 1),
+            Ok(2)
+        );
+        // A day missed ends it.
+        assert_eq!(puzzles.streak(user, day + 3), 0);
+        let later = puzzles.daily(day + 3).unwrap();
+        assert_eq!(
+            puzzles.solved(user, later["id"].as_str().unwrap(), day + 3),
+            Ok(1)
+        );
+        // Other days have others.
+        assert!((day + 4..day + 12).any(|d| puzzles.daily(d) != Some(daily.clone())));
+
+        // Once there are too many, broken streaks make way for new ones.
+        let broken = (1..MAX_STREAKS).map(|_| (Uuid::new_v4(), (day, 1)));
+        puzzles.streaks.lock().unwrap().extend(broken);
+        let full = puzzles.daily(day + 4).unwrap();
+        let full = full["id"].as_str().unwrap();
+        let newcomer = Uuid::new_v4();
+        assert_eq!(puzzles.solved(newcomer, full, day + 4), Ok(1));
+        assert_eq!(puzzles.streaks.lock().unwrap().len(), 2);
+        // And if none are broken, there's no room.
+        let kept = (2..MAX_STREAKS).map(|_| (Uuid::new_v4(), (day + 4, 1)));
+        puzzles.streaks.lock().unwrap().extend(kept);
+        assert!(puzzles.solved(Uuid::new_v4(), full, day + 4).is_err());
+        assert_eq!(puzzles.solved(newcomer, full, day + 4), Ok(1));
+        puzzles.streaks.lock().unwrap().retain(|&u, _| u == user);
+
+        // Streaks are read back.
+        puzzles.save_streaks().await.unwrap();
+        let puzzles = Puzzles::load(None, 3, &dir).unwrap();
+        assert_eq!(puzzles.streak(user, day + 3), 1);
+        let server = Server {
+            puzzles: Some(Arc::new(puzzles)),
+            ..Default::default()
+        };
+        let res = warp::test::request()
+            .path(&format!("/api/puzzles/daily?user={}", user))
+            .reply(&routes(server.clone()))
+            .await;
+        assert_eq!(res.status(), 200);
+        let today = serde_json::from_slice::<Value>(res.body()).unwrap();
+        assert_eq!(today["day"], json!(super::today()));
+        assert_eq!(today["streak"], json!(0));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/puzzles/daily/solved")
+            .json(&json!({"user": user.to_string(), "id": today["id"]}))
+            .reply(&routes(server.clone()))
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<Value>(res.body()).unwrap(),
+            json!({"streak": 1})
+        );
+        let wrong = warp::test::request()
+            .method("POST")
+            .path("/api/puzzles/daily/solved")
+            .json(&json!({"user": "me", "id": today["id"]}))
+            .reply(&routes(server))
+            .await;
+        assert_eq!(wrong.status(), 400);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
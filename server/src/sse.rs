@@ -0,0 +1,261 @@
+// Server-sent events, for players whose network blocks websockets, e.g. behind some proxies. They
+// play the same way, but get the server's messages as an event stream and send theirs as POSTs:
+//   GET /sse/create?...          like /create, with the same query
+//   GET /sse/join/<game ID>?...  like /join
+// The stream's first event is named "session" and has the player's session ID, which is secret.
+// Every other event is a message as the websocket would have sent it (see ui/src/protocol.rs),
+// except that moves are always JSON. The player sends a message with
+//   POST /sse/send/<session ID>
+// as text, or as a binary move frame with Content-Type: application/octet-stream. Closing the
+// stream leaves the game, like closing the websocket. The browser ui falls back on this by itself
+// when it can't open a websocket (see ui/assets/js/multiplayer.js).
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{atomic::Ordering, Mutex},
+};
+
+use futures_util::{future, stream, StreamExt};
+use tracing::{info_span, warn, Instrument, Span};
+use uuid::Uuid;
+use warp::{http, hyper::body::Bytes, sse::Event, ws::Message, Filter, Reply};
+
+use crate::{
+    add_game, enter, movelog, new_game, outbox, player_disconnected, receive, Games, Refused,
+    Server, DENIED,
+};
+
+// Longer than any message the ui sends.
+const MAX_MESSAGE_LEN: u64 = 64 * 1024;
+
+#[derive(Clone, Copy)]
+struct Session {
+    game_id: Uuid,
+    player_id: Uuid,
+    // Who the player logged in as, if they did.
+    user: Option<Uuid>,
+}
+
+// The players connected by server-sent events, by session ID.
+#[derive(Default)]
+pub struct Sessions(Mutex<HashMap<Uuid, Session>>);
+
+impl Sessions {
+    fn get(&self, id: Uuid) -> Option<Session> {
+        self.0.lock().unwrap().get(&id).copied()
+    }
+
+    fn insert(&self, id: Uuid, session: Session) {
+        self.0.lock().unwrap().insert(id, session);
+    }
+
+    fn remove(&self, id: Uuid) {
+        self.0.lock().unwrap().remove(&id);
+    }
+}
+
+pub fn routes(
+    games: Games,
+    server: Server,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let games = warp::any().map(move || games.clone());
+    let server = warp::any().map(move || server.clone());
+    let create = warp::path!("sse" / "create")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(games.clone())
+        .and(server.clone())
+        .and_then(create);
+    let join = warp::path!("sse" / "join" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(games.clone())
+        .and(server.clone())
+        .and_then(join);
+    let send = warp::path!("sse" / "send" / String)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(MAX_MESSAGE_LEN))
+        .and(warp::body::bytes())
+        .and(games)
+        .and(server)
+        .and_then(send);
+    create.or(join).unify().or(send).unify()
+}
+
+fn shutting_down() -> warp::reply::Response {
+    warp::reply::with_status("Shutting down", http::StatusCode::SERVICE_UNAVAILABLE).into_response()
+}
+
+async fn create(
+    query: HashMap<String, String>,
+    games: Games,
+    server: Server,
+) -> Result<warp::reply::Response, Infallible> {
+    if server.shutting_down.load(Ordering::SeqCst) {
+        return Ok(shutting_down());
+    }
+    let (game, webhook) = match new_game(query, &server).await {
+        Ok(g) => g,
+        Err(e) => {
+            return Ok(warp::reply::with_status(e, http::StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+    let passcode = game.passcode.clone();
+    let game_id = add_game(game, webhook, &games, &server).await;
+    Ok(events(game_id, passcode, false, games, server).await)
+}
+
+async fn join(
+    game_id: String,
+    mut query: HashMap<String, String>,
+    games: Games,
+    server: Server,
+) -> Result<warp::reply::Response, Infallible> {
+    if server.shutting_down.load(Ordering::SeqCst) {
+        return Ok(shutting_down());
+    }
+    let Ok(game_id) = Uuid::parse_str(&game_id) else {
+        warn!("invalid join ID: {}", game_id);
+        return Ok(
+            warp::reply::with_status("Invalid game ID", http::StatusCode::BAD_REQUEST)
+                .into_response(),
+        );
+    };
+    let watch = query.get("watch").map(String::as_str) == Some("true");
+    Ok(events(game_id, query.remove("passcode"), watch, games, server).await)
+}
+
+// Adds a player to the game and streams them its messages.
+async fn events(
+    game_id: Uuid,
+    passcode: Option<String>,
+    watch: bool,
+    games: Games,
+    server: Server,
+) -> warp::reply::Response {
+    let player_id = Uuid::new_v4();
+    let span = info_span!("player", %game_id, %player_id);
+    let (tx, rx) = span.in_scope(outbox::channel);
+    let entered = enter(game_id, player_id, passcode.as_deref(), watch, tx, &games)
+        .instrument(span.clone())
+        .await;
+    match entered {
+        Ok(()) => {}
+        Err(Refused::Private) => {
+            let denied = stream::once(future::ok::<_, Infallible>(Event::default().data(DENIED)));
+            return warp::sse::reply(denied).into_response();
+        }
+        Err(Refused::NoGame) => {
+            return warp::reply::with_status("No such game", http::StatusCode::NOT_FOUND)
+                .into_response()
+        }
+    }
+    let id = Uuid::new_v4();
+    let session = Session {
+        game_id,
+        player_id,
+        user: None,
+    };
+    server.sse.insert(id, session);
+    server.open_sockets.fetch_add(1, Ordering::SeqCst);
+    let left = Left {
+        id,
+        session,
+        games,
+        server,
+        span,
+    };
+    let first = Event::default().event("session").data(id.to_string());
+    // Shutting down closes the connection after telling everyone.
+    let messages = rx
+        .into_stream()
+        .take_while(|m| future::ready(!m.is_close()))
+        .filter_map(|m| future::ready(event(&m)))
+        .map(move |e| {
+            let _left = &left;
+            Ok::<_, Infallible>(e)
+        });
+    let events = stream::once(future::ok(first)).chain(messages);
+    warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response()
+}
+
+// The message as an event. Moves sent as binary frames are passed on as JSON.
+fn event(msg: &Message) -> Option<Event> {
+    if let Ok(s) = msg.to_str() {
+        return Some(Event::default().data(s));
+    }
+    movelog::parse_move(msg).map(|m| Event::default().data(m.to_string()))
+}
+
+// Dropped with the player's event stream, when they close it or the server does, to take them out
+// of the game.
+struct Left {
+    id: Uuid,
+    session: Session,
+    games: Games,
+    server: Server,
+    span: Span,
+}
+
+impl Drop for Left {
+    fn drop(&mut self) {
+        self.server.sse.remove(self.id);
+        self.server.open_sockets.fetch_sub(1, Ordering::SeqCst);
+        let Session {
+            game_id, player_id, ..
+        } = self.session;
+        let (games, server) = (self.games.clone(), self.server.clone());
+        tokio::spawn(
+            async move { player_disconnected(game_id, player_id, &games, &server).await }
+                .instrument(self.span.clone()),
+        );
+    }
+}
+
+async fn send(
+    id: String,
+    content_type: Option<String>,
+    body: Bytes,
+    games: Games,
+    server: Server,
+) -> Result<warp::reply::Response, Infallible> {
+    let Some((id, session)) = Uuid::parse_str(&id)
+        .ok()
+        .and_then(|id| server.sse.get(id).map(|s| (id, s)))
+    else {
+        return Ok(
+            warp::reply::with_status("No such session", http::StatusCode::NOT_FOUND)
+                .into_response(),
+        );
+    };
+    let Session {
+        game_id,
+        player_id,
+        mut user,
+    } = session;
+    let msg = if content_type.is_some_and(|t| t.starts_with("application/octet-stream")) {
+        Message::binary(body.to_vec())
+    } else {
+        match String::from_utf8(body.to_vec()) {
+            Ok(s) => Message::text(s),
+            Err(_) => {
+                return Ok(warp::reply::with_status(
+                    "Invalid message",
+                    http::StatusCode::BAD_REQUEST,
+                )
+                .into_response())
+            }
+        }
+    };
+    let span = info_span!("player", %game_id, %player_id);
+    receive(game_id, player_id, msg, &mut user, &games, &server)
+        .instrument(span)
+        .await;
+    if let Some(mut s) = server.sse.get(id) {
+        s.user = user;
+        server.sse.insert(id, s);
+    }
+    Ok(warp::reply::with_status("OK", http::StatusCode::OK).into_response())
+}
@@ -0,0 +1,229 @@
+// Webhooks, so other services can follow a game without connecting a websocket, e.g. a chat bot
+// or a stream overlay. A game created with /create?webhook=<url> has the server POST JSON to the
+// URL when the game starts, once both players are in it, after each move and when it ends:
+//   {"event": "start", "game_id": "...", "ruleset": {...}}
+//   {"event": "move", "game_id": "...", "move": {"src_row": 2, "src_col": 5, ...}}
+//   {"event": "end", "game_id": "...", "result": "1-0", "reason": "checkmate"}
+// The ruleset is null for standard chess, and moves are as in JSON move messages (see
+// ui/src/protocol.rs), however the player sent them. Events are sent one at a time, in order, and
+// one that fails or takes longer than WEBHOOK_TIMEOUT is logged and dropped, so a broken hook
+// can't hold up the game. Hooks must be https:// URLs, unless WEBHOOK_ALLOW_HTTP is set.
+//
+// Anyone can create a game, so by default hooks can't reach the server's own network: a URL whose
+// host is, or resolves to, a loopback, private, link-local, unique local or unspecified address is
+// refused when the game's created, and the hook's client refuses to connect to one too, in case the
+// name resolves differently by then. WEBHOOK_ALLOW_PRIVATE lifts that, e.g. for a bot on the same
+// machine, which also needs WEBHOOK_ALLOW_HTTP unless it has a certificate.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{debug, warn, Instrument};
+use uuid::Uuid;
+use warp::hyper::client::connect::dns::Name;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Webhook {
+    url: String,
+    game_id: Uuid,
+    events: mpsc::UnboundedSender<Value>,
+}
+
+impl Webhook {
+    // Starts sending the game's events to url, until the webhook is dropped with the game.
+    // allow_private is whether it can reach the server's own network.
+    pub fn start(url: String, game_id: Uuid, allow_private: bool) -> Webhook {
+        let (events, rx) = mpsc::unbounded_channel();
+        tokio::spawn(deliver(url.clone(), rx, allow_private).in_current_span());
+        Webhook {
+            url,
+            game_id,
+            events,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Queues the event, with fields besides its name and the game ID.
+    pub fn send(&self, event: &str, fields: Value) {
+        let mut payload = json!({ "event": event, "game_id": self.game_id.to_string() });
+        if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+            payload.extend(fields);
+        }
+        if self.events.send(payload).is_err() {
+            warn!("webhook stopped");
+        }
+    }
+}
+
+// Checks a creator's webhook URL is one the server will call.
+pub async fn check_url(url: &str, allow_http: bool, allow_private: bool) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid webhook URL: {}", e))?;
+    match parsed.scheme() {
+        "https" => {}
+        "http" if allow_http => {}
+        scheme => return Err(format!("webhook URLs must be https, not {}", scheme)),
+    }
+    if allow_private {
+        return Ok(());
+    }
+    let host = parsed.host_str().ok_or("webhook URL without a host")?;
+    let addrs: Vec<IpAddr> = match literal_ip(host) {
+        Some(ip) => vec![ip],
+        None => tokio::net::lookup_host((host, 0))
+            .await
+            .map_err(|e| format!("couldn't resolve webhook host {}: {}", host, e))?
+            .map(|a| a.ip())
+            .collect(),
+    };
+    match addrs.into_iter().find(|&ip| !is_public(ip)) {
+        Some(ip) => Err(format!("webhook host {} isn't public: {}", host, ip)),
+        None => Ok(()),
+    }
+}
+
+// The address in a URL's host, if it's one rather than a name. IPv6 addresses are in brackets.
+fn literal_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+// Whether ip is outside the server's own network, so a hook may send to it.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT's shared space, 100.64.0.0/10.
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public(v4.into()),
+            None => {
+                let first = ip.segments()[0];
+                // Unique local (fc00::/7) and link-local (fe80::/10) addresses.
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+// Resolves names for hooks that can't reach the server's own network, failing if any of a name's
+// addresses is on it.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(a) = addrs.iter().find(|a| !is_public(a.ip())) {
+                return Err(format!("{} isn't public: {}", name.as_str(), a.ip()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn deliver(url: String, mut events: mpsc::UnboundedReceiver<Value>, allow_private: bool) {
+    // The client doesn't resolve addresses in URLs, so those are checked here. The game may have
+    // been restored after a restart with WEBHOOK_ALLOW_PRIVATE unset since it was created.
+    if let Some(ip) = reqwest::Url::parse(&url)
+        .ok()
+        .and_then(|u| u.host_str().and_then(literal_ip))
+        .filter(|&ip| !allow_private && !is_public(ip))
+    {
+        warn!("webhook host isn't public: {}", ip);
+        return;
+    }
+    // Redirects aren't followed, so a hook can only ever reach the URL the creator gave.
+    let mut builder = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    if !allow_private {
+        builder = builder.dns_resolver(Arc::new(PublicOnly));
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("couldn't start webhook: {}", e);
+            return;
+        }
+    };
+    while let Some(event) = events.recv().await {
+        let sent = client
+            .post(&url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        match sent {
+            Ok(_) => debug!(event = %event["event"], "webhook called"),
+            Err(e) => warn!(event = %event["event"], "webhook failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_url() {
+        assert!(check_url("https://example.com/hook", false, true)
+            .await
+            .is_ok());
+        assert!(check_url("http://localhost:8080/hook", false, true)
+            .await
+            .is_err());
+        assert!(check_url("http://localhost:8080/hook", true, true)
+            .await
+            .is_ok());
+        assert!(check_url("ftp://example.com/hook", true, true)
+            .await
+            .is_err());
+        assert!(check_url("example.com/hook", true, true).await.is_err());
+
+        // Nothing on the server's own network, unless that's allowed.
+        assert!(check_url("https://93.184.215.14/hook", false, false)
+            .await
+            .is_ok());
+        for url in [
+            "https://localhost/hook",
+            "https://127.0.0.1/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://10.0.0.5/hook",
+            "https://192.168.1.1/hook",
+            "https://100.64.0.1/hook",
+            "https://0.0.0.0/hook",
+            "https://[::1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[fe80::1]/hook",
+            "https://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(check_url(url, false, false).await.is_err(), "{}", url);
+            assert!(check_url(url, false, true).await.is_ok(), "{}", url);
+        }
+        // Names are checked again when a hook connects.
+        let localhost = "localhost".parse().unwrap();
+        assert!(PublicOnly.resolve(localhost).await.is_err());
+    }
+}
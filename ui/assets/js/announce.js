@@ -0,0 +1,10 @@
+import { on } from "./events.js";
+
+// Reads the game out to screen reader users: each move, check and result is described in words
+// (see src/announce.rs) and put in element, which should be an ARIA live region, e.g.
+// <div aria-live="polite">. The canvas itself says nothing to assistive technology.
+export function init_announcer(element) {
+    on("announce", (text) => {
+        element.textContent = text;
+    });
+}
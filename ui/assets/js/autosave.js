@@ -0,0 +1,27 @@
+import { take_string, with_string } from "./mem.js";
+
+// The game in progress (see src/autosave.rs) is kept in localStorage under this key, so reloading
+// the page, or the browser crashing, doesn't lose it.
+const STORAGE_KEY = "chess-autosave";
+// How often, in milliseconds, the saved game is updated.
+const INTERVAL = 5000;
+
+// Once the game is loaded, asks the player with ask() whether to resume the saved game, if there
+// is one, and from then on keeps it up to date. ask defaults to the browser's confirm dialog.
+export function init_autosave(ask = () => confirm("Resume previous game?")) {
+    let on_init = function () {
+        let saved = localStorage.getItem(STORAGE_KEY);
+        if (saved !== null && !(ask() && with_string(saved, wasm_exports.resume_game))) {
+            localStorage.removeItem(STORAGE_KEY);
+        }
+        setInterval(() => {
+            let game = take_string(wasm_exports.get_autosave());
+            if (game === "") {
+                localStorage.removeItem(STORAGE_KEY);
+            } else {
+                localStorage.setItem(STORAGE_KEY, game);
+            }
+        }, INTERVAL);
+    };
+    miniquad_add_plugin({on_init});
+}
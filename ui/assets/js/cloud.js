@@ -0,0 +1,31 @@
+import { take_string, call_with_json } from "./mem.js";
+
+// Asks the server serving the page to analyze the position on the board more deeply than the
+// browser can (see server/src/cloud.rs), with optional limits, e.g. { depth: 8, time_ms: 5000 }.
+// The results go to the game's analysis as they come in, which shows them while the position is
+// on the board and analysis is on. Resolves once the server is done, or rejects if it won't do it.
+export async function request_cloud_analysis(limits = {}) {
+    let fen = take_string(wasm_exports.get_fen());
+    let response = await fetch("/api/analyze", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ fen, ...limits }),
+    });
+    if (!response.ok) {
+        throw new Error(`${response.status} ${await response.text()}`);
+    }
+    let { id } = await response.json();
+    let scheme = location.protocol === "https:" ? "wss" : "ws";
+    let ws = new WebSocket(`${scheme}://${location.host}/api/analyze/${id}`);
+    return new Promise((resolve, reject) => {
+        ws.onmessage = (event) => {
+            let message = JSON.parse(event.data);
+            call_with_json(wasm_exports.set_cloud_analysis, { fen, ...message });
+            if (message.error) {
+                reject(new Error(message.error));
+            }
+        };
+        ws.onclose = () => resolve();
+        ws.onerror = () => reject(new Error("Lost the connection to the server"));
+    });
+}
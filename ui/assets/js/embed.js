@@ -0,0 +1,81 @@
+// Generated by `chess-ui --ffi-js` (see src/embed.rs). Don't edit it, run that again when the
+// commands change.
+
+import { EVENTS } from "./ffi.js";
+import { on } from "./events.js";
+import { take_string, with_string, call_with_json } from "./mem.js";
+
+// The commands the parent page can post, by name.
+const COMMANDS = {
+    // Starts a game between two players at the board from the position in FEN.
+    load_fen: Object.assign((fen) => {
+        if (!with_string(String(fen), wasm_exports.load_fen)) {
+            throw new Error("Invalid FEN");
+        }
+    }, { params: ["fen"] }),
+    // Starts a game from the first game in PGN, with its moves played.
+    load_pgn: Object.assign((pgn) => {
+        if (!with_string(String(pgn), wasm_exports.load_pgn)) {
+            throw new Error("Invalid PGN");
+        }
+    }, { params: ["pgn"] }),
+    // Starts a new game between two players at the board.
+    new_game: Object.assign(() => {
+        wasm_exports.set_game_mode(0, 0);
+    }, { params: [] }),
+    // Makes a move in long algebraic notation, e.g. "e2e4", for the player at the board.
+    move: Object.assign((uci) => {
+        with_string(String(uci), wasm_exports.play_move);
+    }, { params: ["uci"] }),
+    // The position shown on the board in FEN.
+    get_fen: Object.assign(() => {
+        return take_string(wasm_exports.get_fen());
+    }, { params: [] }),
+    // Colors the board: "classic", "wood", "gray", "high_contrast" or "color_blind".
+    set_theme: Object.assign((theme) => {
+        call_with_json(wasm_exports.set_settings, { theme });
+    }, { params: ["theme"] }),
+    // Shows the board with black at the bottom if flipped is true.
+    flip: Object.assign((flipped) => {
+        wasm_exports.flip_board(flipped ? 1 : 0);
+    }, { params: ["flipped"] }),
+    // Posts the named events to the parent from now on.
+    subscribe: Object.assign((events) => {
+        for (let name of events) {
+            if (!EVENTS.some((e) => e.name === name)) {
+                throw new Error(`No such event: ${name}`);
+            }
+            on(name, (...args) => post({ event: name, args }));
+        }
+    }, { params: ["events"] }),
+};
+
+let parent_origin = "*";
+
+function post(message) {
+    window.parent.postMessage({ chess: 1, ...message }, parent_origin);
+}
+
+// Answers the parent page's commands, and only theirs. Messages are only posted to origin, if
+// given, and only taken from it. Call it once the game has loaded.
+export function init_embed(origin = "*") {
+    parent_origin = origin;
+    window.addEventListener("message", (event) => {
+        let message = event.data;
+        if (event.source !== window.parent || (origin !== "*" && event.origin !== origin)
+            || !message || message.chess !== 1 || !message.command) {
+            return;
+        }
+        let command = COMMANDS[message.command];
+        try {
+            if (!command) {
+                throw new Error(`No such command: ${message.command}`);
+            }
+            let result = command(...command.params.map((p) => message[p]));
+            post({ id: message.id, result: result === undefined ? null : result });
+        } catch (e) {
+            post({ id: message.id, error: String(e.message || e) });
+        }
+    });
+    post({ event: "ready", args: [] });
+}
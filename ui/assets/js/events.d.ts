@@ -0,0 +1,43 @@
+// Generated by `chess-ui --ffi-js` (see src/ffi.rs). Don't edit it, run that again when the shared
+// structs, the exports or the events change.
+
+export interface EventHandlers {
+    /** A line logged by the game. Without a handler, it goes to the console. */
+    log(line: string): void;
+    /** Our move for the server: a binary move frame, or a JSON message as UTF-8. */
+    move(payload: Uint8Array, binary: boolean): void;
+    /** The color the player plays online: 0 for white, 1 for black. */
+    player_color(): number;
+    /** A player's turn started. */
+    turn_start(color: "white" | "black"): void;
+    /** The game ended: { result, reason }, e.g. "1-0" by "checkmate", or "*" if aborted. */
+    game_end(end: any): void;
+    /** A move or the result described for screen readers, e.g. "White knight from g1 to f3". */
+    announce(text: string): void;
+    /** The picture of the board asked for with request_board_image, as a PNG. */
+    board_image(png: Uint8Array): void;
+    /** The animation of the game asked for with request_game_animation, as an animated PNG. */
+    game_animation(png: Uint8Array): void;
+    /** Asks for GET /api/explorer?hash=<hash>, whose JSON goes to set_explorer. */
+    explorer_query(hash: string): void;
+    /** A move was made on the board: { uci, san, fen, ply }, with the FEN and ply after it. */
+    moved(move: any): void;
+    /** A message for the other player about the board both analyze on after the game. */
+    analysis_move(message: string): void;
+    /** The color drawn over a square (rows and columns 1 to 8) as RGBA, 0xRRGGBBAA, or 0. */
+    decorate_square(row: number, col: number): number;
+    /** The tint of a piece, { row, col, name }, as RGBA, 0xRRGGBBAA, or 0. */
+    decorate_piece(piece: any): number;
+    /** A message for the player, e.g. why a pasted FEN wasn't loaded, for a status bar. */
+    status(text: string): void;
+    /** The player asked to export the game from the game over screen, e.g. to save its PGN. */
+    export(): void;
+    /** The player solved the puzzle loaded with load_puzzle: { id, mistakes }. */
+    puzzle_solved(solved: any): void;
+}
+
+/** Handles the event from now on, instead of any handler before. */
+export function on<E extends keyof EventHandlers>(name: E, handler: EventHandlers[E]): void;
+
+/** Sets up the import the game sends events through. Call it before loading the game. */
+export function init_events(): void;
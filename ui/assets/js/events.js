@@ -0,0 +1,64 @@
+import { EVENTS } from "./ffi.js";
+
+// What the game tells the page (see src/events.rs), with the handlers' types in events.d.ts.
+// on(name, handler) handles an event from now on. Events without a handler are dropped, and the
+// game gets 0 back if it asked for something.
+
+let handlers = {
+    log: (line) => console.log(line),
+};
+
+export function on(name, handler) {
+    if (!EVENTS.some((e) => e.name === name)) {
+        console.log(`No such event: ${name}`);
+    }
+    handlers[name] = handler;
+}
+
+// The handler's arguments, from the numbers the game passed. Strings and bytes are a pointer and
+// a length, and only valid during the call, so bytes are copied.
+function decode(args, numbers) {
+    let values = [];
+    let i = 0;
+    for (let arg of args) {
+        switch (arg) {
+        case "str":
+        case "json":
+        case "bytes":
+            let bytes = new Uint8Array(wasm_memory.buffer, numbers[i], numbers[i + 1]);
+            if (arg === "bytes") {
+                values.push(bytes.slice());
+            } else {
+                let s = (new TextDecoder()).decode(bytes);
+                values.push(arg === "json" ? JSON.parse(s) : s);
+            }
+            i += 2;
+            break;
+        case "bool":
+            values.push(numbers[i++] !== 0);
+            break;
+        case "color":
+            values.push(numbers[i++] === 0 ? "white" : "black");
+            break;
+        case "int":
+            values.push(numbers[i++]);
+            break;
+        }
+    }
+    return values;
+}
+
+export function init_events() {
+    register_plugin = function (importObject) {
+        importObject.env.js_dispatch = (kind, a, b, c) => {
+            let event = EVENTS[kind];
+            let handler = event && handlers[event.name];
+            if (!handler) {
+                return 0;
+            }
+            let result = handler(...decode(event.args, [a, b, c]));
+            return event.returns ? result | 0 : 0;
+        };
+    };
+    miniquad_add_plugin({register_plugin});
+}
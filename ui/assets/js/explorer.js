@@ -0,0 +1,20 @@
+import { on } from "./events.js";
+import { with_bytes } from "./mem.js";
+
+// Fetches the opening explorer's moves (see src/explorer.rs) from the server at base, by default
+// the one serving the page, whenever the game asks in analysis mode. Failures, e.g. a server
+// without the move log, are logged and the game shows no explorer.
+export function init_explorer(base = "") {
+    on("explorer_query", async (hash) => {
+        try {
+            let response = await fetch(`${base}/api/explorer?hash=${encodeURIComponent(hash)}`);
+            if (!response.ok) {
+                throw new Error(`${response.status} ${await response.text()}`);
+            }
+            let json = await response.text();
+            with_bytes(new TextEncoder().encode(json), wasm_exports.set_explorer);
+        } catch (e) {
+            console.log(`No opening explorer: ${e}`);
+        }
+    });
+}
@@ -0,0 +1,59 @@
+import { on } from "./events.js";
+import { take_string } from "./mem.js";
+
+// Pictures of the board (see src/export.rs). options can have flipped (black at the bottom),
+// coordinates and last_move (highlighted), all false by default.
+
+// The PNG the game sends in event once it's drawn it, on its next frame.
+function next_png(event) {
+    return new Promise((resolve) => {
+        on(event, (png) => {
+            on(event, null);
+            resolve(new Blob([png], {type: "image/png"}));
+        });
+    });
+}
+
+function flags(options) {
+    return [options.flipped ? 1 : 0, options.coordinates ? 1 : 0, options.last_move ? 1 : 0];
+}
+
+// Resolves to a PNG Blob of the position shown on the board.
+export function board_image(options = {}) {
+    let png = next_png("board_image");
+    wasm_exports.request_board_image(...flags(options));
+    return png;
+}
+
+// Resolves to an animated PNG Blob of the game so far, showing each position for options.delay
+// milliseconds (a second by default).
+export function game_animation(options = {}) {
+    let png = next_png("game_animation");
+    wasm_exports.request_game_animation(...flags(options), options.delay || 0);
+    return png;
+}
+
+// Downloads a picture of the board as name.
+export function download_board_image(name, options) {
+    return download(name, board_image(options));
+}
+
+// Downloads an animation of the game as name.
+export function download_game_animation(name, options) {
+    return download(name, game_animation(options));
+}
+
+// Downloads the game as name, in PGN with its comments, annotations and variations.
+export function download_pgn(name) {
+    let pgn = take_string(wasm_exports.get_pgn());
+    return download(name, new Blob([pgn], {type: "application/x-chess-pgn"}));
+}
+
+async function download(name, blob) {
+    let url = URL.createObjectURL(await blob);
+    let a = document.createElement("a");
+    a.href = url;
+    a.download = name;
+    a.click();
+    setTimeout(() => URL.revokeObjectURL(url), 0);
+}
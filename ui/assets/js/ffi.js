@@ -0,0 +1,773 @@
+// Generated by `chess-ui --ffi-js` (see src/ffi.rs). Don't edit it, run that again when the shared
+// structs, the exports or the events change.
+
+// A piece: its square and its name as an ASCII code, uppercase for white.
+export const PIECE = { size: 3, row: 0, col: 1, name: 2 };
+// Little-endian u16s. See the GD_ flags in src/rules.rs for the mask.
+export const GAME_DATA = { size: 4, ply: 0, mask: 2 };
+// The board is width * height bytes, row by row from a1, each the ASCII code of the piece on
+// the square or 0. Rows and columns count from 1.
+export const BOARD = { max_size: 15 };
+// Each square's byte of flags, laid out like the board. See the PF_ flags in src/rules.rs.
+export const SQUARE_FLAGS = { moved: 1, frozen: 2, charges_shift: 4, charges: 240 };
+// One of the moves a movement plugin returns, which end at a 0 row.
+export const PLUGIN_MOVE = { size: 3, row: 0, col: 1, name: 2 };
+
+// Reads the piece at ptr.
+export function read_piece(ptr) {
+    let b = new Uint8Array(wasm_memory.buffer, ptr, PIECE.size);
+    return { row: b[PIECE.row], col: b[PIECE.col], name: String.fromCharCode(b[PIECE.name]) };
+}
+
+// Reads the game data at ptr.
+export function read_game_data(ptr) {
+    let v = new DataView(wasm_memory.buffer, ptr, GAME_DATA.size);
+    return { ply: v.getUint16(GAME_DATA.ply, true), mask: v.getUint16(GAME_DATA.mask, true) };
+}
+
+// The events the game sends the page, by kind (see events.js).
+export const EVENTS = [
+    { name: "log", args: ["str"], returns: false },
+    { name: "move", args: ["bytes", "bool"], returns: false },
+    { name: "player_color", args: [], returns: true },
+    { name: "turn_start", args: ["color"], returns: false },
+    { name: "game_end", args: ["json"], returns: false },
+    { name: "announce", args: ["str"], returns: false },
+    { name: "board_image", args: ["bytes"], returns: false },
+    { name: "game_animation", args: ["bytes"], returns: false },
+    { name: "explorer_query", args: ["str"], returns: false },
+    { name: "moved", args: ["json"], returns: false },
+    { name: "analysis_move", args: ["str"], returns: false },
+    { name: "decorate_square", args: ["int", "int"], returns: true },
+    { name: "decorate_piece", args: ["json"], returns: true },
+    { name: "status", args: ["str"], returns: false },
+    { name: "export", args: [], returns: false },
+    { name: "puzzle_solved", args: ["json"], returns: false },
+];
+
+/**
+ * So JS can tell WASM to make a move
+ * From src/main.rs.
+ * @param {number} src_row usize
+ * @param {number} src_col usize
+ * @param {number} dst_row usize
+ * @param {number} dst_col usize
+ */
+export function make_move_from_js(src_row, src_col, dst_row, dst_col) {
+    return wasm_exports.make_move_from_js(src_row, src_col, dst_row, dst_col);
+}
+
+/**
+ * Makes the other player's move, as received from the server. binary is 1 for a binary move
+ * frame and 0 for a JSON message.
+ * From src/main.rs.
+ * @param {number} move_ptr *const u8, a pointer into wasm_memory
+ * @param {number} binary u32
+ */
+export function receive_move(move_ptr, binary) {
+    return wasm_exports.receive_move(move_ptr, binary);
+}
+
+/**
+ * Makes a move in long algebraic notation, e.g. "e2e4" or "e7e8q", for the player at the board, as
+ * if they'd made it themselves. Illegal moves, and moves when it isn't their turn, are ignored.
+ * From src/main.rs.
+ * @param {number} move_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function play_move(move_str_ptr) {
+    return wasm_exports.play_move(move_str_ptr);
+}
+
+/**
+ * Starts a game between two players at the board from the position in FEN. Returns 1 if it's
+ * valid, otherwise 0.
+ * From src/main.rs.
+ * @param {number} fen_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function load_fen(fen_str_ptr) {
+    return wasm_exports.load_fen(fen_str_ptr);
+}
+
+/**
+ * Starts a game between two players at the board from the first game in PGN, from its FEN tag if
+ * it has one, and plays its moves up to the first illegal one. Returns 1 if it's valid PGN,
+ * otherwise 0.
+ * From src/main.rs.
+ * @param {number} pgn_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function load_pgn(pgn_str_ptr) {
+    return wasm_exports.load_pgn(pgn_str_ptr);
+}
+
+/**
+ * Starts guessing the moves of the first game in PGN for a side, 0 for white or 1 for black (see
+ * guess.rs). Returns 1 if it's valid PGN, otherwise 0.
+ * From src/main.rs.
+ * @param {number} pgn_str_ptr *const u8, a pointer into wasm_memory
+ * @param {number} side u32
+ * @returns {number} u32
+ */
+export function load_guess_pgn(pgn_str_ptr, side) {
+    return wasm_exports.load_guess_pgn(pgn_str_ptr, side);
+}
+
+/**
+ * Starts the puzzle in JSON (see puzzle_mode.rs), e.g. the puzzle of the day from the server, with
+ * the board turned to the side to move. Returns 1 if it's valid, otherwise 0.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function load_puzzle(json_str_ptr) {
+    return wasm_exports.load_puzzle(json_str_ptr);
+}
+
+/**
+ * Analyzes the position in FEN the player pasted, e.g. from the clipboard. Unlike load_fen, it has
+ * to be a position that could come up in a game, and if it isn't, the status event says why.
+ * Returns 1 if it's loaded, otherwise 0.
+ * From src/main.rs.
+ * @param {number} fen_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function paste_fen(fen_str_ptr) {
+    return wasm_exports.paste_fen(fen_str_ptr);
+}
+
+/**
+ * Returns a pointer to the game as JSON, to resume it later with resume_game, or to an empty string
+ * if there's nothing to resume. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_autosave() {
+    return wasm_exports.get_autosave();
+}
+
+/**
+ * Picks up a game from get_autosave where it was left off. Returns 1 if it's a valid save,
+ * otherwise 0.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function resume_game(json_str_ptr) {
+    return wasm_exports.resume_game(json_str_ptr);
+}
+
+/**
+ * Sends moves as binary frames (the default) if on is nonzero, or as JSON, which is easier to
+ * debug. Either way, both are understood when received.
+ * From src/main.rs.
+ * @param {number} on u32
+ */
+export function set_binary_moves(on) {
+    return wasm_exports.set_binary_moves(on);
+}
+
+/**
+ * Who's connected to the online game, from the server's presence messages.
+ * From src/main.rs.
+ * @param {number} players u32
+ * @param {number} spectators u32
+ */
+export function set_presence(players, spectators) {
+    return wasm_exports.set_presence(players, spectators);
+}
+
+/**
+ * Whether we're watching the online game rather than playing it, as the server says when we join.
+ * The board is read-only while we are. A new game starts with us playing.
+ * From src/main.rs.
+ * @param {number} spectating u32
+ */
+export function set_spectating(spectating) {
+    return wasm_exports.set_spectating(spectating);
+}
+
+/**
+ * The online game is rated, as the server says when we take a seat, so it's played without the
+ * check for blunders. A new game starts unrated.
+ * From src/main.rs.
+ */
+export function set_rated() {
+    return wasm_exports.set_rated();
+}
+
+/**
+ * From src/main.rs.
+ * @returns {number} u32
+ */
+export function takeback_ply() {
+    return wasm_exports.takeback_ply();
+}
+
+/**
+ * Goes back to the position at ply, undoing the moves since. Online, JS only calls this once both
+ * players have agreed to it (see assets/js/multiplayer.js).
+ * From src/main.rs.
+ * @param {number} ply u32
+ */
+export function take_back(ply) {
+    return wasm_exports.take_back(ply);
+}
+
+/**
+ * Returns 1 if the player can abort the online game, e.g. to show a button for it, otherwise 0.
+ * From src/main.rs.
+ * @returns {number} u32
+ */
+export function abortable() {
+    return wasm_exports.abortable();
+}
+
+/**
+ * The server says whether the player can abort the online game: 1 once the other player has taken
+ * too long to make their first move, 0 when it refused to abort it.
+ * From src/main.rs.
+ * @param {number} allowed u32
+ */
+export function set_abortable(allowed) {
+    return wasm_exports.set_abortable(allowed);
+}
+
+/**
+ * The server aborted the online game, at either player's request.
+ * From src/main.rs.
+ */
+export function game_aborted() {
+    return wasm_exports.game_aborted();
+}
+
+/**
+ * Takes the other player's message about the board both analyze on after the online game,
+ * {"analysis": {"fen", "uci"}} (see protocol.rs). The board follows theirs.
+ * From src/main.rs.
+ * @param {number} message_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function receive_analysis(message_str_ptr) {
+    return wasm_exports.receive_analysis(message_str_ptr);
+}
+
+/**
+ * Once an online game is over, starts analyzing it together with the other player from the
+ * position shown, or goes back to the game's final position if already analyzing it.
+ * From src/main.rs.
+ */
+export function toggle_shared_analysis() {
+    return wasm_exports.toggle_shared_analysis();
+}
+
+/**
+ * Passes the player's turn, if the rules let them pass now (see "pass" in the rule set), or
+ * otherwise says why not on the status line.
+ * From src/main.rs.
+ */
+export function pass_turn() {
+    return wasm_exports.pass_turn();
+}
+
+/**
+ * The other player abandoned the online game, and the server awards fallback: 0 for a win, 1 for
+ * a draw and 2 to abort. See Adjudication::result for when it doesn't apply.
+ * From src/main.rs.
+ * @param {number} fallback u32
+ */
+export function adjudicate(fallback) {
+    return wasm_exports.adjudicate(fallback);
+}
+
+/**
+ * From src/main.rs.
+ * @param {number} flipped u32
+ */
+export function flip_board(flipped) {
+    return wasm_exports.flip_board(flipped);
+}
+
+/**
+ * Starts a new game. mode is 0 for two players on this device, 1 to play against the computer
+ * and 2 to play online. strength is only used against the computer.
+ * From src/main.rs.
+ * @param {number} mode u32
+ * @param {number} strength u32
+ */
+export function set_game_mode(mode, strength) {
+    return wasm_exports.set_game_mode(mode, strength);
+}
+
+/**
+ * Turns the teaching preset (see teaching.rs) on if on is 1, or off, for the game being played and
+ * the ones started after it. Online games are played without it.
+ * From src/main.rs.
+ * @param {number} on u32
+ */
+export function set_teaching(on) {
+    return wasm_exports.set_teaching(on);
+}
+
+/**
+ * Opens a game link (see link.rs), e.g. the page's location: starts an online game, shown from the
+ * side of the color in the link, if any. Returns a pointer to JSON with what the page needs to
+ * join it, {"game_id", "color", "passcode", "path"}, or {"error"} if it isn't a game link. Free it
+ * when done.
+ * From src/main.rs.
+ * @param {number} link_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function open_game_link(link_str_ptr) {
+    return wasm_exports.open_game_link(link_str_ptr);
+}
+
+/**
+ * Returns a pointer to the fragment of the link to a game, e.g. "#/game/<game ID>/white", for
+ * color 0 (white), 1 (black) or 2 (either), with the passcode of a private game unless it's empty.
+ * It's empty if the game ID or passcode isn't valid. Free it when done.
+ * From src/main.rs.
+ * @param {number} game_id_str_ptr *const u8, a pointer into wasm_memory
+ * @param {number} color u32
+ * @param {number} passcode_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function game_link(game_id_str_ptr, color, passcode_str_ptr) {
+    return wasm_exports.game_link(game_id_str_ptr, color, passcode_str_ptr);
+}
+
+/**
+ * Overrides the engine level's time limit (unless it's 0) and contempt, in centipawns, and turns off
+ * the pruning in no_pruning, if any: 1 for null-move pruning and 2 for late move reductions. The
+ * pruning lets the engine see deeper in the same time, so turn it off only to compare. Applies to
+ * the current game and later ones.
+ * From src/main.rs.
+ * @param {number} time_limit_ms u32
+ * @param {number} contempt i32
+ * @param {number} no_pruning u32
+ */
+export function set_engine_options(time_limit_ms, contempt, no_pruning) {
+    return wasm_exports.set_engine_options(time_limit_ms, contempt, no_pruning);
+}
+
+/**
+ * Returns a pointer to the game's metadata as a JSON object of PGN style tags. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_game_metadata() {
+    return wasm_exports.get_game_metadata();
+}
+
+/**
+ * Shows a move from get_move_list: JSON {"path", "view"}, as the move has them. Moves of
+ * variations are played out on the board after the game's moves leading to them, and the game's
+ * own go back to the game.
+ * From src/main.rs.
+ * @param {number} json_ptr *const u8, a pointer into wasm_memory
+ */
+export function seek_variation(json_ptr) {
+    return wasm_exports.seek_variation(json_ptr);
+}
+
+/**
+ * Comments on the move leading to the position shown on the board, or with an empty string, removes
+ * the comment. Only in analysis: while it's on, or once the game is over.
+ * From src/main.rs.
+ * @param {number} comment_ptr *const u8, a pointer into wasm_memory
+ */
+export function set_comment(comment_ptr) {
+    return wasm_exports.set_comment(comment_ptr);
+}
+
+/**
+ * Returns a pointer to the game's moves as a JSON array, for a move list. Each move is
+ * {"path", "view", "san", "nags", "glyphs", "before", "comment", "variations"}, where path and view
+ * say where it is, for seek_variation, glyphs are its NAGs written like "!?", before and comment
+ * are comments or null, and variations are arrays of the moves that could have been played
+ * instead, in the same form. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_move_list() {
+    return wasm_exports.get_move_list();
+}
+
+/**
+ * Returns a pointer to the game in PGN, with its comments, annotations and variations. Free it
+ * when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_pgn() {
+    return wasm_exports.get_pgn();
+}
+
+/**
+ * Returns a pointer to the game's positions and moves as a binary snapshot, to pick the game up
+ * again with load_snapshot. It has no comments or variations, but is much smaller than the game's
+ * positions as FEN. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_snapshot() {
+    return wasm_exports.get_snapshot();
+}
+
+/**
+ * Starts a game between two players at the board from a snapshot from get_snapshot, where it was
+ * left off. Returns 1 if it's a valid snapshot, otherwise 0.
+ * From src/main.rs.
+ * @param {number} snapshot_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} u32
+ */
+export function load_snapshot(snapshot_ptr) {
+    return wasm_exports.load_snapshot(snapshot_ptr);
+}
+
+/**
+ * Shows an earlier (delta < 0) or later (delta > 0) position of the game. Moves can only be made
+ * on the current position.
+ * From src/main.rs.
+ * @param {number} delta i32
+ */
+export function step_view(delta) {
+    return wasm_exports.step_view(delta);
+}
+
+/**
+ * Steps through the game's history a full turn, a move of each player, at a time: back if delta < 0
+ * and forward if it's > 0.
+ * From src/main.rs.
+ * @param {number} delta i32
+ */
+export function step_turns(delta) {
+    return wasm_exports.step_turns(delta);
+}
+
+/**
+ * Shows the position ply moves into the game, 0 for the start, e.g. for a scrubber.
+ * From src/main.rs.
+ * @param {number} ply u32
+ */
+export function seek_view(ply) {
+    return wasm_exports.seek_view(ply);
+}
+
+/**
+ * Starts (1) or stops (0) playing through the game's history on its own (see playback.rs).
+ * Started on the last position, it plays from the start.
+ * From src/main.rs.
+ * @param {number} on u32
+ */
+export function set_playing(on) {
+    return wasm_exports.set_playing(on);
+}
+
+/**
+ * Sets how fast the game's history plays, in percent of normal speed, a move a second. It's
+ * rounded to 25, 50, 100, 200 or 400.
+ * From src/main.rs.
+ * @param {number} percent u32
+ */
+export function set_playback_speed(percent) {
+    return wasm_exports.set_playback_speed(percent);
+}
+
+/**
+ * Where the board is in the game's history, as JSON: {"view", "last", "playing", "speed", "path"},
+ * where view is how many moves into the game the position shown is, last how many moves there are,
+ * speed how many times normal speed it plays at and path the variation shown (see
+ * get_move_list), or [] for the game.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_playback() {
+    return wasm_exports.get_playback();
+}
+
+/**
+ * Turns analysis of the position shown on the board on or off.
+ * From src/main.rs.
+ * @param {number} on u32
+ */
+export function set_analysis(on) {
+    return wasm_exports.set_analysis(on);
+}
+
+/**
+ * Returns a pointer to the recording of what went into the game since it started or was loaded,
+ * as JSON (see recording.rs), to attach to a bug report. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_input_recording() {
+    return wasm_exports.get_input_recording();
+}
+
+/**
+ * Turns the rule debug overlay on or off, like the D key: for the piece under the mouse, every move
+ * its movement rules come up with, green if it's allowed and red if a constraint rule rejects it,
+ * with the rules' names. For working out why a ruleset or plugin doesn't do what it should.
+ * From src/main.rs.
+ * @param {number} on u32
+ */
+export function set_rule_debug(on) {
+    return wasm_exports.set_rule_debug(on);
+}
+
+/**
+ * Asks the page again how to decorate the board's squares and pieces (see decorations.rs), e.g.
+ * after a plugin's own state changed. Otherwise it's only asked when the position does.
+ * From src/main.rs.
+ */
+export function refresh_decorations() {
+    return wasm_exports.refresh_decorations();
+}
+
+/**
+ * Returns a pointer to the position shown on the board in FEN, e.g. to have the server analyze it.
+ * Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_fen() {
+    return wasm_exports.get_fen();
+}
+
+/**
+ * Returns the flags of the piece on a square of the position shown on the board, as SQUARE_FLAGS
+ * in ffi.js has them, or 0 if there's no piece there.
+ * From src/main.rs.
+ * @param {number} row u32
+ * @param {number} col u32
+ * @returns {number} u32
+ */
+export function get_square_flags(row, col) {
+    return wasm_exports.get_square_flags(row, col);
+}
+
+/**
+ * Sets the flags of the piece on a square of the current position, e.g. for a plugin to count down
+ * a piece's charges or freeze it. They're this board's alone, so in online games both sides' pages
+ * have to set them alike.
+ * From src/main.rs.
+ * @param {number} row u32
+ * @param {number} col u32
+ * @param {number} flags u32
+ */
+export function set_square_flags(row, col, flags) {
+    return wasm_exports.set_square_flags(row, col, flags);
+}
+
+/**
+ * Gives the analysis a message from the server's analysis of a position (see
+ * server/src/cloud.rs), as JSON with the position's FEN added, e.g.
+ * {"fen": "...", "info": {"depth": 12, "score": {"cp": 35}, "pv": ["e2e4", "e7e5"]}}
+ * It's shown instead of the local analysis while that position is on the board, once it's deeper.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function set_cloud_analysis(json_str_ptr) {
+    return wasm_exports.set_cloud_analysis(json_str_ptr);
+}
+
+/**
+ * Gives the game the server's answer to an explorer_query event: the JSON from
+ * /api/explorer?hash=<hash> (see explorer.rs).
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function set_explorer(json_str_ptr) {
+    return wasm_exports.set_explorer(json_str_ptr);
+}
+
+/**
+ * Returns a pointer to the player's settings as a JSON object (see settings.rs), for the page to
+ * save. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_settings() {
+    return wasm_exports.get_settings();
+}
+
+/**
+ * Changes the settings in a JSON object, e.g. {"coordinates": true}, leaving the others as they
+ * are. Nothing changes if any of them is invalid.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function set_settings(json_str_ptr) {
+    return wasm_exports.set_settings(json_str_ptr);
+}
+
+/**
+ * Asks for a picture of the position shown on the board, with black at the bottom if flipped,
+ * coordinates along the edges, and the last move highlighted, as asked. The game draws it on its
+ * next frame and sends it as a PNG in a board_image event.
+ * From src/main.rs.
+ * @param {number} flipped u32
+ * @param {number} coordinates u32
+ * @param {number} last_move u32
+ */
+export function request_board_image(flipped, coordinates, last_move) {
+    return wasm_exports.request_board_image(flipped, coordinates, last_move);
+}
+
+/**
+ * Asks for an animation of the game so far, from the first position to the current one, showing
+ * each position for delay_ms milliseconds (or a second if 0). The other arguments are as for
+ * request_board_image. The game draws it on its next frame and sends it as an animated PNG in a
+ * game_animation event.
+ * From src/main.rs.
+ * @param {number} flipped u32
+ * @param {number} coordinates u32
+ * @param {number} last_move u32
+ * @param {number} delay_ms u32
+ */
+export function request_game_animation(flipped, coordinates, last_move, delay_ms) {
+    return wasm_exports.request_game_animation(flipped, coordinates, last_move, delay_ms);
+}
+
+/**
+ * Shows the game's text in the language of a locale like "fr" or "pt-BR", e.g. the browser's
+ * navigator.language. English is used for anything there's no translation for.
+ * From src/main.rs.
+ * @param {number} locale_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function set_locale(locale_str_ptr) {
+    return wasm_exports.set_locale(locale_str_ptr);
+}
+
+/**
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function rules_update(json_str_ptr) {
+    return wasm_exports.rules_update(json_str_ptr);
+}
+
+/**
+ * The version of the plugin interface the game implements. See plugin_handshake.
+ * From src/main.rs.
+ * @returns {number} u32
+ */
+export function plugin_abi_version() {
+    return wasm_exports.plugin_abi_version();
+}
+
+/**
+ * Tells the game which plugin hooks the page implements, as JSON: {"abi_version": 3, "hooks":
+ * ["movement"]}. Plugins are off until then, and stay off if the page's version isn't the game's.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function plugin_handshake(json_str_ptr) {
+    return wasm_exports.plugin_handshake(json_str_ptr);
+}
+
+/**
+ * Returns a pointer to what's wrong with the rules in play, e.g. a piece no movement rule moves, as
+ * a JSON array of messages, empty if nothing is. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function get_rule_problems() {
+    return wasm_exports.get_rule_problems();
+}
+
+/**
+ * Checks a rule set as import_ruleset would take it, without playing it. Returns a pointer to
+ * what's wrong with it as a JSON array of messages, empty if nothing is. Free it when done.
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function check_ruleset(json_str_ptr) {
+    return wasm_exports.check_ruleset(json_str_ptr);
+}
+
+/**
+ * From src/main.rs.
+ * @param {number} json_str_ptr *const u8, a pointer into wasm_memory
+ */
+export function import_ruleset(json_str_ptr) {
+    return wasm_exports.import_ruleset(json_str_ptr);
+}
+
+/**
+ * Returns a pointer to the JSON rule set. Free it when done.
+ * From src/main.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function export_ruleset() {
+    return wasm_exports.export_ruleset();
+}
+
+/**
+ * Copies the crash report, a JSON object, into the retval_len bytes at retval_ptr. Returns the
+ * report's length, which is 0 if the game hasn't crashed. If it's more than retval_len, nothing is
+ * copied and JS should call again with a bigger buffer.
+ * From src/crash.rs.
+ * @param {number} retval_ptr *mut u8, a pointer into wasm_memory
+ * @param {number} retval_len usize
+ * @returns {number} usize
+ */
+export function get_crash_report(retval_ptr, retval_len) {
+    return wasm_exports.get_crash_report(retval_ptr, retval_len);
+}
+
+/**
+ * 0 = trace, 1 = debug, 2 = info (the default), 3 = warn, 4 = error, anything higher is off.
+ * From src/logging.rs.
+ * @param {number} n u32
+ */
+export function set_log_level(n) {
+    return wasm_exports.set_log_level(n);
+}
+
+/**
+ * How many recent lines to keep for log_history. 0 turns the history off and clears it.
+ * From src/logging.rs.
+ * @param {number} lines u32
+ */
+export function set_log_history(lines) {
+    return wasm_exports.set_log_history(lines);
+}
+
+/**
+ * Returns a pointer to the recent log lines. Free it when done.
+ * From src/logging.rs.
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function log_history() {
+    return wasm_exports.log_history();
+}
+
+/**
+ * Only exported in the browser. On the desktop, an unmangled free would replace libc's.
+ * From src/mem.rs.
+ * @param {number} len usize
+ * @returns {number} *mut u8, a pointer into wasm_memory
+ */
+export function alloc(len) {
+    return wasm_exports.alloc(len);
+}
+
+/**
+ * From src/mem.rs.
+ * @param {number} ptr *const u8, a pointer into wasm_memory
+ */
+export function free(ptr) {
+    return wasm_exports.free(ptr);
+}
+
+/**
+ * The length of the buffer at ptr, or 0 if ptr isn't a buffer from alloc.
+ * From src/mem.rs.
+ * @param {number} ptr *const u8, a pointer into wasm_memory
+ * @returns {number} usize
+ */
+export function memlen(ptr) {
+    return wasm_exports.memlen(ptr);
+}
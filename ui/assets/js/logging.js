@@ -0,0 +1,37 @@
+import { take_string, with_bytes } from "./mem.js";
+
+// Log levels understood by set_log_level. Anything above ERROR turns logging off.
+export const LogLevel = { TRACE: 0, DEBUG: 1, INFO: 2, WARN: 3, ERROR: 4, OFF: 5 };
+
+export function set_log_level(level) {
+    wasm_exports.set_log_level(level);
+}
+
+// How many recent log lines to keep for log_history. 0 turns the history off.
+export function set_log_history(lines) {
+    wasm_exports.set_log_history(lines);
+}
+
+// Returns the most recent log lines as a string, one per line, e.g. to attach to a bug report.
+export function log_history() {
+    return take_string(wasm_exports.log_history());
+}
+
+// Returns the crash report as an object, or null if the game hasn't crashed. The report has the
+// panic message, the position as FEN, the moves so far in long algebraic notation, the rule set
+// and the recent log lines.
+export function get_crash_report() {
+    let len = 64 * 1024;
+    for (;;) {
+        let buf = new Uint8Array(len);
+        let n = with_bytes(buf, (ptr) => {
+            let n = wasm_exports.get_crash_report(ptr, len);
+            if (n <= len)
+                buf.set(new Uint8Array(wasm_memory.buffer, ptr, n));
+            return n;
+        });
+        if (n <= len)
+            return n > 0 ? JSON.parse((new TextDecoder()).decode(buf.subarray(0, n))) : null;
+        len = n;
+    }
+}
@@ -0,0 +1,42 @@
+// Helpers for buffers shared with WASM (see src/mem.rs). A buffer's length is stored as a
+// little-endian u32 just before the pointer WASM hands out.
+
+export function buffer_len(ptr) {
+    return new DataView(wasm_memory.buffer).getUint32(ptr - 4, true);
+}
+
+// Reads a string WASM returned in a buffer, and frees the buffer.
+export function take_string(ptr) {
+    let s = (new TextDecoder()).decode(new Uint8Array(wasm_memory.buffer, ptr, buffer_len(ptr)));
+    wasm_exports.free(ptr);
+    return s;
+}
+
+// Reads the bytes WASM returned in a buffer, e.g. from get_snapshot, and frees the buffer.
+export function take_bytes(ptr) {
+    let bytes = new Uint8Array(wasm_memory.buffer, ptr, buffer_len(ptr)).slice();
+    wasm_exports.free(ptr);
+    return bytes;
+}
+
+// Copies bytes into a new buffer and passes its pointer to f, which can't keep it: the buffer is
+// freed as soon as f returns. Returns what f returns.
+export function with_bytes(bytes, f) {
+    let ptr = wasm_exports.alloc(bytes.length);
+    new Uint8Array(wasm_memory.buffer, ptr, bytes.length).set(bytes);
+    try {
+        return f(ptr);
+    } finally {
+        wasm_exports.free(ptr);
+    }
+}
+
+// Copies s as UTF-8 into a buffer and passes its pointer to f, as with_bytes does.
+export function with_string(s, f) {
+    return with_bytes((new TextEncoder()).encode(s), f);
+}
+
+// Copies obj as a JSON string into a buffer and passes its pointer to the WASM function f.
+export function call_with_json(f, obj) {
+    return with_bytes((new TextEncoder()).encode(JSON.stringify(obj)), f);
+}
@@ -1,31 +1,103 @@
+import { on } from "./events.js";
+import { PeerLink } from "./p2p.js";
+
 export class Multiplayer {
     constructor() {
         // public
         this.game_id = null;
         this.on_created = (game_id) => {};
         this.on_opponent_join = (color) => {};
-        this.on_opponent_move = (src_row, src_col, dst_row, dst_col) => {};
+        // move is the message as bytes, binary or JSON text, for WASM's receive_move.
+        this.on_opponent_move = (move, binary) => {};
+        this.on_ruleset = (ruleset) => {};
+        // Who's connected, {players, spectators}. Sent whenever someone joins or leaves.
+        this.on_presence = (presence) => {};
+        // Takebacks: the other player asks to go back to the position at ply, or answers our
+        // request. Both players go back once it's accepted.
+        this.on_takeback_request = (ply) => {};
+        this.on_takeback_accepted = (ply) => {};
+        this.on_takeback_declined = () => {};
+        // The other player left and didn't come back in time. fallback is what the server awards
+        // us, "win", "draw" or "abort", for WASM's adjudicate.
+        this.on_abandoned = (fallback) => {};
+        // Whether we can abort the game, when the server changes its mind about it: true once the
+        // other player has taken too long to make their first move, false when it refused to.
+        this.on_abortable = (abortable) => {};
+        // The server aborted the game, at either player's request.
+        this.on_aborted = () => {};
+        // Once the game is over, the other player's message about the board both analyze on, as
+        // text for WASM's receive_analysis.
+        this.on_analysis = (message) => {};
+        // A player reported the end of the game, e.g. to a spectator.
+        this.on_game_over = (result, reason) => {};
+        // The server is shutting down. The game can be rejoined with its link once it's back.
+        this.on_server_shutdown = () => {};
+        // The game is private, and we joined without its passcode when it doesn't let anyone else
+        // watch. The server closes the connection.
+        this.on_denied = () => {};
+        // We joined once both seats were taken, or without the passcode of a private game, so we
+        // watch the game rather than play it.
+        this.on_spectating = () => {};
+        // We took a seat in a rated game, which is played without assists like the blunder check.
+        this.on_rated = () => {};
+        // Our user ID (see settings.js), if we log in with it when we connect. The server then
+        // sends the settings we saved from any device, for WASM's set_settings.
+        this.user = null;
+        this.on_prefs = (prefs) => {};
         this.color = null;
+        // Whether to send moves peer to peer when possible (see p2p.js). It's up to the creator of
+        // the game; the other player goes along with it.
+        this.p2p = false;
 
         // private
         this._ws = null;
+        this._peer = null;
     }
 
-    create() {
+    // ruleset is optional. If given, it's sent to players when they join. The options are also
+    // optional: a private game has a passcode, which only those who join with it get a seat for,
+    // and spectators says whether anyone else can watch it. The server tells webhook, a URL, about
+    // the game as it's played (see server/src/webhooks.rs). A rated game turns off the ui's assists
+    // for both players.
+    create(ruleset, { passcode, spectators = true, webhook, rated = false } = {}) {
         this.close();
-        this._connect(`create`, (message) => {
+        let query = new URLSearchParams();
+        if (ruleset) {
+            query.set("ruleset", JSON.stringify(ruleset));
+        }
+        if (passcode) {
+            query.set("passcode", passcode);
+        }
+        if (!spectators) {
+            query.set("spectators", "false");
+        }
+        if (webhook) {
+            query.set("webhook", webhook);
+        }
+        if (rated) {
+            query.set("rated", "true");
+        }
+        let path = query.toString() ? `create?${query}` : `create`;
+        this._connect(path, (message) => {
             this.dispatch(message);
         });
     }
 
-    join(game_id) {
+    // passcode is optional, for a seat in a private game.
+    join(game_id, passcode) {
         this.close();
-        this._connect(`join/${game_id}`, (message) => {
+        let path = passcode ? `join/${game_id}?passcode=${passcode}` : `join/${game_id}`;
+        this._connect(path, (message) => {
             this.dispatch(message);
         });
     }
 
     dispatch(event) {
+        if (event.data instanceof ArrayBuffer) {
+            // Only moves are sent as binary.
+            this.on_opponent_move(new Uint8Array(event.data), true);
+            return;
+        }
         console.log(`Received message: ${event.data}`);
         let data = JSON.parse(event.data);
         if (data.game_id) {
@@ -45,6 +117,11 @@ export class Multiplayer {
             });
             this._ws.send(msg);
             this.on_opponent_join(this.color);
+            if (this.p2p) {
+                this._peer_link().offer();
+            }
+        } else if (data.presence) {
+            this.on_presence(data.presence);
         } else if (data.color) {
             // This message is received by the player not creating the game.
             // It tells them their color.
@@ -53,19 +130,51 @@ export class Multiplayer {
         } else if (data.src_row) {
             // This message is sent when the other player makes a move. It
             // should be validated and applied locally.
-            this.on_opponent_move(
-                data.src_row, data.src_col, data.dst_row, data.dst_col
-            );
+            this.on_opponent_move((new TextEncoder()).encode(event.data), false);
+        } else if (data.ruleset) {
+            // This message is received by the player joining the game, if
+            // the creator picked a rule set.
+            this.on_ruleset(data.ruleset);
         } else if (data.rules) {
             this.on_rules_update(data.rules);
+        } else if (data.takeback === "request") {
+            this.on_takeback_request(data.ply);
+        } else if (data.takeback === "accept") {
+            this.on_takeback_accepted(data.ply);
+        } else if (data.takeback === "decline") {
+            this.on_takeback_declined();
+        } else if (data.abandoned) {
+            this.on_abandoned(data.abandoned);
+        } else if (data.abortable !== undefined) {
+            this.on_abortable(data.abortable);
+        } else if (data.aborted) {
+            this.on_aborted();
+        } else if (data.analysis) {
+            this.on_analysis(event.data);
+        } else if (data.game_over) {
+            this.on_game_over(data.game_over.result, data.game_over.reason);
+        } else if (data.shutdown) {
+            this.on_server_shutdown();
+        } else if (data.denied) {
+            this.on_denied();
+        } else if (data.role === "spectator") {
+            this.on_spectating();
+        } else if (data.rated) {
+            this.on_rated();
+        } else if (data.prefs) {
+            this.on_prefs(data.prefs);
+        } else if (data.signal) {
+            // Setting up the peer to peer connection.
+            this._peer_link().handle_signal(data.signal);
         }
     }
 
-    on_move(src_row, src_col, dst_row, dst_col) {
-        if (this._ws) {
-            let data = JSON.stringify({
-                src_row, src_col, dst_row, dst_col
-            });
+    // move is our move as encoded by WASM: a binary frame, or JSON text if binary is false.
+    on_move(move, binary) {
+        let data = binary ? move : (new TextDecoder()).decode(move);
+        if (this._peer && this._peer.is_open()) {
+            this._peer.send(data);
+        } else if (this._ws) {
             this._ws.send(data);
         }
     }
@@ -77,30 +186,155 @@ export class Multiplayer {
         }
     }
 
+    request_takeback(ply) {
+        if (this._ws) {
+            this._ws.send(JSON.stringify({ takeback: "request", ply }));
+        }
+    }
+
+    answer_takeback(accept, ply) {
+        if (this._ws) {
+            let msg = accept ? { takeback: "accept", ply } : { takeback: "decline" };
+            this._ws.send(JSON.stringify(msg));
+        }
+    }
+
+    // message is our move on the board both players analyze on after the game, from WASM.
+    send_analysis(message) {
+        if (this._ws) {
+            this._ws.send(message);
+        }
+    }
+
+    // Asks the server to abort the game, which it only does before both players have moved.
+    abort() {
+        if (this._ws) {
+            this._ws.send(JSON.stringify({ abort: true }));
+        }
+    }
+
+    // Tells the server how the game ended, so it can record it. reason is the game's Termination
+    // tag.
+    report_result(result, reason) {
+        if (this._ws) {
+            this._ws.send(JSON.stringify({ game_over: { result, reason } }));
+        }
+    }
+
+    // Saves our settings on the server, for the next time we log in, if we have.
+    save_prefs(settings) {
+        if (this._ws && this.user) {
+            this._ws.send(JSON.stringify({ prefs: settings }));
+        }
+    }
+
     close() {
+        this.color = null;
+        if (this._peer) {
+            this._peer.close();
+            this._peer = null;
+        }
         if (this._ws) {
             this._ws.close();
             this._ws = null;
         }
     }
 
+    _peer_link() {
+        if (!this._peer) {
+            this._peer = new PeerLink(
+                (signal) => this._ws.send(JSON.stringify({ signal })),
+                (event) => this.dispatch(event),
+            );
+        }
+        return this._peer;
+    }
+
     _connect(path, onmessage) {
         let host = location.host;
         this._ws = new WebSocket(`wss://${host}/${path}`);
+        this._ws.binaryType = "arraybuffer";
         this._ws.onmessage = onmessage;
+        this._ws.onopen = () => this._login();
         // Do this because wss:// isn't implemented in local dev
         this._ws.onerror = (evt) => {
             console.log("Trying ws");
             this._ws = new WebSocket(`ws://${host}/${path}`);
+            this._ws.binaryType = "arraybuffer";
             this._ws.onmessage = onmessage;
+            this._ws.onopen = () => this._login();
+            // Some networks block websockets altogether. Fall back on server-sent events, unless
+            // the websocket got through and dropped later, which would start over in a new game.
+            let ws = this._ws;
+            let opened = false;
+            ws.addEventListener("open", () => opened = true);
+            ws.onerror = () => {
+                if (this._ws !== ws || opened) {
+                    return;
+                }
+                console.log("Trying server-sent events");
+                this._ws = new EventStream(`${location.protocol}//${host}/sse`, path);
+                this._ws.onmessage = onmessage;
+                this._ws.onopen = () => this._login();
+            };
+        }
+    }
+
+    _login() {
+        if (this.user) {
+            this._ws.send(JSON.stringify({ login: this.user }));
         }
     }
 }
 
+// Stands in for a websocket with server-sent events and POSTs (see server/src/sse.rs). Only what
+// Multiplayer uses of a websocket: onopen, onmessage, send() and close().
+class EventStream {
+    // base is the server's /sse URL, and path what would follow the websocket's.
+    constructor(base, path) {
+        this.onopen = () => {};
+        this.onmessage = (event) => {};
+
+        this._base = base;
+        this._session = null;
+        // Sent once we have a session.
+        this._queue = [];
+        // POSTs are sent one at a time, so they arrive in order.
+        this._sending = Promise.resolve();
+        this._source = new EventSource(`${base}/${path}`);
+        this._source.addEventListener("session", (event) => {
+            this._session = event.data;
+            this._queue.forEach((data) => this.send(data));
+            this._queue = [];
+            this.onopen();
+        });
+        this._source.onmessage = (event) => this.onmessage(event);
+        // EventSource reconnects by itself, but that would join the game again as someone else.
+        this._source.onerror = () => this.close();
+    }
+
+    // data is text, or a binary move frame.
+    send(data) {
+        if (!this._session) {
+            this._queue.push(data);
+            return;
+        }
+        let binary = data instanceof Uint8Array;
+        this._sending = this._sending
+            .then(() => fetch(`${this._base}/send/${this._session}`, {
+                method: "POST",
+                headers: { "Content-Type": binary ? "application/octet-stream" : "text/plain" },
+                body: data,
+            }))
+            .catch((e) => console.log(`Couldn't send message: ${e}`));
+    }
+
+    close() {
+        this._source.close();
+    }
+}
+
 export function init_multiplayer(on_move, get_player_color) {
-    register_plugin = function (importObject) {
-        importObject.env.on_move = on_move;
-        importObject.env.get_player_color = get_player_color;
-    };
-    miniquad_add_plugin({register_plugin});
+    on("move", on_move);
+    on("player_color", get_player_color);
 }
@@ -0,0 +1,65 @@
+// A WebRTC data channel between the two players, so moves don't have to go through the server.
+// The server is only used to exchange the connection details ("signals"), which it relays like any
+// other message. Until the channel is open, or if it can't be opened (e.g. the other player is
+// the desktop app, or a firewall is in the way), moves keep going through the server.
+export class PeerLink {
+    // send_signal(signal) sends a signal to the other player through the server. on_message(event)
+    // gets messages from the data channel, like a websocket's onmessage.
+    constructor(send_signal, on_message) {
+        this._send_signal = send_signal;
+        this._on_message = on_message;
+        this._channel = null;
+        this._pc = new RTCPeerConnection({
+            iceServers: [{ urls: "stun:stun.l.google.com:19302" }],
+        });
+        this._pc.onicecandidate = (event) => {
+            if (event.candidate) {
+                this._send_signal({ candidate: event.candidate });
+            }
+        };
+        this._pc.ondatachannel = (event) => this._use_channel(event.channel);
+    }
+
+    // Called by one player, the creator, to start connecting.
+    async offer() {
+        this._use_channel(this._pc.createDataChannel("moves", { ordered: true }));
+        await this._pc.setLocalDescription(await this._pc.createOffer());
+        this._send_signal({ description: this._pc.localDescription });
+    }
+
+    async handle_signal(signal) {
+        try {
+            if (signal.description) {
+                await this._pc.setRemoteDescription(signal.description);
+                if (signal.description.type === "offer") {
+                    await this._pc.setLocalDescription(await this._pc.createAnswer());
+                    this._send_signal({ description: this._pc.localDescription });
+                }
+            } else if (signal.candidate) {
+                await this._pc.addIceCandidate(signal.candidate);
+            }
+        } catch (e) {
+            console.log(`Peer to peer connection failed, moves will go through the server: ${e}`);
+        }
+    }
+
+    is_open() {
+        return this._channel !== null && this._channel.readyState === "open";
+    }
+
+    send(data) {
+        this._channel.send(data);
+    }
+
+    close() {
+        this._pc.close();
+        this._channel = null;
+    }
+
+    _use_channel(channel) {
+        channel.binaryType = "arraybuffer";
+        channel.onopen = () => console.log("Peer to peer connection open");
+        channel.onmessage = this._on_message;
+        this._channel = channel;
+    }
+}
@@ -0,0 +1,32 @@
+import { with_string } from "./mem.js";
+
+// Pasting into the page, anywhere but a text field, loads the position pasted for analysis with
+// paste_fen, which reports a position it can't load with the status event. Pasted text is read as
+// FEN. The game can't read a screenshot of a board itself, so a page that can passes
+// recognize(file), which resolves to the FEN in the pasted image.
+export function init_paste(recognize) {
+    document.addEventListener("paste", async (event) => {
+        if (event.target instanceof HTMLInputElement || event.target instanceof HTMLTextAreaElement) {
+            return;
+        }
+        let data = event.clipboardData;
+        let image = [...data.items].find((item) => item.type.startsWith("image/"));
+        let fen;
+        if (image && recognize) {
+            event.preventDefault();
+            try {
+                fen = await recognize(image.getAsFile());
+            } catch (e) {
+                console.log(`Couldn't read the pasted board: ${e}`);
+                return;
+            }
+        } else {
+            fen = data.getData("text/plain");
+            if (!fen) {
+                return;
+            }
+            event.preventDefault();
+        }
+        with_string(fen, wasm_exports.paste_fen);
+    });
+}
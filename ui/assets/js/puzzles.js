@@ -0,0 +1,40 @@
+import { on } from "./events.js";
+import { with_string } from "./mem.js";
+
+// The puzzle of the day from the server serving the page (see server/src/puzzles.rs). Loads it on
+// the board as the given user, and resolves with their streak, or rejects if there's no puzzle or
+// the game won't take it.
+export async function load_daily_puzzle(user) {
+    let response = await fetch(`/api/puzzles/daily?user=${encodeURIComponent(user)}`);
+    if (!response.ok) {
+        throw new Error(`${response.status} ${await response.text()}`);
+    }
+    let json = await response.text();
+    if (!with_string(json, wasm_exports.load_puzzle)) {
+        throw new Error("Invalid puzzle");
+    }
+    return JSON.parse(json).streak;
+}
+
+// Tells the server whenever the user solves the puzzle of the day without a wrong move, and calls
+// on_streak with their streak. Other puzzles, and solves with mistakes, don't count.
+export function init_puzzles(user, on_streak) {
+    on("puzzle_solved", async ({ id, mistakes }) => {
+        if (mistakes > 0) {
+            return;
+        }
+        try {
+            let response = await fetch("/api/puzzles/daily/solved", {
+                method: "POST",
+                headers: { "Content-Type": "application/json" },
+                body: JSON.stringify({ user, id }),
+            });
+            if (!response.ok) {
+                throw new Error(`${response.status} ${await response.text()}`);
+            }
+            on_streak((await response.json()).streak);
+        } catch (e) {
+            console.log(`Solve not counted: ${e}`);
+        }
+    });
+}
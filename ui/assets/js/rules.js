@@ -1,30 +1,54 @@
+import { PLUGIN_MOVE, SQUARE_FLAGS, read_piece } from "./ffi.js";
+import { call_with_json, take_string } from "./mem.js";
+
 class MovementRule {
-    constructor(piece_ptr, placements_ptr, retval_ptr, retval_len) {
+    constructor(piece_ptr, placements_ptr, flags_ptr, placements_len, width, height, retval_ptr, retval_len) {
         let memory = wasm_memory.buffer;
-        let piece_arr = new Uint8Array(memory, piece_ptr, 3);
-        this.row = piece_arr[0];
-        this.col = piece_arr[1];
-        let piece_ascii = piece_arr[2];
-        this.piece_name = String.fromCharCode(piece_ascii);
-        this.stride = 8 + 1;  // TODO: Get this constant from Rust
-        this.placements = new Uint8Array(memory, placements_ptr, (8 + 1) * this.stride);
+        let piece = read_piece(piece_ptr);
+        this.row = piece.row;
+        this.col = piece.col;
+        this.piece_name = piece.name;
+        // Row by row from a1, with rows and columns counting from 1.
+        this.width = width;
+        this.height = height;
+        this.placements = new Uint8Array(memory, placements_ptr, placements_len);
+        this.flags = new Uint8Array(memory, flags_ptr, placements_len);
         this.retval = new Uint8Array(memory, retval_ptr, retval_len);
         this.ri = 0;
         console.log(`Movement plugin called: (${this.row}, ${this.col}, ${this.piece_name})`);
     }
 
     piece_at(r, c) {
-        // TODO: bounds check
-        let piece_ascii = this.placements[r * this.stride + c];
+        if (r < 1 || r > this.height || c < 1 || c > this.width)
+            return null;
+        let piece_ascii = this.placements[(r - 1) * this.width + c - 1];
         return piece_ascii !== 0 ? String.fromCharCode(piece_ascii) : null;
     }
 
+    // The flags of the piece at (r, c), as SQUARE_FLAGS has them, or 0.
+    flags_at(r, c) {
+        if (r < 1 || r > this.height || c < 1 || c > this.width)
+            return 0;
+        return this.flags[(r - 1) * this.width + c - 1];
+    }
+
+    has_moved(r, c) {
+        return (this.flags_at(r, c) & SQUARE_FLAGS.moved) !== 0;
+    }
+
+    charges(r, c) {
+        return (this.flags_at(r, c) & SQUARE_FLAGS.charges) >> SQUARE_FLAGS.charges_shift;
+    }
+
     add_allowed_move(r, c, n) {
-        // TODO: bounds check
-        this.retval[this.ri] = r;
-        this.retval[this.ri + 1] = c;
-        this.retval[this.ri + 2] = n.charCodeAt(0);
-        this.ri += 3;
+        if (this.ri + PLUGIN_MOVE.size > this.retval.length) {
+            console.log(`Too many moves from the movement rule, ignoring (${r}, ${c}, ${n})`);
+            return;
+        }
+        this.retval[this.ri + PLUGIN_MOVE.row] = r;
+        this.retval[this.ri + PLUGIN_MOVE.col] = c;
+        this.retval[this.ri + PLUGIN_MOVE.name] = n.charCodeAt(0);
+        this.ri += PLUGIN_MOVE.size;
     }
 }
 
@@ -36,20 +60,53 @@ export function register_movement_rule(func) {
     rules.movement_rule = func;
 }
 
+// Toggles movement and constraint rules by name, e.g. {"forced-capture": true}. "double-move" gives each
+// side two moves a turn (Marseillais chess) and starts a new game.
 export function rules_update(rules) {
-    const json = (new TextEncoder()).encode(JSON.stringify(rules));
-    let strptr = wasm_exports.alloc(json.length);
-    new Uint8Array (wasm_memory.buffer, strptr, json.length).set(json);
-    wasm_exports.rules_update(strptr);
-    wasm_exports.free(strptr);
+    call_with_json(wasm_exports.rules_update, rules);
+}
+
+// Returns the active rule set (rule toggles, promotions, board size, name) as an object that can be shared
+// and later passed to import_ruleset.
+export function export_ruleset() {
+    return JSON.parse(take_string(wasm_exports.export_ruleset()));
 }
 
+export function import_ruleset(ruleset) {
+    call_with_json(wasm_exports.import_ruleset, ruleset);
+}
+
+// What's wrong with a rule set, e.g. a piece no movement rule moves, as an array of messages saying
+// what to fix. It's empty if the rule set is fine. The rule set isn't played.
+export function check_ruleset(ruleset) {
+    return JSON.parse(take_string(call_with_json(wasm_exports.check_ruleset, ruleset)));
+}
+
+// What's wrong with the rules in play, as check_ruleset has it. The game also shows the first
+// problem on its status line (see the status event) when the rules change.
+export function rule_problems() {
+    return JSON.parse(take_string(wasm_exports.get_rule_problems()));
+}
+
+// The plugin ABI this file implements (see PLUGIN_ABI_VERSION in src/rules.rs), and its hooks.
+const PLUGIN_ABI_VERSION = 3;
+const PLUGIN_HOOKS = ["movement"];
+
 export function init_rules() {
     register_plugin = function (importObject) {
-        importObject.env.movement_plugin = (piece_ptr, placements_ptr, retval_ptr, retval_len) => {
-            let rule = new MovementRule(piece_ptr, placements_ptr, retval_ptr, retval_len);
+        importObject.env.movement_plugin = (piece_ptr, placements_ptr, flags_ptr, placements_len, width, height, retval_ptr, retval_len) => {
+            let rule = new MovementRule(piece_ptr, placements_ptr, flags_ptr, placements_len, width, height, retval_ptr, retval_len);
             rules.movement_rule(rule);
         }
     };
-    miniquad_add_plugin({register_plugin});
+    // Once WASM is loaded, tell it which hooks we implement. It only calls them if it implements
+    // the same version.
+    let on_init = function () {
+        let version = wasm_exports.plugin_abi_version();
+        if (version !== PLUGIN_ABI_VERSION) {
+            console.log(`Plugins are off: the game implements plugin ABI version ${version}, this page ${PLUGIN_ABI_VERSION}`);
+        }
+        call_with_json(wasm_exports.plugin_handshake, { abi_version: PLUGIN_ABI_VERSION, hooks: PLUGIN_HOOKS });
+    };
+    miniquad_add_plugin({register_plugin, on_init});
 }
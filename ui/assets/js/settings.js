@@ -0,0 +1,48 @@
+import { call_with_json, take_string } from "./mem.js";
+
+// The player's settings (see src/settings.rs) are kept in localStorage under this key, so they
+// survive reloads.
+const STORAGE_KEY = "chess-settings";
+// The user ID the page logs in to the server with, so settings follow the player to other devices
+// (see server/src/prefs.rs). It's made up the first time.
+const USER_KEY = "chess-user";
+
+// Gives the game the saved settings once it's loaded. on_load(settings) is then called with all of
+// them, e.g. to show them in the page's controls.
+export function init_settings(on_load) {
+    let on_init = function () {
+        let saved = localStorage.getItem(STORAGE_KEY);
+        if (saved !== null) {
+            try {
+                call_with_json(wasm_exports.set_settings, JSON.parse(saved));
+            } catch (e) {
+                console.log(`Ignoring saved settings: ${e}`);
+            }
+        }
+        if (on_load) {
+            on_load(get_settings());
+        }
+    };
+    miniquad_add_plugin({on_init});
+}
+
+export function get_settings() {
+    return JSON.parse(take_string(wasm_exports.get_settings()));
+}
+
+// Changes some of the settings, e.g. {coordinates: true}, and saves them all. The game applies
+// them on its next frame.
+export function set_settings(settings) {
+    call_with_json(wasm_exports.set_settings, settings);
+    localStorage.setItem(STORAGE_KEY, JSON.stringify(get_settings()));
+}
+
+// The player's user ID, a random UUID. Anyone who has it gets their settings, like a password.
+export function user_id() {
+    let user = localStorage.getItem(USER_KEY);
+    if (user === null) {
+        user = crypto.randomUUID();
+        localStorage.setItem(USER_KEY, user);
+    }
+    return user;
+}
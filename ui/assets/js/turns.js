@@ -0,0 +1,35 @@
+import { on } from "./events.js";
+
+// Lets the page react when a turn starts or the game ends, e.g. to flash the tab's title, show a
+// desktop notification or play a sound while the player is looking at another tab.
+// on_turn_start(color) gets "white" or "black", and on_game_end(result, reason) gets "1-0", "0-1",
+// "1/2-1/2", or "*" if the game was aborted, and why it ended, e.g. "checkmate" or "resignation".
+export function init_turns(on_turn_start, on_game_end) {
+    on("turn_start", on_turn_start);
+    on("game_end", (end) => on_game_end(end.result, end.reason));
+}
+
+let stop_flashing = null;
+
+// Alternates the tab's title with message until the page is visible again.
+export function flash_title(message) {
+    if (stop_flashing) {
+        stop_flashing();
+    }
+    let title = document.title;
+    let timer = setInterval(() => {
+        document.title = document.title === message ? title : message;
+    }, 1000);
+    let on_visible = () => {
+        if (!document.hidden) {
+            stop_flashing();
+        }
+    };
+    stop_flashing = () => {
+        clearInterval(timer);
+        document.title = title;
+        document.removeEventListener("visibilitychange", on_visible);
+        stop_flashing = null;
+    };
+    document.addEventListener("visibilitychange", on_visible);
+}
@@ -0,0 +1,21 @@
+#![no_main]
+
+use chess_ui::{
+    notation::{fen, parse_fen},
+    prelude::*,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    if let Ok((pp, gd)) = parse_fen(data) {
+        // Whatever parses must round trip, and be safe to generate moves in. The position may
+        // not be reachable, e.g. have no kings, or pawns on the back rank.
+        let s = fen(&pp, gd);
+        assert_eq!(parse_fen(&s), Ok((pp, gd)), "{}", s);
+        let rules = Rules::defaults();
+        for (p, m) in rules.legal_moves(gd.player_to_move(), &pp, gd) {
+            let mut after = pp;
+            Rules::make_move(p, m, &mut after);
+        }
+    }
+});
@@ -0,0 +1,32 @@
+#![no_main]
+
+use chess_ui::{
+    notation::{parse_pgn, parse_san, san},
+    prelude::*,
+};
+use libfuzzer_sys::fuzz_target;
+
+// Replays pasted games as far as their moves are legal.
+fuzz_target!(|data: &str| {
+    let Ok(games) = parse_pgn(data) else {
+        return;
+    };
+    let rules = Rules::defaults();
+    for game in games {
+        let mut pp = rules.initial_placements();
+        let mut gd = GameData { ply: 1, mask: 0 };
+        for s in &game.moves {
+            let Some((p, m)) = parse_san(&rules, &pp, gd, s) else {
+                break;
+            };
+            // What we write, we must be able to read back.
+            let written = san(&rules, &pp, gd, p, m);
+            assert_eq!(parse_san(&rules, &pp, gd, &written), Some((p, m)));
+            Rules::make_move(p, m, &mut pp);
+            gd = GameData {
+                ply: m.game_data.ply + 1,
+                ..m.game_data
+            };
+        }
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use chess_ui::protocol::{decode, decode_binary, encode_move, encode_move_json, Message};
+use libfuzzer_sys::fuzz_target;
+
+// Messages from the other player, relayed by the server, as text or as a binary move frame.
+fuzz_target!(|data: &[u8]| {
+    let mut decoded = vec![decode_binary(data)];
+    if let Ok(s) = std::str::from_utf8(data) {
+        decoded.push(decode(s));
+    }
+    for d in decoded {
+        if let Ok(Message::Move(m)) = d {
+            // The game indexes the board with these.
+            for n in [m.src_row, m.src_col, m.dst_row, m.dst_col] {
+                assert!((1..=8).contains(&n));
+            }
+            assert_eq!(decode_binary(&encode_move(&m)), Ok(Message::Move(m)));
+            assert_eq!(decode(&encode_move_json(&m)), Ok(Message::Move(m)));
+        }
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use chess_ui::prelude::*;
+use libfuzzer_sys::fuzz_target;
+
+// Rule sets come from links and other players.
+fuzz_target!(|data: &str| {
+    let mut rules = Rules::defaults();
+    if rules.import_ruleset(data).is_ok() {
+        let mut again = Rules::defaults();
+        again.import_ruleset(&rules.export_ruleset()).unwrap();
+        assert_eq!(again.export_ruleset(), rules.export_ruleset());
+    }
+});
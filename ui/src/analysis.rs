@@ -0,0 +1,396 @@
+// Keeps the engine analyzing the position on the board, e.g. while replaying a game. On the
+// desktop, the search runs on its own thread. WASM has no threads, so in the browser it's time
+// sliced instead: each frame searches for what's left of the frame's budget (see scheduler.rs) and
+// picks up where it left off on the next one. Either way, dropping the Analyzer stops the search.
+// The page can also ask the server for a deeper analysis (see server/src/cloud.rs), which is shown
+// instead of the local one once it's gone deeper.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use macroquad::miniquad::date;
+use macroquad::prelude::*;
+
+use serde_json::Value;
+
+use chess_ui::notation::{long_algebraic, parse_long_algebraic};
+
+use crate::{
+    engine::{mate_in, Analysis, AnalysisInfo, PvLine, SearchInfo, MATE_SCORE},
+    prelude::*,
+    warn,
+};
+
+// A root move's search can't be split across frames, so deeper searches would freeze the page.
+#[cfg(target_arch = "wasm32")]
+const MAX_DEPTH: u32 = 4;
+// How many of the best moves to show lines for.
+const MULTI_PV: usize = 3;
+const EVAL_BAR_WIDTH: f32 = 12.0;
+const PV_HEIGHT: f32 = 28.0;
+const PV_MOVES: usize = 8;
+// The search panel in the top corner, which shows how the search is going.
+const PANEL_ROW_HEIGHT: f32 = 20.0;
+const PANEL_WIDTH: f32 = 260.0;
+// How often the desktop analysis thread reports a SearchInfo, in seconds.
+#[cfg(not(target_arch = "wasm32"))]
+const REPORT_INTERVAL: f64 = 0.25;
+
+#[cfg(target_arch = "wasm32")]
+pub struct Analyzer {
+    pp: PiecePlacements,
+    gd: GameData,
+    analysis: Analysis,
+    remote: Option<AnalysisInfo>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Analyzer {
+    pub fn start(_rules: &Rules, pp: PiecePlacements, gd: GameData) -> Self {
+        let mut analysis = Analysis::new(pp, gd, MULTI_PV);
+        analysis.max_depth = MAX_DEPTH;
+        Self {
+            pp,
+            gd,
+            analysis,
+            remote: None,
+        }
+    }
+
+    // Searches until the time until has passed. Returns whether there's more to search.
+    pub fn update(&mut self, rules: &Rules, until: f64) -> bool {
+        self.analysis.step(rules, until, None);
+        !self.analysis.done()
+    }
+
+    pub fn info(&self) -> Option<&AnalysisInfo> {
+        deeper(self.analysis.info.as_ref(), self.remote.as_ref())
+    }
+
+    // An analysis of the same position from elsewhere.
+    pub fn set_remote(&mut self, info: AnalysisInfo) {
+        self.remote = Some(info);
+    }
+
+    pub fn search_info(&self) -> Option<SearchInfo> {
+        Some(self.analysis.search_info()).filter(|s| s.nodes > 0)
+    }
+
+    pub fn is_analyzing(&self, pp: &PiecePlacements, gd: GameData) -> bool {
+        (&self.pp, self.gd) == (pp, gd)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Analyzer {
+    pp: PiecePlacements,
+    gd: GameData,
+    stop: Arc<AtomicBool>,
+    // A SearchInfo every REPORT_INTERVAL, with an AnalysisInfo when a depth was completed.
+    rx: mpsc::Receiver<(Option<AnalysisInfo>, SearchInfo)>,
+    info: Option<AnalysisInfo>,
+    search: Option<SearchInfo>,
+    remote: Option<AnalysisInfo>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Analyzer {
+    pub fn start(rules: &Rules, pp: PiecePlacements, gd: GameData) -> Self {
+        // Rules can't be sent to another thread, so the thread makes its own from the rule set.
+        let ruleset = rules.export_ruleset();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            let mut rules = Rules::defaults();
+            if let Err(e) = rules.import_ruleset(&ruleset) {
+                warn!("Can't analyze: {}", e);
+                return;
+            }
+            let mut analysis = Analysis::new(pp, gd, MULTI_PV);
+            while !analysis.done() && !thread_stop.load(Ordering::Relaxed) {
+                let until = date::now() + REPORT_INTERVAL;
+                let updated = analysis.step(&rules, until, Some(&thread_stop));
+                let info = analysis.info.clone().filter(|_| updated);
+                if tx.send((info, analysis.search_info())).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            pp,
+            gd,
+            stop,
+            rx,
+            info: None,
+            search: None,
+            remote: None,
+        }
+    }
+
+    // Picks up what the thread found. It searches on its own, so there's never more to do here.
+    pub fn update(&mut self, _rules: &Rules, _until: f64) -> bool {
+        while let Ok((info, search)) = self.rx.try_recv() {
+            if info.is_some() {
+                self.info = info;
+            }
+            self.search = Some(search);
+        }
+        false
+    }
+
+    pub fn info(&self) -> Option<&AnalysisInfo> {
+        deeper(self.info.as_ref(), self.remote.as_ref())
+    }
+
+    // An analysis of the same position from elsewhere.
+    pub fn set_remote(&mut self, info: AnalysisInfo) {
+        self.remote = Some(info);
+    }
+
+    pub fn search_info(&self) -> Option<SearchInfo> {
+        self.search.clone()
+    }
+
+    pub fn is_analyzing(&self, pp: &PiecePlacements, gd: GameData) -> bool {
+        (&self.pp, self.gd) == (pp, gd)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Analyzer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn deeper<'a>(
+    local: Option<&'a AnalysisInfo>,
+    remote: Option<&'a AnalysisInfo>,
+) -> Option<&'a AnalysisInfo> {
+    match (local, remote) {
+        (Some(l), Some(r)) if l.depth >= r.depth => Some(l),
+        _ => remote.or(local),
+    }
+}
+
+// An info message from the server's analysis of the position pp, gd, e.g.
+//   {"depth": 4, "score": {"cp": 35}, "pv": ["e2e4", "e7e5"]}
+// where the score is from the point of view of the player to move, as in UCI. The line stops at
+// the first move that isn't legal.
+pub fn remote_info(
+    info: &Value,
+    rules: &Rules,
+    pp: &PiecePlacements,
+    gd: GameData,
+) -> Result<AnalysisInfo, String> {
+    let depth = info["depth"].as_u64().ok_or("Missing depth")? as u32;
+    let score = &info["score"];
+    let score = match (score["cp"].as_i64(), score["mate"].as_i64()) {
+        (Some(cp), _) => cp as i32,
+        (_, Some(n)) if n > 0 => MATE_SCORE - (2 * n as i32 - 1),
+        (_, Some(n)) => -(MATE_SCORE - 2 * n.unsigned_abs() as i32),
+        _ => return Err("Missing score".to_string()),
+    };
+    let score = if gd.player_to_move() == 0 {
+        score
+    } else {
+        -score
+    };
+    let mut moves = Vec::new();
+    let (mut pp, mut gd) = (*pp, gd);
+    for m in info["pv"].as_array().into_iter().flatten() {
+        let Some((p, m)) = m
+            .as_str()
+            .and_then(|s| parse_long_algebraic(rules, &pp, gd, s))
+        else {
+            break;
+        };
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+        moves.push((p, m));
+    }
+    let lines = if moves.is_empty() {
+        Vec::new()
+    } else {
+        vec![PvLine { score, moves }]
+    };
+    Ok(AnalysisInfo {
+        depth,
+        lines,
+        score,
+    })
+}
+
+// E.g. "+0.35", or "#3" when white mates in 3 moves, or "#-3" when black does.
+pub fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(n) if score < 0 => format!("#-{}", n.abs()),
+        Some(n) => format!("#{}", n),
+        None => format!("{:+.2}", score as f32 / 100.0),
+    }
+}
+
+// Draws an eval bar along the left edge of the board and a row for each line along the bottom.
+// flipped is true when black is at the bottom. The selected line, if any, is highlighted.
+pub fn draw_analysis(info: &AnalysisInfo, flipped: bool, selected: Option<usize>) {
+    // TODO: get board size from rules
+    let board = 8.0 * SQUARE_SIZE;
+    // White's share of the bar. A 10 pawn advantage fills it.
+    let white = match mate_in(info.score) {
+        Some(_) if info.score > 0 => 1.0,
+        Some(_) => 0.0,
+        None => (0.5 + info.score as f32 / 2000.0).clamp(0.0, 1.0),
+    };
+    let white_height = white * board;
+    let white_y = if flipped { 0.0 } else { board - white_height };
+    draw_rectangle(0.0, 0.0, EVAL_BAR_WIDTH, board, DARKGRAY);
+    draw_rectangle(0.0, white_y, EVAL_BAR_WIDTH, white_height, WHITE);
+
+    for (i, line) in info.lines.iter().enumerate() {
+        let mut text = format!("{}  {}", info.depth, format_score(line.score));
+        for &(p, m) in line.moves.iter().take(PV_MOVES) {
+            text.push_str("  ");
+            text.push_str(&long_algebraic(p, m));
+        }
+        let y = line_y(info, i);
+        let alpha = if selected == Some(i) { 0.85 } else { 0.6 };
+        draw_rectangle(
+            EVAL_BAR_WIDTH,
+            y,
+            board - EVAL_BAR_WIDTH,
+            PV_HEIGHT,
+            Color::new(0.0, 0.0, 0.0, alpha),
+        );
+        draw_text(
+            &text,
+            EVAL_BAR_WIDTH + 8.0,
+            y + PV_HEIGHT - 7.0,
+            PV_HEIGHT,
+            WHITE,
+        );
+    }
+}
+
+// Draws how the search is going in the top corner of the board: a header, which is all there is
+// while the panel is closed, then a row each for the depths, the node counts, and the best line.
+pub fn draw_search_panel(search: &SearchInfo, open: bool) {
+    let header = format!(
+        "[{}] Search  depth {}",
+        if open { "-" } else { "+" },
+        search.depth
+    );
+    let mut rows = vec![header];
+    if open {
+        let pv: Vec<_> = search
+            .pv
+            .iter()
+            .take(PV_MOVES)
+            .map(|&(p, m)| long_algebraic(p, m))
+            .collect();
+        rows.extend([
+            format!("depth {}  seldepth {}", search.depth, search.seldepth),
+            format!(
+                "{} nodes  {} nps  {:.1}s",
+                format_count(search.nodes),
+                format_count(search.nps()),
+                search.elapsed
+            ),
+            format!("pv {}", pv.join(" ")),
+        ]);
+    }
+    draw_rectangle(
+        EVAL_BAR_WIDTH,
+        0.0,
+        PANEL_WIDTH,
+        rows.len() as f32 * PANEL_ROW_HEIGHT,
+        Color::new(0.0, 0.0, 0.0, 0.6),
+    );
+    for (i, row) in rows.iter().enumerate() {
+        let y = (i + 1) as f32 * PANEL_ROW_HEIGHT - 5.0;
+        draw_text(row, EVAL_BAR_WIDTH + 8.0, y, PANEL_ROW_HEIGHT, LIGHTGRAY);
+    }
+}
+
+// Whether x, y is on the search panel's header, which opens and closes it.
+pub fn search_header_at(x: f32, y: f32) -> bool {
+    (EVAL_BAR_WIDTH..EVAL_BAR_WIDTH + PANEL_WIDTH).contains(&x)
+        && (0.0..PANEL_ROW_HEIGHT).contains(&y)
+}
+
+// E.g. "950", "12.3k" or "4.5M".
+fn format_count(n: u64) -> String {
+    match n {
+        0..=999 => n.to_string(),
+        1000..=999_999 => format!("{:.1}k", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+// The top of the row for the i-th line. The last line is at the bottom of the board.
+fn line_y(info: &AnalysisInfo, i: usize) -> f32 {
+    // TODO: get board size from rules
+    8.0 * SQUARE_SIZE - (info.lines.len() - i) as f32 * PV_HEIGHT
+}
+
+// The index of the line drawn at x, y, if any.
+pub fn line_at(info: &AnalysisInfo, x: f32, y: f32) -> Option<usize> {
+    if !(EVAL_BAR_WIDTH..8.0 * SQUARE_SIZE).contains(&x) {
+        return None;
+    }
+    (0..info.lines.len()).find(|&i| {
+        let top = line_y(info, i);
+        top <= y && y < top + PV_HEIGHT
+    })
+}
+
+// The position at the end of the line, as far as it's drawn.
+pub fn line_position(pp: &PiecePlacements, line: &PvLine) -> PiecePlacements {
+    let mut pp = *pp;
+    for &(p, m) in line.moves.iter().take(PV_MOVES) {
+        Rules::make_move(p, m, &mut pp);
+    }
+    pp
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use chess_ui::notation::parse_fen;
+
+    use super::*;
+
+    #[test]
+    fn test_remote_info() {
+        let rules = Rules::defaults();
+        // Black to move, so the scores are from black's point of view.
+        let (pp, gd) = parse_fen("6k1/5ppp/8/8/8/8/8/R5K1 b - - 0 1").unwrap();
+        let info = json!({"depth": 9, "score": {"mate": -1}, "pv": ["g8f8", "a1a8", "e1e2"]});
+        let info = remote_info(&info, &rules, &pp, gd).unwrap();
+        assert_eq!(format_score(info.score), "#1");
+        // e1e2 isn't a move.
+        assert_eq!(info.lines[0].moves.len(), 2);
+        let info = json!({"depth": 2, "score": {"cp": 35}, "pv": []});
+        let info = remote_info(&info, &rules, &pp, gd).unwrap();
+        assert_eq!((info.score, info.lines.len()), (-35, 0));
+        assert!(remote_info(&json!({"depth": 2}), &rules, &pp, gd).is_err());
+        let remote = AnalysisInfo {
+            depth: 9,
+            ..info.clone()
+        };
+        assert_eq!(deeper(Some(&info), Some(&remote)), Some(&remote));
+        assert_eq!(deeper(Some(&remote), Some(&info)), Some(&remote));
+        assert_eq!(deeper(Some(&info), None), Some(&info));
+    }
+}
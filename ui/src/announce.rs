@@ -0,0 +1,120 @@
+// Moves and results described in words, e.g. "White knight from g1 to f3, check", in the player's
+// language (see i18n.rs). The game sends them to the page as announce events, so it can read them
+// out to screen reader users, e.g. through an ARIA live region.
+
+use chess_ui::notation::square_name;
+
+use crate::{
+    i18n::{has_message, tr, tr_with},
+    prelude::*,
+};
+
+// E.g. "White knight", or just the letter for pieces the game has no name for.
+pub fn piece_name(name: u8) -> String {
+    let key = format!("piece.{}", name as char);
+    if has_message(&key) {
+        tr(&key)
+    } else {
+        (name as char).to_string()
+    }
+}
+
+// pp is the position before the move, and check whether the move gives check.
+pub fn describe_move(pp: &PiecePlacements, p: Piece, m: Move, check: bool) -> String {
+    let piece = piece_name(p.name);
+    let from = square_name(p.row, p.col);
+    let to = square_name(m.dst.row, m.dst.col);
+    let squares = [
+        ("piece", &piece as &dyn std::fmt::Display),
+        ("from", &from),
+        ("to", &to),
+    ];
+    let mut text = match m.typ {
+        MoveType::Secondary { .. } if p.name.eq_ignore_ascii_case(&b'k') => {
+            let key = if m.dst.col > p.col {
+                "announce.castle_kingside"
+            } else {
+                "announce.castle_queenside"
+            };
+            tr_with(key, &[("piece", &piece)])
+        }
+        MoveType::Capture { row, col } => {
+            let captured = piece_name(pp.get(row as usize, col as usize));
+            let args = [squares.as_slice(), &[("captured", &captured)]].concat();
+            tr_with("announce.capture", &args)
+        }
+        MoveType::Captures { squares: captured } => {
+            let count = captured.count_ones();
+            let args = [squares.as_slice(), &[("count", &count)]].concat();
+            tr_with("announce.captures", &args)
+        }
+        // A piece put on the board, like the duck, moves from and to the same square.
+        MoveType::Pass if p.is_white() => tr("announce.pass_white"),
+        MoveType::Pass => tr("announce.pass_black"),
+        _ if (p.row, p.col) == (m.dst.row, m.dst.col) => tr_with("announce.place", &squares),
+        _ => tr_with("announce.move", &squares),
+    };
+    if m.dst.name != p.name {
+        let promoted = piece_name(m.dst.name);
+        text = tr_with(
+            "announce.promotion",
+            &[("move", &text), ("piece", &promoted)],
+        );
+    }
+    if check {
+        text = tr_with("announce.check", &[("move", &text)]);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_move() {
+        let rules = Rules::defaults();
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ......P.
+            ........
+            ........
+            ...b....
+            .....N..
+            ........
+            R...K...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0 };
+        let describe = |from: &str, to: &str| {
+            // Pawns promote to a queen.
+            let (p, m) = rules
+                .legal_moves(0, &pp, gd)
+                .into_iter()
+                .filter(|&(p, m)| {
+                    square_name(p.row, p.col) == from && square_name(m.dst.row, m.dst.col) == to
+                })
+                .max_by_key(|&(_, m)| m.dst.name == b'Q')
+                .unwrap();
+            let mut after = pp;
+            Rules::make_move(p, m, &mut after);
+            let next = GameData {
+                ply: m.game_data.ply + 1,
+                ..m.game_data
+            };
+            describe_move(&pp, p, m, Rules::in_check(false, &after, next))
+        };
+        assert_eq!(describe("f3", "h4"), "White knight from f3 to h4");
+        assert_eq!(
+            describe("f3", "d4"),
+            "White knight from f3 to d4, takes Black bishop"
+        );
+        assert_eq!(describe("e1", "c1"), "White king castles queenside");
+        assert_eq!(describe("a1", "a8"), "White rook from a1 to a8, check");
+        assert_eq!(
+            describe("g7", "g8"),
+            "White pawn from g7 to g8, promotes to White queen, check"
+        );
+    }
+}
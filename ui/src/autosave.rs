@@ -0,0 +1,200 @@
+// Saving the local game as it's played, so closing the window, reloading the page or a crash doesn't
+// lose it. Every few seconds the game is saved as JSON with what it takes to pick it up again:
+//   {"mode": 1, "strength": 3, "flipped": false, "ruleset": {...}, "pgn": "..."}
+// mode and strength are as set_game_mode has them, ruleset is as export_ruleset has it, and the
+// PGN has the moves with their comments and variations. The desktop binary writes it to a file:
+// CHESS_AUTOSAVE, or chess-ui/autosave.json in the user's config directory, and offers to resume
+// it in the start menu. In the browser the page gets it with get_autosave, keeps it, e.g. in
+// localStorage, and gives it back with resume_game. Online games are the server's to keep, and
+// once a game is over, or before its first move, there's nothing to resume, so the save is
+// removed.
+
+use serde_json::{json, Value};
+
+use crate::menu::GameMode;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{settings::config_path, warn};
+
+// Seconds between saves.
+const INTERVAL: f64 = 5.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Saved {
+    pub mode: GameMode,
+    pub flipped: bool,
+    pub ruleset: String,
+    pub pgn: String,
+}
+
+impl Saved {
+    // None for online games, which can't be resumed on their own.
+    pub fn new(mode: GameMode, flipped: bool, ruleset: &str, pgn: &str) -> Option<Saved> {
+        (mode != GameMode::Online).then(|| Saved {
+            mode,
+            flipped,
+            ruleset: ruleset.to_string(),
+            pgn: pgn.to_string(),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let (mode, strength) = match self.mode {
+            GameMode::VsComputer { strength } => (1, strength),
+            _ => (0, 0),
+        };
+        json!({
+            "mode": mode,
+            "strength": strength,
+            "flipped": self.flipped,
+            "ruleset": serde_json::from_str::<Value>(&self.ruleset).unwrap_or(Value::Null),
+            "pgn": self.pgn,
+        })
+        .to_string()
+    }
+
+    pub fn parse(s: &str) -> Result<Saved, String> {
+        let v: Value = serde_json::from_str(s).map_err(|e| format!("invalid save: {}", e))?;
+        let mode = v["mode"]
+            .as_u64()
+            .filter(|&m| m < 2)
+            .and_then(|m| GameMode::from_js(m as u32, v["strength"].as_u64().unwrap_or(0) as u32))
+            .ok_or("invalid mode in save")?;
+        let pgn = v["pgn"].as_str().ok_or("no PGN in save")?;
+        if !v["ruleset"].is_object() {
+            return Err("no ruleset in save".to_string());
+        }
+        Ok(Saved {
+            mode,
+            flipped: v["flipped"].as_bool().unwrap_or(false),
+            ruleset: v["ruleset"].to_string(),
+            pgn: pgn.to_string(),
+        })
+    }
+}
+
+pub struct Autosave {
+    // What was last saved, None if nothing is.
+    saved: Option<String>,
+    // When, or minus infinity to save as soon as possible.
+    last_save: f64,
+    #[cfg(not(target_arch = "wasm32"))]
+    path: Option<PathBuf>,
+}
+
+impl Autosave {
+    pub fn new() -> Autosave {
+        Autosave {
+            saved: None,
+            last_save: f64::NEG_INFINITY,
+            #[cfg(not(target_arch = "wasm32"))]
+            path: path(),
+        }
+    }
+
+    // Whether it's time to save again, at time now.
+    pub fn due(&self, now: f64) -> bool {
+        now - self.last_save >= INTERVAL
+    }
+
+    // Saves game, or removes the save if there's no game to resume. Returns whether that changed
+    // what's saved.
+    pub fn save(&mut self, game: Option<&Saved>, now: f64) -> bool {
+        self.last_save = now;
+        let json = game.map(Saved::to_json);
+        if json == self.saved {
+            return false;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = &self.path {
+            write(path, json.as_deref());
+        }
+        self.saved = json;
+        true
+    }
+
+    // The JSON saved, or an empty string if there's nothing to resume.
+    pub fn saved(&self) -> &str {
+        self.saved.as_deref().unwrap_or("")
+    }
+}
+
+// The game saved by an earlier run, if there is one.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load() -> Option<Saved> {
+    read(&path()?)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path() -> Option<PathBuf> {
+    config_path("CHESS_AUTOSAVE", "autosave.json")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(path: &Path) -> Option<Saved> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Saved::parse(&s)
+            .map_err(|e| warn!("Ignoring {}: {}", path.display(), e))
+            .ok(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Couldn't read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(path: &Path, json: Option<&str>) {
+    let written = match json {
+        Some(json) => path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(path, json)),
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        },
+    };
+    if let Err(e) = written {
+        warn!("Couldn't autosave to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autosave() {
+        assert_eq!(Saved::new(GameMode::Online, false, "{}", ""), None);
+        let saved = Saved::new(
+            GameMode::VsComputer { strength: 3 },
+            true,
+            r#"{"toggles":{},"version":1}"#,
+            "1. e4 {Best by test} e5 *",
+        )
+        .unwrap();
+        let json = saved.to_json();
+        assert_eq!(Saved::parse(&json), Ok(saved.clone()));
+        assert!(Saved::parse(r#"{"mode": 2, "ruleset": {}, "pgn": ""}"#).is_err());
+        assert!(Saved::parse(r#"{"mode": 0, "pgn": ""}"#).is_err());
+
+        // Only changes are written, and a save is due every INTERVAL seconds.
+        let path = std::env::temp_dir().join("chess-autosave.json");
+        let mut autosave = Autosave::new();
+        autosave.path = Some(path.clone());
+        assert_eq!(autosave.saved(), "");
+        assert!(autosave.due(1.0));
+        assert!(autosave.save(Some(&saved), INTERVAL));
+        assert_eq!(read(&path), Some(saved.clone()));
+        assert!(!autosave.due(INTERVAL + 1.0));
+        assert!(autosave.due(2.0 * INTERVAL));
+        assert!(!autosave.save(Some(&saved), 2.0 * INTERVAL));
+        assert!(autosave.save(None, 3.0 * INTERVAL));
+        assert_eq!(autosave.saved(), "");
+        assert_eq!(read(&path), None);
+    }
+}
@@ -0,0 +1,283 @@
+// Balance tests for variants: the engine plays itself under each rule set it's given, and the
+// results say whether the rules favor a side, and how long games take, next to standard chess.
+// Run the desktop binary with --balance (see USAGE). Each game starts with a few random moves, so
+// an engine that doesn't blunder doesn't play the same game every time.
+
+use std::fs;
+
+use macroquad::{miniquad::date, rand};
+
+use chess_ui::notation::{long_algebraic, pgn};
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    prelude::*,
+    selfplay::{parse_engine, parse_number, play, read, GameRecord},
+};
+
+const USAGE: &str = "\
+Usage: chess-ui --balance [options] RULESET...
+
+Plays the engine against itself under each rule set, as exported by the ui, and reports how often
+white wins, how often black does, how often it's a draw and how long games last.
+
+Options:
+  --games N          Games per rule set (default 20)
+  --engine CONFIG    The engine playing both sides, as in --selfplay (default 2)
+  --random-plies N   Random moves each game starts with (default 4)
+  --max-plies N      Call the game a draw after this many plies (default 300)
+  --no-baseline      Don't play standard chess to compare with
+  --pgn FILE         Write the games here";
+
+// The options in USAGE that take a value.
+const FLAGS: [&str; 5] = [
+    "--games",
+    "--engine",
+    "--random-plies",
+    "--max-plies",
+    "--pgn",
+];
+
+struct Options {
+    games: usize,
+    engine: EngineConfig,
+    random_plies: usize,
+    max_plies: u16,
+    baseline: bool,
+    pgn: Option<String>,
+    // The rule sets' files and contents.
+    rulesets: Vec<(String, String)>,
+}
+
+// How a rule set's games went.
+#[derive(Debug, Default, PartialEq)]
+struct Tally {
+    white: usize,
+    draws: usize,
+    black: usize,
+    plies: usize,
+}
+
+impl Tally {
+    fn add(&mut self, game: &GameRecord) {
+        match game.result {
+            "1-0" => self.white += 1,
+            "0-1" => self.black += 1,
+            _ => self.draws += 1,
+        }
+        self.plies += game.moves.len();
+    }
+
+    fn games(&self) -> usize {
+        self.white + self.draws + self.black
+    }
+
+    // White's points per game: 0.5 is balanced.
+    fn white_score(&self) -> f64 {
+        (self.white as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // A row of the report.
+    fn row(&self, name: &str) -> String {
+        let games = self.games().max(1) as f64;
+        let percent = |n: usize| 100.0 * n as f64 / games;
+        format!(
+            "{:<24} {:>5} {:>5.0}% {:>5.0}% {:>5.0}% {:>9.1} {:>11.2}",
+            name,
+            self.games(),
+            percent(self.white),
+            percent(self.draws),
+            percent(self.black),
+            self.plies as f64 / games,
+            self.white_score(),
+        )
+    }
+}
+
+pub fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    rand::srand((date::now() * 1000.0) as u64);
+    let mut variants = Vec::new();
+    if options.baseline {
+        variants.push(Rules::defaults());
+    }
+    for (path, ruleset) in &options.rulesets {
+        let mut rules = Rules::defaults();
+        if let Err(e) = rules.import_ruleset(ruleset) {
+            eprintln!("Couldn't import {}: {}", path, e);
+            std::process::exit(1);
+        }
+        variants.push(rules);
+    }
+    let engine = Engine {
+        config: options.engine,
+    };
+    let mut out = String::new();
+    let mut report = Vec::new();
+    for rules in &variants {
+        let mut tally = Tally::default();
+        for i in 0..options.games {
+            let opening = random_opening(rules, options.random_plies);
+            let game = match play(rules, &engine, &engine, &opening, options.max_plies) {
+                Ok(g) => g,
+                Err(e) => {
+                    eprintln!("Skipping a game of {}: {}", rules.name, e);
+                    continue;
+                }
+            };
+            tally.add(&game);
+            let tags = [
+                ("Event", "Balance test".to_string()),
+                ("Site", "?".to_string()),
+                ("Date", "????.??.??".to_string()),
+                ("Round", (i + 1).to_string()),
+                ("White", "Engine".to_string()),
+                ("Black", "Engine".to_string()),
+                ("Result", game.result.to_string()),
+                ("Variant", rules.name.clone()),
+                ("Termination", game.termination.to_string()),
+            ];
+            out.push_str(&pgn(&tags, &game.moves, game.result));
+            out.push('\n');
+            eprintln!(
+                "{} game {}: {} ({}, {} plies)",
+                rules.name,
+                i + 1,
+                game.result,
+                game.termination,
+                game.moves.len()
+            );
+        }
+        report.push(tally.row(&rules.name));
+    }
+    let written = options
+        .pgn
+        .as_ref()
+        .map(|path| fs::write(path, &out).map_err(|e| format!("Couldn't write {}: {}", path, e)));
+    if let Some(Err(e)) = written {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!(
+        "{:<24} {:>5} {:>6} {:>6} {:>6} {:>9} {:>11}",
+        "Rule set", "Games", "White", "Draw", "Black", "Avg plies", "White score"
+    );
+    for row in report {
+        println!("{}", row);
+    }
+}
+
+// Up to plies random legal moves from the rule set's initial position, in long algebraic notation,
+// stopping early if the game ends.
+fn random_opening(rules: &Rules, plies: usize) -> String {
+    let mut pp = rules.initial_placements();
+    let mut gd = rules.initial_game_data();
+    let mut moves = Vec::new();
+    for _ in 0..plies {
+        let legal = rules.legal_moves(gd.player_to_move(), &pp, gd);
+        if legal.is_empty() {
+            break;
+        }
+        let (p, m) = legal[rand::gen_range(0, legal.len())];
+        moves.push(long_algebraic(p, m));
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+    }
+    moves.join(" ")
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        games: 20,
+        engine: EngineConfig::level(2),
+        random_plies: 4,
+        max_plies: 300,
+        baseline: true,
+        pgn: None,
+        rulesets: Vec::new(),
+    };
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--balance" => continue,
+            "--no-baseline" => {
+                options.baseline = false;
+                continue;
+            }
+            _ if !a.starts_with("--") => {
+                let ruleset = read(&a)?;
+                options.rulesets.push((a, ruleset));
+                continue;
+            }
+            _ if !FLAGS.contains(&a.as_str()) => return Err(format!("Unknown argument: {}", a)),
+            _ => {}
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", a))?;
+        match a.as_str() {
+            "--games" => options.games = parse_number(&a, &value)?,
+            "--engine" => options.engine = parse_engine(&value)?,
+            "--random-plies" => options.random_plies = parse_number(&a, &value)?,
+            "--max-plies" => options.max_plies = parse_number(&a, &value)?,
+            "--pgn" => options.pgn = Some(value),
+            _ => unreachable!(),
+        }
+    }
+    if options.rulesets.is_empty() && !options.baseline {
+        return Err("No rule sets to play".to_string());
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balance() {
+        let rules = Rules::defaults();
+        let opening = random_opening(&rules, 6);
+        assert_eq!(opening.split_whitespace().count(), 6);
+        let engine = Engine {
+            config: parse_engine("1,blunder=0").unwrap(),
+        };
+        // The opening is legal, so the game is played from it.
+        let game = play(&rules, &engine, &engine, &opening, 10).unwrap();
+        assert_eq!(game.moves.len(), 10);
+
+        let mut tally = Tally::default();
+        for result in ["1-0", "1-0", "1/2-1/2", "0-1"] {
+            tally.add(&GameRecord {
+                moves: vec!["e4".to_string(); 10],
+                positions: Vec::new(),
+                result,
+                termination: "checkmate",
+            });
+        }
+        assert_eq!(
+            tally,
+            Tally {
+                white: 2,
+                draws: 1,
+                black: 1,
+                plies: 40
+            }
+        );
+        assert_eq!(tally.white_score(), 0.625);
+        assert!(tally.row("Standard").ends_with("10.0        0.62"));
+
+        assert!(parse_args(["--no-baseline".to_string()].into_iter()).is_err());
+        assert!(parse_args(["--speed".to_string(), "1".to_string()].into_iter()).is_err());
+        let options = parse_args(["--games".to_string(), "4".to_string()].into_iter()).unwrap();
+        assert_eq!((options.games, options.baseline), (4, true));
+    }
+}
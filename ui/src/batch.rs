@@ -0,0 +1,227 @@
+// Analyzes many positions in one go, e.g. to build puzzle sets or check the engine against a test
+// suite: run the desktop binary with --analyze FILE (see USAGE), where FILE has a FEN per line.
+// Each position is searched to a fixed depth, or until a time limit, and its best move and score
+// are written as CSV or JSON. Positions that can't be read are reported on stderr and skipped.
+
+use std::fs;
+
+use macroquad::miniquad::date;
+use serde_json::{json, Value};
+
+use chess_ui::notation::{long_algebraic, parse_fen, san};
+
+use crate::{
+    engine::{mate_in, Analysis},
+    prelude::*,
+};
+
+const USAGE: &str = "\
+Usage: chess-ui --analyze FILE [options]
+
+FILE has a position per line in FEN. Blank lines and lines starting with # are skipped.
+
+Options:
+  --depth N        Search each position this deep, at most 8 (default 4)
+  --time SECONDS   Stop deepening after this long, keeping the deepest completed search
+  --format FORMAT  csv or json (default csv)
+  --out FILE       Write the results here instead of to stdout";
+
+// The options in USAGE, which all take a value.
+const FLAGS: [&str; 4] = ["--depth", "--time", "--format", "--out"];
+
+struct Options {
+    path: String,
+    depth: u32,
+    time: f64,
+    json: bool,
+    out: Option<String>,
+}
+
+// The analysis of one position.
+#[derive(Debug, PartialEq)]
+pub struct Analyzed {
+    pub fen: String,
+    // In SAN and long algebraic notation. None if the game is over.
+    pub best: Option<(String, String)>,
+    // From white's point of view, in centipawns.
+    pub score: i32,
+    // Moves until mate, negative if black mates. None without a forced mate.
+    pub mate: Option<i32>,
+    pub depth: u32,
+}
+
+pub fn main() {
+    let options = match parse_args(std::env::args().skip_while(|a| a != "--analyze")) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let input = match fs::read_to_string(&options.path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", options.path, e);
+            std::process::exit(1);
+        }
+    };
+    let rules = Rules::defaults();
+    let mut results = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match analyze(&rules, line, options.depth, options.time) {
+            Ok(r) => results.push(r),
+            Err(e) => eprintln!("Skipping line {}: {}", i + 1, e),
+        }
+    }
+    let out = if options.json {
+        let results: Vec<Value> = results.iter().map(to_json).collect();
+        format!("{:#}\n", Value::Array(results))
+    } else {
+        csv(&results)
+    };
+    match &options.out {
+        Some(path) => {
+            if let Err(e) = fs::write(path, out) {
+                eprintln!("Couldn't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", out),
+    }
+    eprintln!("Analyzed {} positions", results.len());
+}
+
+pub fn analyze(rules: &Rules, fen: &str, depth: u32, time: f64) -> Result<Analyzed, String> {
+    let (pp, gd) = parse_fen(fen)?;
+    let mut analysis = Analysis::new(pp, gd, 1);
+    analysis.max_depth = depth;
+    let deadline = date::now() + time;
+    // Always finish the first depth, so there's a move to report.
+    while !analysis.done() && (analysis.info.is_none() || date::now() < deadline) {
+        analysis.step(rules, deadline, None);
+    }
+    let info = analysis.info.ok_or("No analysis")?;
+    let best = info.lines.first().map(|line| {
+        let (p, m) = line.moves[0];
+        (san(rules, &pp, gd, p, m), long_algebraic(p, m))
+    });
+    Ok(Analyzed {
+        fen: fen.to_string(),
+        best,
+        score: info.score,
+        mate: mate_in(info.score),
+        depth: info.depth,
+    })
+}
+
+fn csv(results: &[Analyzed]) -> String {
+    let mut out = "fen,best,uci,score,mate,depth\n".to_string();
+    for r in results {
+        let (best, uci) = r.best.clone().unwrap_or_default();
+        let mate = r.mate.map_or(String::new(), |m| m.to_string());
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            r.fen, best, uci, r.score, mate, r.depth
+        ));
+    }
+    out
+}
+
+fn to_json(r: &Analyzed) -> Value {
+    let (best, uci) = r.best.clone().unzip();
+    json!({
+        "fen": r.fen,
+        "best": best,
+        "uci": uci,
+        "score": r.score,
+        "mate": r.mate,
+        "depth": r.depth,
+    })
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    args.next();
+    let path = args.next().ok_or("Missing FILE")?;
+    let mut options = Options {
+        path,
+        depth: 4,
+        time: f64::INFINITY,
+        json: false,
+        out: None,
+    };
+    while let Some(a) = args.next() {
+        if !FLAGS.contains(&a.as_str()) {
+            return Err(format!("Unknown argument: {}", a));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", a))?;
+        let invalid = || format!("Invalid value for {}: {}", a, value);
+        match a.as_str() {
+            "--depth" => {
+                options.depth = value
+                    .parse()
+                    .ok()
+                    .filter(|d| (1..=Analysis::MAX_DEPTH).contains(d))
+                    .ok_or_else(invalid)?
+            }
+            "--time" => {
+                options.time = value
+                    .parse()
+                    .ok()
+                    .filter(|&t: &f64| t > 0.0)
+                    .ok_or_else(invalid)?
+            }
+            "--format" => {
+                options.json = match value.as_str() {
+                    "csv" => false,
+                    "json" => true,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--out" => options.out = Some(value),
+            _ => unreachable!(),
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let rules = Rules::defaults();
+        // Back rank mate.
+        let r = analyze(
+            &rules,
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            2,
+            f64::INFINITY,
+        )
+        .unwrap();
+        assert_eq!(r.best, Some(("Ra8#".to_string(), "a1a8".to_string())));
+        assert_eq!(r.mate, Some(1));
+        assert_eq!(r.depth, 2);
+        // Checkmated.
+        let mated = analyze(&rules, "R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", 2, 1.0).unwrap();
+        assert_eq!(mated.best, None);
+        assert!(analyze(&rules, "not a fen", 2, 1.0).is_err());
+
+        let csv = csv(&[r, mated]);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("fen,best,uci,score,mate,depth"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1,Ra8#,a1a8,"));
+        let mated_line = lines.next().unwrap();
+        assert!(mated_line.starts_with("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1,,,"));
+        assert!(mated_line.ends_with(",0"));
+    }
+}
@@ -0,0 +1,62 @@
+// The check before playing a move, when Settings::blunder_check is on: a shallow search of the
+// player's move against the best one in the position, so a move that drops material or lets the
+// other player mate can be confirmed first (see Game::submit_move in main.rs). Rated online games
+// go without it.
+
+use crate::{
+    engine::{mate_in, Engine, EngineConfig},
+    prelude::*,
+};
+
+// How deep the engine looks. It's searched at once, before the move is played, so it has to be
+// quick in the browser.
+const DEPTH: u32 = 2;
+// How many centipawns worse than the best move a move has to be to lose significant material:
+// about a minor piece, less a pawn for the search's rough edges.
+const MATERIAL: i32 = 200;
+
+// Whether playing m loses significant material or allows mate, when there's a better move that
+// doesn't.
+pub fn is_blunder(rules: &Rules, pp: &PiecePlacements, gd: GameData, m: (Piece, Move)) -> bool {
+    let engine = Engine {
+        config: EngineConfig {
+            depth: DEPTH,
+            time_limit: f64::INFINITY,
+            blunder_chance: 0.0,
+            ..EngineConfig::level(3)
+        },
+    };
+    let scored = engine.score_moves(rules, pp, gd);
+    let (Some(&(best, _)), Some(&(score, _))) =
+        (scored.first(), scored.iter().find(|&&(_, s)| s == m))
+    else {
+        return false;
+    };
+    let mated = |s| mate_in(s).is_some_and(|n| n < 0);
+    (mated(score) && !mated(best)) || best - score >= MATERIAL
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::{parse_fen, parse_san};
+
+    use super::*;
+
+    #[test]
+    fn test_blunder() {
+        let rules = Rules::defaults();
+        let blunder = |fen, san| {
+            let (pp, gd) = parse_fen(fen).unwrap();
+            let m = parse_san(&rules, &pp, gd, san).unwrap();
+            is_blunder(&rules, &pp, gd, m)
+        };
+        // The pawn takes a queen on d5.
+        let fen = "4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1";
+        assert!(blunder(fen, "Qd5"));
+        assert!(!blunder(fen, "Qd2"));
+        // Leaving the back rank lets the rook mate on e1.
+        let fen = "4r1k1/8/8/8/8/8/5PPP/3R2K1 w - - 0 1";
+        assert!(blunder(fen, "Rd2"));
+        assert!(!blunder(fen, "h3"));
+    }
+}
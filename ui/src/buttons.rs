@@ -0,0 +1,190 @@
+// The desktop binary's buttons, in a bar along the bottom of the window, for what the page around
+// the browser version has buttons for: resigning, offering or accepting a draw, flipping the board,
+// the player's settings and exporting the game. The settings button opens a panel above it with a
+// row for each setting, which a click changes. Clicks on the bar or the open panel are the
+// buttons', not the board's.
+
+use macroquad::prelude::*;
+
+use crate::i18n::tr;
+
+pub const BAR_HEIGHT: f32 = 32.0;
+const ROW_HEIGHT: f32 = 28.0;
+const PANEL_WIDTH: f32 = 320.0;
+const FONT_SIZE: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Resign,
+    // Offers a draw, or accepts the other player's offer.
+    Draw,
+    Flip,
+    Settings,
+    // Saves a picture of the board and the game's PGN.
+    Export,
+}
+
+const COMMANDS: [Command; 5] = [
+    Command::Resign,
+    Command::Draw,
+    Command::Flip,
+    Command::Settings,
+    Command::Export,
+];
+
+// The rows of the settings panel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    Coordinates,
+    Click,
+    AutoQueen,
+    Ponder,
+    BlunderCheck,
+    // Switches to the next theme.
+    Theme,
+}
+
+const SETTINGS: [Setting; 6] = [
+    Setting::Coordinates,
+    Setting::Click,
+    Setting::AutoQueen,
+    Setting::Ponder,
+    Setting::BlunderCheck,
+    Setting::Theme,
+];
+
+// What was clicked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clicked {
+    Command(Command),
+    Setting(Setting),
+}
+
+// Where each button is on a screen of this size: side by side along the bottom, sharing its width.
+fn button_rects(screen: Vec2) -> impl Iterator<Item = (Command, Rect)> {
+    let width = screen.x / COMMANDS.len() as f32;
+    let y = screen.y - BAR_HEIGHT;
+    COMMANDS
+        .into_iter()
+        .enumerate()
+        .map(move |(i, c)| (c, Rect::new(i as f32 * width, y, width, BAR_HEIGHT)))
+}
+
+// Where each row of the settings panel is: above the settings button, with its right edge on the
+// button's unless that would push it off the screen.
+fn setting_rects(screen: Vec2) -> impl Iterator<Item = (Setting, Rect)> {
+    let (_, button) = button_rects(screen)
+        .find(|&(c, _)| c == Command::Settings)
+        .unwrap();
+    let x = (button.right() - PANEL_WIDTH).max(0.0);
+    let top = button.y - SETTINGS.len() as f32 * ROW_HEIGHT;
+    SETTINGS.into_iter().enumerate().map(move |(i, s)| {
+        let y = top + i as f32 * ROW_HEIGHT;
+        (s, Rect::new(x, y, PANEL_WIDTH, ROW_HEIGHT))
+    })
+}
+
+// What's at (x, y) on the screen, if it's the bar or, when it's open, the settings panel.
+pub fn clicked(x: f32, y: f32, screen: Vec2, settings_open: bool) -> Option<Clicked> {
+    let at = vec2(x, y);
+    if let Some((c, _)) = button_rects(screen).find(|(_, r)| r.contains(at)) {
+        return Some(Clicked::Command(c));
+    }
+    if !settings_open {
+        return None;
+    }
+    setting_rects(screen)
+        .find(|(_, r)| r.contains(at))
+        .map(|(s, _)| Clicked::Setting(s))
+}
+
+// Draws the bar, and the settings panel if it's open, with a box on each setting that's on. The
+// draw button offers a draw unless the other player has.
+pub fn draw(settings_open: bool, draw_offered: bool, on: impl Fn(Setting) -> Option<bool>) {
+    let screen = vec2(screen_width(), screen_height());
+    let background = Color::new(0.0, 0.0, 0.0, 0.6);
+    let hovered = Color::new(1.0, 1.0, 1.0, 0.2);
+    let (mx, my) = mouse_position();
+    for (c, r) in button_rects(screen) {
+        draw_rectangle(r.x, r.y, r.w, r.h, background);
+        if r.contains(vec2(mx, my)) || (c == Command::Settings && settings_open) {
+            draw_rectangle(r.x, r.y, r.w, r.h, hovered);
+        }
+        draw_rectangle_lines(r.x, r.y, r.w, r.h, 1.0, GRAY);
+        let label = tr(match c {
+            Command::Resign => "button.resign",
+            Command::Draw if draw_offered => "button.accept_draw",
+            Command::Draw => "button.offer_draw",
+            Command::Flip => "button.flip",
+            Command::Settings => "button.settings",
+            Command::Export => "button.export",
+        });
+        let width = measure_text(&label, None, FONT_SIZE as u16, 1.0).width;
+        let x = r.x + ((r.w - width) / 2.0).max(4.0);
+        draw_text(
+            &label,
+            x,
+            r.y + (r.h + FONT_SIZE) / 2.0 - 4.0,
+            FONT_SIZE,
+            WHITE,
+        );
+    }
+    if !settings_open {
+        return;
+    }
+    for (s, r) in setting_rects(screen) {
+        draw_rectangle(r.x, r.y, r.w, r.h, background);
+        if r.contains(vec2(mx, my)) {
+            draw_rectangle(r.x, r.y, r.w, r.h, hovered);
+        }
+        let size = ROW_HEIGHT - 12.0;
+        let (bx, by) = (r.x + 6.0, r.y + 6.0);
+        match on(s) {
+            Some(true) => draw_rectangle(bx, by, size, size, WHITE),
+            Some(false) => draw_rectangle_lines(bx, by, size, size, 2.0, WHITE),
+            None => {}
+        }
+        let label = tr(match s {
+            Setting::Coordinates => "settings.coordinates",
+            Setting::Click => "settings.click",
+            Setting::AutoQueen => "settings.auto_queen",
+            Setting::Ponder => "settings.ponder",
+            Setting::BlunderCheck => "settings.blunder_check",
+            Setting::Theme => "settings.theme",
+        });
+        draw_text(&label, bx + size + 8.0, r.y + r.h - 8.0, FONT_SIZE, WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buttons() {
+        let screen = vec2(800.0, 800.0);
+        assert_eq!(
+            clicked(10.0, 790.0, screen, false),
+            Some(Clicked::Command(Command::Resign))
+        );
+        assert_eq!(
+            clicked(799.0, 770.0, screen, false),
+            Some(Clicked::Command(Command::Export))
+        );
+        // Above the bar is the board's.
+        assert_eq!(clicked(10.0, 760.0, screen, false), None);
+        // The settings panel sits above the settings button, ending where it does.
+        let rows: Vec<_> = setting_rects(screen).collect();
+        assert_eq!(rows.len(), SETTINGS.len());
+        assert_eq!(rows[0].1.right(), 640.0);
+        assert_eq!(rows[5].1.bottom(), 800.0 - BAR_HEIGHT);
+        let (x, y) = (rows[1].1.x + 1.0, rows[1].1.y + 1.0);
+        assert_eq!(clicked(x, y, screen, false), None);
+        assert_eq!(
+            clicked(x, y, screen, true),
+            Some(Clicked::Setting(Setting::Click))
+        );
+        // On a narrow screen, it stays on it.
+        assert!(setting_rects(vec2(300.0, 600.0)).all(|(_, r)| r.x == 0.0));
+    }
+}
@@ -0,0 +1,398 @@
+// Plays in the terminal, without opening a window, e.g. over SSH or from a script: run the desktop
+// binary with --cli (see USAGE). The board is printed after every move, and each line read from
+// stdin is a move, in SAN (Nf3) or long algebraic notation (g1f3), or one of the commands in
+// HELP. Errors go to stderr, so a script can read the boards and answers from stdout.
+
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use macroquad::{miniquad::date, rand};
+
+use chess_ui::{
+    core::GameCore,
+    notation::{fen, parse_long_algebraic, parse_san, pgn, san},
+};
+
+use crate::{
+    analysis::format_score,
+    engine::{Analysis, Engine, MAX_STRENGTH},
+    i18n::game_over_text,
+    prelude::*,
+};
+
+const USAGE: &str = "\
+Usage: chess-ui --cli [options]
+
+Options:
+  --ascii           Letters for the pieces instead of chess symbols
+  --ruleset FILE    Rule set to play, as exported by the ui (default: standard chess)";
+
+const HELP: &str = "\
+Moves are in SAN (Nf3, O-O, e8=Q) or long algebraic notation (g1f3, e7e8q).
+Commands:
+  computer white|black [STRENGTH]  The computer plays that side, at strength 1 to 5 (default 3)
+  computer off                     Two players again
+  go                               The computer moves for the side to move
+  analyze [DEPTH]                  The best three lines (default depth 4)
+  moves                            The legal moves
+  undo                             Takes back a move, or the computer's and yours
+  flip                             Turns the board around
+  new                              Starts over
+  fen                              The position in FEN
+  pgn                              The game so far in PGN
+  quit";
+
+struct Session<'a> {
+    core: GameCore<'a>,
+    // The color the computer plays, and how.
+    computer: Option<(usize, Engine)>,
+    flipped: bool,
+    unicode: bool,
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip_while(|a| a != "--cli").skip(1);
+    let mut rules = Rules::defaults();
+    let mut unicode = true;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--ascii" => unicode = false,
+            "--ruleset" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                let imported = fs::read_to_string(&path)
+                    .map_err(|e| format!("Couldn't read {}: {}", path, e))
+                    .and_then(|r| rules.import_ruleset(&r));
+                if let Err(e) = imported {
+                    eprintln!("Couldn't import ruleset: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            _ => usage(),
+        }
+    }
+    rand::srand((date::now() * 1000.0) as u64);
+    let mut session = Session::new(rules, unicode);
+    let stdin = io::stdin();
+    if let Err(e) = session.run(stdin.lock(), &mut io::stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+impl<'a> Session<'a> {
+    fn new(rules: Rules<'a>, unicode: bool) -> Self {
+        Self {
+            core: GameCore::new(rules),
+            computer: None,
+            flipped: false,
+            unicode,
+        }
+    }
+
+    // Reads lines until quit or the end of input.
+    fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.board())?;
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "quit" {
+                break;
+            }
+            match self.command(line) {
+                Ok(text) => writeln!(out, "{}", text)?,
+                Err(e) => eprintln!("{}", e),
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    // What to print after the line.
+    fn command(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        match (command, args.as_slice()) {
+            ("help", []) => Ok(HELP.to_string()),
+            ("new", []) => {
+                self.core.restart();
+                self.after_move()
+            }
+            ("computer", ["off"]) => {
+                self.computer = None;
+                Ok("Two players".to_string())
+            }
+            ("computer", [color, strength @ ..]) if strength.len() <= 1 => {
+                let color = match *color {
+                    "white" => 0,
+                    "black" => 1,
+                    _ => return Err(format!("Unknown color: {}", color)),
+                };
+                let strength = match strength.first() {
+                    Some(s) => s
+                        .parse()
+                        .ok()
+                        .filter(|s| (1..=MAX_STRENGTH).contains(s))
+                        .ok_or_else(|| format!("Invalid strength: {}", s))?,
+                    None => 3,
+                };
+                self.computer = Some((color, Engine::new(strength)));
+                self.after_move()
+            }
+            ("go", []) => {
+                let engine = self
+                    .computer
+                    .as_ref()
+                    .map_or(Engine::new(3), |(_, e)| Engine { config: e.config });
+                self.engine_move(&engine)?;
+                self.after_move()
+            }
+            ("analyze", _) if args.len() <= 1 => {
+                let depth = match args.first() {
+                    Some(d) => d
+                        .parse()
+                        .ok()
+                        .filter(|d| (1..=Analysis::MAX_DEPTH).contains(d))
+                        .ok_or_else(|| format!("Invalid depth: {}", d))?,
+                    None => 4,
+                };
+                Ok(self.analyze(depth))
+            }
+            ("moves", []) => {
+                let (pp, gd) = self.position();
+                let moves = self.core.rules.legal_moves(gd.player_to_move(), &pp, gd);
+                let sans: Vec<String> = moves
+                    .into_iter()
+                    .map(|(p, m)| san(&self.core.rules, &pp, gd, p, m))
+                    .collect();
+                Ok(sans.join(" "))
+            }
+            ("undo", []) => {
+                if self.core.moves.is_empty() {
+                    return Err("No move to take back".to_string());
+                }
+                self.undo();
+                // Back to a position the player moves in.
+                let computer = self.computer.as_ref().map(|&(c, _)| c);
+                if computer == Some(self.position().1.player_to_move())
+                    && !self.core.moves.is_empty()
+                {
+                    self.undo();
+                }
+                Ok(self.board())
+            }
+            ("flip", []) => {
+                self.flipped = !self.flipped;
+                Ok(self.board())
+            }
+            ("fen", []) => {
+                let (pp, gd) = self.position();
+                Ok(fen(&pp, gd))
+            }
+            ("pgn", []) => {
+                let tags = [("Variant", self.core.rules.name.clone())];
+                Ok(pgn(&tags, &self.core.sans(), self.result().unwrap_or("*"))
+                    .trim_end()
+                    .to_string())
+            }
+            _ => {
+                let (pp, gd) = self.position();
+                let (p, m) = parse_san(&self.core.rules, &pp, gd, line)
+                    .or_else(|| parse_long_algebraic(&self.core.rules, &pp, gd, line))
+                    .ok_or_else(|| format!("Not a legal move or command: {} (try help)", line))?;
+                self.core.play(p, m);
+                self.after_move()
+            }
+        }
+    }
+
+    fn position(&self) -> (PiecePlacements, GameData) {
+        self.core.position()
+    }
+
+    fn undo(&mut self) {
+        self.core.history.pop();
+        self.core.moves.pop();
+    }
+
+    // Returns the engine's move in SAN.
+    fn engine_move(&mut self, engine: &Engine) -> Result<String, String> {
+        let (pp, gd) = self.position();
+        let (p, m) = engine
+            .best_move(&self.core.rules, &pp, gd)
+            .ok_or("No moves, the game is over")?;
+        self.core.play(p, m);
+        Ok(san(&self.core.rules, &pp, gd, p, m))
+    }
+
+    // Lets the computer move as long as it's its turn, then shows the board, and the result if the
+    // game is over.
+    fn after_move(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        while self.result().is_none() {
+            let (_, gd) = self.position();
+            let engine = match &self.computer {
+                Some((color, e)) if *color == gd.player_to_move() => Engine { config: e.config },
+                _ => break,
+            };
+            // The player didn't type it, so say what it was.
+            let m = self.engine_move(&engine)?;
+            text.push_str(&format!("Computer plays {}\n", m));
+        }
+        text.push_str(&self.board());
+        if let Some(result) = self.result() {
+            text.push('\n');
+            text.push_str(&game_over_text(result, "normal"));
+        }
+        Ok(text)
+    }
+
+    // The result, if the player to move has no moves.
+    fn result(&self) -> Option<&'static str> {
+        let (pp, gd) = self.position();
+        let color = gd.player_to_move();
+        if !self.core.rules.legal_moves(color, &pp, gd).is_empty() {
+            return None;
+        }
+        Some(
+            if !self.core.rules.lost_without_moves(color == 0, &pp, gd) {
+                "1/2-1/2"
+            } else if color == 0 {
+                "0-1"
+            } else {
+                "1-0"
+            },
+        )
+    }
+
+    fn analyze(&self, depth: u32) -> String {
+        let (pp, gd) = self.position();
+        let mut analysis = Analysis::new(pp, gd, 3);
+        analysis.max_depth = depth;
+        while !analysis.done() {
+            analysis.step(&self.core.rules, f64::INFINITY, None);
+        }
+        let Some(info) = analysis.info else {
+            return String::new();
+        };
+        let mut text = format!("Depth {}", info.depth);
+        for line in &info.lines {
+            let (mut pp, mut gd) = (pp, gd);
+            let mut sans = Vec::new();
+            for &(p, m) in &line.moves {
+                sans.push(san(&self.core.rules, &pp, gd, p, m));
+                Rules::make_move(p, m, &mut pp);
+                gd = GameData {
+                    ply: m.game_data.ply + 1,
+                    ..m.game_data
+                };
+            }
+            text.push_str(&format!(
+                "\n{} {}",
+                format_score(line.score),
+                sans.join(" ")
+            ));
+        }
+        text
+    }
+
+    // The current position, white at the bottom unless flipped, with the ranks and files.
+    fn board(&self) -> String {
+        let (pp, gd) = self.position();
+        // TODO: get board size from rules
+        let mut rows: Vec<usize> = (1..=8).rev().collect();
+        let mut cols: Vec<usize> = (1..=8).collect();
+        if self.flipped {
+            rows.reverse();
+            cols.reverse();
+        }
+        let mut text = String::new();
+        for &r in &rows {
+            text.push_str(&format!("{} ", r));
+            for &c in &cols {
+                text.push(' ');
+                text.push(self.symbol(pp.get(r, c)));
+            }
+            text.push('\n');
+        }
+        text.push_str("  ");
+        for &c in &cols {
+            text.push(' ');
+            text.push((b'a' + c as u8 - 1) as char);
+        }
+        let side = if gd.player_to_move() == 0 {
+            "White"
+        } else {
+            "Black"
+        };
+        text.push_str(&format!("\n{} to move", side));
+        text
+    }
+
+    fn symbol(&self, name: u8) -> char {
+        const SYMBOLS: [(u8, char); 12] = [
+            (b'K', '♔'),
+            (b'Q', '♕'),
+            (b'R', '♖'),
+            (b'B', '♗'),
+            (b'N', '♘'),
+            (b'P', '♙'),
+            (b'k', '♚'),
+            (b'q', '♛'),
+            (b'r', '♜'),
+            (b'b', '♝'),
+            (b'n', '♞'),
+            (b'p', '♟'),
+        ];
+        match name {
+            0 => '.',
+            _ if self.unicode => SYMBOLS
+                .iter()
+                .find(|&&(n, _)| n == name)
+                .map_or(name as char, |&(_, s)| s),
+            _ => name as char,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new(Rules::defaults(), false);
+        let script = "e4\ne7e5\nNf3\nbogus\nundo\nfen\nquit\nd4\n";
+        let mut out = Vec::new();
+        session.run(script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("8  r n b q k b n r\n"));
+        assert!(out.contains("1  R N B Q K B N R\n   a b c d e f g h\nWhite to move"));
+        assert!(out.contains("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"));
+        // Nothing after quit is read.
+        assert_eq!(session.core.sans(), ["e4", "e5"]);
+
+        // The computer answers straight away, and undo takes back its move too.
+        let text = session.command("computer black 1").unwrap();
+        assert!(text.ends_with("White to move"));
+        let text = session.command("Nf3").unwrap();
+        assert!(text.starts_with("Computer plays "));
+        assert_eq!(session.core.moves.len(), 4);
+        session.command("undo").unwrap();
+        assert_eq!(session.core.sans(), ["e4", "e5"]);
+        assert!(session
+            .command("analyze 2")
+            .unwrap()
+            .starts_with("Depth 2\n"));
+    }
+}
@@ -0,0 +1,268 @@
+// What the player does, whatever they do it with, so handle_input doesn't read the mouse and keys
+// itself. The mouse picks pieces up and puts them down, and so do touches, which macroquad passes
+// on as the mouse. The keys in the player's bindings (see Settings) do the rest, including moving a
+// cursor over the board to play without a mouse. Bindings are a JSON object from actions to key
+// names, as macroquad names them:
+//   {"confirm": "Enter", "cancel": "Backspace", "flip": "F", "undo": "T", "menu": "Escape",
+//    "up": "K", "down": "J", "left": "H", "right": "L"}
+// Each action needs a key of its own. A key the game uses for something else too, like Space for
+// playing through the game, does both.
+
+use macroquad::prelude::*;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    // The main mouse button, or a finger, went down on a square.
+    Select((usize, usize)),
+    // It came up on a square.
+    Release((usize, usize)),
+    // Picks up, or puts down, a piece on the cursor's square.
+    Confirm,
+    // Puts back the piece being moved: the right mouse button, or the key.
+    Cancel,
+    Flip,
+    // Takes back the player's last move.
+    Undo,
+    Menu,
+    // Moves the cursor a square across and down the board, as it's shown.
+    Cursor(i32, i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Confirm,
+    Cancel,
+    Flip,
+    Undo,
+    Menu,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+const ACTIONS: [(Action, &str); 9] = [
+    (Action::Confirm, "confirm"),
+    (Action::Cancel, "cancel"),
+    (Action::Flip, "flip"),
+    (Action::Undo, "undo"),
+    (Action::Menu, "menu"),
+    (Action::Up, "up"),
+    (Action::Down, "down"),
+    (Action::Left, "left"),
+    (Action::Right, "right"),
+];
+
+// The keys that can be bound. Modifiers can't, since the game uses them with other keys.
+const KEYS: [KeyCode; 82] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpEnter,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Space,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Escape,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Semicolon,
+    KeyCode::Apostrophe,
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::GraveAccent,
+];
+
+fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+fn named_key(name: &str) -> Option<KeyCode> {
+    KEYS.iter().copied().find(|&k| key_name(k) == name)
+}
+
+// The key with the letter, e.g. Q for b'q', to pick a piece by its letter.
+pub fn letter_key(letter: u8) -> Option<KeyCode> {
+    named_key(&(letter as char).to_ascii_uppercase().to_string())
+}
+
+// The key of each action, in the order of ACTIONS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bindings([KeyCode; ACTIONS.len()]);
+
+impl Bindings {
+    // The arrow keys go through the game's history, so the cursor moves like in vi.
+    pub const DEFAULT: Bindings = Bindings([
+        KeyCode::Enter,
+        KeyCode::Backspace,
+        KeyCode::F,
+        KeyCode::T,
+        KeyCode::Escape,
+        KeyCode::K,
+        KeyCode::J,
+        KeyCode::H,
+        KeyCode::L,
+    ]);
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.0[action as usize]
+    }
+
+    pub fn to_json(self) -> Value {
+        let bindings: Map<String, Value> = ACTIONS
+            .iter()
+            .map(|&(a, name)| (name.to_string(), key_name(self.key(a)).into()))
+            .collect();
+        bindings.into()
+    }
+
+    // Rebinds the actions in the JSON object v, leaving the others as they are.
+    pub fn update(&mut self, v: &Value) -> Result<(), String> {
+        let fields = v.as_object().ok_or("Bindings must be a JSON object")?;
+        let mut new = *self;
+        for (k, v) in fields {
+            let &(action, _) = ACTIONS
+                .iter()
+                .find(|(_, name)| name == k)
+                .ok_or_else(|| format!("Unknown action: {}", k))?;
+            new.0[action as usize] = v
+                .as_str()
+                .and_then(named_key)
+                .ok_or_else(|| format!("Invalid key for {}: {}", k, v))?;
+        }
+        for (i, key) in new.0.iter().enumerate() {
+            if new.0[..i].contains(key) {
+                return Err(format!("{} is bound twice", key_name(*key)));
+            }
+        }
+        *self = new;
+        Ok(())
+    }
+}
+
+// What the player did this frame. square is the square under the mouse.
+pub fn events(bindings: &Bindings, square: (usize, usize)) -> Vec<InputEvent> {
+    let mut events = Vec::new();
+    if is_mouse_button_pressed(MouseButton::Left) {
+        events.push(InputEvent::Select(square));
+    }
+    if is_mouse_button_released(MouseButton::Left) {
+        events.push(InputEvent::Release(square));
+    }
+    if is_mouse_button_pressed(MouseButton::Right) {
+        events.push(InputEvent::Cancel);
+    }
+    for &(action, _) in &ACTIONS {
+        if !is_key_pressed(bindings.key(action)) {
+            continue;
+        }
+        events.push(match action {
+            Action::Confirm => InputEvent::Confirm,
+            Action::Cancel => InputEvent::Cancel,
+            Action::Flip => InputEvent::Flip,
+            Action::Undo => InputEvent::Undo,
+            Action::Menu => InputEvent::Menu,
+            Action::Up => InputEvent::Cursor(0, -1),
+            Action::Down => InputEvent::Cursor(0, 1),
+            Action::Left => InputEvent::Cursor(-1, 0),
+            Action::Right => InputEvent::Cursor(1, 0),
+        });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_bindings() {
+        let mut b = Bindings::DEFAULT;
+        b.update(&json!({ "confirm": "Space", "left": "Kp4" }))
+            .unwrap();
+        assert_eq!(b.key(Action::Confirm), KeyCode::Space);
+        assert_eq!(b.key(Action::Left), KeyCode::Kp4);
+        assert_eq!(b.key(Action::Undo), KeyCode::T);
+        assert_eq!(b.to_json()["confirm"], "Space");
+
+        let before = b;
+        assert!(b.update(&json!({ "flip": "LeftShift" })).is_err());
+        assert!(b.update(&json!({ "jump": "F" })).is_err());
+        // Undo would be on F as well as flip.
+        assert!(b.update(&json!({ "undo": "F" })).is_err());
+        assert_eq!(b, before);
+
+        let mut loaded = Bindings::DEFAULT;
+        loaded.update(&b.to_json()).unwrap();
+        assert_eq!(loaded, b);
+    }
+}
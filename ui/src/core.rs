@@ -0,0 +1,620 @@
+// The game without its window: the rules, the positions and moves of the game, and what the player
+// is doing with the pieces, which the pointer's presses and releases turn into moves. The game
+// (main.rs) draws it and feeds it the mouse, and the terminal mode (cli.rs) and the replay of
+// recorded inputs (reproduce.rs) play on it too. Nothing here needs macroquad, so it can be tested
+// on its own.
+
+use crate::{
+    notation::{long_algebraic, parse_long_algebraic, san},
+    prelude::*,
+};
+
+// How the player moves pieces with the mouse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputMode {
+    // Drag the piece to its square.
+    Drag,
+    // Click the piece, then its square.
+    Click,
+}
+
+// What the player is doing with a piece.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pointer {
+    Up,
+    Dragging((usize, usize)),
+    // In click mode, the piece clicked waits on its square for a click on where it goes.
+    Selected((usize, usize)),
+    // Partway through a move in several legs, like a chain of jumps in draughts, the piece waits
+    // on its last leg for a click on the next one.
+    Chaining {
+        source: (usize, usize),
+        // The squares the piece has stopped on so far.
+        legs: Vec<(usize, usize)>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerEvent {
+    // The main button, on a square.
+    Press((usize, usize)),
+    Release((usize, usize)),
+    // E.g. a right click, which puts back the piece being moved by clicks.
+    Cancel,
+}
+
+// Why the player can't pick up a piece.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Refusal {
+    // It's the other player's, or not one the rules let them move now.
+    NotYourPiece,
+    // Or the game's over.
+    NotYourTurn,
+}
+
+// Where moves are made, and who makes them.
+#[derive(Clone, Copy, Debug)]
+pub struct Turn {
+    pub pp: PiecePlacements,
+    pub gd: GameData,
+    pub player: usize,
+    // Games can end with moves left, e.g. when abandoned.
+    pub over: bool,
+}
+
+// The legal moves of a player in a position.
+pub struct LegalMoves {
+    pub player: usize,
+    pub pp: PiecePlacements,
+    pub gd: GameData,
+    pub moves: Vec<(Piece, Move)>,
+}
+
+impl LegalMoves {
+    pub fn is_for(&self, player: usize, pp: &PiecePlacements, gd: GameData) -> bool {
+        (self.player, &self.pp, self.gd) == (player, pp, gd)
+    }
+}
+
+// The player's moves after each of the other player's replies in a position, worked out one reply
+// at a time while the other player thinks.
+pub struct Lookahead {
+    player: usize,
+    pp: PiecePlacements,
+    gd: GameData,
+    // The replies not looked at yet.
+    replies: Vec<(Piece, Move)>,
+    // The player's moves after the replies looked at.
+    found: Vec<LegalMoves>,
+}
+
+pub struct GameCore<'a> {
+    pub rules: Rules<'a>,
+    // Every position of the game so far, oldest first. The last one is the current position.
+    pub history: Vec<(PiecePlacements, GameData)>,
+    // The moves of the game in long algebraic notation. history[i + 1] is history[i] after moves[i].
+    pub moves: Vec<String>,
+    pub pointer: Pointer,
+    // The moves of the player at the board in the current position, worked out ahead of time so
+    // picking up a piece doesn't have to.
+    pub legal: Option<LegalMoves>,
+    // The player's moves in the positions the other player's move can lead to, so whichever it
+    // is, legal doesn't have to be worked out all at once when it's played.
+    pub ahead: Option<Lookahead>,
+    // When moving by clicks, clicking another of the player's pieces picks it up, rather than only
+    // putting back the one picked up.
+    pub change_piece: bool,
+}
+
+impl<'a> GameCore<'a> {
+    pub fn new(rules: Rules<'a>) -> GameCore<'a> {
+        let start = (rules.initial_placements(), rules.initial_game_data());
+        GameCore {
+            rules,
+            history: vec![start],
+            moves: Vec::new(),
+            pointer: Pointer::Up,
+            legal: None,
+            ahead: None,
+            change_piece: false,
+        }
+    }
+
+    // Back to the rules' starting position.
+    pub fn restart(&mut self) {
+        let start = (
+            self.rules.initial_placements(),
+            self.rules.initial_game_data(),
+        );
+        self.history = vec![start];
+        self.moves.clear();
+        self.pointer = Pointer::Up;
+    }
+
+    pub fn position(&self) -> (PiecePlacements, GameData) {
+        *self.history.last().unwrap()
+    }
+
+    pub fn board(&self) -> PiecePlacements {
+        self.position().0
+    }
+
+    pub fn game_data(&self) -> GameData {
+        self.position().1
+    }
+
+    // The ply of the game's first position: 1, unless it started from a FEN further on.
+    pub fn first_ply(&self) -> u16 {
+        self.history[0].1.ply
+    }
+
+    // The player's turn in the current position.
+    pub fn turn(&self, player: usize, over: bool) -> Turn {
+        let (pp, gd) = self.position();
+        Turn {
+            pp,
+            gd,
+            player,
+            over,
+        }
+    }
+
+    // Makes the move on the current position.
+    pub fn play(&mut self, piece: Piece, m: Move) {
+        self.history.push(after(self.board(), piece, m));
+        self.moves.push(long_algebraic(piece, m));
+    }
+
+    // The moves in SAN, for people to read.
+    pub fn sans(&self) -> Vec<String> {
+        self.moves
+            .iter()
+            .zip(&self.history)
+            .map(
+                |(uci, (pp, gd))| match parse_long_algebraic(&self.rules, pp, *gd, uci) {
+                    Some((p, m)) => san(&self.rules, pp, *gd, p, m),
+                    None => uci.clone(),
+                },
+            )
+            .collect()
+    }
+
+    // Back to the position at ply, if the game has got past it and didn't start after it.
+    pub fn take_back(&mut self, ply: u16) -> bool {
+        let first = self.first_ply();
+        if ply < first || ply >= self.game_data().ply {
+            return false;
+        }
+        let moves = (ply - first) as usize;
+        self.history.truncate(moves + 1);
+        self.moves.truncate(moves);
+        self.pointer = Pointer::Up;
+        true
+    }
+
+    // Works out the player's moves in the current position, unless they're already worked out,
+    // here or ahead of time.
+    pub fn prepare_legal(&mut self, player: usize) {
+        let (pp, gd) = self.position();
+        if matches!(&self.legal, Some(l) if l.is_for(player, &pp, gd)) {
+            return;
+        }
+        let found = self.ahead.as_mut().and_then(|a| {
+            let i = a.found.iter().position(|l| l.is_for(player, &pp, gd))?;
+            Some(a.found.swap_remove(i))
+        });
+        self.legal = Some(found.unwrap_or_else(|| LegalMoves {
+            player,
+            pp,
+            gd,
+            moves: self.rules.legal_moves(player, &pp, gd),
+        }));
+    }
+
+    // On the other player's turn, works out the player's moves after one more of their replies.
+    // Returns whether there are replies left.
+    pub fn look_ahead(&mut self, player: usize) -> bool {
+        let (pp, gd) = self.position();
+        let other = gd.player_to_move();
+        if other == player {
+            return false;
+        }
+        if !matches!(&self.ahead, Some(a) if (a.player, &a.pp, a.gd) == (player, &pp, gd)) {
+            self.ahead = Some(Lookahead {
+                player,
+                pp,
+                gd,
+                replies: self.rules.legal_moves(other, &pp, gd),
+                found: Vec::new(),
+            });
+        }
+        let ahead = self.ahead.as_mut().unwrap();
+        let Some((piece, m)) = ahead.replies.pop() else {
+            return false;
+        };
+        let (pp, gd) = after(pp, piece, m);
+        // Some turn orders give the other player another move.
+        if gd.player_to_move() == player {
+            ahead.found.push(LegalMoves {
+                player,
+                pp,
+                gd,
+                moves: self.rules.legal_moves(player, &pp, gd),
+            });
+        }
+        !ahead.replies.is_empty()
+    }
+
+    // Why the player can't pick up the piece on square, if they can't. Empty squares are clicked to
+    // place pieces, so they aren't refused.
+    pub fn refusal(&self, turn: &Turn, (r, c): (usize, usize)) -> Option<Refusal> {
+        let piece = Piece {
+            row: r as u8,
+            col: c as u8,
+            name: turn.pp.get(r, c),
+        };
+        if piece.name == 0 || (self.rules.is_turn(turn.player, piece, turn.gd) && !turn.over) {
+            return None;
+        }
+        if turn.over || turn.gd.player_to_move() != turn.player {
+            Some(Refusal::NotYourTurn)
+        } else {
+            Some(Refusal::NotYourPiece)
+        }
+    }
+
+    // The moves of piece, if it's the player's to move. They come from the legal moves worked out
+    // ahead of time if those are for the turn.
+    pub fn allowed_moves(&self, turn: &Turn, piece: Piece) -> Vec<Move> {
+        if piece.name == 0 || !self.rules.is_turn(turn.player, piece, turn.gd) || turn.over {
+            return Vec::new();
+        }
+        let moves: Vec<Move> = match &self.legal {
+            Some(l) if l.is_for(turn.player, &turn.pp, turn.gd) => l
+                .moves
+                .iter()
+                .filter(|(p, _)| *p == piece)
+                .map(|&(_, m)| m)
+                .collect(),
+            _ => self
+                .rules
+                .allowed_moves(piece, &turn.pp, turn.gd)
+                .into_iter()
+                .collect(),
+        };
+        // Passes aren't made with the pieces, see pass_move.
+        moves
+            .into_iter()
+            .filter(|m| m.typ != MoveType::Pass)
+            .collect()
+    }
+
+    // The legs of the moves of the piece on source that start with legs.
+    pub fn moves_starting_with(
+        &self,
+        turn: &Turn,
+        source: (usize, usize),
+        legs: &[(usize, usize)],
+    ) -> Vec<Vec<(usize, usize)>> {
+        let piece = Piece {
+            row: source.0 as u8,
+            col: source.1 as u8,
+            name: turn.pp.get(source.0, source.1),
+        };
+        self.allowed_moves(turn, piece)
+            .into_iter()
+            .map(|m| move_legs(piece, m))
+            .filter(|l| l.starts_with(legs))
+            .collect()
+    }
+
+    // The move of the piece on source ending on the last of legs. With more than one, the piece has
+    // to stop on each of them, which picks between chains of jumps ending on the same square.
+    // promotion picks the piece a pawn promotes to. The default is the rules' first choice, or a
+    // queen with queen_first.
+    pub fn find_move(
+        &self,
+        turn: &Turn,
+        (sr, sc): (usize, usize),
+        legs: &[(usize, usize)],
+        promotion: Option<u8>,
+        queen_first: bool,
+    ) -> Option<(Piece, Move)> {
+        // The squares can come from JS or the other player, so don't trust them.
+        // TODO: get board size from rules
+        let on_board = |r, c| (1..=8).contains(&r) && (1..=8).contains(&c);
+        if !on_board(sr, sc) || legs.is_empty() || !legs.iter().all(|&(r, c)| on_board(r, c)) {
+            return None;
+        }
+        let mut name = turn.pp.get(sr, sc);
+        // Placing a piece, like the duck, is a move from and to the empty square.
+        if name == 0 && legs == [(sr, sc)] {
+            name = self.rules.placement(&turn.pp, turn.gd).unwrap_or(0);
+        }
+        let piece = Piece {
+            row: sr as u8,
+            col: sc as u8,
+            name,
+        };
+        let mut moves = self.allowed_moves(turn, piece).into_iter().filter(|&m| {
+            let l = move_legs(piece, m);
+            l.last() == legs.last() && (legs.len() == 1 || l == legs)
+        });
+        let m = match promotion {
+            Some(p) => moves.rfind(|m| m.dst.name == p),
+            None => moves.min_by_key(|m| {
+                let queen = m.dst.name.eq_ignore_ascii_case(&b'q');
+                (
+                    queen_first && !queen,
+                    self.rules.promotion_order(piece, m.dst.name),
+                )
+            }),
+        }?;
+        Some((piece, m))
+    }
+
+    // What the piece making the move could turn into instead, in the rules' order, when the player
+    // has a choice: the other moves that stop on the same squares differ only in that. Empty for a
+    // move with nothing to choose, like most moves.
+    pub fn promotion_choices(&self, turn: &Turn, piece: Piece, m: Move) -> Vec<u8> {
+        let legs = move_legs(piece, m);
+        let mut choices: Vec<u8> = self
+            .allowed_moves(turn, piece)
+            .into_iter()
+            .filter(|&o| move_legs(piece, o) == legs)
+            .map(|o| o.dst.name)
+            .collect();
+        choices.sort_by_key(|&n| (self.rules.promotion_order(piece, n), n));
+        choices.dedup();
+        if choices.len() < 2 {
+            choices.clear();
+        }
+        choices
+    }
+
+    // The player's pass, if the rules let them pass now.
+    pub fn pass_move(&self, turn: &Turn) -> Option<(Piece, Move)> {
+        if turn.over {
+            return None;
+        }
+        self.rules.pass_move(turn.player, &turn.pp, turn.gd)
+    }
+
+    // Follows the pointer, picking pieces up and putting them down. Returns the move once the
+    // player has made one, for the caller to make. Their promotions are to a queen with
+    // auto_queen.
+    pub fn pointer_event(
+        &mut self,
+        turn: &Turn,
+        mode: InputMode,
+        auto_queen: bool,
+        event: PointerEvent,
+    ) -> Option<(Piece, Move)> {
+        match (self.pointer.clone(), event) {
+            (Pointer::Up, PointerEvent::Press(square)) => {
+                if turn.pp.get(square.0, square.1) == 0 {
+                    // An empty square can only be clicked to place a piece there.
+                    return self.find_move(turn, square, &[square], None, auto_queen);
+                }
+                // Pieces the player can't move aren't picked up, rather than put back once dropped.
+                if self.refusal(turn, square).is_some() {
+                    return None;
+                }
+                self.pointer = match mode {
+                    InputMode::Drag => Pointer::Dragging(square),
+                    InputMode::Click => Pointer::Selected(square),
+                };
+                None
+            }
+            // Dropping the piece, or clicking, anywhere it can't go puts it back.
+            (Pointer::Dragging(source), PointerEvent::Release(square))
+            | (Pointer::Selected(source), PointerEvent::Press(square)) => {
+                self.pointer = Pointer::Up;
+                let found = self.find_move(turn, source, &[square], None, auto_queen);
+                if found.is_some() {
+                    return found;
+                }
+                if self.change_piece
+                    && event == PointerEvent::Press(square)
+                    && square != source
+                    && turn.pp.get(square.0, square.1) != 0
+                    && self.refusal(turn, square).is_none()
+                {
+                    self.pointer = Pointer::Selected(square);
+                    return None;
+                }
+                // Short of the end of a move in several legs, the piece waits there.
+                self.continue_chain(turn, source, vec![square], auto_queen)
+            }
+            (Pointer::Chaining { source, mut legs }, PointerEvent::Press(square)) => {
+                legs.push(square);
+                // Clicks anywhere else are ignored.
+                self.continue_chain(turn, source, legs, auto_queen)
+            }
+            (Pointer::Selected(_) | Pointer::Chaining { .. }, PointerEvent::Cancel) => {
+                self.pointer = Pointer::Up;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // The move in several legs once the piece has stopped on all of them. Otherwise the piece
+    // waits for the next one, if some move goes that way.
+    fn continue_chain(
+        &mut self,
+        turn: &Turn,
+        source: (usize, usize),
+        legs: Vec<(usize, usize)>,
+        queen_first: bool,
+    ) -> Option<(Piece, Move)> {
+        let moves = self.moves_starting_with(turn, source, &legs);
+        if moves.contains(&legs) {
+            self.pointer = Pointer::Up;
+            return self.find_move(turn, source, &legs, None, queen_first);
+        }
+        if !moves.is_empty() {
+            self.pointer = Pointer::Chaining { source, legs };
+        }
+        None
+    }
+}
+
+// The position after the move.
+pub fn after(mut pp: PiecePlacements, piece: Piece, m: Move) -> (PiecePlacements, GameData) {
+    Rules::make_move(piece, m, &mut pp);
+    let gd = GameData {
+        ply: m.game_data.ply + 1,
+        ..m.game_data
+    };
+    (pp, gd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds the events to the player to move, making the moves they come to.
+    fn follow(core: &mut GameCore, mode: InputMode, events: &[PointerEvent]) {
+        for &event in events {
+            let turn = core.turn(core.game_data().player_to_move(), false);
+            if let Some((p, m)) = core.pointer_event(&turn, mode, true, event) {
+                core.play(p, m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_game_core() {
+        let mut core = GameCore::new(Rules::defaults());
+        follow(
+            &mut core,
+            InputMode::Drag,
+            &[
+                PointerEvent::Press((2, 5)),
+                PointerEvent::Release((4, 5)),
+                // Black can't drop a knight where it can't go.
+                PointerEvent::Press((8, 2)),
+                PointerEvent::Release((5, 2)),
+            ],
+        );
+        assert_eq!(core.moves, ["e2e4"]);
+        assert_eq!(core.pointer, Pointer::Up);
+
+        // On black's turn, white's moves after each of black's 20 replies are worked out ahead.
+        assert!(!core.look_ahead(1));
+        let mut steps = 1;
+        while core.look_ahead(0) {
+            steps += 1;
+        }
+        assert_eq!(steps, 20);
+        assert_eq!(core.ahead.as_ref().unwrap().found.len(), 20);
+
+        core.prepare_legal(1);
+        follow(
+            &mut core,
+            InputMode::Click,
+            &[
+                PointerEvent::Press((7, 5)),
+                PointerEvent::Release((7, 5)),
+                PointerEvent::Cancel,
+                PointerEvent::Press((7, 4)),
+                PointerEvent::Press((5, 4)),
+            ],
+        );
+        assert_eq!(core.moves, ["e2e4", "d7d5"]);
+        assert_eq!(core.history.len(), 3);
+        // White's moves now are the ones worked out after d7d5.
+        core.prepare_legal(0);
+        assert_eq!(core.ahead.as_ref().unwrap().found.len(), 19);
+        let (pp, gd) = core.position();
+        let legal = core.legal.as_ref().unwrap();
+        assert!(legal.is_for(0, &pp, gd));
+        assert_eq!(legal.moves.len(), core.rules.legal_moves(0, &pp, gd).len());
+
+        // White can't pick up black's pieces, and black can't pick up theirs out of turn.
+        let mut white = core.turn(0, false);
+        assert_eq!(core.refusal(&white, (7, 5)), Some(Refusal::NotYourPiece));
+        assert_eq!(core.refusal(&white, (6, 5)), None);
+        assert_eq!(core.refusal(&white, (2, 4)), None);
+        assert_eq!(
+            core.refusal(&core.turn(1, false), (7, 5)),
+            Some(Refusal::NotYourTurn)
+        );
+        let event = PointerEvent::Press((7, 5));
+        assert!(core
+            .pointer_event(&white, InputMode::Drag, true, event)
+            .is_none());
+        assert_eq!(core.pointer, Pointer::Up);
+        white.over = true;
+        assert_eq!(core.refusal(&white, (2, 4)), Some(Refusal::NotYourTurn));
+
+        // Not white's pieces to move once the game is over.
+        let over = core.turn(0, true);
+        assert!(core
+            .find_move(&over, (4, 5), &[(5, 4)], None, true)
+            .is_none());
+        let turn = core.turn(0, false);
+        assert!(core
+            .find_move(&turn, (4, 5), &[(5, 4)], None, true)
+            .is_some());
+        assert!(core
+            .find_move(&turn, (4, 5), &[(9, 4)], None, true)
+            .is_none());
+
+        // A pawn on the last rank but one can promote to any of the rules' pieces.
+        let (pp, gd) = crate::notation::parse_fen("8/4P3/8/8/8/8/8/k6K w - - 0 1").unwrap();
+        let promoting = Turn {
+            pp,
+            gd,
+            player: 0,
+            over: false,
+        };
+        let (piece, m) = core
+            .find_move(&promoting, (7, 5), &[(8, 5)], None, true)
+            .unwrap();
+        assert_eq!(m.dst.name, b'Q');
+        assert_eq!(core.promotion_choices(&promoting, piece, m), b"QRBN");
+        let (piece, m) = core
+            .find_move(&turn, (4, 5), &[(5, 4)], None, true)
+            .unwrap();
+        assert!(core.promotion_choices(&turn, piece, m).is_empty());
+
+        assert!(!core.take_back(3));
+        assert_eq!(core.sans(), ["e4", "d5"]);
+        assert!(core.take_back(2));
+        assert_eq!(core.moves, ["e2e4"]);
+
+        // With change_piece, clicking another of black's pieces picks it up instead.
+        core.prepare_legal(1);
+        core.change_piece = true;
+        let black = core.turn(1, false);
+        for square in [(7, 5), (7, 4)] {
+            core.pointer_event(&black, InputMode::Click, true, PointerEvent::Press(square));
+        }
+        assert_eq!(core.pointer, Pointer::Selected((7, 4)));
+        core.restart();
+        assert_eq!(core.history.len(), 1);
+    }
+
+    #[test]
+    fn test_take_back_from_fen() {
+        let mut core = GameCore::new(Rules::defaults());
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 12";
+        core.history = vec![crate::notation::parse_fen(fen).unwrap()];
+        assert_eq!(core.first_ply(), 23);
+        for uci in ["g1f3", "b8c6"] {
+            let (pp, gd) = core.position();
+            let (p, m) = parse_long_algebraic(&core.rules, &pp, gd, uci).unwrap();
+            core.play(p, m);
+        }
+        assert!(!core.take_back(22));
+        assert!(!core.take_back(25));
+        assert!(core.take_back(24));
+        assert_eq!(core.moves, ["g1f3"]);
+        assert_eq!({ core.game_data().ply }, 24);
+        assert!(core.take_back(23));
+        assert!(core.moves.is_empty());
+        assert_eq!(core.history.len(), 1);
+    }
+}
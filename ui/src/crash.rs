@@ -0,0 +1,111 @@
+// Crash reports: when the game panics, the hook saves the panic message along with enough of the
+// game's state to reproduce it, so it can be attached to a bug report. That includes the recording
+// of what went into the game (see recording.rs), which --replay-inputs replays.
+
+use std::{panic, sync::Mutex};
+
+use serde_json::{json, Value};
+
+use crate::{error, logging::recent_logs, recording};
+
+// The position, moves and rule set of the current game. Kept up to date by the game loop, since the
+// panic hook can't get at the game.
+struct GameState {
+    fen: String,
+    moves: Vec<String>,
+    ruleset: Value,
+}
+
+static GAME_STATE: Mutex<GameState> = Mutex::new(GameState {
+    fen: String::new(),
+    moves: Vec::new(),
+    ruleset: Value::Null,
+});
+
+static CRASH_REPORT: Mutex<Option<String>> = Mutex::new(None);
+
+// moves are in long algebraic notation, starting from the initial position.
+pub fn set_position(fen: String, moves: &[String]) {
+    let mut s = GAME_STATE.lock().unwrap();
+    s.fen = fen;
+    s.moves = moves.to_vec();
+}
+
+pub fn set_ruleset(ruleset: &str) {
+    let mut s = GAME_STATE.lock().unwrap();
+    s.ruleset = serde_json::from_str(ruleset).unwrap_or(Value::Null);
+}
+
+fn report(message: &str) -> String {
+    // The panic may have happened while the state was being updated, in which case it's left out
+    // rather than deadlocking.
+    let state = GAME_STATE.try_lock().ok();
+    json!({
+        "panic": message,
+        "fen": state.as_ref().map(|s| s.fen.as_str()),
+        "moves": state.as_ref().map(|s| &s.moves),
+        "ruleset": state.as_ref().map(|s| &s.ruleset),
+        "inputs": recording::json(),
+        "log": recent_logs(),
+    })
+    .to_string()
+}
+
+pub fn hook(info: &panic::PanicHookInfo) {
+    let message = info.to_string();
+    error!("{}", message);
+    let report = report(&message);
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("Crash report: {}", report);
+    if let Ok(mut r) = CRASH_REPORT.try_lock() {
+        *r = Some(report);
+    }
+}
+
+// Copies the crash report, a JSON object, into the retval_len bytes at retval_ptr. Returns the
+// report's length, which is 0 if the game hasn't crashed. If it's more than retval_len, nothing is
+// copied and JS should call again with a bigger buffer.
+#[no_mangle]
+pub extern "C" fn get_crash_report(retval_ptr: *mut u8, retval_len: usize) -> usize {
+    let r = match CRASH_REPORT.try_lock() {
+        Ok(r) => r,
+        Err(_) => return 0,
+    };
+    let report = match r.as_deref() {
+        Some(report) => report.as_bytes(),
+        None => return 0,
+    };
+    if report.len() <= retval_len {
+        unsafe {
+            std::ptr::copy_nonoverlapping(report.as_ptr(), retval_ptr, report.len());
+        }
+    }
+    report.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        set_position(
+            "8/8/8/8/8/8/8/K6k w - - 0 1".to_string(),
+            &["e2e4".to_string()],
+        );
+        set_ruleset(r#"{"name": "Test"}"#);
+        let v: Value = serde_json::from_str(&report("boom")).unwrap();
+        assert_eq!(v["panic"], "boom");
+        assert_eq!(v["fen"], "8/8/8/8/8/8/8/K6k w - - 0 1");
+        assert_eq!(v["moves"], json!(["e2e4"]));
+        assert_eq!(v["ruleset"]["name"], "Test");
+        assert!(v["inputs"]["inputs"].is_array());
+        assert!(v["log"].is_string());
+
+        // Held by a panic in the middle of an update.
+        let _state = GAME_STATE.lock().unwrap();
+        let v: Value = serde_json::from_str(&report("again")).unwrap();
+        assert_eq!(v["panic"], "again");
+        assert!(v["fen"].is_null());
+    }
+}
@@ -0,0 +1,97 @@
+// Lets JS plugins draw on the board, e.g. a variant showing which pieces are frozen or how long a
+// bomb has left, without a renderer of their own. The game asks the page about each square and
+// each piece with the decorate_square and decorate_piece events (see events.rs), which answer with
+// a color as RGBA, 0xRRGGBBAA, or 0 for none. A square's color is drawn over it, and a piece is
+// tinted with its color. Asking about every square and piece each frame would be slow, so the
+// answers are kept until the position changes, or the page calls refresh_decorations because its
+// own state did.
+
+use macroquad::prelude::*;
+use serde_json::json;
+
+use crate::{
+    events::{dispatch, Event},
+    prelude::*,
+};
+
+pub struct Decorations {
+    // The position the answers are for.
+    position: Option<(PiecePlacements, GameData)>,
+    squares: Vec<((usize, usize), Color)>,
+    pieces: Vec<((usize, usize), Color)>,
+}
+
+impl Decorations {
+    pub fn new() -> Decorations {
+        Decorations {
+            position: None,
+            squares: Vec::new(),
+            pieces: Vec::new(),
+        }
+    }
+
+    // Asks the page again if the answers aren't for pp, gd, or refresh is set.
+    pub fn update(&mut self, pp: &PiecePlacements, gd: GameData, refresh: bool) {
+        if !refresh && self.position == Some((*pp, gd)) {
+            return;
+        }
+        self.position = Some((*pp, gd));
+        self.squares.clear();
+        self.pieces.clear();
+        // TODO: get board size from rules
+        for r in 1..=8 {
+            for c in 1..=8 {
+                if let Some(color) = rgba(dispatch(Event::DecorateSquare(r, c))) {
+                    self.squares.push(((r, c), color));
+                }
+                let n = pp.get(r, c);
+                if n == 0 {
+                    continue;
+                }
+                let piece = json!({"row": r, "col": c, "name": (n as char).to_string()});
+                if let Some(color) = rgba(dispatch(Event::DecoratePiece(&piece.to_string()))) {
+                    self.pieces.push(((r, c), color));
+                }
+            }
+        }
+    }
+
+    // The squares to draw over, and their colors.
+    pub fn squares(&self) -> &[((usize, usize), Color)] {
+        &self.squares
+    }
+
+    // The tint of the piece on r, c, if it has one.
+    pub fn piece_tint(&self, r: usize, c: usize) -> Option<Color> {
+        self.pieces
+            .iter()
+            .find(|(rc, _)| *rc == (r, c))
+            .map(|&(_, color)| color)
+    }
+}
+
+fn rgba(n: u32) -> Option<Color> {
+    (n != 0).then(|| Color::from_rgba((n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorations() {
+        assert_eq!(rgba(0), None);
+        assert_eq!(rgba(0xff00_0080), Some(Color::from_rgba(255, 0, 0, 128)));
+
+        // Without a page, there's nothing to draw, and nothing to ask again until the position
+        // changes.
+        let rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = rules.initial_game_data();
+        let mut decorations = Decorations::new();
+        decorations.update(&pp, gd, false);
+        assert_eq!(decorations.position, Some((pp, gd)));
+        assert!(decorations.squares().is_empty());
+        assert_eq!(decorations.piece_tint(1, 1), None);
+    }
+}
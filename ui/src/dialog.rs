@@ -0,0 +1,205 @@
+// Questions the game stops to ask the player, drawn over the board in both the browser and the
+// desktop binary: a question, maybe a line more about it, and a row of answers to pick from. A
+// click picks one, and so does its key, if it has one, or the confirm key on the one selected,
+// which the left and right keys (or the cursor's, see controls.rs) change. The cancel and menu keys
+// pick the answer given as the dialog's cancel. Until it's answered, the board doesn't get the
+// player's input.
+
+use macroquad::prelude::*;
+
+use crate::{controls::InputEvent, i18n::tr};
+
+const FONT_SIZE: f32 = 24.0;
+const ANSWER_WIDTH: f32 = 160.0;
+const ANSWER_HEIGHT: f32 = 40.0;
+const GAP: f32 = 12.0;
+const BOX_HEIGHT: f32 = 3.0 * GAP + FONT_SIZE + ANSWER_HEIGHT + GAP;
+// The detail's line, when there is one.
+const DETAIL_HEIGHT: f32 = FONT_SIZE + GAP;
+
+// One answer: what it says, the key that picks it, and what picking it gives.
+pub struct Answer<T> {
+    pub label: String,
+    pub key: Option<KeyCode>,
+    pub value: T,
+}
+
+pub struct Dialog<T> {
+    question: String,
+    // Under the question, smaller.
+    detail: Option<String>,
+    answers: Vec<Answer<T>>,
+    // Given for the cancel key.
+    cancel: T,
+    selected: usize,
+}
+
+impl<T: Copy> Dialog<T> {
+    pub fn new(question: String, answers: Vec<Answer<T>>, cancel: T) -> Dialog<T> {
+        Dialog {
+            question,
+            detail: None,
+            answers,
+            cancel,
+            selected: 0,
+        }
+    }
+
+    // Adds the line under the question.
+    pub fn with_detail(self, detail: String) -> Dialog<T> {
+        Dialog {
+            detail: Some(detail),
+            ..self
+        }
+    }
+
+    // A question answered with yes, Y, or no, N, which cancelling is too.
+    pub fn yes_no(question: String, yes: &str, no: &str, value: impl Fn(bool) -> T) -> Dialog<T> {
+        let answers = vec![
+            Answer {
+                label: tr(yes),
+                key: Some(KeyCode::Y),
+                value: value(true),
+            },
+            Answer {
+                label: tr(no),
+                key: Some(KeyCode::N),
+                value: value(false),
+            },
+        ];
+        Dialog::new(question, answers, value(false))
+    }
+
+    // The answer the player picked this frame, with events from controls::events.
+    pub fn update(&mut self, events: &[InputEvent]) -> Option<T> {
+        if let Some(a) = self
+            .answers
+            .iter()
+            .find(|a| a.key.is_some_and(is_key_pressed))
+        {
+            return Some(a.value);
+        }
+        let screen = vec2(screen_width(), screen_height());
+        let last = self.answers.len() - 1;
+        let detail = self.detail.is_some();
+        for &event in events {
+            match event {
+                InputEvent::Select(_) => {
+                    let (x, y) = mouse_position();
+                    let rects = answer_rects(self.answers.len(), detail, screen);
+                    if let Some(i) = rects.iter().position(|r| r.contains(vec2(x, y))) {
+                        return Some(self.answers[i].value);
+                    }
+                }
+                InputEvent::Confirm => return Some(self.answers[self.selected].value),
+                InputEvent::Cancel | InputEvent::Menu => return Some(self.cancel),
+                InputEvent::Cursor(dx, _) => {
+                    self.selected = self.selected.saturating_add_signed(dx as isize).min(last)
+                }
+                _ => {}
+            }
+        }
+        if is_key_pressed(KeyCode::Left) {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.selected = (self.selected + 1).min(last);
+        }
+        None
+    }
+
+    pub fn draw(&self) {
+        let screen = vec2(screen_width(), screen_height());
+        draw_rectangle(0.0, 0.0, screen.x, screen.y, Color::new(0.0, 0.0, 0.0, 0.3));
+        let detail = self.detail.is_some();
+        let b = box_rect(self.answers.len(), detail, screen);
+        draw_rectangle(b.x, b.y, b.w, b.h, Color::new(0.1, 0.1, 0.1, 0.9));
+        draw_rectangle_lines(b.x, b.y, b.w, b.h, 2.0, GRAY);
+        let centered = |text: &str, r: Rect, y: f32, size: f32, color: Color| {
+            let width = measure_text(text, None, size as u16, 1.0).width;
+            draw_text(text, r.x + (r.w - width) / 2.0, y, size, color);
+        };
+        centered(&self.question, b, b.y + GAP + FONT_SIZE, FONT_SIZE, WHITE);
+        if let Some(detail) = &self.detail {
+            let y = b.y + GAP + FONT_SIZE + DETAIL_HEIGHT;
+            centered(detail, b, y, FONT_SIZE * 0.8, LIGHTGRAY);
+        }
+        let (mx, my) = mouse_position();
+        for (i, (a, r)) in self
+            .answers
+            .iter()
+            .zip(answer_rects(self.answers.len(), detail, screen))
+            .enumerate()
+        {
+            let hovered = r.contains(vec2(mx, my));
+            let fill = if hovered || i == self.selected {
+                Color::new(0.35, 0.35, 0.35, 1.0)
+            } else {
+                Color::new(0.2, 0.2, 0.2, 1.0)
+            };
+            draw_rectangle(r.x, r.y, r.w, r.h, fill);
+            if i == self.selected {
+                draw_rectangle_lines(r.x, r.y, r.w, r.h, 2.0, WHITE);
+            }
+            let y = r.y + (r.h + FONT_SIZE) / 2.0 - 4.0;
+            centered(&a.label, r, y, FONT_SIZE, WHITE);
+        }
+    }
+}
+
+// The dialog's box for n answers, and a detail if there is one, in the middle of the screen, wide
+// enough for them and the question but no wider than the screen.
+fn box_rect(n: usize, detail: bool, screen: Vec2) -> Rect {
+    let width = (n as f32 * (ANSWER_WIDTH + GAP) + GAP)
+        .max(480.0)
+        .min(screen.x);
+    let height = if detail {
+        BOX_HEIGHT + DETAIL_HEIGHT
+    } else {
+        BOX_HEIGHT
+    };
+    Rect::new(
+        (screen.x - width) / 2.0,
+        (screen.y - height) / 2.0,
+        width,
+        height,
+    )
+}
+
+// The answers' buttons, side by side along the bottom of the box, narrower if they don't fit.
+fn answer_rects(n: usize, detail: bool, screen: Vec2) -> Vec<Rect> {
+    let b = box_rect(n, detail, screen);
+    let width = ANSWER_WIDTH.min((b.w - GAP) / n as f32 - GAP);
+    let left = b.x + (b.w - n as f32 * (width + GAP) + GAP) / 2.0;
+    let y = b.bottom() - GAP - ANSWER_HEIGHT;
+    (0..n)
+        .map(|i| Rect::new(left + i as f32 * (width + GAP), y, width, ANSWER_HEIGHT))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialog_layout() {
+        let screen = vec2(800.0, 800.0);
+        let two = answer_rects(2, false, screen);
+        let b = box_rect(2, false, screen);
+        assert_eq!(b.center(), screen / 2.0);
+        assert!(two
+            .iter()
+            .all(|r| b.contains(r.point()) && r.bottom() < b.bottom()));
+        assert!(two[0].right() < two[1].x);
+        // The answers share the width of a narrow screen.
+        let narrow = vec2(300.0, 400.0);
+        let four = answer_rects(4, false, narrow);
+        assert_eq!(box_rect(4, false, narrow).w, 300.0);
+        assert!(four[0].x >= 0.0 && four[3].right() <= 300.0);
+        assert!(four.windows(2).all(|w| w[0].right() < w[1].x));
+        // A detail makes room for itself, above the answers.
+        let b = box_rect(2, true, screen);
+        assert_eq!(b.h, BOX_HEIGHT + DETAIL_HEIGHT);
+        assert_eq!(answer_rects(2, true, screen)[0].bottom(), b.bottom() - GAP);
+    }
+}
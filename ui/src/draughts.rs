@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::*;
+
+// Draughts (English checkers) on the chess board, as a second rules profile. Men are pawns and
+// kings are queens, so they share the chess sprites, notation and protocol: a man is crowned by
+// promoting on the far rank. Each side has 12 men on the dark squares of its first three ranks,
+// and white moves first. Pieces move one square diagonally, men only forward, and capture by
+// jumping an enemy piece to the empty square behind it. A capture goes on for as long as the
+// piece can keep jumping, and is one move (MoveType::Captures) from where it started to where it
+// stops. Capturing is compulsory, and a player who can't move loses.
+
+const MAN_DIRECTIONS: [[(i32, i32); 2]; 2] = [[(1, -1), (1, 1)], [(-1, -1), (-1, 1)]];
+const KING_DIRECTIONS: [(i32, i32); 4] = [(1, -1), (1, 1), (-1, -1), (-1, 1)];
+
+impl<'a> Rules<'a> {
+    pub fn draughts() -> Self {
+        let mut move_constraint_rules = Self::default_move_constraint_rules();
+        // There's no check, and the kings aren't royal.
+        move_constraint_rules.remove("resolve-check");
+        move_constraint_rules.remove("kings-may-not-touch");
+        move_constraint_rules
+            .get_mut("forced-capture")
+            .unwrap()
+            .active = true;
+        let mut promotions = HashMap::new();
+        promotions.insert(
+            b'p',
+            Promotion {
+                rank: 8,
+                pieces: b"q".to_vec(),
+                captured_only: false,
+            },
+        );
+        Self {
+            name: "Draughts".to_string(),
+            profile: "draughts",
+            no_moves_loses: true,
+            double_move: false,
+            phases: vec![Phase::Move],
+            pass: false,
+            piece_name_to_offsets: Self::default_piece_name_to_offsets(),
+            setup_rules: Self::draughts_setup_rules(),
+            turn_rules: Self::default_turn_rules(),
+            movement_rules: Self::draughts_movement_rules(),
+            move_constraint_rules,
+            promotions,
+            zones: Zones::defaults(),
+            seed: None,
+        }
+    }
+
+    fn draughts_setup_rules() -> HashMap<&'a str, Box<dyn SetupRuleFn>> {
+        let mut hm = HashMap::<&'a str, Box<dyn SetupRuleFn>>::new();
+        hm.insert(
+            "men",
+            Box::new(|| {
+                let mut p = Vec::new();
+                for (rows, name) in [(1..=3u8, b'P'), (6..=8, b'p')] {
+                    for row in rows {
+                        // The dark squares, a1 being one.
+                        for col in (1..=8).filter(|c| (row + c) % 2 == 0) {
+                            p.push(Piece { row, col, name });
+                        }
+                    }
+                }
+                p
+            }),
+        );
+        hm
+    }
+
+    fn draughts_movement_rules() -> HashMap<&'a str, MovementRule> {
+        let mut hm = HashMap::<&'a str, MovementRule>::new();
+        hm.insert(
+            "man",
+            MovementRule {
+                active: true,
+                piece_constrait: Some('p'),
+                f: Box::new(
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
+                        add_steps(p, pp, gd, hs, &MAN_DIRECTIONS[!p.is_white() as usize]);
+                    },
+                ),
+            },
+        );
+        hm.insert(
+            "king",
+            MovementRule {
+                active: true,
+                piece_constrait: Some('q'),
+                f: Box::new(
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
+                        add_steps(p, pp, gd, hs, &KING_DIRECTIONS);
+                    },
+                ),
+            },
+        );
+        hm.insert(
+            "jump",
+            MovementRule {
+                active: true,
+                piece_constrait: None,
+                f: Box::new(
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
+                        let dirs: &[(i32, i32)] = match p.name {
+                            b'P' | b'p' => &MAN_DIRECTIONS[!p.is_white() as usize],
+                            b'Q' | b'q' => &KING_DIRECTIONS,
+                            _ => return,
+                        };
+                        add_jumps(p, dirs, (p.row as i32, p.col as i32), pp, 0, gd, hs);
+                    },
+                ),
+            },
+        );
+        hm
+    }
+}
+
+fn add_steps(
+    p: Piece,
+    pp: &PiecePlacements,
+    gd: GameData,
+    hs: &mut HashSet<Move>,
+    dirs: &[(i32, i32)],
+) {
+    for &(dr, dc) in dirs {
+        let (r, c) = (p.row as i32 + dr, p.col as i32 + dc);
+        if std_in_bounds(r, c) && pp.get(r as usize, c as usize) == 0 {
+            hs.insert(Move::normal(r as usize, c as usize, p.name, gd));
+        }
+    }
+}
+
+// Adds the captures p can make by jumping on from square, having already captured the pieces in
+// captured. Jumped pieces stay on the board until the move is over, but can't be jumped twice.
+fn add_jumps(
+    p: Piece,
+    dirs: &[(i32, i32)],
+    square: (i32, i32),
+    pp: &PiecePlacements,
+    captured: u64,
+    gd: GameData,
+    hs: &mut HashSet<Move>,
+) {
+    let (r, c) = square;
+    let mut stopped = true;
+    for &(dr, dc) in dirs {
+        let (jr, jc) = (r + dr, c + dc);
+        let (lr, lc) = (r + 2 * dr, c + 2 * dc);
+        if !std_in_bounds(lr, lc) {
+            continue;
+        }
+        let jumped = pp.get(jr as usize, jc as usize);
+        let bit = square_bit(jr as usize, jc as usize);
+        if !is_enemy(jumped, p.is_white()) || captured & bit != 0 {
+            continue;
+        }
+        // The square the piece started from is empty by now.
+        let start = (lr, lc) == (p.row as i32, p.col as i32);
+        if pp.get(lr as usize, lc as usize) != 0 && !start {
+            continue;
+        }
+        stopped = false;
+        add_jumps(p, dirs, (lr, lc), pp, captured | bit, gd, hs);
+    }
+    if stopped && captured != 0 {
+        hs.insert(Move {
+            dst: Piece {
+                row: r as u8,
+                col: c as u8,
+                name: p.name,
+            },
+            typ: MoveType::Captures { squares: captured },
+            game_data: gd,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moves_of(rules: &Rules, board: &str, row: u8, col: u8) -> Vec<(u8, u8, u8, usize)> {
+        let pp = string_board_to_placements(board);
+        let piece = Piece {
+            row,
+            col,
+            name: pp.get(row as usize, col as usize),
+        };
+        let mut moves: Vec<(u8, u8, u8, usize)> = rules
+            .allowed_moves(piece, &pp, GameData { ply: 1, mask: 0 })
+            .iter()
+            .map(|m| {
+                let captured = match m.typ {
+                    MoveType::Captures { squares } => bit_squares(squares).count(),
+                    _ => 0,
+                };
+                (m.dst.row, m.dst.col, m.dst.name, captured)
+            })
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn test_setup_and_steps() {
+        let rules = Rules::draughts();
+        let pp = rules.initial_placements();
+        assert_eq!((pp.count(b'P'), pp.count(b'p')), (12, 12));
+        assert_eq!(pp.get(1, 1), b'P');
+        assert_eq!(pp.get(8, 8), b'p');
+        let gd = GameData { ply: 1, mask: 0 };
+        // Each of the four front men can step to an empty dark square, the edge one to just one.
+        assert_eq!(rules.legal_moves(0, &pp, gd).len(), 7);
+    }
+
+    #[test]
+    fn test_chained_jumps_and_crowning() {
+        let rules = Rules::draughts();
+        let board = "
+            ........
+            ........
+            ...p.p..
+            ........
+            ...p....
+            ..P.....
+            ........
+            ........
+        ";
+        // c3xe5xg7 or c3xe5xc7, two pieces each, but not the step away since capturing is
+        // compulsory.
+        assert_eq!(
+            moves_of(&rules, board, 3, 3),
+            [(7, 3, b'P', 2), (7, 7, b'P', 2)]
+        );
+        // Crowned on reaching the far rank.
+        let board = "
+            ........
+            ..p.p...
+            .P......
+            ........
+            ........
+            ........
+            ........
+            ........
+        ";
+        assert_eq!(moves_of(&rules, board, 6, 2), [(8, 4, b'Q', 1)]);
+        // Kings jump backwards too.
+        let board = "
+            ........
+            ........
+            ........
+            ........
+            ........
+            ..Q.....
+            .p......
+            ........
+        ";
+        assert_eq!(moves_of(&rules, board, 3, 3), [(1, 1, b'Q', 1)]);
+    }
+
+    #[test]
+    fn test_captures_made_and_no_moves_loses() {
+        let rules = Rules::draughts();
+        let board = "
+            ........
+            ........
+            ...p.p..
+            ........
+            ...p....
+            ..P.....
+            ........
+            ........
+        ";
+        let mut pp = string_board_to_placements(board);
+        let gd = GameData { ply: 1, mask: 0 };
+        let piece = Piece {
+            row: 3,
+            col: 3,
+            name: b'P',
+        };
+        let before = pp;
+        let m = *rules
+            .allowed_moves(piece, &pp, gd)
+            .iter()
+            .find(|m| (m.dst.row, m.dst.col) == (7, 7))
+            .unwrap();
+        assert_eq!(move_legs(piece, m), [(5, 5), (7, 7)]);
+        Rules::make_move(piece, m, &mut pp);
+        assert_eq!(pp.get(7, 7), b'P');
+        assert_eq!((pp.get(4, 4), pp.get(6, 6), pp.get(6, 4)), (0, 0, b'p'));
+        Rules::unmake_move(piece, m, &before, &mut pp);
+        assert_eq!(pp, before);
+
+        // Black's men are blocked or gone.
+        let board = "
+            ........
+            ........
+            ........
+            ........
+            ........
+            ........
+            ........
+            ......Pp
+        ";
+        let pp = string_board_to_placements(board);
+        let black = GameData { ply: 2, mask: 0 };
+        assert!(rules.legal_moves(1, &pp, black).is_empty());
+        assert!(rules.lost_without_moves(false, &pp, black));
+        assert!(!Rules::defaults().lost_without_moves(false, &pp, black));
+    }
+}
@@ -0,0 +1,145 @@
+// Short effects drawn over the board when something happens in the game: a burst of particles for
+// a capture, a ring spreading out from a promotion, the king's and rook's squares fading out after
+// castling, and a bigger burst over the king that's checkmated. Their color and how many particles
+// a burst has come from the theme (see Palette). They play at the player's animation speed, so
+// setting it to 0, for reduced motion, turns them off.
+
+use std::f32::consts::TAU;
+
+use macroquad::prelude::*;
+
+use crate::settings::Palette;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EffectKind {
+    Capture,
+    Promotion,
+    Castle,
+    Checkmate,
+}
+
+impl EffectKind {
+    // In seconds, at normal speed.
+    fn duration(self) -> f64 {
+        match self {
+            EffectKind::Capture => 0.5,
+            EffectKind::Promotion => 0.7,
+            EffectKind::Castle => 0.4,
+            EffectKind::Checkmate => 1.5,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Effect {
+    kind: EffectKind,
+    square: (usize, usize),
+    started: f64,
+    duration: f64,
+    color: Color,
+    particles: usize,
+}
+
+#[derive(Default)]
+pub struct Effects {
+    active: Vec<Effect>,
+}
+
+impl Effects {
+    // Starts an effect on the square r, c at time now. speed is the animation speed.
+    pub fn trigger(
+        &mut self,
+        kind: EffectKind,
+        (r, c): (usize, usize),
+        now: f64,
+        palette: &Palette,
+        speed: f32,
+    ) {
+        if speed <= 0.0 {
+            return;
+        }
+        self.active.push(Effect {
+            kind,
+            square: (r, c),
+            started: now,
+            duration: kind.duration() / speed as f64,
+            color: palette.effect,
+            particles: palette.particles,
+        });
+    }
+
+    // Forgets the effects that have finished by now.
+    pub fn expire(&mut self, now: f64) {
+        self.active.retain(|e| now - e.started < e.duration);
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    // Draws the effects at time now. xy is the top left corner of a square, as in rc_to_xy.
+    pub fn draw(&self, now: f64, square_size: f32, xy: impl Fn(usize, usize) -> (f32, f32)) {
+        for e in &self.active {
+            let t = (((now - e.started) / e.duration) as f32).clamp(0.0, 1.0);
+            let (x, y) = xy(e.square.0, e.square.1);
+            let (cx, cy) = (x + square_size / 2.0, y + square_size / 2.0);
+            let color = Color {
+                a: e.color.a * (1.0 - t),
+                ..e.color
+            };
+            match e.kind {
+                EffectKind::Capture | EffectKind::Checkmate => {
+                    let (reach, size, n) = if e.kind == EffectKind::Checkmate {
+                        (1.5 * square_size, 6.0, 2 * e.particles)
+                    } else {
+                        (0.75 * square_size, 4.0, e.particles)
+                    };
+                    for (px, py) in burst(n, t * reach) {
+                        draw_circle(cx + px, cy + py, size * (1.0 - t / 2.0), color);
+                    }
+                }
+                EffectKind::Promotion => {
+                    draw_circle_lines(cx, cy, square_size * (0.3 + 0.7 * t), 4.0, color);
+                }
+                EffectKind::Castle => draw_rectangle(x, y, square_size, square_size, color),
+            }
+        }
+    }
+}
+
+// Where n particles spread evenly around a square's center are once they've gone distance.
+fn burst(n: usize, distance: f32) -> impl Iterator<Item = (f32, f32)> {
+    (0..n).map(move |i| {
+        let angle = TAU * i as f32 / n as f32;
+        (distance * angle.cos(), distance * angle.sin())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Theme;
+
+    #[test]
+    fn test_effects() {
+        let palette = Theme::Classic.palette();
+        let mut effects = Effects::default();
+        effects.trigger(EffectKind::Capture, (4, 5), 10.0, &palette, 1.0);
+        effects.trigger(EffectKind::Checkmate, (8, 5), 10.0, &palette, 2.0);
+        assert_eq!(effects.active.len(), 2);
+        assert_eq!(effects.active[1].duration, 0.75);
+        effects.expire(10.6);
+        assert_eq!(effects.active.len(), 1);
+        assert_eq!(effects.active[0].kind, EffectKind::Checkmate);
+        effects.expire(10.75);
+        assert!(effects.active.is_empty());
+
+        // Reduced motion.
+        effects.trigger(EffectKind::Promotion, (8, 1), 10.0, &palette, 0.0);
+        assert!(effects.active.is_empty());
+
+        let points: Vec<_> = burst(4, 10.0).collect();
+        assert!((points[0].0 - 10.0).abs() < 1e-4 && points[0].1.abs() < 1e-4);
+        assert!(points[2].0 + 10.0 < 1e-4);
+    }
+}
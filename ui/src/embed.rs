@@ -0,0 +1,169 @@
+// The iframe API, so other sites can embed the board like a widget: embed.html shows just the
+// board, and the page embedding it drives it with postMessage. The parent posts commands, e.g.
+//   {"chess": 1, "id": 1, "command": "load_fen", "fen": "..."}
+// and gets an answer with the same id, {"chess": 1, "id": 1, "result": ...}, or
+// {"chess": 1, "id": 1, "error": "..."} if the command failed. After a subscribe command, the
+// events it names (see events.rs) are posted to the parent as they happen, e.g.
+//   {"chess": 1, "event": "moved", "args": [{"uci": "e2e4", ...}]}
+// and once the board has loaded, it posts {"chess": 1, "event": "ready", "args": []}.
+//
+// The shim that does this, assets/js/embed.js, is generated with the rest of the JS side of the
+// WASM interface (see ffi.rs), from COMMANDS, which a test checks only call exports that exist.
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub doc: &'static str,
+    // The fields of the message the command takes.
+    pub params: &'static [&'static str],
+    // The body of the JS function handling the command, which gets the params as arguments. What
+    // it returns is the answer's result, and what it throws is the error.
+    pub js: &'static str,
+}
+
+pub const COMMANDS: [CommandSpec; 8] = [
+    CommandSpec {
+        name: "load_fen",
+        doc: "Starts a game between two players at the board from the position in FEN.",
+        params: &["fen"],
+        js: "if (!with_string(String(fen), wasm_exports.load_fen)) {
+            throw new Error(\"Invalid FEN\");
+        }",
+    },
+    CommandSpec {
+        name: "load_pgn",
+        doc: "Starts a game from the first game in PGN, with its moves played.",
+        params: &["pgn"],
+        js: "if (!with_string(String(pgn), wasm_exports.load_pgn)) {
+            throw new Error(\"Invalid PGN\");
+        }",
+    },
+    CommandSpec {
+        name: "new_game",
+        doc: "Starts a new game between two players at the board.",
+        params: &[],
+        js: "wasm_exports.set_game_mode(0, 0);",
+    },
+    CommandSpec {
+        name: "move",
+        doc: "Makes a move in long algebraic notation, e.g. \"e2e4\", for the player at the board.",
+        params: &["uci"],
+        js: "with_string(String(uci), wasm_exports.play_move);",
+    },
+    CommandSpec {
+        name: "get_fen",
+        doc: "The position shown on the board in FEN.",
+        params: &[],
+        js: "return take_string(wasm_exports.get_fen());",
+    },
+    CommandSpec {
+        name: "set_theme",
+        doc: "Colors the board: \"classic\", \"wood\", \"gray\", \"high_contrast\" or \"color_blind\".",
+        params: &["theme"],
+        js: "call_with_json(wasm_exports.set_settings, { theme });",
+    },
+    CommandSpec {
+        name: "flip",
+        doc: "Shows the board with black at the bottom if flipped is true.",
+        params: &["flipped"],
+        js: "wasm_exports.flip_board(flipped ? 1 : 0);",
+    },
+    CommandSpec {
+        name: "subscribe",
+        doc: "Posts the named events to the parent from now on.",
+        params: &["events"],
+        js: "for (let name of events) {
+            if (!EVENTS.some((e) => e.name === name)) {
+                throw new Error(`No such event: ${name}`);
+            }
+            on(name, (...args) => post({ event: name, args }));
+        }",
+    },
+];
+
+const HEADER: &str = "\
+// Generated by `chess-ui --ffi-js` (see src/embed.rs). Don't edit it, run that again when the
+// commands change.
+
+import { EVENTS } from \"./ffi.js\";
+import { on } from \"./events.js\";
+import { take_string, with_string, call_with_json } from \"./mem.js\";
+";
+
+const FOOTER: &str = "
+let parent_origin = \"*\";
+
+function post(message) {
+    window.parent.postMessage({ chess: 1, ...message }, parent_origin);
+}
+
+// Answers the parent page's commands, and only theirs. Messages are only posted to origin, if
+// given, and only taken from it. Call it once the game has loaded.
+export function init_embed(origin = \"*\") {
+    parent_origin = origin;
+    window.addEventListener(\"message\", (event) => {
+        let message = event.data;
+        if (event.source !== window.parent || (origin !== \"*\" && event.origin !== origin)
+            || !message || message.chess !== 1 || !message.command) {
+            return;
+        }
+        let command = COMMANDS[message.command];
+        try {
+            if (!command) {
+                throw new Error(`No such command: ${message.command}`);
+            }
+            let result = command(...command.params.map((p) => message[p]));
+            post({ id: message.id, result: result === undefined ? null : result });
+        } catch (e) {
+            post({ id: message.id, error: String(e.message || e) });
+        }
+    });
+    post({ event: \"ready\", args: [] });
+}
+";
+
+pub fn generate() -> String {
+    let mut js = HEADER.to_string();
+    js.push_str("\n// The commands the parent page can post, by name.\nconst COMMANDS = {\n");
+    for c in &COMMANDS {
+        let params = c.params.join(", ");
+        js.push_str(&format!(
+            "    // {}\n    {}: Object.assign(({}) => {{\n        {}\n    }}, {{ params: [{}] }}),\n",
+            c.doc,
+            c.name,
+            params,
+            c.js,
+            c.params
+                .iter()
+                .map(|p| format!("\"{}\"", p))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    js.push_str("};\n");
+    js.push_str(FOOTER);
+    js
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        let ffi = include_str!("../assets/js/ffi.js");
+        for c in &COMMANDS {
+            for export in c.js.split("wasm_exports.").skip(1) {
+                let name: String = export
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '_')
+                    .collect();
+                assert!(
+                    ffi.contains(&format!("export function {}(", name)),
+                    "{} calls {}, which isn't exported",
+                    c.name,
+                    name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1587 @@
+// A small alpha-beta engine to play against. It only uses the public rules API, so it plays
+// whatever variant the rules describe, including rules toggled off or added by plugins.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use macroquad::{miniquad::date, rand};
+
+use crate::prelude::*;
+
+pub const MATE_SCORE: i32 = 100_000;
+pub const MAX_STRENGTH: u8 = 5;
+// Closer to the leaves than this, captures are ordered by a cheap guess instead of see.
+const SEE_MIN_DEPTH: u32 = 3;
+// Null-move pruning searches the position after a pass this many plies less deep than the moves,
+// and only where at least NULL_MOVE_MIN_DEPTH plies are left.
+const NULL_MOVE_REDUCTION: u32 = 2;
+const NULL_MOVE_MIN_DEPTH: u32 = 3;
+// Late move reductions search quiet moves after the first LMR_MIN_MOVES a ply less deep, where at
+// least LMR_MIN_DEPTH plies are left.
+const LMR_MIN_MOVES: usize = 3;
+const LMR_MIN_DEPTH: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EngineConfig {
+    // Maximum search depth in plies.
+    pub depth: u32,
+    // Stop deepening the search after this many seconds.
+    pub time_limit: f64,
+    // Chance from 0 to 1 of playing a random move instead of the best one.
+    pub blunder_chance: f64,
+    // How much the engine dislikes draws, in centipawns. Negative values make it seek draws.
+    pub contempt: i32,
+    // How many plies of captures to keep searching past depth, so the engine doesn't stop in the
+    // middle of an exchange. 0 turns quiescence search off.
+    pub quiescence_depth: u32,
+    // How many times a line can be searched a ply deeper because of a check, so forcing lines are
+    // seen further ahead.
+    pub check_extensions: u32,
+    // Whether to cut off positions that are good enough even after passing, see negamax.
+    pub null_move: bool,
+    // Whether to search moves that are unlikely to be best less deep, see negamax.
+    pub late_move_reductions: bool,
+    // Added to the material in the evaluation, e.g. as fitted by --tune. Loaded once and kept for
+    // the whole run.
+    pub pst: Option<&'static PieceSquareTables>,
+}
+
+impl EngineConfig {
+    // Presets for strength 1 (weakest) to MAX_STRENGTH.
+    pub fn level(strength: u8) -> Self {
+        let (depth, time_limit, blunder_chance, quiescence_depth, check_extensions) =
+            match strength.clamp(1, MAX_STRENGTH) {
+                1 => (1, 0.2, 0.3, 0, 0),
+                2 => (2, 0.5, 0.15, 2, 0),
+                3 => (3, 1.0, 0.05, 4, 1),
+                4 => (4, 2.0, 0.0, 6, 2),
+                _ => (6, 3.0, 0.0, 8, 3),
+            };
+        Self {
+            depth,
+            time_limit,
+            blunder_chance,
+            contempt: 0,
+            quiescence_depth,
+            check_extensions,
+            null_move: true,
+            late_move_reductions: true,
+            pst: None,
+        }
+    }
+}
+
+// Settings that override a level's presets.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EngineOptions {
+    // None keeps the level's time limit.
+    pub time_limit: Option<f64>,
+    pub contempt: i32,
+    // None keeps the level's pruning, which is on at every level.
+    pub null_move: Option<bool>,
+    pub late_move_reductions: Option<bool>,
+}
+
+impl EngineConfig {
+    pub fn with_options(self, options: EngineOptions) -> Self {
+        Self {
+            time_limit: options.time_limit.unwrap_or(self.time_limit),
+            contempt: options.contempt,
+            null_move: options.null_move.unwrap_or(self.null_move),
+            late_move_reductions: options
+                .late_move_reductions
+                .unwrap_or(self.late_move_reductions),
+            ..self
+        }
+    }
+}
+
+pub struct Engine {
+    pub config: EngineConfig,
+}
+
+// The pieces piece-square tables have bonuses for, in the order of their tables.
+pub const PST_PIECES: [u8; 6] = *b"pnbrqk";
+
+// Bonuses in centipawns for where each kind of piece stands on an 8x8 board, e.g. for knights in
+// the center. Each table is from the point of view of the piece's owner, starting from their back
+// rank: a1 to h1 for white, then a2 to h2, and a8 to h8 then a7 to h7 for black.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PieceSquareTables(pub [[i32; 64]; 6]);
+
+impl PieceSquareTables {
+    // The table and the index in it for the piece named name on row, col, if there is one.
+    pub fn index(name: u8, row: usize, col: usize) -> Option<(usize, usize)> {
+        let table = PST_PIECES
+            .iter()
+            .position(|&p| p == name.to_ascii_lowercase())?;
+        let rank = if name.is_ascii_uppercase() {
+            row
+        } else {
+            9 - row
+        };
+        Some((table, (rank - 1) * 8 + col - 1))
+    }
+}
+
+// State for a single call to best_move, or a slice of an analysis.
+struct Search<'r, 'a> {
+    rules: &'r Rules<'a>,
+    // The player the engine is playing for, i.e. to move at the root.
+    player: usize,
+    contempt: i32,
+    quiescence_depth: u32,
+    check_extensions: u32,
+    null_move: bool,
+    late_move_reductions: bool,
+    pst: Option<&'static PieceSquareTables>,
+    #[cfg(feature = "nnue")]
+    nnue: crate::nnue::Evaluator,
+    // Check extensions used by the line being searched.
+    extended: u32,
+    // The ply of the last pass in the line being searched, so there are never two in a row.
+    passed_at: Option<u32>,
+    deadline: f64,
+    // Lets another thread abort the search.
+    stop: Option<&'r AtomicBool>,
+    timed_out: bool,
+    history: History,
+    // Positions searched so far, including by quiescence search.
+    nodes: u64,
+    seldepth: u32,
+}
+
+// What the search has learned about which quiet moves cause cutoffs, so they can be tried early in
+// other positions. Kept between depths, since a good move at one depth is often good at the next.
+#[derive(Clone, Debug, Default)]
+struct History {
+    // Up to two quiet moves per ply, as (from, to), that caused a cutoff there, most recent first.
+    killers: Vec<[Option<(Piece, Piece)>; 2]>,
+    // How often each quiet move, by piece and destination, caused a cutoff, weighted towards
+    // cutoffs far from the leaves.
+    scores: HashMap<(u8, u8, u8), u32>,
+}
+
+// How an analysis is going, reported while it searches, between the completed depths that update
+// AnalysisInfo. There's no hash table, so unlike UCI engines it has no hashfull.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchInfo {
+    // The deepest completed depth.
+    pub depth: u32,
+    // The furthest from the root any line has been searched, with extensions and quiescence search.
+    pub seldepth: u32,
+    // Positions searched, including by quiescence search.
+    pub nodes: u64,
+    // Seconds spent searching.
+    pub elapsed: f64,
+    // The best line found so far, which may be from the depth being searched.
+    pub pv: Line,
+}
+
+impl SearchInfo {
+    // Nodes per second.
+    pub fn nps(&self) -> u64 {
+        if self.elapsed > 0.0 {
+            (self.nodes as f64 / self.elapsed) as u64
+        } else {
+            0
+        }
+    }
+}
+
+// A sequence of moves, alternating between the players.
+pub type Line = Vec<(Piece, Move)>;
+
+// The result of searching a position to some depth.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnalysisInfo {
+    pub depth: u32,
+    // The best lines, best first. Empty if the game is over.
+    pub lines: Vec<PvLine>,
+    // From white's point of view, in centipawns. Same as the first line's score, if any.
+    pub score: i32,
+}
+
+// A principal variation: the best line for both sides after one of the root moves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvLine {
+    // From white's point of view, in centipawns. See mate_in.
+    pub score: i32,
+    // Starts with the root move.
+    pub moves: Line,
+}
+
+// An iterative deepening search of one position that can be paused between root moves and
+// resumed later, so it can be spread over many frames or stopped at any time.
+pub struct Analysis {
+    pp: PiecePlacements,
+    gd: GameData,
+    // Root moves, best first as of the last completed depth. Empty until the first step.
+    moves: Vec<(Piece, Move)>,
+    depth: u32,
+    // The next root move to search at this depth.
+    next: usize,
+    // How many of the best root moves to find exact scores and lines for.
+    multi_pv: usize,
+    // Stop after this depth. At most MAX_DEPTH.
+    pub max_depth: u32,
+    scored: Vec<(i32, (Piece, Move), Line)>,
+    // The result of the deepest completed search.
+    pub info: Option<AnalysisInfo>,
+    // The strongest level, unless it's searching for an engine's move.
+    config: EngineConfig,
+    history: History,
+    // Totals for all the steps so far, see SearchInfo.
+    nodes: u64,
+    seldepth: u32,
+    elapsed: f64,
+}
+
+// A call to best_move spread over many calls to step, so the engine can think a little each frame
+// instead of freezing the board until it has moved.
+pub struct Thinking {
+    analysis: Analysis,
+    // Play anything but the best move found at a shallow depth, decided up front like best_move.
+    blunder: bool,
+    // Stop deepening the search after this time (see date::now).
+    deadline: f64,
+    // The engine's time limit, for ponder_hit.
+    time_limit: f64,
+}
+
+impl Engine {
+    pub fn new(strength: u8) -> Self {
+        Self {
+            config: EngineConfig::level(strength),
+        }
+    }
+
+    // Returns the best move for the player to move, or None if they have no moves. Searches
+    // deeper and deeper until the configured depth or time limit is reached.
+    pub fn best_move(
+        &self,
+        rules: &Rules,
+        pp: &PiecePlacements,
+        gd: GameData,
+    ) -> Option<(Piece, Move)> {
+        let mut moves = rules.legal_moves(gd.player_to_move(), pp, gd);
+        if moves.is_empty() {
+            return None;
+        }
+        order_moves(rules, pp, &mut moves, self.config.depth);
+        if moves.len() > 1 && rand::gen_range(0.0, 1.0) < self.config.blunder_chance {
+            // Anything but the best move found at a shallow depth.
+            self.search(rules, pp, gd, &mut moves, 1);
+            let i = rand::gen_range(1, moves.len());
+            return Some(moves[i]);
+        }
+        self.search(rules, pp, gd, &mut moves, self.config.depth);
+        Some(moves[0])
+    }
+
+    // Starts looking for the best move for the player to move, like best_move, but only searches
+    // when the returned Thinking is stepped.
+    pub fn think(&self, pp: PiecePlacements, gd: GameData) -> Thinking {
+        let blunder = rand::gen_range(0.0, 1.0) < self.config.blunder_chance;
+        let mut analysis = Analysis::new(pp, gd, 1);
+        analysis.config = self.config;
+        analysis.max_depth = if blunder { 1 } else { self.config.depth.max(1) };
+        Thinking {
+            analysis,
+            blunder,
+            deadline: date::now() + self.config.time_limit,
+            time_limit: self.config.time_limit,
+        }
+    }
+
+    // Like think, for the position the engine expects after the opponent's reply, so it can search
+    // on the opponent's time (see Thinking::ponder). Its time limit only starts at ponder_hit.
+    pub fn ponder(&self, pp: PiecePlacements, gd: GameData) -> Thinking {
+        Thinking {
+            deadline: f64::INFINITY,
+            ..self.think(pp, gd)
+        }
+    }
+
+    // Every move for the player to move with its exact score at the configured depth, best first.
+    // Slower than best_move, which only needs to know which move is best, but tells how much
+    // worse the others are.
+    pub fn score_moves(
+        &self,
+        rules: &Rules,
+        pp: &PiecePlacements,
+        gd: GameData,
+    ) -> Vec<(i32, (Piece, Move))> {
+        let mut moves = rules.legal_moves(gd.player_to_move(), pp, gd);
+        order_moves(rules, pp, &mut moves, self.config.depth);
+        let deadline = date::now() + self.config.time_limit;
+        let mut search = Search::new(rules, gd, &self.config, deadline, None);
+        let mut line = Vec::new();
+        let (alpha, beta) = (-MATE_SCORE - 1, MATE_SCORE + 1);
+        let depth = self.config.depth.max(1);
+        let mut scored: Vec<_> = moves
+            .into_iter()
+            .map(|(p, m)| {
+                let score = search.search_move(pp, p, m, depth, 1, alpha, beta, &mut line);
+                (score, (p, m))
+            })
+            .collect();
+        scored.sort_by_key(|&(score, _)| -score);
+        scored
+    }
+
+    // Sorts moves, best first, by iteratively deepening up to depth. If time runs out, the order
+    // from the last completed depth is kept.
+    fn search(
+        &self,
+        rules: &Rules,
+        pp: &PiecePlacements,
+        gd: GameData,
+        moves: &mut Vec<(Piece, Move)>,
+        depth: u32,
+    ) {
+        let deadline = date::now() + self.config.time_limit;
+        let mut search = Search::new(rules, gd, &self.config, deadline, None);
+        let mut line = Vec::new();
+        for d in 1..=depth {
+            let mut scored = Vec::with_capacity(moves.len());
+            let mut alpha = -MATE_SCORE - 1;
+            for &(p, m) in moves.iter() {
+                let score = search.search_move(pp, p, m, d, 1, alpha, MATE_SCORE + 1, &mut line);
+                alpha = alpha.max(score);
+                scored.push((score, (p, m)));
+            }
+            if search.timed_out {
+                break;
+            }
+            // Stable, so equally good moves keep the order from the previous depth.
+            scored.sort_by_key(|&(score, _)| -score);
+            *moves = scored.into_iter().map(|(_, m)| m).collect();
+        }
+    }
+}
+
+impl Analysis {
+    // Deeper searches take too long to be useful.
+    pub const MAX_DEPTH: u32 = 8;
+
+    // multi_pv is the number of lines to report, at least 1.
+    pub fn new(pp: PiecePlacements, gd: GameData, multi_pv: usize) -> Self {
+        Self {
+            pp,
+            gd,
+            moves: Vec::new(),
+            depth: 1,
+            next: 0,
+            multi_pv: multi_pv.max(1),
+            max_depth: Self::MAX_DEPTH,
+            scored: Vec::new(),
+            info: None,
+            config: EngineConfig::level(MAX_STRENGTH),
+            history: History::default(),
+            nodes: 0,
+            seldepth: 0,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.depth > self.max_depth.min(Self::MAX_DEPTH)
+    }
+
+    // Searches root moves until the time until (see date::now) has passed or stop is set, but
+    // always at least one. A root move that's interrupted is searched again on the next call.
+    // Returns true if a depth was completed, i.e. info changed.
+    pub fn step(&mut self, rules: &Rules, until: f64, stop: Option<&AtomicBool>) -> bool {
+        if self.done() {
+            return false;
+        }
+        let white = self.gd.player_to_move() == 0;
+        let pov = if white { 1 } else { -1 };
+        if self.moves.is_empty() {
+            self.moves = rules.legal_moves(self.gd.player_to_move(), &self.pp, self.gd);
+            order_moves(rules, &self.pp, &mut self.moves, Self::MAX_DEPTH);
+            if self.moves.is_empty() {
+                // Nothing to search, the game is over.
+                let score = if rules.lost_without_moves(white, &self.pp, self.gd) {
+                    -MATE_SCORE
+                } else {
+                    0
+                };
+                self.info = Some(AnalysisInfo {
+                    depth: 0,
+                    lines: Vec::new(),
+                    score: score * pov,
+                });
+                self.depth = u32::MAX;
+                return true;
+            }
+        }
+        let start = date::now();
+        let mut search = Search::new(rules, self.gd, &self.config, f64::INFINITY, stop);
+        search.history = std::mem::take(&mut self.history);
+        let completed = self.search_moves(&mut search, until);
+        self.history = search.history;
+        self.nodes += search.nodes;
+        self.seldepth = self.seldepth.max(search.seldepth);
+        self.elapsed += date::now() - start;
+        if !completed {
+            return false;
+        }
+        // Stable, so equally good moves keep the order from the previous depth.
+        self.scored.sort_by_key(|&(score, _, _)| -score);
+        let lines: Vec<_> = self
+            .scored
+            .iter()
+            .take(self.multi_pv)
+            .map(|(score, m, line)| PvLine {
+                score: score * pov,
+                moves: std::iter::once(*m).chain(line.iter().copied()).collect(),
+            })
+            .collect();
+        self.info = Some(AnalysisInfo {
+            depth: self.depth,
+            score: lines[0].score,
+            lines,
+        });
+        self.moves = self.scored.drain(..).map(|(_, m, _)| m).collect();
+        self.depth += 1;
+        self.next = 0;
+        true
+    }
+
+    pub fn search_info(&self) -> SearchInfo {
+        // Of the root moves searched at this depth, the best has an exact score (see alpha).
+        let searching = self
+            .scored
+            .iter()
+            .min_by_key(|&&(score, _, _)| -score)
+            .map(|(_, m, line)| std::iter::once(*m).chain(line.iter().copied()).collect());
+        let info = self.info.as_ref();
+        let completed = info.and_then(|i| i.lines.first()).map(|l| l.moves.clone());
+        SearchInfo {
+            depth: info.map_or(0, |i| i.depth),
+            seldepth: self.seldepth,
+            nodes: self.nodes,
+            elapsed: self.elapsed,
+            pv: searching.or(completed).unwrap_or_default(),
+        }
+    }
+
+    // The best root move as of the last completed depth, or the first to be searched if no depth
+    // was. None before the first step, or if the game is over.
+    pub fn best_move(&self) -> Option<(Piece, Move)> {
+        self.moves.first().copied()
+    }
+
+    // Searches the rest of the root moves at this depth, until the time until has passed, but
+    // always at least one. Returns whether all of them were searched.
+    fn search_moves(&mut self, search: &mut Search, until: f64) -> bool {
+        let mut searched = 0;
+        while self.next < self.moves.len() {
+            if searched > 0 && date::now() > until {
+                return false;
+            }
+            searched += 1;
+            let (p, m) = self.moves[self.next];
+            let mut line = Vec::new();
+            let score = search.search_move(
+                &self.pp,
+                p,
+                m,
+                self.depth,
+                1,
+                self.alpha(),
+                MATE_SCORE + 1,
+                &mut line,
+            );
+            if search.timed_out {
+                return false;
+            }
+            self.scored.push((score, (p, m), line));
+            self.next += 1;
+        }
+        true
+    }
+
+    // Only moves that beat the multi_pv-th best score so far need an exact score. Any others can
+    // be cut off early.
+    fn alpha(&self) -> i32 {
+        if self.scored.len() < self.multi_pv {
+            return -MATE_SCORE - 1;
+        }
+        let mut scores: Vec<_> = self.scored.iter().map(|&(score, _, _)| score).collect();
+        scores.sort_unstable_by_key(|&score| -score);
+        scores[self.multi_pv - 1]
+    }
+}
+
+impl Thinking {
+    // Searches until the time until has passed, or the engine's time limit, but at least one root
+    // move. Returns the move once it's decided, which is None if the player has no moves.
+    pub fn step(&mut self, rules: &Rules, until: f64) -> Option<Option<(Piece, Move)>> {
+        let a = &mut self.analysis;
+        // Even out of time, the root moves are needed to pick one.
+        if !a.done() && (a.moves.is_empty() || date::now() < self.deadline) {
+            a.step(rules, until.min(self.deadline), None);
+            if !a.done() && date::now() < self.deadline {
+                return None;
+            }
+        }
+        // Best first as of the last completed depth, or in search order if none was.
+        let moves = &a.moves;
+        if self.blunder && moves.len() > 1 {
+            return Some(Some(moves[rand::gen_range(1, moves.len())]));
+        }
+        Some(moves.first().copied())
+    }
+
+    pub fn is_thinking_about(&self, pp: &PiecePlacements, gd: GameData) -> bool {
+        (&self.analysis.pp, self.analysis.gd) == (pp, gd)
+    }
+
+    // Searches a pondered position until the time until has passed, but at least one root move.
+    // Unlike step, it never decides on a move, since the opponent may not play the expected reply.
+    // Returns whether there's more to search.
+    pub fn ponder(&mut self, rules: &Rules, until: f64) -> bool {
+        let a = &mut self.analysis;
+        if !a.done() {
+            a.step(rules, until, None);
+        }
+        !a.done()
+    }
+
+    // The opponent played the expected reply, so the search so far counts, and the time limit
+    // starts now.
+    pub fn ponder_hit(&mut self) {
+        self.deadline = date::now() + self.time_limit;
+    }
+
+    // The opponent's best reply to the move played, if the search got that far. None if played
+    // isn't the best move found, e.g. when blundering.
+    pub fn expected_reply(&self, played: (Piece, Move)) -> Option<(Piece, Move)> {
+        let line = &self.analysis.info.as_ref()?.lines.first()?.moves;
+        if line.first() != Some(&played) {
+            return None;
+        }
+        line.get(1).copied()
+    }
+}
+
+impl<'r, 'a> Search<'r, 'a> {
+    fn new(
+        rules: &'r Rules<'a>,
+        gd: GameData,
+        config: &EngineConfig,
+        deadline: f64,
+        stop: Option<&'r AtomicBool>,
+    ) -> Self {
+        Self {
+            rules,
+            player: gd.player_to_move(),
+            contempt: config.contempt,
+            quiescence_depth: config.quiescence_depth,
+            check_extensions: config.check_extensions,
+            null_move: config.null_move,
+            late_move_reductions: config.late_move_reductions,
+            pst: config.pst,
+            #[cfg(feature = "nnue")]
+            nnue: crate::nnue::Evaluator::new(crate::nnue::network()),
+            extended: 0,
+            passed_at: None,
+            deadline,
+            stop,
+            timed_out: false,
+            history: History::default(),
+            nodes: 0,
+            seldepth: 0,
+        }
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        if self.timed_out
+            || date::now() > self.deadline
+            || self.stop.is_some_and(|s| s.load(Ordering::Relaxed))
+        {
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+
+    // Makes the move and searches the position after it to depth - 1, or depth if the move gives
+    // check and the line has check extensions left. The score and the window are from the point
+    // of view of the player making the move, who may also be the one to move after it (see
+    // GD_DOUBLE_MOVE).
+    #[allow(clippy::too_many_arguments)]
+    fn search_move(
+        &mut self,
+        pp: &PiecePlacements,
+        p: Piece,
+        m: Move,
+        depth: u32,
+        ply: u32,
+        alpha: i32,
+        beta: i32,
+        pv: &mut Line,
+    ) -> i32 {
+        let mover = m.game_data.player_to_move();
+        let (pp, gd) = (self.make_move(p, m, pp), next(m));
+        let again = gd.player_to_move() == mover;
+        let (alpha, beta) = if again {
+            (alpha, beta)
+        } else {
+            (-beta, -alpha)
+        };
+        let extend = self.extended < self.check_extensions
+            && Rules::in_check(gd.player_to_move() == 0, &pp, gd);
+        let score = if extend {
+            self.extended += 1;
+            let score = self.negamax(&pp, gd, depth, ply, alpha, beta, pv);
+            self.extended -= 1;
+            score
+        } else {
+            self.negamax(&pp, gd, depth - 1, ply, alpha, beta, pv)
+        };
+        #[cfg(feature = "nnue")]
+        self.nnue.pop();
+        if again {
+            score
+        } else {
+            -score
+        }
+    }
+
+    // Returns the score of the position from the point of view of the player to move, and sets pv
+    // to the best line found. ply is the distance from the root.
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        pp: &PiecePlacements,
+        gd: GameData,
+        depth: u32,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Line,
+    ) -> i32 {
+        pv.clear();
+        if self.out_of_time() {
+            return 0;
+        }
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
+        if depth == 0 {
+            return self.quiesce(pp, gd, self.quiescence_depth, ply, alpha, beta, pv);
+        }
+        let mut moves = self.rules.legal_moves(gd.player_to_move(), pp, gd);
+        if moves.is_empty() {
+            let white = gd.player_to_move() == 0;
+            return if self.rules.lost_without_moves(white, pp, gd) {
+                // Prefer quicker mates.
+                -MATE_SCORE + ply as i32
+            } else {
+                self.draw_score(gd)
+            };
+        }
+        let in_check = Rules::in_check(gd.player_to_move() == 0, pp, gd);
+        if !in_check && self.null_move_cutoff(pp, gd, depth, ply, beta) {
+            return beta;
+        }
+        self.order_moves(pp, &mut moves, depth, ply);
+        let mut line = Vec::new();
+        for (i, (p, m)) in moves.into_iter().enumerate() {
+            // Late quiet moves rarely turn out best, so they're searched a ply less deep first, with
+            // a null window, and again at full depth only if they might be better than alpha after
+            // all. Not in check, where every move matters.
+            let reduce = self.late_move_reductions
+                && !in_check
+                && i >= LMR_MIN_MOVES
+                && depth >= LMR_MIN_DEPTH
+                && !m.is_capture()
+                && m.dst.name == p.name;
+            let mut score = alpha + 1;
+            if reduce {
+                score = self.search_move(pp, p, m, depth - 1, ply + 1, alpha, alpha + 1, &mut line);
+            }
+            if score > alpha {
+                score = self.search_move(pp, p, m, depth, ply + 1, alpha, beta, &mut line);
+            }
+            if score >= beta {
+                if !m.is_capture() {
+                    self.history.add_cutoff(p, m, depth, ply);
+                }
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push((p, m));
+                pv.extend_from_slice(&line);
+            }
+        }
+        alpha
+    }
+
+    // Null-move pruning: if the player to move would still score at least beta after passing, at a
+    // reduced depth, a real move would most likely do even better, so the position is cut off
+    // without searching its moves. Passing is only safe to assume worse than moving when the
+    // player isn't in check, moving isn't what loses (as in draughts, or king and pawn endings
+    // with zugzwang), the other player moves next, and there's no mate around the window.
+    fn null_move_cutoff(
+        &mut self,
+        pp: &PiecePlacements,
+        gd: GameData,
+        depth: u32,
+        ply: u32,
+        beta: i32,
+    ) -> bool {
+        let (nobody, pass) = null_move(gd);
+        if !self.null_move
+            || depth < NULL_MOVE_MIN_DEPTH
+            || self.passed_at.is_some_and(|p| p + 1 == ply)
+            || beta.abs() >= MATE_SCORE - 1000
+            || self.rules.no_moves_loses
+            || next(pass).player_to_move() == gd.player_to_move()
+            || !has_pieces(pp, gd.player_to_move() == 0)
+        {
+            return false;
+        }
+        let outer = self.passed_at.replace(ply);
+        let mut line = Vec::new();
+        let depth = depth - NULL_MOVE_REDUCTION;
+        let score = self.search_move(pp, nobody, pass, depth, ply + 1, beta - 1, beta, &mut line);
+        self.passed_at = outer;
+        score >= beta
+    }
+
+    // Searches only captures and promotions until the position is quiet, or depth runs out. The
+    // player to move can always "stand pat", i.e. take the static evaluation instead of capturing,
+    // since they aren't forced to capture.
+    #[allow(clippy::too_many_arguments)]
+    fn quiesce(
+        &mut self,
+        pp: &PiecePlacements,
+        gd: GameData,
+        depth: u32,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Line,
+    ) -> i32 {
+        pv.clear();
+        if self.out_of_time() {
+            return 0;
+        }
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
+        let stand_pat = self.evaluate(pp, gd);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+        if depth == 0 {
+            return alpha;
+        }
+        let mut moves: Line = self
+            .rules
+            .legal_moves(gd.player_to_move(), pp, gd)
+            .into_iter()
+            .filter(|&(p, m)| m.is_capture() || m.dst.name != p.name)
+            .collect();
+        order_moves(self.rules, pp, &mut moves, 0);
+        let mut line = Vec::new();
+        let mover = gd.player_to_move();
+        for (p, m) in moves {
+            let (pp, gd) = (self.make_move(p, m, pp), next(m));
+            let score = if gd.player_to_move() == mover {
+                self.quiesce(&pp, gd, depth - 1, ply + 1, alpha, beta, &mut line)
+            } else {
+                -self.quiesce(&pp, gd, depth - 1, ply + 1, -beta, -alpha, &mut line)
+            };
+            #[cfg(feature = "nnue")]
+            self.nnue.pop();
+            if score >= beta {
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push((p, m));
+                pv.extend_from_slice(&line);
+            }
+        }
+        alpha
+    }
+
+    // Like order_moves, but quiet moves that caused cutoffs elsewhere go before the other quiet
+    // moves: killers, which did at the same ply, first, then the rest by their history.
+    fn order_moves(&self, pp: &PiecePlacements, moves: &mut Line, depth: u32, ply: u32) {
+        let killers = self.history.killers.get(ply as usize).copied();
+        moves.sort_by_cached_key(|&(p, m)| match capture_gain(self.rules, pp, p, m, depth) {
+            Some(gain) if gain >= 0 => (0, -gain),
+            Some(gain) => (3, -gain),
+            None => match killers.and_then(|k| k.iter().position(|&k| k == Some((p, m.dst)))) {
+                Some(i) => (1, i as i32),
+                None => (2, -(self.history.score(p, m) as i32)),
+            },
+        });
+    }
+
+    // The placements after the move. With the nnue feature, the accumulators are updated for it too,
+    // so pop them when done with the position.
+    fn make_move(&mut self, p: Piece, m: Move, pp: &PiecePlacements) -> PiecePlacements {
+        let after = after(p, m, pp);
+        #[cfg(feature = "nnue")]
+        self.nnue.push(pp, &after);
+        after
+    }
+
+    // The neural network's evaluation, if there's one for the position, or else the classical one.
+    fn evaluate(&self, pp: &PiecePlacements, gd: GameData) -> i32 {
+        #[cfg(feature = "nnue")]
+        if let Some(score) = self.nnue.evaluate(pp, gd.player_to_move()) {
+            return score;
+        }
+        evaluate(pp, gd, self.pst)
+    }
+
+    fn draw_score(&self, gd: GameData) -> i32 {
+        if gd.player_to_move() == self.player {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+}
+
+impl History {
+    fn add_cutoff(&mut self, p: Piece, m: Move, depth: u32, ply: u32) {
+        let ply = ply as usize;
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some((p, m.dst)) {
+            *killers = [Some((p, m.dst)), killers[0]];
+        }
+        let score = self
+            .scores
+            .entry((p.name, m.dst.row, m.dst.col))
+            .or_default();
+        *score = score.saturating_add(depth * depth);
+    }
+
+    fn score(&self, p: Piece, m: Move) -> u32 {
+        let key = (p.name, m.dst.row, m.dst.col);
+        self.scores.get(&key).copied().unwrap_or(0)
+    }
+}
+
+fn after(p: Piece, m: Move, pp: &PiecePlacements) -> PiecePlacements {
+    let mut pp = *pp;
+    Rules::make_move(p, m, &mut pp);
+    pp
+}
+
+// A pass by the player to move, for null-move pruning, whether or not the rules let them pass.
+// Nothing moves, so no piece makes it.
+fn null_move(gd: GameData) -> (Piece, Move) {
+    let nobody = Piece {
+        row: 0,
+        col: 0,
+        name: 0,
+    };
+    let pass = Move {
+        dst: nobody,
+        typ: MoveType::Pass,
+        game_data: GameData {
+            mask: gd.mask & !GD_EN_PASSANT,
+            ..gd
+        },
+    };
+    (nobody, pass)
+}
+
+// Whether the player has pieces besides pawns and their king, without which zugzwang is common.
+fn has_pieces(pp: &PiecePlacements, white: bool) -> bool {
+    pp.squares().any(|(_, _, n)| {
+        n != 0
+            && n != DUCK
+            && (n as char).is_ascii_uppercase() == white
+            && !matches!(n.to_ascii_lowercase(), b'p' | b'k')
+    })
+}
+
+fn next(m: Move) -> GameData {
+    GameData {
+        ply: m.game_data.ply + 1,
+        ..m.game_data
+    }
+}
+
+// Puts the moves most likely to be best first, so alpha-beta can cut off more of the rest:
+// captures that win material, then other moves, then captures that lose material.
+fn order_moves(rules: &Rules, pp: &PiecePlacements, moves: &mut Line, depth: u32) {
+    moves.sort_by_cached_key(|&(p, m)| match capture_gain(rules, pp, p, m, depth) {
+        Some(gain) if gain >= 0 => (0, -gain),
+        Some(gain) => (2, -gain),
+        None => (1, 0),
+    });
+}
+
+// How good a capture looks, or None if m isn't one. Near the leaves, where see would cost more
+// than it saves, captures are ordered by MVV-LVA instead: most valuable victim first, and of
+// captures of the same piece, the one with the least valuable attacker.
+fn capture_gain(rules: &Rules, pp: &PiecePlacements, p: Piece, m: Move, depth: u32) -> Option<i32> {
+    match m.typ {
+        MoveType::Capture { .. } if depth >= SEE_MIN_DEPTH => Some(see(rules, pp, p, m)),
+        MoveType::Capture { row, col } => {
+            Some(10 * piece_value(pp.get(row as usize, col as usize)) - piece_value(p.name))
+        }
+        // A chain of jumps, as in draughts, which see doesn't follow: what it takes, all told.
+        MoveType::Captures { squares } => {
+            let taken: i32 = bit_squares(squares)
+                .map(|(r, c)| piece_value(pp.get(r, c)))
+                .sum();
+            Some(if depth >= SEE_MIN_DEPTH {
+                taken
+            } else {
+                10 * taken - piece_value(p.name)
+            })
+        }
+        _ => None,
+    }
+}
+
+// Static exchange evaluation: the material won, or lost if negative, by capturing with p, if both
+// sides then keep recapturing on the same square with their least valuable piece, and either side
+// can stop when continuing would lose material. Returns 0 if m isn't a capture.
+pub fn see(rules: &Rules, pp: &PiecePlacements, p: Piece, m: Move) -> i32 {
+    let (row, col) = match m.typ {
+        MoveType::Capture { row, col } => (row as usize, col as usize),
+        _ => return 0,
+    };
+    let square = (m.dst.row, m.dst.col);
+    // gains[i] is what the side making the i-th capture gains, if the other side then stops.
+    let mut gains = vec![piece_value(pp.get(row, col))];
+    let mut on_square = m.dst.name;
+    let (mut pp, mut gd) = (after(p, m, pp), next(m));
+    while let Some((p, m)) = least_valuable_capture(rules, &pp, gd, square) {
+        gains.push(piece_value(on_square) - gains[gains.len() - 1]);
+        on_square = m.dst.name;
+        (pp, gd) = (after(p, m, &pp), next(m));
+    }
+    // Work back from the last capture. Each side only captures if it doesn't lose by it.
+    while gains.len() > 1 {
+        let gain = gains.pop().unwrap();
+        let prev = gains.last_mut().unwrap();
+        *prev = -(-*prev).max(gain);
+    }
+    gains[0]
+}
+
+// The capture onto square by the player to move with their least valuable piece. Kings go last,
+// and only get to capture if the square isn't defended, since the move has to be legal.
+fn least_valuable_capture(
+    rules: &Rules,
+    pp: &PiecePlacements,
+    gd: GameData,
+    square: (u8, u8),
+) -> Option<(Piece, Move)> {
+    rules
+        .legal_moves(gd.player_to_move(), pp, gd)
+        .into_iter()
+        .filter(|&(_, m)| {
+            matches!(m.typ, MoveType::Capture { .. }) && (m.dst.row, m.dst.col) == square
+        })
+        .min_by_key(|&(p, m)| {
+            let king = p.name.eq_ignore_ascii_case(&b'k');
+            // Promote to the most valuable piece.
+            (king, piece_value(p.name), -piece_value(m.dst.name))
+        })
+}
+
+// The number of moves until mate if score is a mate score. Negative if the player the score is
+// for gets mated.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score.abs() < MATE_SCORE - 1000 {
+        return None;
+    }
+    let moves = (MATE_SCORE - score.abs() + 1) / 2;
+    Some(if score > 0 { moves } else { -moves })
+}
+
+pub fn piece_value(name: u8) -> i32 {
+    match (name as char).to_ascii_lowercase() {
+        'p' => 100,
+        'n' => 320,
+        'b' => 330,
+        'r' => 500,
+        'q' => 900,
+        _ => 0,
+    }
+}
+
+// Material balance from the point of view of the player to move, plus the pieces' bonuses in pst,
+// if any, on an 8x8 board.
+pub fn evaluate(pp: &PiecePlacements, gd: GameData, pst: Option<&PieceSquareTables>) -> i32 {
+    let pst = pst.filter(|_| (pp.width(), pp.height()) == (8, 8));
+    let mut score = 0;
+    for (r, c, n) in pp.squares() {
+        if n == 0 {
+            continue;
+        }
+        let bonus = pst
+            .zip(PieceSquareTables::index(n, r, c))
+            .map_or(0, |(pst, (t, i))| pst.0[t][i]);
+        let v = piece_value(n) + bonus;
+        score += if (n as char).is_ascii_uppercase() {
+            v
+        } else {
+            -v
+        };
+    }
+    if gd.player_to_move() == 0 {
+        score
+    } else {
+        -score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::long_algebraic;
+
+    use super::*;
+
+    #[test]
+    fn test_takes_free_queen() {
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            ...q....
+            ........
+            ........
+            ........
+            ...RK...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let (p, m) = strongest(2).best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_eq!((p.row, p.col), (1, 4));
+        assert_eq!((m.dst.row, m.dst.col), (5, 4));
+
+        let mut blunderer = strongest(2);
+        blunderer.config.blunder_chance = 1.0;
+        let (_, m) = blunderer.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_ne!((m.dst.row, m.dst.col), (5, 4));
+    }
+
+    #[test]
+    fn test_thinking() {
+        let pp = string_board_to_placements(
+            "
+            ......k.
+            .....ppp
+            ........
+            ........
+            ........
+            ........
+            ........
+            R.....K.
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let engine = strongest(2);
+        let mut thinking = engine.think(pp, gd);
+        assert!(thinking.is_thinking_about(&pp, gd));
+        // One root move per step.
+        let mut steps = 0;
+        let m = loop {
+            steps += 1;
+            if let Some(m) = thinking.step(&Rules::defaults(), 0.0) {
+                break m;
+            }
+        };
+        assert!(steps > 1);
+        assert_eq!(m, engine.best_move(&Rules::defaults(), &pp, gd));
+
+        let mut engine = strongest(2);
+        engine.config.time_limit = 0.0;
+        let m = engine.think(pp, gd).step(&Rules::defaults(), f64::INFINITY);
+        assert!(matches!(m, Some(Some(_))));
+
+        let mated = string_board_to_placements(
+            "
+            R.....k.
+            .....ppp
+            ........
+            ........
+            ........
+            ........
+            ........
+            ......K.
+        ",
+        );
+        let gd = GameData { ply: 2, mask: 0xf };
+        let m = engine.think(mated, gd).step(&Rules::defaults(), 0.0);
+        assert_eq!(m, Some(None));
+    }
+
+    #[test]
+    fn test_ponder() {
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            ...q....
+            ........
+            ........
+            ........
+            ...RK...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let rules = Rules::defaults();
+        let engine = strongest(3);
+        let mut thinking = engine.think(pp, gd);
+        let played = loop {
+            if let Some(m) = thinking.step(&rules, f64::INFINITY) {
+                break m.unwrap();
+            }
+        };
+        // After Rxd5, black's king is all that's left to move.
+        let (p, m) = thinking.expected_reply(played).unwrap();
+        assert_eq!(p.name, b'k');
+        assert_eq!(thinking.expected_reply((p, m)), None);
+
+        let (pp, gd) = (after(p, m, &after(played.0, played.1, &pp)), next(m));
+        let mut pondering = engine.ponder(pp, gd);
+        // The clock doesn't run until the opponent replies.
+        pondering.time_limit = 0.0;
+        while pondering.ponder(&rules, f64::INFINITY) {}
+        assert!(pondering.is_thinking_about(&pp, gd));
+        pondering.ponder_hit();
+        // Already searched as deep as it goes, so the move is ready.
+        assert!(matches!(pondering.step(&rules, 0.0), Some(Some(_))));
+    }
+
+    #[test]
+    fn test_finds_mate_in_one() {
+        let pp = string_board_to_placements(
+            "
+            ......k.
+            .....ppp
+            ........
+            ........
+            ........
+            ........
+            ........
+            R.....K.
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let (_, m) = strongest(2).best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_eq!((m.dst.row, m.dst.col), (8, 1));
+    }
+
+    #[test]
+    fn test_no_moves_when_mated() {
+        let pp = string_board_to_placements(
+            "
+            R.....k.
+            .....ppp
+            ........
+            ........
+            ........
+            ........
+            ........
+            ......K.
+        ",
+        );
+        let gd = GameData { ply: 2, mask: 0xf };
+        assert!(strongest(1)
+            .best_move(&Rules::defaults(), &pp, gd)
+            .is_none());
+    }
+
+    #[test]
+    fn test_contempt() {
+        // Qc7 stalemates black.
+        let pp = string_board_to_placements(
+            "
+            k.......
+            ........
+            ........
+            ........
+            ........
+            ........
+            ........
+            ..Q....K
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let mut engine = strongest(2);
+        engine.config.contempt = -1000;
+        let (_, m) = engine.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_eq!((m.dst.row, m.dst.col), (7, 3));
+        engine.config.contempt = 1000;
+        let (_, m) = engine.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_ne!((m.dst.row, m.dst.col), (7, 3));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let pp = string_board_to_placements(
+            "
+            rnbqkbnr
+            pppppppp
+            ........
+            ........
+            ........
+            ........
+            PPPPPPPP
+            RNBQKBNR
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0 };
+        let mut engine = strongest(20);
+        engine.config.time_limit = 0.0;
+        // Even without time to finish a single depth, there's a move to play.
+        assert!(engine.best_move(&Rules::defaults(), &pp, gd).is_some());
+    }
+
+    #[test]
+    fn test_analysis() {
+        let pp = string_board_to_placements(
+            "
+            ......k.
+            .....ppp
+            ........
+            ........
+            ........
+            ........
+            ........
+            R.....K.
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let rules = Rules::defaults();
+        let mut analysis = Analysis::new(pp, gd, 1);
+        // Search one root move at a time.
+        while analysis.info.as_ref().is_none_or(|i| i.depth < 2) {
+            analysis.step(&rules, 0.0, None);
+        }
+        let info = analysis.info.unwrap();
+        assert_eq!(mate_in(info.score), Some(1));
+        let (p, m) = info.lines[0].moves[0];
+        assert_eq!((p.row, p.col, m.dst.row, m.dst.col), (1, 1, 8, 1));
+
+        // Already mated, and black to move.
+        let mut analysis = Analysis::new(after(p, m, &pp), next(m), 1);
+        assert!(analysis.step(&rules, f64::INFINITY, None));
+        assert!(analysis.done());
+        assert_eq!(analysis.info.unwrap().score, MATE_SCORE);
+    }
+
+    #[test]
+    fn test_multi_pv() {
+        // Taking the queen with either piece wins material, anything else doesn't.
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            ...q....
+            .....N..
+            ........
+            ........
+            ...RK...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let mut analysis = Analysis::new(pp, gd, 3);
+        while !analysis.step(&Rules::defaults(), f64::INFINITY, None) {}
+        while !analysis.step(&Rules::defaults(), f64::INFINITY, None) {}
+        let info = analysis.info.unwrap();
+        assert_eq!(info.depth, 2);
+        assert_eq!(info.lines.len(), 3);
+        let scores: Vec<_> = info.lines.iter().map(|l| l.score).collect();
+        assert_eq!(scores[..2], [500 + 320, 500 + 320]);
+        assert!(scores[2] < scores[1]);
+        for l in &info.lines {
+            assert!(l.moves.len() >= 2);
+        }
+        // Exact scores, not just bounds
+        let mut single = Analysis::new(pp, gd, 1);
+        while !single.step(&Rules::defaults(), f64::INFINITY, None) {}
+        while !single.step(&Rules::defaults(), f64::INFINITY, None) {}
+        assert_eq!(single.info.unwrap().score, scores[0]);
+    }
+
+    #[test]
+    fn test_see() {
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ..p.....
+            ...p.r..
+            .N......
+            ........
+            ........
+            ...Q....
+            ....K...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let rules = Rules::defaults();
+        let see_at = |sr, sc, dr, dc| {
+            let p = Piece {
+                row: sr,
+                col: sc,
+                name: pp.get(sr as usize, sc as usize),
+            };
+            let m = rules
+                .allowed_moves(p, &pp, gd)
+                .into_iter()
+                .find(|m| (m.dst.row, m.dst.col) == (dr, dc))
+                .unwrap();
+            see(&rules, &pp, p, m)
+        };
+        // Nxd6 cxd6, and Qxd6 would lose the queen to Rxd6.
+        assert_eq!(see_at(5, 2, 6, 4), 100 - 320);
+        // c7 isn't defended.
+        assert_eq!(see_at(5, 2, 7, 3), 100);
+        // Qxd6 cxd6 loses the queen for a pawn.
+        assert_eq!(see_at(2, 4, 6, 4), 100 - 900);
+    }
+
+    #[test]
+    fn test_move_ordering() {
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            ...q.p..
+            ........
+            ....N...
+            ........
+            ...RK...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let rules = Rules::defaults();
+        let mut search = Search::new(&rules, gd, &strongest(2).config, f64::INFINITY, None);
+        let moves = rules.legal_moves(0, &pp, gd);
+        let find = |name: &str| {
+            moves
+                .iter()
+                .copied()
+                .find(|&(p, m)| long_algebraic(p, m) == name)
+                .unwrap()
+        };
+        let (p, m) = find("e1f1");
+        search.history.add_cutoff(p, m, 2, 1);
+        let (p, m) = find("e1f2");
+        search.history.add_cutoff(p, m, 1, 3);
+        let mut ordered = moves.clone();
+        search.order_moves(&pp, &mut ordered, 1, 1);
+        let names: Vec<_> = ordered.iter().map(|&(p, m)| long_algebraic(p, m)).collect();
+        // MVV-LVA: the queen, first with the knight, then the pawn, then the killer, then the quiet
+        // move that caused a cutoff at another ply.
+        assert_eq!(names[..5], ["e3d5", "d1d5", "e3f5", "e1f1", "e1f2"]);
+
+        let mut analysis = Analysis::new(pp, gd, 1);
+        analysis.max_depth = 3;
+        while !analysis.done() {
+            analysis.step(&rules, f64::INFINITY, None);
+        }
+        let search = analysis.search_info();
+        assert_eq!(search.depth, 3);
+        assert!(search.nodes > 0);
+        // Quiescence search looks past the captures on d5.
+        assert!(search.seldepth > 3);
+        assert_eq!(search.pv, analysis.info.unwrap().lines[0].moves);
+    }
+
+    #[test]
+    fn test_quiescence() {
+        // Qxd5 wins a pawn, until exd5.
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ....p...
+            ...p....
+            ........
+            ........
+            ........
+            ...QK...
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let mut engine = strongest(1);
+        engine.config.quiescence_depth = 0;
+        let (_, m) = engine.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_eq!((m.dst.row, m.dst.col), (5, 4));
+        engine.config.quiescence_depth = 2;
+        let (_, m) = engine.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_ne!((m.dst.row, m.dst.col), (5, 4));
+    }
+
+    #[test]
+    fn test_draughts_quiescence() {
+        // After d4-e5, f6 jumps it, which quiescence search sees and standing pat doesn't.
+        let pp = string_board_to_placements(
+            "
+            .......p
+            ........
+            .....p..
+            ....P...
+            ........
+            ........
+            ........
+            ........
+        ",
+        );
+        let gd = GameData { ply: 2, mask: 0 };
+        let rules = Rules::draughts();
+        let config = strongest(1).config;
+        let mut search = Search::new(&rules, gd, &config, f64::INFINITY, None);
+        let mut pv = Vec::new();
+        let score = search.quiesce(&pp, gd, 2, 0, -MATE_SCORE, MATE_SCORE, &mut pv);
+        assert!(score > search.evaluate(&pp, gd));
+        assert!(matches!(pv[..], [(_, m)] if m.is_capture()));
+
+        // A double jump is worth both men.
+        let pp = string_board_to_placements(
+            "
+            ........
+            ........
+            ........
+            ....p...
+            ........
+            ..p.....
+            .P......
+            ........
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0 };
+        let (p, m) = rules.legal_moves(0, &pp, gd)[0];
+        assert!(matches!(m.typ, MoveType::Captures { .. }));
+        assert_eq!(capture_gain(&rules, &pp, p, m, SEE_MIN_DEPTH), Some(200));
+        assert_eq!(capture_gain(&rules, &pp, p, m, 0), Some(1900));
+    }
+
+    #[test]
+    fn test_check_extension() {
+        // Re1+ skewers the king and queen, but the queen is only taken 3 plies in.
+        let pp = string_board_to_placements(
+            "
+            ....q...
+            ........
+            ........
+            ....k...
+            ........
+            ........
+            ........
+            R.....K.
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let mut engine = strongest(2);
+        engine.config.quiescence_depth = 0;
+        engine.config.check_extensions = 1;
+        let (_, m) = engine.best_move(&Rules::defaults(), &pp, gd).unwrap();
+        assert_eq!((m.dst.row, m.dst.col), (1, 5));
+    }
+
+    #[test]
+    fn test_pruning() {
+        // Nxd5 wins the queen, which pruning finds too, searching fewer positions.
+        let pp = string_board_to_placements(
+            "
+            ....k..r
+            pp...ppp
+            ..n.....
+            ...q....
+            ........
+            ....N...
+            PP...PPP
+            R...K..R
+        ",
+        );
+        let gd = GameData { ply: 1, mask: 0xf };
+        let rules = Rules::defaults();
+        let search = |null_move, late_move_reductions| {
+            let mut analysis = Analysis::new(pp, gd, 1);
+            analysis.config = EngineConfig {
+                null_move,
+                late_move_reductions,
+                ..analysis.config
+            };
+            analysis.max_depth = 4;
+            while !analysis.done() {
+                analysis.step(&rules, f64::INFINITY, None);
+            }
+            let (p, m) = analysis.best_move().unwrap();
+            (long_algebraic(p, m), analysis.nodes)
+        };
+        let (full, full_nodes) = search(false, false);
+        let (pruned, pruned_nodes) = search(true, true);
+        assert_eq!((full.as_str(), pruned.as_str()), ("e3d5", "e3d5"));
+        assert!(
+            pruned_nodes < full_nodes,
+            "{} >= {}",
+            pruned_nodes,
+            full_nodes
+        );
+    }
+
+    #[test]
+    fn test_double_move_mate() {
+        // The bishop is in the rook's way, but with two moves a turn white clears it and mates.
+        let pp = string_board_to_placements(
+            "
+            ......k.
+            .....ppp
+            ........
+            ........
+            .B......
+            ........
+            ........
+            .R....K.
+        ",
+        );
+        let engine = strongest(3);
+        let single = GameData { ply: 1, mask: 0 };
+        assert!(engine.score_moves(&Rules::defaults(), &pp, single)[0].0 < MATE_SCORE - 1000);
+        let double = GameData {
+            ply: 4,
+            mask: GD_DOUBLE_MOVE,
+        };
+        assert!(engine.score_moves(&Rules::defaults(), &pp, double)[0].0 > MATE_SCORE - 1000);
+    }
+
+    fn strongest(depth: u32) -> Engine {
+        Engine {
+            config: EngineConfig {
+                depth,
+                time_limit: 60.0,
+                blunder_chance: 0.0,
+                contempt: 0,
+                quiescence_depth: 8,
+                check_extensions: 2,
+                null_move: true,
+                late_move_reductions: true,
+                pst: None,
+            },
+        }
+    }
+}
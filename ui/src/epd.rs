@@ -0,0 +1,162 @@
+// Runs an EPD test suite, e.g. Win at Chess, to see whether a change made the engine stronger: run
+// the desktop binary with --epd FILE (see USAGE). Each position says which moves are best (bm) or
+// to be avoided (am), and the engine solves it if the move it finds within the time limit is one
+// of the best moves and none of the avoided ones. Each result is written to stdout, followed by
+// how many were solved.
+
+use std::fs;
+
+use chess_ui::notation::{long_algebraic, parse_fen, parse_san};
+
+use crate::{batch::analyze, engine::Analysis, prelude::*};
+
+const USAGE: &str = "\
+Usage: chess-ui --epd FILE [options]
+
+Options:
+  --time SECONDS  How long the engine gets for each position (default 1)
+  --depth N       Search at most this deep, at most 8 (default 8)";
+
+// A line of an EPD file: a position without the move counters, followed by operations.
+#[derive(Debug, Default, PartialEq)]
+struct EpdPosition {
+    // The four fields of the position, which parse_fen reads.
+    fen: String,
+    id: Option<String>,
+    // In SAN, as written.
+    best: Vec<String>,
+    avoid: Vec<String>,
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip_while(|a| a != "--epd").skip(1);
+    let mut path = None;
+    let mut time = 1.0;
+    let mut depth = Analysis::MAX_DEPTH;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--time" => match args.next().and_then(|t| t.parse().ok()) {
+                Some(t) if t > 0.0 => time = t,
+                _ => usage(),
+            },
+            "--depth" => match args.next().and_then(|d| d.parse().ok()) {
+                Some(d) if (1..=Analysis::MAX_DEPTH).contains(&d) => depth = d,
+                _ => usage(),
+            },
+            _ if path.is_none() => path = Some(a),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let input = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let rules = Rules::defaults();
+    let (mut solved, mut total) = (0, 0);
+    for (i, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let name = format!("line {}", i + 1);
+        let result = parse_epd(line).and_then(|p| {
+            let solved = solves(&rules, &p, depth, time)?;
+            Ok((p.id.unwrap_or(name.clone()), solved))
+        });
+        match result {
+            Ok((id, (found, ok))) => {
+                total += 1;
+                if ok {
+                    solved += 1;
+                }
+                println!("{}: {} {}", id, found, if ok { "ok" } else { "wrong" });
+            }
+            Err(e) => eprintln!("Skipping {}: {}", name, e),
+        }
+    }
+    println!(
+        "Solved {}/{} at {}s a position, searching at most {} deep",
+        solved, total, time, depth
+    );
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn parse_epd(line: &str) -> Result<EpdPosition, String> {
+    let fields: Vec<&str> = line.splitn(5, char::is_whitespace).collect();
+    if fields.len() < 4 {
+        return Err(format!("Expected at least 4 fields in EPD: {}", line));
+    }
+    let mut position = EpdPosition {
+        fen: fields[..4].join(" "),
+        ..Default::default()
+    };
+    // Operations end with semicolons, which can't be in quoted operands.
+    for op in fields.get(4).unwrap_or(&"").split(';') {
+        let mut words = op.split_whitespace();
+        let Some(opcode) = words.next() else {
+            continue;
+        };
+        let operands = words.map(|w| w.trim_matches('"').to_string());
+        match opcode {
+            "bm" => position.best.extend(operands),
+            "am" => position.avoid.extend(operands),
+            "id" => position.id = Some(operands.collect::<Vec<_>>().join(" ")),
+            _ => {}
+        }
+    }
+    if position.best.is_empty() && position.avoid.is_empty() {
+        return Err("No bm or am operation".to_string());
+    }
+    Ok(position)
+}
+
+// The move the engine found in SAN, and whether it's right.
+fn solves(rules: &Rules, p: &EpdPosition, depth: u32, time: f64) -> Result<(String, bool), String> {
+    let (pp, gd) = parse_fen(&p.fen)?;
+    let to_uci = |moves: &[String]| {
+        moves
+            .iter()
+            .map(|s| {
+                parse_san(rules, &pp, gd, s)
+                    .map(|(p, m)| long_algebraic(p, m))
+                    .ok_or_else(|| format!("{} isn't a legal move", s))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let (best, avoid) = (to_uci(&p.best)?, to_uci(&p.avoid)?);
+    let (san, uci) = analyze(rules, &p.fen, depth, time)?
+        .best
+        .ok_or("The game is over")?;
+    let ok = (best.is_empty() || best.contains(&uci)) && !avoid.contains(&uci);
+    Ok((san, ok))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epd() {
+        let rules = Rules::defaults();
+        let p = parse_epd("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; id \"back rank\";").unwrap();
+        assert_eq!(p.fen, "6k1/5ppp/8/8/8/8/8/R5K1 w - -");
+        assert_eq!(p.best, ["Ra8#"]);
+        assert_eq!(p.id.as_deref(), Some("back rank"));
+        assert_eq!(solves(&rules, &p, 2, 10.0), Ok(("Ra8#".to_string(), true)));
+
+        // Playing a move to avoid fails, even if it mates.
+        let p = parse_epd("6k1/5ppp/8/8/8/8/8/R5K1 w - - am Ra8;").unwrap();
+        assert_eq!(solves(&rules, &p, 2, 10.0), Ok(("Ra8#".to_string(), false)));
+
+        assert!(parse_epd("6k1/5ppp/8/8/8/8/8/R5K1 w - - id \"x\";").is_err());
+        let p = parse_epd("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Qh8;").unwrap();
+        assert!(solves(&rules, &p, 2, 10.0).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+// What the game tells the page. Every call out to JS goes through one import, js_dispatch, with
+// the event's kind and up to three numbers, strings and bytes being a pointer and a length that
+// are only valid during the call. The page registers a handler for each event it cares about
+// with assets/js/events.js, which decodes the arguments. Events without a handler are dropped
+// and return 0, so a page that doesn't handle an event, e.g. one added later, keeps working. The
+// events' names and arguments are generated into assets/js/ffi.js and events.d.ts (see ffi.rs).
+
+// Some events are only sent in the browser.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn js_dispatch(kind: u32, a: u32, b: u32, c: u32) -> u32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event<'a> {
+    Log(&'a str),
+    // Our move, encoded for the server, and whether it's a binary frame.
+    Move(&'a [u8], bool),
+    // Asks for the color the player plays online, 0 for white and 1 for black.
+    PlayerColor,
+    // So the page can tell the player it's their turn, e.g. by flashing the tab's title.
+    TurnStart(usize),
+    // As JSON: {"result", "reason"}, where result is "1-0", "0-1", "1/2-1/2", or "*" if the game
+    // was aborted, and reason why it ended, more precisely than the game's Termination tag (see
+    // get_game_metadata), e.g. "checkmate" rather than "normal".
+    GameEnd(&'a str),
+    // A move or the result in words, for screen readers (see announce.rs).
+    Announce(&'a str),
+    // The picture of the board asked for with request_board_image, as a PNG.
+    BoardImage(&'a [u8]),
+    // The animation asked for with request_game_animation, as an animated PNG.
+    GameAnimation(&'a [u8]),
+    // Asks for the opening explorer's moves in the position with this key (see explorer.rs), to
+    // be handed back with set_explorer.
+    ExplorerQuery(&'a str),
+    // A move was made on the board, as JSON: {"uci", "san", "fen", "ply"}, where fen and ply are
+    // for the position after it.
+    Moved(&'a str),
+    // A message for the other player about the board both analyze on after the game (see
+    // protocol::encode_analysis).
+    AnalysisMove(&'a str),
+    // Asks how a JS plugin decorates the square at row, col, and the piece, as JSON (see
+    // decorations.rs).
+    DecorateSquare(usize, usize),
+    DecoratePiece(&'a str),
+    // A message for the player, also shown on the board's status line, e.g. why a pasted FEN
+    // wasn't loaded.
+    Status(&'a str),
+    // The player asked to export the game, from the game over screen, so the page saves it.
+    Export,
+    // A puzzle was solved (see puzzle_mode.rs), as JSON: {"id", "mistakes"}.
+    PuzzleSolved(&'a str),
+}
+
+// How an argument is passed to js_dispatch, and what the handler gets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    // A pointer and a length, decoded as UTF-8.
+    Str,
+    // A pointer and a length, copied into a Uint8Array.
+    Bytes,
+    // 0 or 1, as a boolean.
+    Bool,
+    // 0 or 1, as "white" or "black".
+    Color,
+    // A pointer and a length, decoded as UTF-8 and parsed as JSON.
+    Json,
+    // A number, as is.
+    Int,
+}
+
+pub struct EventSpec {
+    // The handler's name in JS.
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub args: &'static [(&'static str, Arg)],
+    // The handler returns a number, rather than nothing.
+    pub returns: bool,
+}
+
+// Indexed by the event's kind.
+pub const EVENTS: [EventSpec; 16] = [
+    EventSpec {
+        name: "log",
+        doc: "A line logged by the game. Without a handler, it goes to the console.",
+        args: &[("line", Arg::Str)],
+        returns: false,
+    },
+    EventSpec {
+        name: "move",
+        doc: "Our move for the server: a binary move frame, or a JSON message as UTF-8.",
+        args: &[("payload", Arg::Bytes), ("binary", Arg::Bool)],
+        returns: false,
+    },
+    EventSpec {
+        name: "player_color",
+        doc: "The color the player plays online: 0 for white, 1 for black.",
+        args: &[],
+        returns: true,
+    },
+    EventSpec {
+        name: "turn_start",
+        doc: "A player's turn started.",
+        args: &[("color", Arg::Color)],
+        returns: false,
+    },
+    EventSpec {
+        name: "game_end",
+        doc: "The game ended: { result, reason }, e.g. \"1-0\" by \"checkmate\", or \"*\" if aborted.",
+        args: &[("end", Arg::Json)],
+        returns: false,
+    },
+    EventSpec {
+        name: "announce",
+        doc: "A move or the result described for screen readers, e.g. \"White knight from g1 to f3\".",
+        args: &[("text", Arg::Str)],
+        returns: false,
+    },
+    EventSpec {
+        name: "board_image",
+        doc: "The picture of the board asked for with request_board_image, as a PNG.",
+        args: &[("png", Arg::Bytes)],
+        returns: false,
+    },
+    EventSpec {
+        name: "game_animation",
+        doc: "The animation of the game asked for with request_game_animation, as an animated PNG.",
+        args: &[("png", Arg::Bytes)],
+        returns: false,
+    },
+    EventSpec {
+        name: "explorer_query",
+        doc: "Asks for GET /api/explorer?hash=<hash>, whose JSON goes to set_explorer.",
+        args: &[("hash", Arg::Str)],
+        returns: false,
+    },
+    EventSpec {
+        name: "moved",
+        doc: "A move was made on the board: { uci, san, fen, ply }, with the FEN and ply after it.",
+        args: &[("move", Arg::Json)],
+        returns: false,
+    },
+    EventSpec {
+        name: "analysis_move",
+        doc: "A message for the other player about the board both analyze on after the game.",
+        args: &[("message", Arg::Str)],
+        returns: false,
+    },
+    EventSpec {
+        name: "decorate_square",
+        doc: "The color drawn over a square (rows and columns 1 to 8) as RGBA, 0xRRGGBBAA, or 0.",
+        args: &[("row", Arg::Int), ("col", Arg::Int)],
+        returns: true,
+    },
+    EventSpec {
+        name: "decorate_piece",
+        doc: "The tint of a piece, { row, col, name }, as RGBA, 0xRRGGBBAA, or 0.",
+        args: &[("piece", Arg::Json)],
+        returns: true,
+    },
+    EventSpec {
+        name: "status",
+        doc: "A message for the player, e.g. why a pasted FEN wasn't loaded, for a status bar.",
+        args: &[("text", Arg::Str)],
+        returns: false,
+    },
+    EventSpec {
+        name: "export",
+        doc: "The player asked to export the game from the game over screen, e.g. to save its PGN.",
+        args: &[],
+        returns: false,
+    },
+    EventSpec {
+        name: "puzzle_solved",
+        doc: "The player solved the puzzle loaded with load_puzzle: { id, mistakes }.",
+        args: &[("solved", Arg::Json)],
+        returns: false,
+    },
+];
+
+impl Event<'_> {
+    // The kind, and the arguments to js_dispatch.
+    fn encode(&self) -> (u32, [u32; 3]) {
+        let ptr = |b: &[u8]| b.as_ptr() as usize as u32;
+        match *self {
+            Event::Log(s) => (0, [ptr(s.as_bytes()), s.len() as u32, 0]),
+            Event::Move(payload, binary) => {
+                (1, [ptr(payload), payload.len() as u32, binary as u32])
+            }
+            Event::PlayerColor => (2, [0; 3]),
+            Event::TurnStart(color) => (3, [color as u32, 0, 0]),
+            Event::GameEnd(json) => (4, [ptr(json.as_bytes()), json.len() as u32, 0]),
+            Event::Announce(text) => (5, [ptr(text.as_bytes()), text.len() as u32, 0]),
+            Event::BoardImage(png) => (6, [ptr(png), png.len() as u32, 0]),
+            Event::GameAnimation(png) => (7, [ptr(png), png.len() as u32, 0]),
+            Event::ExplorerQuery(key) => (8, [ptr(key.as_bytes()), key.len() as u32, 0]),
+            Event::Moved(json) => (9, [ptr(json.as_bytes()), json.len() as u32, 0]),
+            Event::DecorateSquare(row, col) => (11, [row as u32, col as u32, 0]),
+            Event::DecoratePiece(json) => (12, [ptr(json.as_bytes()), json.len() as u32, 0]),
+            Event::Status(text) => (13, [ptr(text.as_bytes()), text.len() as u32, 0]),
+            Event::Export => (14, [0; 3]),
+            Event::PuzzleSolved(json) => (15, [ptr(json.as_bytes()), json.len() as u32, 0]),
+            Event::AnalysisMove(message) => {
+                (10, [ptr(message.as_bytes()), message.len() as u32, 0])
+            }
+        }
+    }
+}
+
+// Tells the page, returning what its handler returned, or 0 if there's no handler. The desktop
+// binary has no page, so it's always 0 there.
+#[cfg(target_arch = "wasm32")]
+pub fn dispatch(event: Event) -> u32 {
+    let (kind, [a, b, c]) = event.encode();
+    unsafe { js_dispatch(kind, a, b, c) }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn dispatch(_event: Event) -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // Each event uses as many numbers as its spec's arguments take.
+        let numbers = |args: &[(&str, Arg)]| -> usize {
+            args.iter()
+                .map(|(_, a)| match a {
+                    Arg::Str | Arg::Bytes | Arg::Json => 2,
+                    Arg::Bool | Arg::Color | Arg::Int => 1,
+                })
+                .sum()
+        };
+        let events = [
+            (Event::Log("hi"), "log"),
+            (Event::Move(&[1, 2, 3], true), "move"),
+            (Event::PlayerColor, "player_color"),
+            (Event::TurnStart(1), "turn_start"),
+            (Event::GameEnd(r#"{"result": "1-0"}"#), "game_end"),
+            (Event::Announce("Draw"), "announce"),
+            (Event::BoardImage(&[137, 80]), "board_image"),
+            (Event::GameAnimation(&[137, 80]), "game_animation"),
+            (Event::ExplorerQuery("0123456789abcdef"), "explorer_query"),
+            (Event::Moved(r#"{"uci": "e2e4"}"#), "moved"),
+            (Event::AnalysisMove(r#"{"analysis": {}}"#), "analysis_move"),
+            (Event::DecorateSquare(4, 5), "decorate_square"),
+            (Event::DecoratePiece(r#"{"name": "K"}"#), "decorate_piece"),
+            (Event::Status("Invalid FEN"), "status"),
+            (Event::Export, "export"),
+            (Event::PuzzleSolved(r#"{"id": "g-39"}"#), "puzzle_solved"),
+        ];
+        for (event, name) in events {
+            let (kind, args) = event.encode();
+            let spec = &EVENTS[kind as usize];
+            assert_eq!(spec.name, name);
+            let used = args.iter().rposition(|&a| a != 0).map_or(0, |i| i + 1);
+            assert_eq!(used, numbers(spec.args), "{}", name);
+        }
+        assert_eq!(Event::Move(&[1, 2, 3], true).encode().1[1..], [3, 1]);
+        assert_eq!(Event::TurnStart(1).encode().1[0], 1);
+    }
+}
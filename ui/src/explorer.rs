@@ -0,0 +1,226 @@
+// The opening explorer panel in analysis mode: the moves played in the position on the board in the
+// games the server has logged (see server/src/explorer.rs), with how often each was played and how
+// those games ended. The server knows positions by the hash the players sent with their moves, and
+// the starting position by "". In the browser, the page fetches them when the game sends an
+// explorer_query event, and hands the answer back with set_explorer (see assets/js/explorer.js).
+// The desktop binary asks the server it plays online on itself.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use macroquad::prelude::*;
+use serde_json::Value;
+
+use chess_ui::notation::{parse_long_algebraic, san};
+
+use crate::prelude::*;
+
+// How many of the most played moves to show.
+const MOVES: usize = 8;
+const ROW_HEIGHT: f32 = 20.0;
+const PANEL_WIDTH: f32 = 250.0;
+// Below where the online players' presence is shown.
+const PANEL_TOP: f32 = 32.0;
+#[cfg(not(target_arch = "wasm32"))]
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub struct ExplorerMove {
+    pub san: String,
+    pub games: u32,
+    pub white: u32,
+    pub draws: u32,
+    pub black: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Explorer {
+    // The position's key, as in position_key.
+    pub key: String,
+    pub games: u32,
+    // Most played first.
+    pub moves: Vec<ExplorerMove>,
+}
+
+// What the server knows the position by.
+pub fn position_key(pp: &PiecePlacements, gd: GameData) -> String {
+    let rules = Rules::defaults();
+    if (pp, gd) == (&rules.initial_placements(), rules.initial_game_data()) {
+        String::new()
+    } else {
+        format!("{:016x}", Rules::position_hash(pp, gd))
+    }
+}
+
+// The server's answer for the position pp, gd. Moves that aren't legal in it are left out, in case
+// another position has the same hash.
+pub fn parse(
+    json: &str,
+    rules: &Rules,
+    pp: &PiecePlacements,
+    gd: GameData,
+) -> Result<Explorer, String> {
+    let data: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let key = data["hash"].as_str().ok_or("Missing hash")?.to_string();
+    let count = |v: &Value| v.as_u64().map(|n| n as u32).ok_or("Invalid count");
+    let mut moves = Vec::new();
+    for m in data["moves"].as_array().ok_or("Missing moves")? {
+        let uci = m["uci"].as_str().ok_or("Invalid move")?;
+        let Some((p, mv)) = parse_long_algebraic(rules, pp, gd, uci) else {
+            continue;
+        };
+        moves.push(ExplorerMove {
+            san: san(rules, pp, gd, p, mv),
+            games: count(&m["games"])?,
+            white: count(&m["white"])?,
+            draws: count(&m["draws"])?,
+            black: count(&m["black"])?,
+        });
+    }
+    Ok(Explorer {
+        key,
+        games: count(&data["games"])?,
+        moves,
+    })
+}
+
+// E.g. "e4  62%  40 / 30 / 30": how often the move was played here, then the share of those games
+// white won, drawn and black won.
+fn format_move(m: &ExplorerMove, total: u32) -> String {
+    let percent = |n: u32, of: u32| (n as f32 * 100.0 / of.max(1) as f32).round() as u32;
+    format!(
+        "{:<7} {:>3}%  {} / {} / {}",
+        m.san,
+        percent(m.games, total),
+        percent(m.white, m.games),
+        percent(m.draws, m.games),
+        percent(m.black, m.games)
+    )
+}
+
+fn panel_x() -> f32 {
+    // TODO: get board size from rules
+    8.0 * SQUARE_SIZE - PANEL_WIDTH
+}
+
+// Draws the panel in the top right corner of the board: a header, which is all there is while the
+// panel is closed, then a row for each of the most played moves.
+pub fn draw_explorer_panel(explorer: &Explorer, open: bool) {
+    let header = format!(
+        "[{}] Explorer  {} games",
+        if open { "-" } else { "+" },
+        explorer.games
+    );
+    let mut rows = vec![header];
+    if open {
+        rows.extend(
+            explorer
+                .moves
+                .iter()
+                .take(MOVES)
+                .map(|m| format_move(m, explorer.games)),
+        );
+    }
+    draw_rectangle(
+        panel_x(),
+        PANEL_TOP,
+        PANEL_WIDTH,
+        rows.len() as f32 * ROW_HEIGHT,
+        Color::new(0.0, 0.0, 0.0, 0.6),
+    );
+    for (i, row) in rows.iter().enumerate() {
+        let y = PANEL_TOP + (i + 1) as f32 * ROW_HEIGHT - 5.0;
+        draw_text(row, panel_x() + 8.0, y, ROW_HEIGHT, LIGHTGRAY);
+    }
+}
+
+// Whether x, y is on the panel's header, which opens and closes it.
+pub fn explorer_header_at(x: f32, y: f32) -> bool {
+    (panel_x()..panel_x() + PANEL_WIDTH).contains(&x)
+        && (PANEL_TOP..PANEL_TOP + ROW_HEIGHT).contains(&y)
+}
+
+// Asks the server, at a ws:// URL like net::DEFAULT_SERVER, about the position with this key, on
+// another thread. The answer, or why there isn't one, arrives on the returned channel.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn fetch(server: &str, key: &str) -> mpsc::Receiver<Result<String, String>> {
+    let (tx, rx) = mpsc::channel();
+    let (server, key) = (server.to_string(), key.to_string());
+    thread::spawn(move || {
+        let _ = tx.send(get(&server, &key));
+    });
+    rx
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn get(server: &str, key: &str) -> Result<String, String> {
+    let host = server
+        .strip_prefix("ws://")
+        .map(|h| h.trim_end_matches('/'))
+        .ok_or_else(|| format!("The explorer needs a ws:// server, not {}", server))?;
+    let mut stream = TcpStream::connect(host).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    // HTTP/1.0, so the server closes the connection once it's answered.
+    write!(
+        stream,
+        "GET /api/explorer?hash={} HTTP/1.0\r\nHost: {}\r\n\r\n",
+        key, host
+    )
+    .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Invalid response from the server")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{}: {}", status, body.trim()));
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::parse_fen;
+
+    use super::*;
+
+    #[test]
+    fn test_explorer() {
+        let rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = rules.initial_game_data();
+        assert_eq!(position_key(&pp, gd), "");
+        let (after_e4, gd_e4) =
+            parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(
+            position_key(&after_e4, gd_e4),
+            format!("{:016x}", Rules::position_hash(&after_e4, gd_e4))
+        );
+
+        let json = r#"{"hash": "", "games": 4, "moves": [
+            {"uci": "e2e4", "games": 3, "white": 2, "draws": 1, "black": 0},
+            {"uci": "e7e5", "games": 1, "white": 0, "draws": 0, "black": 1}
+        ]}"#;
+        let explorer = parse(json, &rules, &pp, gd).unwrap();
+        assert_eq!(explorer.key, "");
+        assert_eq!(explorer.games, 4);
+        // Black can't play e5 yet.
+        assert_eq!(explorer.moves.len(), 1);
+        assert_eq!(
+            format_move(&explorer.moves[0], 4),
+            "e4       75%  67 / 33 / 0"
+        );
+        assert!(parse(r#"{"hash": ""}"#, &rules, &pp, gd).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+// Pictures of the board for sharing positions and games. The game draws positions offscreen, into
+// a render target the size of the board, and encodes what it drew as a PNG, or for a whole game an
+// animated PNG (APNG) with a frame per position. The page asks for them with request_board_image
+// and request_game_animation, and gets them in board_image and game_animation events. The desktop
+// binary saves a picture when the player presses P or its Export button, which saves the game's PGN
+// too, and animates a PGN game with --export-apng.
+
+use macroquad::texture::Image;
+
+use chess_ui::notation::{long_algebraic, parse_pgn, parse_san};
+
+use crate::prelude::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "\
+Usage: chess-ui --export-apng GAME.pgn [options]
+
+Animates the first game in GAME.pgn, from the initial position to the last move.
+
+Options:
+  --out FILE      Where to write the animation (default: GAME.png)
+  --delay MS      How long each position is shown, in milliseconds (default 1000)
+  --flip          Black at the bottom
+  --coordinates   Files and ranks along the board's edges";
+
+// How long each position of an animation is shown, in milliseconds.
+pub const DEFAULT_DELAY: u16 = 1000;
+
+// What to draw besides the board and pieces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageOptions {
+    // Black at the bottom.
+    pub flipped: bool,
+    // Files and ranks along the board's edges.
+    pub coordinates: bool,
+    // The squares the last move was from and to, highlighted.
+    pub last_move: bool,
+}
+
+pub fn png(image: &Image) -> Result<Vec<u8>, String> {
+    encode(std::slice::from_ref(image), 0)
+}
+
+// An APNG showing each frame for delay milliseconds, and the last for three times as long before
+// it starts over. Viewers without APNG support show the first frame.
+pub fn apng(frames: &[Image], delay: u16) -> Result<Vec<u8>, String> {
+    encode(frames, delay.max(1))
+}
+
+// A still PNG of the only frame if delay is 0.
+fn encode(frames: &[Image], delay: u16) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or("No frames to encode")?;
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, first.width as u32, first.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let e = |e: png::EncodingError| e.to_string();
+    if delay > 0 {
+        encoder.set_animated(frames.len() as u32, 0).map_err(e)?;
+    }
+    let mut writer = encoder.write_header().map_err(e)?;
+    for (i, frame) in frames.iter().enumerate() {
+        if delay > 0 {
+            let last = i == frames.len() - 1;
+            let delay = if last { delay.saturating_mul(3) } else { delay };
+            writer.set_frame_delay(delay, 1000).map_err(e)?;
+        }
+        // Render targets are stored bottom row first.
+        let row = frame.width as usize * 4;
+        let upright: Vec<u8> = frame.bytes.chunks(row).rev().flatten().copied().collect();
+        writer.write_image_data(&upright).map_err(e)?;
+    }
+    writer.finish().map_err(e)?;
+    Ok(out)
+}
+
+// The squares a move in long algebraic notation, e.g. "e7e8q", is from and to, as (row, column).
+pub fn move_squares(m: &str) -> Option<((usize, usize), (usize, usize))> {
+    let square = |s: &[u8]| -> Option<(usize, usize)> {
+        match *s {
+            [f @ b'a'..=b'z', r @ b'1'..=b'9'] => {
+                Some(((r - b'0') as usize, (f - b'a' + 1) as usize))
+            }
+            _ => None,
+        }
+    };
+    let b = m.as_bytes();
+    Some((square(b.get(0..2)?)?, square(b.get(2..4)?)?))
+}
+
+// Every position of a game, oldest first, like Game's history.
+type Positions = Vec<(PiecePlacements, GameData)>;
+
+// Plays the first game in a PGN file from the initial position. Returns its positions, and the
+// moves in long algebraic notation.
+pub fn load_pgn(rules: &Rules, s: &str) -> Result<(Positions, Vec<String>), String> {
+    let game = parse_pgn(s)?.into_iter().next().ok_or("No game in PGN")?;
+    let mut pp = rules.initial_placements();
+    let mut gd = rules.initial_game_data();
+    let mut history = vec![(pp, gd)];
+    let mut moves = Vec::new();
+    for (i, s) in game.moves.iter().enumerate() {
+        let (p, m) = parse_san(rules, &pp, gd, s)
+            .ok_or_else(|| format!("Illegal move {}: {}", i / 2 + 1, s))?;
+        moves.push(long_algebraic(p, m));
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+        history.push((pp, gd));
+    }
+    Ok((history, moves))
+}
+
+// What --export-apng was asked to do.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct AnimationArgs {
+    pub pgn: String,
+    pub out: String,
+    pub delay: u16,
+    pub options: ImageOptions,
+}
+
+// The arguments after --export-apng. Exits with the usage if they're wrong.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn animation_args() -> AnimationArgs {
+    let mut args = std::env::args()
+        .skip_while(|a| a != "--export-apng")
+        .skip(1);
+    let usage = |e: &str| -> ! {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    };
+    let pgn = args.next().unwrap_or_else(|| usage("Missing GAME.pgn"));
+    let mut parsed = AnimationArgs {
+        out: format!("{}.png", pgn.strip_suffix(".pgn").unwrap_or(&pgn)),
+        pgn,
+        delay: DEFAULT_DELAY,
+        options: ImageOptions {
+            flipped: false,
+            coordinates: false,
+            last_move: true,
+        },
+    };
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--flip" => parsed.options.flipped = true,
+            "--coordinates" => parsed.options.coordinates = true,
+            "--out" => parsed.out = args.next().unwrap_or_else(|| usage("Missing FILE")),
+            "--delay" => {
+                let value = args.next().unwrap_or_default();
+                parsed.delay = value
+                    .parse()
+                    .unwrap_or_else(|_| usage(&format!("Invalid delay: {}", value)));
+            }
+            _ => usage(&format!("Unknown argument: {}", a)),
+        }
+    }
+    parsed
+}
+
+// Saves the PNG, named after the ply it shows, e.g. board-12.png, in the current directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(png: &[u8], ply: u16) -> Result<String, String> {
+    let path = format!("board-{}.png", ply);
+    std::fs::write(&path, png).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    Ok(path)
+}
+
+// Saves the game's PGN as game.pgn, in the current directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_pgn(pgn: &str) -> Result<String, String> {
+    let path = "game.pgn";
+    std::fs::write(path, pgn).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    Ok(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png() {
+        // Red on the bottom row, as stored, and blue on the top one.
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let image = Image {
+            bytes: [red, red, blue, blue].concat(),
+            width: 2,
+            height: 2,
+        };
+        let decode = |png: Vec<u8>| {
+            let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+            let frames = reader.info().animation_control.map(|a| a.num_frames);
+            let mut pixels = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut pixels).unwrap();
+            (frames, pixels)
+        };
+        let (frames, pixels) = decode(png(&image).unwrap());
+        assert_eq!(frames, None);
+        assert_eq!(pixels, [blue, blue, red, red].concat());
+        let (frames, _) = decode(apng(&[image.clone(), image], 500).unwrap());
+        assert_eq!(frames, Some(2));
+
+        assert_eq!(move_squares("e7e8q"), Some(((7, 5), (8, 5))));
+        assert_eq!(move_squares("g1f3"), Some(((1, 7), (3, 6))));
+        assert_eq!(move_squares("e7"), None);
+    }
+
+    #[test]
+    fn test_load_pgn() {
+        let rules = Rules::defaults();
+        let (history, moves) = load_pgn(&rules, "[White \"?\"]\n1. e4 e5 2. Nf3 Nc6 *").unwrap();
+        assert_eq!(moves, ["e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[4].0.get(3, 6), b'N');
+        assert!(load_pgn(&rules, "1. e4 e4 *")
+            .unwrap_err()
+            .contains("Illegal move 1"));
+    }
+}
@@ -0,0 +1,260 @@
+// Fair play checks of an online game, from the server's move log (see replay.rs): how often each
+// player's moves are as good as the engine's best at a fixed depth, and how steady their thinking
+// times are. Matching the engine most of the time, especially at a machine-like pace, gets a
+// player flagged for an admin to look at. It's a hint, not proof: strong players and forced lines
+// match too. Run the desktop binary with --fair-play FILE (see USAGE). The report goes to stdout as JSON:
+//   {"players": [{"player_id": "...", "moves": 30, "matches": 27, "match_rate": 0.9,
+//                 "mean_think_ms": 4100, "think_cv": 0.12, "suspicious": true}, ...]}
+// The server runs this after each game when built with its fair-play feature.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    prelude::*,
+    replay::{read_log, replay, Played},
+};
+
+const USAGE: &str = "Usage: chess-ui --fair-play FILE [--depth N]";
+
+// The opening is often played from memory, so its moves say little.
+const SKIP_PLIES: u16 = 8;
+// Positions where more moves than this are as good as the best one don't tell anything, since
+// most players would find one of them.
+const MAX_TIED: usize = 2;
+// Fewer compared moves than this are never flagged.
+const MIN_MOVES: usize = 10;
+// Flagged at this match rate, or at STEADY_MATCH_RATE with thinking times steadier than
+// STEADY_THINK_CV (the standard deviation over the mean).
+const MATCH_RATE: f64 = 0.9;
+const STEADY_MATCH_RATE: f64 = 0.7;
+const STEADY_THINK_CV: f64 = 0.25;
+
+#[derive(Debug, Default, PartialEq)]
+struct PlayerStats {
+    player_id: String,
+    // Moves compared with the engine's, i.e. made where only a few moves are best.
+    moves: usize,
+    matches: usize,
+    // For every move after the opening that wasn't forced.
+    think_ms: Vec<u64>,
+}
+
+impl PlayerStats {
+    fn match_rate(&self) -> f64 {
+        if self.moves == 0 {
+            return 0.0;
+        }
+        self.matches as f64 / self.moves as f64
+    }
+
+    fn mean_think_ms(&self) -> f64 {
+        if self.think_ms.is_empty() {
+            return 0.0;
+        }
+        self.think_ms.iter().sum::<u64>() as f64 / self.think_ms.len() as f64
+    }
+
+    fn think_cv(&self) -> f64 {
+        let mean = self.mean_think_ms();
+        if mean == 0.0 {
+            return 0.0;
+        }
+        let variance = self
+            .think_ms
+            .iter()
+            .map(|&t| (t as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.think_ms.len() as f64;
+        variance.sqrt() / mean
+    }
+
+    fn suspicious(&self) -> bool {
+        let rate = self.match_rate();
+        self.moves >= MIN_MOVES
+            && (rate >= MATCH_RATE
+                || (rate >= STEADY_MATCH_RATE && self.think_cv() < STEADY_THINK_CV))
+    }
+
+    fn report(&self) -> Value {
+        json!({
+            "player_id": self.player_id,
+            "moves": self.moves,
+            "matches": self.matches,
+            "match_rate": self.match_rate(),
+            "mean_think_ms": self.mean_think_ms().round(),
+            "think_cv": self.think_cv(),
+            "suspicious": self.suspicious(),
+        })
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip_while(|a| a != "--fair-play").skip(1);
+    let mut path = None;
+    let mut depth = 3;
+    while let Some(a) = args.next() {
+        if a == "--depth" {
+            depth = match args.next().and_then(|d| d.parse().ok()) {
+                Some(d) if d > 0 => d,
+                _ => usage(),
+            };
+        } else if path.is_none() {
+            path = Some(a);
+        } else {
+            usage();
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    match read_log(Path::new(&path)).and_then(|log| replay(&log)) {
+        Ok(r) => {
+            let players: Vec<Value> = analyze(&r.played, depth)
+                .iter()
+                .map(PlayerStats::report)
+                .collect();
+            println!("{}", json!({ "players": players }));
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+// Each player's stats, in the order they first moved. The moves are checked with the standard
+// rules, like the replay.
+fn analyze(played: &[Played], depth: u32) -> Vec<PlayerStats> {
+    let rules = Rules::defaults();
+    let engine = Engine {
+        config: EngineConfig {
+            depth,
+            // The depth is what's fixed, so results don't depend on the machine.
+            time_limit: f64::INFINITY,
+            blunder_chance: 0.0,
+            ..EngineConfig::level(3)
+        },
+    };
+    let mut stats: Vec<PlayerStats> = Vec::new();
+    for p in played {
+        let i = match stats.iter().position(|s| s.player_id == p.player_id) {
+            Some(i) => i,
+            None => {
+                stats.push(PlayerStats {
+                    player_id: p.player_id.clone(),
+                    ..Default::default()
+                });
+                stats.len() - 1
+            }
+        };
+        let forced = rules.legal_moves(p.gd.player_to_move(), &p.pp, p.gd).len() <= 1;
+        if p.gd.ply <= SKIP_PLIES || forced {
+            continue;
+        }
+        let s = &mut stats[i];
+        s.think_ms.push(p.think_ms);
+        // Any of the best moves counts, since which of them the engine picks is arbitrary.
+        let scored = engine.score_moves(&rules, &p.pp, p.gd);
+        let best = scored[0].0;
+        if scored.iter().filter(|&&(score, _)| score == best).count() > MAX_TIED {
+            continue;
+        }
+        s.moves += 1;
+        let matched = scored
+            .iter()
+            .any(|&(score, (piece, m))| score == best && piece == p.piece && m.dst == p.m.dst);
+        if matched {
+            s.matches += 1;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::{parse_pgn, parse_san};
+
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        // In each position of some real games, "e" plays the engine's best move every 2 seconds
+        // and "h" the worst one at an uneven pace.
+        let rules = Rules::defaults();
+        let engine = Engine {
+            config: EngineConfig {
+                depth: 1,
+                time_limit: f64::INFINITY,
+                blunder_chance: 0.0,
+                ..EngineConfig::level(3)
+            },
+        };
+        let games = parse_pgn(include_str!("../testdata/golden_games.pgn")).unwrap();
+        let mut played = Vec::new();
+        for game in games.iter().take(1) {
+            let mut pp = rules.initial_placements();
+            let mut gd = GameData { ply: 1, mask: 0 };
+            for s in game.moves.iter().take(30) {
+                // The opening isn't analyzed anyway.
+                if gd.ply > SKIP_PLIES {
+                    let scored = engine.score_moves(&rules, &pp, gd);
+                    let best = scored[0].1;
+                    let worst = scored[scored.len() - 1].1;
+                    for (player_id, (piece, m), think_ms) in [
+                        ("e", best, 2000),
+                        ("h", worst, 500 + 3000 * (played.len() as u64 % 3)),
+                    ] {
+                        played.push(Played {
+                            player_id: player_id.to_string(),
+                            think_ms,
+                            pp,
+                            gd,
+                            piece,
+                            m,
+                        });
+                    }
+                }
+                let (p, m) = parse_san(&rules, &pp, gd, s).unwrap();
+                Rules::make_move(p, m, &mut pp);
+                gd = GameData {
+                    ply: m.game_data.ply + 1,
+                    ..m.game_data
+                };
+            }
+        }
+
+        let stats = analyze(&played, 1);
+        assert_eq!(stats.len(), 2);
+        let (engine, human) = (&stats[0], &stats[1]);
+        assert_eq!(engine.player_id, "e");
+        assert!(engine.moves >= MIN_MOVES);
+        assert_eq!(engine.matches, engine.moves);
+        assert_eq!(engine.think_cv(), 0.0);
+        assert!(engine.suspicious());
+        assert_eq!(engine.report()["suspicious"], true);
+        assert_eq!(human.moves, engine.moves);
+        assert!(human.match_rate() < STEADY_MATCH_RATE);
+        assert!(human.think_cv() > STEADY_THINK_CV);
+        assert!(!human.suspicious());
+
+        // Matching a little less often is suspicious only at a steady pace.
+        let steady = PlayerStats {
+            player_id: "s".to_string(),
+            moves: 10,
+            matches: 8,
+            think_ms: vec![1000; 10],
+        };
+        assert!(steady.suspicious());
+        let uneven = PlayerStats {
+            think_ms: vec![500, 4000, 1000, 9000, 2000, 700, 3000, 600, 12000, 800],
+            ..steady
+        };
+        assert!(!uneven.suspicious());
+    }
+}
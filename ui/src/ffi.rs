@@ -0,0 +1,335 @@
+// The JS side of the WASM interface, generated so pages don't hand-maintain byte offsets that
+// silently break when a struct changes: the layouts of the data shared with JS (Piece, GameData,
+// the board and what movement plugins return), a wrapper for each function WASM exports, with its
+// doc comment, and the events the game sends the page (see events.rs), with TypeScript
+// definitions for their handlers. Run the desktop binary with --ffi-js DIR to write them to
+// DIR/ffi.js and DIR/events.d.ts. They're checked in under assets/js, and a test fails when
+// they're out of date. The iframe API's shim, embed.js, is written with them (see embed.rs).
+
+use std::{
+    mem::{offset_of, size_of},
+    path::Path,
+};
+
+use crate::{
+    embed,
+    events::{Arg, EVENTS},
+    prelude::*,
+};
+
+const USAGE: &str = "Usage: chess-ui --ffi-js DIR";
+
+// Where the exports are. A test checks no other file has any.
+const SOURCES: [(&str, &str); 4] = [
+    ("src/main.rs", include_str!("main.rs")),
+    ("src/crash.rs", include_str!("crash.rs")),
+    ("src/logging.rs", include_str!("logging.rs")),
+    ("src/mem.rs", include_str!("mem.rs")),
+];
+
+const HEADER: &str = "\
+// Generated by `chess-ui --ffi-js` (see src/ffi.rs). Don't edit it, run that again when the shared
+// structs, the exports or the events change.
+";
+
+pub fn main() {
+    let dir = match std::env::args().skip_while(|a| a != "--ffi-js").nth(1) {
+        Some(d) => d,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let dir = Path::new(&dir);
+    for (name, contents) in [
+        ("ffi.js", generate()),
+        ("events.d.ts", generate_events_ts()),
+        ("embed.js", embed::generate()),
+    ] {
+        if let Err(e) = std::fs::write(dir.join(name), contents) {
+            eprintln!("Couldn't write {}: {}", dir.join(name).display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Export {
+    name: String,
+    // Names and Rust types.
+    params: Vec<(String, String)>,
+    ret: Option<String>,
+    doc: Vec<String>,
+}
+
+// The #[no_mangle] functions in source, in order.
+fn exports(source: &str) -> Vec<Export> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut exports = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(rest) = line.strip_prefix("pub extern \"C\" fn ") else {
+            continue;
+        };
+        if i == 0 || !lines[i - 1].contains("no_mangle") {
+            continue;
+        }
+        let doc = lines[..i - 1]
+            .iter()
+            .rev()
+            .take_while(|l| l.starts_with("//"))
+            .map(|l| l.trim_start_matches('/').trim().to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        // The signature can span lines, up to the body.
+        let mut signature = rest.to_string();
+        for l in &lines[i + 1..] {
+            if signature.contains('{') {
+                break;
+            }
+            signature.push(' ');
+            signature.push_str(l.trim());
+        }
+        let signature = signature[..signature.find('{').unwrap()].trim();
+        let (name, rest) = signature.split_once('(').unwrap();
+        let (params, ret) = rest.rsplit_once(')').unwrap();
+        let params = params
+            .split(',')
+            .filter_map(|p| p.split_once(':'))
+            .map(|(n, t)| (n.trim().to_string(), t.trim().to_string()))
+            .collect();
+        let ret = ret.trim().strip_prefix("->").map(|r| r.trim().to_string());
+        exports.push(Export {
+            name: name.trim().to_string(),
+            params,
+            ret,
+            doc,
+        });
+    }
+    exports
+}
+
+// Every argument and return value is a number in JS, so the JSDoc tells what it is in Rust.
+// Pointers are offsets into wasm_memory.
+fn rust_type_doc(rust: &str) -> String {
+    if rust.starts_with('*') {
+        format!("{}, a pointer into wasm_memory", rust)
+    } else {
+        rust.to_string()
+    }
+}
+
+fn layout(name: &str, doc: &str, size: usize, fields: &[(&str, usize)]) -> String {
+    let fields: String = fields
+        .iter()
+        .map(|(f, offset)| format!(", {}: {}", f, offset))
+        .collect();
+    format!(
+        "// {}\nexport const {} = {{ size: {}{} }};\n",
+        doc, name, size, fields
+    )
+}
+
+fn generate() -> String {
+    let mut js = HEADER.to_string();
+    js.push('\n');
+    js.push_str(&layout(
+        "PIECE",
+        "A piece: its square and its name as an ASCII code, uppercase for white.",
+        size_of::<Piece>(),
+        &[
+            ("row", offset_of!(Piece, row)),
+            ("col", offset_of!(Piece, col)),
+            ("name", offset_of!(Piece, name)),
+        ],
+    ));
+    js.push_str(&layout(
+        "GAME_DATA",
+        "Little-endian u16s. See the GD_ flags in src/rules.rs for the mask.",
+        size_of::<GameData>(),
+        &[
+            ("ply", offset_of!(GameData, ply)),
+            ("mask", offset_of!(GameData, mask)),
+        ],
+    ));
+    js.push_str(&format!(
+        "// The board is width * height bytes, row by row from a1, each the ASCII code of the piece on\n\
+         // the square or 0. Rows and columns count from 1.\n\
+         export const BOARD = {{ max_size: {} }};\n",
+        MAX_BOARD_SIZE
+    ));
+    js.push_str(&format!(
+        "// Each square's byte of flags, laid out like the board. See the PF_ flags in src/rules.rs.\n\
+         export const SQUARE_FLAGS = {{ moved: {}, frozen: {}, charges_shift: {}, charges: {} }};\n",
+        PF_MOVED, PF_FROZEN, PF_CHARGES_SHIFT, PF_CHARGES
+    ));
+    js.push_str(&layout(
+        "PLUGIN_MOVE",
+        "One of the moves a movement plugin returns, which end at a 0 row.",
+        3,
+        &[("row", 0), ("col", 1), ("name", 2)],
+    ));
+    js.push_str(
+        "
+// Reads the piece at ptr.
+export function read_piece(ptr) {
+    let b = new Uint8Array(wasm_memory.buffer, ptr, PIECE.size);
+    return { row: b[PIECE.row], col: b[PIECE.col], name: String.fromCharCode(b[PIECE.name]) };
+}
+
+// Reads the game data at ptr.
+export function read_game_data(ptr) {
+    let v = new DataView(wasm_memory.buffer, ptr, GAME_DATA.size);
+    return { ply: v.getUint16(GAME_DATA.ply, true), mask: v.getUint16(GAME_DATA.mask, true) };
+}
+",
+    );
+    js.push_str("\n// The events the game sends the page, by kind (see events.js).\nexport const EVENTS = [\n");
+    for e in &EVENTS {
+        let args: Vec<String> = e
+            .args
+            .iter()
+            .map(|(_, a)| format!("\"{}\"", arg_name(*a)))
+            .collect();
+        js.push_str(&format!(
+            "    {{ name: \"{}\", args: [{}], returns: {} }},\n",
+            e.name,
+            args.join(", "),
+            e.returns
+        ));
+    }
+    js.push_str("];\n");
+    for (path, source) in SOURCES {
+        for e in exports(source) {
+            js.push_str("\n/**\n");
+            for line in e.doc.iter().chain([&format!("From {}.", path)]) {
+                js.push_str(format!(" * {}", line).trim_end());
+                js.push('\n');
+            }
+            for (n, t) in &e.params {
+                js.push_str(&format!(
+                    " * @param {{number}} {} {}\n",
+                    n,
+                    rust_type_doc(t)
+                ));
+            }
+            if let Some(r) = &e.ret {
+                js.push_str(&format!(" * @returns {{number}} {}\n", rust_type_doc(r)));
+            }
+            js.push_str(" */\n");
+            let args: Vec<&str> = e.params.iter().map(|(n, _)| n.as_str()).collect();
+            let args = args.join(", ");
+            js.push_str(&format!(
+                "export function {}({}) {{\n    return wasm_exports.{}({});\n}}\n",
+                e.name, args, e.name, args
+            ));
+        }
+    }
+    js
+}
+
+fn arg_name(a: Arg) -> &'static str {
+    match a {
+        Arg::Str => "str",
+        Arg::Bytes => "bytes",
+        Arg::Bool => "bool",
+        Arg::Color => "color",
+        Arg::Json => "json",
+        Arg::Int => "int",
+    }
+}
+
+fn ts_type(a: Arg) -> &'static str {
+    match a {
+        Arg::Str => "string",
+        Arg::Bytes => "Uint8Array",
+        Arg::Bool => "boolean",
+        Arg::Color => "\"white\" | \"black\"",
+        Arg::Json => "any",
+        Arg::Int => "number",
+    }
+}
+
+// Types for assets/js/events.js.
+fn generate_events_ts() -> String {
+    let mut ts = HEADER.to_string();
+    ts.push_str("\nexport interface EventHandlers {\n");
+    for e in &EVENTS {
+        let params: Vec<String> = e
+            .args
+            .iter()
+            .map(|&(n, a)| format!("{}: {}", n, ts_type(a)))
+            .collect();
+        let ret = if e.returns { "number" } else { "void" };
+        ts.push_str(&format!(
+            "    /** {} */\n    {}({}): {};\n",
+            e.doc,
+            e.name,
+            params.join(", "),
+            ret
+        ));
+    }
+    ts.push_str(
+        "}
+
+/** Handles the event from now on, instead of any handler before. */
+export function on<E extends keyof EventHandlers>(name: E, handler: EventHandlers[E]): void;
+
+/** Sets up the import the game sends events through. Call it before loading the game. */
+export function init_events(): void;
+",
+    );
+    ts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exports() {
+        let source = "
+// Says hi.
+#[no_mangle]
+pub extern \"C\" fn hi(
+    a: usize,
+    b: *const u8,
+) -> *mut u8 {
+}
+
+pub extern \"C\" fn not_exported() {}
+";
+        assert_eq!(
+            exports(source),
+            [Export {
+                name: "hi".to_string(),
+                params: vec![
+                    ("a".to_string(), "usize".to_string()),
+                    ("b".to_string(), "*const u8".to_string())
+                ],
+                ret: Some("*mut u8".to_string()),
+                doc: vec!["Says hi.".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_generated_module_is_up_to_date() {
+        // Any file with exports has to be in SOURCES.
+        for entry in std::fs::read_dir("src").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let path = format!("src/{}", path.file_name().unwrap().to_str().unwrap());
+            if !exports(&source).is_empty() {
+                assert!(SOURCES.iter().any(|&(p, _)| p == path), "{}", path);
+            }
+        }
+        assert!(
+            include_str!("../assets/js/ffi.js") == generate()
+                && include_str!("../assets/js/events.d.ts") == generate_events_ts()
+                && include_str!("../assets/js/embed.js") == embed::generate(),
+            "assets/js is out of date, regenerate it with `chess-ui --ffi-js assets/js`"
+        );
+    }
+}
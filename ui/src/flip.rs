@@ -0,0 +1,79 @@
+// Turning the board around, when the player flips it or is given black online, is animated rather
+// than done at once: the pieces, and everything else drawn on squares, swing half a turn around the
+// board's center to where they are the other way up. The squares themselves look the same either
+// way up, so they stay put. The mouse picks squares the new way up from the start, so a piece being
+// dragged when the board turns lands where the player can see the board is going. It plays at the
+// player's animation speed, and not at all at 0.
+
+use std::f32::consts::PI;
+
+// In seconds, at normal speed.
+const DURATION: f64 = 0.4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlipAnimation {
+    started: f64,
+    duration: f64,
+}
+
+impl FlipAnimation {
+    // A flip starting at time now, or None if animations are off.
+    pub fn start(now: f64, speed: f32) -> Option<FlipAnimation> {
+        (speed > 0.0).then(|| FlipAnimation {
+            started: now,
+            duration: DURATION / speed as f64,
+        })
+    }
+
+    pub fn finished(&self, now: f64) -> bool {
+        now - self.started >= self.duration
+    }
+
+    // How far the board has turned at time now, from 0 to 1. It speeds up, then slows down.
+    pub fn progress(&self, now: f64) -> f32 {
+        let t = (((now - self.started) / self.duration) as f32).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+// Where a square is drawn partway through a flip, given where its top left corner, (x, y), is the
+// new way up: its center is turned back around the center of the board, of size board, by what's
+// left of the half turn.
+pub fn turn((x, y): (f32, f32), progress: f32, square_size: f32, board: f32) -> (f32, f32) {
+    let center = board / 2.0;
+    let half = square_size / 2.0;
+    let (dx, dy) = (x + half - center, y + half - center);
+    let (sin, cos) = (PI * (1.0 - progress)).sin_cos();
+    (
+        center + dx * cos - dy * sin - half,
+        center + dx * sin + dy * cos - half,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flip_animation() {
+        let close = |(x, y): (f32, f32), (ex, ey): (f32, f32)| {
+            assert!(
+                (x - ex).abs() < 0.01 && (y - ey).abs() < 0.01,
+                "{:?}",
+                (x, y)
+            )
+        };
+        // a1 in the bottom left corner, the new way up, starts in the top right where it was.
+        close(turn((0.0, 700.0), 0.0, 100.0, 800.0), (700.0, 0.0));
+        close(turn((0.0, 700.0), 1.0, 100.0, 800.0), (0.0, 700.0));
+        // Halfway, it's a quarter turn from both.
+        close(turn((0.0, 700.0), 0.5, 100.0, 800.0), (0.0, 0.0));
+
+        let flip = FlipAnimation::start(10.0, 2.0).unwrap();
+        assert_eq!(flip.progress(10.0), 0.0);
+        assert_eq!(flip.progress(10.1), 0.5);
+        assert!(!flip.finished(10.1));
+        assert!(flip.finished(10.25));
+        assert!(FlipAnimation::start(10.0, 0.0).is_none());
+    }
+}
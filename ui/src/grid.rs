@@ -0,0 +1,275 @@
+// Watching several online games at once, e.g. on a TV, or all the boards of a simul: run the desktop
+// app with --watch <game ID or link> for each game. Each game is a GameCore of its own, fed by its
+// own connection to the server, which joins it only to watch (watch=true, see the server), and the
+// boards are tiled over the window as big as they fit. Clicking a board shows it alone, filling the
+// window, and clicking again or the menu key goes back to all of them. Like any spectator, a board
+// only sees the moves played after it joined.
+
+use macroquad::prelude::*;
+
+use chess_ui::{
+    core::GameCore,
+    protocol::{MoveFrame, MOVE_PASS, MOVE_PHASE, MOVE_PHASE_SHIFT},
+};
+
+use crate::{
+    controls::Action,
+    export,
+    i18n::tr,
+    net::{Client, Mode, NetEvent},
+    prelude::*,
+    settings::Settings,
+    warn,
+};
+
+// Room for the line under each board.
+const LABEL_HEIGHT: f32 = 24.0;
+const GAP: f32 = 8.0;
+
+// One of the games watched.
+struct Watched<'a> {
+    core: GameCore<'a>,
+    // Shown under the board.
+    name: String,
+    // The result once the game's over, or why it can't be followed.
+    outcome: Option<String>,
+}
+
+impl<'a> Watched<'a> {
+    fn new(name: String) -> Watched<'a> {
+        Watched {
+            core: GameCore::new(Rules::defaults()),
+            name,
+            outcome: None,
+        }
+    }
+
+    // Follows the game from what the server sent.
+    fn apply(&mut self, event: NetEvent) {
+        match event {
+            NetEvent::OpponentMove(f) => self.play(f),
+            // Sent as we join, before any moves.
+            NetEvent::Ruleset(r) => match self.core.rules.import_ruleset(&r) {
+                Ok(()) => self.core.restart(),
+                Err(e) => warn!("Couldn't import the ruleset of {}: {}", self.name, e),
+            },
+            NetEvent::RulesUpdate(r) => {
+                for (n, active) in self.core.rules.rule_toggles_mut() {
+                    if let Some(&a) = r.get(n) {
+                        *active = a;
+                    }
+                }
+            }
+            // Both players go back once one accepts the other's request.
+            NetEvent::TakebackAccepted(ply) => {
+                self.core.take_back(ply);
+            }
+            NetEvent::GameOver { result, .. } => self.outcome = Some(result),
+            NetEvent::Aborted => self.outcome = Some(tr("result.aborted")),
+            NetEvent::Denied => self.outcome = Some(tr("online.denied")),
+            NetEvent::Disconnected if self.outcome.is_none() => {
+                self.outcome = Some(tr("online.disconnected"))
+            }
+            _ => {}
+        }
+    }
+
+    // A move by either player, who can only be the player to move.
+    fn play(&mut self, f: MoveFrame) {
+        let phase = ((f.flags & MOVE_PHASE) >> MOVE_PHASE_SHIFT) as usize;
+        let player = self.core.game_data().player_to_move();
+        let turn = self.core.turn(player, false);
+        let found = if phase != self.core.game_data().phase_index() {
+            None
+        } else if f.flags & MOVE_PASS != 0 {
+            self.core.pass_move(&turn)
+        } else {
+            let source = (f.src_row, f.src_col);
+            self.core
+                .find_move(&turn, source, &f.legs(), f.promotion, false)
+        };
+        match found {
+            Some((p, m)) => self.core.play(p, m),
+            None => warn!("Ignoring illegal move in {}: {:?}", self.name, f),
+        }
+    }
+}
+
+// Where on the screen to draw n boards, as big as they fit in rows with a label under each, and
+// centered.
+fn tiles(n: usize, screen: Vec2) -> Vec<Rect> {
+    let Some((columns, size)) = (1..=n)
+        .map(|columns| {
+            let rows = n.div_ceil(columns) as f32;
+            let width = (screen.x - GAP) / columns as f32 - GAP;
+            let height = (screen.y - GAP) / rows - GAP - LABEL_HEIGHT;
+            (columns, width.min(height).max(0.0))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+    else {
+        return Vec::new();
+    };
+    let rows = n.div_ceil(columns);
+    let (cell_w, cell_h) = (size + GAP, size + LABEL_HEIGHT + GAP);
+    let left = (screen.x - columns as f32 * cell_w + GAP) / 2.0;
+    let top = (screen.y - rows as f32 * cell_h + GAP) / 2.0;
+    (0..n)
+        .map(|i| {
+            let (row, column) = (i / columns, i % columns);
+            Rect::new(
+                left + column as f32 * cell_w,
+                top + row as f32 * cell_h,
+                size,
+                size,
+            )
+        })
+        .collect()
+}
+
+// The path to join the game at path, e.g. "join/<game ID>?passcode=...", only to watch it.
+fn watch_path(path: &str) -> String {
+    let separator = if path.contains('?') { '&' } else { '?' };
+    format!("{}{}watch=true", path, separator)
+}
+
+pub async fn run(server: &str, paths: &[String], settings: Settings) {
+    let pieces = load_texture("assets/img/pieces.png")
+        .await
+        .expect("Couldn't load pieces sprite sheet");
+    let mut games: Vec<(Watched, Client)> = paths
+        .iter()
+        .map(|path| {
+            let id = path.trim_start_matches("join/");
+            let name = id.chars().take(8).collect();
+            let net = Client::connect(server, &Mode::Join(watch_path(path)), None);
+            (Watched::new(name), net)
+        })
+        .collect();
+    // The board shown alone, if one's been clicked.
+    let mut focus = None;
+    loop {
+        for (game, net) in &mut games {
+            for event in net.poll() {
+                game.apply(event);
+            }
+        }
+        clear_background(Color::new(0.15, 0.15, 0.15, 1.0));
+        let shown: Vec<usize> = match focus {
+            Some(i) => vec![i],
+            None => (0..games.len()).collect(),
+        };
+        let screen = vec2(screen_width(), screen_height());
+        let tiles = tiles(shown.len(), screen);
+        for (&i, &tile) in shown.iter().zip(&tiles) {
+            draw_watched(&games[i].0, tile, pieces, &settings);
+        }
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (x, y) = mouse_position();
+            focus = match focus {
+                Some(_) => None,
+                None => tiles.iter().position(|t| t.contains(vec2(x, y))),
+            };
+        }
+        if is_key_pressed(settings.bindings.key(Action::Menu)) {
+            focus = None;
+        }
+        next_frame().await
+    }
+}
+
+// Draws the game's board in tile, scaled down from its usual size, with the game's name and how it
+// ended under it.
+fn draw_watched(game: &Watched, tile: Rect, pieces: Texture2D, settings: &Settings) {
+    let palette = settings.theme.palette();
+    // TODO: get board size from rules
+    let size = 8.0 * SQUARE_SIZE;
+    set_camera(&Camera2D {
+        // From the bottom of the screen, as GL has it.
+        viewport: Some((
+            tile.x as i32,
+            (screen_height() - tile.y - tile.h) as i32,
+            tile.w as i32,
+            tile.h as i32,
+        )),
+        ..Camera2D::from_display_rect(Rect::new(0.0, 0.0, size, size))
+    });
+    draw_rectangle(0.0, 0.0, size, size, palette.light);
+    let xy = |r: usize, c: usize| ((c - 1) as f32 * SQUARE_SIZE, (8 - r) as f32 * SQUARE_SIZE);
+    let (pp, _) = game.core.position();
+    let last_move = game.core.moves.last().and_then(|m| export::move_squares(m));
+    for (r, c, n) in pp.squares() {
+        let (x, y) = xy(r, c);
+        if (r + c) % 2 == 0 {
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, palette.dark);
+        }
+        if last_move.is_some_and(|(from, to)| from == (r, c) || to == (r, c)) {
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, palette.highlight);
+        }
+        if let Some(&(sx, sy)) = game.core.rules.piece_name_to_offsets.get(&n) {
+            let source = Rect::new(sx as f32, sy as f32, SQUARE_SIZE, SQUARE_SIZE);
+            draw_texture_ex(
+                pieces,
+                x,
+                y,
+                WHITE,
+                DrawTextureParams {
+                    source: Some(source),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+    set_default_camera();
+    let label = match &game.outcome {
+        Some(outcome) => format!("{}  {}", game.name, outcome),
+        None => game.name.clone(),
+    };
+    draw_text(&label, tile.x, tile.y + tile.h + 18.0, 20.0, WHITE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        let screen = vec2(800.0, 600.0);
+        let four = tiles(4, screen);
+        assert_eq!(four.len(), 4);
+        for (i, a) in four.iter().enumerate() {
+            assert!(a.x >= 0.0 && a.y >= 0.0);
+            assert!(a.right() <= screen.x && a.bottom() + LABEL_HEIGHT <= screen.y);
+            assert!(four[..i].iter().all(|b| !a.overlaps(b)));
+        }
+        // One board, focused, is as big as the window lets it be.
+        assert_eq!(tiles(1, screen)[0].w, 600.0 - 2.0 * GAP - LABEL_HEIGHT);
+        assert!(tiles(0, screen).is_empty());
+        assert_eq!(watch_path("join/abc"), "join/abc?watch=true");
+        assert_eq!(
+            watch_path("join/abc?passcode=x"),
+            "join/abc?passcode=x&watch=true"
+        );
+
+        let mut game = Watched::new("game".to_string());
+        let frame = |src_row, src_col, dst_row, dst_col| MoveFrame {
+            src_row,
+            src_col,
+            dst_row,
+            dst_col,
+            ..Default::default()
+        };
+        // Both sides' moves, and one that isn't legal.
+        game.apply(NetEvent::OpponentMove(frame(2, 5, 4, 5)));
+        game.apply(NetEvent::OpponentMove(frame(2, 4, 4, 4)));
+        game.apply(NetEvent::OpponentMove(frame(7, 5, 5, 5)));
+        assert_eq!(game.core.moves, ["e2e4", "e7e5"]);
+        game.apply(NetEvent::TakebackAccepted(2));
+        assert_eq!(game.core.moves, ["e2e4"]);
+        game.apply(NetEvent::GameOver {
+            result: "1-0".to_string(),
+            reason: "resigned".to_string(),
+        });
+        game.apply(NetEvent::Disconnected);
+        assert_eq!(game.outcome.as_deref(), Some("1-0"));
+    }
+}
@@ -0,0 +1,116 @@
+// Guessing the moves of an imported game, e.g. a master's, for one side: the board shows the game
+// from its start, without the moves to come, and on each of that side's turns the player's move is
+// taken as a guess rather than played. The game's move is played instead, then the other side's
+// reply, and the guess scores up to MAX_POINTS: all of them if it was the game's move, and fewer
+// the worse the engine thinks it is than the game's. Loaded from PGN with load_guess_pgn (see
+// main.rs). Once the game's moves run out, it can be replayed like any other.
+
+use std::collections::VecDeque;
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    prelude::*,
+};
+
+pub const MAX_POINTS: u32 = 5;
+// How deep the engine looks at a guess. It's searched at once, so not too deep for the browser.
+const DEPTH: u32 = 2;
+
+pub struct Guessing {
+    // The side the player guesses for, 0 for white.
+    pub side: usize,
+    // The game's moves still to come, in SAN.
+    hidden: VecDeque<String>,
+    pub points: u32,
+    pub guesses: u32,
+}
+
+impl Guessing {
+    pub fn new(side: usize, moves: Vec<String>) -> Guessing {
+        Guessing {
+            side,
+            hidden: moves.into(),
+            points: 0,
+            guesses: 0,
+        }
+    }
+
+    // The game's next move, revealed.
+    pub fn reveal(&mut self) -> Option<String> {
+        self.hidden.pop_front()
+    }
+
+    pub fn done(&self) -> bool {
+        self.hidden.is_empty()
+    }
+
+    // Scores the guess against the game's move in the position, and returns its points.
+    pub fn score(
+        &mut self,
+        rules: &Rules,
+        pp: &PiecePlacements,
+        gd: GameData,
+        guess: (Piece, Move),
+        actual: (Piece, Move),
+    ) -> u32 {
+        let points = if guess == actual {
+            MAX_POINTS
+        } else {
+            let engine = Engine {
+                config: EngineConfig {
+                    depth: DEPTH,
+                    time_limit: f64::INFINITY,
+                    blunder_chance: 0.0,
+                    ..EngineConfig::level(3)
+                },
+            };
+            let scored = engine.score_moves(rules, pp, gd);
+            let score_of = |m| scored.iter().find(|&&(_, s)| s == m).map(|&(s, _)| s);
+            match (score_of(guess), score_of(actual)) {
+                (Some(g), Some(a)) => points(a - g),
+                _ => 0,
+            }
+        };
+        self.points += points;
+        self.guesses += 1;
+        points
+    }
+}
+
+// The points for a guess that isn't the game's move, by how many centipawns worse it is. One at
+// least as good is still worth a point less than the game's.
+fn points(loss: i32) -> u32 {
+    match loss {
+        ..=0 => MAX_POINTS - 1,
+        1..=50 => 3,
+        51..=150 => 2,
+        151..=300 => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::{parse_fen, parse_san};
+
+    use super::*;
+
+    #[test]
+    fn test_guess() {
+        let rules = Rules::defaults();
+        // White can take the queen, as the game did, or push a pawn.
+        let (pp, gd) = parse_fen("4k3/8/8/3q4/8/2N5/P7/4K3 w - - 0 30").unwrap();
+        let mut guessing = Guessing::new(0, vec!["Nxd5".to_string(), "Kd7".to_string()]);
+        let san = guessing.reveal().unwrap();
+        let actual = parse_san(&rules, &pp, gd, &san).unwrap();
+        assert_eq!(guessing.score(&rules, &pp, gd, actual, actual), MAX_POINTS);
+        let push = parse_san(&rules, &pp, gd, "a3").unwrap();
+        assert_eq!(guessing.score(&rules, &pp, gd, push, actual), 0);
+        assert_eq!((guessing.points, guessing.guesses), (MAX_POINTS, 2));
+        assert!(!guessing.done());
+        assert_eq!(guessing.reveal().as_deref(), Some("Kd7"));
+        assert!(guessing.done());
+        assert_eq!(points(-20), MAX_POINTS - 1);
+        assert_eq!(points(100), 2);
+    }
+}
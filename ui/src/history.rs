@@ -0,0 +1,159 @@
+// A game's history kept as its moves rather than every position it went through, which for a long
+// game is most of what it takes to keep, save or send. Every CHECKPOINT_INTERVAL moves the position
+// is kept too, so getting back to any position only replays the moves since the checkpoint before
+// it. Replays (see replay.rs) and snapshots (see snapshot.rs) keep games this way.
+
+use crate::{notation::parse_long_algebraic, prelude::*};
+
+pub const CHECKPOINT_INTERVAL: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    // In long algebraic notation.
+    moves: Vec<String>,
+    // checkpoints[i] is the position after i * CHECKPOINT_INTERVAL moves, so the first is where
+    // the game started.
+    checkpoints: Vec<(PiecePlacements, GameData)>,
+}
+
+impl History {
+    pub fn new(start: (PiecePlacements, GameData)) -> History {
+        History {
+            moves: Vec::new(),
+            checkpoints: vec![start],
+        }
+    }
+
+    // From every position of a game, positions[i + 1] being positions[i] after moves[i].
+    pub fn from_positions(positions: &[(PiecePlacements, GameData)], moves: &[String]) -> History {
+        assert_eq!(positions.len(), moves.len() + 1);
+        History {
+            moves: moves.to_vec(),
+            checkpoints: positions
+                .iter()
+                .step_by(CHECKPOINT_INTERVAL)
+                .copied()
+                .collect(),
+        }
+    }
+
+    // From the moves and the positions at every checkpoint, as snapshots keep them.
+    pub fn from_checkpoints(
+        moves: Vec<String>,
+        checkpoints: Vec<(PiecePlacements, GameData)>,
+    ) -> Result<History, String> {
+        if checkpoints.len() != moves.len() / CHECKPOINT_INTERVAL + 1 {
+            return Err(format!(
+                "{} checkpoints for {} moves",
+                checkpoints.len(),
+                moves.len()
+            ));
+        }
+        Ok(History { moves, checkpoints })
+    }
+
+    pub fn start(&self) -> (PiecePlacements, GameData) {
+        self.checkpoints[0]
+    }
+
+    pub fn moves(&self) -> &[String] {
+        &self.moves
+    }
+
+    pub fn checkpoints(&self) -> &[(PiecePlacements, GameData)] {
+        &self.checkpoints
+    }
+
+    // Adds a move, and the position it led to.
+    pub fn push(&mut self, m: String, after: (PiecePlacements, GameData)) {
+        self.moves.push(m);
+        if self.moves.len().is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(after);
+        }
+    }
+
+    // Keeps the first moves, e.g. to take back the rest.
+    pub fn truncate(&mut self, moves: usize) {
+        self.moves.truncate(moves);
+        self.checkpoints
+            .truncate(self.moves.len() / CHECKPOINT_INTERVAL + 1);
+    }
+
+    // The position after the first i moves, replaying them from the checkpoint before it.
+    pub fn position(&self, rules: &Rules, i: usize) -> Result<(PiecePlacements, GameData), String> {
+        if i > self.moves.len() {
+            return Err(format!("no position after move {}", i));
+        }
+        let checkpoint = i / CHECKPOINT_INTERVAL;
+        let mut position = self.checkpoints[checkpoint];
+        for m in &self.moves[checkpoint * CHECKPOINT_INTERVAL..i] {
+            position = play(rules, position, m)?;
+        }
+        Ok(position)
+    }
+
+    // Every position, from the start, replaying each move once.
+    pub fn positions(&self, rules: &Rules) -> Result<Vec<(PiecePlacements, GameData)>, String> {
+        let mut positions = vec![self.start()];
+        for m in &self.moves {
+            positions.push(play(rules, *positions.last().unwrap(), m)?);
+        }
+        Ok(positions)
+    }
+}
+
+fn play(
+    rules: &Rules,
+    (mut pp, gd): (PiecePlacements, GameData),
+    s: &str,
+) -> Result<(PiecePlacements, GameData), String> {
+    let (p, m) = parse_long_algebraic(rules, &pp, gd, s)
+        .ok_or_else(|| format!("illegal move in history: {}", s))?;
+    Rules::make_move(p, m, &mut pp);
+    let gd = GameData {
+        ply: m.game_data.ply + 1,
+        ..m.game_data
+    };
+    Ok((pp, gd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let rules = Rules::defaults();
+        let start = (rules.initial_placements(), rules.initial_game_data());
+        let mut history = History::new(start);
+        let mut positions = vec![start];
+        // The knights go out and back, long enough to pass a couple of checkpoints.
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8"]
+            .iter()
+            .cycle()
+            .take(CHECKPOINT_INTERVAL * 2 + 3)
+        {
+            let after = play(&rules, *positions.last().unwrap(), m).unwrap();
+            history.push(m.to_string(), after);
+            positions.push(after);
+        }
+        assert_eq!(history.checkpoints().len(), 3);
+        assert_eq!(history.positions(&rules).unwrap(), positions);
+        for i in [0, 1, CHECKPOINT_INTERVAL, CHECKPOINT_INTERVAL * 2 + 3] {
+            assert_eq!(history.position(&rules, i).unwrap(), positions[i]);
+        }
+        assert!(history.position(&rules, positions.len()).is_err());
+        assert_eq!(
+            History::from_positions(&positions, history.moves()),
+            history
+        );
+
+        history.truncate(CHECKPOINT_INTERVAL - 1);
+        assert_eq!(history.checkpoints().len(), 1);
+        assert_eq!(
+            history.position(&rules, CHECKPOINT_INTERVAL - 1).unwrap(),
+            positions[CHECKPOINT_INTERVAL - 1]
+        );
+        assert!(History::from_checkpoints(vec!["e2e4".to_string()], vec![]).is_err());
+    }
+}
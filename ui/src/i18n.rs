@@ -0,0 +1,332 @@
+// The text the game shows players, in their language. Each message has a key, and English is built
+// in. Other languages come from language packs, assets/i18n/<locale>.json, which map keys to
+// translations. Keys a pack leaves out are shown in English, so a pack can be partial. Messages
+// can have arguments, written {name}, which are filled in wherever the translation puts them.
+// The page picks the language with set_locale, and the desktop binary goes by LANG.
+
+use std::{collections::HashMap, fmt::Display, sync::Mutex};
+
+use macroquad::file::load_string;
+
+use crate::warn;
+
+const ENGLISH: [(&str, &str); 105] = [
+    ("menu.title", "Chess"),
+    ("menu.two_players", "Two players"),
+    ("menu.vs_computer", "Vs computer"),
+    ("menu.online", "Online"),
+    ("menu.resume", "Resume previous game"),
+    ("menu.teaching", "Teaching"),
+    ("presence.connected", "Both players connected"),
+    ("presence.waiting", "Waiting for a player"),
+    ("presence.watching", "{count} watching"),
+    ("presence.spectating", "Watching"),
+    ("color.white", "white"),
+    ("color.black", "black"),
+    ("result.white_wins", "White wins"),
+    ("result.black_wins", "Black wins"),
+    ("result.draw", "Draw"),
+    ("result.aborted", "Game aborted"),
+    ("game_over", "Game over: {result}"),
+    (
+        "game_over.abandoned",
+        "Game over, the other player left: {result}",
+    ),
+    (
+        "game_over.aborted",
+        "Game aborted before both players moved",
+    ),
+    ("game_over.no_moves", "The computer has no moves, game over"),
+    ("game_over.resignation", "{result} by resignation"),
+    ("game_over.agreement", "Draw agreed"),
+    ("game_over.checkmate", "Checkmate: {result}"),
+    ("game_over.stalemate", "Draw by stalemate"),
+    (
+        "online.created",
+        "Created game. Join with: --join {game_id}",
+    ),
+    ("online.opponent_joined", "Opponent joined, playing {color}"),
+    ("online.disconnected", "Disconnected from server"),
+    ("online.denied", "This game is private"),
+    (
+        "takeback.requested",
+        "The other player asks to take back moves",
+    ),
+    (
+        "takeback.declined",
+        "The other player declined the takeback",
+    ),
+    ("takeback.none", "No move to take back"),
+    ("draw.offered", "You offered a draw"),
+    ("draw.received", "The other player offers a draw"),
+    ("draw.declined", "The computer declines the draw"),
+    ("refused.not_your_piece", "Not your piece"),
+    ("refused.not_your_turn", "Not your turn"),
+    (
+        "abort.allowed",
+        "The other player hasn't made their first move. Press X to abort the game.",
+    ),
+    (
+        "abort.not_allowed",
+        "The game can only be aborted before both players have moved",
+    ),
+    (
+        "shared_analysis.started",
+        "Analyzing the game together: moves on the board are shown to the other player",
+    ),
+    (
+        "shared_analysis.not_over",
+        "Online games can be analyzed together once they're over",
+    ),
+    ("paste.loaded", "Pasted the position, analyzing it"),
+    ("paste.invalid", "Couldn't paste the position: {error}"),
+    ("puzzle.started", "Puzzle: find the best move for {color}"),
+    ("puzzle.wrong", "That's not it, try again"),
+    ("puzzle.solved", "Puzzle solved!"),
+    (
+        "puzzle.solved_mistakes",
+        "Puzzle solved, after {count} wrong moves",
+    ),
+    ("guess.started", "Guess the moves for {color}"),
+    ("guess.right", "That's the game's move: {points} points"),
+    (
+        "guess.scored",
+        "The game went {move}: {points} points for yours",
+    ),
+    (
+        "guess.finished",
+        "All moves guessed: {points} of {max} points",
+    ),
+    ("piece.P", "White pawn"),
+    ("piece.N", "White knight"),
+    ("piece.B", "White bishop"),
+    ("piece.R", "White rook"),
+    ("piece.Q", "White queen"),
+    ("piece.K", "White king"),
+    ("piece.p", "Black pawn"),
+    ("piece.n", "Black knight"),
+    ("piece.b", "Black bishop"),
+    ("piece.r", "Black rook"),
+    ("piece.q", "Black queen"),
+    ("piece.k", "Black king"),
+    ("piece.*", "Duck"),
+    ("announce.move", "{piece} from {from} to {to}"),
+    ("announce.place", "{piece} placed on {to}"),
+    (
+        "announce.capture",
+        "{piece} from {from} to {to}, takes {captured}",
+    ),
+    (
+        "announce.captures",
+        "{piece} from {from} to {to}, takes {count} pieces",
+    ),
+    ("announce.castle_kingside", "{piece} castles kingside"),
+    ("announce.castle_queenside", "{piece} castles queenside"),
+    ("announce.promotion", "{move}, promotes to {piece}"),
+    ("announce.check", "{move}, check"),
+    ("announce.pass_white", "White passes"),
+    ("announce.pass_black", "Black passes"),
+    ("pass.not_allowed", "You can't pass now"),
+    ("teaching.hanging", "{piece} on {square} is hanging"),
+    ("button.resign", "Resign"),
+    ("button.offer_draw", "Offer draw"),
+    ("button.accept_draw", "Accept draw"),
+    ("button.flip", "Flip board"),
+    ("button.settings", "Settings"),
+    ("button.export", "Export"),
+    ("settings.coordinates", "Coordinates"),
+    ("settings.click", "Move pieces by clicking"),
+    ("settings.auto_queen", "Always promote to a queen"),
+    ("settings.ponder", "Computer thinks on your time"),
+    ("settings.blunder_check", "Ask before blunders"),
+    ("settings.theme", "Next theme"),
+    ("dialog.yes", "Yes"),
+    ("dialog.no", "No"),
+    ("dialog.accept", "Accept"),
+    ("dialog.decline", "Decline"),
+    ("dialog.resign", "Resign the game?"),
+    (
+        "dialog.blunder",
+        "This move loses material or allows mate. Play it anyway?",
+    ),
+    ("dialog.promotion", "Promote to"),
+    ("dialog.close", "Close"),
+    ("ending.rematch", "Rematch"),
+    ("ending.analyze", "Analyze"),
+    ("ending.checkmate", "By checkmate"),
+    ("ending.stalemate", "By stalemate"),
+    ("ending.no_moves", "No moves left"),
+    ("ending.resignation", "By resignation"),
+    ("ending.agreement", "By agreement"),
+    ("ending.time_forfeit", "On time"),
+    ("ending.abandoned", "The other player left"),
+    ("ending.aborted", "Before both players moved"),
+];
+
+// The current language pack. None for English.
+static PACK: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+// The message with key in the current language.
+pub fn tr(key: &str) -> String {
+    tr_with(key, &[])
+}
+
+// The message with key in the current language, with its arguments filled in.
+pub fn tr_with(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = {
+        let pack = PACK.lock().unwrap();
+        let text = pack.as_ref().and_then(|p| p.get(key)).map(String::as_str);
+        text.or_else(|| english(key)).unwrap_or(key).to_string()
+    };
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
+
+fn english(key: &str) -> Option<&'static str> {
+    ENGLISH.iter().find(|&&(k, _)| k == key).map(|&(_, t)| t)
+}
+
+pub fn has_message(key: &str) -> bool {
+    english(key).is_some()
+}
+
+// E.g. "Game over: White wins". reason is a PGN Termination, e.g. "abandoned".
+pub fn game_over_text(result: &str, reason: &str) -> String {
+    let key = match reason {
+        "abandoned" => "game_over.abandoned",
+        "aborted" => "game_over.aborted",
+        "resignation" => "game_over.resignation",
+        "agreement" => "game_over.agreement",
+        "checkmate" => "game_over.checkmate",
+        "stalemate" => "game_over.stalemate",
+        _ => "game_over",
+    };
+    tr_with(key, &[("result", &result_text(result))])
+}
+
+// How a game ended, e.g. "By checkmate", for the game over screen. reason is one of the reasons
+// the game ends with (see main.rs) or a PGN Termination, and None if there's nothing more to say.
+pub fn reason_text(reason: &str) -> Option<String> {
+    let key = match reason {
+        "checkmate" => "ending.checkmate",
+        "stalemate" => "ending.stalemate",
+        "no_moves" => "ending.no_moves",
+        "resignation" => "ending.resignation",
+        "agreement" => "ending.agreement",
+        "time forfeit" => "ending.time_forfeit",
+        "abandoned" => "ending.abandoned",
+        "aborted" => "ending.aborted",
+        _ => return None,
+    };
+    Some(tr(key))
+}
+
+// The result of a game, e.g. "White wins" for "1-0".
+pub fn result_text(result: &str) -> String {
+    tr(match result {
+        "1-0" => "result.white_wins",
+        "0-1" => "result.black_wins",
+        "1/2-1/2" => "result.draw",
+        _ => "result.aborted",
+    })
+}
+
+// A pack's translations, keeping only the messages the game has. A translation has to have the
+// same arguments as the English message, or it's left out too, with a warning.
+fn parse_pack(json: &str) -> Result<HashMap<String, String>, String> {
+    let messages: HashMap<String, String> =
+        serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut pack = HashMap::new();
+    for (key, text) in messages {
+        match english(&key) {
+            None => warn!("Ignoring unknown message {}", key),
+            Some(en) if arguments(en) != arguments(&text) => {
+                warn!(
+                    "Ignoring {}: it should have the arguments {:?}",
+                    key,
+                    arguments(en)
+                )
+            }
+            Some(_) => {
+                pack.insert(key, text);
+            }
+        }
+    }
+    Ok(pack)
+}
+
+// The names in braces, sorted.
+fn arguments(text: &str) -> Vec<&str> {
+    let mut args: Vec<&str> = text
+        .split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}').map(|(name, _)| name))
+        .collect();
+    args.sort_unstable();
+    args
+}
+
+// Switches to the language of locale, a tag like "fr" or "pt-BR". Without a pack for the whole
+// tag, its language's pack is used, e.g. pt.json. If there's neither, the language stays as it is.
+pub async fn set_locale(locale: &str) -> Result<(), String> {
+    let valid = |t: &str| !t.is_empty() && t.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid(locale) {
+        return Err(format!("Invalid locale {:?}", locale));
+    }
+    let language = locale.split('-').next().unwrap();
+    if language.eq_ignore_ascii_case("en") {
+        *PACK.lock().unwrap() = None;
+        return Ok(());
+    }
+    for tag in [locale, language] {
+        if let Ok(json) = load_string(&format!("assets/i18n/{}.json", tag)).await {
+            let pack = parse_pack(&json).map_err(|e| format!("Bad pack for {}: {}", tag, e))?;
+            *PACK.lock().unwrap() = Some(pack);
+            return Ok(());
+        }
+    }
+    Err(format!("No language pack for {}", locale))
+}
+
+// The locale LANG asks for, e.g. "pt-BR" for pt_BR.UTF-8. None if it's unset or "C".
+#[cfg(not(target_arch = "wasm32"))]
+pub fn system_locale() -> Option<String> {
+    let lang = std::env::var("LANG").ok()?;
+    let tag = lang.split(['.', '@']).next()?.replace('_', "-");
+    (!tag.is_empty() && tag != "C" && tag != "POSIX").then_some(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packs() {
+        // Every pack is complete and has the right arguments.
+        for entry in std::fs::read_dir("assets/i18n").unwrap() {
+            let path = entry.unwrap().path();
+            let json = std::fs::read_to_string(&path).unwrap();
+            let pack = parse_pack(&json).unwrap();
+            for (key, _) in ENGLISH {
+                assert!(pack.contains_key(key), "{} has no {}", path.display(), key);
+            }
+        }
+
+        let pack = parse_pack(
+            r#"{"presence.watching": "{count} regardent", "game_over": "Fini", "nope": "x"}"#,
+        )
+        .unwrap();
+        assert_eq!(pack.len(), 1);
+        assert_eq!(english("presence.watching"), Some("{count} watching"));
+        assert_eq!(tr_with("presence.watching", &[("count", &3)]), "3 watching");
+        assert_eq!(tr("no.such.key"), "no.such.key");
+        assert_eq!(game_over_text("1-0", "checkmate"), "Checkmate: White wins");
+        assert_eq!(
+            reason_text("resignation").as_deref(),
+            Some("By resignation")
+        );
+        assert_eq!(reason_text("normal"), None);
+    }
+}
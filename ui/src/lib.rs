@@ -0,0 +1,23 @@
+#![feature(trait_alias)]
+
+// The parts of the game that don't need a window: the rules, the game itself as a state machine over
+// the player's input (core.rs), and reading and writing positions, games, network messages and game
+// links. The game itself (main.rs) is built on these, and they can be used on
+// their own, e.g. by the fuzz targets in fuzz/.
+
+pub mod core;
+pub mod draughts;
+pub mod history;
+pub mod link;
+pub mod notation;
+pub mod protocol;
+pub mod rules;
+pub mod ruleset;
+pub mod snapshot;
+pub mod zones;
+
+pub mod prelude {
+    pub const SQUARE_SIZE: f32 = 90.0; // TODO: get from rules
+    pub use crate::rules::*;
+    pub use crate::zones::*;
+}
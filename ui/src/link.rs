@@ -0,0 +1,177 @@
+// Game links, so the page, the desktop binary and whoever shares a link agree on how a URL names
+// an online game. The link's fragment is "#/game/<game ID>", optionally with the color the link
+// is for after it, e.g. "#/game/<game ID>/black", which shows the board from that side while
+// joining. A private game's link for its players ends with its passcode, e.g.
+// "#/game/<game ID>/black?passcode=<passcode>", which gets them a seat. Links from before,
+// "#join=<game ID>", still open.
+
+use serde_json::json;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameLink {
+    pub game_id: String,
+    // 0 for white, 1 for black.
+    pub color: Option<usize>,
+    // For private games (see server/src/main.rs).
+    pub passcode: Option<String>,
+}
+
+const COLORS: [&str; 2] = ["white", "black"];
+// As the server allows them, so they don't need escaping.
+const MAX_PASSCODE_LEN: usize = 64;
+
+impl GameLink {
+    // Reads a link, its fragment, or just the part after "#".
+    pub fn parse(link: &str) -> Result<GameLink, String> {
+        let fragment = link.rsplit_once('#').map_or(link, |(_, f)| f);
+        if let Some(game_id) = fragment.strip_prefix("join=") {
+            return GameLink::new(game_id, None);
+        }
+        let (fragment, passcode) = match fragment.split_once('?') {
+            Some((f, query)) => {
+                let passcode = query
+                    .strip_prefix("passcode=")
+                    .ok_or_else(|| format!("Not a game link: {}", link))?;
+                (f, Some(passcode))
+            }
+            None => (fragment, None),
+        };
+        let mut parts = fragment.trim_start_matches('/').split('/');
+        if parts.next() != Some("game") {
+            return Err(format!("Not a game link: {}", link));
+        }
+        let game_id = parts.next().unwrap_or_default();
+        let color = match parts.next() {
+            None | Some("") => None,
+            Some(c) => Some(
+                COLORS
+                    .iter()
+                    .position(|&name| name == c)
+                    .ok_or_else(|| format!("Unknown color in game link: {}", c))?,
+            ),
+        };
+        if parts.next().is_some() {
+            return Err(format!("Not a game link: {}", link));
+        }
+        let link = GameLink::new(game_id, color)?;
+        match passcode {
+            Some(p) => link.with_passcode(p),
+            None => Ok(link),
+        }
+    }
+
+    // Game IDs are UUIDs, as the server makes them.
+    pub fn new(game_id: &str, color: Option<usize>) -> Result<GameLink, String> {
+        let uuid = game_id.len() == 36
+            && game_id.char_indices().all(|(i, c)| match i {
+                8 | 13 | 18 | 23 => c == '-',
+                _ => c.is_ascii_hexdigit(),
+            });
+        if !uuid {
+            return Err(format!("Invalid game ID: {}", game_id));
+        }
+        if color.is_some_and(|c| c >= COLORS.len()) {
+            return Err("Invalid color".to_string());
+        }
+        Ok(GameLink {
+            game_id: game_id.to_ascii_lowercase(),
+            color,
+            passcode: None,
+        })
+    }
+
+    // The link for the players of a private game.
+    pub fn with_passcode(self, passcode: &str) -> Result<GameLink, String> {
+        let valid = (1..=MAX_PASSCODE_LEN).contains(&passcode.len())
+            && passcode
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err("Invalid passcode".to_string());
+        }
+        Ok(GameLink {
+            passcode: Some(passcode.to_string()),
+            ..self
+        })
+    }
+
+    // E.g. "?passcode=<passcode>", or "" for a link without one.
+    fn query(&self) -> String {
+        self.passcode
+            .as_ref()
+            .map_or(String::new(), |p| format!("?passcode={}", p))
+    }
+
+    // E.g. "#/game/<game ID>/white".
+    pub fn fragment(&self) -> String {
+        match self.color {
+            Some(c) => format!("#/game/{}/{}{}", self.game_id, COLORS[c], self.query()),
+            None => format!("#/game/{}{}", self.game_id, self.query()),
+        }
+    }
+
+    // Where on the server to connect to join the game, e.g. "join/<game ID>".
+    pub fn join_path(&self) -> String {
+        format!("join/{}{}", self.game_id, self.query())
+    }
+
+    // What the page needs to join the game: {"game_id", "color", "passcode", "path"}, where color
+    // is "white", "black" or null, and passcode is null for a link without one.
+    pub fn to_json(&self) -> String {
+        json!({
+            "game_id": self.game_id,
+            "color": self.color.map(|c| COLORS[c]),
+            "passcode": self.passcode,
+            "path": self.join_path(),
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_link() {
+        let id = "0f8fad5b-d9cb-469f-a165-70867728950e";
+        let link = GameLink::parse(&format!("https://example.com/ui/#/game/{}/black", id)).unwrap();
+        assert_eq!(link.color, Some(1));
+        assert_eq!(link.fragment(), format!("#/game/{}/black", id));
+        assert_eq!(GameLink::parse(&link.fragment()), Ok(link.clone()));
+        assert_eq!(
+            link.to_json(),
+            format!(
+                r#"{{"color":"black","game_id":"{}","passcode":null,"path":"join/{}"}}"#,
+                id, id
+            )
+        );
+        let old = GameLink::parse(&format!("#join={}", id.to_uppercase())).unwrap();
+        assert_eq!((old.game_id.as_str(), old.color), (id, None));
+        assert_eq!(
+            GameLink::parse(&format!("/game/{}/", id)).unwrap().color,
+            None
+        );
+        let private = link.clone().with_passcode("s3cret").unwrap();
+        assert_eq!(
+            private.fragment(),
+            format!("#/game/{}/black?passcode=s3cret", id)
+        );
+        assert_eq!(private.join_path(), format!("join/{}?passcode=s3cret", id));
+        assert_eq!(GameLink::parse(&private.fragment()), Ok(private));
+        assert!(link.clone().with_passcode("two words").is_err());
+
+        for bad in [
+            "".to_string(),
+            "#/game/".to_string(),
+            "#/game/not-a-uuid".to_string(),
+            format!("#/game/{}/green", id),
+            format!("#/game/{}/white/extra", id),
+            format!("#/games/{}", id),
+            format!("#/game/{}?passcode=", id),
+            format!("#/game/{}?color=white", id),
+        ] {
+            assert!(GameLink::parse(&bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -1,18 +1,163 @@
-use std::ffi::CString;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-extern "C" {
-    // From miniquad
-    fn console_log(msg: *const ::std::os::raw::c_char);
+use crate::mem::alloc_bytes;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+// Anything below this level is dropped without being formatted. Levels above Error turn logging
+// off entirely.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+// Recent log lines, kept so they can be attached to bug reports.
+const DEFAULT_HISTORY_LINES: usize = 200;
+static HISTORY_LINES: AtomicUsize = AtomicUsize::new(DEFAULT_HISTORY_LINES);
+static HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+pub fn log_enabled(level: Level) -> bool {
+    level as u8 >= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn write_log(level: Level, s: &str) {
+    let line = format!("{} {}", level.label(), s);
+    wrap_log(&line);
+    let max = HISTORY_LINES.load(Ordering::Relaxed);
+    if max > 0 {
+        let mut history = HISTORY.lock().unwrap();
+        while history.len() >= max {
+            history.pop_front();
+        }
+        history.push_back(line);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn wrap_log(s: &str) {
+    crate::events::dispatch(crate::events::Event::Log(s));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn wrap_log(s: &str) {
+    eprintln!("{}", s);
+}
+
+// 0 = trace, 1 = debug, 2 = info (the default), 3 = warn, 4 = error, anything higher is off.
+#[no_mangle]
+pub extern "C" fn set_log_level(n: u32) {
+    LOG_LEVEL.store(n.min(u8::MAX as u32) as u8, Ordering::Relaxed);
+}
+
+// How many recent lines to keep for log_history. 0 turns the history off and clears it.
+#[no_mangle]
+pub extern "C" fn set_log_history(lines: u32) {
+    let max = lines as usize;
+    HISTORY_LINES.store(max, Ordering::Relaxed);
+    let mut history = HISTORY.lock().unwrap();
+    while history.len() > max {
+        history.pop_front();
+    }
 }
 
-pub fn wrap_log(s: &str) {
-    let cs = CString::new(s).unwrap();
-    unsafe {
-        console_log(cs.as_ptr());
+// The recent log lines, newline separated.
+pub fn recent_logs() -> String {
+    let history = HISTORY.lock().unwrap();
+    let mut s = String::new();
+    for line in history.iter() {
+        s.push_str(line);
+        s.push('\n');
     }
+    s
+}
+
+// Returns a pointer to the recent log lines. Free it when done.
+#[no_mangle]
+pub extern "C" fn log_history() -> *mut u8 {
+    alloc_bytes(recent_logs().as_bytes())
 }
 
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($t:tt)*) => {
+        if $crate::logging::log_enabled($level) {
+            $crate::logging::write_log($level, &format!($($t)*))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($t:tt)*) => ($crate::log_at!($crate::logging::Level::Trace, $($t)*))
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($t:tt)*) => ($crate::log_at!($crate::logging::Level::Debug, $($t)*))
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($t:tt)*) => ($crate::log_at!($crate::logging::Level::Info, $($t)*))
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($t:tt)*) => ($crate::log_at!($crate::logging::Level::Warn, $($t)*))
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($t:tt)*) => ($crate::log_at!($crate::logging::Level::Error, $($t)*))
+}
+
+// Same as info!
 #[macro_export]
 macro_rules! log {
-    ($($t:tt)*) => (wrap_log(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => ($crate::info!($($t)*))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_and_history() {
+        set_log_history(2);
+        set_log_level(Level::Warn as u32);
+        info!("dropped");
+        warn!("first {}", 1);
+        error!("second");
+        log!("also dropped");
+        assert!(!log_enabled(Level::Debug));
+        set_log_level(Level::Debug as u32);
+        debug!("third");
+        assert!(log_enabled(Level::Debug));
+
+        assert_eq!(recent_logs(), "ERROR second\nDEBUG third\n");
+
+        set_log_level(99);
+        assert!(!log_enabled(Level::Error));
+        set_log_history(0);
+        set_log_level(Level::Info as u32);
+    }
 }
@@ -1,293 +1,3937 @@
-#![feature(trait_alias)]
-
-use std::{collections::HashMap, panic, sync::Mutex};
+use std::{
+    collections::{BTreeMap, HashMap},
+    panic,
+    sync::Mutex,
+};
 
 use macroquad::prelude::*;
 
+use chess_ui::{
+    core::{GameCore, Pointer, PointerEvent, Refusal, Turn},
+    history::History,
+    link::GameLink,
+    notation::{
+        annotated_pgn, check_position, fen, long_algebraic, parse_fen, parse_long_algebraic,
+        parse_pgn, parse_san, san, square_name, Notes,
+    },
+    protocol::{
+        self, Adjudication, Message as ProtocolMessage, MoveFrame, Presence, MOVE_CAPTURE,
+        MOVE_CASTLE, MOVE_EN_PASSANT, MOVE_PASS, MOVE_PHASE, MOVE_PHASE_SHIFT,
+    },
+    snapshot::{self, Snapshot},
+};
+
+mod analysis;
+mod announce;
+mod autosave;
+#[cfg(not(target_arch = "wasm32"))]
+mod balance;
+#[cfg(not(target_arch = "wasm32"))]
+mod batch;
+mod blunder;
+#[cfg(not(target_arch = "wasm32"))]
+mod buttons;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod controls;
+mod crash;
+mod decorations;
+mod dialog;
+mod effects;
+#[cfg(not(target_arch = "wasm32"))]
+mod embed;
+mod engine;
+#[cfg(not(target_arch = "wasm32"))]
+mod epd;
+mod events;
+mod explorer;
+mod export;
+#[cfg(not(target_arch = "wasm32"))]
+mod fairplay;
+#[cfg(not(target_arch = "wasm32"))]
+mod ffi;
+mod flip;
+#[cfg(not(target_arch = "wasm32"))]
+mod grid;
+mod guess;
+mod i18n;
 mod logging;
 mod mem;
-mod rules;
+mod menu;
+#[cfg(not(target_arch = "wasm32"))]
+mod net;
+#[cfg(feature = "nnue")]
+mod nnue;
+mod playback;
+mod puzzle_mode;
+#[cfg(not(target_arch = "wasm32"))]
+mod puzzles;
+mod recording;
+#[cfg(not(target_arch = "wasm32"))]
+mod replay;
+#[cfg(not(target_arch = "wasm32"))]
+mod reproduce;
+mod scheduler;
+#[cfg(not(target_arch = "wasm32"))]
+mod selfplay;
+mod settings;
+mod teaching;
+#[cfg(not(target_arch = "wasm32"))]
+mod tuning;
+#[cfg(not(target_arch = "wasm32"))]
+mod uci;
+mod variations;
+mod viewport;
 mod prelude {
-    pub const SQUARE_SIZE: f32 = 90.0; // TODO: get from rules
-    pub use crate::logging::*;
     pub use crate::mem::*;
-    pub use crate::rules::*;
+    pub use chess_ui::prelude::*;
 }
 
+use analysis::{
+    draw_analysis, draw_search_panel, format_score, line_at, line_position, remote_info,
+    search_header_at, Analyzer,
+};
+use autosave::{Autosave, Saved};
+#[cfg(not(target_arch = "wasm32"))]
+use buttons::{Clicked, Command, Setting};
+use controls::InputEvent;
+use decorations::Decorations;
+use dialog::{Answer, Dialog};
+use effects::{EffectKind, Effects};
+use engine::{see, Engine, EngineConfig, EngineOptions, Thinking};
+use events::{dispatch, Event};
+use explorer::{draw_explorer_panel, explorer_header_at, Explorer};
+use export::ImageOptions;
+use flip::FlipAnimation;
+use guess::{Guessing, MAX_POINTS};
+use i18n::{tr, tr_with};
+use menu::{Choice, GameMode, Menu};
+use playback::Playback;
 use prelude::*;
+use puzzle_mode::{Puzzle, Step};
+use recording::{Button, Input};
+use scheduler::{Task, Tasks};
+use settings::{InputMode, Palette, Settings};
+use teaching::Aids;
+use variations::Path;
+use viewport::Viewport;
+
+// How long a message stays on the status line.
+const STATUS_SECONDS: f64 = 4.0;
+
+// We shouldn't really need a mutex since JS is single-threaded, but it provides
+// a warm fuzzy feeling.
+static JS_MOVE: Mutex<Option<MoveFrame>> = Mutex::new(None);
+
+// So JS can tell WASM to make a move
+#[no_mangle]
+pub extern "C" fn make_move_from_js(
+    src_row: usize,
+    src_col: usize,
+    dst_row: usize,
+    dst_col: usize,
+) {
+    debug!("Got a move from JS!");
+    let mut m = JS_MOVE.lock().unwrap();
+    *m = Some(MoveFrame {
+        src_row,
+        src_col,
+        dst_row,
+        dst_col,
+        ..Default::default()
+    })
+}
+
+// Makes the other player's move, as received from the server. binary is 1 for a binary move
+// frame and 0 for a JSON message.
+#[no_mangle]
+pub extern "C" fn receive_move(move_ptr: *const u8, binary: u32) {
+    let decoded = read_bytes(move_ptr).and_then(|b| {
+        if binary != 0 {
+            protocol::decode_binary(&b)
+        } else {
+            String::from_utf8(b)
+                .map_err(|e| e.to_string())
+                .and_then(|s| protocol::decode(&s))
+        }
+    });
+    match decoded {
+        Ok(ProtocolMessage::Move(f)) => {
+            let mut m = JS_MOVE.lock().unwrap();
+            *m = Some(f);
+        }
+        Ok(msg) => warn!("Not a move: {:?}", msg),
+        Err(e) => warn!("Ignoring move: {}", e),
+    }
+}
+
+// Moves in long algebraic notation, for the player at the board.
+static PLAYED_MOVES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Makes a move in long algebraic notation, e.g. "e2e4" or "e7e8q", for the player at the board, as
+// if they'd made it themselves. Illegal moves, and moves when it isn't their turn, are ignored.
+#[no_mangle]
+pub extern "C" fn play_move(move_str_ptr: *const u8) {
+    match read_string(move_str_ptr) {
+        Ok(s) => PLAYED_MOVES.lock().unwrap().push(s),
+        Err(e) => warn!("Ignoring move: {}", e),
+    }
+}
+
+// A position to set up, and the moves in SAN to play from it.
+struct Load {
+    mode: GameMode,
+    start: Option<(PiecePlacements, GameData)>,
+    moves: Vec<String>,
+    // What's annotated on the moves, from PGN.
+    notes: Vec<Notes>,
+    tags: Vec<(String, String)>,
+}
+
+static LOAD: Mutex<Option<Load>> = Mutex::new(None);
+
+// Starts a game between two players at the board from the position in FEN. Returns 1 if it's
+// valid, otherwise 0.
+#[no_mangle]
+pub extern "C" fn load_fen(fen_str_ptr: *const u8) -> u32 {
+    match read_string(fen_str_ptr).and_then(|s| parse_fen(s.trim())) {
+        Ok(start) => {
+            *LOAD.lock().unwrap() = Some(Load {
+                mode: GameMode::HotSeat,
+                start: Some(start),
+                moves: Vec::new(),
+                notes: Vec::new(),
+                tags: Vec::new(),
+            });
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring FEN: {}", e);
+            0
+        }
+    }
+}
+
+// Starts a game between two players at the board from the first game in PGN, from its FEN tag if
+// it has one, and plays its moves up to the first illegal one. Returns 1 if it's valid PGN,
+// otherwise 0.
+#[no_mangle]
+pub extern "C" fn load_pgn(pgn_str_ptr: *const u8) -> u32 {
+    match read_string(pgn_str_ptr).and_then(|s| pgn_load(&s, GameMode::HotSeat)) {
+        Ok(load) => {
+            *LOAD.lock().unwrap() = Some(load);
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring PGN: {}", e);
+            0
+        }
+    }
+}
+
+static GUESS: Mutex<Option<(Load, usize)>> = Mutex::new(None);
+
+// Starts guessing the moves of the first game in PGN for a side, 0 for white or 1 for black (see
+// guess.rs). Returns 1 if it's valid PGN, otherwise 0.
+#[no_mangle]
+pub extern "C" fn load_guess_pgn(pgn_str_ptr: *const u8, side: u32) -> u32 {
+    match read_string(pgn_str_ptr).and_then(|s| pgn_load(&s, GameMode::HotSeat)) {
+        Ok(load) => {
+            *GUESS.lock().unwrap() = Some((load, (side != 0) as usize));
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring PGN: {}", e);
+            0
+        }
+    }
+}
+
+static PUZZLE: Mutex<Option<(Puzzle, (PiecePlacements, GameData))>> = Mutex::new(None);
+
+// Starts the puzzle in JSON (see puzzle_mode.rs), e.g. the puzzle of the day from the server, with
+// the board turned to the side to move. Returns 1 if it's valid, otherwise 0.
+#[no_mangle]
+pub extern "C" fn load_puzzle(json_str_ptr: *const u8) -> u32 {
+    match read_string(json_str_ptr).and_then(|s| Puzzle::parse(&s)) {
+        Ok(puzzle) => {
+            *PUZZLE.lock().unwrap() = Some(puzzle);
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring puzzle: {}", e);
+            0
+        }
+    }
+}
+
+static PASTE: Mutex<Option<Result<(PiecePlacements, GameData), String>>> = Mutex::new(None);
+
+// Analyzes the position in FEN the player pasted, e.g. from the clipboard. Unlike load_fen, it has
+// to be a position that could come up in a game, and if it isn't, the status event says why.
+// Returns 1 if it's loaded, otherwise 0.
+#[no_mangle]
+pub extern "C" fn paste_fen(fen_str_ptr: *const u8) -> u32 {
+    let pasted = read_string(fen_str_ptr).and_then(|s| pasted_position(&s));
+    let loaded = pasted.is_ok() as u32;
+    *PASTE.lock().unwrap() = Some(pasted);
+    loaded
+}
+
+fn pasted_position(s: &str) -> Result<(PiecePlacements, GameData), String> {
+    let (pp, gd) = parse_fen(s.trim())?;
+    check_position(&pp, gd)?;
+    Ok((pp, gd))
+}
+
+fn pgn_load(pgn: &str, mode: GameMode) -> Result<Load, String> {
+    let game = parse_pgn(pgn)?
+        .into_iter()
+        .next()
+        .ok_or("No game in PGN".to_string())?;
+    let start = match game.tags.iter().find(|(t, _)| t == "FEN") {
+        Some((_, fen)) => Some(parse_fen(fen)?),
+        None => None,
+    };
+    Ok(Load {
+        mode,
+        start,
+        moves: game.moves,
+        notes: game.notes,
+        tags: game.tags,
+    })
+}
+
+// The autosave (see autosave.rs), kept up to date by the game loop.
+static AUTOSAVE: Mutex<String> = Mutex::new(String::new());
+static RESUME: Mutex<Option<Saved>> = Mutex::new(None);
+
+// Returns a pointer to the game as JSON, to resume it later with resume_game, or to an empty string
+// if there's nothing to resume. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_autosave() -> *mut u8 {
+    alloc_bytes(AUTOSAVE.lock().unwrap().as_bytes())
+}
+
+// Picks up a game from get_autosave where it was left off. Returns 1 if it's a valid save,
+// otherwise 0.
+#[no_mangle]
+pub extern "C" fn resume_game(json_str_ptr: *const u8) -> u32 {
+    match read_string(json_str_ptr).and_then(|s| Saved::parse(&s)) {
+        Ok(saved) => {
+            *RESUME.lock().unwrap() = Some(saved);
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring saved game: {}", e);
+            0
+        }
+    }
+}
+
+static BINARY_MOVES: Mutex<bool> = Mutex::new(true);
+
+// Sends moves as binary frames (the default) if on is nonzero, or as JSON, which is easier to
+// debug. Either way, both are understood when received.
+#[no_mangle]
+pub extern "C" fn set_binary_moves(on: u32) {
+    let mut b = BINARY_MOVES.lock().unwrap();
+    *b = on != 0;
+}
+
+static PRESENCE: Mutex<Option<Presence>> = Mutex::new(None);
+
+// Who's connected to the online game, from the server's presence messages.
+#[no_mangle]
+pub extern "C" fn set_presence(players: u32, spectators: u32) {
+    let mut p = PRESENCE.lock().unwrap();
+    *p = Some(Presence {
+        players: players as usize,
+        spectators: spectators as usize,
+    });
+}
+
+static SPECTATING: Mutex<Option<bool>> = Mutex::new(None);
+static RATED: Mutex<Option<bool>> = Mutex::new(None);
+
+// Whether we're watching the online game rather than playing it, as the server says when we join.
+// The board is read-only while we are. A new game starts with us playing.
+#[no_mangle]
+pub extern "C" fn set_spectating(spectating: u32) {
+    *SPECTATING.lock().unwrap() = Some(spectating != 0);
+}
+
+// The online game is rated, as the server says when we take a seat, so it's played without the
+// check for blunders. A new game starts unrated.
+#[no_mangle]
+pub extern "C" fn set_rated() {
+    *RATED.lock().unwrap() = Some(true);
+}
+
+// The ply to go back to for the player to take back their last move, or 0 if they haven't moved.
+// Kept up to date by the game loop.
+static TAKEBACK_PLY: Mutex<u16> = Mutex::new(0);
+
+#[no_mangle]
+pub extern "C" fn takeback_ply() -> u32 {
+    *TAKEBACK_PLY.lock().unwrap() as u32
+}
+
+static TAKE_BACK: Mutex<Option<u16>> = Mutex::new(None);
+
+// Goes back to the position at ply, undoing the moves since. Online, JS only calls this once both
+// players have agreed to it (see assets/js/multiplayer.js).
+#[no_mangle]
+pub extern "C" fn take_back(ply: u32) {
+    let mut t = TAKE_BACK.lock().unwrap();
+    *t = u16::try_from(ply).ok();
+}
+
+// Whether the player can abort the online game (see Game::abortable), kept up to date by the game
+// loop.
+static ABORTABLE: Mutex<bool> = Mutex::new(false);
+
+// Returns 1 if the player can abort the online game, e.g. to show a button for it, otherwise 0.
+#[no_mangle]
+pub extern "C" fn abortable() -> u32 {
+    *ABORTABLE.lock().unwrap() as u32
+}
+
+static ABORT_ALLOWED: Mutex<Option<bool>> = Mutex::new(None);
+
+// The server says whether the player can abort the online game: 1 once the other player has taken
+// too long to make their first move, 0 when it refused to abort it.
+#[no_mangle]
+pub extern "C" fn set_abortable(allowed: u32) {
+    *ABORT_ALLOWED.lock().unwrap() = Some(allowed != 0);
+}
+
+static ABORTED: Mutex<bool> = Mutex::new(false);
+
+// The server aborted the online game, at either player's request.
+#[no_mangle]
+pub extern "C" fn game_aborted() {
+    *ABORTED.lock().unwrap() = true;
+}
+
+// Messages from the other player about the board both analyze on after the game: the position
+// and the move that led there, if any.
+static ANALYSIS_MOVES: Mutex<Vec<(String, Option<String>)>> = Mutex::new(Vec::new());
+
+// Takes the other player's message about the board both analyze on after the online game,
+// {"analysis": {"fen", "uci"}} (see protocol.rs). The board follows theirs.
+#[no_mangle]
+pub extern "C" fn receive_analysis(message_str_ptr: *const u8) {
+    match read_string(message_str_ptr).and_then(|s| protocol::decode(&s)) {
+        Ok(ProtocolMessage::Analysis { fen, uci }) => {
+            ANALYSIS_MOVES.lock().unwrap().push((fen, uci));
+        }
+        Ok(msg) => warn!("Not analysis: {:?}", msg),
+        Err(e) => warn!("Ignoring analysis: {}", e),
+    }
+}
+
+static TOGGLE_SHARED_ANALYSIS: Mutex<bool> = Mutex::new(false);
+
+// Once an online game is over, starts analyzing it together with the other player from the
+// position shown, or goes back to the game's final position if already analyzing it.
+#[no_mangle]
+pub extern "C" fn toggle_shared_analysis() {
+    *TOGGLE_SHARED_ANALYSIS.lock().unwrap() = true;
+}
+
+static PASS: Mutex<bool> = Mutex::new(false);
+
+// Passes the player's turn, if the rules let them pass now (see "pass" in the rule set), or
+// otherwise says why not on the status line.
+#[no_mangle]
+pub extern "C" fn pass_turn() {
+    *PASS.lock().unwrap() = true;
+}
+
+static ADJUDICATE: Mutex<Option<Adjudication>> = Mutex::new(None);
+
+// The other player abandoned the online game, and the server awards fallback: 0 for a win, 1 for
+// a draw and 2 to abort. See Adjudication::result for when it doesn't apply.
+#[no_mangle]
+pub extern "C" fn adjudicate(fallback: u32) {
+    let mut a = ADJUDICATE.lock().unwrap();
+    *a = match fallback {
+        0 => Some(Adjudication::Win),
+        1 => Some(Adjudication::Draw),
+        2 => Some(Adjudication::Abort),
+        _ => None,
+    };
+}
+
+static FLIPPED: Mutex<bool> = Mutex::new(false);
+
+#[no_mangle]
+pub extern "C" fn flip_board(flipped: u32) {
+    let mut f = FLIPPED.lock().unwrap();
+    *f = flipped != 0;
+}
+
+static GAME_MODE: Mutex<Option<GameMode>> = Mutex::new(None);
+
+// Starts a new game. mode is 0 for two players on this device, 1 to play against the computer
+// and 2 to play online. strength is only used against the computer.
+#[no_mangle]
+pub extern "C" fn set_game_mode(mode: u32, strength: u32) {
+    let mut m = GAME_MODE.lock().unwrap();
+    *m = GameMode::from_js(mode, strength);
+}
+
+static TEACHING: Mutex<Option<bool>> = Mutex::new(None);
+
+// Turns the teaching preset (see teaching.rs) on if on is 1, or off, for the game being played and
+// the ones started after it. Online games are played without it.
+#[no_mangle]
+pub extern "C" fn set_teaching(on: u32) {
+    *TEACHING.lock().unwrap() = Some(on != 0);
+}
+
+// Opens a game link (see link.rs), e.g. the page's location: starts an online game, shown from the
+// side of the color in the link, if any. Returns a pointer to JSON with what the page needs to
+// join it, {"game_id", "color", "passcode", "path"}, or {"error"} if it isn't a game link. Free it
+// when done.
+#[no_mangle]
+pub extern "C" fn open_game_link(link_str_ptr: *const u8) -> *mut u8 {
+    let json = match read_string(link_str_ptr).and_then(|s| GameLink::parse(&s)) {
+        Ok(link) => {
+            *GAME_MODE.lock().unwrap() = Some(GameMode::Online);
+            if let Some(color) = link.color {
+                *FLIPPED.lock().unwrap() = color == 1;
+            }
+            link.to_json()
+        }
+        Err(e) => serde_json::json!({ "error": e }).to_string(),
+    };
+    alloc_bytes(json.as_bytes())
+}
+
+// Returns a pointer to the fragment of the link to a game, e.g. "#/game/<game ID>/white", for
+// color 0 (white), 1 (black) or 2 (either), with the passcode of a private game unless it's empty.
+// It's empty if the game ID or passcode isn't valid. Free it when done.
+#[no_mangle]
+pub extern "C" fn game_link(
+    game_id_str_ptr: *const u8,
+    color: u32,
+    passcode_str_ptr: *const u8,
+) -> *mut u8 {
+    let color = (color < 2).then_some(color as usize);
+    let fragment = read_string(game_id_str_ptr)
+        .and_then(|id| GameLink::new(&id, color))
+        .and_then(|link| match read_string(passcode_str_ptr)? {
+            p if p.is_empty() => Ok(link),
+            p => link.with_passcode(&p),
+        })
+        .map(|link| link.fragment())
+        .unwrap_or_default();
+    alloc_bytes(fragment.as_bytes())
+}
+
+static ENGINE_OPTIONS: Mutex<Option<EngineOptions>> = Mutex::new(None);
+
+// Overrides the engine level's time limit (unless it's 0) and contempt, in centipawns, and turns off
+// the pruning in no_pruning, if any: 1 for null-move pruning and 2 for late move reductions. The
+// pruning lets the engine see deeper in the same time, so turn it off only to compare. Applies to
+// the current game and later ones.
+#[no_mangle]
+pub extern "C" fn set_engine_options(time_limit_ms: u32, contempt: i32, no_pruning: u32) {
+    let mut o = ENGINE_OPTIONS.lock().unwrap();
+    *o = Some(EngineOptions {
+        time_limit: if time_limit_ms == 0 {
+            None
+        } else {
+            Some(time_limit_ms as f64 / 1000.0)
+        },
+        contempt,
+        null_move: (no_pruning & 1 != 0).then_some(false),
+        late_move_reductions: (no_pruning & 2 != 0).then_some(false),
+    });
+}
+
+// The metadata of the current game, kept up to date by the game loop.
+static GAME_METADATA: Mutex<Option<String>> = Mutex::new(None);
+
+// Returns a pointer to the game's metadata as a JSON object of PGN style tags. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_game_metadata() -> *mut u8 {
+    let m = GAME_METADATA.lock().unwrap();
+    alloc_bytes(m.as_deref().unwrap_or("{}").as_bytes())
+}
+
+static SEEK_VARIATION: Mutex<Option<(Path, usize)>> = Mutex::new(None);
+
+// Shows a move from get_move_list: JSON {"path", "view"}, as the move has them. Moves of
+// variations are played out on the board after the game's moves leading to them, and the game's
+// own go back to the game.
+#[no_mangle]
+pub extern "C" fn seek_variation(json_ptr: *const u8) {
+    let seek = read_string(json_ptr).and_then(|s| {
+        let v: serde_json::Value = serde_json::from_str(&s).map_err(|e| e.to_string())?;
+        let path = serde_json::from_value(v["path"].clone()).map_err(|e| e.to_string())?;
+        let view = v["view"].as_u64().ok_or("No view")?;
+        Ok((path, view as usize))
+    });
+    match seek {
+        Ok(seek) => *SEEK_VARIATION.lock().unwrap() = Some(seek),
+        Err(e) => warn!("Ignoring variation: {}", e),
+    }
+}
+
+static COMMENT: Mutex<Option<String>> = Mutex::new(None);
+
+// Comments on the move leading to the position shown on the board, or with an empty string, removes
+// the comment. Only in analysis: while it's on, or once the game is over.
+#[no_mangle]
+pub extern "C" fn set_comment(comment_ptr: *const u8) {
+    match read_string(comment_ptr) {
+        Ok(s) => *COMMENT.lock().unwrap() = Some(s),
+        Err(e) => warn!("Ignoring comment: {}", e),
+    }
+}
+
+// The game's moves with their annotations, and the game in PGN, kept up to date by the game loop.
+static MOVE_LIST: Mutex<Option<String>> = Mutex::new(None);
+static PGN: Mutex<Option<String>> = Mutex::new(None);
+
+// Returns a pointer to the game's moves as a JSON array, for a move list. Each move is
+// {"path", "view", "san", "nags", "glyphs", "before", "comment", "variations"}, where path and view
+// say where it is, for seek_variation, glyphs are its NAGs written like "!?", before and comment
+// are comments or null, and variations are arrays of the moves that could have been played
+// instead, in the same form. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_move_list() -> *mut u8 {
+    let m = MOVE_LIST.lock().unwrap();
+    alloc_bytes(m.as_deref().unwrap_or("[]").as_bytes())
+}
+
+// Returns a pointer to the game in PGN, with its comments, annotations and variations. Free it
+// when done.
+#[no_mangle]
+pub extern "C" fn get_pgn() -> *mut u8 {
+    let p = PGN.lock().unwrap();
+    alloc_bytes(p.as_deref().unwrap_or("").as_bytes())
+}
+
+// The game as a binary snapshot (see snapshot.rs), kept up to date with PGN.
+static SNAPSHOT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static RESTORE: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+// Returns a pointer to the game's positions and moves as a binary snapshot, to pick the game up
+// again with load_snapshot. It has no comments or variations, but is much smaller than the game's
+// positions as FEN. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_snapshot() -> *mut u8 {
+    alloc_bytes(&SNAPSHOT.lock().unwrap())
+}
+
+// Starts a game between two players at the board from a snapshot from get_snapshot, where it was
+// left off. Returns 1 if it's a valid snapshot, otherwise 0.
+#[no_mangle]
+pub extern "C" fn load_snapshot(snapshot_ptr: *const u8) -> u32 {
+    match read_bytes(snapshot_ptr).and_then(|b| snapshot::decode(&b)) {
+        Ok(s) => {
+            *RESTORE.lock().unwrap() = Some(s);
+            1
+        }
+        Err(e) => {
+            warn!("Ignoring snapshot: {}", e);
+            0
+        }
+    }
+}
+
+// Plies to move through the game's history, forward if positive.
+static VIEW_STEP: Mutex<i32> = Mutex::new(0);
+
+// Shows an earlier (delta < 0) or later (delta > 0) position of the game. Moves can only be made
+// on the current position.
+#[no_mangle]
+pub extern "C" fn step_view(delta: i32) {
+    let mut v = VIEW_STEP.lock().unwrap();
+    *v = v.saturating_add(delta);
+}
+
+// Steps through the game's history a full turn, a move of each player, at a time: back if delta < 0
+// and forward if it's > 0.
+#[no_mangle]
+pub extern "C" fn step_turns(delta: i32) {
+    let mut v = VIEW_STEP.lock().unwrap();
+    *v = v.saturating_add(delta.saturating_mul(2));
+}
+
+static SEEK_VIEW: Mutex<Option<usize>> = Mutex::new(None);
+
+// Shows the position ply moves into the game, 0 for the start, e.g. for a scrubber.
+#[no_mangle]
+pub extern "C" fn seek_view(ply: u32) {
+    let mut s = SEEK_VIEW.lock().unwrap();
+    *s = Some(ply as usize);
+}
+
+static PLAYING: Mutex<Option<bool>> = Mutex::new(None);
+
+// Starts (1) or stops (0) playing through the game's history on its own (see playback.rs).
+// Started on the last position, it plays from the start.
+#[no_mangle]
+pub extern "C" fn set_playing(on: u32) {
+    let mut p = PLAYING.lock().unwrap();
+    *p = Some(on != 0);
+}
+
+static PLAYBACK_SPEED: Mutex<Option<u32>> = Mutex::new(None);
+
+// Sets how fast the game's history plays, in percent of normal speed, a move a second. It's
+// rounded to 25, 50, 100, 200 or 400.
+#[no_mangle]
+pub extern "C" fn set_playback_speed(percent: u32) {
+    let mut s = PLAYBACK_SPEED.lock().unwrap();
+    *s = Some(percent);
+}
+
+static PLAYBACK: Mutex<Option<String>> = Mutex::new(None);
+
+// Where the board is in the game's history, as JSON: {"view", "last", "playing", "speed", "path"},
+// where view is how many moves into the game the position shown is, last how many moves there are,
+// speed how many times normal speed it plays at and path the variation shown (see
+// get_move_list), or [] for the game.
+#[no_mangle]
+pub extern "C" fn get_playback() -> *mut u8 {
+    let p = PLAYBACK.lock().unwrap();
+    alloc_bytes(p.as_deref().unwrap_or("{}").as_bytes())
+}
+
+static ANALYSIS: Mutex<Option<bool>> = Mutex::new(None);
+
+// Turns analysis of the position shown on the board on or off.
+#[no_mangle]
+pub extern "C" fn set_analysis(on: u32) {
+    let mut a = ANALYSIS.lock().unwrap();
+    *a = Some(on != 0);
+}
+
+// Returns a pointer to the recording of what went into the game since it started or was loaded,
+// as JSON (see recording.rs), to attach to a bug report. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_input_recording() -> *mut u8 {
+    let recording = recording::json().unwrap_or_default();
+    alloc_bytes(recording.to_string().as_bytes())
+}
+
+static RULE_DEBUG: Mutex<Option<bool>> = Mutex::new(None);
 
-extern "C" {
-    // JS callbacks
-    fn on_move(piece_ptr: u32, placements_ptr: u32, retval_ptr: u32, retval_len: u32);
-    fn get_player_color() -> usize;
+// Turns the rule debug overlay on or off, like the D key: for the piece under the mouse, every move
+// its movement rules come up with, green if it's allowed and red if a constraint rule rejects it,
+// with the rules' names. For working out why a ruleset or plugin doesn't do what it should.
+#[no_mangle]
+pub extern "C" fn set_rule_debug(on: u32) {
+    *RULE_DEBUG.lock().unwrap() = Some(on != 0);
+}
+
+static REFRESH_DECORATIONS: Mutex<bool> = Mutex::new(false);
+
+// Asks the page again how to decorate the board's squares and pieces (see decorations.rs), e.g.
+// after a plugin's own state changed. Otherwise it's only asked when the position does.
+#[no_mangle]
+pub extern "C" fn refresh_decorations() {
+    *REFRESH_DECORATIONS.lock().unwrap() = true;
+}
+
+// The position shown on the board, kept up to date by the game loop.
+static VIEWED_POSITION: Mutex<Option<(PiecePlacements, GameData)>> = Mutex::new(None);
+
+// Returns a pointer to the position shown on the board in FEN, e.g. to have the server analyze it.
+// Free it when done.
+#[no_mangle]
+pub extern "C" fn get_fen() -> *mut u8 {
+    let fen = match *VIEWED_POSITION.lock().unwrap() {
+        Some((pp, gd)) => fen(&pp, gd),
+        None => String::new(),
+    };
+    alloc_bytes(fen.as_bytes())
+}
+
+// Returns the flags of the piece on a square of the position shown on the board, as SQUARE_FLAGS
+// in ffi.js has them, or 0 if there's no piece there.
+#[no_mangle]
+pub extern "C" fn get_square_flags(row: u32, col: u32) -> u32 {
+    VIEWED_POSITION
+        .lock()
+        .unwrap()
+        .map_or(0, |(pp, _)| pp.flags(row as usize, col as usize) as u32)
+}
+
+// Flags the page set, applied by the game loop.
+static SQUARE_FLAGS: Mutex<Vec<(usize, usize, u8)>> = Mutex::new(Vec::new());
+
+// Sets the flags of the piece on a square of the current position, e.g. for a plugin to count down
+// a piece's charges or freeze it. They're this board's alone, so in online games both sides' pages
+// have to set them alike.
+#[no_mangle]
+pub extern "C" fn set_square_flags(row: u32, col: u32, flags: u32) {
+    SQUARE_FLAGS
+        .lock()
+        .unwrap()
+        .push((row as usize, col as usize, flags as u8));
 }
 
-#[derive(Clone, Copy, Debug)]
-struct JsMove {
-    pub src_row: usize,
-    pub src_col: usize,
-    pub dst_row: usize,
-    pub dst_col: usize,
-}
-// We shouldn't really need a mutex since JS is single-threaded, but it provides
-// a warm fuzzy feeling.
-static JS_MOVE: Mutex<Option<JsMove>> = Mutex::new(None);
+static CLOUD_ANALYSIS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Gives the analysis a message from the server's analysis of a position (see
+// server/src/cloud.rs), as JSON with the position's FEN added, e.g.
+//   {"fen": "...", "info": {"depth": 12, "score": {"cp": 35}, "pv": ["e2e4", "e7e5"]}}
+// It's shown instead of the local analysis while that position is on the board, once it's deeper.
+#[no_mangle]
+pub extern "C" fn set_cloud_analysis(json_str_ptr: *const u8) {
+    match read_string(json_str_ptr) {
+        Ok(s) => CLOUD_ANALYSIS.lock().unwrap().push(s),
+        Err(e) => warn!("Ignoring the cloud analysis: {}", e),
+    }
+}
+
+static EXPLORER: Mutex<Option<String>> = Mutex::new(None);
+
+// Gives the game the server's answer to an explorer_query event: the JSON from
+// /api/explorer?hash=<hash> (see explorer.rs).
+#[no_mangle]
+pub extern "C" fn set_explorer(json_str_ptr: *const u8) {
+    match read_string(json_str_ptr) {
+        Ok(s) => *EXPLORER.lock().unwrap() = Some(s),
+        Err(e) => warn!("Ignoring the opening explorer: {}", e),
+    }
+}
+
+// The game picks up changes on the next frame.
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings::DEFAULT);
+
+// Returns a pointer to the player's settings as a JSON object (see settings.rs), for the page to
+// save. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_settings() -> *mut u8 {
+    alloc_bytes(SETTINGS.lock().unwrap().to_json().as_bytes())
+}
+
+// Changes the settings in a JSON object, e.g. {"coordinates": true}, leaving the others as they
+// are. Nothing changes if any of them is invalid.
+#[no_mangle]
+pub extern "C" fn set_settings(json_str_ptr: *const u8) {
+    let updated = read_string(json_str_ptr).and_then(|s| SETTINGS.lock().unwrap().update(&s));
+    if let Err(e) = updated {
+        warn!("Ignoring settings: {}", e);
+    }
+}
+
+static IMAGE_REQUEST: Mutex<Option<ImageOptions>> = Mutex::new(None);
+
+// Asks for a picture of the position shown on the board, with black at the bottom if flipped,
+// coordinates along the edges, and the last move highlighted, as asked. The game draws it on its
+// next frame and sends it as a PNG in a board_image event.
+#[no_mangle]
+pub extern "C" fn request_board_image(flipped: u32, coordinates: u32, last_move: u32) {
+    *IMAGE_REQUEST.lock().unwrap() = Some(ImageOptions {
+        flipped: flipped != 0,
+        coordinates: coordinates != 0,
+        last_move: last_move != 0,
+    });
+}
+
+static ANIMATION_REQUEST: Mutex<Option<(ImageOptions, u16)>> = Mutex::new(None);
+
+// Asks for an animation of the game so far, from the first position to the current one, showing
+// each position for delay_ms milliseconds (or a second if 0). The other arguments are as for
+// request_board_image. The game draws it on its next frame and sends it as an animated PNG in a
+// game_animation event.
+#[no_mangle]
+pub extern "C" fn request_game_animation(
+    flipped: u32,
+    coordinates: u32,
+    last_move: u32,
+    delay_ms: u32,
+) {
+    let options = ImageOptions {
+        flipped: flipped != 0,
+        coordinates: coordinates != 0,
+        last_move: last_move != 0,
+    };
+    let delay = match delay_ms {
+        0 => export::DEFAULT_DELAY,
+        d => d.min(u16::MAX as u32) as u16,
+    };
+    *ANIMATION_REQUEST.lock().unwrap() = Some((options, delay));
+}
+
+static LOCALE: Mutex<Option<String>> = Mutex::new(None);
+
+// Shows the game's text in the language of a locale like "fr" or "pt-BR", e.g. the browser's
+// navigator.language. English is used for anything there's no translation for.
+#[no_mangle]
+pub extern "C" fn set_locale(locale_str_ptr: *const u8) {
+    match read_string(locale_str_ptr) {
+        Ok(l) => *LOCALE.lock().unwrap() = Some(l),
+        Err(e) => warn!("Ignoring locale: {}", e),
+    }
+}
+
+// Loads the language pack asked for by set_locale, if any.
+async fn handle_locale() {
+    let locale = LOCALE.lock().unwrap().take();
+    if let Some(l) = locale {
+        match i18n::set_locale(&l).await {
+            Ok(()) => log!("Using locale {}", l),
+            Err(e) => warn!("Keeping the current language: {}", e),
+        }
+    }
+}
+
+static RULES_UPDATE: Mutex<Option<HashMap<String, bool>>> = Mutex::new(None);
+
+#[no_mangle]
+pub extern "C" fn rules_update(json_str_ptr: *const u8) {
+    let parsed = read_string(json_str_ptr)
+        .and_then(|s| serde_json::from_str::<HashMap<String, bool>>(&s).map_err(|e| e.to_string()));
+    match parsed {
+        Ok(v) => {
+            let mut r = RULES_UPDATE.lock().unwrap();
+            *r = Some(v);
+        }
+        Err(e) => warn!("Ignoring rules update: {}", e),
+    }
+}
+
+// The version of the plugin interface the game implements. See plugin_handshake.
+#[no_mangle]
+pub extern "C" fn plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
+// Tells the game which plugin hooks the page implements, as JSON: {"abi_version": 3, "hooks":
+// ["movement"]}. Plugins are off until then, and stay off if the page's version isn't the game's.
+#[no_mangle]
+pub extern "C" fn plugin_handshake(json_str_ptr: *const u8) {
+    match read_string(json_str_ptr).and_then(|s| enable_plugins(&s)) {
+        Ok((enabled, ignored)) => {
+            log!("Plugin hooks enabled: {:?}", enabled);
+            if !ignored.is_empty() {
+                warn!("Ignoring unknown plugin hooks: {}", ignored.join(", "));
+            }
+        }
+        Err(e) => warn!("Plugins are off: {}", e),
+    }
+    // A movement plugin can move pieces no other rule does.
+    *CHECK_RULES.lock().unwrap() = true;
+}
+
+static CHECK_RULES: Mutex<bool> = Mutex::new(false);
+// What's wrong with the rules in play (see Rules::check_ruleset), as a JSON array. Kept up to date
+// by the game loop.
+static RULE_PROBLEMS: Mutex<String> = Mutex::new(String::new());
+
+// Returns a pointer to what's wrong with the rules in play, e.g. a piece no movement rule moves, as
+// a JSON array of messages, empty if nothing is. Free it when done.
+#[no_mangle]
+pub extern "C" fn get_rule_problems() -> *mut u8 {
+    let p = RULE_PROBLEMS.lock().unwrap();
+    alloc_bytes(if p.is_empty() { "[]" } else { &p }.as_bytes())
+}
+
+// Checks a rule set as import_ruleset would take it, without playing it. Returns a pointer to
+// what's wrong with it as a JSON array of messages, empty if nothing is. Free it when done.
+#[no_mangle]
+pub extern "C" fn check_ruleset(json_str_ptr: *const u8) -> *mut u8 {
+    let mut rules = Rules::defaults();
+    let problems = match read_string(json_str_ptr).and_then(|s| rules.import_ruleset(&s)) {
+        Ok(()) => rules.check_ruleset(),
+        Err(e) => vec![e],
+    };
+    alloc_bytes(serde_json::Value::from(problems).to_string().as_bytes())
+}
+
+static RULESET_IMPORT: Mutex<Option<String>> = Mutex::new(None);
+// The most recently exported rule set. Kept up to date by the game loop so JS can read it
+// synchronously.
+static RULESET_EXPORT: Mutex<Option<String>> = Mutex::new(None);
+
+#[no_mangle]
+pub extern "C" fn import_ruleset(json_str_ptr: *const u8) {
+    match read_string(json_str_ptr) {
+        Ok(s) => {
+            let mut r = RULESET_IMPORT.lock().unwrap();
+            *r = Some(s);
+        }
+        Err(e) => warn!("Couldn't import ruleset: {}", e),
+    }
+}
+
+// Returns a pointer to the JSON rule set. Free it when done.
+#[no_mangle]
+pub extern "C" fn export_ruleset() -> *mut u8 {
+    let r = RULESET_EXPORT.lock().unwrap();
+    alloc_bytes(r.as_deref().unwrap_or("").as_bytes())
+}
+
+// The game put aside while its players analyze it together.
+struct FinishedGame {
+    history: Vec<(PiecePlacements, GameData)>,
+    moves: Vec<String>,
+    notes: BTreeMap<usize, Notes>,
+}
+
+// What the player answered in a dialog, and what it was about.
+#[derive(Clone, Copy, Debug)]
+enum Reply {
+    // Whether they really resign.
+    #[cfg(not(target_arch = "wasm32"))]
+    Resign(bool),
+    // Whether they accept the other player's draw offer.
+    #[cfg(not(target_arch = "wasm32"))]
+    Draw(bool),
+    // The ply the other player asked to go back to, if they agreed to it.
+    #[cfg(not(target_arch = "wasm32"))]
+    Takeback(Option<u16>),
+    // What the piece promotes to, or None to put it back.
+    Promote(Option<u8>),
+    // The move the player confirmed after being warned it's a blunder, or None if they didn't.
+    Blunder(Option<(usize, Piece, Move)>),
+    // What to do now the game's over.
+    Ending(AfterGame),
+}
+
+// The game over screen's buttons.
+#[derive(Clone, Copy, Debug)]
+enum AfterGame {
+    // A new game of the same kind, offline.
+    Rematch,
+    Analyze,
+    Export,
+    Close,
+}
+
+struct Game<'a> {
+    pieces_sprite: Texture2D,
+    // The rules, the game's positions and moves, and what the player is doing with the pieces.
+    core: GameCore<'a>,
+    // Where on the piece being dragged the player picked it up.
+    drag_offset: (f32, f32),
+    // The square the keyboard's cursor is on, once it's been used (see controls.rs).
+    cursor: Option<(usize, usize)>,
+    // Watching an online game, which the board only shows (see read_only).
+    spectating: bool,
+    // Playing a rated online game, which goes without the check for blunders.
+    rated: bool,
+    // The square of the last piece the player tried to pick up and couldn't, why, and when.
+    refused: Option<((usize, usize), Refusal, f64)>,
+    flipped: bool,
+    player: usize, // 0 for white, 1 for black
+    mode: GameMode,
+    // Shown instead of the board while picking a mode.
+    menu: Option<Menu>,
+    // Questions waiting for the player's answer, shown one at a time over the board, oldest first.
+    dialogs: Vec<Dialog<Reply>>,
+    // The move waiting for the player to pick what the piece promotes to: who's making it, the
+    // piece and the move to the first of the choices.
+    promoting: Option<(usize, Piece, Move)>,
+    // The computer opponent, if playing against it.
+    engine: Option<Engine>,
+    engine_options: EngineOptions,
+    settings: Settings,
+    // The engine's search for its next move, spread over as many frames as it takes.
+    thinking: Option<Thinking>,
+    // The engine's search of the position it expects after the player's reply (see
+    // Settings::ponder).
+    pondering: Option<Thinking>,
+    // PGN style tags describing the game, e.g. who's playing.
+    metadata: BTreeMap<&'static str, String>,
+    // What's annotated on the moves, by index in moves: from the PGN the game was loaded from, and
+    // the player's own comments.
+    notes: BTreeMap<usize, Notes>,
+    // The moves and notes MOVE_LIST and PGN are for.
+    published_moves: (Vec<String>, BTreeMap<usize, Notes>, Option<String>),
+    // Index in history of the position shown on the board.
+    view: usize,
+    // Whether the engine should analyze the position shown on the board.
+    analysis_on: bool,
+    analyzer: Option<Analyzer>,
+    // The analysis line shown on the board as ghost pieces, if any.
+    preview: Option<usize>,
+    // Whether the search panel shows everything, or only its header.
+    search_panel: bool,
+    // The key of the position the opening explorer was last asked about, and its answer once it's
+    // in.
+    explorer_key: Option<String>,
+    explorer: Option<Explorer>,
+    explorer_panel: bool,
+    // The desktop binary's request to the opening explorer, while it's waiting for the answer.
+    #[cfg(not(target_arch = "wasm32"))]
+    explorer_request: Option<std::sync::mpsc::Receiver<Result<String, String>>>,
+    // Who's connected to the online game, once the server has said.
+    presence: Option<Presence>,
+    // The ply of the last drawn position. The engine waits until the player's move has been drawn
+    // before it starts thinking.
+    drawn_ply: u16,
+    // Online play for the desktop binary. In the browser, JS handles this.
+    #[cfg(not(target_arch = "wasm32"))]
+    net: Option<net::Client>,
+    #[cfg(not(target_arch = "wasm32"))]
+    server: String,
+    // Online takebacks for the desktop binary: the ply we asked the other player to go back to.
+    // Theirs are asked in a dialog.
+    #[cfg(not(target_arch = "wasm32"))]
+    takeback_requested: Option<u16>,
+    // The player who offered a draw, until the other player accepts it or moves.
+    #[cfg(not(target_arch = "wasm32"))]
+    draw_offered: Option<usize>,
+    // Whether the desktop binary's settings panel is open (see buttons.rs).
+    #[cfg(not(target_arch = "wasm32"))]
+    settings_open: bool,
+    // The server lets the player abort the online game even though they've made their first move,
+    // since the other player is taking too long to make theirs.
+    abort_allowed: bool,
+    // Once an online game is over, both players can analyze it on a shared board, where either of
+    // them moves pieces for both sides. Meanwhile the game is kept here, and the core's history and
+    // moves are the shared board's.
+    shared_analysis: Option<FinishedGame>,
+    // In analysis, moves made on an earlier position play out a variation (see variations.rs).
+    // Meanwhile the game is kept here, and the core's history and moves are the board's,
+    // showing the variation after the moves leading to it.
+    variation: Option<(FinishedGame, Path)>,
+    // How JS plugins decorate the position shown.
+    decorations: Decorations,
+    // Whether the rule debug overlay is shown (see set_rule_debug).
+    rule_debug: bool,
+    // Captures, promotions and such being shown.
+    effects: Effects,
+    // The board turning around, after it's been flipped.
+    flipping: Option<FlipAnimation>,
+    playback: Playback,
+    viewport: Viewport,
+    autosave: Autosave,
+    // The message on the status line, and when it was shown.
+    status: Option<(String, f64)>,
+    // The puzzle being solved, if the game is one.
+    puzzle: Option<Puzzle>,
+    // The imported game whose moves the player is guessing, if any.
+    guessing: Option<Guessing>,
+    // Whether the teaching preset's aids are on, away from online games (see aids).
+    teaching: bool,
+}
+
+impl<'a> Game<'a> {
+    pub async fn new() -> Game<'a> {
+        let mut s = Self {
+            pieces_sprite: load_texture("assets/img/pieces.png")
+                .await
+                .expect("Couldn't load pieces sprite sheet"),
+            core: GameCore::new(Rules::defaults()),
+            drag_offset: (0.0, 0.0),
+            cursor: None,
+            spectating: false,
+            rated: false,
+            refused: None,
+            flipped: false,
+            player: 0,
+            mode: GameMode::HotSeat,
+            menu: None,
+            dialogs: Vec::new(),
+            promoting: None,
+            engine: None,
+            engine_options: EngineOptions::default(),
+            settings: Settings::DEFAULT,
+            thinking: None,
+            pondering: None,
+            metadata: BTreeMap::new(),
+            notes: BTreeMap::new(),
+            published_moves: Default::default(),
+            view: 0,
+            analysis_on: false,
+            analyzer: None,
+            preview: None,
+            search_panel: false,
+            explorer_key: None,
+            explorer: None,
+            explorer_panel: true,
+            #[cfg(not(target_arch = "wasm32"))]
+            explorer_request: None,
+            presence: None,
+            drawn_ply: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            net: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            server: net::DEFAULT_SERVER.to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            takeback_requested: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            draw_offered: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            settings_open: false,
+            abort_allowed: false,
+            shared_analysis: None,
+            variation: None,
+            decorations: Decorations::new(),
+            rule_debug: false,
+            effects: Effects::default(),
+            flipping: None,
+            playback: Playback::new(),
+            viewport: Viewport::default(),
+            autosave: Autosave::new(),
+            status: None,
+            puzzle: None,
+            guessing: None,
+            teaching: false,
+        };
+        s.publish_position();
+        s.publish_ruleset();
+        s.check_rules();
+        s.start_recording();
+        s
+    }
+
+    pub fn start(&mut self, mode: GameMode) {
+        log!("Starting new game: {:?}", mode);
+        self.shared_analysis = None;
+        self.variation = None;
+        self.effects.clear();
+        self.core.restart();
+        self.notes.clear();
+        self.publish_position();
+        self.view = 0;
+        self.menu = None;
+        self.dialogs.clear();
+        self.promoting = None;
+        self.presence = None;
+        self.spectating = false;
+        self.rated = false;
+        self.puzzle = None;
+        self.guessing = None;
+        self.thinking = None;
+        self.pondering = None;
+        self.engine = match mode {
+            GameMode::VsComputer { strength } => Some(Engine {
+                config: EngineConfig::level(strength).with_options(self.engine_options),
+            }),
+            _ => None,
+        };
+        self.mode = mode;
+        self.metadata.clear();
+        let (white, black) = match mode {
+            GameMode::HotSeat => ("Player 1", "Player 2"),
+            GameMode::VsComputer { .. } => ("Human", "Computer"),
+            GameMode::Online => ("?", "?"),
+        };
+        self.metadata.insert("White", white.to_string());
+        self.metadata.insert("Black", black.to_string());
+        self.metadata
+            .insert("Variant", self.core.rules.name.clone());
+        if let GameMode::VsComputer { strength } = mode {
+            self.metadata.insert("EngineLevel", strength.to_string());
+        }
+        self.publish_metadata();
+        self.announce_turn();
+        self.abort_allowed = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.takeback_requested = None;
+            self.draw_offered = None;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if mode != GameMode::Online {
+            self.net = None;
+        } else if self.net.is_none() {
+            self.connect(net::Mode::Create);
+        }
+        self.start_recording();
+    }
+
+    // Starts recording what goes into the game from here (see recording.rs).
+    fn start_recording(&self) {
+        let game = Snapshot {
+            board: self.core.board(),
+            game_data: self.core.game_data(),
+            history: History::from_positions(&self.core.history, &self.core.moves),
+        };
+        recording::start(self.core.rules.export_ruleset(), snapshot::encode(&game));
+    }
+
+    pub fn handle_js_changes(&mut self) {
+        {
+            let f = *FLIPPED.lock().unwrap();
+            if f != self.flipped {
+                self.flipping = FlipAnimation::start(get_time(), self.settings.animation_speed);
+            }
+            self.flipped = f;
+            self.player = self.player_color();
+        }
+
+        self.settings = *SETTINGS.lock().unwrap();
+
+        if let Some(on) = TEACHING.lock().unwrap().take() {
+            self.teaching = on;
+        }
+        self.core.change_piece = self.aids().change_piece;
+
+        {
+            let mut o = ENGINE_OPTIONS.lock().unwrap();
+            if let Some(o) = *o {
+                self.engine_options = o;
+                if let Some(engine) = &mut self.engine {
+                    engine.config = engine.config.with_options(o);
+                }
+            }
+            *o = None;
+        }
+
+        {
+            let mut p = PRESENCE.lock().unwrap();
+            if let Some(p) = p.take() {
+                self.set_presence(p);
+            }
+        }
+
+        {
+            let mut t = TAKE_BACK.lock().unwrap();
+            if let Some(ply) = t.take() {
+                self.take_back(ply);
+            }
+            // The player's color can change at any time, so this is kept up to date here rather
+            // than after each move.
+            *TAKEBACK_PLY.lock().unwrap() = self.takeback_ply().unwrap_or(0);
+        }
+
+        {
+            if let Some(allowed) = ABORT_ALLOWED.lock().unwrap().take() {
+                self.abort_allowed = allowed;
+            }
+            if std::mem::take(&mut *ABORTED.lock().unwrap()) {
+                self.abort();
+            }
+            *ABORTABLE.lock().unwrap() = self.abortable();
+        }
+
+        {
+            let mut a = ADJUDICATE.lock().unwrap();
+            if let Some(a) = a.take() {
+                self.adjudicate(a);
+            }
+        }
+
+        {
+            if std::mem::take(&mut *TOGGLE_SHARED_ANALYSIS.lock().unwrap()) {
+                self.toggle_shared_analysis();
+            }
+            let moves = std::mem::take(&mut *ANALYSIS_MOVES.lock().unwrap());
+            for (fen, uci) in moves {
+                self.receive_analysis(&fen, uci.as_deref());
+            }
+        }
+
+        if std::mem::take(&mut *PASS.lock().unwrap()) {
+            self.pass_turn();
+        }
+
+        for (r, c, flags) in std::mem::take(&mut *SQUARE_FLAGS.lock().unwrap()) {
+            self.set_square_flags(r, c, flags);
+        }
+
+        {
+            let mut v = VIEW_STEP.lock().unwrap();
+            self.step_view(*v);
+            *v = 0;
+        }
+
+        if let Some(view) = SEEK_VIEW.lock().unwrap().take() {
+            self.step_view(view as i32 - self.view as i32);
+        }
+
+        if let Some(on) = PLAYING.lock().unwrap().take() {
+            self.play(on);
+        }
+
+        if let Some(percent) = PLAYBACK_SPEED.lock().unwrap().take() {
+            self.playback.set_speed(percent as f32 / 100.0);
+        }
+
+        if let Some((path, view)) = SEEK_VARIATION.lock().unwrap().take() {
+            self.seek_variation(path, view);
+        }
+
+        if let Some(pasted) = PASTE.lock().unwrap().take() {
+            self.paste(pasted);
+        }
+
+        if let Some(saved) = RESUME.lock().unwrap().take() {
+            self.resume(saved);
+        }
+
+        if let Some(comment) = COMMENT.lock().unwrap().take() {
+            self.set_comment(&comment);
+        }
+        self.publish_moves();
+
+        {
+            let mut a = ANALYSIS.lock().unwrap();
+            if let Some(a) = *a {
+                self.analysis_on = a;
+            }
+            *a = None;
+        }
+
+        if let Some(on) = RULE_DEBUG.lock().unwrap().take() {
+            self.rule_debug = on;
+        }
+
+        {
+            let mut m = GAME_MODE.lock().unwrap();
+            if let Some(m) = *m {
+                self.start(m);
+            }
+            *m = None;
+        }
+
+        if let Some(spectating) = SPECTATING.lock().unwrap().take() {
+            self.spectating = spectating;
+        }
+        if let Some(rated) = RATED.lock().unwrap().take() {
+            self.rated = rated;
+        }
+
+        {
+            let mut r = RULES_UPDATE.lock().unwrap();
+            if let Some(r) = &*r {
+                self.apply_rules_update(r);
+            }
+            *r = None;
+        }
+
+        {
+            let mut r = RULESET_IMPORT.lock().unwrap();
+            if let Some(r) = &*r {
+                self.import_ruleset(r);
+                self.publish_ruleset();
+            }
+            *r = None;
+        }
+
+        if std::mem::take(&mut *CHECK_RULES.lock().unwrap()) {
+            self.check_rules();
+        }
+    }
+
+    fn set_presence(&mut self, p: Presence) {
+        let was_full = self.presence.is_some_and(|old| old.players >= 2);
+        if was_full && p.players < 2 {
+            warn!("A player disconnected");
+        }
+        self.presence = Some(p);
+    }
+
+    // The ply to go back to for the player to take back their last move: in a game between two
+    // players on this device, the last move whoever made it. None if there's nothing to take back,
+    // i.e. no move since the game's first position.
+    fn takeback_ply(&self) -> Option<u16> {
+        if self.spectating {
+            return None;
+        }
+        let (first, ply) = (self.core.first_ply(), self.core.game_data().ply);
+        if self.mode == GameMode::HotSeat {
+            return (ply > first).then(|| ply - 1);
+        }
+        (first..ply).rev().find(|&p| {
+            let gd = GameData {
+                ply: p,
+                ..self.core.game_data()
+            };
+            gd.player_to_move() == self.player
+        })
+    }
+
+    // Goes back to the position at ply, forgetting the moves since.
+    fn take_back(&mut self, ply: u16) {
+        if self.shared_analysis.is_some() {
+            warn!("Can't take back moves while analyzing the game together");
+            return;
+        }
+        self.leave_variation();
+        let (first, current) = (self.core.first_ply(), self.core.game_data().ply);
+        if ply < first || ply >= current {
+            warn!("Can't take back to ply {} at ply {}", ply, current);
+            return;
+        }
+        log!("Taking back to ply {}", ply);
+        recording::record(Input::TakeBack(ply));
+        // The moves still to guess would be out of step with the board.
+        self.guessing = None;
+        self.core.take_back(ply);
+        self.notes.split_off(&((ply - first) as usize));
+        self.view = self.core.history.len() - 1;
+        if self.metadata.remove("Result").is_some() {
+            self.metadata.remove("Termination");
+            self.publish_metadata();
+        }
+        self.publish_position();
+        self.announce_turn();
+    }
+
+    // Does what the player answered in a dialog.
+    fn reply(&mut self, reply: Reply) {
+        match reply {
+            #[cfg(not(target_arch = "wasm32"))]
+            Reply::Resign(yes) => {
+                if yes {
+                    self.resign();
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Reply::Draw(true) => self.offer_draw(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Reply::Draw(false) => self.draw_offered = None,
+            #[cfg(not(target_arch = "wasm32"))]
+            Reply::Takeback(ply) => {
+                if let Some(net) = &self.net {
+                    net.answer_takeback(ply);
+                }
+                if let Some(ply) = ply {
+                    self.take_back(ply);
+                }
+            }
+            Reply::Promote(promotion) => self.promote(promotion),
+            Reply::Blunder(Some((player, piece, m))) => self.apply_move(player, piece, m),
+            Reply::Blunder(None) => {}
+            Reply::Ending(AfterGame::Rematch) => self.start(self.mode),
+            Reply::Ending(AfterGame::Analyze) => self.analysis_on = true,
+            #[cfg(not(target_arch = "wasm32"))]
+            Reply::Ending(AfterGame::Export) => self.export_game(),
+            #[cfg(target_arch = "wasm32")]
+            Reply::Ending(AfterGame::Export) => {
+                dispatch(Event::Export);
+            }
+            Reply::Ending(AfterGame::Close) => {}
+        }
+    }
+
+    // Shows how the game ended, and what the player can do next. Online, the page or the other
+    // player decides on a rematch, so there's no button for it.
+    fn show_ending(&mut self, result: &str, reason: &str) {
+        let mut answers = Vec::new();
+        let mut answer = |key: &str, letter, value| {
+            answers.push(Answer {
+                label: tr(key),
+                key: Some(letter),
+                value: Reply::Ending(value),
+            })
+        };
+        if self.mode != GameMode::Online {
+            answer("ending.rematch", KeyCode::R, AfterGame::Rematch);
+        }
+        answer("ending.analyze", KeyCode::A, AfterGame::Analyze);
+        answer("button.export", KeyCode::E, AfterGame::Export);
+        answer("dialog.close", KeyCode::C, AfterGame::Close);
+        let dialog = Dialog::new(
+            i18n::result_text(result),
+            answers,
+            Reply::Ending(AfterGame::Close),
+        );
+        self.dialogs.push(match i18n::reason_text(reason) {
+            Some(detail) => dialog.with_detail(detail),
+            None => dialog,
+        });
+    }
+
+    // Asks the player what the piece making the move promotes to, before making it.
+    fn ask_promotion(&mut self, turn: &Turn, piece: Piece, m: Move) {
+        let answers = self
+            .core
+            .promotion_choices(turn, piece, m)
+            .into_iter()
+            .map(|n| Answer {
+                label: announce::piece_name(n),
+                key: controls::letter_key(n),
+                value: Reply::Promote(Some(n)),
+            })
+            .collect();
+        let question = tr("dialog.promotion");
+        self.dialogs
+            .push(Dialog::new(question, answers, Reply::Promote(None)));
+        self.promoting = Some((turn.player, piece, m));
+    }
+
+    // Makes the move waiting for a promotion, to the piece named promotion, or puts the piece back
+    // without one.
+    fn promote(&mut self, promotion: Option<u8>) {
+        let Some((player, piece, m)) = self.promoting.take() else {
+            return;
+        };
+        if !self.branching() && self.shared_analysis.is_none() {
+            recording::record(Input::Promote(promotion));
+        }
+        let turn = self.move_turn(player);
+        let source = (piece.row as usize, piece.col as usize);
+        let legs = move_legs(piece, m);
+        // The position can't have changed under the dialog, but check anyway.
+        let found =
+            promotion.and_then(|p| self.core.find_move(&turn, source, &legs, Some(p), false));
+        if let Some((piece, m)) = found {
+            self.submit_move(player, piece, m);
+        }
+    }
+
+    // Whether the player can abort the online game: before both players have moved, as long as
+    // they haven't moved themselves, or the server allows it since the other player is taking too
+    // long to.
+    fn abortable(&self) -> bool {
+        let ply = self.core.game_data().ply;
+        self.mode == GameMode::Online
+            && !self.spectating
+            && !self.metadata.contains_key("Result")
+            && ply < 3
+            && (ply < 2 + self.player as u16 || self.abort_allowed)
+    }
+
+    // The server aborted the online game.
+    fn abort(&mut self) {
+        if !self.metadata.contains_key("Result") {
+            self.end_game("*", "aborted");
+        }
+    }
+
+    // Whether moves can't be made anymore. Once an online game is over, its players can still
+    // analyze it together.
+    fn game_over(&self) -> bool {
+        self.metadata.contains_key("Result") && self.shared_analysis.is_none() && !self.branching()
+    }
+
+    // Whether moves on an earlier position of the game can start a variation: in analysis, when
+    // no one's waiting for the game to go on, i.e. once it's over, or between players at the board.
+    fn can_branch(&self) -> bool {
+        self.shared_analysis.is_none()
+            && (self.metadata.contains_key("Result")
+                || (self.analysis_on && self.mode == GameMode::HotSeat))
+    }
+
+    // Whether the board only shows the game, and can't be played on: for spectators, and for
+    // players looking back through the game, since only the current position can be played on
+    // outside analysis.
+    fn read_only(&self) -> bool {
+        self.spectating || (!self.live() && !self.can_branch())
+    }
+
+    // Whether moves made now go into a variation.
+    fn branching(&self) -> bool {
+        self.variation.is_some() || (!self.live() && self.can_branch())
+    }
+
+    // The position moves are made on: the one shown when branching, otherwise the current one.
+    fn move_position(&self) -> (PiecePlacements, GameData) {
+        if self.branching() {
+            self.core.history[self.view]
+        } else {
+            self.core.position()
+        }
+    }
+
+    // The player's turn on the position moves are made on.
+    fn move_turn(&self, player: usize) -> Turn {
+        let (pp, gd) = self.move_position();
+        Turn {
+            pp,
+            gd,
+            player,
+            over: self.game_over(),
+        }
+    }
+
+    // Plays a move on the position shown, in analysis. It follows the board's next move if it's
+    // the same, or otherwise the variation of that move starting with it, adding one if there's
+    // none yet. At the end of a variation, it's added to it.
+    fn play_variation_move(&mut self, piece: Piece, m: Move) {
+        let i = self.view;
+        let (pp, gd) = self.core.history[i];
+        self.trigger_effects(piece, m);
+        if self.core.moves.get(i) == Some(&long_algebraic(piece, m)) {
+            self.view += 1;
+            self.core.pointer = Pointer::Up;
+            return;
+        }
+        let san = san(&self.core.rules, &pp, gd, piece, m);
+        if self.variation.is_none() {
+            let game = self.put_game_aside();
+            self.variation = Some((game, Vec::new()));
+        }
+        let (game, path) = self.variation.as_mut().unwrap();
+        let (level, j) = variations::locate(path, i);
+        let path = if level > 0
+            && level == path.len()
+            && j == self.core.moves.len() - variations::start(path)
+        {
+            variations::extend(&mut game.notes, path, &san);
+            path.clone()
+        } else {
+            match variations::branch(&mut game.notes, &path[..level], j, &san) {
+                Some(path) => path,
+                None => return,
+            }
+        };
+        self.show_variation(path);
+        self.view = (i + 1).min(self.core.history.len() - 1);
+    }
+
+    // Takes the game off the board, to show something else there.
+    fn put_game_aside(&mut self) -> FinishedGame {
+        FinishedGame {
+            history: std::mem::take(&mut self.core.history),
+            moves: std::mem::take(&mut self.core.moves),
+            notes: std::mem::take(&mut self.notes),
+        }
+    }
+
+    // Puts the game back on the board, at its current position.
+    fn restore_game(&mut self, game: FinishedGame) {
+        self.core.history = game.history;
+        self.core.moves = game.moves;
+        self.notes = game.notes;
+        self.view = self.core.history.len() - 1;
+        self.core.pointer = Pointer::Up;
+    }
+
+    // Shows the variation at path on the board, from the game put aside in self.variation, after
+    // the moves leading to it. A move that isn't legal, e.g. from a PGN, ends it there.
+    fn show_variation(&mut self, path: Path) {
+        let Some((game, _)) = &self.variation else {
+            return;
+        };
+        let Some(&(first, _)) = path.first() else {
+            return;
+        };
+        let mut history = game.history[..=first].to_vec();
+        let mut moves = game.moves[..first].to_vec();
+        'lines: for level in 1..=path.len() {
+            let Some(line) = variations::line(&game.notes, &path[..level]) else {
+                break;
+            };
+            let n = path.get(level).map_or(line.len(), |&(at, _)| at);
+            for (s, _) in line.iter().take(n) {
+                let (mut pp, gd) = *history.last().unwrap();
+                let Some((p, m)) = parse_san(&self.core.rules, &pp, gd, s) else {
+                    warn!("Variation stops at {}: it's not legal here", s);
+                    break 'lines;
+                };
+                Rules::make_move(p, m, &mut pp);
+                let gd = GameData {
+                    ply: m.game_data.ply + 1,
+                    ..m.game_data
+                };
+                history.push((pp, gd));
+                moves.push(long_algebraic(p, m));
+            }
+        }
+        self.core.history = history;
+        self.core.moves = moves;
+        self.variation.as_mut().unwrap().1 = path;
+        self.view = self.view.min(self.core.history.len() - 1);
+        self.core.pointer = Pointer::Up;
+    }
+
+    // Shows the position after the board's first view moves with the variation at path on it, or
+    // the game's if path is empty.
+    fn seek_variation(&mut self, path: Path, view: usize) {
+        if path.is_empty() {
+            if let Some((game, _)) = self.variation.take() {
+                self.restore_game(game);
+            }
+        } else if self.shared_analysis.is_some() {
+            return;
+        } else if self.variation.is_none() {
+            let game = self.put_game_aside();
+            self.variation = Some((game, Vec::new()));
+        }
+        self.show_variation(path);
+        self.view = view.min(self.core.history.len() - 1);
+        self.core.pointer = Pointer::Up;
+    }
+
+    // Goes back from a variation to the game's current position.
+    fn leave_variation(&mut self) {
+        if let Some((game, _)) = self.variation.take() {
+            self.restore_game(game);
+        }
+    }
+
+    // Once the online game is over, sets up the shared board from the position shown and tells the
+    // other player, or goes back to the game's final position.
+    fn toggle_shared_analysis(&mut self) {
+        if let Some(game) = self.shared_analysis.take() {
+            self.restore_game(game);
+            return;
+        }
+        self.leave_variation();
+        if self.mode != GameMode::Online || !self.metadata.contains_key("Result") {
+            log!("{}", tr("shared_analysis.not_over"));
+            return;
+        }
+        let (pp, gd) = self.core.history[self.view];
+        self.set_shared_board(pp, gd);
+        self.send_analysis(None);
+    }
+
+    // Puts the game aside, unless it already is, and starts the shared board from pp, gd.
+    fn set_shared_board(&mut self, pp: PiecePlacements, gd: GameData) {
+        if self.shared_analysis.is_none() {
+            log!("{}", tr("shared_analysis.started"));
+            self.leave_variation();
+            self.shared_analysis = Some(self.put_game_aside());
+        }
+        self.core.history = vec![(pp, gd)];
+        self.core.moves.clear();
+        self.view = 0;
+        self.core.pointer = Pointer::Up;
+    }
+
+    fn set_square_flags(&mut self, r: usize, c: usize, flags: u8) {
+        let pp = self.core.board();
+        if !(1..=pp.height()).contains(&r) || !(1..=pp.width()).contains(&c) {
+            warn!("Ignoring flags for ({}, {}), which is off the board", r, c);
+            return;
+        }
+        if let Some((pp, _)) = self.core.history.last_mut() {
+            pp.set_flags(r, c, flags);
+        }
+    }
+
+    // Makes a move on the shared board, returning it in long algebraic notation.
+    fn play_shared_move(&mut self, piece: Piece, m: Move) -> String {
+        if self.live() {
+            self.view += 1;
+        }
+        self.core.play(piece, m);
+        self.core.moves.last().unwrap().clone()
+    }
+
+    // The other player's move on the shared board, or the position they set it up from. Whenever
+    // the boards don't agree, e.g. after both moved at once, ours follows theirs.
+    fn receive_analysis(&mut self, position: &str, uci: Option<&str>) {
+        if self.mode != GameMode::Online || !self.metadata.contains_key("Result") {
+            warn!("Ignoring analysis before the game is over");
+            return;
+        }
+        let Ok((pp, gd)) = parse_fen(position) else {
+            warn!("Ignoring analysis of an invalid position: {}", position);
+            return;
+        };
+        let m = uci.and_then(|uci| {
+            parse_long_algebraic(
+                &self.core.rules,
+                &self.core.board(),
+                self.core.game_data(),
+                uci,
+            )
+        });
+        if let (Some(_), Some((piece, m))) = (&self.shared_analysis, m) {
+            self.play_shared_move(piece, m);
+            if fen(&self.core.board(), self.core.game_data()) == position {
+                return;
+            }
+        }
+        self.set_shared_board(pp, gd);
+    }
+
+    // Tells the other player about the shared board: the move just made on it, or the position it
+    // starts from.
+    fn send_analysis(&self, uci: Option<&str>) {
+        let position = fen(&self.core.board(), self.core.game_data());
+        #[cfg(target_arch = "wasm32")]
+        dispatch(Event::AnalysisMove(&protocol::encode_analysis(
+            &position, uci,
+        )));
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(net) = &self.net {
+            net.send_analysis(&position, uci);
+        }
+    }
+
+    // X asks the server to abort the online game, while the player can.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_abort_key(&mut self) {
+        if !is_key_pressed(KeyCode::X) {
+            return;
+        }
+        match &self.net {
+            Some(net) if self.abortable() => net.abort(),
+            _ => log!("{}", tr("abort.not_allowed")),
+        }
+    }
+
+    fn import_ruleset(&mut self, r: &str) {
+        recording::record(Input::Ruleset(r.to_string()));
+        let setup = self.game_setup();
+        match self.core.rules.import_ruleset(r) {
+            Ok(()) => log!("Imported ruleset {}", self.core.rules.name),
+            Err(e) => warn!("Couldn't import ruleset: {}", e),
+        }
+        if let Some(seed) = self.core.rules.seed {
+            rand::srand(seed);
+        }
+        self.restart_if_setup_changed(setup);
+        self.check_rules();
+    }
+
+    // Tells the player and the page what's wrong with the rules, if anything, before a game with
+    // them goes wrong. Call it whenever the rules change.
+    fn check_rules(&mut self) {
+        let problems = self.core.rules.check_ruleset();
+        for p in &problems {
+            warn!("{}", p);
+        }
+        *RULE_PROBLEMS.lock().unwrap() = serde_json::Value::from(problems.clone()).to_string();
+        match problems.len() {
+            0 => {}
+            1 => self.set_status(problems[0].clone()),
+            n => self.set_status(format!("{} (and {} more problems)", problems[0], n - 1)),
+        }
+    }
+
+    // What a game starts from that the rules can change: the game itself and the turn order.
+    fn game_setup(&self) -> (&'static str, GameData) {
+        (self.core.rules.profile, self.core.rules.initial_game_data())
+    }
+
+    // Another game needs its own board, and another turn order its own count of plies. Call it
+    // whenever the rules change.
+    fn restart_if_setup_changed(&mut self, setup: (&'static str, GameData)) {
+        // The moves worked out so far were for the old rules.
+        self.core.legal = None;
+        self.core.ahead = None;
+        self.thinking = None;
+        self.pondering = None;
+        if self.game_setup() != setup {
+            self.start(self.mode);
+        }
+    }
+
+    fn apply_rules_update(&mut self, r: &HashMap<String, bool>) {
+        recording::record(Input::Rules(
+            r.iter().map(|(n, &a)| (n.clone(), a)).collect(),
+        ));
+        let setup = self.game_setup();
+        for (n, active) in self.core.rules.rule_toggles_mut() {
+            if let Some(&a) = r.get(n) {
+                if *active != a {
+                    debug!("Toggling {} to {}", n, a);
+                    *active = a;
+                }
+            }
+        }
+        self.restart_if_setup_changed(setup);
+        self.publish_ruleset();
+        self.check_rules();
+    }
+
+    fn publish_metadata(&self) {
+        let mut m = GAME_METADATA.lock().unwrap();
+        *m = Some(serde_json::to_string(&self.metadata).unwrap());
+    }
+
+    fn publish_ruleset(&self) {
+        let ruleset = self.core.rules.export_ruleset();
+        crash::set_ruleset(&ruleset);
+        let mut r = RULESET_EXPORT.lock().unwrap();
+        *r = Some(ruleset);
+    }
+
+    // Updates MOVE_LIST and PGN when the moves, their notes or the result have changed.
+    fn publish_moves(&mut self) {
+        let (history, moves, notes) = match &self.variation {
+            Some((game, _)) => (&game.history, &game.moves, &game.notes),
+            None => (&self.core.history, &self.core.moves, &self.notes),
+        };
+        let result = self.metadata.get("Result").cloned();
+        let (published, published_notes, published_result) = &self.published_moves;
+        if published == moves && published_notes == notes && *published_result == result {
+            return;
+        }
+        let mut line = Vec::new();
+        for (i, uci) in moves.iter().enumerate() {
+            let (pp, gd) = history[i];
+            let san = match parse_long_algebraic(&self.core.rules, &pp, gd, uci) {
+                Some((p, m)) => san(&self.core.rules, &pp, gd, p, m),
+                None => uci.clone(),
+            };
+            line.push((san, notes.get(&i).cloned().unwrap_or_default()));
+        }
+        let mut tags: Vec<(&str, String)> = ["Event", "Site", "Date", "Round", "White", "Black"]
+            .iter()
+            .map(|&t| (t, self.metadata.get(t).cloned().unwrap_or("?".to_string())))
+            .collect();
+        let pgn_result = result.clone().unwrap_or("*".to_string());
+        tags.push(("Result", pgn_result.clone()));
+        let (pp, gd) = history[0];
+        if (pp, gd)
+            != (
+                self.core.rules.initial_placements(),
+                self.core.rules.initial_game_data(),
+            )
+        {
+            tags.push(("SetUp", "1".to_string()));
+            tags.push(("FEN", fen(&pp, gd)));
+        }
+        let list = variations::move_list(&line, &[], 0);
+        *MOVE_LIST.lock().unwrap() = Some(serde_json::Value::from(list).to_string());
+        let (sans, line_notes): (Vec<_>, Vec<_>) = line.into_iter().unzip();
+        *PGN.lock().unwrap() = Some(annotated_pgn(&tags, &sans, &line_notes, &pgn_result));
+        let (board, game_data) = *history.last().unwrap();
+        *SNAPSHOT.lock().unwrap() = snapshot::encode(&Snapshot {
+            board,
+            game_data,
+            history: History::from_positions(history, moves),
+        });
+        self.published_moves = (moves.clone(), notes.clone(), result);
+    }
+
+    // The player's own comment on the move leading to the position shown, in analysis: while
+    // it's on, or once the game is over. An empty comment removes it.
+    fn set_comment(&mut self, comment: &str) {
+        if self.view == 0 {
+            warn!("Ignoring comment: there's no move to comment on");
+            return;
+        }
+        if !self.analysis_on && !self.metadata.contains_key("Result") {
+            warn!("Ignoring comment: comments can only be added in analysis");
+            return;
+        }
+        let comment = comment.trim();
+        let (notes, path) = match &mut self.variation {
+            Some((game, path)) => (&mut game.notes, path.as_slice()),
+            None => (&mut self.notes, &[][..]),
+        };
+        let (level, i) = variations::locate(path, self.view - 1);
+        let Some(n) = variations::notes_mut(notes, &path[..level], i) else {
+            return;
+        };
+        n.comment = (!comment.is_empty()).then(|| comment.to_string());
+        if level == 0 && n.is_empty() {
+            notes.remove(&i);
+        }
+    }
+
+    fn publish_position(&self) {
+        crash::set_position(
+            fen(&self.core.board(), self.core.game_data()),
+            &self.core.moves,
+        );
+    }
+
+    pub fn draw(&mut self) {
+        if let Some(menu) = &self.menu {
+            menu.draw();
+            return;
+        }
+        let (pp, gd) = self.core.history[self.view];
+        let refresh = std::mem::take(&mut *REFRESH_DECORATIONS.lock().unwrap());
+        self.decorations.update(&pp, gd, refresh);
+        let screen = vec2(screen_width(), screen_height());
+        self.viewport.snap(screen, board_size());
+        set_camera(&self.viewport.camera(screen));
+        self.draw_board();
+        self.draw_decorations();
+        self.draw_coordinates();
+        self.draw_selected();
+        self.draw_chain();
+        self.draw_cursor();
+        self.draw_pieces();
+        self.draw_refused();
+        let explained = self.explain_hovered();
+        if let Some((piece, candidates)) = &explained {
+            self.draw_explained_squares(*piece, candidates);
+        }
+        let now = get_time();
+        self.effects.expire(now);
+        if self.flipping.is_some_and(|f| f.finished(now)) {
+            self.flipping = None;
+        }
+        self.effects
+            .draw(now, SQUARE_SIZE, |r, c| self.rc_to_xy(r, c));
+        self.draw_exchange();
+        set_default_camera();
+        if let Some((piece, candidates)) = &explained {
+            draw_move_explanations(*piece, candidates);
+        }
+        self.draw_presence();
+        self.draw_status(now);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let s = self.settings;
+            let offered = self.draw_offered.is_some_and(|p| p != self.player);
+            buttons::draw(self.settings_open, offered, |setting| match setting {
+                Setting::Coordinates => Some(s.coordinates),
+                Setting::Click => Some(s.input_mode == InputMode::Click),
+                Setting::AutoQueen => Some(s.auto_queen),
+                Setting::Ponder => Some(s.ponder),
+                Setting::BlunderCheck => Some(s.blunder_check),
+                Setting::Theme => None,
+            });
+        }
+        if let Some(dialog) = self.dialogs.first() {
+            dialog.draw();
+        }
+        if let Some(info) = self.analyzer.as_ref().and_then(|a| a.info()) {
+            draw_analysis(info, self.flipped, self.preview);
+        }
+        if let Some(search) = self.analyzer.as_ref().and_then(|a| a.search_info()) {
+            draw_search_panel(&search, self.search_panel);
+        }
+        if let Some(explorer) = &self.explorer {
+            draw_explorer_panel(explorer, self.explorer_panel);
+        }
+        self.drawn_ply = self.core.game_data().ply;
+    }
+
+    // Draws a position of the game (an index in history) offscreen, without the analysis or a
+    // piece being moved.
+    fn render_image(&mut self, view: usize, options: ImageOptions) -> Image {
+        // TODO: get board size from rules
+        let size = 8.0 * SQUARE_SIZE;
+        let target = render_target(size as u32, size as u32);
+        set_camera(&Camera2D {
+            render_target: Some(target),
+            ..Camera2D::from_display_rect(Rect::new(0.0, 0.0, size, size))
+        });
+        let (flipped, flipping, settings) = (self.flipped, self.flipping, self.settings);
+        self.flipped = options.flipped;
+        self.flipping = None;
+        self.settings.coordinates = options.coordinates;
+        self.draw_board();
+        self.draw_coordinates();
+        let last_move = view.checked_sub(1).map(|i| &self.core.moves[i]);
+        if let Some((from, to)) = last_move
+            .filter(|_| options.last_move)
+            .and_then(|m| export::move_squares(m))
+        {
+            self.draw_highlight(from.0, from.1);
+            self.draw_highlight(to.0, to.1);
+        }
+        let (pp, _) = &self.core.history[view];
+        for (r, c, n) in pp.squares().filter(|&(_, _, n)| n != 0) {
+            let (x, y) = self.rc_to_xy(r, c);
+            self.draw_piece(n, x, y, WHITE);
+        }
+        set_default_camera();
+        self.flipped = flipped;
+        self.flipping = flipping;
+        self.settings = settings;
+        let image = target.texture.get_texture_data();
+        target.delete();
+        image
+    }
+
+    // Every position of the game so far, as the frames of an animation.
+    fn render_game(&mut self, options: ImageOptions) -> Vec<Image> {
+        (0..self.core.history.len())
+            .map(|view| self.render_image(view, options))
+            .collect()
+    }
+
+    // Sends the page the pictures it asked for. A long game's animation takes a while to draw and
+    // encode, so that frame runs over.
+    pub fn handle_image_requests(&mut self) {
+        let request = IMAGE_REQUEST.lock().unwrap().take();
+        if let Some(options) = request {
+            let image = self.render_image(self.view, options);
+            match export::png(&image) {
+                Ok(png) => {
+                    dispatch(Event::BoardImage(&png));
+                }
+                Err(e) => warn!("Couldn't make a picture of the board: {}", e),
+            }
+        }
+        let request = ANIMATION_REQUEST.lock().unwrap().take();
+        if let Some((options, delay)) = request {
+            let frames = self.render_game(options);
+            match export::apng(&frames, delay) {
+                Ok(png) => {
+                    dispatch(Event::GameAnimation(&png));
+                }
+                Err(e) => warn!("Couldn't animate the game: {}", e),
+            }
+        }
+    }
+
+    // Writes an animation of a PGN game, for --export-apng.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_animation(&mut self, args: export::AnimationArgs) -> Result<(), String> {
+        let pgn = std::fs::read_to_string(&args.pgn)
+            .map_err(|e| format!("Couldn't read {}: {}", args.pgn, e))?;
+        let (history, moves) = export::load_pgn(&self.core.rules, &pgn)?;
+        self.core.history = history;
+        self.core.moves = moves;
+        let frames = self.render_game(args.options);
+        let png = export::apng(&frames, args.delay)?;
+        std::fs::write(&args.out, png).map_err(|e| format!("Couldn't write {}: {}", args.out, e))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_image_key(&mut self) {
+        if is_key_pressed(KeyCode::P) {
+            self.save_image();
+        }
+    }
+
+    // Saves a picture of the position shown, as the board looks now, with the last move.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_image(&mut self) {
+        let image = self.render_image(
+            self.view,
+            ImageOptions {
+                flipped: self.flipped,
+                coordinates: self.settings.coordinates,
+                last_move: true,
+            },
+        );
+        let (_, gd) = self.core.history[self.view];
+        match export::png(&image).and_then(|png| export::save(&png, gd.ply)) {
+            Ok(path) => log!("Saved the board to {}", path),
+            Err(e) => warn!("Couldn't save the board: {}", e),
+        }
+    }
+
+    fn live(&self) -> bool {
+        self.view == self.core.history.len() - 1
+    }
+
+    // Starts or stops playing through the game. Started on the last position, it plays from the
+    // start.
+    fn play(&mut self, on: bool) {
+        if on && self.live() {
+            self.step_view(i32::MIN);
+        }
+        self.playback.play(on && !self.live(), get_time());
+    }
+
+    // Steps forward when it's time to, while playing, and stops on the last position.
+    pub fn handle_playback(&mut self) {
+        if self.playback.tick(get_time()) {
+            self.step_view(1);
+        }
+        if self.live() {
+            self.playback.playing = false;
+        }
+        *PLAYBACK.lock().unwrap() = Some(
+            serde_json::json!({
+                "view": self.view,
+                "last": self.core.history.len() - 1,
+                "playing": self.playback.playing,
+                "speed": self.playback.speed(),
+                "path": self.variation.as_ref().map_or(&[][..], |(_, path)| path),
+            })
+            .to_string(),
+        );
+    }
+
+    fn step_view(&mut self, delta: i32) {
+        let last = self.core.history.len() as i64 - 1;
+        let view = (self.view as i64 + delta as i64).clamp(0, last) as usize;
+        if view != self.view {
+            self.view = view;
+            self.core.pointer = Pointer::Up;
+        }
+    }
+
+    // Starts analyzing the position on the board whenever it changes, and stops when analysis is
+    // turned off. The search itself is a task (see run_tasks).
+    pub fn handle_analysis(&mut self) {
+        let messages = std::mem::take(&mut *CLOUD_ANALYSIS.lock().unwrap());
+        if !self.analysis_on || self.menu.is_some() {
+            self.analyzer = None;
+            return;
+        }
+        let (pp, gd) = self.core.history[self.view];
+        if !matches!(&self.analyzer, Some(a) if a.is_analyzing(&pp, gd)) {
+            // Replacing the old analyzer stops it.
+            self.analyzer = Some(Analyzer::start(&self.core.rules, pp, gd));
+            self.preview = None;
+        }
+        for message in messages {
+            let Ok(data) = serde_json::from_str::<serde_json::Value>(&message) else {
+                warn!("Invalid cloud analysis: {}", message);
+                continue;
+            };
+            if let Some(e) = data["error"].as_str() {
+                warn!("The cloud analysis failed: {}", e);
+            }
+            // Messages about positions the board has moved on from are dropped.
+            if data["info"].is_null() || data["fen"].as_str() != Some(&fen(&pp, gd)) {
+                continue;
+            }
+            match remote_info(&data["info"], &self.core.rules, &pp, gd) {
+                Ok(info) => self.analyzer.as_mut().unwrap().set_remote(info),
+                Err(e) => warn!("Invalid cloud analysis: {}", e),
+            }
+        }
+    }
+
+    // Asks the opening explorer about the position on the board whenever it changes in analysis
+    // mode, and picks up the answer.
+    pub fn handle_explorer(&mut self) {
+        if self.analyzer.is_none() {
+            self.explorer_key = None;
+            self.explorer = None;
+            return;
+        }
+        let (pp, gd) = self.core.history[self.view];
+        let key = explorer::position_key(&pp, gd);
+        if self.explorer_key.as_ref() != Some(&key) {
+            self.explorer = None;
+            #[cfg(target_arch = "wasm32")]
+            dispatch(Event::ExplorerQuery(&key));
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.explorer_request = Some(explorer::fetch(&self.server, &key));
+            }
+            self.explorer_key = Some(key);
+        }
+        let answer = EXPLORER.lock().unwrap().take();
+        #[cfg(not(target_arch = "wasm32"))]
+        let answer = answer.or_else(|| {
+            let answer = self.explorer_request.as_ref()?.try_recv().ok()?;
+            self.explorer_request = None;
+            answer
+                .map_err(|e| debug!("No opening explorer: {}", e))
+                .ok()
+        });
+        let Some(answer) = answer else {
+            return;
+        };
+        match explorer::parse(&answer, &self.core.rules, &pp, gd) {
+            // An answer about a position the board has moved on from is dropped.
+            Ok(e) if self.explorer_key.as_ref() == Some(&e.key) => self.explorer = Some(e),
+            Ok(_) => {}
+            Err(e) => warn!("Invalid answer from the opening explorer: {}", e),
+        }
+    }
+
+    pub fn handle_input(&mut self) {
+        if let Some(menu) = &mut self.menu {
+            match menu.update() {
+                Some(Choice::Start(mode)) => {
+                    self.teaching = false;
+                    self.start(mode);
+                }
+                Some(Choice::Teaching) => {
+                    self.teaching = true;
+                    self.start(GameMode::VsComputer { strength: 1 });
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                Some(Choice::Resume) => match autosave::load() {
+                    Some(saved) => self.resume(saved),
+                    None => self.menu = Some(Menu::new(false)),
+                },
+                _ => {}
+            }
+            return;
+        }
+        let square = {
+            let (x, y) = self.mouse();
+            self.xy_to_rc(x, y)
+        };
+        let events = controls::events(&self.settings.bindings, square);
+        if let Some(dialog) = self.dialogs.first_mut() {
+            if let Some(reply) = dialog.update(&events) {
+                self.dialogs.remove(0);
+                self.reply(reply);
+            }
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if events.contains(&InputEvent::Menu) {
+            self.menu = Some(Menu::new(false));
+            return;
+        }
+        // With shift, a full turn at a time.
+        let step = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            2
+        } else {
+            1
+        };
+        if is_key_pressed(KeyCode::Left) {
+            self.step_view(-step);
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.step_view(step);
+        }
+        if is_key_pressed(KeyCode::Home) {
+            self.step_view(i32::MIN);
+        }
+        // From a variation, back to the game.
+        if is_key_pressed(KeyCode::End) {
+            self.leave_variation();
+            self.step_view(i32::MAX);
+        }
+        if is_key_pressed(KeyCode::Space) {
+            self.play(!self.playback.playing);
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.playback.change_speed(true);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.playback.change_speed(false);
+        }
+        if is_key_pressed(KeyCode::A) {
+            self.analysis_on = !self.analysis_on;
+        }
+        if is_key_pressed(KeyCode::S) {
+            self.toggle_shared_analysis();
+        }
+        if is_key_pressed(KeyCode::Period) {
+            self.pass_turn();
+        }
+        if is_key_pressed(KeyCode::D) {
+            self.rule_debug = !self.rule_debug;
+        }
+        for &event in &events {
+            match event {
+                InputEvent::Flip => self.flip(),
+                InputEvent::Undo => self.undo(),
+                InputEvent::Cursor(dx, dy) => self.move_cursor(dx, dy),
+                _ => {}
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.handle_abort_key();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.handle_settings_keys();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.handle_image_key();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.handle_paste_key();
+        let screen = vec2(screen_width(), screen_height());
+        self.viewport
+            .zoom_at(mouse_wheel().1, mouse_position(), screen, board_size());
+        if let Pointer::Dragging(_) = self.core.pointer {
+            self.viewport
+                .auto_scroll(mouse_position(), screen, board_size(), get_frame_time());
+        }
+        if events.iter().any(|e| matches!(e, InputEvent::Select(_))) {
+            let (x, y) = mouse_position();
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                if let Some(clicked) = buttons::clicked(x, y, screen, self.settings_open) {
+                    self.button_clicked(clicked);
+                    return;
+                }
+                // Clicking the board closes the settings.
+                self.settings_open = false;
+            }
+            if self.analyzer.is_some() && search_header_at(x, y) {
+                self.search_panel = !self.search_panel;
+                return;
+            }
+            if self.explorer.is_some() && explorer_header_at(x, y) {
+                self.explorer_panel = !self.explorer_panel;
+                return;
+            }
+            let info = self.analyzer.as_ref().and_then(|a| a.info());
+            if let Some(i) = info.and_then(|info| line_at(info, x, y)) {
+                // Clicking the previewed line again hides it.
+                self.preview = if self.preview == Some(i) {
+                    None
+                } else {
+                    Some(i)
+                };
+                return;
+            }
+        }
+        if self.read_only() {
+            // Nothing picked up before carries over.
+            self.core.pointer = Pointer::Up;
+            self.cursor = None;
+            return;
+        }
+        for event in events {
+            self.board_event(event, square);
+        }
+    }
+
+    // Picks pieces up and puts them down, for the mouse and the keyboard's cursor. square is the
+    // square under the mouse.
+    fn board_event(&mut self, event: InputEvent, square: (usize, usize)) {
+        let mode = self.settings.input_mode;
+        let (pointer, mode) = match event {
+            InputEvent::Select(square) => {
+                let (x, y) = self.mouse();
+                self.drag_offset = (x % SQUARE_SIZE, y % SQUARE_SIZE);
+                self.cursor = None;
+                (PointerEvent::Press(square), mode)
+            }
+            InputEvent::Release(square) => (PointerEvent::Release(square), mode),
+            InputEvent::Cancel => (PointerEvent::Cancel, mode),
+            // The keyboard moves pieces by clicks. Before the cursor's been moved, this shows it.
+            InputEvent::Confirm => match self.cursor {
+                Some(cursor) => (PointerEvent::Press(cursor), InputMode::Click),
+                None => return self.move_cursor(0, 0),
+            },
+            _ => return,
+        };
+        trace!("{:?} with the pointer {:?}", event, self.core.pointer);
+        if !self.branching() && self.shared_analysis.is_none() {
+            self.record_pointer(event, pointer, square);
+        }
+        let turn = self.move_turn(self.player);
+        if let (Pointer::Up, PointerEvent::Press(square)) = (&self.core.pointer, pointer)
+            && let Some(refusal) = self.core.refusal(&turn, square)
+        {
+            self.refused = Some((square, refusal, get_time()));
+        }
+        let auto_queen = self.settings.auto_queen;
+        if let Some((piece, m)) = self.core.pointer_event(&turn, mode, auto_queen, pointer) {
+            if !auto_queen && !self.core.promotion_choices(&turn, piece, m).is_empty() {
+                return self.ask_promotion(&turn, piece, m);
+            }
+            self.submit_move(self.player, piece, m);
+        }
+    }
+
+    // Records what the pointer was told as the mouse button or key it came from (see
+    // recording.rs). A cancel is recorded as a right click on the square under the mouse.
+    fn record_pointer(&self, event: InputEvent, pointer: PointerEvent, square: (usize, usize)) {
+        let player = self.player;
+        let mouse = |square, button, pressed| Input::Mouse {
+            square,
+            player,
+            button,
+            pressed,
+        };
+        let input = match (event, pointer) {
+            (InputEvent::Confirm, PointerEvent::Press(square)) => Input::Confirm { square, player },
+            (_, PointerEvent::Press(square)) => mouse(square, Button::Left, true),
+            (_, PointerEvent::Release(square)) => mouse(square, Button::Left, false),
+            (_, PointerEvent::Cancel) => mouse(square, Button::Right, true),
+        };
+        recording::record_mouse(&self.settings, input);
+    }
+
+    // Moves the keyboard's cursor a square across and down the board as it's shown, staying on it.
+    // It starts in the bottom left corner.
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let Some((r, c)) = self.cursor else {
+            let corner = self.xy_to_rc(SQUARE_SIZE / 2.0, board_size() - SQUARE_SIZE / 2.0);
+            self.cursor = Some(corner);
+            return;
+        };
+        let (x, y) = self.rc_to_xy(r, c);
+        let x = x + (dx as f32 + 0.5) * SQUARE_SIZE;
+        let y = y + (dy as f32 + 0.5) * SQUARE_SIZE;
+        if (0.0..board_size()).contains(&x) && (0.0..board_size()).contains(&y) {
+            self.cursor = Some(self.xy_to_rc(x, y));
+        }
+    }
+
+    // Turns the board around, like flip_board.
+    fn flip(&mut self) {
+        let mut f = FLIPPED.lock().unwrap();
+        *f = !*f;
+    }
+
+    // Takes back the player's last move, or online, asks the other player to. In the browser, the
+    // page asks for takebacks online (see assets/js/multiplayer.js).
+    fn undo(&mut self) {
+        let Some(ply) = self.takeback_ply() else {
+            log!("{}", tr("takeback.none"));
+            return;
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(net) = &self.net {
+            net.request_takeback(ply);
+            self.takeback_requested = Some(ply);
+            log!("Asked the other player to take back to ply {}", ply);
+            return;
+        }
+        #[cfg(target_arch = "wasm32")]
+        if self.mode == GameMode::Online {
+            return;
+        }
+        self.take_back(ply);
+    }
+
+    // C shows or hides the coordinates, and I switches between dragging and clicking pieces.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_settings_keys(&mut self) {
+        if is_key_pressed(KeyCode::C) {
+            self.change_setting(Setting::Coordinates);
+        }
+        if is_key_pressed(KeyCode::I) {
+            self.change_setting(Setting::Click);
+        }
+    }
+
+    // Changes a setting, from its key or its row in the settings panel, and saves the settings, on
+    // the server too when logged in.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn change_setting(&self, setting: Setting) {
+        let mut s = SETTINGS.lock().unwrap();
+        match setting {
+            Setting::Coordinates => s.coordinates = !s.coordinates,
+            Setting::Click => {
+                s.input_mode = match s.input_mode {
+                    InputMode::Drag => InputMode::Click,
+                    InputMode::Click => InputMode::Drag,
+                }
+            }
+            Setting::AutoQueen => s.auto_queen = !s.auto_queen,
+            Setting::Ponder => s.ponder = !s.ponder,
+            Setting::BlunderCheck => s.blunder_check = !s.blunder_check,
+            Setting::Theme => s.theme = s.theme.next(),
+        }
+        s.save();
+        if let Some(net) = &self.net {
+            net.save_prefs(&s.to_json());
+        }
+    }
+
+    // What the desktop binary's buttons do (see buttons.rs).
+    #[cfg(not(target_arch = "wasm32"))]
+    fn button_clicked(&mut self, clicked: Clicked) {
+        match clicked {
+            Clicked::Command(Command::Resign) if self.can_end_game() => {
+                self.dialogs.push(Dialog::yes_no(
+                    tr("dialog.resign"),
+                    "dialog.yes",
+                    "dialog.no",
+                    Reply::Resign,
+                ))
+            }
+            Clicked::Command(Command::Resign) => {}
+            Clicked::Command(Command::Draw) => self.offer_draw(),
+            Clicked::Command(Command::Flip) => self.flip(),
+            Clicked::Command(Command::Settings) => self.settings_open = !self.settings_open,
+            Clicked::Command(Command::Export) => self.export_game(),
+            Clicked::Setting(setting) => self.change_setting(setting),
+        }
+    }
+
+    // Whether the player at the board can resign or agree to a draw: while the game's on, and
+    // they're playing it.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn can_end_game(&self) -> bool {
+        !self.spectating && self.shared_analysis.is_none() && !self.metadata.contains_key("Result")
+    }
+
+    // The player at the board gives up, and the other player wins.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn resign(&mut self) {
+        if !self.can_end_game() {
+            return;
+        }
+        let result = if self.player == 0 { "0-1" } else { "1-0" };
+        self.end_game(result, "resignation");
+    }
+
+    // Offers the other player a draw, or accepts theirs. Against the computer, the game's drawn
+    // unless the computer's ahead.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn offer_draw(&mut self) {
+        if !self.can_end_game() {
+            return;
+        }
+        let accepted = match self.mode {
+            GameMode::VsComputer { .. } => self.computer_accepts_draw(),
+            _ => self.draw_offered.is_some_and(|p| p != self.player),
+        };
+        if accepted {
+            self.draw_offered = None;
+            self.end_game("1/2-1/2", "agreement");
+        } else if let GameMode::VsComputer { .. } = self.mode {
+            log!("{}", tr("draw.declined"));
+        } else if self.draw_offered.is_none() {
+            self.draw_offered = Some(self.player);
+            log!("{}", tr("draw.offered"));
+            if let Some(net) = &self.net {
+                net.offer_draw();
+            }
+        }
+    }
+
+    // Whether the computer is no better off than the player at the board, on material.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn computer_accepts_draw(&self) -> bool {
+        let (pp, gd) = self.core.position();
+        // From the point of view of the player to move.
+        let score = engine::evaluate(&pp, gd, None);
+        let computer = 1 - self.player;
+        let score = if gd.player_to_move() == computer {
+            score
+        } else {
+            -score
+        };
+        score <= 0
+    }
+
+    // Saves a picture of the position shown, as P does, and the game's PGN.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_game(&mut self) {
+        self.save_image();
+        let pgn = PGN.lock().unwrap().clone().unwrap_or_default();
+        match export::save_pgn(&pgn) {
+            Ok(path) => log!("Saved the game to {}", path),
+            Err(e) => warn!("Couldn't save the game: {}", e),
+        }
+    }
+
+    pub fn handle_js_move(&mut self) {
+        let mut m = JS_MOVE.lock().unwrap();
+        if let Some(m) = m.take() {
+            debug!("Got a JsMove! {:?}", m);
+            self.apply_remote_move(m);
+        }
+        drop(m);
+        let load = LOAD.lock().unwrap().take();
+        if let Some(load) = load {
+            self.load(load);
+        }
+        let puzzle = PUZZLE.lock().unwrap().take();
+        if let Some((puzzle, start)) = puzzle {
+            self.start_puzzle(puzzle, start);
+        }
+        let guess = GUESS.lock().unwrap().take();
+        if let Some((load, side)) = guess {
+            self.start_guessing(load, side);
+        }
+        let restore = RESTORE.lock().unwrap().take();
+        if let Some(snapshot) = restore {
+            self.restore(snapshot);
+        }
+        let played = std::mem::take(&mut *PLAYED_MOVES.lock().unwrap());
+        for s in played {
+            let m = parse_long_algebraic(
+                &self.core.rules,
+                &self.core.board(),
+                self.core.game_data(),
+                &s,
+            );
+            // Several can come in a frame, so the player isn't the one picked up for it.
+            let player = self.player_color();
+            recording::record(Input::Play {
+                m: s.clone(),
+                player,
+            });
+            match m {
+                Some((p, m)) if self.live() && self.core.game_data().player_to_move() == player => {
+                    self.apply_move(player, p, m);
+                }
+                _ => warn!(
+                    "Ignoring move {}: it's not legal for the player at the board",
+                    s
+                ),
+            }
+        }
+    }
+
+    // Sets up a game between two players at the board from a position and moves.
+    fn load(&mut self, load: Load) {
+        self.start(load.mode);
+        if let Some((pp, gd)) = load.start {
+            self.core.history = vec![(pp, gd)];
+        }
+        for (tag, value) in load.tags {
+            if let Some(&tag) = ["White", "Black", "Event", "Site", "Date"]
+                .iter()
+                .find(|&&t| t == tag)
+            {
+                self.metadata.insert(tag, value);
+            }
+        }
+        for (i, s) in load.moves.iter().enumerate() {
+            let (pp, gd) = self.core.position();
+            let Some((p, m)) = parse_san(&self.core.rules, &pp, gd, s) else {
+                warn!("Stopped loading at {}: it's not legal here", s);
+                break;
+            };
+            self.core.play(p, m);
+            if let Some(notes) = load.notes.get(i).filter(|n| !n.is_empty()) {
+                self.notes.insert(i, notes.clone());
+            }
+        }
+        self.view = self.core.history.len() - 1;
+        self.publish_position();
+        self.publish_metadata();
+        self.announce_turn();
+        self.start_recording();
+    }
+
+    // Sets up the puzzle's position for the player, on their side of the board.
+    fn start_puzzle(&mut self, puzzle: Puzzle, start: (PiecePlacements, GameData)) {
+        self.load(Load {
+            mode: GameMode::HotSeat,
+            start: Some(start),
+            moves: Vec::new(),
+            notes: Vec::new(),
+            tags: Vec::new(),
+        });
+        let flipped = puzzle.side == 1;
+        *FLIPPED.lock().unwrap() = flipped;
+        self.flipped = flipped;
+        let color = tr(if flipped {
+            "color.black"
+        } else {
+            "color.white"
+        });
+        self.set_status(tr_with("puzzle.started", &[("color", &color)]));
+        self.puzzle = Some(puzzle);
+    }
+
+    // The teaching preset's aids if it's on, and otherwise none. Online games don't get them.
+    fn aids(&self) -> Aids {
+        if self.teaching && self.mode != GameMode::Online {
+            Aids::TEACHING
+        } else {
+            Aids::default()
+        }
+    }
+
+    // Warns the player whose move it was if it left one of their pieces to be won.
+    fn warn_hanging(&mut self) {
+        let (pp, gd) = self.core.position();
+        if let Some((name, (r, c))) = teaching::hanging(&self.core.rules, &pp, gd) {
+            let piece = announce::piece_name(name);
+            let square = square_name(r as u8, c as u8);
+            self.set_status(tr_with(
+                "teaching.hanging",
+                &[("piece", &piece), ("square", &square)],
+            ));
+        }
+    }
+
+    // Sets up the game's start for the player to guess its moves for a side, on that side of the
+    // board.
+    fn start_guessing(&mut self, load: Load, side: usize) {
+        let moves = load.moves;
+        self.load(Load {
+            moves: Vec::new(),
+            notes: Vec::new(),
+            ..load
+        });
+        let flipped = side == 1;
+        *FLIPPED.lock().unwrap() = flipped;
+        self.flipped = flipped;
+        let color = tr(if flipped {
+            "color.black"
+        } else {
+            "color.white"
+        });
+        self.set_status(tr_with("guess.started", &[("color", &color)]));
+        self.guessing = Some(Guessing::new(side, moves));
+        self.reveal_replies();
+    }
+
+    // Scores the player's move as a guess at the game's, and plays the game's instead.
+    fn guess(&mut self, piece: Piece, m: Move) {
+        let Some(mut guessing) = self.guessing.take() else {
+            return;
+        };
+        let (pp, gd) = self.core.position();
+        let Some(actual) = guessing
+            .reveal()
+            .and_then(|s| parse_san(&self.core.rules, &pp, gd, &s))
+        else {
+            warn!("Stopped guessing: the game's move isn't legal here");
+            return;
+        };
+        let points = guessing.score(&self.core.rules, &pp, gd, (piece, m), actual);
+        let text = if (piece, m) == actual {
+            tr_with("guess.right", &[("points", &points.to_string())])
+        } else {
+            let played = san(&self.core.rules, &pp, gd, actual.0, actual.1);
+            tr_with(
+                "guess.scored",
+                &[("move", &played), ("points", &points.to_string())],
+            )
+        };
+        self.set_status(text);
+        let side = guessing.side;
+        // Not guessing, it's played like any other move.
+        self.apply_move(side, actual.0, actual.1);
+        self.guessing = Some(guessing);
+        self.reveal_replies();
+    }
+
+    // Plays the game's moves for the side the player isn't guessing for, up to their next guess,
+    // and tells them their score once there's nothing left to guess.
+    fn reveal_replies(&mut self) {
+        let Some(mut guessing) = self.guessing.take() else {
+            return;
+        };
+        while self.core.game_data().player_to_move() != guessing.side {
+            let Some(s) = guessing.reveal() else {
+                break;
+            };
+            let (pp, gd) = self.core.position();
+            let Some((p, m)) = parse_san(&self.core.rules, &pp, gd, &s) else {
+                warn!("Stopped guessing at {}: it's not legal here", s);
+                return;
+            };
+            self.apply_move(1 - guessing.side, p, m);
+        }
+        if guessing.done() {
+            let max = guessing.guesses * MAX_POINTS;
+            self.set_status(tr_with(
+                "guess.finished",
+                &[
+                    ("points", &guessing.points.to_string()),
+                    ("max", &max.to_string()),
+                ],
+            ));
+        } else {
+            self.guessing = Some(guessing);
+        }
+    }
+
+    // Sets up a game between two players at the board from a snapshot, where it was left off.
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.start(GameMode::HotSeat);
+        let history = match snapshot.history.positions(&self.core.rules) {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Ignoring snapshot: {}", e);
+                return;
+            }
+        };
+        self.core.history = history;
+        self.core.moves = snapshot.history.moves().to_vec();
+        self.view = self.core.history.len() - 1;
+        self.publish_position();
+        self.publish_metadata();
+        self.announce_turn();
+        self.start_recording();
+    }
+
+    // Analyzes a position the player pasted, or tells them why it can't be.
+    fn paste(&mut self, pasted: Result<(PiecePlacements, GameData), String>) {
+        match pasted {
+            Ok(start) => {
+                self.load(Load {
+                    mode: GameMode::HotSeat,
+                    start: Some(start),
+                    moves: Vec::new(),
+                    notes: Vec::new(),
+                    tags: Vec::new(),
+                });
+                self.analysis_on = true;
+                self.set_status(tr("paste.loaded"));
+            }
+            Err(e) => self.set_status(tr_with("paste.invalid", &[("error", &e)])),
+        }
+    }
+
+    // Ctrl+V (or Cmd+V) pastes a FEN from the clipboard.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_paste_key(&mut self) {
+        let modifier = [
+            KeyCode::LeftControl,
+            KeyCode::RightControl,
+            KeyCode::LeftSuper,
+            KeyCode::RightSuper,
+        ]
+        .into_iter()
+        .any(is_key_down);
+        if !modifier || !is_key_pressed(KeyCode::V) {
+            return;
+        }
+        let text = unsafe { get_internal_gl() }.quad_context.clipboard_get();
+        self.paste(
+            text.ok_or("The clipboard is empty".to_string())
+                .and_then(|s| pasted_position(&s)),
+        );
+    }
+
+    // Shows text on the status line for a while, and tells the page.
+    fn set_status(&mut self, text: String) {
+        log!("{}", text);
+        dispatch(Event::Status(&text));
+        self.status = Some((text, get_time()));
+    }
+
+    // Picks up a saved game (see autosave.rs) where it was left off.
+    fn resume(&mut self, saved: Saved) {
+        let load = match pgn_load(&saved.pgn, saved.mode) {
+            Ok(load) => load,
+            Err(e) => {
+                warn!("Couldn't resume the saved game: {}", e);
+                return;
+            }
+        };
+        self.import_ruleset(&saved.ruleset);
+        self.publish_ruleset();
+        *FLIPPED.lock().unwrap() = saved.flipped;
+        self.flipped = saved.flipped;
+        self.load(load);
+        // Saved again right away, so it isn't lost if the game closes before the next save.
+        self.autosave = Autosave::new();
+    }
+
+    // Saves the game every so often. Not while the menu is up, though: the game behind it isn't
+    // one the player is playing, and saving it would replace the one they can resume.
+    pub fn handle_autosave(&mut self) {
+        let now = get_time();
+        if self.menu.is_some() || !self.autosave.due(now) {
+            return;
+        }
+        let moves = match &self.variation {
+            Some((game, _)) => &game.moves,
+            None => &self.core.moves,
+        };
+        let saved = if moves.is_empty() || self.metadata.contains_key("Result") {
+            None
+        } else {
+            let pgn = PGN.lock().unwrap().clone().unwrap_or_default();
+            Saved::new(
+                self.mode,
+                self.flipped,
+                &self.core.rules.export_ruleset(),
+                &pgn,
+            )
+        };
+        if self.autosave.save(saved.as_ref(), now) {
+            *AUTOSAVE.lock().unwrap() = self.autosave.saved().to_string();
+        }
+    }
+
+    // Makes the other player's move, and checks the position matches theirs afterwards.
+    fn apply_remote_move(&mut self, f: MoveFrame) {
+        // Spectators get both players' moves.
+        let player = if self.spectating {
+            self.core.game_data().player_to_move()
+        } else {
+            1 - self.player
+        };
+        recording::record(Input::Remote { m: f, player });
+        let promotion = f.promotion;
+        let passed = f.flags & MOVE_PASS != 0;
+        let phase = ((f.flags & MOVE_PHASE) >> MOVE_PHASE_SHIFT) as usize;
+        if phase != self.core.game_data().phase_index() {
+            warn!(
+                "Ignoring a move from the other player in another phase: {:?}",
+                f
+            );
+            return;
+        }
+        let made = if passed {
+            self.try_pass(player)
+        } else {
+            self.try_move(player, f.src_row, f.src_col, &f.legs(), promotion)
+        };
+        if !made {
+            warn!("Ignoring illegal move from the other player: {:?}", f);
+            return;
+        }
+        let hash = Rules::position_hash(&self.core.board(), self.core.game_data());
+        if f.hash != 0 && f.hash != hash {
+            warn!("Out of sync with the other player after {:?}", f);
+        }
+    }
+
+    // Runs the engine's search, the legal moves and the analysis for a while (see scheduler.rs).
+    pub fn run_tasks(&mut self) {
+        scheduler::run_frame(self);
+    }
+
+    // Thinks about the engine's move when it's the engine's turn, and makes it once it's found.
+    fn step_engine(&mut self, until: f64) -> bool {
+        let player = self.core.game_data().player_to_move();
+        let engine = match &self.engine {
+            Some(e) if self.menu.is_none() && player != self.player && !self.branching() => e,
+            _ => {
+                self.thinking = None;
+                return false;
+            }
+        };
+        // Wait until the player's move has been drawn.
+        if self.drawn_ply != self.core.game_data().ply {
+            return false;
+        }
+        let (pp, gd) = self.core.position();
+        let thinking = match &mut self.thinking {
+            Some(t) if t.is_thinking_about(&pp, gd) => t,
+            t => t.insert(match self.pondering.take() {
+                // The player made the reply the engine expected, so what it found meanwhile counts.
+                Some(mut p) if p.is_thinking_about(&pp, gd) => {
+                    p.ponder_hit();
+                    p
+                }
+                _ => engine.think(pp, gd),
+            }),
+        };
+        let found = match thinking.step(&self.core.rules, until) {
+            Some(found) => found,
+            None => return true,
+        };
+        let ponder = match found {
+            Some(played) if self.settings.ponder => thinking.expected_reply(played).map(|reply| {
+                let mut pp = pp;
+                for (p, m) in [played, reply] {
+                    Rules::make_move(p, m, &mut pp);
+                }
+                let gd = GameData {
+                    ply: reply.1.game_data.ply + 1,
+                    ..reply.1.game_data
+                };
+                engine.ponder(pp, gd)
+            }),
+            _ => None,
+        };
+        self.thinking = None;
+        match found {
+            Some((p, m)) => {
+                recording::record(Input::Engine {
+                    m: long_algebraic(p, m),
+                    player,
+                });
+                self.apply_move(player, p, m);
+                self.pondering = ponder;
+            }
+            None => {
+                log!("{}", tr("game_over.no_moves"));
+                self.engine = None;
+            }
+        }
+        false
+    }
+
+    // Searches the position the engine expects after the player's reply while they think. It's
+    // left alone once they've moved, for step_engine to pick up if they played the expected reply.
+    fn step_ponder(&mut self, until: f64) -> bool {
+        if !self.settings.ponder || self.engine.is_none() || self.menu.is_some() {
+            self.pondering = None;
+            return false;
+        }
+        match &mut self.pondering {
+            Some(t) if self.core.game_data().player_to_move() == self.player => {
+                t.ponder(&self.core.rules, until)
+            }
+            _ => false,
+        }
+    }
 
-// So JS can tell WASM to make a move
-#[no_mangle]
-pub extern "C" fn make_move_from_js(
-    src_row: usize,
-    src_col: usize,
-    dst_row: usize,
-    dst_col: usize,
-) {
-    log!("Got a move from JS!");
-    let mut m = JS_MOVE.lock().unwrap();
-    *m = Some(JsMove {
-        src_row,
-        src_col,
-        dst_row,
-        dst_col,
-    })
-}
+    // Works out the moves of the player at the board, unless it already has for this position.
+    fn step_legal_moves(&mut self) -> bool {
+        self.core.prepare_legal(self.player);
+        false
+    }
 
-static FLIPPED: Mutex<bool> = Mutex::new(false);
+    // Works out the player's moves after the other player's replies while they think, so whichever
+    // they play, the player's moves are ready for them. Rules from JS plugins can make that slow.
+    fn step_lookahead(&mut self) -> bool {
+        if self.spectating || self.game_over() {
+            return false;
+        }
+        self.core.look_ahead(self.player)
+    }
 
-#[no_mangle]
-pub extern "C" fn flip_board(flipped: u32) {
-    let mut f = FLIPPED.lock().unwrap();
-    *f = flipped != 0;
-}
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect(&mut self, mode: net::Mode) {
+        let ruleset = self.core.rules.export_ruleset();
+        let mut net = net::Client::connect(&self.server, &mode, Some(ruleset));
+        if let Some(user) = net::user_id() {
+            net.login(&user);
+        }
+        self.net = Some(net);
+    }
 
-static RULES_UPDATE: Mutex<Option<HashMap<String, bool>>> = Mutex::new(None);
+    // Desktop counterpart of the JS multiplayer callbacks.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn handle_net_events(&mut self) {
+        let events = match &mut self.net {
+            Some(net) => net.poll(),
+            None => return,
+        };
+        for e in events {
+            match e {
+                net::NetEvent::Created(game_id) => {
+                    log!("{}", tr_with("online.created", &[("game_id", &game_id)]));
+                }
+                net::NetEvent::OpponentJoined(color) => {
+                    let name = tr(["color.white", "color.black"][color]);
+                    log!("{}", tr_with("online.opponent_joined", &[("color", &name)]));
+                    flip_board(color as u32);
+                }
+                net::NetEvent::OpponentMove(m) => self.apply_remote_move(m),
+                net::NetEvent::Presence(p) => self.set_presence(p),
+                net::NetEvent::Ruleset(r) => self.import_ruleset(&r),
+                net::NetEvent::RulesUpdate(r) => self.apply_rules_update(&r),
+                net::NetEvent::TakebackRequest(ply) => {
+                    let question = tr("takeback.requested");
+                    log!("{}", question);
+                    self.dialogs.push(Dialog::yes_no(
+                        question,
+                        "dialog.accept",
+                        "dialog.decline",
+                        |yes| Reply::Takeback(yes.then_some(ply)),
+                    ));
+                }
+                net::NetEvent::TakebackAccepted(ply) => {
+                    if self.takeback_requested.take() == Some(ply) {
+                        self.take_back(ply);
+                    } else {
+                        warn!("Ignoring takeback to ply {} we didn't ask for", ply);
+                    }
+                }
+                net::NetEvent::TakebackDeclined => {
+                    self.takeback_requested = None;
+                    log!("{}", tr("takeback.declined"));
+                }
+                net::NetEvent::DrawOffered if !self.spectating => {
+                    let question = tr("draw.received");
+                    log!("{}", question);
+                    self.draw_offered = Some(1 - self.player);
+                    self.dialogs.push(Dialog::yes_no(
+                        question,
+                        "dialog.accept",
+                        "dialog.decline",
+                        Reply::Draw,
+                    ));
+                }
+                net::NetEvent::DrawOffered => {}
+                net::NetEvent::Abandoned(fallback) => {
+                    warn!("The other player left the game");
+                    self.adjudicate(fallback);
+                }
+                net::NetEvent::Abortable(allowed) => {
+                    self.abort_allowed = allowed;
+                    if allowed {
+                        log!("{}", tr("abort.allowed"));
+                    }
+                }
+                net::NetEvent::Aborted => self.abort(),
+                net::NetEvent::Analysis { fen, uci } => self.receive_analysis(&fen, uci.as_deref()),
+                // E.g. the other player resigned, or accepted our draw offer.
+                net::NetEvent::GameOver { result, reason } => {
+                    if self.metadata.contains_key("Result") {
+                        announce_game_over(&result, &reason);
+                    } else {
+                        self.record_result(&result, &reason);
+                    }
+                }
+                net::NetEvent::UserPrefs(prefs) => {
+                    let mut s = SETTINGS.lock().unwrap();
+                    match s.update(&prefs) {
+                        Ok(()) => s.save(),
+                        Err(e) => warn!("Ignoring saved preferences: {}", e),
+                    }
+                }
+                net::NetEvent::Denied => log!("{}", tr("online.denied")),
+                net::NetEvent::Spectating => self.spectating = true,
+                net::NetEvent::Rated => self.rated = true,
+                net::NetEvent::ServerShutdown => {
+                    warn!("The server is shutting down. Rejoin the game once it's back.")
+                }
+                net::NetEvent::Disconnected => log!("{}", tr("online.disconnected")),
+            }
+        }
+    }
 
-#[no_mangle]
-pub extern "C" fn rules_update(json_str_ptr: *const u8) {
-    let len = memlen(json_str_ptr);
-    let s = unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(json_str_ptr, len)) };
-    if let Ok(v) = serde_json::from_str::<HashMap<String, bool>>(s) {
-        let mut r = RULES_UPDATE.lock().unwrap();
-        *r = Some(v);
+    fn player_color(&self) -> usize {
+        // Either player moves pieces for both sides on the shared board, and in variations.
+        if self.shared_analysis.is_some() || self.branching() {
+            return self.move_position().1.player_to_move();
+        }
+        match self.mode {
+            GameMode::HotSeat => self.core.game_data().player_to_move(),
+            GameMode::VsComputer { .. } => 0,
+            GameMode::Online => self.online_player_color(),
+        }
     }
-}
 
-// Mouse stuff
-#[derive(Clone, Copy, Debug)]
-struct DraggingState {
-    pub source_rc: (usize, usize),
-    pub piece_off_x: f32,
-    pub piece_off_y: f32,
-}
+    #[cfg(target_arch = "wasm32")]
+    fn online_player_color(&self) -> usize {
+        dispatch(Event::PlayerColor) as usize
+    }
 
-enum InputState {
-    NotDragging,
-    Dragging(DraggingState),
-}
+    #[cfg(not(target_arch = "wasm32"))]
+    fn online_player_color(&self) -> usize {
+        self.net
+            .as_ref()
+            .and_then(|n| n.color)
+            .unwrap_or(self.player)
+    }
 
-struct Game<'a> {
-    pieces_sprite: Texture2D,
-    piece_placements: PiecePlacements,
-    rules: Rules<'a>,
-    game_data: GameData,
-    input: InputState,
-    flipped: bool,
-    player: usize, // 0 for white, 1 for black
-}
+    // The move just made, for the other player. Call after it's been applied.
+    fn move_frame(&self, piece: Piece, m: Move) -> MoveFrame {
+        let flags = match m.typ {
+            MoveType::Capture { row, col } if (row, col) != (m.dst.row, m.dst.col) => {
+                MOVE_CAPTURE | MOVE_EN_PASSANT
+            }
+            MoveType::Capture { .. } | MoveType::Captures { .. } => MOVE_CAPTURE,
+            MoveType::Secondary { .. } => MOVE_CASTLE,
+            MoveType::Pass => MOVE_PASS,
+            MoveType::Normal => 0,
+        } | (m.game_data.phase_index() as u8) << MOVE_PHASE_SHIFT;
+        let mut frame = MoveFrame {
+            src_row: piece.row as usize,
+            src_col: piece.col as usize,
+            promotion: (m.dst.name != piece.name).then_some(m.dst.name),
+            flags,
+            clock_ms: 0,
+            hash: Rules::position_hash(&self.core.board(), self.core.game_data()),
+            ..Default::default()
+        };
+        frame.set_legs(&move_legs(piece, m));
+        frame
+    }
 
-impl<'a> Game<'a> {
-    pub async fn new() -> Game<'a> {
-        let mut s = Self {
-            pieces_sprite: load_texture("assets/img/pieces.png")
-                .await
-                .expect("Couldn't load pieces sprite sheet"),
-            piece_placements: [[0; 8 + 1]; 8 + 1],
-            rules: Rules::defaults(),
-            game_data: GameData { ply: 1, mask: 0 },
-            input: InputState::NotDragging,
-            flipped: false,
-            player: 0,
+    #[cfg(target_arch = "wasm32")]
+    fn send_move(&self, player: usize, piece: Piece, m: Move) {
+        // Don't echo the opponent's moves back to them.
+        if player != self.player {
+            return;
+        }
+        let frame = self.move_frame(piece, m);
+        let binary = *BINARY_MOVES.lock().unwrap();
+        let payload = if binary {
+            protocol::encode_move(&frame)
+        } else {
+            protocol::encode_move_json(&frame).into_bytes()
         };
-        s.setup();
-        s
+        dispatch(Event::Move(&payload, binary));
     }
 
-    fn setup(&mut self) {
-        for (_, r) in self.rules.setup_rules.iter() {
-            let pieces = r();
-            for Piece {
-                row: r,
-                col: c,
-                name: n,
-            } in pieces.iter()
-            {
-                self.piece_placements[*r as usize][*c as usize] = *n;
+    #[cfg(not(target_arch = "wasm32"))]
+    fn send_move(&self, player: usize, piece: Piece, m: Move) {
+        // Don't echo the opponent's moves back to them.
+        if let Some(net) = &self.net {
+            if player == self.player {
+                net.send_move(&self.move_frame(piece, m), *BINARY_MOVES.lock().unwrap());
             }
         }
     }
 
-    pub fn handle_js_changes(&mut self) {
-        {
-            let f = FLIPPED.lock().unwrap();
-            self.flipped = *f;
-            self.player = unsafe { get_player_color() };
+    // legs ends with the destination, see GameCore::find_move. promotion picks the piece a pawn
+    // promotes to. The default is the rules' first choice. Returns whether the move was made.
+    fn try_move(
+        &mut self,
+        player: usize,
+        sr: usize,
+        sc: usize,
+        legs: &[(usize, usize)],
+        promotion: Option<u8>,
+    ) -> bool {
+        let turn = self.move_turn(player);
+        // The player at the board's promotions are to a queen if they have auto_queen on.
+        let queen_first = self.settings.auto_queen && player == self.player;
+        let found = self
+            .core
+            .find_move(&turn, (sr, sc), legs, promotion, queen_first);
+        self.core.pointer = Pointer::Up;
+        match found {
+            Some((piece, m)) => {
+                self.apply_move(player, piece, m);
+                true
+            }
+            None => false,
         }
+    }
 
-        {
-            let mut r = RULES_UPDATE.lock().unwrap();
-            if let Some(r) = &*r {
-                for (&n, m) in self.rules.movement_rules.iter_mut() {
-                    if let Some(&a) = r.get(n) {
-                        if m.active != a {
-                            log!("Toggling {} to {}", n, a);
-                            m.active = a;
-                        }
-                    }
-                }
+    // Passes for the player, if the rules let them pass now. Returns whether they passed.
+    fn try_pass(&mut self, player: usize) -> bool {
+        let turn = self.move_turn(player);
+        self.core.pointer = Pointer::Up;
+        match self.core.pass_move(&turn) {
+            Some((piece, m)) => {
+                self.apply_move(player, piece, m);
+                true
             }
-            *r = None;
+            None => false,
         }
     }
 
-    pub fn draw(&self) {
-        self.draw_board();
-        self.draw_pieces();
+    // The player passes, with the period key or pass_turn.
+    fn pass_turn(&mut self) {
+        if !self.branching() && self.shared_analysis.is_none() {
+            recording::record(Input::Pass(self.player));
+        }
+        if (self.live() || self.can_branch()) && self.try_pass(self.player) {
+            return;
+        }
+        self.set_status(tr("pass.not_allowed"));
     }
 
-    pub fn handle_input(&mut self) {
-        let pos = mouse_position();
-        let (r, c) = self.xy_to_rc(pos.0, pos.1);
-        match self.input {
-            InputState::NotDragging => {
-                if is_mouse_button_pressed(MouseButton::Left) {
-                    log!("Clicked ({}, {})", r, c);
-                    if self.piece_placements[r][c] != 0 {
-                        self.input = InputState::Dragging(DraggingState {
-                            source_rc: (r, c),
-                            piece_off_x: pos.0 % SQUARE_SIZE,
-                            piece_off_y: pos.1 % SQUARE_SIZE,
-                        })
-                    }
+    // Plays the move the player made on the board, first asking them whether they're sure if they
+    // have the blunder check on and it looks like one (see blunder.rs). Only moves in the game
+    // itself are checked, not ones in analysis, guesses or puzzles, and rated games go without.
+    fn submit_move(&mut self, player: usize, piece: Piece, m: Move) {
+        let checked = self.settings.blunder_check
+            && !self.rated
+            && self.shared_analysis.is_none()
+            && !self.branching()
+            && !self.guessing.as_ref().is_some_and(|g| g.side == player)
+            && !self.puzzle.as_ref().is_some_and(|p| p.side == player);
+        let (pp, gd) = self.core.position();
+        if checked && blunder::is_blunder(&self.core.rules, &pp, gd, (piece, m)) {
+            self.dialogs.push(Dialog::yes_no(
+                tr("dialog.blunder"),
+                "dialog.yes",
+                "dialog.no",
+                |yes| Reply::Blunder(yes.then_some((player, piece, m))),
+            ));
+            return;
+        }
+        self.apply_move(player, piece, m);
+    }
+
+    fn apply_move(&mut self, player: usize, piece: Piece, m: Move) {
+        if self.shared_analysis.is_some() {
+            let uci = self.play_shared_move(piece, m);
+            self.send_analysis(Some(&uci));
+            return;
+        }
+        if self.branching() {
+            self.play_variation_move(piece, m);
+            return;
+        }
+        if self.guessing.as_ref().is_some_and(|g| g.side == player) {
+            self.guess(piece, m);
+            return;
+        }
+        // In a puzzle, only the solution's moves are played.
+        let step = match &mut self.puzzle {
+            Some(puzzle) if puzzle.side == player => Some(puzzle.check(&long_algebraic(piece, m))),
+            _ => None,
+        };
+        if step == Some(Step::Wrong) {
+            self.set_status(tr("puzzle.wrong"));
+            return;
+        }
+        let (before, before_gd) = self.core.position();
+        // A draw offer lapses once the player it was made to moves.
+        #[cfg(not(target_arch = "wasm32"))]
+        if self
+            .draw_offered
+            .is_some_and(|p| p != before_gd.player_to_move())
+        {
+            self.draw_offered = None;
+        }
+        self.trigger_effects(piece, m);
+        // Keep showing the current position, unless looking at an earlier one.
+        if self.live() {
+            self.view += 1;
+        }
+        self.core.play(piece, m);
+        let (pp, gd) = self.core.position();
+        let check = Rules::in_check(gd.player_to_move() == 0, &pp, gd);
+        let text = announce::describe_move(&before, piece, m, check);
+        dispatch(Event::Announce(&text));
+        let ply = gd.ply;
+        let moved = serde_json::json!({
+            "uci": long_algebraic(piece, m),
+            "san": san(&self.core.rules, &before, before_gd, piece, m),
+            "fen": fen(&pp, gd),
+            "ply": ply,
+        });
+        dispatch(Event::Moved(&moved.to_string()));
+        self.publish_position();
+        self.send_move(player, piece, m);
+        self.announce_turn();
+        // The computer's moves aren't the player's to worry about.
+        let computer = matches!(self.mode, GameMode::VsComputer { .. }) && player == 1;
+        if self.aids().hanging && !computer {
+            self.warn_hanging();
+        }
+        if let Some(step) = step {
+            self.follow_puzzle(step);
+        }
+    }
+
+    // Answers a right move in the puzzle with the solution's next, or, if it was the last, tells
+    // the page the puzzle's solved.
+    fn follow_puzzle(&mut self, step: Step) {
+        let Some(puzzle) = &self.puzzle else {
+            return;
+        };
+        match step {
+            Step::Reply(uci) => {
+                let reply = parse_long_algebraic(
+                    &self.core.rules,
+                    &self.core.board(),
+                    self.core.game_data(),
+                    &uci,
+                );
+                match reply {
+                    Some((p, m)) => self.apply_move(1 - puzzle.side, p, m),
+                    None => warn!("The puzzle's reply {} isn't legal", uci),
                 }
             }
-            InputState::Dragging(drag) => {
-                if is_mouse_button_released(MouseButton::Left) {
-                    log!("Released ({}, {})", r, c);
-                    let (sr, sc) = drag.source_rc;
-                    self.try_move(self.player, sr, sc, r, c);
-                    self.input = InputState::NotDragging;
-                }
+            Step::Solved => {
+                let solved = serde_json::json!({"id": puzzle.id, "mistakes": puzzle.mistakes});
+                let text = match puzzle.mistakes {
+                    0 => tr("puzzle.solved"),
+                    n => tr_with("puzzle.solved_mistakes", &[("count", &n.to_string())]),
+                };
+                dispatch(Event::PuzzleSolved(&solved.to_string()));
+                self.set_status(text);
+                self.puzzle = None;
             }
+            Step::Wrong => {}
         }
     }
 
-    pub fn handle_js_move(&mut self) {
-        let mut m = JS_MOVE.lock().unwrap();
-        if let Some(m) = *m {
-            log!("Got a JsMove! {:?}", m);
-            self.try_move(1 - self.player, m.src_row, m.src_col, m.dst_row, m.dst_col);
+    // Shows what the move does: captures, promotes or castles.
+    fn trigger_effects(&mut self, piece: Piece, m: Move) {
+        let dst = (m.dst.row as usize, m.dst.col as usize);
+        let mut kinds = Vec::new();
+        match m.typ {
+            MoveType::Capture { .. } | MoveType::Captures { .. } => {
+                kinds.push((EffectKind::Capture, dst))
+            }
+            MoveType::Secondary { dst: rook, .. } => {
+                kinds.push((EffectKind::Castle, dst));
+                kinds.push((EffectKind::Castle, (rook.row as usize, rook.col as usize)));
+            }
+            MoveType::Normal | MoveType::Pass => {}
+        }
+        if m.dst.name != piece.name {
+            kinds.push((EffectKind::Promotion, dst));
         }
-        *m = None;
+        self.show_effects(&kinds);
     }
 
-    fn try_move(&mut self, player: usize, sr: usize, sc: usize, dr: usize, dc: usize) {
-        if 1 <= dr && dr <= 8 && 1 <= dc && dc <= 8 {
-            let name = self.piece_placements[sr][sc];
-            if name != 0 {
-                let source_piece = Piece {
-                    row: sr as u8,
-                    col: sc as u8,
-                    name,
-                };
-                if let Some(m) = self.get_legal(player, source_piece, (dr, dc)) {
-                    Rules::make_move(source_piece, m, &mut self.piece_placements);
-                    self.game_data = m.game_data;
-                    self.game_data.ply += 1;
-                    unsafe {
-                        on_move(sr as u32, sc as u32, m.dst.row as u32, m.dst.col as u32);
-                    }
-                }
+    fn show_effects(&mut self, kinds: &[(EffectKind, (usize, usize))]) {
+        let (palette, speed) = (self.settings.theme.palette(), self.settings.animation_speed);
+        let now = get_time();
+        for &(kind, square) in kinds {
+            self.effects.trigger(kind, square, now, &palette, speed);
+        }
+    }
+
+    // Tells the page whose turn it is now, or the result if the player to move has no moves.
+    fn announce_turn(&mut self) {
+        let (pp, gd) = self.core.position();
+        let color = gd.player_to_move();
+        let has_moves = !self.core.rules.legal_moves(color, &pp, gd).is_empty();
+        if has_moves {
+            // When a player moves twice in a row, only the first move starts their turn.
+            let ply = gd.ply;
+            let previous = GameData {
+                ply: ply.saturating_sub(1),
+                ..gd
+            };
+            if ply == 1 || previous.player_to_move() != color {
+                turn_started(color);
             }
+            return;
         }
-        self.input = InputState::NotDragging;
+        let result = if !self.core.rules.lost_without_moves(color == 0, &pp, gd) {
+            "1/2-1/2"
+        } else if color == 0 {
+            "0-1"
+        } else {
+            "1-0"
+        };
+        let king = if color == 0 { b'K' } else { b'k' };
+        let mated = pp
+            .squares()
+            .find(|&(_, _, n)| n == king)
+            .filter(|_| Rules::in_check(color == 0, &pp, gd));
+        let reason = if let Some((r, c, _)) = mated {
+            self.show_effects(&[(EffectKind::Checkmate, (r, c))]);
+            "checkmate"
+        } else if result == "1/2-1/2" {
+            "stalemate"
+        } else {
+            // A variant's loss without being in check.
+            "no_moves"
+        };
+        self.end_game(result, reason);
     }
 
-    fn get_legal(&self, player: usize, piece: Piece, to: (usize, usize)) -> Option<Move> {
-        if !self.is_turn(player, piece) {
-            return None;
+    // The other player abandoned the online game.
+    fn adjudicate(&mut self, fallback: Adjudication) {
+        if self.metadata.contains_key("Result") {
+            return;
         }
-        self.rules
-            .allowed_moves(piece, &self.piece_placements, self.game_data)
-            .into_iter()
-            .find(|m| m.dst.row == to.0 as u8 && m.dst.col == to.1 as u8)
+        let insufficient = Rules::insufficient_material(&self.core.board());
+        let result = fallback.result(self.core.game_data().ply, insufficient, self.player);
+        self.end_game(result, "abandoned");
     }
 
-    fn is_turn(&self, player: usize, piece: Piece) -> bool {
-        for (_, r) in self.rules.turn_rules.iter() {
-            if r(player, piece, self.game_data) {
-                return true;
-            }
+    // Records the result and reason and tells the page. Online, the server is told too, so it can
+    // record it. In the browser, JS does that when the page is told.
+    fn end_game(&mut self, result: &str, reason: &str) {
+        self.record_result(result, reason);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(net) = &self.net {
+            net.report_result(result, termination(reason));
         }
-        return false;
+    }
+
+    // Ends the game without telling the server, e.g. when it's the one telling us.
+    fn record_result(&mut self, result: &str, reason: &str) {
+        recording::record(Input::End(result.to_string()));
+        announce_game_over(result, reason);
+        self.metadata.insert("Result", result.to_string());
+        self.metadata
+            .insert("Termination", termination(reason).to_string());
+        self.publish_metadata();
+        game_ended(result, reason);
+        self.show_ending(result, reason);
     }
 
     fn draw_board(&self) {
-        let light = Color::new(0.93, 1.0, 0.98, 1.0);
-        let dark = Color::new(0.4, 0.7, 0.7, 1.0);
-        clear_background(light);
+        let palette = self.settings.theme.palette();
+        clear_background(palette.light);
         for r in 0..8 {
             // TODO: get board size from rules
             for c in 0..8 {
                 if (r + c) % 2 == 1 {
                     let y = r as f32 * SQUARE_SIZE;
                     let x = c as f32 * SQUARE_SIZE;
-                    draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, dark);
+                    draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, palette.dark);
+                    if let Some(color) = palette.hatching {
+                        draw_hatching(x, y, color);
+                    }
                 }
             }
         }
+        // Zones the rules show, faintly so pieces and highlights stand out over them.
+        let shade = Color {
+            a: 0.2,
+            ..palette.highlight
+        };
+        for (r, c) in bit_squares(self.core.rules.zones.shaded()) {
+            let (x, y) = self.rc_to_xy(r, c);
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, shade);
+        }
+    }
+
+    // The colors JS plugins draw over squares.
+    fn draw_decorations(&self) {
+        for &((r, c), color) in self.decorations.squares() {
+            let (x, y) = self.rc_to_xy(r, c);
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, color);
+        }
+    }
+
+    // Marks a square a moving piece is on.
+    fn draw_highlight(&self, r: usize, c: usize) {
+        let p = self.settings.theme.palette();
+        let (x, y) = self.rc_to_xy(r, c);
+        if p.outline == 0.0 {
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, p.highlight);
+            return;
+        }
+        let inset = p.outline / 2.0;
+        let size = SQUARE_SIZE - p.outline;
+        draw_rectangle_lines(x + inset, y + inset, size, size, p.outline, p.highlight);
+        if let Some(edge) = p.edge {
+            draw_rectangle_lines(x, y, SQUARE_SIZE, SQUARE_SIZE, 1.0, edge);
+            let size = SQUARE_SIZE - 2.0 * p.outline;
+            draw_rectangle_lines(x + p.outline, y + p.outline, size, size, 1.0, edge);
+        }
+    }
+
+    // Marks a square a piece can go to.
+    fn draw_marker(&self, r: usize, c: usize) {
+        let p = self.settings.theme.palette();
+        let (x, y) = self.rc_to_xy(r, c);
+        let half = SQUARE_SIZE / 2.0;
+        let radius = SQUARE_SIZE * p.marker_size;
+        draw_circle(x + half, y + half, radius, p.marker);
+        if let Some(edge) = p.edge {
+            draw_circle_lines(x + half, y + half, radius, 2.0, edge);
+        }
+    }
+
+    // The files along the bottom edge and the ranks along the left one, if the player wants them.
+    fn draw_coordinates(&self) {
+        if !self.settings.coordinates {
+            return;
+        }
+        let Palette { light, dark, .. } = self.settings.theme.palette();
+        // In the opposite color to the square they're on, so they can be read. a1 is dark.
+        let color = |r: usize, c: usize| if (r + c) % 2 == 1 { dark } else { light };
+        // TODO: get board size from rules
+        let edge = if self.flipped { 8 } else { 1 };
+        for i in 1..=8 {
+            let (x, y) = self.rc_to_xy(i, edge);
+            draw_text(&i.to_string(), x + 2.0, y + 14.0, 18.0, color(i, edge));
+            let (x, y) = self.rc_to_xy(edge, i);
+            let file = ((b'a' + i as u8 - 1) as char).to_string();
+            let (x, y) = (x + SQUARE_SIZE - 10.0, y + SQUARE_SIZE - 4.0);
+            draw_text(&file, x, y, 18.0, color(edge, i));
+        }
     }
 
     fn draw_pieces(&self) {
+        let (pp, _) = &self.core.history[self.view];
+        // Where the previewed analysis line ends up. Pieces that would move are faded out, and
+        // the pieces replacing them drawn as ghosts.
+        let ghosts = self.preview.and_then(|i| {
+            let info = self.analyzer.as_ref()?.info()?;
+            Some(line_position(pp, info.lines.get(i)?))
+        });
+        let faded = Color::new(1.0, 1.0, 1.0, 0.35);
+        let ghost = Color::new(1.0, 1.0, 1.0, 0.6);
         for r in 1..=8 {
             // TODO: don't hard code board dimensions
             for c in 1..=8 {
-                let n = self.piece_placements[r][c];
+                let n = pp.get(r, c);
+                let g = ghosts.map_or(n, |g| g.get(r, c));
                 if n != 0 {
-                    let (x, y) = match self.input {
-                        InputState::Dragging(drag) if drag.source_rc == (r, c) => {
-                            let pos = mouse_position();
-                            (pos.0 - drag.piece_off_x, pos.1 - drag.piece_off_y)
+                    let (x, y) = match &self.core.pointer {
+                        Pointer::Dragging(source) if *source == (r, c) => {
+                            let pos = self.mouse();
+                            (pos.0 - self.drag_offset.0, pos.1 - self.drag_offset.1)
+                        }
+                        Pointer::Chaining { source, legs } if *source == (r, c) => {
+                            let &(lr, lc) = legs.last().unwrap();
+                            self.rc_to_xy(lr, lc)
                         }
                         _ => self.rc_to_xy(r, c),
                     };
-                    if let Some((sx, sy)) = self.rules.piece_name_to_offsets.get(&n) {
-                        draw_texture_ex(
-                            self.pieces_sprite,
-                            x,
-                            y,
-                            WHITE,
-                            DrawTextureParams {
-                                source: Some(Rect::new(
-                                    *sx as f32,
-                                    *sy as f32,
-                                    SQUARE_SIZE,
-                                    SQUARE_SIZE,
-                                )),
-                                ..Default::default()
-                            },
-                        );
-                    }
+                    let tint = self.decorations.piece_tint(r, c).unwrap_or(WHITE);
+                    self.draw_piece(n, x, y, if g == n { tint } else { faded });
+                }
+                if g != n && g != 0 {
+                    let (x, y) = self.rc_to_xy(r, c);
+                    self.draw_piece(g, x, y, ghost);
                 }
             }
         }
     }
 
+    // In click mode, marks the piece picked up and where it can go.
+    // With the teaching preset's hints, also while it's dragged.
+    fn draw_selected(&self) {
+        let source = match self.core.pointer {
+            Pointer::Selected(source) => source,
+            Pointer::Dragging(source) if self.aids().hints => source,
+            _ => return,
+        };
+        self.draw_highlight(source.0, source.1);
+        let turn = self.move_turn(self.player);
+        for l in self.core.moves_starting_with(&turn, source, &[]) {
+            let (r, c) = l[0];
+            self.draw_marker(r, c);
+        }
+    }
+
+    // Outlines the keyboard's cursor.
+    fn draw_cursor(&self) {
+        let Some((r, c)) = self.cursor else {
+            return;
+        };
+        let p = self.settings.theme.palette();
+        let (x, y) = self.rc_to_xy(r, c);
+        let width = p.outline.max(4.0);
+        let inset = width / 2.0;
+        let size = SQUARE_SIZE - width;
+        draw_rectangle_lines(x + inset, y + inset, size, size, width, p.marker);
+    }
+
+    // For a moment after the player tries to pick up a piece they can't move, tints its square and
+    // says why under it.
+    fn draw_refused(&self) {
+        const SECONDS: f64 = 1.0;
+        let Some(((r, c), refusal, at)) = self.refused else {
+            return;
+        };
+        let fade = 1.0 - ((get_time() - at) / SECONDS) as f32;
+        if fade <= 0.0 {
+            return;
+        }
+        let (x, y) = self.rc_to_xy(r, c);
+        draw_rectangle(
+            x,
+            y,
+            SQUARE_SIZE,
+            SQUARE_SIZE,
+            Color::new(0.9, 0.0, 0.0, 0.3 * fade),
+        );
+        let text = tr(match refusal {
+            Refusal::NotYourPiece => "refused.not_your_piece",
+            Refusal::NotYourTurn => "refused.not_your_turn",
+        });
+        let size = measure_text(&text, None, 18, 1.0);
+        // Centered under the square, and over it on the bottom rank.
+        let tx = (x + (SQUARE_SIZE - size.width) / 2.0).clamp(0.0, board_size() - size.width);
+        let ty = if y + SQUARE_SIZE + 24.0 > board_size() {
+            y - 6.0
+        } else {
+            y + SQUARE_SIZE + 18.0
+        };
+        let background = Color::new(0.0, 0.0, 0.0, 0.6 * fade);
+        draw_rectangle(tx - 4.0, ty - 16.0, size.width + 8.0, 22.0, background);
+        draw_text(&text, tx, ty, 18.0, Color::new(1.0, 1.0, 1.0, fade));
+    }
+
+    // Partway through a move in several legs, marks the squares the piece has stopped on and the
+    // ones it can go on to.
+    fn draw_chain(&self) {
+        let (source, legs) = match &self.core.pointer {
+            Pointer::Chaining { source, legs } => (*source, legs),
+            _ => return,
+        };
+        for &(r, c) in legs.iter() {
+            self.draw_highlight(r, c);
+        }
+        let turn = self.move_turn(self.player);
+        for l in self.core.moves_starting_with(&turn, source, legs) {
+            if let Some(&(r, c)) = l.get(legs.len()) {
+                self.draw_marker(r, c);
+            }
+        }
+    }
+
+    // For the rule debug overlay, the piece under the mouse in the position shown and every move its
+    // movement rules come up with.
+    fn explain_hovered(&self) -> Option<(Piece, Vec<Candidate<'a>>)> {
+        if !self.rule_debug || self.read_only() {
+            return None;
+        }
+        let (x, y) = self.mouse();
+        if !(0.0..board_size()).contains(&x) || !(0.0..board_size()).contains(&y) {
+            return None;
+        }
+        let (r, c) = self.xy_to_rc(x, y);
+        let (pp, gd) = self.core.history[self.view];
+        let piece = Piece {
+            row: r as u8,
+            col: c as u8,
+            name: pp.get(r, c),
+        };
+        if piece.name == 0 {
+            return None;
+        }
+        Some((piece, self.core.rules.explain_moves(piece, &pp, gd)))
+    }
+
+    // Marks each square the piece's movement rules let it go to: green if some move there is
+    // allowed, red if constraint rules reject them all.
+    fn draw_explained_squares(&self, piece: Piece, candidates: &[Candidate]) {
+        self.draw_highlight(piece.row as usize, piece.col as usize);
+        for c in candidates {
+            let dst = (c.m.dst.row, c.m.dst.col);
+            let allowed = candidates
+                .iter()
+                .any(|o| (o.m.dst.row, o.m.dst.col) == dst && o.rejected_by.is_empty());
+            let color = if allowed {
+                Color::new(0.0, 0.8, 0.0, 0.35)
+            } else {
+                Color::new(0.9, 0.0, 0.0, 0.35)
+            };
+            let (x, y) = self.rc_to_xy(dst.0 as usize, dst.1 as usize);
+            draw_rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE, color);
+        }
+    }
+
+    // While a piece is dragged over something it can capture, shows how much material the
+    // exchange on that square wins or loses. Not when playing online, where it'd be an unfair aid.
+    fn draw_exchange(&self) {
+        let source = match self.core.pointer {
+            Pointer::Dragging(source) if self.mode != GameMode::Online => source,
+            _ => return,
+        };
+        let (x, y) = self.mouse();
+        let (r, c) = self.xy_to_rc(x, y);
+        let turn = self.move_turn(self.player);
+        let found = self
+            .core
+            .find_move(&turn, source, &[(r, c)], None, self.settings.auto_queen);
+        let (piece, m) = match found {
+            Some((p, m)) if matches!(m.typ, MoveType::Capture { .. }) => (p, m),
+            _ => return,
+        };
+        let pp = turn.pp;
+        let value = see(&self.core.rules, &pp, piece, m);
+        let color = match value {
+            v if v > 0 => DARKGREEN,
+            v if v < 0 => RED,
+            _ => DARKGRAY,
+        };
+        let (x, y) = self.rc_to_xy(r, c);
+        draw_text(&format_score(value), x + 4.0, y + 24.0, 28.0, color);
+    }
+
+    // A badge in the top right corner of online games, saying whether both players are connected
+    // and how many people are watching.
+    fn draw_presence(&self) {
+        let p = match self.presence {
+            Some(p) if self.mode == GameMode::Online => p,
+            _ => return,
+        };
+        let (mut text, dot) = if p.players >= 2 {
+            (tr("presence.connected"), GREEN)
+        } else {
+            (tr("presence.waiting"), RED)
+        };
+        if self.spectating {
+            text = format!("{}: {}", tr("presence.spectating"), text);
+        }
+        if p.spectators > 0 {
+            text.push_str(", ");
+            text.push_str(&tr_with("presence.watching", &[("count", &p.spectators)]));
+        }
+        let size = 20.0;
+        let width = measure_text(&text, None, size as u16, 1.0).width + size + 12.0;
+        let x = screen_width() - width - 4.0;
+        draw_rectangle(x, 4.0, width, size + 4.0, Color::new(0.0, 0.0, 0.0, 0.6));
+        draw_circle(x + 4.0 + size / 2.0, 6.0 + size / 2.0, size / 3.0, dot);
+        draw_text(&text, x + size + 8.0, size, size, WHITE);
+    }
+
+    // Along the bottom of the window, until it's been there STATUS_SECONDS.
+    fn draw_status(&self, now: f64) {
+        let text = match &self.status {
+            Some((text, shown)) if now - shown < STATUS_SECONDS => text,
+            _ => return,
+        };
+        let size = 20.0;
+        let y = screen_height() - size - 8.0;
+        // Above the desktop binary's buttons.
+        #[cfg(not(target_arch = "wasm32"))]
+        let y = y - buttons::BAR_HEIGHT;
+        draw_rectangle(
+            0.0,
+            y,
+            screen_width(),
+            size + 8.0,
+            Color::new(0.0, 0.0, 0.0, 0.6),
+        );
+        draw_text(text, 4.0, y + size, size, WHITE);
+    }
+
+    fn draw_piece(&self, n: u8, x: f32, y: f32, color: Color) {
+        if let Some((sx, sy)) = self.core.rules.piece_name_to_offsets.get(&n) {
+            draw_texture_ex(
+                self.pieces_sprite,
+                x,
+                y,
+                color,
+                DrawTextureParams {
+                    source: Some(Rect::new(*sx as f32, *sy as f32, SQUARE_SIZE, SQUARE_SIZE)),
+                    ..Default::default()
+                },
+            );
+        } else if n == DUCK {
+            // The sprite has no duck, so it's a yellow disc.
+            let duck = Color::new(0.95 * color.r, 0.8 * color.g, 0.1 * color.b, color.a);
+            let half = SQUARE_SIZE / 2.0;
+            draw_circle(x + half, y + half, half * 0.6, duck);
+        }
+    }
+
+    // Partway through a flip, squares are drawn on their way around (see flip.rs).
     fn rc_to_xy(&self, r: usize, c: usize) -> (f32, f32) {
         // TODO: get board size from rules
         let y = if self.flipped { r - 1 } else { 8 - r } as f32 * SQUARE_SIZE;
         let x = if self.flipped { 8 - c } else { c - 1 } as f32 * SQUARE_SIZE;
-        (x, y)
+        match self.flipping {
+            Some(f) => flip::turn((x, y), f.progress(get_time()), SQUARE_SIZE, board_size()),
+            None => (x, y),
+        }
+    }
+
+    // Where the mouse is on the board, which is scrolled and zoomed by the viewport.
+    fn mouse(&self) -> (f32, f32) {
+        self.viewport.to_board(mouse_position())
     }
 
+    // x, y are on the board, as from mouse, not the screen.
     fn xy_to_rc(&self, x: f32, y: f32) -> (usize, usize) {
         let x = x as usize / SQUARE_SIZE as usize;
         let y = y as usize / SQUARE_SIZE as usize;
@@ -298,17 +3942,236 @@ impl<'a> Game<'a> {
     }
 }
 
-pub fn hook(info: &panic::PanicInfo) {
-    log!("{}", info.to_string());
+impl Tasks for Game<'_> {
+    fn step(&mut self, task: Task, until: f64) -> bool {
+        match task {
+            Task::LegalMoves => self.step_legal_moves(),
+            Task::Lookahead => self.step_lookahead(),
+            Task::Engine => self.step_engine(until),
+            Task::Analysis => match &mut self.analyzer {
+                Some(a) => a.update(&self.core.rules, until),
+                None => false,
+            },
+            Task::Ponder => self.step_ponder(until),
+        }
+    }
+}
+
+fn turn_started(color: usize) {
+    dispatch(Event::TurnStart(color));
+}
+
+fn game_ended(result: &str, reason: &str) {
+    let end = serde_json::json!({ "result": result, "reason": reason });
+    dispatch(Event::GameEnd(&end.to_string()));
+}
+
+// The PGN Termination for why a game ended: "normal" for the ways the rules end it, e.g.
+// "checkmate", and otherwise the reason itself, e.g. "abandoned".
+fn termination(reason: &str) -> &str {
+    match reason {
+        "checkmate" | "stalemate" | "no_moves" => "normal",
+        _ => reason,
+    }
+}
+
+// The browser sizes the canvas itself, so this only matters for the desktop binary.
+// In board pixels.
+fn board_size() -> f32 {
+    // TODO: get board size from rules
+    8.0 * SQUARE_SIZE
+}
+
+fn window_conf() -> Conf {
+    let size = board_size() as i32;
+    Conf {
+        window_title: "Chess".to_string(),
+        window_width: size,
+        window_height: size,
+        ..Default::default()
+    }
+}
+
+// Lists the moves of the rule debug overlay down the left of the screen, each with the movement
+// rules that came up with it and, after a slash, the constraint rules that reject it.
+fn draw_move_explanations(piece: Piece, candidates: &[Candidate]) {
+    let size = 16.0;
+    for (i, c) in candidates.iter().enumerate() {
+        let mut line = format!(
+            "{} {}",
+            long_algebraic(piece, c.m),
+            c.produced_by.join(", ")
+        );
+        if !c.rejected_by.is_empty() {
+            line.push_str(&format!(" / {}", c.rejected_by.join(", ")));
+        }
+        let y = (i + 1) as f32 * size + 4.0;
+        let width = measure_text(&line, None, size as u16, 1.0).width;
+        draw_rectangle(
+            0.0,
+            y - size + 4.0,
+            width + 8.0,
+            size,
+            Color::new(1.0, 1.0, 1.0, 0.8),
+        );
+        let color = if c.rejected_by.is_empty() {
+            DARKGREEN
+        } else {
+            RED
+        };
+        draw_text(&line, 4.0, y, size, color);
+    }
+}
+
+// Diagonal lines across the square at x, y.
+fn draw_hatching(x: f32, y: f32, color: Color) {
+    let gap = SQUARE_SIZE / 4.0;
+    for i in 1..8 {
+        // From the left or bottom edge to the top or right one.
+        let t = i as f32 * gap;
+        let (x0, y0) = if t <= SQUARE_SIZE {
+            (x, y + t)
+        } else {
+            (x + t - SQUARE_SIZE, y + SQUARE_SIZE)
+        };
+        let (x1, y1) = if t <= SQUARE_SIZE {
+            (x + t, y)
+        } else {
+            (x + SQUARE_SIZE, y + t - SQUARE_SIZE)
+        };
+        draw_line(x0, y0, x1, y1, 1.5, color);
+    }
+}
+
+// reason is why the game ended, e.g. "checkmate", or a PGN Termination, e.g. "abandoned".
+fn announce_game_over(result: &str, reason: &str) {
+    let text = i18n::game_over_text(result, reason);
+    log!("{}", text);
+    dispatch(Event::Announce(&text));
+}
+
+fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--selfplay") {
+        // No window needed.
+        selfplay::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--balance") {
+        balance::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--tune") {
+        tuning::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--analyze") {
+        batch::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--epd") {
+        epd::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--cli") {
+        // No window needed.
+        cli::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--uci") {
+        // No window needed.
+        uci::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--replay") {
+        replay::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--replay-inputs") {
+        // No window needed.
+        reproduce::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--fair-play") {
+        fairplay::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--puzzles") {
+        puzzles::main();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().any(|a| a == "--ffi-js") {
+        ffi::main();
+        return;
+    }
+    macroquad::Window::from_config(window_conf(), run());
 }
 
-#[macroquad::main("Chess")]
-async fn main() {
-    panic::set_hook(Box::new(hook));
+async fn run() {
+    panic::set_hook(Box::new(crash::hook));
+    // For the engine's blunders.
+    let seed = (miniquad::date::now() * 1000.0) as u64;
+    rand::srand(seed);
+    recording::set_seed(seed);
     let mut game = Game::new().await;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        *SETTINGS.lock().unwrap() = Settings::load();
+        *LOCALE.lock().unwrap() = i18n::system_locale();
+        if std::env::args().any(|a| a == "--export-apng") {
+            let args = export::animation_args();
+            let out = args.out.clone();
+            match game.export_animation(args) {
+                Ok(()) => eprintln!("Wrote {}", out),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        let (server, mode, json_moves, watched) = net::args();
+        if !watched.is_empty() {
+            let settings = *SETTINGS.lock().unwrap();
+            grid::run(&server, &watched, settings).await;
+            return;
+        }
+        game.server = server;
+        if json_moves {
+            set_binary_moves(0);
+        }
+        match mode {
+            Some(mode) => {
+                game.connect(mode);
+                game.start(GameMode::Online);
+            }
+            None => game.menu = Some(Menu::new(autosave::load().is_some())),
+        }
+    }
     loop {
+        handle_locale().await;
+        *VIEWED_POSITION.lock().unwrap() = Some(game.core.history[game.view]);
+        game.handle_analysis();
+        game.handle_explorer();
         game.handle_js_move();
+        #[cfg(not(target_arch = "wasm32"))]
+        game.handle_net_events();
         game.handle_js_changes();
+        game.handle_playback();
+        game.handle_autosave();
+        game.run_tasks();
+        game.handle_image_requests();
         game.draw();
         game.handle_input();
         next_frame().await
@@ -1,41 +1,92 @@
-use std::{collections::HashMap, sync::Mutex};
+// Buffers for passing data between JS and WASM. Each buffer starts with its length as a
+// little-endian u32, and the pointer handed out points just past it, so JS can read the length
+// without calling into WASM (see assets/js/mem.js). Buffers are owned here until they're freed,
+// which is also how pointers coming from JS are checked before they're read.
 
-static MEM: Mutex<Option<HashMap<usize, Vec<u8>>>> = Mutex::new(None);
+// The exports are only called from JS, so they're unused in the desktop binary.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
 
-#[no_mangle]
+use std::{collections::BTreeMap, sync::Mutex};
+
+const LEN_PREFIX: usize = 4;
+
+static BUFFERS: Mutex<BTreeMap<usize, Box<[u8]>>> = Mutex::new(BTreeMap::new());
+
+// Only exported in the browser. On the desktop, an unmangled free would replace libc's.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
 pub extern "C" fn alloc(len: usize) -> *mut u8 {
-    let mut v = Vec::with_capacity(len);
-    unsafe {
-        v.set_len(len);
-    }
-    let p = v.as_mut_ptr();
-    let k = p as usize;
-    let mut m = MEM.lock().unwrap();
-    if m.is_none() {
-        *m = Some(HashMap::new());
-    }
-    if let Some(h) = &mut *m {
-        h.insert(k, v);
-    }
-    p
+    alloc_bytes(&vec![0; len])
 }
 
-#[no_mangle]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
 pub extern "C" fn free(ptr: *const u8) {
-    let k = ptr as usize;
-    let mut m = MEM.lock().unwrap();
-    if let Some(h) = &mut *m {
-        h.remove(&k);
+    let mut b = BUFFERS.lock().unwrap();
+    b.remove(&(ptr as usize));
+}
+
+// Copies bytes into a new buffer, so they can be handed to JS. JS must free the pointer when it's
+// done.
+pub fn alloc_bytes(bytes: &[u8]) -> *mut u8 {
+    let len = u32::try_from(bytes.len()).expect("Buffer too large");
+    let mut buf = Vec::with_capacity(LEN_PREFIX + bytes.len());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(bytes);
+    let mut buf = buf.into_boxed_slice();
+    // Moving the box into the map doesn't move its contents, so the pointer stays valid.
+    let p = buf[LEN_PREFIX..].as_mut_ptr();
+    let mut b = BUFFERS.lock().unwrap();
+    b.insert(p as usize, buf);
+    p
+}
+
+// The length of the buffer at ptr, or 0 if ptr isn't a buffer from alloc.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn memlen(ptr: *const u8) -> usize {
+    let b = BUFFERS.lock().unwrap();
+    b.get(&(ptr as usize))
+        .map_or(0, |buf| buf.len() - LEN_PREFIX)
+}
+
+// A copy of the contents of the buffer at ptr.
+pub fn read_bytes(ptr: *const u8) -> Result<Vec<u8>, String> {
+    let b = BUFFERS.lock().unwrap();
+    match b.get(&(ptr as usize)) {
+        Some(buf) => Ok(buf[LEN_PREFIX..].to_vec()),
+        None => Err(format!("Not an allocated buffer: {:p}", ptr)),
     }
 }
 
-pub fn memlen(ptr: *const u8) -> usize {
-    let k = ptr as usize;
-    let m = MEM.lock().unwrap();
-    if let Some(h) = &*m {
-        if let Some(v) = h.get(&k) {
-            return v.len();
-        }
+pub fn read_string(ptr: *const u8) -> Result<String, String> {
+    String::from_utf8(read_bytes(ptr)?).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers() {
+        let p = alloc(3);
+        assert_eq!(memlen(p), 3);
+        assert_eq!(read_bytes(p), Ok(vec![0, 0, 0]));
+        let prefix = unsafe { std::slice::from_raw_parts(p.sub(LEN_PREFIX), LEN_PREFIX) };
+        assert_eq!(prefix, &3u32.to_le_bytes());
+        free(p);
+        assert_eq!(memlen(p), 0);
+        assert!(read_bytes(p).is_err());
+        free(p);
+
+        let p = alloc_bytes("héllo".as_bytes());
+        assert_eq!(read_string(p), Ok("héllo".to_string()));
+        free(p);
+
+        let p = alloc_bytes(&[0xff]);
+        assert!(read_string(p).is_err());
+        free(p);
+
+        let p = alloc(0);
+        assert_eq!(memlen(p), 0);
+        assert_eq!(read_string(p), Ok(String::new()));
+        free(p);
     }
-    panic!()
 }
@@ -0,0 +1,170 @@
+// Start menu for picking how to play. The desktop binary shows it at start up, and when Escape is
+// pressed. In the browser, JS picks the mode with set_game_mode instead, so the menu isn't shown.
+// At start up, if the last game wasn't finished (see autosave.rs), it can be resumed from here too.
+
+use macroquad::prelude::*;
+
+use crate::{engine::MAX_STRENGTH, i18n::tr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameMode {
+    // Two players taking turns on the same device.
+    HotSeat,
+    // Against the built-in engine. Strength goes from 1 to MAX_STRENGTH.
+    VsComputer { strength: u8 },
+    Online,
+}
+
+impl GameMode {
+    // How JS refers to modes in set_game_mode.
+    pub fn from_js(mode: u32, strength: u32) -> Option<Self> {
+        match mode {
+            0 => Some(GameMode::HotSeat),
+            1 => Some(GameMode::VsComputer {
+                strength: strength.clamp(1, MAX_STRENGTH as u32) as u8,
+            }),
+            2 => Some(GameMode::Online),
+            _ => None,
+        }
+    }
+}
+
+// Message keys, see i18n.rs. RESUME comes after them when there's a game to resume.
+const ITEMS: [&str; 4] = [
+    "menu.two_players",
+    "menu.vs_computer",
+    "menu.online",
+    "menu.teaching",
+];
+const RESUME: &str = "menu.resume";
+const LEFT: f32 = 40.0;
+const TOP: f32 = 120.0;
+const ITEM_HEIGHT: f32 = 60.0;
+const FONT_SIZE: f32 = 40.0;
+const SLIDER_BOX: f32 = 30.0;
+
+// What the player picked in the menu.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Choice {
+    Start(GameMode),
+    // Against the computer at its weakest, with the teaching preset (see teaching.rs).
+    Teaching,
+    Resume,
+}
+
+pub struct Menu {
+    selected: usize,
+    strength: u8,
+    // Whether there's a game to resume.
+    resume: bool,
+}
+
+impl Menu {
+    pub fn new(resume: bool) -> Self {
+        Self {
+            // Picking up where the player left off is the likeliest choice.
+            selected: if resume { ITEMS.len() } else { 0 },
+            strength: 2,
+            resume,
+        }
+    }
+
+    fn items(&self) -> usize {
+        ITEMS.len() + self.resume as usize
+    }
+
+    // Handles keyboard and mouse input. Returns the player's choice once they make it.
+    pub fn update(&mut self) -> Option<Choice> {
+        let items = self.items();
+        if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1) % items;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.selected = (self.selected + items - 1) % items;
+        }
+        if is_key_pressed(KeyCode::Left) {
+            self.strength = (self.strength - 1).max(1);
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.strength = (self.strength + 1).min(MAX_STRENGTH);
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            return Some(self.choice());
+        }
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (x, y) = mouse_position();
+            if let Some(s) = self.slider_at(x, y) {
+                self.strength = s;
+            } else if let Some(i) = self.item_at(y) {
+                self.selected = i;
+                return Some(self.choice());
+            }
+        }
+        None
+    }
+
+    pub fn draw(&self) {
+        clear_background(Color::new(0.4, 0.7, 0.7, 1.0));
+        draw_text(
+            &tr("menu.title"),
+            LEFT,
+            TOP - ITEM_HEIGHT,
+            FONT_SIZE * 1.5,
+            WHITE,
+        );
+        for (i, item) in ITEMS
+            .iter()
+            .chain(self.resume.then_some(&RESUME))
+            .enumerate()
+        {
+            let y = TOP + i as f32 * ITEM_HEIGHT;
+            let color = if i == self.selected { WHITE } else { DARKGRAY };
+            draw_text(&tr(item), LEFT, y, FONT_SIZE, color);
+        }
+        // Strength slider, to the right of "Vs computer"
+        for s in 1..=MAX_STRENGTH {
+            let (x, y) = self.slider_box_xy(s);
+            if s <= self.strength {
+                draw_rectangle(x, y, SLIDER_BOX, SLIDER_BOX, WHITE);
+            }
+            draw_rectangle_lines(x, y, SLIDER_BOX, SLIDER_BOX, 2.0, DARKGRAY);
+        }
+    }
+
+    fn choice(&self) -> Choice {
+        match self.selected {
+            0 => Choice::Start(GameMode::HotSeat),
+            1 => Choice::Start(GameMode::VsComputer {
+                strength: self.strength,
+            }),
+            2 => Choice::Start(GameMode::Online),
+            3 => Choice::Teaching,
+            _ => Choice::Resume,
+        }
+    }
+
+    fn item_at(&self, y: f32) -> Option<usize> {
+        // Text is drawn with y at the baseline.
+        let i = (y - TOP + FONT_SIZE) / ITEM_HEIGHT;
+        if i >= 0.0 && (i as usize) < self.items() {
+            Some(i as usize)
+        } else {
+            None
+        }
+    }
+
+    fn slider_box_xy(&self, strength: u8) -> (f32, f32) {
+        // Past the end of "Vs computer", however long it is in the player's language.
+        let label = measure_text(&tr(ITEMS[1]), None, FONT_SIZE as u16, 1.0).width;
+        let x = LEFT + (label + 20.0).max(260.0) + (strength - 1) as f32 * (SLIDER_BOX + 5.0);
+        let y = TOP + ITEM_HEIGHT - SLIDER_BOX;
+        (x, y)
+    }
+
+    fn slider_at(&self, x: f32, y: f32) -> Option<u8> {
+        (1..=MAX_STRENGTH).find(|&s| {
+            let (bx, by) = self.slider_box_xy(s);
+            bx <= x && x < bx + SLIDER_BOX && by <= y && y < by + SLIDER_BOX
+        })
+    }
+}
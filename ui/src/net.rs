@@ -0,0 +1,397 @@
+// Native client for the multiplayer server, so the desktop binary can play online. It speaks the
+// same protocol as assets/js/multiplayer.js (see chess_ui::protocol), so desktop and browser players
+// can play each other. The websocket runs on its own thread with a small tokio runtime; the game loop talks to
+// it through channels and polls for events once per frame.
+
+use std::{
+    collections::HashMap,
+    sync::mpsc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use macroquad::rand;
+use serde_json::{json, Value};
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use chess_ui::{
+    link::GameLink,
+    protocol::{self, Adjudication, Message as ProtocolMessage, MoveFrame, Presence},
+};
+
+use crate::{debug, error, settings::config_path, warn};
+
+pub const DEFAULT_SERVER: &str = "ws://localhost:58597";
+
+pub enum Mode {
+    Create,
+    // Where on the server to join the game, as in GameLink::join_path.
+    Join(String),
+}
+
+pub enum NetEvent {
+    // We created a game. Share the ID with the other player.
+    Created(String),
+    // Both players are in the game. The value is our color: 0 for white, 1 for black.
+    OpponentJoined(usize),
+    OpponentMove(MoveFrame),
+    // Who's connected to the game. Sent whenever someone joins or leaves.
+    Presence(Presence),
+    Ruleset(String),
+    RulesUpdate(HashMap<String, bool>),
+    // The other player asks to go back to the position at this ply.
+    TakebackRequest(u16),
+    // The other player agreed to our request to go back to this ply.
+    TakebackAccepted(u16),
+    TakebackDeclined,
+    // The other player offers a draw.
+    DrawOffered,
+    // The other player left and didn't come back in time. We adjudicate the game.
+    Abandoned(Adjudication),
+    // Whether we can abort the game, when the server changes its mind about it: once the other
+    // player has taken too long to make their first move, or when it refused to.
+    Abortable(bool),
+    Aborted,
+    // A move on the board both players analyze on after the game, or where it starts.
+    Analysis { fen: String, uci: Option<String> },
+    // The other player, or for a spectator either player, says the game ended.
+    GameOver { result: String, reason: String },
+    // Our preferences as the server has them, after we logged in: settings to apply, as in
+    // Settings::update.
+    UserPrefs(String),
+    // The game is private and we don't have its passcode. Disconnected follows.
+    Denied,
+    // We're watching the game, not playing it.
+    Spectating,
+    // We're playing a rated game, without assists.
+    Rated,
+    // The server is about to go away. Disconnected follows.
+    ServerShutdown,
+    Disconnected,
+}
+
+pub struct Client {
+    tx: tokio_mpsc::UnboundedSender<Message>,
+    rx: mpsc::Receiver<Option<Message>>,
+    pub game_id: Option<String>,
+    pub color: Option<usize>,
+    // Our user ID once we've logged in.
+    user: Option<String>,
+}
+
+// Parses --create, --join <id or link>, --server <url>, --json-moves and --watch <id or link> from
+// the command line. The mode is None when neither --create nor --join is given. --json-moves sends
+// moves as JSON instead of binary frames, which is easier to read in the server's logs. --watch can
+// be given for any number of games.
+pub fn args() -> (String, Option<Mode>, bool, Vec<String>) {
+    let mut server = DEFAULT_SERVER.to_string();
+    let mut mode = None;
+    let mut json_moves = false;
+    let mut watched = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--create" => mode = Some(Mode::Create),
+            // A game ID, or a link to the game.
+            "--join" => mode = args.next().map(|a| Mode::Join(join_path(&a))),
+            // Games to watch at once, each as a path to join like Mode::Join's (see grid.rs).
+            "--watch" => watched.extend(args.next().map(|a| join_path(&a))),
+            "--server" => server = args.next().unwrap_or(server),
+            "--json-moves" => json_moves = true,
+            _ => warn!("Ignoring unknown argument: {}", a),
+        }
+    }
+    (server, mode, json_moves, watched)
+}
+
+// Where to join the game with the ID, or at the link.
+fn join_path(game: &str) -> String {
+    GameLink::parse(game).map_or(format!("join/{}", game), |link| link.join_path())
+}
+
+impl Client {
+    // ruleset is only used when creating a game. See Rules::export_ruleset.
+    pub fn connect(server: &str, mode: &Mode, ruleset: Option<String>) -> Self {
+        let url = match mode {
+            Mode::Create => match ruleset {
+                Some(r) => format!("{}/create?ruleset={}", server, encode_uri_component(&r)),
+                None => format!("{}/create", server),
+            },
+            Mode::Join(path) => format!("{}/{}", server, path),
+        };
+        let (tx, out_rx) = tokio_mpsc::unbounded_channel();
+        let (in_tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Couldn't start tokio runtime");
+            rt.block_on(run(url, out_rx, in_tx));
+        });
+        Self {
+            tx,
+            rx,
+            game_id: None,
+            color: None,
+            user: None,
+        }
+    }
+
+    // Returns the events received since the last call. Like the JS client, this also does the
+    // protocol bookkeeping, e.g. the creator assigns colors when the opponent joins.
+    pub fn poll(&mut self) -> Vec<NetEvent> {
+        let mut events = Vec::new();
+        while let Ok(msg) = self.rx.try_recv() {
+            match msg {
+                Some(msg) => {
+                    if let Some(e) = self.dispatch(&msg) {
+                        events.push(e);
+                    }
+                }
+                None => events.push(NetEvent::Disconnected),
+            }
+        }
+        events
+    }
+
+    pub fn send_move(&self, m: &MoveFrame, binary: bool) {
+        if binary {
+            self.send_message(Message::Binary(protocol::encode_move(m)));
+        } else {
+            self.send_message(Message::Text(protocol::encode_move_json(m)));
+        }
+    }
+
+    // Asks the other player to go back to the position at ply.
+    pub fn request_takeback(&self, ply: u16) {
+        self.send(json!({"takeback": "request", "ply": ply}));
+    }
+
+    // Accepts the other player's request to go back to ply, or declines it if ply is None.
+    pub fn answer_takeback(&self, ply: Option<u16>) {
+        match ply {
+            Some(ply) => self.send(json!({"takeback": "accept", "ply": ply})),
+            None => self.send(json!({"takeback": "decline"})),
+        }
+    }
+
+    // Offers the other player a draw.
+    pub fn offer_draw(&self) {
+        self.send(json!({"draw": "offer"}));
+    }
+
+    // Asks the server to abort the game, which it does if both players haven't moved yet.
+    pub fn abort(&self) {
+        self.send(json!({"abort": true}));
+    }
+
+    // Tells the other player about a move on the board both analyze on after the game.
+    pub fn send_analysis(&self, fen: &str, uci: Option<&str>) {
+        self.send_message(Message::Text(protocol::encode_analysis(fen, uci)));
+    }
+
+    // Tells the server how the game ended, so it can record it.
+    pub fn report_result(&self, result: &str, reason: &str) {
+        self.send(json!({"game_over": {"result": result, "reason": reason}}));
+    }
+
+    // Logs in, so the server sends our preferences and keeps the ones we save.
+    pub fn login(&mut self, user: &str) {
+        self.send_message(Message::Text(protocol::encode_login(user)));
+        self.user = Some(user.to_string());
+    }
+
+    // Saves our settings, as in Settings::to_json, for the next time we log in, if we have.
+    pub fn save_prefs(&self, settings: &str) {
+        if self.user.is_none() {
+            return;
+        }
+        match protocol::encode_prefs(settings) {
+            Ok(msg) => self.send_message(Message::Text(msg)),
+            Err(e) => warn!("Not saving preferences: {}", e),
+        }
+    }
+
+    fn send(&self, v: Value) {
+        self.send_message(Message::Text(v.to_string()));
+    }
+
+    fn send_message(&self, msg: Message) {
+        if self.tx.send(msg).is_err() {
+            warn!("Can't send, not connected");
+        }
+    }
+
+    fn dispatch(&mut self, msg: &Message) -> Option<NetEvent> {
+        debug!("Received message: {}", msg);
+        let decoded = match msg {
+            Message::Text(s) => protocol::decode(s),
+            Message::Binary(b) => protocol::decode_binary(b),
+            _ => return None,
+        };
+        let msg = match decoded {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Ignoring message: {}", e);
+                return None;
+            }
+        };
+        match msg {
+            ProtocolMessage::GameId(game_id) => {
+                self.game_id = Some(game_id.clone());
+                Some(NetEvent::Created(game_id))
+            }
+            ProtocolMessage::Joined => {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.subsec_nanos());
+                let color = (nanos % 2) as usize;
+                self.send(json!({ "color": color_name(1 - color) }));
+                self.color = Some(color);
+                Some(NetEvent::OpponentJoined(color))
+            }
+            // Presence follows, which is what's shown.
+            ProtocolMessage::Left => None,
+            ProtocolMessage::Presence(p) => Some(NetEvent::Presence(p)),
+            ProtocolMessage::ServerShutdown => Some(NetEvent::ServerShutdown),
+            ProtocolMessage::Denied => Some(NetEvent::Denied),
+            ProtocolMessage::Spectating => Some(NetEvent::Spectating),
+            ProtocolMessage::Rated => Some(NetEvent::Rated),
+            ProtocolMessage::Color(color) => {
+                self.color = Some(color);
+                Some(NetEvent::OpponentJoined(color))
+            }
+            ProtocolMessage::Move(m) => Some(NetEvent::OpponentMove(m)),
+            ProtocolMessage::Ruleset(r) => Some(NetEvent::Ruleset(r)),
+            ProtocolMessage::RulesUpdate(r) => Some(NetEvent::RulesUpdate(r)),
+            ProtocolMessage::TakebackRequest(ply) => Some(NetEvent::TakebackRequest(ply)),
+            ProtocolMessage::TakebackAccept(ply) => Some(NetEvent::TakebackAccepted(ply)),
+            ProtocolMessage::TakebackDecline => Some(NetEvent::TakebackDeclined),
+            ProtocolMessage::DrawOffer => Some(NetEvent::DrawOffered),
+            ProtocolMessage::Abandoned(a) => Some(NetEvent::Abandoned(a)),
+            ProtocolMessage::Abortable(a) => Some(NetEvent::Abortable(a)),
+            ProtocolMessage::Aborted => Some(NetEvent::Aborted),
+            ProtocolMessage::GameOver { result, reason } => {
+                Some(NetEvent::GameOver { result, reason })
+            }
+            // We can't do WebRTC, so a browser opponent keeps sending moves through the server.
+            ProtocolMessage::Analysis { fen, uci } => Some(NetEvent::Analysis { fen, uci }),
+            ProtocolMessage::Signal => None,
+            ProtocolMessage::UserPrefs(p) => Some(NetEvent::UserPrefs(p)),
+        }
+    }
+}
+
+// The user ID we log in with: CHESS_USER, or else the one in chess-ui/user.txt in the user's config
+// directory, which is made up the first time. Like a password, anyone who has it gets our
+// preferences.
+pub fn user_id() -> Option<String> {
+    if let Ok(user) = std::env::var("CHESS_USER") {
+        return Some(user);
+    }
+    let path = config_path("CHESS_USER_FILE", "user.txt")?;
+    match std::fs::read_to_string(&path) {
+        Ok(user) => return Some(user.trim().to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            warn!("Couldn't read {}: {}", path.display(), e);
+            return None;
+        }
+    }
+    let user = new_user_id();
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&path, &user));
+    match saved {
+        Ok(()) => Some(user),
+        Err(e) => {
+            warn!("Couldn't save {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// A random (version 4) UUID, as the server expects user IDs to be.
+fn new_user_id() -> String {
+    let mut bytes: [u8; 16] = std::array::from_fn(|_| rand::gen_range(0, 256) as u8);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn color_name(color: usize) -> &'static str {
+    if color == 0 {
+        "white"
+    } else {
+        "black"
+    }
+}
+
+// Forwards messages between the websocket and the game loop until either side goes away. A None
+// is sent to the game loop when the connection closes.
+async fn run(
+    url: String,
+    mut out_rx: tokio_mpsc::UnboundedReceiver<Message>,
+    in_tx: mpsc::Sender<Option<Message>>,
+) {
+    let ws = match connect_async(&url).await {
+        Ok((ws, _)) => ws,
+        Err(e) => {
+            error!("Couldn't connect to {}: {}", url, e);
+            let _ = in_tx.send(None);
+            return;
+        }
+    };
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    loop {
+        tokio::select! {
+            msg = ws_rx.next() => match msg {
+                Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => {
+                    if in_tx.send(Some(msg)).is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    error!("websocket error: {}", e);
+                    break;
+                }
+                None => break,
+            },
+            msg = out_rx.recv() => match msg {
+                Some(msg) => {
+                    if let Err(e) = ws_tx.send(msg).await {
+                        error!("websocket send error: {}", e);
+                        break;
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+    let _ = in_tx.send(None);
+}
+
+// Same as JS's encodeURIComponent.
+fn encode_uri_component(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(b as char),
+            b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
@@ -0,0 +1,295 @@
+// A small neural network evaluation in the style of NNUE (an efficiently updatable neural network),
+// built with --features nnue. The network's weights are read once from the file CHESS_NNUE names,
+// and without one, or in positions it can't describe, e.g. on other board sizes or with pieces
+// chess doesn't have, the engine falls back on its classical evaluation.
+//
+// Each of the 768 inputs is a kind of piece, of the side whose point of view it is or the other,
+// on one of the 64 squares, with black's mirrored so both sides see the board from their own back
+// rank. The first layer's sums, the accumulators, are kept for both points of view. A move only
+// changes a few inputs, so the search updates its parent's accumulators by what the move changed
+// instead of adding up every piece again. The output is a weighted sum of the side to move's
+// accumulators and the other side's, each clipped to 0..=QA.
+//
+// The file is little-endian: the magic bytes "CNUE", the number of accumulators H as a u16, then
+// the input weights as 768 rows of H i16s, H i16 biases, 2H i16 output weights (the side to move's
+// first) and an i32 output bias.
+
+use std::sync::OnceLock;
+
+use crate::prelude::*;
+
+const MAGIC: &[u8; 4] = b"CNUE";
+const INPUTS: usize = 768;
+// The accumulators' and output weights' quantization: the clipped accumulators are at most QA, and
+// an output weight of QB is 1.
+const QA: i32 = 255;
+const QB: i32 = 64;
+// Centipawns for an output of 1.
+const SCALE: i32 = 400;
+const PIECES: &[u8; 6] = b"pnbrqk";
+
+static NETWORK: OnceLock<Option<Network>> = OnceLock::new();
+
+#[derive(Debug, PartialEq)]
+pub struct Network {
+    hidden: usize,
+    // INPUTS rows of hidden weights.
+    input_weights: Vec<i16>,
+    input_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+// The first layer's sums for white's point of view and black's.
+#[derive(Clone, Debug, PartialEq)]
+struct Accumulators([Vec<i16>; 2]);
+
+// Keeps the accumulators in step with the search: push when a move is made, pop when it's taken
+// back. None stands for a position the network can't describe.
+pub struct Evaluator {
+    network: Option<&'static Network>,
+    stack: Vec<Option<Accumulators>>,
+}
+
+// The network in CHESS_NNUE, loaded the first time it's needed. None if the variable isn't set or
+// the file can't be used, which is logged once.
+pub fn network() -> Option<&'static Network> {
+    NETWORK
+        .get_or_init(|| {
+            let path = std::env::var("CHESS_NNUE").ok()?;
+            let network = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| Network::parse(&bytes));
+            match network {
+                Ok(n) => Some(n),
+                Err(e) => {
+                    crate::warn!("Not using {}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+impl Network {
+    pub fn parse(bytes: &[u8]) -> Result<Network, String> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or("Not a network file")?;
+        let (hidden, rest) = rest
+            .split_first_chunk::<2>()
+            .ok_or("Truncated network file")?;
+        let hidden = u16::from_le_bytes(*hidden) as usize;
+        let expected = 2 * (INPUTS * hidden + 3 * hidden) + 4;
+        if hidden == 0 || rest.len() != expected {
+            return Err(format!(
+                "Expected {} bytes after the header for {} accumulators, got {}",
+                expected,
+                hidden,
+                rest.len()
+            ));
+        }
+        let mut i16s = rest
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]));
+        let mut take = |n: usize| i16s.by_ref().take(n).collect::<Vec<_>>();
+        let input_weights = take(INPUTS * hidden);
+        let input_bias = take(hidden);
+        let output_weights = take(2 * hidden);
+        let bias = &rest[rest.len() - 4..];
+        Ok(Network {
+            hidden,
+            input_weights,
+            input_bias,
+            output_weights,
+            output_bias: i32::from_le_bytes([bias[0], bias[1], bias[2], bias[3]]),
+        })
+    }
+
+    // The inverse of parse.
+    #[cfg(test)]
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((self.hidden as u16).to_le_bytes());
+        for w in [&self.input_weights, &self.input_bias, &self.output_weights] {
+            bytes.extend(w.iter().flat_map(|w| w.to_le_bytes()));
+        }
+        bytes.extend(self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    // Adds up the inputs for every piece on the board.
+    fn refresh(&self, pp: &PiecePlacements) -> Option<Accumulators> {
+        if (pp.width(), pp.height()) != (8, 8) {
+            return None;
+        }
+        let mut acc = Accumulators([self.input_bias.clone(), self.input_bias.clone()]);
+        for (r, c, n) in pp.squares() {
+            if n != 0 {
+                self.add(&mut acc, n, r, c, 1)?;
+            }
+        }
+        Some(acc)
+    }
+
+    // The accumulators after a move from before to after, from the ones before it.
+    fn update(
+        &self,
+        acc: &Accumulators,
+        before: &PiecePlacements,
+        after: &PiecePlacements,
+    ) -> Option<Accumulators> {
+        let mut acc = acc.clone();
+        let changed = before
+            .squares()
+            .zip(after.squares())
+            .filter(|((_, _, b), (_, _, a))| a != b);
+        for ((r, c, b), (_, _, a)) in changed {
+            if b != 0 {
+                self.add(&mut acc, b, r, c, -1)?;
+            }
+            if a != 0 {
+                self.add(&mut acc, a, r, c, 1)?;
+            }
+        }
+        Some(acc)
+    }
+
+    // Adds the piece named name on row, col to both points of view, sign times.
+    fn add(&self, acc: &mut Accumulators, name: u8, r: usize, c: usize, sign: i16) -> Option<()> {
+        let kind = PIECES
+            .iter()
+            .position(|&p| p == name.to_ascii_lowercase())?;
+        let white = name.is_ascii_uppercase();
+        for (view, values) in acc.0.iter_mut().enumerate() {
+            let own = white == (view == 0);
+            let rank = if view == 0 { r } else { 9 - r };
+            let input = (!own as usize * 6 + kind) * 64 + (rank - 1) * 8 + c - 1;
+            let weights = &self.input_weights[input * self.hidden..(input + 1) * self.hidden];
+            for (v, &w) in values.iter_mut().zip(weights) {
+                *v = v.wrapping_add(sign.wrapping_mul(w));
+            }
+        }
+        Some(())
+    }
+
+    // From the point of view of player, in centipawns.
+    fn output(&self, acc: &Accumulators, player: usize) -> i32 {
+        let (own, other) = self.output_weights.split_at(self.hidden);
+        let sum = |values: &[i16], weights: &[i16]| -> i32 {
+            values
+                .iter()
+                .zip(weights)
+                .map(|(&v, &w)| (v as i32).clamp(0, QA) * w as i32)
+                .sum()
+        };
+        let total = sum(&acc.0[player], own) + sum(&acc.0[1 - player], other) + self.output_bias;
+        total * SCALE / (QA * QB)
+    }
+}
+
+impl Evaluator {
+    pub fn new(network: Option<&'static Network>) -> Self {
+        Self {
+            network,
+            stack: Vec::new(),
+        }
+    }
+
+    // Call before searching the position after a move from before.
+    pub fn push(&mut self, before: &PiecePlacements, after: &PiecePlacements) {
+        let Some(network) = self.network else {
+            return;
+        };
+        if self.stack.is_empty() {
+            self.stack.push(network.refresh(before));
+        }
+        let next = match self.stack.last().unwrap() {
+            Some(acc) => network.update(acc, before, after),
+            None => network.refresh(after),
+        };
+        self.stack.push(next);
+    }
+
+    // Call when done with the position push was called for.
+    pub fn pop(&mut self) {
+        if self.network.is_some() {
+            self.stack.pop();
+        }
+    }
+
+    // The network's evaluation of the position after the moves pushed, from the point of view of
+    // player. None without a network, or if it can't describe the position.
+    pub fn evaluate(&self, pp: &PiecePlacements, player: usize) -> Option<i32> {
+        let network = self.network?;
+        match self.stack.last() {
+            Some(acc) => Some(network.output(acc.as_ref()?, player)),
+            // Nothing pushed yet, so pp is the root.
+            None => Some(network.output(&network.refresh(pp)?, player)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only counts pawns: each accumulator is how many pawns one side has, and each pawn more than
+    // the other side is worth 100.
+    fn pawn_network() -> Network {
+        let hidden = 2;
+        let mut input_weights = vec![0; INPUTS * hidden];
+        for own in [0, 1] {
+            // Pawns are the first kind of piece, so they're on the first 64 inputs of each side.
+            for square in 0..64 {
+                input_weights[(own * 6 * 64 + square) * hidden + own] = 1;
+            }
+        }
+        let w = (100 * QA * QB / SCALE / 2) as i16;
+        Network {
+            hidden,
+            input_weights,
+            input_bias: vec![0; hidden],
+            output_weights: vec![w, -w, -w, w],
+            output_bias: 0,
+        }
+    }
+
+    #[test]
+    fn test_nnue() {
+        let network = pawn_network();
+        assert_eq!(Network::parse(&network.to_bytes()), Ok(pawn_network()));
+        assert!(Network::parse(b"CNUE\x02\x00").is_err());
+        let network: &'static Network = Box::leak(Box::new(network));
+
+        let rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = rules.initial_game_data();
+        let mut evaluator = Evaluator::new(Some(network));
+        assert_eq!(evaluator.evaluate(&pp, 0), Some(0));
+        // 1. e4 d5 2. exd5
+        let mut positions = vec![pp];
+        let (mut pp, mut gd) = (pp, gd);
+        for s in ["e2e4", "d7d5", "e4d5"] {
+            let (p, m) = chess_ui::notation::parse_long_algebraic(&rules, &pp, gd, s).unwrap();
+            Rules::make_move(p, m, &mut pp);
+            gd = GameData {
+                ply: m.game_data.ply + 1,
+                ..m.game_data
+            };
+            evaluator.push(positions.last().unwrap(), &pp);
+            positions.push(pp);
+        }
+        // Updated move by move, the same as adding up every piece again.
+        assert_eq!(evaluator.stack.last().unwrap(), &network.refresh(&pp));
+        // White is a pawn up.
+        assert_eq!(evaluator.evaluate(&pp, 0), Some(100));
+        assert_eq!(evaluator.evaluate(&pp, 1), Some(-100));
+        evaluator.pop();
+        assert_eq!(evaluator.evaluate(&positions[2], 0), Some(0));
+
+        // Pieces chess doesn't have fall back on the classical evaluation.
+        let mut odd = pp;
+        odd.set(4, 4, b'X');
+        assert_eq!(Evaluator::new(Some(network)).evaluate(&odd, 0), None);
+        assert_eq!(Evaluator::new(None).evaluate(&pp, 0), None);
+    }
+}
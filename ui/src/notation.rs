@@ -0,0 +1,782 @@
+// Writing moves and squares as text, e.g. for showing engine lines or saving games as PGN.
+
+use crate::prelude::*;
+
+// E.g. "e4" for row 4, column 5.
+pub fn square_name(row: u8, col: u8) -> String {
+    format!("{}{}", (b'a' + col - 1) as char, row)
+}
+
+// The move's source and destination squares, e.g. "e2e4", followed by the piece promoted to, if
+// any, e.g. "e7e8q". This is what UCI engines use, and like them a pass is "0000".
+pub fn long_algebraic(p: Piece, m: Move) -> String {
+    if m.typ == MoveType::Pass {
+        return "0000".to_string();
+    }
+    let mut s = square_name(p.row, p.col) + &square_name(m.dst.row, m.dst.col);
+    if m.dst.name != p.name {
+        s.push((m.dst.name as char).to_ascii_lowercase());
+    }
+    s
+}
+
+// The legal move written as s by long_algebraic, if there is one.
+pub fn parse_long_algebraic(
+    rules: &Rules,
+    pp: &PiecePlacements,
+    gd: GameData,
+    s: &str,
+) -> Option<(Piece, Move)> {
+    rules
+        .legal_moves(gd.player_to_move(), pp, gd)
+        .into_iter()
+        .find(|&(p, m)| long_algebraic(p, m) == s)
+}
+
+// Forsyth-Edwards Notation for the position, e.g. for sharing it with other chess programs. The
+// rules don't track the fifty move rule, so the halfmove clock is always 0.
+pub fn fen(pp: &PiecePlacements, gd: GameData) -> String {
+    let mut ranks = Vec::new();
+    for r in (1..=pp.height()).rev() {
+        let mut rank = String::new();
+        let mut empty = 0;
+        for name in (1..=pp.width()).map(|c| pp.get(r, c)) {
+            if name == 0 {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+                empty = 0;
+            }
+            rank.push(name as char);
+        }
+        if empty > 0 {
+            rank.push_str(&empty.to_string());
+        }
+        ranks.push(rank);
+    }
+    let turn = if gd.player_to_move() == 0 { "w" } else { "b" };
+    let mut castling: String = [
+        (GD_NO_WHITE_KS_CASTLE, 'K'),
+        (GD_NO_WHITE_QS_CASTLE, 'Q'),
+        (GD_NO_BLACK_KS_CASTLE, 'k'),
+        (GD_NO_BLACK_QS_CASTLE, 'q'),
+    ]
+    .iter()
+    .filter(|&&(flag, _)| gd.mask & flag == 0)
+    .map(|&(_, c)| c)
+    .collect();
+    if castling.is_empty() {
+        castling.push('-');
+    }
+    // The square behind the pawn that just moved two squares.
+    let ep = match (gd.mask & GD_EN_PASSANT) >> GD_EN_PASSANT_SHIFT {
+        0 => "-".to_string(),
+        col if turn == "w" => square_name(6, col as u8),
+        col => square_name(3, col as u8),
+    };
+    let fullmove = gd.ply.div_ceil(2);
+    format!(
+        "{} {} {} {} 0 {}",
+        ranks.join("/"),
+        turn,
+        castling,
+        ep,
+        fullmove
+    )
+}
+
+// The position written as s by fen. The halfmove clock is ignored. The last three fields may be
+// left out.
+pub fn parse_fen(s: &str) -> Result<(PiecePlacements, GameData), String> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() < 3 {
+        return Err(format!("Expected at least 3 fields in FEN: {}", s));
+    }
+    let mut pp = PiecePlacements::default();
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+    // TODO: get board size from rules
+    if ranks.len() != 8 {
+        return Err(format!("Expected 8 ranks in FEN: {}", fields[0]));
+    }
+    for (i, rank) in ranks.iter().enumerate() {
+        let r = 8 - i;
+        let mut c = 1;
+        for ch in rank.chars() {
+            if let Some(n) = ch.to_digit(10) {
+                c += n as usize;
+            } else if "kqrbnpKQRBNP*".contains(ch) && c <= 8 {
+                pp.set(r, c, ch as u8);
+                c += 1;
+            } else {
+                return Err(format!("Unexpected {} in FEN rank: {}", ch, rank));
+            }
+        }
+        if c != 9 {
+            return Err(format!("Expected 8 squares in FEN rank: {}", rank));
+        }
+    }
+    let black = match fields[1] {
+        "w" => false,
+        "b" => true,
+        t => return Err(format!("Expected w or b to move in FEN, got {}", t)),
+    };
+    let mut mask = GD_NO_WHITE_KS_CASTLE
+        | GD_NO_WHITE_QS_CASTLE
+        | GD_NO_BLACK_KS_CASTLE
+        | GD_NO_BLACK_QS_CASTLE;
+    for ch in fields[2].chars() {
+        mask &= !match ch {
+            'K' => GD_NO_WHITE_KS_CASTLE,
+            'Q' => GD_NO_WHITE_QS_CASTLE,
+            'k' => GD_NO_BLACK_KS_CASTLE,
+            'q' => GD_NO_BLACK_QS_CASTLE,
+            '-' => 0,
+            _ => return Err(format!("Unexpected {} in FEN castling rights", ch)),
+        };
+    }
+    match fields.get(3).map(|f| f.as_bytes()) {
+        None | Some(b"-") => {}
+        Some(&[col @ b'a'..=b'h', row]) if row == if black { b'3' } else { b'6' } => {
+            mask |= ((col - b'a' + 1) as u16) << GD_EN_PASSANT_SHIFT;
+        }
+        Some(_) => return Err(format!("Invalid FEN en passant square: {}", fields[3])),
+    }
+    let fullmove: u16 = match fields.get(5) {
+        Some(f) => f
+            .parse()
+            .ok()
+            // The ply has to fit in a u16.
+            .filter(|n| (1..=u16::MAX / 2).contains(n))
+            .ok_or_else(|| format!("Invalid FEN move number: {}", f))?,
+        None => 1,
+    };
+    let ply = (fullmove - 1) * 2 + if black { 2 } else { 1 };
+    Ok((pp, GameData { ply, mask }))
+}
+
+// Checks a position from parse_fen could come up in a game, e.g. before loading one the player
+// pasted: each side has one king, no pawns are on the first or last rank, and the side that just
+// moved isn't left in check.
+pub fn check_position(pp: &PiecePlacements, gd: GameData) -> Result<(), String> {
+    let mut kings = [0, 0];
+    // TODO: get board size from rules
+    for r in 1..=8 {
+        for c in 1..=8 {
+            match pp.get(r, c) {
+                b'K' => kings[0] += 1,
+                b'k' => kings[1] += 1,
+                b'P' | b'p' if r == 1 || r == 8 => {
+                    return Err(format!("Pawn on {}", square_name(r as u8, c as u8)));
+                }
+                _ => {}
+            }
+        }
+    }
+    if kings != [1, 1] {
+        return Err(format!(
+            "Expected one king each, got {} white and {} black",
+            kings[0], kings[1]
+        ));
+    }
+    let white_to_move = gd.player_to_move() == 0;
+    if Rules::in_check(!white_to_move, pp, gd) {
+        return Err("The side that just moved is in check".to_string());
+    }
+    Ok(())
+}
+
+// Standard algebraic notation, e.g. "Nbd7", "exd5", "O-O" or "e8=Q#", as used in PGN. pp and gd are
+// the position before the move.
+pub fn san(rules: &Rules, pp: &PiecePlacements, gd: GameData, p: Piece, m: Move) -> String {
+    let letter = (p.name as char).to_ascii_uppercase();
+    let dst = (m.dst.row, m.dst.col);
+    let mut s = String::new();
+    // There's only one duck, so where it goes is enough, as in Duck Chess.
+    // PGN has no passes, so it's written as most programs write null moves.
+    if m.typ == MoveType::Pass {
+        s.push_str("--");
+    } else if p.name == DUCK {
+        s.push('@');
+        s.push_str(&square_name(dst.0, dst.1));
+    } else if letter == 'K' && matches!(m.typ, MoveType::Secondary { .. }) {
+        s.push_str(if m.dst.col > p.col { "O-O" } else { "O-O-O" });
+    } else {
+        let capture = matches!(m.typ, MoveType::Capture { .. });
+        if letter == 'P' {
+            if capture {
+                s.push((b'a' + p.col - 1) as char);
+            }
+        } else {
+            s.push(letter);
+            // Say which piece moves if another of the same kind could move to the same square.
+            let others: Vec<Piece> = rules
+                .legal_moves(gd.player_to_move(), pp, gd)
+                .into_iter()
+                .filter(|&(o, om)| {
+                    o.name == p.name
+                        && (o.row, o.col) != (p.row, p.col)
+                        && (om.dst.row, om.dst.col) == dst
+                })
+                .map(|(o, _)| o)
+                .collect();
+            if !others.is_empty() {
+                let square = square_name(p.row, p.col);
+                if others.iter().all(|o| o.col != p.col) {
+                    s.push_str(&square[..1]);
+                } else if others.iter().all(|o| o.row != p.row) {
+                    s.push_str(&square[1..]);
+                } else {
+                    s.push_str(&square);
+                }
+            }
+        }
+        if capture {
+            s.push('x');
+        }
+        s.push_str(&square_name(dst.0, dst.1));
+        if m.dst.name != p.name {
+            s.push('=');
+            s.push((m.dst.name as char).to_ascii_uppercase());
+        }
+    }
+    let mut after = *pp;
+    Rules::make_move(p, m, &mut after);
+    let next = GameData {
+        ply: m.game_data.ply + 1,
+        ..m.game_data
+    };
+    let white = next.player_to_move() == 0;
+    if Rules::in_check(white, &after, next) {
+        let mated = rules
+            .legal_moves(next.player_to_move(), &after, next)
+            .is_empty();
+        s.push(if mated { '#' } else { '+' });
+    }
+    s
+}
+
+// The legal move written as s in SAN, if there is one. Check marks and annotations like "!?" are
+// optional, and castling may be written with zeros.
+pub fn parse_san(
+    rules: &Rules,
+    pp: &PiecePlacements,
+    gd: GameData,
+    s: &str,
+) -> Option<(Piece, Move)> {
+    let normalize = |s: &str| s.trim_end_matches(['+', '#', '!', '?']).replace('0', "O");
+    let s = normalize(s);
+    rules
+        .legal_moves(gd.player_to_move(), pp, gd)
+        .into_iter()
+        .find(|&(p, m)| normalize(&san(rules, pp, gd, p, m)) == s)
+}
+
+// A game read by parse_pgn.
+#[derive(Debug, Default, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    // In SAN, as written, without annotations like "!?".
+    pub moves: Vec<String>,
+    // What's annotated on each move: notes[i] is for moves[i].
+    pub notes: Vec<Notes>,
+    pub result: String,
+}
+
+// What's annotated on a move in PGN.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Notes {
+    // A comment before the move, which only the first move of a game or variation can have.
+    pub before: Option<String>,
+    // Numeric annotation glyphs, e.g. 1 for a good move, written "$1" or "!".
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    // Moves that could have been played instead, each a line of moves in SAN from the position
+    // before this one.
+    pub variations: Vec<Vec<(String, Notes)>>,
+}
+
+impl Notes {
+    pub fn is_empty(&self) -> bool {
+        *self == Notes::default()
+    }
+}
+
+// The annotations PGN also lets moves have as suffixes, and the NAGs they stand for.
+const GLYPHS: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6),
+];
+
+// E.g. "!?" for NAG 5, if it has one.
+pub fn glyph(nag: u8) -> Option<&'static str> {
+    GLYPHS.iter().find(|&&(_, n)| n == nag).map(|&(g, _)| g)
+}
+
+// The games in a PGN file, with their comments, annotations and variations.
+pub fn parse_pgn(s: &str) -> Result<Vec<PgnGame>, String> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    // The line being read, after the lines it's a variation in. The first is the game's.
+    let mut lines: Vec<Vec<(String, Notes)>> = vec![Vec::new()];
+    // A comment before the next move.
+    let mut before: Option<String> = None;
+    let mut chars = s.chars().peekable();
+    let mut at_line_start = true;
+    while let Some(ch) = chars.next() {
+        let line_start = at_line_start;
+        at_line_start = ch == '\n';
+        match ch {
+            // The rest of the line is to be ignored.
+            '%' if line_start => {
+                chars.by_ref().find(|&c| c == '\n');
+                at_line_start = true;
+            }
+            // The rest of the line is a comment.
+            ';' => {
+                let text: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                add_comment(lines.last_mut().unwrap(), &mut before, &text);
+                at_line_start = true;
+            }
+            '{' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next().ok_or("Unterminated comment in PGN")? {
+                        '}' => break,
+                        c => text.push(c),
+                    }
+                }
+                add_comment(lines.last_mut().unwrap(), &mut before, &text);
+            }
+            '(' => {
+                if lines.last().unwrap().is_empty() {
+                    return Err("Variation before any move in PGN".to_string());
+                }
+                lines.push(Vec::new());
+                before = None;
+            }
+            ')' => {
+                if lines.len() == 1 {
+                    return Err("Unmatched ) in PGN".to_string());
+                }
+                let variation = lines.pop().unwrap();
+                let (_, notes) = lines.last_mut().unwrap().last_mut().unwrap();
+                if !variation.is_empty() {
+                    notes.variations.push(variation);
+                }
+                before = None;
+            }
+            '[' => {
+                let tag: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                let mut value = String::new();
+                loop {
+                    match chars.next().ok_or("Unterminated tag in PGN")? {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+                chars.by_ref().find(|&c| c == ']');
+                game.tags.push((tag.trim().to_string(), value));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}();[".contains(c) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                let line = lines.last_mut().unwrap();
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        if lines.len() > 1 {
+                            return Err("Unterminated variation in PGN".to_string());
+                        }
+                        game.result = token;
+                        (game.moves, game.notes) = lines[0].drain(..).unzip();
+                        games.push(std::mem::take(&mut game));
+                        before = None;
+                    }
+                    t if t.starts_with('$') => {
+                        if let (Some((_, notes)), Ok(nag)) = (line.last_mut(), t[1..].parse()) {
+                            notes.nags.push(nag);
+                        }
+                    }
+                    t => {
+                        // Move numbers, e.g. "12." or "12...", possibly with the move after them.
+                        let digits = t.find(|c: char| !c.is_ascii_digit()).unwrap_or(t.len());
+                        let rest = &t[digits..];
+                        let m = if digits > 0 && rest.starts_with('.') {
+                            rest.trim_start_matches('.')
+                        } else {
+                            t
+                        };
+                        // Annotations, e.g. "!?", on the move or after it.
+                        let san = m.trim_end_matches(['!', '?']);
+                        let nag = GLYPHS
+                            .iter()
+                            .find(|&&(g, _)| g == &m[san.len()..])
+                            .map(|&(_, n)| n);
+                        if !san.is_empty() {
+                            let notes = Notes {
+                                before: before.take(),
+                                ..Notes::default()
+                            };
+                            line.push((san.to_string(), notes));
+                        }
+                        if let (Some((_, notes)), Some(nag)) = (line.last_mut(), nag) {
+                            notes.nags.push(nag);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if lines.len() > 1 {
+        return Err("Unterminated variation in PGN".to_string());
+    }
+    if !lines[0].is_empty() || !game.tags.is_empty() {
+        return Err("PGN game has no result".to_string());
+    }
+    Ok(games)
+}
+
+// Comments go on the last move of the line, or the next move if it has none yet. They're kept on
+// one line, so they can be written back wrapped however's needed.
+fn add_comment(line: &mut [(String, Notes)], before: &mut Option<String>, text: &str) {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return;
+    }
+    let to = match line.last_mut() {
+        Some((_, notes)) => &mut notes.comment,
+        None => before,
+    };
+    match to {
+        Some(c) => {
+            c.push(' ');
+            c.push_str(&text);
+        }
+        None => *to = Some(text),
+    }
+}
+
+// A game in PGN. tags are written in the given order, so the caller should start with the seven
+// tag roster (Event, Site, Date, Round, White, Black, Result). moves are in SAN, starting with
+// white's first move.
+pub fn pgn(tags: &[(&str, String)], moves: &[String], result: &str) -> String {
+    annotated_pgn(tags, moves, &[], result)
+}
+
+// Like pgn, with what's annotated on the moves: notes[i] is for moves[i], and moves past the end
+// of notes have none.
+pub fn annotated_pgn(
+    tags: &[(&str, String)],
+    moves: &[String],
+    notes: &[Notes],
+    result: &str,
+) -> String {
+    let mut out = String::new();
+    for (name, value) in tags {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!("[{} \"{}\"]\n", name, value));
+    }
+    out.push('\n');
+    let none = Notes::default();
+    let line: Vec<_> = moves
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.as_str(), notes.get(i).unwrap_or(&none)))
+        .collect();
+    let mut tokens = movetext(&line, 0);
+    tokens.push(result.to_string());
+    // Lines are supposed to be at most 80 characters.
+    let mut line = String::new();
+    for t in tokens {
+        if !line.is_empty() && line.len() + 1 + t.len() > 80 {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&t);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
+}
+
+// The tokens of a line of moves in PGN, starting ply moves into the game.
+fn movetext(line: &[(&str, &Notes)], ply: usize) -> Vec<String> {
+    let mut tokens = Vec::new();
+    // A word at a time, so long comments can be wrapped. They can't have "}" in them.
+    let comment = |tokens: &mut Vec<String>, text: &str| {
+        let mut words: Vec<String> = text
+            .replace('}', "")
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        if words.is_empty() {
+            words.push(String::new());
+        }
+        words[0].insert(0, '{');
+        words.last_mut().unwrap().push('}');
+        tokens.extend(words);
+    };
+    // Black's moves are numbered too, where they don't come right after white's.
+    let mut numbered = true;
+    for (i, &(m, notes)) in line.iter().enumerate() {
+        let ply = ply + i;
+        if let Some(text) = &notes.before {
+            comment(&mut tokens, text);
+            numbered = true;
+        }
+        match (ply % 2, numbered) {
+            (0, _) => tokens.push(format!("{}.", ply / 2 + 1)),
+            (_, true) => tokens.push(format!("{}...", ply / 2 + 1)),
+            _ => {}
+        }
+        tokens.push(m.to_string());
+        numbered = false;
+        tokens.extend(notes.nags.iter().map(|nag| format!("${}", nag)));
+        if let Some(text) = &notes.comment {
+            comment(&mut tokens, text);
+            numbered = true;
+        }
+        for v in notes.variations.iter().filter(|v| !v.is_empty()) {
+            let v: Vec<_> = v.iter().map(|(m, n)| (m.as_str(), n)).collect();
+            let mut inner = movetext(&v, ply);
+            inner[0].insert(0, '(');
+            inner.last_mut().unwrap().push(')');
+            tokens.extend(inner);
+            numbered = true;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_algebraic() {
+        let p = Piece {
+            row: 2,
+            col: 5,
+            name: b'P',
+        };
+        let m = Move {
+            dst: Piece {
+                row: 4,
+                col: 5,
+                name: b'P',
+            },
+            typ: MoveType::Normal,
+            game_data: GameData { ply: 1, mask: 0 },
+        };
+        assert_eq!(long_algebraic(p, m), "e2e4");
+        assert_eq!(square_name(8, 1), "a8");
+    }
+
+    #[test]
+    fn test_san() {
+        let pp = string_board_to_placements(
+            "
+            r...k..r
+            .P......
+            ........
+            ........
+            ........
+            ........
+            ........
+            R...K..R
+        ",
+        );
+        let rules = Rules::defaults();
+        let gd = GameData { ply: 1, mask: 0 };
+        let san_of = |s: &str| {
+            let (p, m) = parse_long_algebraic(&rules, &pp, gd, s).unwrap();
+            san(&rules, &pp, gd, p, m)
+        };
+        assert_eq!(san_of("e1g1"), "O-O");
+        assert_eq!(san_of("e1c1"), "O-O-O");
+        assert_eq!(san_of("a1a8"), "Rxa8+");
+        assert_eq!(san_of("h1f1"), "Rf1");
+        assert_eq!(san_of("b7a8q"), "bxa8=Q+");
+        assert!(parse_long_algebraic(&rules, &pp, gd, "b7b8").is_none());
+
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            R.......
+            ........
+            ........
+            ....K...
+            R......R
+        ",
+        );
+        let san_of = |s: &str| {
+            let (p, m) = parse_long_algebraic(&rules, &pp, gd, s).unwrap();
+            san(&rules, &pp, gd, p, m)
+        };
+        assert_eq!(san_of("h1d1"), "Rhd1");
+        assert_eq!(san_of("a1a3"), "R1a3");
+        assert_eq!(san_of("a1d1"), "Rad1");
+    }
+
+    #[test]
+    fn test_fen() {
+        let rules = Rules::defaults();
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let gd = GameData { ply: 1, mask: 0 };
+        assert_eq!(fen(&rules.initial_placements(), gd), start);
+        assert_eq!(parse_fen(start), Ok((rules.initial_placements(), gd)));
+
+        let (pp, gd) = parse_fen("4k3/8/8/8/8/8/8/R3K2R b K - 3 12").unwrap();
+        assert_eq!(pp.get(1, 1), b'R');
+        assert_eq!(pp.get(8, 5), b'k');
+        assert_eq!(
+            gd,
+            GameData {
+                ply: 24,
+                mask: GD_NO_WHITE_QS_CASTLE | GD_NO_BLACK_KS_CASTLE | GD_NO_BLACK_QS_CASTLE,
+            }
+        );
+        assert_eq!(fen(&pp, gd), "4k3/8/8/8/8/8/8/R3K2R b K - 0 12");
+
+        assert!(parse_fen("8/8/8 w - - 0 1").is_err());
+        assert!(parse_fen("4k3/9/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K2X w - - 0 1").is_err());
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 w - e3 0 1").is_err());
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 40000").is_err());
+
+        let checked = |f| parse_fen(f).and_then(|(pp, gd)| check_position(&pp, gd));
+        assert_eq!(checked(start), Ok(()));
+        assert!(checked("4k3/8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert!(checked("4k3/8/8/8/8/8/8/3KK3 w - - 0 1").is_err());
+        assert!(checked("P3k3/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        // White to move, and black's king is attacked by the rook.
+        assert!(checked("4k3/8/8/8/8/8/8/4RK2 w - - 0 1").is_err());
+        assert_eq!(checked("4k3/8/8/8/8/8/8/4RK2 b - - 0 1"), Ok(()));
+
+        let ep = "4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 30";
+        let (pp, gd) = parse_fen(ep).unwrap();
+        assert_eq!(fen(&pp, gd), ep);
+        let (p, m) = parse_long_algebraic(&rules, &pp, gd, "d5e6").unwrap();
+        assert_eq!(san(&rules, &pp, gd, p, m), "dxe6");
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = GameData { ply: 1, mask: 0 };
+        let parse = |s| parse_san(&rules, &pp, gd, s).map(|(p, m)| long_algebraic(p, m));
+        assert_eq!(parse("Nf3").as_deref(), Some("g1f3"));
+        assert_eq!(parse("e4!?").as_deref(), Some("e2e4"));
+        assert_eq!(parse("e5"), None);
+        assert_eq!(parse("Nd2"), None);
+
+        let pp = string_board_to_placements(
+            "
+            ....k...
+            .P......
+            ........
+            ........
+            ........
+            ........
+            ........
+            R...K..R
+        ",
+        );
+        let parse = |s| parse_san(&rules, &pp, gd, s).map(|(p, m)| long_algebraic(p, m));
+        assert_eq!(parse("0-0").as_deref(), Some("e1g1"));
+        assert_eq!(parse("O-O-O").as_deref(), Some("e1c1"));
+        assert_eq!(parse("b8=N").as_deref(), Some("b7b8n"));
+        assert_eq!(parse("b8=Q+").as_deref(), Some("b7b8q"));
+    }
+
+    #[test]
+    fn test_parse_pgn() {
+        let s = "% Not part of the games
+[Event \"Test \\\"game\\\"\"]
+[Result \"1-0\"]
+
+1. e4 {best by test} e5 (1... c5 2. Nf3) 2.Nf3 $1 ; rest of line
+Nc6 1-0
+
+[Result \"*\"]
+1. 0-0 *
+";
+        let games = parse_pgn(s).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(
+            games[0].tags,
+            [
+                ("Event".to_string(), "Test \"game\"".to_string()),
+                ("Result".to_string(), "1-0".to_string())
+            ]
+        );
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6"]);
+        let notes = &games[0].notes;
+        assert_eq!(notes[0].comment.as_deref(), Some("best by test"));
+        assert_eq!(
+            notes[1].variations,
+            [vec![
+                ("c5".to_string(), Notes::default()),
+                ("Nf3".to_string(), Notes::default())
+            ]]
+        );
+        assert_eq!(notes[2].nags, [1]);
+        assert_eq!(notes[2].comment.as_deref(), Some("rest of line"));
+        assert!(notes[3].is_empty());
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].moves, ["0-0"]);
+        assert!(parse_pgn("1. e4 e5").is_err());
+        assert!(parse_pgn("1. e4 {e5 *").is_err());
+        assert!(parse_pgn("1. e4 (1. d4 *").is_err());
+        assert!(parse_pgn("1. e4 ) *").is_err());
+        assert!(parse_pgn("(1. e4) *").is_err());
+
+        // Nested variations, glyphs and comments before a variation's first move.
+        let games = parse_pgn("{Start} 1. e4!? e5 (1... c5?! ({Or} 1... e6) 2. Nf3) *").unwrap();
+        let notes = &games[0].notes;
+        assert_eq!(notes[0].before.as_deref(), Some("Start"));
+        assert_eq!(notes[0].nags, [5]);
+        let (c5, c5_notes) = &notes[1].variations[0][0];
+        assert_eq!((c5.as_str(), &c5_notes.nags[..]), ("c5", &[6][..]));
+        let e6 = &c5_notes.variations[0][0];
+        assert_eq!((e6.0.as_str(), e6.1.before.as_deref()), ("e6", Some("Or")));
+    }
+
+    #[test]
+    fn test_pgn() {
+        let moves: Vec<String> = ["e4", "e5", "Nf3"].iter().map(|m| m.to_string()).collect();
+        let tags = [("Event", "Test \"game\"".to_string())];
+        assert_eq!(
+            pgn(&tags, &moves, "*"),
+            "[Event \"Test \\\"game\\\"\"]\n\n1. e4 e5 2. Nf3 *\n"
+        );
+
+        let movetext = "{Start} 1. e4 $5 e5 {A comment} 2. Nf3 (2. d4 {Center} (2. c3) 2... exd4) \
+                        2... Nc6 *";
+        let game = parse_pgn(movetext).unwrap().remove(0);
+        let written = annotated_pgn(&[], &game.moves, &game.notes, &game.result);
+        assert_eq!(written.replace('\n', " ").trim(), movetext);
+        assert_eq!(glyph(5), Some("!?"));
+    }
+}
@@ -0,0 +1,93 @@
+// Playing through the game's history on its own, to replay it: once started, the board steps
+// forward a move at a time, a move a second at normal speed, until it reaches the last position.
+// Stepping by hand, by move or by full turn (a move of each player), is step_view's.
+
+// How many times faster than normal playback can go, slowest first.
+pub const SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const NORMAL: usize = 2;
+
+pub struct Playback {
+    pub playing: bool,
+    // Index in SPEEDS.
+    speed: usize,
+    // When the board last stepped, or playback started.
+    last_step: f64,
+}
+
+impl Playback {
+    pub fn new() -> Playback {
+        Playback {
+            playing: false,
+            speed: NORMAL,
+            last_step: 0.0,
+        }
+    }
+
+    pub fn play(&mut self, on: bool, now: f64) {
+        self.playing = on;
+        self.last_step = now;
+    }
+
+    pub fn speed(&self) -> f32 {
+        SPEEDS[self.speed]
+    }
+
+    // Picks the speed in SPEEDS closest to speed.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = (0..SPEEDS.len())
+            .min_by(|&a, &b| {
+                (SPEEDS[a] - speed)
+                    .abs()
+                    .total_cmp(&(SPEEDS[b] - speed).abs())
+            })
+            .unwrap();
+    }
+
+    // Goes to the next speed up (faster) or down.
+    pub fn change_speed(&mut self, faster: bool) {
+        self.speed = if faster {
+            (self.speed + 1).min(SPEEDS.len() - 1)
+        } else {
+            self.speed.saturating_sub(1)
+        };
+    }
+
+    // Whether it's time for the board to step forward, at time now.
+    pub fn tick(&mut self, now: f64) -> bool {
+        if !self.playing || now - self.last_step < 1.0 / self.speed() as f64 {
+            return false;
+        }
+        self.last_step = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback() {
+        let mut p = Playback::new();
+        assert!(!p.tick(10.0));
+        p.play(true, 10.0);
+        assert!(!p.tick(10.5));
+        assert!(p.tick(11.0));
+        assert!(!p.tick(11.5));
+
+        p.change_speed(true);
+        assert_eq!(p.speed(), 2.0);
+        assert!(p.tick(11.5));
+        for _ in 0..10 {
+            p.change_speed(true);
+        }
+        assert_eq!(p.speed(), 4.0);
+        p.set_speed(0.3);
+        assert_eq!(p.speed(), 0.25);
+        p.change_speed(false);
+        assert_eq!(p.speed(), 0.25);
+
+        p.play(false, 20.0);
+        assert!(!p.tick(100.0));
+    }
+}
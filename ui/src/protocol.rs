@@ -0,0 +1,658 @@
+// The messages players send each other through the multiplayer server, which passes them on as is
+// (see server/src/main.rs and assets/js/multiplayer.js). Messages are JSON, except that moves can
+// also be sent as small binary frames, which is the default since they're what's sent most. Both
+// are always accepted. Since they come from the other player, anything malformed is rejected here
+// rather than trusted by the game.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    // From the server to the player who created the game.
+    GameId(String),
+    // From the server when another player joins.
+    Joined,
+    // From the server when another player leaves.
+    Left,
+    // From the server whenever someone joins or leaves, including us.
+    Presence(Presence),
+    // From the server when the other player left and didn't come back within the grace period.
+    // The player who's still there adjudicates the game (see Adjudication::result).
+    Abandoned(Adjudication),
+    // From a player when the game ends, so the server can record it. The result is as in PGN, with
+    // "*" for an aborted game, and the reason is its Termination tag, e.g. "abandoned".
+    GameOver { result: String, reason: String },
+    // Aborting the game, which is only allowed before both players have moved (see
+    // server/src/main.rs). A player asks the server with {"abort": true}. If they can, everyone
+    // gets Aborted, and otherwise they get Abortable(false). The player who made the first move
+    // gets Abortable(true) once the other player has taken too long to make theirs.
+    Abortable(bool),
+    Aborted,
+    // From the server, instead of anything else, to someone joining a private game without its
+    // passcode when it doesn't allow spectators. The server then closes the connection.
+    Denied,
+    // From the server to a spectator as they join: they watch, and can't move.
+    Spectating,
+    // From the server to a player as they take a seat in a rated game, which is played without the
+    // ui's assists, e.g. its check for blunders.
+    Rated,
+    // From the server just before it shuts down and closes the connection. The game is saved, so
+    // it can be rejoined once the server is back.
+    ServerShutdown,
+    // From the creator, who picks colors: 0 for white, 1 for black.
+    Color(usize),
+    Move(MoveFrame),
+    // A rule set as exported by Rules::export_ruleset.
+    Ruleset(String),
+    // Movement rules toggled during the game.
+    RulesUpdate(HashMap<String, bool>),
+    // Taking back moves. Either player can ask to go back to the position at a ply (as in
+    // GameData), usually the one before their last move, and the other player accepts or
+    // declines. Both then go back to that ply, which stays right even if a move crossed the
+    // request.
+    TakebackRequest(u16),
+    TakebackAccept(u16),
+    TakebackDecline,
+    // A player offers a draw: {"draw": "offer"}. The other player accepts by ending the game with
+    // GameOver, as a draw by agreement, or declines by moving.
+    DrawOffer,
+    // Analyzing the game together once it's over: the position on the shared board, as FEN, and
+    // the move in long algebraic notation that led there from the one before, if it was a move.
+    // Either player can move pieces on it, for both sides.
+    Analysis { fen: String, uci: Option<String> },
+    // Browsers setting up a peer to peer connection for their moves (see assets/js/p2p.js). Only
+    // JS can do anything with these.
+    Signal,
+    // From the server, after a player logs in with their user ID: their preferences, the settings
+    // they saved from any device, as a JSON object (see settings.rs). A logged-in player sends the
+    // same message to save them. The server doesn't pass either on (see server/src/prefs.rs).
+    UserPrefs(String),
+}
+
+// Who's connected to the game. Anyone who joins once both players are there is a spectator.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Presence {
+    pub players: usize,
+    pub spectators: usize,
+}
+
+// What the server awards the player who's still there when their opponent abandons the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Adjudication {
+    Win,
+    Draw,
+    Abort,
+}
+
+impl Adjudication {
+    // The result of a game abandoned at ply (as in GameData) by the other player, as seen by
+    // player (0 for white, 1 for black): aborted before both players have moved, drawn if neither
+    // side can mate, and otherwise self.
+    pub fn result(self, ply: u16, insufficient_material: bool, player: usize) -> &'static str {
+        if ply < 3 {
+            return "*";
+        }
+        if insufficient_material {
+            return "1/2-1/2";
+        }
+        match (self, player) {
+            (Adjudication::Win, 0) => "1-0",
+            (Adjudication::Win, _) => "0-1",
+            (Adjudication::Draw, _) => "1/2-1/2",
+            (Adjudication::Abort, _) => "*",
+        }
+    }
+}
+
+// A move, in either encoding. Only the squares are needed to make it. The rest lets the other
+// player check they agree on what happened.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MoveFrame {
+    pub src_row: usize,
+    pub src_col: usize,
+    pub dst_row: usize,
+    pub dst_col: usize,
+    // The piece a pawn promoted to, as its FEN letter.
+    pub promotion: Option<u8>,
+    // MOVE_CAPTURE etc.
+    pub flags: u8,
+    // Milliseconds left on the mover's clock after the move, or 0 in untimed games.
+    pub clock_ms: u32,
+    // Rules::position_hash of the position after the move, or 0 if not known.
+    pub hash: u64,
+    // For a move in several legs, like a chain of jumps, the squares the piece stops on before
+    // the destination, as row << 4 | col, followed by zeros.
+    pub via: [u8; MOVE_MAX_VIA],
+}
+
+impl MoveFrame {
+    // The squares the piece stops on, ending with the destination, as from rules::move_legs.
+    pub fn legs(&self) -> Vec<(usize, usize)> {
+        let mut legs: Vec<(usize, usize)> = self
+            .via
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| ((b >> 4) as usize, (b & 0xf) as usize))
+            .collect();
+        legs.push((self.dst_row, self.dst_col));
+        legs
+    }
+
+    // Sets the destination and the squares before it from legs, which has at most
+    // MOVE_MAX_VIA + 1 squares.
+    pub fn set_legs(&mut self, legs: &[(usize, usize)]) {
+        let (&(dst_row, dst_col), via) = legs.split_last().unwrap();
+        (self.dst_row, self.dst_col) = (dst_row, dst_col);
+        self.via = [0; MOVE_MAX_VIA];
+        for (b, &(r, c)) in self.via.iter_mut().zip(via) {
+            *b = square_byte(r, c);
+        }
+    }
+}
+
+pub const MOVE_CAPTURE: u8 = 0x01;
+pub const MOVE_EN_PASSANT: u8 = 0x02;
+pub const MOVE_CASTLE: u8 = 0x04;
+// A pass: nothing moves, and both squares are the passing piece's (see Rules::pass_move).
+pub const MOVE_PASS: u8 = 0x08;
+// Which phase of the turn the move was made in (see Phase), so a duck placed after the move
+// isn't taken for the move itself.
+pub const MOVE_PHASE_SHIFT: u8 = 4;
+pub const MOVE_PHASE: u8 = 0x30;
+
+// The most squares a multi-leg move can stop on before its destination, enough for a draughts
+// king taking every piece.
+pub const MOVE_MAX_VIA: usize = 12;
+
+// A binary move frame is MOVE_FRAME_LEN bytes:
+//   0      MOVE_FRAME_TAG
+//   1      source square, row << 4 | col
+//   2      destination square, row << 4 | col
+//   3      promotion, or 0
+//   4      flags
+//   5..9   clock_ms, little-endian
+//   9..17  hash, little-endian
+//   17..   for a multi-leg move, one byte per square in via, row << 4 | col
+// The server checks the tag and length before relaying it, so keep it in sync if this changes.
+pub const MOVE_FRAME_TAG: u8 = b'M';
+pub const MOVE_FRAME_LEN: usize = 17;
+
+fn square_byte(r: usize, c: usize) -> u8 {
+    ((r as u8) << 4) | c as u8
+}
+
+pub fn encode_move(m: &MoveFrame) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(MOVE_FRAME_LEN);
+    frame.push(MOVE_FRAME_TAG);
+    frame.push(square_byte(m.src_row, m.src_col));
+    frame.push(square_byte(m.dst_row, m.dst_col));
+    frame.push(m.promotion.unwrap_or(0));
+    frame.push(m.flags);
+    frame.extend_from_slice(&m.clock_ms.to_le_bytes());
+    frame.extend_from_slice(&m.hash.to_le_bytes());
+    frame.extend(m.via.iter().take_while(|&&b| b != 0));
+    frame
+}
+
+// Logging in with the player's user ID, so the server sends their preferences.
+pub fn encode_login(user: &str) -> String {
+    json!({ "login": user }).to_string()
+}
+
+// Saving the player's preferences, a JSON object of their settings, once they've logged in.
+pub fn encode_prefs(settings: &str) -> Result<String, String> {
+    let prefs: Value = serde_json::from_str(settings).map_err(|e| e.to_string())?;
+    Ok(json!({ "prefs": prefs }).to_string())
+}
+
+// The same move as a JSON message, for debugging. Fields that are unset are left out.
+// A move on the board both players analyze on after the game, or the position it starts from if
+// uci is None.
+pub fn encode_analysis(fen: &str, uci: Option<&str>) -> String {
+    let mut analysis = json!({ "fen": fen });
+    if let Some(uci) = uci {
+        analysis["uci"] = json!(uci);
+    }
+    json!({ "analysis": analysis }).to_string()
+}
+
+pub fn encode_move_json(m: &MoveFrame) -> String {
+    let mut data = json!({
+        "src_row": m.src_row,
+        "src_col": m.src_col,
+        "dst_row": m.dst_row,
+        "dst_col": m.dst_col,
+    });
+    if let Some(p) = m.promotion {
+        data["promotion"] = json!((p as char).to_string());
+    }
+    if m.flags != 0 {
+        data["flags"] = json!(m.flags);
+    }
+    if m.clock_ms != 0 {
+        data["clock_ms"] = json!(m.clock_ms);
+    }
+    // As hex, since JS numbers can't hold 64 bits.
+    if m.hash != 0 {
+        data["hash"] = json!(format!("{:016x}", m.hash));
+    }
+    let legs = m.legs();
+    if legs.len() > 1 {
+        data["via"] = json!(legs[..legs.len() - 1]);
+    }
+    data.to_string()
+}
+
+// TODO: get board size from rules
+fn on_board(n: usize) -> bool {
+    (1..=8).contains(&n)
+}
+
+fn valid_promotion(p: u8) -> Result<u8, String> {
+    if p.is_ascii_alphabetic() {
+        Ok(p)
+    } else {
+        Err(format!("invalid promotion: {}", p))
+    }
+}
+
+// Decodes a binary websocket message. Only moves are sent this way.
+pub fn decode_binary(frame: &[u8]) -> Result<Message, String> {
+    let via_len = frame.len().wrapping_sub(MOVE_FRAME_LEN);
+    if via_len > MOVE_MAX_VIA || frame[0] != MOVE_FRAME_TAG {
+        return Err(format!("invalid frame: {:?}", frame));
+    }
+    let square = |b: u8| {
+        let (r, c) = ((b >> 4) as usize, (b & 0xf) as usize);
+        if on_board(r) && on_board(c) {
+            Ok((r, c))
+        } else {
+            Err(format!("invalid square: {:#04x}", b))
+        }
+    };
+    let (src_row, src_col) = square(frame[1])?;
+    let (dst_row, dst_col) = square(frame[2])?;
+    let mut via = [0; MOVE_MAX_VIA];
+    for (i, &b) in frame[MOVE_FRAME_LEN..].iter().enumerate() {
+        square(b)?;
+        via[i] = b;
+    }
+    let promotion = match frame[3] {
+        0 => None,
+        p => Some(valid_promotion(p)?),
+    };
+    Ok(Message::Move(MoveFrame {
+        src_row,
+        src_col,
+        dst_row,
+        dst_col,
+        promotion,
+        flags: frame[4],
+        clock_ms: u32::from_le_bytes(frame[5..9].try_into().unwrap()),
+        hash: u64::from_le_bytes(frame[9..17].try_into().unwrap()),
+        via,
+    }))
+}
+
+pub fn decode(msg: &str) -> Result<Message, String> {
+    let data: Value = serde_json::from_str(msg).map_err(|e| format!("invalid message: {}", e))?;
+    if let Some(game_id) = data["game_id"].as_str() {
+        Ok(Message::GameId(game_id.to_string()))
+    } else if !data["joined"].is_null() {
+        Ok(Message::Joined)
+    } else if !data["disconnected"].is_null() {
+        Ok(Message::Left)
+    } else if data["presence"].is_object() {
+        let count = |k: &str| {
+            data["presence"][k]
+                .as_u64()
+                .map(|n| n as usize)
+                .ok_or_else(|| format!("invalid presence: {}", data["presence"]))
+        };
+        Ok(Message::Presence(Presence {
+            players: count("players")?,
+            spectators: count("spectators")?,
+        }))
+    } else if let Some(a) = data["abandoned"].as_str() {
+        match a {
+            "win" => Ok(Message::Abandoned(Adjudication::Win)),
+            "draw" => Ok(Message::Abandoned(Adjudication::Draw)),
+            "abort" => Ok(Message::Abandoned(Adjudication::Abort)),
+            _ => Err(format!("invalid adjudication: {}", a)),
+        }
+    } else if data["game_over"].is_object() {
+        let field = |k: &str| {
+            data["game_over"][k]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("invalid game over: {}", data["game_over"]))
+        };
+        Ok(Message::GameOver {
+            result: field("result")?,
+            reason: field("reason")?,
+        })
+    } else if let Some(abortable) = data["abortable"].as_bool() {
+        Ok(Message::Abortable(abortable))
+    } else if !data["aborted"].is_null() {
+        Ok(Message::Aborted)
+    } else if !data["shutdown"].is_null() {
+        Ok(Message::ServerShutdown)
+    } else if data["denied"].is_string() {
+        Ok(Message::Denied)
+    } else if data["role"] == "spectator" {
+        Ok(Message::Spectating)
+    } else if data["rated"] == true {
+        Ok(Message::Rated)
+    } else if let Some(color) = data["color"].as_str() {
+        match color {
+            "white" => Ok(Message::Color(0)),
+            "black" => Ok(Message::Color(1)),
+            _ => Err(format!("invalid color: {}", color)),
+        }
+    } else if !data["src_row"].is_null() {
+        let square = |k: &str| {
+            data[k]
+                .as_u64()
+                .map(|n| n as usize)
+                .filter(|&n| on_board(n))
+                .ok_or_else(|| format!("invalid {}: {}", k, data[k]))
+        };
+        let invalid = |k: &str| format!("invalid {}: {}", k, data[k]);
+        let promotion = match &data["promotion"] {
+            Value::Null => None,
+            Value::String(p) if p.len() == 1 => Some(valid_promotion(p.as_bytes()[0])?),
+            _ => return Err(invalid("promotion")),
+        };
+        let flags = match &data["flags"] {
+            Value::Null => 0,
+            v => v
+                .as_u64()
+                .and_then(|n| u8::try_from(n).ok())
+                .ok_or_else(|| invalid("flags"))?,
+        };
+        let clock_ms = match &data["clock_ms"] {
+            Value::Null => 0,
+            v => v
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| invalid("clock_ms"))?,
+        };
+        let hash = match &data["hash"] {
+            Value::Null => 0,
+            v => v
+                .as_str()
+                .and_then(|h| u64::from_str_radix(h, 16).ok())
+                .ok_or_else(|| invalid("hash"))?,
+        };
+        let mut via = [0; MOVE_MAX_VIA];
+        if !data["via"].is_null() {
+            let squares = data["via"].as_array().ok_or_else(|| invalid("via"))?;
+            if squares.len() > MOVE_MAX_VIA {
+                return Err(invalid("via"));
+            }
+            for (b, v) in via.iter_mut().zip(squares) {
+                let n = |i: usize| v[i].as_u64().map(|n| n as usize).filter(|&n| on_board(n));
+                match (n(0), n(1)) {
+                    (Some(r), Some(c)) => *b = square_byte(r, c),
+                    _ => return Err(invalid("via")),
+                }
+            }
+        }
+        Ok(Message::Move(MoveFrame {
+            src_row: square("src_row")?,
+            src_col: square("src_col")?,
+            dst_row: square("dst_row")?,
+            dst_col: square("dst_col")?,
+            promotion,
+            flags,
+            clock_ms,
+            hash,
+            via,
+        }))
+    } else if let Some(t) = data["takeback"].as_str() {
+        let ply = || {
+            data["ply"]
+                .as_u64()
+                .and_then(|n| u16::try_from(n).ok())
+                .filter(|&n| n >= 1)
+                .ok_or_else(|| format!("invalid ply: {}", data["ply"]))
+        };
+        match t {
+            "request" => Ok(Message::TakebackRequest(ply()?)),
+            "accept" => Ok(Message::TakebackAccept(ply()?)),
+            "decline" => Ok(Message::TakebackDecline),
+            _ => Err(format!("invalid takeback: {}", t)),
+        }
+    } else if let Some(d) = data["draw"].as_str() {
+        match d {
+            "offer" => Ok(Message::DrawOffer),
+            _ => Err(format!("invalid draw: {}", d)),
+        }
+    } else if data["ruleset"].is_object() {
+        Ok(Message::Ruleset(data["ruleset"].to_string()))
+    } else if data["analysis"].is_object() {
+        let analysis = &data["analysis"];
+        let fen = analysis["fen"]
+            .as_str()
+            .ok_or_else(|| format!("invalid analysis: {}", analysis))?;
+        Ok(Message::Analysis {
+            fen: fen.to_string(),
+            uci: analysis["uci"].as_str().map(str::to_string),
+        })
+    } else if data["signal"].is_object() {
+        Ok(Message::Signal)
+    } else if data["prefs"].is_object() {
+        Ok(Message::UserPrefs(data["prefs"].to_string()))
+    } else if let Some(rules) = data["rules"].as_object() {
+        Ok(Message::RulesUpdate(
+            rules
+                .iter()
+                .filter_map(|(k, v)| v.as_bool().map(|b| (k.clone(), b)))
+                .collect(),
+        ))
+    } else {
+        Err(format!("unknown message: {}", msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode(r#"{"game_id": "abc"}"#),
+            Ok(Message::GameId("abc".to_string()))
+        );
+        assert_eq!(decode(r#"{"joined": "abc"}"#), Ok(Message::Joined));
+        assert_eq!(
+            decode(r#"{"presence": {"players": 1, "spectators": 3}}"#),
+            Ok(Message::Presence(Presence {
+                players: 1,
+                spectators: 3
+            }))
+        );
+        assert_eq!(
+            decode(r#"{"shutdown": "The server is shutting down"}"#),
+            Ok(Message::ServerShutdown)
+        );
+        assert_eq!(decode(r#"{"color": "black"}"#), Ok(Message::Color(1)));
+        assert_eq!(
+            decode(r#"{"abandoned": "draw"}"#),
+            Ok(Message::Abandoned(Adjudication::Draw))
+        );
+        assert_eq!(
+            decode(r#"{"game_over": {"result": "1-0", "reason": "abandoned"}}"#),
+            Ok(Message::GameOver {
+                result: "1-0".to_string(),
+                reason: "abandoned".to_string()
+            })
+        );
+        assert_eq!(
+            decode(r#"{"abortable": true}"#),
+            Ok(Message::Abortable(true))
+        );
+        assert_eq!(decode(r#"{"aborted": true}"#), Ok(Message::Aborted));
+        assert_eq!(
+            decode(r#"{"denied": "This game is private"}"#),
+            Ok(Message::Denied)
+        );
+        assert_eq!(decode(r#"{"role": "spectator"}"#), Ok(Message::Spectating));
+        assert_eq!(decode(r#"{"rated": true}"#), Ok(Message::Rated));
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        assert_eq!(
+            decode(&encode_analysis(fen, Some("e2e4"))),
+            Ok(Message::Analysis {
+                fen: fen.to_string(),
+                uci: Some("e2e4".to_string())
+            })
+        );
+        assert!(decode(r#"{"analysis": {"uci": "e2e4"}}"#).is_err());
+        assert_eq!(
+            decode(r#"{"takeback": "request", "ply": 3}"#),
+            Ok(Message::TakebackRequest(3))
+        );
+        assert_eq!(
+            decode(r#"{"takeback": "accept", "ply": 3}"#),
+            Ok(Message::TakebackAccept(3))
+        );
+        assert_eq!(
+            decode(r#"{"takeback": "decline"}"#),
+            Ok(Message::TakebackDecline)
+        );
+        assert_eq!(decode(r#"{"draw": "offer"}"#), Ok(Message::DrawOffer));
+        assert!(decode(r#"{"draw": "maybe"}"#).is_err());
+        assert_eq!(
+            decode(r#"{"signal": {"candidate": {"candidate": "", "sdpMid": "0"}}}"#),
+            Ok(Message::Signal)
+        );
+        assert_eq!(
+            decode(r#"{"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5}"#),
+            Ok(Message::Move(MoveFrame {
+                src_row: 2,
+                src_col: 5,
+                dst_row: 4,
+                dst_col: 5,
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            decode(r#"{"rules": {"knight": false, "bad": 1}}"#),
+            Ok(Message::RulesUpdate(HashMap::from([(
+                "knight".to_string(),
+                false
+            )])))
+        );
+        assert_eq!(
+            decode(&encode_prefs(r#"{"theme": "wood"}"#).unwrap()),
+            Ok(Message::UserPrefs(r#"{"theme":"wood"}"#.to_string()))
+        );
+        assert_eq!(
+            encode_login("0c2e4a5e-8d5b-4f9e-9a62-3b3f0e7c1d2a"),
+            r#"{"login":"0c2e4a5e-8d5b-4f9e-9a62-3b3f0e7c1d2a"}"#
+        );
+    }
+
+    #[test]
+    fn test_decode_rejected() {
+        assert!(decode("").is_err());
+        assert!(decode("[]").is_err());
+        assert!(decode(r#"{"color": "red"}"#).is_err());
+        assert!(decode(r#"{"abandoned": "lose"}"#).is_err());
+        assert!(decode(r#"{"game_over": {"result": "1-0"}}"#).is_err());
+        assert!(decode(r#"{"takeback": "request"}"#).is_err());
+        assert!(decode(r#"{"prefs": "wood"}"#).is_err());
+        assert!(decode(r#"{"takeback": "accept", "ply": 0}"#).is_err());
+        assert!(decode(r#"{"takeback": "maybe", "ply": 3}"#).is_err());
+        assert!(decode(r#"{"presence": {"players": -1, "spectators": 0}}"#).is_err());
+        assert!(decode(r#"{"src_row": 2, "src_col": 5, "dst_row": 4}"#).is_err());
+        assert!(decode(r#"{"src_row": 99, "src_col": 5, "dst_row": 4, "dst_col": 5}"#).is_err());
+        assert!(decode(r#"{"src_row": -1, "src_col": 5, "dst_row": 4, "dst_col": 5}"#).is_err());
+        let m = r#""src_row": 7, "src_col": 1, "dst_row": 8, "dst_col": 1"#;
+        assert!(decode(&format!(r#"{{{}, "promotion": "qq"}}"#, m)).is_err());
+        assert!(decode(&format!(r#"{{{}, "flags": 256}}"#, m)).is_err());
+        assert!(decode(&format!(r#"{{{}, "hash": 12}}"#, m)).is_err());
+    }
+
+    #[test]
+    fn test_adjudication() {
+        // Aborted until both players have moved.
+        assert_eq!(Adjudication::Win.result(2, false, 0), "*");
+        assert_eq!(Adjudication::Win.result(3, false, 0), "1-0");
+        assert_eq!(Adjudication::Win.result(3, false, 1), "0-1");
+        assert_eq!(Adjudication::Win.result(40, true, 1), "1/2-1/2");
+        assert_eq!(Adjudication::Draw.result(40, false, 0), "1/2-1/2");
+        assert_eq!(Adjudication::Abort.result(40, false, 0), "*");
+    }
+
+    #[test]
+    fn test_move_encodings() {
+        let m = MoveFrame {
+            src_row: 7,
+            src_col: 2,
+            dst_row: 8,
+            dst_col: 1,
+            promotion: Some(b'N'),
+            flags: MOVE_CAPTURE,
+            clock_ms: 61_500,
+            hash: 0xfedc_ba98_7654_3210,
+            ..Default::default()
+        };
+        let frame = encode_move(&m);
+        assert_eq!(frame.len(), MOVE_FRAME_LEN);
+        assert_eq!(&frame[..5], &[b'M', 0x72, 0x81, b'N', MOVE_CAPTURE]);
+        assert_eq!(decode_binary(&frame), Ok(Message::Move(m)));
+        assert_eq!(decode(&encode_move_json(&m)), Ok(Message::Move(m)));
+
+        // Unset fields are left out of JSON, so it's the same as before they existed.
+        let plain = MoveFrame {
+            src_row: 2,
+            src_col: 5,
+            dst_row: 4,
+            dst_col: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_move_json(&plain),
+            r#"{"dst_col":5,"dst_row":4,"src_col":5,"src_row":2}"#
+        );
+        assert_eq!(
+            decode_binary(&encode_move(&plain)),
+            Ok(Message::Move(plain))
+        );
+
+        // A chain of jumps.
+        let mut chain = plain;
+        chain.set_legs(&[(4, 7), (6, 5), (8, 7)]);
+        assert_eq!(chain.legs(), [(4, 7), (6, 5), (8, 7)]);
+        assert_eq!(encode_move(&chain).len(), MOVE_FRAME_LEN + 2);
+        assert_eq!(
+            decode_binary(&encode_move(&chain)),
+            Ok(Message::Move(chain))
+        );
+        assert!(encode_move_json(&chain).contains(r#""via":[[4,7],[6,5]]"#));
+        assert_eq!(decode(&encode_move_json(&chain)), Ok(Message::Move(chain)));
+        let mut bad = encode_move(&chain);
+        bad.push(0x99);
+        assert!(decode_binary(&bad).is_err());
+        assert!(decode_binary(&[encode_move(&plain), vec![0x11; 13]].concat()).is_err());
+        assert!(decode(
+            r#"{"src_row": 2, "src_col": 5, "dst_row": 4, "dst_col": 5, "via": [[9, 1]]}"#
+        )
+        .is_err());
+
+        assert!(decode_binary(&frame[..16]).is_err());
+        let mut bad = frame.clone();
+        bad[0] = b'X';
+        assert!(decode_binary(&bad).is_err());
+        let mut bad = frame.clone();
+        bad[2] = 0x91;
+        assert!(decode_binary(&bad).is_err());
+        let mut bad = frame;
+        bad[3] = b'+';
+        assert!(decode_binary(&bad).is_err());
+    }
+}
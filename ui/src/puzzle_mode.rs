@@ -0,0 +1,96 @@
+// Solving a puzzle from the server's store (see server/src/puzzles.rs), e.g. the puzzle of the day:
+// the player plays the side to move in its position, and each move of theirs is checked against
+// the solution. A right one is answered with the solution's next move, a wrong one is taken back
+// and counted as a mistake, and the player's last move solves it. The page gets the puzzle as JSON
+// through load_puzzle (see main.rs):
+//   {"id": "<game ID>-<ply>", "fen": "...", "moves": ["d5c7", "e8d7", "c7a8"], ...}
+
+use serde_json::Value;
+
+use chess_ui::notation::parse_fen;
+
+use crate::prelude::*;
+
+pub struct Puzzle {
+    pub id: String,
+    // The solution in long algebraic notation, the player's moves and the replies in turn.
+    moves: Vec<String>,
+    // The solution's next move.
+    next: usize,
+    pub mistakes: u32,
+    // The player's side, 0 for white.
+    pub side: usize,
+}
+
+// What a move of the player's does.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    // It's right, and this is the reply to it.
+    Reply(String),
+    // It's right, and the last.
+    Solved,
+    Wrong,
+}
+
+impl Puzzle {
+    // The puzzle in JSON, and the position it starts from.
+    pub fn parse(json: &str) -> Result<(Puzzle, (PiecePlacements, GameData)), String> {
+        let data: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let id = data["id"].as_str().ok_or("Puzzle without an ID")?;
+        let fen = data["fen"].as_str().ok_or("Puzzle without a position")?;
+        let moves = data["moves"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|m| m.as_str().map(String::from))
+                    .collect()
+            })
+            .filter(|m: &Vec<String>| !m.is_empty())
+            .ok_or("Puzzle without a solution")?;
+        let (pp, gd) = parse_fen(fen)?;
+        let puzzle = Puzzle {
+            id: id.to_string(),
+            moves,
+            next: 0,
+            mistakes: 0,
+            side: gd.player_to_move(),
+        };
+        Ok((puzzle, (pp, gd)))
+    }
+
+    // Checks the player's move, in long algebraic notation.
+    pub fn check(&mut self, played: &str) -> Step {
+        let Some(expected) = self.moves.get(self.next) else {
+            return Step::Wrong;
+        };
+        if !played.eq_ignore_ascii_case(expected) {
+            self.mistakes += 1;
+            return Step::Wrong;
+        }
+        self.next += 2;
+        match self.moves.get(self.next - 1) {
+            Some(reply) => Step::Reply(reply.clone()),
+            None => Step::Solved,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_puzzle() {
+        let json = r#"{"id": "g-39", "fen": "q3k3/8/8/3N4/8/8/8/4K3 w - - 0 20",
+                       "moves": ["d5c7", "e8d7", "c7a8"], "streak": 2}"#;
+        let (mut puzzle, (_, gd)) = Puzzle::parse(json).unwrap();
+        assert_eq!((&puzzle.id[..], puzzle.side, gd.ply), ("g-39", 0, 39));
+        assert_eq!(puzzle.check("d5e7"), Step::Wrong);
+        assert_eq!(puzzle.check("D5C7"), Step::Reply("e8d7".to_string()));
+        assert_eq!(puzzle.check("c7e8"), Step::Wrong);
+        assert_eq!(puzzle.check("c7a8"), Step::Solved);
+        assert_eq!(puzzle.mistakes, 2);
+        assert!(Puzzle::parse(r#"{"id": "g", "fen": "8/8 w", "moves": ["a1a2"]}"#).is_err());
+        assert!(Puzzle::parse(r#"{"id": "g", "fen": "4k3/8/8/8/8/8/8/4K3 b - - 0 1"}"#).is_err());
+    }
+}
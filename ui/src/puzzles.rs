@@ -0,0 +1,223 @@
+// Puzzles from online games, from the server's move logs (see replay.rs): positions after the
+// opening where the player to move has exactly one winning move, while the material is still about
+// even. The solution follows the engine's best reply for as long as the player's winning move stays
+// the only one. Run the desktop binary with --puzzles FILE... (see USAGE). Each puzzle goes to
+// stdout as a line of JSON, with the moves in long algebraic notation, the player's first:
+//   {"id": "<game ID>-<ply>", "game_id": "...", "fen": "...", "moves": ["d5c7", "e8d7", "c7a8"]}
+// Only games of standard chess are looked at. The server runs this after each game to fill its
+// puzzle store (see server/src/puzzles.rs).
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use chess_ui::{
+    core::after,
+    notation::{fen, long_algebraic},
+};
+
+use crate::{
+    engine::{evaluate, Engine, EngineConfig, MATE_SCORE},
+    prelude::*,
+    replay::{read_log, replay},
+};
+
+const USAGE: &str = "Usage: chess-ui --puzzles FILE... [--depth N]";
+
+// The opening is often played from memory, so it's left out.
+const SKIP_PLIES: u16 = 8;
+// A move wins if it scores at least this much, in centipawns, and the position is only a puzzle if
+// the material isn't that far ahead already.
+const WINNING: i32 = 250;
+// How much worse than the winning move the second best has to be.
+const UNIQUE_GAP: i32 = 200;
+// The player's moves in a solution, at most.
+const MAX_MOVES: usize = 3;
+
+#[derive(Debug, PartialEq)]
+struct Puzzle {
+    // The position's ply, which tells it apart from the game's other puzzles.
+    ply: u16,
+    fen: String,
+    moves: Vec<String>,
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip_while(|a| a != "--puzzles").skip(1);
+    let mut paths = Vec::new();
+    let mut depth = 3;
+    while let Some(a) = args.next() {
+        if a == "--depth" {
+            depth = match args.next().and_then(|d| d.parse().ok()) {
+                Some(d) if d > 0 => d,
+                _ => usage(),
+            };
+        } else {
+            paths.push(a);
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+    let rules = Rules::defaults();
+    let engine = Engine {
+        config: EngineConfig {
+            depth,
+            // The depth is what's fixed, so the puzzles don't depend on the machine.
+            time_limit: f64::INFINITY,
+            blunder_chance: 0.0,
+            ..EngineConfig::level(3)
+        },
+    };
+    let mut failed = false;
+    for path in paths {
+        match read_log(Path::new(&path)).and_then(|log| puzzles_in(&engine, &rules, &log)) {
+            Ok(puzzles) => {
+                for puzzle in puzzles {
+                    println!("{}", puzzle);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+// The puzzles in a game's log, as JSON. Games with a rule set of their own have none.
+fn puzzles_in(engine: &Engine, rules: &Rules, log: &str) -> Result<Vec<Value>, String> {
+    let header: Value = log
+        .lines()
+        .next()
+        .and_then(|l| serde_json::from_str(l).ok())
+        .unwrap_or_default();
+    if header["ruleset"].is_object() {
+        return Ok(Vec::new());
+    }
+    let game_id = header["game_id"].as_str().unwrap_or_default();
+    let positions: Vec<_> = replay(log)?.played.iter().map(|p| (p.pp, p.gd)).collect();
+    let puzzles = find(engine, rules, &positions)
+        .into_iter()
+        .map(|p| {
+            json!({
+                "id": format!("{}-{}", game_id, p.ply),
+                "game_id": game_id,
+                "fen": p.fen,
+                "moves": p.moves,
+            })
+        })
+        .collect();
+    Ok(puzzles)
+}
+
+// The puzzles in a game's positions, in order. A puzzle's solution isn't searched for more.
+fn find(engine: &Engine, rules: &Rules, positions: &[(PiecePlacements, GameData)]) -> Vec<Puzzle> {
+    let mut puzzles = Vec::new();
+    let mut solved_until = 0;
+    for &(pp, gd) in positions {
+        if gd.ply <= SKIP_PLIES || gd.ply < solved_until || evaluate(&pp, gd, None) >= WINNING {
+            continue;
+        }
+        let scored = engine.score_moves(rules, &pp, gd);
+        // A move that's forced isn't found.
+        if scored.len() < 2 {
+            continue;
+        }
+        if let Some(first) = winning_move(&scored) {
+            let moves = solve(engine, rules, pp, first);
+            solved_until = gd.ply + moves.len() as u16;
+            puzzles.push(Puzzle {
+                ply: gd.ply,
+                fen: fen(&pp, gd),
+                moves,
+            });
+        }
+    }
+    puzzles
+}
+
+// The only move that wins, if there's just one, from moves scored best first.
+fn winning_move(scored: &[(i32, (Piece, Move))]) -> Option<(Piece, Move)> {
+    let &(best, m) = scored.first()?;
+    let second = scored.get(1).map_or(-MATE_SCORE, |&(score, _)| score);
+    (best >= WINNING && second < WINNING && best - second >= UNIQUE_GAP).then_some(m)
+}
+
+// The solution starting with the winning move first in the position pp: it, then the engine's
+// best reply and the player's next move, for as long as that's the only winning move too.
+fn solve(engine: &Engine, rules: &Rules, pp: PiecePlacements, first: (Piece, Move)) -> Vec<String> {
+    let mut moves = Vec::new();
+    let mut position = pp;
+    let mut next = Some(first);
+    while let Some((piece, m)) = next {
+        moves.push(long_algebraic(piece, m));
+        if moves.len() == 2 * MAX_MOVES - 1 {
+            break;
+        }
+        let (pp, gd) = after(position, piece, m);
+        let Some(&(_, (rp, rm))) = engine.score_moves(rules, &pp, gd).first() else {
+            // Mate, or stalemate.
+            break;
+        };
+        let (pp, gd) = after(pp, rp, rm);
+        next = winning_move(&engine.score_moves(rules, &pp, gd));
+        if next.is_some() {
+            moves.push(long_algebraic(rp, rm));
+            position = pp;
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::parse_fen;
+
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let rules = Rules::defaults();
+        let engine = Engine {
+            config: EngineConfig {
+                depth: 2,
+                time_limit: f64::INFINITY,
+                blunder_chance: 0.0,
+                ..EngineConfig::level(3)
+            },
+        };
+        let positions: Vec<_> = [
+            // A knight fork of the king and the queen, a queen down.
+            "q3k3/8/8/3N4/8/8/8/4K3 w - - 0 20",
+            // Nothing to win.
+            "4k3/8/8/8/8/8/3P4/4K3 w - - 0 25",
+            // A queen up already.
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 30",
+            // The fork again, but in the opening.
+            "q3k3/8/8/3N4/8/8/8/4K3 w - - 0 2",
+        ]
+        .iter()
+        .map(|f| parse_fen(f).unwrap())
+        .collect();
+        let puzzles = find(&engine, &rules, &positions);
+        assert_eq!(puzzles.len(), 1);
+        let fork = &puzzles[0];
+        assert_eq!(fork.ply, 39);
+        assert_eq!(fork.fen, "q3k3/8/8/3N4/8/8/8/4K3 w - - 0 20");
+        assert_eq!(fork.moves.len(), 3);
+        assert_eq!((&fork.moves[0][..], &fork.moves[2][..]), ("d5c7", "c7a8"));
+
+        // A game with its own rules has none.
+        let log = r#"{"game_id": "g", "ruleset": {"name": "Test"}, "time_ms": 0}"#;
+        assert_eq!(puzzles_in(&engine, &rules, log), Ok(Vec::new()));
+    }
+}
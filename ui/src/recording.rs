@@ -0,0 +1,383 @@
+// A recording of what went into the game, to reproduce a reported bug exactly: the game as it was
+// when it started, or was loaded, the rule set and the seed of the random numbers, and every input
+// since, in order. The page gets it with get_input_recording, and crash reports have it (see
+// crash.rs). The desktop binary replays one without a window (see reproduce.rs). As JSON:
+//   {"seed": 1234, "ruleset": {...}, "game": [...], "inputs": [...]}
+// where game is a snapshot of the game (see snapshot.rs), as an array of bytes, and each input is
+// one of
+//   {"mouse": [row, col], "player": 0, "button": "left", "pressed": true}
+//   {"confirm": [row, col], "player": 0}          the confirm key, on the cursor's square
+//   {"promote": "n"}                              what a piece promotes to, or null to put it back
+//   {"settings": {"input_mode": "drag", "auto_queen": true}}
+//   {"remote": <move, as the server sends it>, "player": 1}
+//   {"play": "e2e4", "player": 0}                 from play_move
+//   {"engine": "e7e5", "player": 1}               the engine's move
+//   {"pass": 0}
+//   {"rules": {"resolve-check": false}}           rules toggled
+//   {"ruleset": {...}}                            a rule set imported
+//   {"take_back": 3}                              back to that ply
+//   {"end": "1-0"}                                the game ended, e.g. abandoned
+// Mouse buttons are recorded as the squares they were pressed and released on, and they and the keys
+// are only recorded while the game is being played: not in variations or shared analysis. The engine's moves are recorded
+// since how far it searches depends on how fast the machine is.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde_json::{json, Value};
+
+use chess_ui::protocol::{self, encode_move_json, Message, MoveFrame};
+
+use crate::settings::{InputMode, Settings};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Mouse {
+        square: (usize, usize),
+        player: usize,
+        button: Button,
+        pressed: bool,
+    },
+    Confirm {
+        square: (usize, usize),
+        player: usize,
+    },
+    // Picked in a dialog once the mouse or keys make a move with a choice of promotions.
+    Promote(Option<u8>),
+    Settings {
+        input_mode: InputMode,
+        auto_queen: bool,
+    },
+    Remote {
+        m: MoveFrame,
+        player: usize,
+    },
+    Play {
+        m: String,
+        player: usize,
+    },
+    Engine {
+        m: String,
+        player: usize,
+    },
+    Pass(usize),
+    Rules(BTreeMap<String, bool>),
+    Ruleset(String),
+    TakeBack(u16),
+    End(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub seed: u64,
+    // As export_ruleset returns it.
+    pub ruleset: String,
+    pub game: Vec<u8>,
+    pub inputs: Vec<Input>,
+}
+
+static RECORDING: Mutex<Recording> = Mutex::new(Recording {
+    seed: 0,
+    ruleset: String::new(),
+    game: Vec::new(),
+    inputs: Vec::new(),
+});
+
+pub fn set_seed(seed: u64) {
+    RECORDING.lock().unwrap().seed = seed;
+}
+
+// Starts a new recording from the game, a snapshot, keeping the seed.
+pub fn start(ruleset: String, game: Vec<u8>) {
+    let mut r = RECORDING.lock().unwrap();
+    r.ruleset = ruleset;
+    r.game = game;
+    r.inputs.clear();
+}
+
+pub fn record(input: Input) {
+    RECORDING.lock().unwrap().inputs.push(input);
+}
+
+// Records a mouse button or the confirm key, after the player's settings if they've changed since
+// they were last recorded, since they change what those do.
+pub fn record_mouse(settings: &Settings, mouse: Input) {
+    let mut r = RECORDING.lock().unwrap();
+    let changed = Input::Settings {
+        input_mode: settings.input_mode,
+        auto_queen: settings.auto_queen,
+    };
+    let last = r
+        .inputs
+        .iter()
+        .rfind(|i| matches!(i, Input::Settings { .. }));
+    if last != Some(&changed) {
+        r.inputs.push(changed);
+    }
+    r.inputs.push(mouse);
+}
+
+// The recording as JSON, or None if it's being updated, e.g. by a panic in the middle of it.
+pub fn json() -> Option<Value> {
+    RECORDING.try_lock().ok().map(|r| r.to_json())
+}
+
+fn button_name(b: Button) -> &'static str {
+    match b {
+        Button::Left => "left",
+        Button::Right => "right",
+    }
+}
+
+fn input_mode_name(m: InputMode) -> &'static str {
+    match m {
+        InputMode::Drag => "drag",
+        InputMode::Click => "click",
+    }
+}
+
+impl Input {
+    fn to_json(&self) -> Value {
+        match self {
+            Input::Mouse {
+                square,
+                player,
+                button,
+                pressed,
+            } => json!({
+                "mouse": [square.0, square.1],
+                "player": player,
+                "button": button_name(*button),
+                "pressed": pressed,
+            }),
+            Input::Confirm { square, player } => json!({
+                "confirm": [square.0, square.1],
+                "player": player,
+            }),
+            Input::Promote(p) => json!({ "promote": p.map(|p| (p as char).to_string()) }),
+            Input::Settings {
+                input_mode,
+                auto_queen,
+            } => json!({
+                "settings": {
+                    "input_mode": input_mode_name(*input_mode),
+                    "auto_queen": auto_queen,
+                }
+            }),
+            Input::Remote { m, player } => {
+                let m: Value = serde_json::from_str(&encode_move_json(m)).unwrap();
+                json!({ "remote": m, "player": player })
+            }
+            Input::Play { m, player } => json!({ "play": m, "player": player }),
+            Input::Engine { m, player } => json!({ "engine": m, "player": player }),
+            Input::Pass(player) => json!({ "pass": player }),
+            Input::Rules(r) => json!({ "rules": r }),
+            Input::Ruleset(r) => json!({
+                "ruleset": serde_json::from_str::<Value>(r).unwrap_or(Value::Null)
+            }),
+            Input::TakeBack(ply) => json!({ "take_back": ply }),
+            Input::End(result) => json!({ "end": result }),
+        }
+    }
+
+    fn from_json(v: &Value) -> Result<Input, String> {
+        let invalid = || format!("invalid input: {}", v);
+        let player = || {
+            v["player"]
+                .as_u64()
+                .filter(|&p| p < 2)
+                .map(|p| p as usize)
+                .ok_or_else(invalid)
+        };
+        let string = |s: &Value| s.as_str().map(str::to_string).ok_or_else(invalid);
+        if let Some(square) = v.get("mouse") {
+            let rc = |i: usize| square[i].as_u64().map(|n| n as usize).ok_or_else(invalid);
+            let button = match v["button"].as_str() {
+                Some("left") => Button::Left,
+                Some("right") => Button::Right,
+                _ => return Err(invalid()),
+            };
+            return Ok(Input::Mouse {
+                square: (rc(0)?, rc(1)?),
+                player: player()?,
+                button,
+                pressed: v["pressed"].as_bool().ok_or_else(invalid)?,
+            });
+        }
+        if let Some(square) = v.get("confirm") {
+            let rc = |i: usize| square[i].as_u64().map(|n| n as usize).ok_or_else(invalid);
+            return Ok(Input::Confirm {
+                square: (rc(0)?, rc(1)?),
+                player: player()?,
+            });
+        }
+        if let Some(p) = v.get("promote") {
+            return match p.as_str().map(str::as_bytes) {
+                None if p.is_null() => Ok(Input::Promote(None)),
+                Some(&[p]) => Ok(Input::Promote(Some(p))),
+                _ => Err(invalid()),
+            };
+        }
+        if let Some(s) = v.get("settings") {
+            let input_mode = match s["input_mode"].as_str() {
+                Some("drag") => InputMode::Drag,
+                Some("click") => InputMode::Click,
+                _ => return Err(invalid()),
+            };
+            return Ok(Input::Settings {
+                input_mode,
+                auto_queen: s["auto_queen"].as_bool().ok_or_else(invalid)?,
+            });
+        }
+        if let Some(m) = v.get("remote") {
+            return match protocol::decode(&m.to_string())? {
+                Message::Move(m) => Ok(Input::Remote {
+                    m,
+                    player: player()?,
+                }),
+                _ => Err(invalid()),
+            };
+        }
+        if let Some(m) = v.get("play") {
+            return Ok(Input::Play {
+                m: string(m)?,
+                player: player()?,
+            });
+        }
+        if let Some(m) = v.get("engine") {
+            return Ok(Input::Engine {
+                m: string(m)?,
+                player: player()?,
+            });
+        }
+        if let Some(p) = v.get("pass") {
+            return match p.as_u64() {
+                Some(p) if p < 2 => Ok(Input::Pass(p as usize)),
+                _ => Err(invalid()),
+            };
+        }
+        if let Some(r) = v.get("rules").and_then(Value::as_object) {
+            return r
+                .iter()
+                .map(|(n, a)| a.as_bool().map(|a| (n.clone(), a)).ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Input::Rules);
+        }
+        if let Some(r) = v.get("ruleset") {
+            return Ok(Input::Ruleset(r.to_string()));
+        }
+        if let Some(ply) = v.get("take_back") {
+            return ply
+                .as_u64()
+                .and_then(|p| u16::try_from(p).ok())
+                .map(Input::TakeBack)
+                .ok_or_else(invalid);
+        }
+        if let Some(result) = v.get("end") {
+            return Ok(Input::End(string(result)?));
+        }
+        Err(invalid())
+    }
+}
+
+impl Recording {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "seed": self.seed,
+            "ruleset": serde_json::from_str::<Value>(&self.ruleset).unwrap_or(Value::Null),
+            "game": self.game,
+            "inputs": self.inputs.iter().map(Input::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn from_json(v: &Value) -> Result<Recording, String> {
+        let game = v["game"]
+            .as_array()
+            .and_then(|g| {
+                g.iter()
+                    .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .ok_or("the recording has no game")?;
+        let inputs = v["inputs"]
+            .as_array()
+            .ok_or("the recording has no inputs")?
+            .iter()
+            .map(Input::from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Recording {
+            seed: v["seed"].as_u64().ok_or("the recording has no seed")?,
+            ruleset: v["ruleset"].to_string(),
+            game,
+            inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_json() {
+        let mut m = MoveFrame {
+            src_row: 7,
+            src_col: 5,
+            dst_row: 5,
+            dst_col: 5,
+            ..Default::default()
+        };
+        m.hash = 0x1234;
+        let recording = Recording {
+            seed: 42,
+            ruleset: r#"{"name":"Test"}"#.to_string(),
+            game: vec![1, 2, 255],
+            inputs: vec![
+                Input::Settings {
+                    input_mode: InputMode::Click,
+                    auto_queen: false,
+                },
+                Input::Mouse {
+                    square: (2, 5),
+                    player: 0,
+                    button: Button::Left,
+                    pressed: true,
+                },
+                Input::Confirm {
+                    square: (2, 5),
+                    player: 0,
+                },
+                Input::Promote(Some(b'n')),
+                Input::Promote(None),
+                Input::Remote { m, player: 1 },
+                Input::Play {
+                    m: "d2d4".to_string(),
+                    player: 0,
+                },
+                Input::Engine {
+                    m: "d7d5".to_string(),
+                    player: 1,
+                },
+                Input::Pass(0),
+                Input::Rules(BTreeMap::from([("resolve-check".to_string(), false)])),
+                Input::Ruleset(r#"{"name":"Other"}"#.to_string()),
+                Input::TakeBack(3),
+                Input::End("1-0".to_string()),
+            ],
+        };
+        let v = recording.to_json();
+        assert_eq!(v["inputs"][1]["mouse"], json!([2, 5]));
+        let parsed = Recording::from_json(&serde_json::from_str(&v.to_string()).unwrap()).unwrap();
+        assert_eq!(parsed, recording);
+
+        assert!(Input::from_json(&json!({ "pass": 2 })).is_err());
+        assert!(Input::from_json(&json!({ "promote": "nb" })).is_err());
+        assert!(Input::from_json(&json!({ "jump": [1, 1] })).is_err());
+        assert!(Recording::from_json(&json!({ "seed": 1, "inputs": [] })).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// Replays a game from the server's move log (see server/src/movelog.rs), to check a game
+// afterwards: run the desktop binary with --replay FILE, where FILE is the game's .ndjson file.
+// Rotated files next to it (FILE.1, FILE.2, ...) are read first. Each move has to be legal, and
+// the position after it has to match the hash the player sent with it. Agreed takebacks are
+// followed. The moves, and the result if the game ended, are written to stderr and the final
+// position to stdout, as FEN.
+
+use std::{fs, path::Path};
+
+use serde_json::Value;
+
+use chess_ui::{
+    history::History,
+    notation::{fen, long_algebraic},
+    protocol::{decode, Message},
+};
+
+use crate::prelude::*;
+
+const USAGE: &str = "Usage: chess-ui --replay FILE";
+
+#[derive(Debug)]
+pub struct Replay {
+    // In long algebraic notation.
+    pub moves: Vec<String>,
+    // The same moves, with who played them and when.
+    pub played: Vec<Played>,
+    pub fen: String,
+    // e.g. "1-0 (abandoned)".
+    pub result: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Played {
+    pub player_id: String,
+    // Milliseconds between the line before the move in the log and the move, i.e. how long the
+    // player took to make it.
+    pub think_ms: u64,
+    // The position the move was made in.
+    pub pp: PiecePlacements,
+    pub gd: GameData,
+    pub piece: Piece,
+    pub m: Move,
+}
+
+pub fn main() {
+    let path = match std::env::args().skip_while(|a| a != "--replay").nth(1) {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    match read_log(Path::new(&path)).and_then(|log| replay(&log)) {
+        Ok(r) => {
+            eprintln!("{}", r.moves.join(" "));
+            if let Some(result) = r.result {
+                eprintln!("{}", result);
+            }
+            println!("{}", r.fen);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The game's rotated files, oldest first, followed by the file itself.
+pub fn read_log(path: &Path) -> Result<String, String> {
+    let read = |p: &Path| {
+        fs::read_to_string(p).map_err(|e| format!("Couldn't read {}: {}", p.display(), e))
+    };
+    let mut log = String::new();
+    for n in 1.. {
+        let rotated = format!("{}.{}", path.display(), n);
+        if !Path::new(&rotated).exists() {
+            break;
+        }
+        log.push_str(&read(Path::new(&rotated))?);
+    }
+    log.push_str(&read(path)?);
+    Ok(log)
+}
+
+pub fn replay(log: &str) -> Result<Replay, String> {
+    let mut rules = Rules::defaults();
+    let mut pp = rules.initial_placements();
+    let mut gd = GameData { ply: 1, mask: 0 };
+    let mut played = Vec::new();
+    let mut last_time_ms = 0;
+    // The moves so far, and their positions for takebacks.
+    let mut history = History::new((pp, gd));
+    let mut result = None;
+    for (i, line) in log.lines().enumerate() {
+        let err = |e: String| format!("Line {}: {}", i + 1, e);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let data: Value = serde_json::from_str(line).map_err(|e| err(e.to_string()))?;
+        let time_ms = data["time_ms"].as_u64().unwrap_or(last_time_ms);
+        let think_ms = time_ms.saturating_sub(last_time_ms);
+        last_time_ms = time_ms;
+        // The line describing the game.
+        if data["game_id"].is_string() {
+            if !history.moves().is_empty() {
+                return Err(err("Game started again after moves".to_string()));
+            }
+            if data["ruleset"].is_object() {
+                rules = Rules::defaults();
+                rules
+                    .import_ruleset(&data["ruleset"].to_string())
+                    .map_err(err)?;
+                pp = rules.initial_placements();
+                gd = rules.initial_game_data();
+                history = History::new((pp, gd));
+            }
+            continue;
+        }
+        if let (Some(r), Some(reason)) = (data["result"].as_str(), data["reason"].as_str()) {
+            result = Some(format!("{} ({})", r, reason));
+            continue;
+        }
+        if let Some(ply) = data["takeback"].as_u64() {
+            if ply < 1 || ply >= gd.ply as u64 {
+                return Err(err(format!("Can't take back to ply {}", ply)));
+            }
+            history.truncate(ply as usize - 1);
+            played.truncate(ply as usize - 1);
+            (pp, gd) = history.position(&rules, ply as usize - 1).map_err(err)?;
+            continue;
+        }
+        let f = match decode(line).map_err(err)? {
+            Message::Move(f) => f,
+            m => return Err(err(format!("Not a move: {:?}", m))),
+        };
+        // A pawn promotes to the rules' default unless the move says otherwise, like in the game.
+        let mut candidates = rules
+            .legal_moves(gd.player_to_move(), &pp, gd)
+            .into_iter()
+            .filter(|&(p, m)| {
+                let legs = move_legs(p, m);
+                (p.row as usize, p.col as usize) == (f.src_row, f.src_col)
+                    && legs.last() == Some(&(f.dst_row, f.dst_col))
+                    && (f.legs().len() == 1 || legs == f.legs())
+            });
+        let found = match f.promotion {
+            Some(promotion) => candidates.find(|(_, m)| m.dst.name == promotion),
+            None => candidates.min_by_key(|(p, m)| rules.promotion_order(*p, m.dst.name)),
+        };
+        let (p, m) = found.ok_or_else(|| err(format!("Illegal move: {:?}", f)))?;
+        played.push(Played {
+            player_id: data["player_id"].as_str().unwrap_or_default().to_string(),
+            think_ms,
+            pp,
+            gd,
+            piece: p,
+            m,
+        });
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+        history.push(long_algebraic(p, m), (pp, gd));
+        if f.hash != 0 && f.hash != Rules::position_hash(&pp, gd) {
+            return Err(err(format!(
+                "Position doesn't match the player's after {}",
+                history.moves().last().unwrap()
+            )));
+        }
+    }
+    Ok(Replay {
+        moves: history.moves().to_vec(),
+        played,
+        fen: fen(&pp, gd),
+        result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::parse_long_algebraic;
+    use serde_json::json;
+
+    use super::*;
+
+    // A log of the moves, as the server would write it, with the hashes the players would send.
+    fn log(rules: &Rules, ruleset: Value, moves: &[&str]) -> Vec<String> {
+        let mut lines = vec![json!({"game_id": "g", "ruleset": ruleset, "time_ms": 1}).to_string()];
+        let mut pp = rules.initial_placements();
+        let mut gd = GameData { ply: 1, mask: 0 };
+        for s in moves {
+            let (p, m) = parse_long_algebraic(rules, &pp, gd, s).unwrap();
+            Rules::make_move(p, m, &mut pp);
+            gd = GameData {
+                ply: m.game_data.ply + 1,
+                ..m.game_data
+            };
+            let b = s.as_bytes();
+            lines.push(
+                json!({
+                    "src_row": b[1] - b'0',
+                    "src_col": b[0] - b'a' + 1,
+                    "dst_row": b[3] - b'0',
+                    "dst_col": b[2] - b'a' + 1,
+                    "hash": format!("{:016x}", Rules::position_hash(&pp, gd)),
+                    "player_id": "p",
+                    "time_ms": 2,
+                })
+                .to_string(),
+            );
+        }
+        lines
+    }
+
+    #[test]
+    fn test_replay() {
+        let rules = Rules::defaults();
+        let lines = log(&rules, Value::Null, &["e2e4", "e7e5", "g1f3"]);
+        let r = replay(&lines.join("\n")).unwrap();
+        assert_eq!(r.moves, ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(
+            r.fen,
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 0 2"
+        );
+        assert_eq!(r.result, None);
+        let mut over = lines.clone();
+        over.push(json!({"result": "1-0", "reason": "abandoned", "player_id": "p"}).to_string());
+        let r = replay(&over.join("\n")).unwrap();
+        assert_eq!(r.result.as_deref(), Some("1-0 (abandoned)"));
+
+        // Out of sync with the player.
+        let mut bad = lines.clone();
+        let mut m: Value = serde_json::from_str(&bad[2]).unwrap();
+        m["hash"] = json!("0000000000000001");
+        bad[2] = m.to_string();
+        assert_eq!(
+            replay(&bad.join("\n")).unwrap_err(),
+            "Line 3: Position doesn't match the player's after e7e5"
+        );
+        // Moved twice.
+        let mut bad = lines.clone();
+        bad.insert(2, lines[1].clone());
+        assert!(replay(&bad.join("\n"))
+            .unwrap_err()
+            .contains("Illegal move"));
+
+        // Black takes back e7e5 and plays d7d5 instead.
+        let mut taken_back = lines[..3].to_vec();
+        taken_back.push(json!({"takeback": 2, "player_id": "p", "time_ms": 3}).to_string());
+        taken_back.push(log(&rules, Value::Null, &["e2e4", "d7d5"])[2].clone());
+        let r = replay(&taken_back.join("\n")).unwrap();
+        assert_eq!(r.moves, ["e2e4", "d7d5"]);
+        let mut bad = lines.clone();
+        bad.push(json!({"takeback": 4}).to_string());
+        assert!(replay(&bad.join("\n")).is_err());
+    }
+
+    #[test]
+    fn test_replay_ruleset() {
+        let mut rules = Rules::defaults();
+        let ruleset = json!({"version": 1, "name": "No knights", "rules": {"knight": false}});
+        rules.import_ruleset(&ruleset.to_string()).unwrap();
+        let lines = log(&rules, ruleset, &["e2e4"]);
+        assert!(replay(&lines.join("\n")).is_ok());
+        // Knights can't move in this variant.
+        let mut bad = lines.clone();
+        bad.push(log(&Rules::defaults(), Value::Null, &["e2e4", "g8f6"])[2].clone());
+        assert!(replay(&bad.join("\n")).is_err());
+    }
+}
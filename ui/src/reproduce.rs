@@ -0,0 +1,275 @@
+// Replays a recording of what went into a game (see recording.rs) without a window, to reproduce a
+// reported bug: run the desktop binary with --replay-inputs FILE, where FILE is a recording from
+// get_input_recording, or a crash report with one. The game's core (see core.rs) follows the mouse
+// the way it does in the game, picking pieces up and putting them down, and everything else is
+// handled the way the game handles it. Each move is written to stderr and the final position to
+// stdout, as FEN. A bug that panics the game panics the replay on the same input.
+
+use std::fs;
+
+use macroquad::rand;
+use serde_json::Value;
+
+use chess_ui::{
+    core::{GameCore, InputMode, Pointer, PointerEvent, Turn},
+    notation::{fen, parse_long_algebraic},
+    protocol::{MOVE_PASS, MOVE_PHASE, MOVE_PHASE_SHIFT},
+    snapshot,
+};
+
+use crate::{
+    prelude::*,
+    recording::{Button, Input, Recording},
+    settings::Settings,
+    warn,
+};
+
+const USAGE: &str = "Usage: chess-ui --replay-inputs FILE";
+
+struct Replayer<'a> {
+    core: GameCore<'a>,
+    over: bool,
+    input_mode: InputMode,
+    auto_queen: bool,
+    // A move made with a choice of promotions, until the player picks one.
+    promoting: Option<(usize, Piece, Move)>,
+}
+
+pub fn main() {
+    let path = match std::env::args()
+        .skip_while(|a| a != "--replay-inputs")
+        .nth(1)
+    {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let replayed = fs::read_to_string(&path)
+        .map_err(|e| format!("Couldn't read {}: {}", path, e))
+        .and_then(|s| serde_json::from_str::<Value>(&s).map_err(|e| e.to_string()))
+        .and_then(|v| {
+            // A crash report has the recording under "inputs".
+            let v = if v["inputs"].is_object() {
+                &v["inputs"]
+            } else {
+                &v
+            };
+            Recording::from_json(v)
+        })
+        .and_then(|r| replay(&r));
+    match replayed {
+        Ok((moves, fen)) => {
+            eprintln!("{}", moves.join(" "));
+            println!("{}", fen);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The moves made, and the final position in FEN.
+pub fn replay(recording: &Recording) -> Result<(Vec<String>, String), String> {
+    rand::srand(recording.seed);
+    let mut rules = Rules::defaults();
+    rules.import_ruleset(&recording.ruleset)?;
+    let game = snapshot::decode(&recording.game)?;
+    let mut core = GameCore::new(rules);
+    core.history = game.history.positions(&core.rules)?;
+    core.moves = game.history.moves().to_vec();
+    let mut r = Replayer {
+        core,
+        over: false,
+        input_mode: Settings::DEFAULT.input_mode,
+        auto_queen: Settings::DEFAULT.auto_queen,
+        promoting: None,
+    };
+    let start = r.core.moves.len();
+    for input in &recording.inputs {
+        r.input(input);
+    }
+    let (pp, gd) = r.core.position();
+    let moves = r.core.moves.split_off(start.min(r.core.moves.len()));
+    Ok((moves, fen(&pp, gd)))
+}
+
+impl<'a> Replayer<'a> {
+    fn turn(&self, player: usize) -> Turn {
+        self.core.turn(player, self.over)
+    }
+
+    fn pointer(&mut self, player: usize, mode: InputMode, event: PointerEvent) {
+        let turn = self.turn(player);
+        let auto_queen = self.auto_queen;
+        if let Some((p, m)) = self.core.pointer_event(&turn, mode, auto_queen, event) {
+            // Like the game, which asks what the piece promotes to first.
+            if !auto_queen && !self.core.promotion_choices(&turn, p, m).is_empty() {
+                self.promoting = Some((player, p, m));
+                return;
+            }
+            self.core.play(p, m);
+        }
+    }
+
+    fn input(&mut self, input: &Input) {
+        match input {
+            &Input::Mouse {
+                square,
+                player,
+                button,
+                pressed,
+            } => {
+                let event = match (button, pressed) {
+                    (Button::Left, true) => PointerEvent::Press(square),
+                    (Button::Left, false) => PointerEvent::Release(square),
+                    (Button::Right, true) => PointerEvent::Cancel,
+                    (Button::Right, false) => return,
+                };
+                self.pointer(player, self.input_mode, event);
+            }
+            // The keyboard moves pieces by clicks.
+            &Input::Confirm { square, player } => {
+                self.pointer(player, InputMode::Click, PointerEvent::Press(square))
+            }
+            &Input::Promote(promotion) => {
+                let Some((player, piece, m)) = self.promoting.take() else {
+                    warn!("Ignoring a promotion without a move");
+                    return;
+                };
+                let turn = self.turn(player);
+                let source = (piece.row as usize, piece.col as usize);
+                let legs = move_legs(piece, m);
+                let found = promotion
+                    .and_then(|p| self.core.find_move(&turn, source, &legs, Some(p), false));
+                if let Some((p, m)) = found {
+                    self.core.play(p, m);
+                }
+            }
+            &Input::Settings {
+                input_mode,
+                auto_queen,
+            } => {
+                self.input_mode = input_mode;
+                self.auto_queen = auto_queen;
+            }
+            Input::Remote { m, player } => {
+                let phase = ((m.flags & MOVE_PHASE) >> MOVE_PHASE_SHIFT) as usize;
+                if phase != self.core.game_data().phase_index() {
+                    return;
+                }
+                // Like Game::try_move, which puts back the piece the player is moving.
+                let turn = self.turn(*player);
+                self.core.pointer = Pointer::Up;
+                let found = if m.flags & MOVE_PASS != 0 {
+                    self.core.pass_move(&turn)
+                } else {
+                    let source = (m.src_row, m.src_col);
+                    self.core
+                        .find_move(&turn, source, &m.legs(), m.promotion, false)
+                };
+                match found {
+                    Some((p, m)) => self.core.play(p, m),
+                    None => warn!("Ignoring illegal move from the other player: {:?}", m),
+                }
+            }
+            Input::Play { m, player } | Input::Engine { m, player } => {
+                let (pp, gd) = self.core.position();
+                match parse_long_algebraic(&self.core.rules, &pp, gd, m) {
+                    Some((p, m)) if gd.player_to_move() == *player => self.core.play(p, m),
+                    _ => warn!("Ignoring move {}: it's not legal", m),
+                }
+            }
+            &Input::Pass(player) => {
+                let turn = self.turn(player);
+                self.core.pointer = Pointer::Up;
+                if let Some((p, m)) = self.core.pass_move(&turn) {
+                    self.core.play(p, m);
+                }
+            }
+            Input::Rules(r) => {
+                for (n, active) in self.core.rules.rule_toggles_mut() {
+                    if let Some(&a) = r.get(n) {
+                        *active = a;
+                    }
+                }
+            }
+            Input::Ruleset(r) => {
+                if let Err(e) = self.core.rules.import_ruleset(r) {
+                    warn!("Couldn't import ruleset: {}", e);
+                }
+            }
+            &Input::TakeBack(ply) => {
+                if self.core.take_back(ply) {
+                    self.over = false;
+                }
+            }
+            Input::End(_) => self.over = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::history::History;
+
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let rules = Rules::defaults();
+        let start = (rules.initial_placements(), rules.initial_game_data());
+        let game = snapshot::Snapshot {
+            board: start.0,
+            game_data: start.1,
+            history: History::new(start),
+        };
+        let mouse = |r, c, player, pressed| Input::Mouse {
+            square: (r, c),
+            player,
+            button: Button::Left,
+            pressed,
+        };
+        let recording = Recording {
+            seed: 1,
+            ruleset: rules.export_ruleset(),
+            game: snapshot::encode(&game),
+            inputs: vec![
+                // Dragged e2 to e4.
+                mouse(2, 5, 0, true),
+                mouse(4, 5, 0, false),
+                // Clicked e7, then e5.
+                Input::Settings {
+                    input_mode: InputMode::Click,
+                    auto_queen: true,
+                },
+                mouse(7, 5, 1, true),
+                mouse(7, 5, 1, false),
+                mouse(5, 5, 1, true),
+                mouse(5, 5, 1, false),
+                // Not black's turn.
+                Input::Play {
+                    m: "d7d5".to_string(),
+                    player: 1,
+                },
+                Input::Engine {
+                    m: "g1f3".to_string(),
+                    player: 0,
+                },
+                Input::TakeBack(3),
+                Input::Play {
+                    m: "d2d4".to_string(),
+                    player: 0,
+                },
+            ],
+        };
+        let (moves, fen) = replay(&recording).unwrap();
+        assert_eq!(moves, ["e2e4", "e7e5", "d2d4"]);
+        assert_eq!(
+            fen,
+            "rnbqkbnr/pppp1ppp/8/4p3/3PP3/8/PPP2PPP/RNBQKBNR b KQkq d3 0 2"
+        );
+    }
+}
@@ -1,8 +1,11 @@
 use std::{
     cmp::{max, min},
     collections::{HashMap, HashSet},
+    sync::Mutex,
 };
 
+use serde_json::Value;
+
 use crate::prelude::*;
 
 // We need to marshal Piece data from Rust to JS efficiently. We'll use a representation that can
@@ -15,12 +18,28 @@ pub struct Piece {
     pub col: u8,
     pub name: u8, // ASCII character
 }
+// The largest board the protocol can describe, with rows and columns in 4 bits (see protocol.rs).
+pub const MAX_BOARD_SIZE: usize = 15;
+
 // We want a data structure that allows us to quickly lookup what piece is on which square.
 // Here again though, we need to marshal this data to and from JS. Hence, we can't use anything
-// fancy like a HashMap. We'll represent the board as a 2x2 array of u8, where the value is the
-// piece name (ASCII char), or 0 if the square is empty. We add 1 to each dimension because we
-// index it starting with 1, in accordance with traditional chess notation.
-pub type PiecePlacements = [[u8; 8 + 1]; 8 + 1]; // TODO: don't hardcode board dimensions
+// fancy like a HashMap. We'll represent the board as a flat array of u8, row by row from a1, where
+// the value is the piece name (ASCII char), or 0 if the square is empty, so JS can read it from a
+// pointer, a length and the board's width and height. Rows and columns count from 1, in
+// accordance with traditional chess notation, and go through get and set. The array has room for
+// the largest board, so positions can be copied around freely, e.g. by the engine. Alongside the
+// pieces is a byte of flags per square, for the piece on it (see the PF_ flags below). They move
+// with the piece in make_move, so rules can ask whether a piece has moved rather than guess from
+// its square, and plugins can keep state on pieces. Positions read from FEN or set up by the rules
+// start with none.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Board {
+    width: u8,
+    height: u8,
+    squares: [u8; MAX_BOARD_SIZE * MAX_BOARD_SIZE],
+    flags: [u8; MAX_BOARD_SIZE * MAX_BOARD_SIZE],
+}
+pub type PiecePlacements = Board;
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 #[repr(C, packed)]
@@ -30,10 +49,50 @@ pub struct GameData {
     pub mask: u16,
 }
 
-const GD_NO_WHITE_KS_CASTLE: u16 = 0x01;
-const GD_NO_BLACK_KS_CASTLE: u16 = 0x02;
-const GD_NO_WHITE_QS_CASTLE: u16 = 0x04;
-const GD_NO_BLACK_QS_CASTLE: u16 = 0x08;
+pub const GD_NO_WHITE_KS_CASTLE: u16 = 0x01;
+pub const GD_NO_BLACK_KS_CASTLE: u16 = 0x02;
+pub const GD_NO_WHITE_QS_CASTLE: u16 = 0x04;
+pub const GD_NO_BLACK_QS_CASTLE: u16 = 0x08;
+// After a pawn moves two squares, its column, so the next move can capture it en passant. 0 when
+// there's no en passant capture.
+pub const GD_EN_PASSANT_SHIFT: u16 = 4;
+pub const GD_EN_PASSANT: u16 = 0xf0;
+// Marseillais chess: each side moves twice a turn, except white's first turn, which is one move to
+// balance white's head start. Set for the whole game, see Rules::initial_game_data.
+pub const GD_DOUBLE_MOVE: u16 = 0x100;
+// Turns in several phases, e.g. a move then a duck move (see Phase): how many phases a turn has,
+// less one, and a bit per phase, from GD_DUCK_PHASES_SHIFT, for the ones that are Phase::Duck.
+// Each phase is a ply. Set for the whole game from Rules::phases, see Rules::initial_game_data.
+pub const GD_PHASES_SHIFT: u16 = 9;
+pub const GD_PHASES: u16 = 0x600;
+pub const GD_DUCK_PHASES_SHIFT: u16 = 11;
+pub const GD_DUCK_PHASES: u16 = 0x7800;
+
+// The piece has moved since the game started. Castling still goes by the GD_ flags, which FEN
+// keeps.
+pub const PF_MOVED: u8 = 0x01;
+// The piece can't move, e.g. frozen by a plugin's spell. Nothing in the rules sets it.
+pub const PF_FROZEN: u8 = 0x02;
+// A counter of up to 15 for variants and plugins to use as they like, e.g. for a piece that can
+// only jump a few times.
+pub const PF_CHARGES_SHIFT: u8 = 4;
+pub const PF_CHARGES: u8 = 0xf0;
+
+// What a player does in a phase of their turn.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Phase {
+    // Moves one of their pieces.
+    Move,
+    // Moves the duck, which belongs to neither side, to any empty square, or puts it on one if
+    // it's not on the board yet, as in Duck Chess. Nothing can capture it or jump it.
+    Duck,
+}
+
+// The most phases a turn can have, as many as GD_PHASES holds.
+pub const MAX_PHASES: usize = 4;
+
+// The duck's name on the board and in FEN.
+pub const DUCK: u8 = b'*';
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum MoveType {
@@ -42,6 +101,11 @@ pub enum MoveType {
     Capture { row: u8, col: u8 },
     // Secondary is a second piece to move. In normal chess, this is only the rook during castles.
     Secondary { src: Piece, dst: Piece },
+    // Several pieces captured in one move, like a chain of jumps in draughts. One bit per square,
+    // see square_bit.
+    Captures { squares: u64 },
+    // The player passes, and nothing moves (see Rules::pass_move).
+    Pass,
 }
 
 // Represents a possible move. Note that the starting piece & square are implicitly known by the
@@ -54,15 +118,31 @@ pub struct Move {
 }
 
 pub trait SetupRuleFn = Fn() -> Vec<Piece>;
-pub trait TurnRuleFn = Fn(usize, Piece, GameData) -> bool;
+// Turn and movement rules are given the rules' zones, e.g. to let pieces move differently in the
+// other side's camp.
+pub trait TurnRuleFn = Fn(usize, Piece, GameData, &Zones) -> bool;
 // FIXME: need to be able to remove a piece on a different square than where the piece moves
 //        for en passant
-pub trait MovementRuleFn = Fn(Piece, &PiecePlacements, GameData, &mut HashSet<Move>);
-pub trait ConstraintRuleFn = Fn(Piece, &PiecePlacements, GameData) -> bool;
+pub trait MovementRuleFn = Fn(Piece, &PiecePlacements, GameData, &Zones, &mut HashSet<Move>);
+// Called with the rules, the piece, the move, and the placements before and after the move.
+pub trait ConstraintRuleFn =
+    Fn(&Rules, Piece, Move, &PiecePlacements, &PiecePlacements, GameData) -> bool;
 
+#[cfg(target_arch = "wasm32")]
 extern "C" {
-    // JS plugins
-    fn movement_plugin(piece_ptr: u32, placements_ptr: u32, retval_ptr: u32, retval_len: u32);
+    // JS plugins. Every buffer is passed as a pointer and its length in bytes; the piece is always 3
+    // bytes and the placements are width * height bytes, row by row from a1 (see Board), as are
+    // their flags.
+    fn movement_plugin(
+        piece_ptr: u32,
+        placements_ptr: u32,
+        flags_ptr: u32,
+        placements_len: u32,
+        width: u32,
+        height: u32,
+        retval_ptr: u32,
+        retval_len: u32,
+    );
 }
 
 pub struct MovementRule {
@@ -71,7 +151,36 @@ pub struct MovementRule {
     pub active: bool,
 }
 
+// Where a pawn-like piece promotes, and to what.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Promotion {
+    // White's pieces promote on reaching this rank or beyond. Black's ranks are mirrored.
+    pub rank: u8,
+    // Lowercase names of the pieces offered, the one picked when there's no choice first.
+    pub pieces: Vec<u8>,
+    // Only to pieces the player has fewer of than at the start, i.e. has lost, like in Grand Chess.
+    pub captured_only: bool,
+}
+
+pub struct ConstraintRule {
+    pub f: Box<dyn ConstraintRuleFn>,
+    pub active: bool,
+}
+
 pub struct Rules<'a> {
+    // Human readable name of this rule set, e.g. for sharing homebrew variants.
+    pub name: String,
+    // The game the rules are for, e.g. "chess" or "draughts". See Rules::profile.
+    pub profile: &'static str,
+    // Whether a player with no legal moves loses, like in draughts, rather than only when in check.
+    pub no_moves_loses: bool,
+    // Marseillais chess, see GD_DOUBLE_MOVE. Toggled like a rule, as "double-move".
+    pub double_move: bool,
+    // What each turn is made of, e.g. [Move, Duck] for Duck Chess. Just a move by default.
+    pub phases: Vec<Phase>,
+    // Whether a player can pass instead of moving, see Rules::pass_move. Toggled like a rule, as
+    // "pass".
+    pub pass: bool,
     // Key: piece ASCII code. Value: coordinates in sprite sheet.
     pub piece_name_to_offsets: HashMap<u8, (usize, usize)>,
     // Key: rule name. Value: a callable that returns some piece locations.
@@ -81,7 +190,143 @@ pub struct Rules<'a> {
     // Key: rule name. Value: a callable that returns allowed moves for a given piece.
     pub movement_rules: HashMap<&'a str, MovementRule>,
     // Key: rule name. Value: a callable that (dis)allows a move (for, leaves king in check).
-    pub move_constraint_rules: HashMap<&'a str, Box<dyn ConstraintRuleFn>>,
+    pub move_constraint_rules: HashMap<&'a str, ConstraintRule>,
+    // Key: lowercase piece name. Value: where and to what the piece promotes.
+    pub promotions: HashMap<u8, Promotion>,
+    // Named regions of the board, for rules that depend on where pieces are (see zones.rs).
+    pub zones: Zones,
+    // Seeds the random numbers, e.g. the engine's blunders, when the rules are imported, so a
+    // shared variant plays out the same. None leaves them random.
+    pub seed: Option<u64>,
+}
+
+impl Board {
+    // An empty board.
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width <= MAX_BOARD_SIZE && height <= MAX_BOARD_SIZE);
+        Board {
+            width: width as u8,
+            height: height as u8,
+            squares: [0; MAX_BOARD_SIZE * MAX_BOARD_SIZE],
+            flags: [0; MAX_BOARD_SIZE * MAX_BOARD_SIZE],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+
+    // Whether the square is on the board.
+    pub fn contains(&self, r: usize, c: usize) -> bool {
+        self.index(r, c).is_some()
+    }
+
+    fn index(&self, r: usize, c: usize) -> Option<usize> {
+        let on_board = (1..=self.height()).contains(&r) && (1..=self.width()).contains(&c);
+        on_board.then(|| (r - 1) * self.width() + c - 1)
+    }
+
+    // The piece on the square, or 0 if it's empty or off the board.
+    pub fn get(&self, r: usize, c: usize) -> u8 {
+        self.index(r, c).map_or(0, |i| self.squares[i])
+    }
+
+    pub fn set(&mut self, r: usize, c: usize, name: u8) {
+        let i = self
+            .index(r, c)
+            .unwrap_or_else(|| panic!("({}, {}) is off the board", r, c));
+        self.squares[i] = name;
+    }
+
+    // The flags of the piece on the square, 0 if it's empty or off the board.
+    pub fn flags(&self, r: usize, c: usize) -> u8 {
+        self.index(r, c).map_or(0, |i| self.flags[i])
+    }
+
+    pub fn set_flags(&mut self, r: usize, c: usize, flags: u8) {
+        let i = self
+            .index(r, c)
+            .unwrap_or_else(|| panic!("({}, {}) is off the board", r, c));
+        self.flags[i] = flags;
+    }
+
+    pub fn has_moved(&self, r: usize, c: usize) -> bool {
+        self.flags(r, c) & PF_MOVED != 0
+    }
+
+    // Copies the square's piece and flags from other.
+    fn restore(&mut self, r: usize, c: usize, other: &Board) {
+        self.set(r, c, other.get(r, c));
+        self.set_flags(r, c, other.flags(r, c));
+    }
+
+    // The squares row by row from a1, width * height of them.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.squares[..self.width() * self.height()]
+    }
+
+    // The squares' flags, laid out like as_slice.
+    pub fn flags_slice(&self) -> &[u8] {
+        &self.flags[..self.width() * self.height()]
+    }
+
+    // Each square's row, column and piece (0 if empty), row by row from a1.
+    pub fn squares(&self) -> impl Iterator<Item = (usize, usize, u8)> + '_ {
+        let width = self.width();
+        self.as_slice()
+            .iter()
+            .enumerate()
+            .map(move |(i, &n)| (i / width + 1, i % width + 1, n))
+    }
+
+    // How many of the piece are on the board.
+    pub fn count(&self, name: u8) -> usize {
+        self.as_slice().iter().filter(|&&n| n == name).count()
+    }
+}
+
+impl Default for Board {
+    // TODO: don't hardcode board dimensions
+    fn default() -> Self {
+        Board::new(8, 8)
+    }
+}
+
+impl GameData {
+    // 0 for white, 1 for black.
+    pub fn player_to_move(&self) -> usize {
+        if self.mask & GD_DOUBLE_MOVE != 0 {
+            // Black has plies 2 and 3, white 4 and 5, and so on.
+            return (self.ply as usize / 2) % 2;
+        }
+        (self.ply.saturating_sub(1) as usize / self.phases()) % 2
+    }
+
+    // How many phases a turn has, 1 unless the rules have several (see GD_PHASES).
+    pub fn phases(&self) -> usize {
+        ((self.mask & GD_PHASES) >> GD_PHASES_SHIFT) as usize + 1
+    }
+
+    // Which phase of their turn the player to move is in, counting from 0.
+    pub fn phase_index(&self) -> usize {
+        if self.mask & GD_DOUBLE_MOVE != 0 {
+            return 0;
+        }
+        self.ply.saturating_sub(1) as usize % self.phases()
+    }
+
+    pub fn phase(&self) -> Phase {
+        let duck = (self.mask & GD_DUCK_PHASES) >> GD_DUCK_PHASES_SHIFT;
+        if duck & (1 << self.phase_index()) != 0 {
+            Phase::Duck
+        } else {
+            Phase::Move
+        }
+    }
 }
 
 impl Piece {
@@ -90,10 +335,73 @@ impl Piece {
     }
 }
 
-fn is_piece_white(n: u8) -> bool {
+pub(crate) fn is_piece_white(n: u8) -> bool {
     (n as char).is_ascii_uppercase()
 }
 
+// Whether a piece of the given color can capture the piece named n. Nothing captures the duck.
+pub(crate) fn is_enemy(n: u8, white: bool) -> bool {
+    n != 0 && n != DUCK && is_piece_white(n) != white
+}
+
+// The bit for a square in MoveType::Captures.
+pub fn square_bit(r: usize, c: usize) -> u64 {
+    // TODO: get board size from rules
+    1 << ((r - 1) * 8 + c - 1)
+}
+
+// The squares in a MoveType::Captures, as (row, col).
+pub fn bit_squares(squares: u64) -> impl Iterator<Item = (usize, usize)> {
+    (0..64)
+        .filter(move |i| squares & (1 << i) != 0)
+        .map(|i| (i / 8 + 1, i % 8 + 1))
+}
+
+// The squares a piece stops on while making a move, in order, ending with the destination. Only
+// chains of jumps (MoveType::Captures) stop more than once: each jumps a piece next to it to the
+// square behind.
+pub fn move_legs(piece: Piece, m: Move) -> Vec<(usize, usize)> {
+    let dst = (m.dst.row as usize, m.dst.col as usize);
+    if let MoveType::Captures { squares } = m.typ {
+        let mut path = Vec::new();
+        if jump_path(
+            (piece.row as usize, piece.col as usize),
+            dst,
+            squares,
+            &mut path,
+        ) {
+            return path;
+        }
+    }
+    vec![dst]
+}
+
+// Finds the squares landed on jumping from from over every square in left, ending on to.
+fn jump_path(
+    from: (usize, usize),
+    to: (usize, usize),
+    left: u64,
+    path: &mut Vec<(usize, usize)>,
+) -> bool {
+    if left == 0 {
+        return from == to;
+    }
+    for (r, c) in bit_squares(left) {
+        let (dr, dc) = (r as i32 - from.0 as i32, c as i32 - from.1 as i32);
+        let (lr, lc) = (r as i32 + dr, c as i32 + dc);
+        if dr.abs() > 1 || dc.abs() > 1 || !std_in_bounds(lr, lc) {
+            continue;
+        }
+        let landing = (lr as usize, lc as usize);
+        path.push(landing);
+        if jump_path(landing, to, left & !square_bit(r, c), path) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
 impl Move {
     pub fn normal(r: usize, c: usize, name: u8, game_data: GameData) -> Self {
         Self {
@@ -107,6 +415,13 @@ impl Move {
         }
     }
 
+    pub fn is_capture(&self) -> bool {
+        matches!(
+            self.typ,
+            MoveType::Capture { .. } | MoveType::Captures { .. }
+        )
+    }
+
     pub fn capture(r: usize, c: usize, name: u8, game_data: GameData) -> Self {
         Self {
             dst: Piece {
@@ -144,8 +459,8 @@ fn add_linear_moves(
                 break;
             }
             let (nr, nc) = (nr as usize, nc as usize);
-            if pp[nr][nc] != 0 {
-                if is_piece_white(pp[nr][nc]) != is_white {
+            if pp.get(nr, nc) != 0 {
+                if is_enemy(pp.get(nr, nc), is_white) {
                     hs.insert(Move::capture(nr, nc, p.name, game_data));
                 }
                 break;
@@ -173,8 +488,8 @@ fn add_knight_moves(p: Piece, pp: &PiecePlacements, hs: &mut HashSet<Move>, gd:
             continue;
         }
         let (nr, nc) = (nr as usize, nc as usize);
-        if pp[nr][nc] != 0 {
-            if is_piece_white(pp[nr][nc]) != is_white {
+        if pp.get(nr, nc) != 0 {
+            if is_enemy(pp.get(nr, nc), is_white) {
                 hs.insert(Move::capture(nr, nc, p.name, gd));
             }
         } else {
@@ -183,28 +498,73 @@ fn add_knight_moves(p: Piece, pp: &PiecePlacements, hs: &mut HashSet<Move>, gd:
     }
 }
 
+// Promotion is up to Rules::promote, which sees the rules' promotions.
 fn add_pawn_move(p: Piece, r: usize, c: usize, gd: GameData, hs: &mut HashSet<Move>, is_cap: bool) {
-    let white = p.is_white();
     let move_ctor = if is_cap { Move::capture } else { Move::normal };
-    if 2 <= r && r <= 7 {
-        hs.insert(move_ctor(r, c, p.name, gd));
-    } else if white && r == 8 {
-        // Promote to Q only for now
-        hs.insert(move_ctor(r, c, 'Q' as u8, gd));
-    } else if !white && r == 1 {
-        hs.insert(move_ctor(r, c, 'q' as u8, gd));
-    }
+    hs.insert(move_ctor(r, c, p.name, gd));
 }
 
 fn add_pawn_captures(p: Piece, pp: &PiecePlacements, hs: &mut HashSet<Move>, gd: GameData) {
     let dir: i8 = if p.is_white() { 1 } else { -1 };
     for i in [-1, 1] {
-        let r = (p.row as i8 + dir) as usize;
-        let c = (p.col as i8 + i) as usize;
-        if 1 <= c && c <= 8 && pp[r][c] != 0 && is_piece_white(pp[r][c]) != p.is_white() {
+        let r = p.row as i8 + dir;
+        let c = p.col as i8 + i;
+        // A pawn can be on the last rank in custom setups.
+        if !std_in_bounds(r as i32, c as i32) {
+            continue;
+        }
+        let (r, c) = (r as usize, c as usize);
+        if is_enemy(pp.get(r, c), p.is_white()) {
             add_pawn_move(p, r, c, gd, hs, true);
         }
     }
+    // En passant: the pawn next to this one just moved two squares past it.
+    let ep = ((gd.mask & GD_EN_PASSANT) >> GD_EN_PASSANT_SHIFT) as u8;
+    let ep_row = if p.is_white() { 5 } else { 4 };
+    let enemy_pawn = if p.is_white() { b'p' } else { b'P' };
+    if ep != 0
+        && p.row == ep_row
+        && p.col.abs_diff(ep) == 1
+        && pp.get(ep_row as usize, ep as usize) == enemy_pawn
+    {
+        hs.insert(Move {
+            dst: Piece {
+                row: (ep_row as i8 + dir) as u8,
+                col: ep,
+                name: p.name,
+            },
+            typ: MoveType::Capture {
+                row: ep_row,
+                col: ep,
+            },
+            game_data: gd,
+        });
+    }
+}
+
+// Updates the game data for things any move can change. En passant is only allowed right after
+// the pawn's two square move, so every other move clears it. Capturing a rook that hasn't moved
+// takes away that side's castling.
+fn update_game_data(p: Piece, mut m: Move) -> Move {
+    // A pawn that just stepped two squares can still be taken en passant after the duck moves.
+    if p.name == DUCK {
+        return m;
+    }
+    m.game_data.mask &= !GD_EN_PASSANT;
+    // Straight ahead, since pawn-like pieces in other games (e.g. draughts) can jump diagonally.
+    if p.name.eq_ignore_ascii_case(&b'p') && p.row.abs_diff(m.dst.row) == 2 && p.col == m.dst.col {
+        m.game_data.mask |= (p.col as u16) << GD_EN_PASSANT_SHIFT;
+    }
+    if let MoveType::Capture { row, col } = m.typ {
+        m.game_data.mask |= match (row, col) {
+            (1, 1) => GD_NO_WHITE_QS_CASTLE,
+            (1, 8) => GD_NO_WHITE_KS_CASTLE,
+            (8, 1) => GD_NO_BLACK_QS_CASTLE,
+            (8, 8) => GD_NO_BLACK_KS_CASTLE,
+            _ => 0,
+        };
+    }
+    m
 }
 
 fn piece_attacked(p: Piece, pp: &PiecePlacements, game_data: GameData) -> bool {
@@ -304,7 +664,7 @@ fn piece_attacked(p: Piece, pp: &PiecePlacements, game_data: GameData) -> bool {
         f(&mut hs);
         for m in hs.iter() {
             if let MoveType::Capture { row, col } = m.typ {
-                let n = (pp[row as usize][col as usize] as char).to_ascii_uppercase();
+                let n = (pp.get(row as usize, col as usize) as char).to_ascii_uppercase();
                 for piece in pieces.chars() {
                     if n == piece {
                         return true;
@@ -356,7 +716,7 @@ fn add_castle(
     // king has moved, no-castle flags would be set. But adding this check
     // makes the tests more intuitive to write because we don't have to set
     // no-castle flags on every test that involves the king.
-    if pp[row][ks] != p.name || pp[row][rook_col] != rn {
+    if pp.get(row, ks) != p.name || pp.get(row, rook_col) != rn {
         return;
     }
 
@@ -375,7 +735,7 @@ fn add_castle(
 
     // Make sure there's nothing between king and rook.
     for col in min(rook_col, ks) + 1..=max(rook_col, ks) - 1 {
-        if pp[row][col] != 0
+        if pp.get(row, col) != 0
             || piece_attacked(
                 Piece {
                     row: row as u8,
@@ -389,7 +749,6 @@ fn add_castle(
             return;
         }
     }
-    // FIXME: Make sure the king isn't in check, or castling through check.
     hs.insert(Move {
         dst: Piece {
             row: row as u8,
@@ -417,28 +776,53 @@ fn add_castle(
 
 fn find_piece(name: char, pp: &PiecePlacements) -> Option<(u8, u8)> {
     let name = name as u8;
-    for r in 1..=8 {
-        // TODO: get board size from rules
-        for c in 1..=8 {
-            if pp[r][c] == name {
-                return Some((r as u8, c as u8));
-            }
-        }
-    }
-    None
+    pp.squares()
+        .find(|&(_, _, n)| n == name)
+        .map(|(r, c, _)| (r as u8, c as u8))
 }
 
 impl<'a> Rules<'a> {
     pub fn defaults() -> Self {
         Self {
+            name: "Standard".to_string(),
+            profile: "chess",
+            no_moves_loses: false,
+            double_move: false,
+            phases: vec![Phase::Move],
+            pass: false,
             piece_name_to_offsets: Self::default_piece_name_to_offsets(),
             setup_rules: Self::default_setup_rules(),
             turn_rules: Self::default_turn_rules(),
             movement_rules: Self::default_movement_rules(),
             move_constraint_rules: Self::default_move_constraint_rules(),
+            promotions: Self::default_promotions(),
+            zones: Zones::defaults(),
+            seed: None,
         }
     }
 
+    // The default rules of a game, by its profile name. None if it isn't one we know.
+    pub fn profile(name: &str) -> Option<Self> {
+        match name {
+            "chess" => Some(Self::defaults()),
+            "draughts" => Some(Self::draughts()),
+            _ => None,
+        }
+    }
+
+    pub fn default_promotions() -> HashMap<u8, Promotion> {
+        let mut hm = HashMap::new();
+        hm.insert(
+            b'p',
+            Promotion {
+                rank: 8,
+                pieces: b"qrbn".to_vec(),
+                captured_only: false,
+            },
+        );
+        hm
+    }
+
     pub fn default_piece_name_to_offsets() -> HashMap<u8, (usize, usize)> {
         let mut hm = HashMap::new();
         let pieces = ['k', 'q', 'b', 'n', 'r', 'p'];
@@ -596,8 +980,15 @@ impl<'a> Rules<'a> {
         let mut hm = HashMap::<&'a str, Box<dyn TurnRuleFn>>::new();
         hm.insert(
             "player-order",
-            Box::new(|player: usize, p: Piece, gd: GameData| {
-                p.is_white() == (gd.ply % 2 == 1) && p.is_white() == (player == 0)
+            Box::new(|player: usize, p: Piece, gd: GameData, _: &Zones| {
+                // In a duck phase, the player to move moves the duck and nothing else.
+                if (p.name == DUCK) != (gd.phase() == Phase::Duck) {
+                    return false;
+                }
+                if p.name == DUCK {
+                    return gd.player_to_move() == player;
+                }
+                p.is_white() == (gd.player_to_move() == 0) && p.is_white() == (player == 0)
             }),
         );
         hm
@@ -611,16 +1002,25 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('p'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         let dir: i32 = if p.is_white() { 1 } else { -1 };
-                        let max = if (dir == 1 && p.row == 2) || (dir == -1 && p.row == 7) {
+                        let home = (dir == 1 && p.row == 2) || (dir == -1 && p.row == 7);
+                        let max = if home && !pp.has_moved(p.row as usize, p.col as usize) {
                             2
                         } else {
                             1
                         };
                         for i in 1..=max {
-                            let (r, c) = ((p.row as i32 + dir * i) as usize, p.col as usize);
-                            if pp[r][c] != 0 {
+                            let r = p.row as i32 + dir * i;
+                            if !std_in_bounds(r, p.col as i32) {
+                                return;
+                            }
+                            let (r, c) = (r as usize, p.col as usize);
+                            if pp.get(r, c) != 0 {
                                 return;
                             }
                             add_pawn_move(p, r, c, gd, hs, false);
@@ -635,7 +1035,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('p'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_pawn_captures(p, pp, hs, gd);
                     },
                 ),
@@ -647,7 +1051,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('n'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_knight_moves(p, pp, hs, gd);
                     },
                 ),
@@ -659,7 +1067,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('b'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_linear_moves(p, pp, hs, &DIAGONALS, 8, gd);
                     },
                 ),
@@ -671,7 +1083,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('r'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         let gd = match (p.row, p.col) {
                             (1, 1) => GameData {
                                 mask: gd.mask | GD_NO_WHITE_QS_CASTLE,
@@ -702,7 +1118,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('q'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_linear_moves(p, pp, hs, &AXES, 8, gd);
                         add_linear_moves(p, pp, hs, &DIAGONALS, 8, gd);
                     },
@@ -715,7 +1135,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('k'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         let gd = if p.is_white() {
                             GameData {
                                 mask: gd.mask | GD_NO_WHITE_KS_CASTLE | GD_NO_WHITE_QS_CASTLE,
@@ -739,7 +1163,11 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('k'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_castle(p, pp, gd, hs, 8);
                     },
                 ),
@@ -751,12 +1179,43 @@ impl<'a> Rules<'a> {
                 active: true,
                 piece_constrait: Some('k'),
                 f: Box::new(
-                    |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
                         add_castle(p, pp, gd, hs, 1);
                     },
                 ),
             },
         );
+        // The duck, in rules with duck phases: to any empty square, or onto the one it's put on.
+        hm.insert(
+            "duck",
+            MovementRule {
+                active: true,
+                piece_constrait: Some(DUCK as char),
+                f: Box::new(
+                    |p: Piece,
+                     pp: &PiecePlacements,
+                     gd: GameData,
+                     _: &Zones,
+                     hs: &mut HashSet<Move>| {
+                        if pp.get(p.row as usize, p.col as usize) != DUCK {
+                            hs.insert(Move::normal(p.row as usize, p.col as usize, DUCK, gd));
+                            return;
+                        }
+                        for (r, c, n) in pp.squares() {
+                            if n == 0 {
+                                hs.insert(Move::normal(r, c, DUCK, gd));
+                            }
+                        }
+                    },
+                ),
+            },
+        );
+        // JS plugins only exist in the browser.
+        #[cfg(target_arch = "wasm32")]
         if !cfg!(test) {
             hm.insert(
                 "js-plugin",
@@ -764,7 +1223,11 @@ impl<'a> Rules<'a> {
                     active: true,
                     piece_constrait: None,
                     f: Box::new(
-                        |p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>| {
+                        |p: Piece,
+                         pp: &PiecePlacements,
+                         gd: GameData,
+                         _: &Zones,
+                         hs: &mut HashSet<Move>| {
                             plugin_movement_rule(p, pp, gd, hs)
                         },
                     ),
@@ -774,44 +1237,357 @@ impl<'a> Rules<'a> {
         hm
     }
 
-    fn default_move_constraint_rules() -> HashMap<&'a str, Box<dyn ConstraintRuleFn>> {
-        let mut hm = HashMap::<&'a str, Box<dyn ConstraintRuleFn>>::new();
+    // The classical constraints. Only resolve-check is on in standard chess; turning it off lets
+    // players move into check, e.g. for teaching.
+    pub fn default_move_constraint_rules() -> HashMap<&'a str, ConstraintRule> {
+        let mut hm = HashMap::<&'a str, ConstraintRule>::new();
         hm.insert(
             "resolve-check",
-            Box::new(|p: Piece, pp: &PiecePlacements, gd: GameData| {
-                let king = if p.is_white() { 'K' } else { 'k' };
-                if let Some((r, c)) = find_piece(king, pp) {
-                    let kp = Piece {
-                        row: r,
-                        col: c,
-                        name: king as u8,
+            ConstraintRule {
+                active: true,
+                f: Box::new(|_, p: Piece, _, _, pp: &PiecePlacements, gd: GameData| {
+                    // The duck is moved by the player to move, whose king it mustn't expose.
+                    let white = if p.name == DUCK {
+                        gd.player_to_move() == 0
+                    } else {
+                        p.is_white()
                     };
-                    return !piece_attacked(kp, pp, gd);
-                }
-                true
-            }),
+                    let king = if white { 'K' } else { 'k' };
+                    if let Some((r, c)) = find_piece(king, pp) {
+                        let kp = Piece {
+                            row: r,
+                            col: c,
+                            name: king as u8,
+                        };
+                        return !piece_attacked(kp, pp, gd);
+                    }
+                    true
+                }),
+            },
+        );
+        // Implied by resolve-check, but still applies with it off.
+        hm.insert(
+            "kings-may-not-touch",
+            ConstraintRule {
+                active: false,
+                f: Box::new(|_, _, _, _, pp: &PiecePlacements, _| {
+                    match (find_piece('K', pp), find_piece('k', pp)) {
+                        (Some((wr, wc)), Some((br, bc))) => {
+                            wr.abs_diff(br) > 1 || wc.abs_diff(bc) > 1
+                        }
+                        _ => true,
+                    }
+                }),
+            },
+        );
+        // Kings can't leave their own half of the board, the white-camp and black-camp zones.
+        hm.insert(
+            "kings-stay-in-camp",
+            ConstraintRule {
+                active: false,
+                f: Box::new(|rules: &Rules, p: Piece, m: Move, _, _, _| {
+                    let camp = if p.is_white() {
+                        "white-camp"
+                    } else {
+                        "black-camp"
+                    };
+                    !p.name.eq_ignore_ascii_case(&b'k')
+                        || rules
+                            .zones
+                            .contains(camp, m.dst.row as usize, m.dst.col as usize)
+                }),
+            },
+        );
+        // As in Antichess: a player who can capture has to.
+        hm.insert(
+            "forced-capture",
+            ConstraintRule {
+                active: false,
+                f: Box::new(
+                    |rules: &Rules, p: Piece, m: Move, pp: &PiecePlacements, _, gd: GameData| {
+                        p.name == DUCK || m.is_capture() || !rules.can_capture(p.is_white(), pp, gd)
+                    },
+                ),
+            },
         );
         hm
     }
 
+    // The names and toggles of the movement and constraint rules, and the turn order, e.g. for
+    // rule sets.
+    pub fn rule_toggles(&self) -> impl Iterator<Item = (&'a str, bool)> + '_ {
+        let movement = self.movement_rules.iter().map(|(&n, r)| (n, r.active));
+        let constraint = self
+            .move_constraint_rules
+            .iter()
+            .map(|(&n, r)| (n, r.active));
+        let turns = [("double-move", self.double_move), ("pass", self.pass)].into_iter();
+        movement.chain(constraint).chain(turns)
+    }
+
+    pub fn rule_toggles_mut(&mut self) -> impl Iterator<Item = (&'a str, &mut bool)> {
+        let movement = self
+            .movement_rules
+            .iter_mut()
+            .map(|(&n, r)| (n, &mut r.active));
+        let constraint = self
+            .move_constraint_rules
+            .iter_mut()
+            .map(|(&n, r)| (n, &mut r.active));
+        let turns = [
+            ("double-move", &mut self.double_move),
+            ("pass", &mut self.pass),
+        ]
+        .into_iter();
+        movement.chain(constraint).chain(turns)
+    }
+
+    // The game data a game starts with.
+    pub fn initial_game_data(&self) -> GameData {
+        if self.double_move {
+            return GameData {
+                ply: 1,
+                mask: GD_DOUBLE_MOVE,
+            };
+        }
+        let phases = self.phases.len().clamp(1, MAX_PHASES);
+        let mut mask = ((phases - 1) as u16) << GD_PHASES_SHIFT;
+        for (i, &phase) in self.phases.iter().take(phases).enumerate() {
+            if phase == Phase::Duck {
+                mask |= 1 << (GD_DUCK_PHASES_SHIFT + i as u16);
+            }
+        }
+        GameData { ply: 1, mask }
+    }
+
+    // The piece the player to move can put on an empty square, rather than move one that's on the
+    // board: the duck, in a duck phase before it's been placed. Such a move's piece is on the
+    // square it's put on, and it moves there.
+    pub fn placement(&self, piece_placements: &PiecePlacements, gd: GameData) -> Option<u8> {
+        (gd.phase() == Phase::Duck && piece_placements.count(DUCK) == 0).then_some(DUCK)
+    }
+
+    // The player's pass, if the rules let them pass now, which is only in a move phase. It's made
+    // by one of their pieces, their king if they have one, which stays where it is. The constraint
+    // rules apply, so e.g. with resolve-check a player in check can't pass.
+    pub fn pass_move(
+        &self,
+        player: usize,
+        piece_placements: &PiecePlacements,
+        gd: GameData,
+    ) -> Option<(Piece, Move)> {
+        if !self.pass || gd.phase() != Phase::Move {
+            return None;
+        }
+        let own: Vec<Piece> = piece_placements
+            .squares()
+            .filter(|&(_, _, n)| n != 0 && n != DUCK && is_piece_white(n) == (player == 0))
+            .map(|(r, c, name)| Piece {
+                row: r as u8,
+                col: c as u8,
+                name,
+            })
+            .collect();
+        let piece = *own
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(&b'k'))
+            .or(own.first())?;
+        if !self.is_turn(player, piece, gd) {
+            return None;
+        }
+        let pass = update_game_data(
+            piece,
+            Move {
+                dst: piece,
+                typ: MoveType::Pass,
+                game_data: gd,
+            },
+        );
+        let allowed = self.constrain_moves(&HashSet::from([pass]), piece, piece_placements, gd);
+        allowed.into_iter().next().map(|m| (piece, m))
+    }
+
+    // The position at the start of a game, from the setup rules. Pieces they put off the board
+    // are left out (check_ruleset reports them).
+    pub fn initial_placements(&self) -> PiecePlacements {
+        let mut pp = PiecePlacements::default();
+        for r in self.setup_rules.values() {
+            for p in r() {
+                if pp.contains(p.row as usize, p.col as usize) {
+                    pp.set(p.row as usize, p.col as usize, p.name);
+                }
+            }
+        }
+        pp
+    }
+
+    pub fn is_turn(&self, player: usize, piece: Piece, gd: GameData) -> bool {
+        self.turn_rules
+            .values()
+            .any(|r| r(player, piece, gd, &self.zones))
+    }
+
+    // All moves the player can make, with the piece that makes each move.
+    pub fn legal_moves(
+        &self,
+        player: usize,
+        piece_placements: &PiecePlacements,
+        gd: GameData,
+    ) -> Vec<(Piece, Move)> {
+        let mut moves = Vec::new();
+        let placement = self.placement(piece_placements, gd);
+        for (r, c, name) in piece_placements.squares() {
+            let name = match (name, placement) {
+                (0, Some(placed)) => placed,
+                (0, None) => continue,
+                _ => name,
+            };
+            let piece = Piece {
+                row: r as u8,
+                col: c as u8,
+                name,
+            };
+            if !self.is_turn(player, piece, gd) {
+                continue;
+            }
+            for m in self.allowed_moves(piece, piece_placements, gd) {
+                moves.push((piece, m));
+            }
+        }
+        moves.extend(self.pass_move(player, piece_placements, gd));
+        moves
+    }
+
+    pub fn in_check(white: bool, piece_placements: &PiecePlacements, gd: GameData) -> bool {
+        let king = if white { 'K' } else { 'k' };
+        match find_piece(king, piece_placements) {
+            Some((row, col)) => piece_attacked(
+                Piece {
+                    row,
+                    col,
+                    name: king as u8,
+                },
+                piece_placements,
+                gd,
+            ),
+            None => false,
+        }
+    }
+
+    // Whether the player to move, having no legal moves, has lost rather than drawn.
+    pub fn lost_without_moves(
+        &self,
+        white: bool,
+        piece_placements: &PiecePlacements,
+        gd: GameData,
+    ) -> bool {
+        self.no_moves_loses || Rules::in_check(white, piece_placements, gd)
+    }
+
+    // Whether neither side has the pieces to mate: kings with at most one knight or bishop between
+    // them, or any number of bishops all on squares of the same color.
+    pub fn insufficient_material(piece_placements: &PiecePlacements) -> bool {
+        let mut knights = 0;
+        let mut bishops = [0; 2];
+        for (r, c, n) in piece_placements.squares() {
+            match n.to_ascii_lowercase() {
+                0 | b'k' => {}
+                b'n' => knights += 1,
+                b'b' => bishops[(r + c) % 2] += 1,
+                _ => return false,
+            }
+        }
+        match knights {
+            0 => bishops[0] == 0 || bishops[1] == 0,
+            1 => bishops == [0, 0],
+            _ => false,
+        }
+    }
+
+    // A Zobrist hash of the position: the pieces and their flags, whose turn it is and the
+    // castling rights, e.g. for spotting repeated positions. PF_MOVED is left out, or a knight
+    // going out and back would never repeat the position.
+    pub fn position_hash(piece_placements: &PiecePlacements, gd: GameData) -> u64 {
+        let mut h = zobrist_key(gd.player_to_move() as u64) ^ zobrist_key(0x100 | gd.mask as u64);
+        for (r, c, name) in piece_placements.squares() {
+            if name != 0 {
+                h ^= zobrist_key(0x10000 | ((r * 9 + c) as u64) << 8 | name as u64);
+                let flags = piece_placements.flags(r, c) & !PF_MOVED;
+                if flags != 0 {
+                    h ^= zobrist_key(0x1000000 | ((r * 9 + c) as u64) << 8 | flags as u64);
+                }
+            }
+        }
+        h
+    }
+
     pub fn make_move(piece: Piece, m: Move, piece_placements: &mut PiecePlacements) {
+        if m.typ == MoveType::Pass {
+            return;
+        }
         let (sr, sc) = (piece.row as usize, piece.col as usize);
         let (r, c) = (m.dst.row as usize, m.dst.col as usize);
-        piece_placements[sr][sc] = 0;
-        piece_placements[r][c] = m.dst.name;
+        let flags = piece_placements.flags(sr, sc) | PF_MOVED;
+        piece_placements.set(sr, sc, 0);
+        piece_placements.set_flags(sr, sc, 0);
+        piece_placements.set(r, c, m.dst.name);
+        piece_placements.set_flags(r, c, flags);
         match m.typ {
             MoveType::Capture { row: cr, col: cc } => {
                 if (cr as usize, cc as usize) != (r, c) {
-                    piece_placements[cr as usize][cc as usize] = 0;
+                    piece_placements.set(cr as usize, cc as usize, 0);
+                    piece_placements.set_flags(cr as usize, cc as usize, 0);
                 }
             }
             MoveType::Secondary { src: ss, dst: sd } => {
-                if (ss.row as usize, ss.col as usize) != (r, c) {
-                    piece_placements[ss.row as usize][ss.col as usize] = 0;
+                let (ssr, ssc) = (ss.row as usize, ss.col as usize);
+                let flags = piece_placements.flags(ssr, ssc) | PF_MOVED;
+                if (ssr, ssc) != (r, c) {
+                    piece_placements.set(ssr, ssc, 0);
+                    piece_placements.set_flags(ssr, ssc, 0);
+                }
+                piece_placements.set(sd.row as usize, sd.col as usize, sd.name);
+                piece_placements.set_flags(sd.row as usize, sd.col as usize, flags);
+            }
+            MoveType::Captures { squares } => {
+                for (cr, cc) in bit_squares(squares) {
+                    piece_placements.set(cr, cc, 0);
+                    piece_placements.set_flags(cr, cc, 0);
+                }
+            }
+            MoveType::Normal | MoveType::Pass => {}
+        }
+    }
+
+    // Undoes make_move, given the placements from before the move. Only the squares the move
+    // touched are restored, which is cheaper than copying the whole board back.
+    pub fn unmake_move(
+        piece: Piece,
+        m: Move,
+        before: &PiecePlacements,
+        piece_placements: &mut PiecePlacements,
+    ) {
+        if m.typ == MoveType::Pass {
+            return;
+        }
+        let (sr, sc) = (piece.row as usize, piece.col as usize);
+        let (r, c) = (m.dst.row as usize, m.dst.col as usize);
+        piece_placements.restore(sr, sc, before);
+        piece_placements.restore(r, c, before);
+        match m.typ {
+            MoveType::Capture { row, col } => {
+                piece_placements.restore(row as usize, col as usize, before);
+            }
+            MoveType::Secondary { src, dst } => {
+                piece_placements.restore(src.row as usize, src.col as usize, before);
+                piece_placements.restore(dst.row as usize, dst.col as usize, before);
+            }
+            MoveType::Captures { squares } => {
+                for (cr, cc) in bit_squares(squares) {
+                    piece_placements.restore(cr, cc, before);
                 }
-                piece_placements[sd.row as usize][sd.col as usize] = sd.name;
             }
-            MoveType::Normal => {}
+            MoveType::Normal | MoveType::Pass => {}
         }
     }
 
@@ -820,15 +1596,108 @@ impl<'a> Rules<'a> {
         piece: Piece,
         piece_placements: &PiecePlacements,
         gd: GameData,
+    ) -> HashSet<Move> {
+        let allowed = self.unconstrained_moves(piece, piece_placements, gd);
+        self.constrain_moves(&allowed, piece, piece_placements, gd)
+    }
+
+    // The moves the movement rules allow, before the constraint rules.
+    fn unconstrained_moves(
+        &self,
+        piece: Piece,
+        piece_placements: &PiecePlacements,
+        gd: GameData,
     ) -> HashSet<Move> {
         let mut allowed: HashSet<Move> = HashSet::new();
+        if piece_placements.flags(piece.row as usize, piece.col as usize) & PF_FROZEN != 0 {
+            return allowed;
+        }
         for (_, r) in self.movement_rules.iter().filter(|(_, r)| r.active) {
             if let Some(p) = r.piece_constrait && p.to_ascii_lowercase() != (piece.name as char).to_ascii_lowercase() {
                 continue;
             }
-            (r.f)(piece, piece_placements, gd, &mut allowed);
+            (r.f)(piece, piece_placements, gd, &self.zones, &mut allowed);
+        }
+        self.promote(piece, piece_placements, allowed)
+            .into_iter()
+            .map(|m| update_game_data(piece, m))
+            .collect()
+    }
+
+    // Whether any of the player's pieces can capture, ignoring the constraint rules so they can
+    // use this.
+    fn can_capture(&self, white: bool, piece_placements: &PiecePlacements, gd: GameData) -> bool {
+        piece_placements.squares().any(|(r, c, name)| {
+            if name == 0 || is_piece_white(name) != white {
+                return false;
+            }
+            let piece = Piece {
+                row: r as u8,
+                col: c as u8,
+                name,
+            };
+            self.unconstrained_moves(piece, piece_placements, gd)
+                .iter()
+                .any(|m| m.is_capture())
+        })
+    }
+
+    // Replaces each move of a pawn-like piece onto its promotion ranks with one move per piece it
+    // can promote to. With nothing to promote to, it can't move there.
+    fn promote(&self, p: Piece, pp: &PiecePlacements, hs: HashSet<Move>) -> HashSet<Move> {
+        let promotion = match self.promotions.get(&p.name.to_ascii_lowercase()) {
+            Some(promotion) => promotion,
+            None => return hs,
+        };
+        let white = p.is_white();
+        // TODO: get board size from rules
+        let reached = |m: &Move| {
+            m.dst.name == p.name
+                && if white {
+                    m.dst.row >= promotion.rank
+                } else {
+                    m.dst.row <= 8 + 1 - promotion.rank
+                }
+        };
+        if !hs.iter().any(reached) {
+            return hs;
+        }
+        let mut offered: Vec<u8> = promotion
+            .pieces
+            .iter()
+            .map(|&n| if white { n.to_ascii_uppercase() } else { n })
+            .collect();
+        if promotion.captured_only {
+            let initial = self.initial_placements();
+            offered.retain(|&n| pp.count(n) < initial.count(n));
+        }
+        let mut promoted = HashSet::new();
+        for m in hs {
+            if !reached(&m) {
+                promoted.insert(m);
+                continue;
+            }
+            for &name in offered.iter() {
+                promoted.insert(Move {
+                    dst: Piece { name, ..m.dst },
+                    ..m
+                });
+            }
         }
-        self.constrain_moves(&allowed, piece, piece_placements, gd)
+        promoted
+    }
+
+    // Where promoting piece to the piece named promoted comes in its promotion's list, for picking
+    // a promotion when the player didn't say: the lowest is the default.
+    pub fn promotion_order(&self, piece: Piece, promoted: u8) -> usize {
+        self.promotions
+            .get(&piece.name.to_ascii_lowercase())
+            .and_then(|p| {
+                p.pieces
+                    .iter()
+                    .position(|&n| n == promoted.to_ascii_lowercase())
+            })
+            .unwrap_or(0)
     }
 
     fn constrain_moves(
@@ -839,65 +1708,182 @@ impl<'a> Rules<'a> {
         gd: GameData,
     ) -> HashSet<Move> {
         let mut post_pp = pp.clone();
-        let (sr, sc) = (p.row as usize, p.col as usize);
         hs.iter()
             .filter(|&&m| {
                 let mut allow = true;
-                let (dr, dc) = (m.dst.row as usize, m.dst.col as usize);
                 // Make the move
                 Rules::make_move(p, m, &mut post_pp);
-                for (_, r) in self.move_constraint_rules.iter() {
-                    if !r(p, &post_pp, gd) {
+                for r in self.move_constraint_rules.values().filter(|r| r.active) {
+                    if !(r.f)(self, p, m, pp, &post_pp, gd) {
                         allow = false;
                         break;
                     }
                 }
-                // Reset the board
-                post_pp[sr][sc] = pp[sr][sc];
-                post_pp[dr][dc] = pp[dr][dc];
-                if let MoveType::Capture { row, col } = m.typ {
-                    let (cr, cc) = (row as usize, col as usize);
-                    post_pp[cr][cc] = pp[cr][cc];
-                } else if let MoveType::Secondary { src, dst } = m.typ {
-                    let (ssr, ssc) = (src.row as usize, src.col as usize);
-                    let (sdr, sdc) = (dst.row as usize, dst.col as usize);
-                    post_pp[ssr][ssc] = pp[ssr][ssc];
-                    post_pp[sdr][sdc] = pp[sdr][sdc];
-                }
+                Rules::unmake_move(p, m, pp, &mut post_pp);
                 allow
             })
             .copied()
             .collect()
     }
+
+    // Every move the piece's movement rules come up with, with which rules came up with it and
+    // which constraint rules reject it, for working out why a rule spec or plugin doesn't do what
+    // it should (see the rule debug overlay in main.rs). The moves allowed_moves returns are the
+    // ones nothing rejects.
+    pub fn explain_moves(
+        &self,
+        piece: Piece,
+        piece_placements: &PiecePlacements,
+        gd: GameData,
+    ) -> Vec<Candidate<'a>> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        if piece_placements.flags(piece.row as usize, piece.col as usize) & PF_FROZEN != 0 {
+            return candidates;
+        }
+        let mut movement: Vec<_> = self
+            .movement_rules
+            .iter()
+            .filter(|(_, r)| r.active)
+            .collect();
+        movement.sort_by_key(|&(&name, _)| name);
+        for (&name, r) in movement {
+            if let Some(p) = r.piece_constrait
+                && !p.eq_ignore_ascii_case(&(piece.name as char))
+            {
+                continue;
+            }
+            let mut moves = HashSet::new();
+            (r.f)(piece, piece_placements, gd, &self.zones, &mut moves);
+            for m in self.promote(piece, piece_placements, moves) {
+                let m = update_game_data(piece, m);
+                match candidates.iter_mut().find(|c| c.m == m) {
+                    Some(c) => c.produced_by.push(name),
+                    None => candidates.push(Candidate {
+                        m,
+                        produced_by: vec![name],
+                        rejected_by: Vec::new(),
+                    }),
+                }
+            }
+        }
+        let mut constraints: Vec<_> = self
+            .move_constraint_rules
+            .iter()
+            .filter(|(_, r)| r.active)
+            .collect();
+        constraints.sort_by_key(|&(&name, _)| name);
+        let mut post_pp = *piece_placements;
+        for c in candidates.iter_mut() {
+            Rules::make_move(piece, c.m, &mut post_pp);
+            for &(&name, r) in constraints.iter() {
+                if !(r.f)(self, piece, c.m, piece_placements, &post_pp, gd) {
+                    c.rejected_by.push(name);
+                }
+            }
+            Rules::unmake_move(piece, c.m, piece_placements, &mut post_pp);
+        }
+        candidates.sort_by_key(|c| (c.m.dst.row, c.m.dst.col, c.m.dst.name));
+        candidates
+    }
+}
+
+// A move explain_moves found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate<'a> {
+    pub m: Move,
+    // The movement rules that came up with it, by name.
+    pub produced_by: Vec<&'a str>,
+    // The constraint rules that reject it. It's allowed if there are none.
+    pub rejected_by: Vec<&'a str>,
 }
 
-fn std_in_bounds(r: i32, c: i32) -> bool {
+// A pseudo-random number for each thing position_hash hashes (splitmix64).
+fn zobrist_key(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+pub(crate) fn std_in_bounds(r: i32, c: i32) -> bool {
     // TODO: Get bounds from rules
     1 <= r && r <= 8 && 1 <= c && c <= 8
 }
 
+// The version of the interface between the game and JS plugins: how hooks are called and what
+// they return. Bump it whenever that changes, so pages written for another version have their
+// plugins turned off instead of read wrong. 2: movement plugins get the board's dimensions. 3: and
+// the squares' flags.
+pub const PLUGIN_ABI_VERSION: u32 = 3;
+
+// The hooks JS plugins can implement.
+pub const PLUGIN_HOOKS: [&str; 1] = ["movement"];
+
+// The hooks the page implements, from its handshake. None until then.
+static ENABLED_PLUGIN_HOOKS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+// Enables the plugin hooks the page declared it implements, as JSON:
+//   {"abi_version": 2, "hooks": ["movement"]}
+// Returns the hooks enabled and the ones that were ignored since the game doesn't know them. If
+// the page's version isn't the game's, every hook is turned off and the error says so.
+pub fn enable_plugins(declaration: &str) -> Result<(Vec<&'static str>, Vec<String>), String> {
+    let mut enabled = ENABLED_PLUGIN_HOOKS.lock().unwrap();
+    enabled.clear();
+    let v: Value = serde_json::from_str(declaration)
+        .map_err(|e| format!("invalid plugin declaration: {}", e))?;
+    let version = v["abi_version"]
+        .as_u64()
+        .ok_or("the plugin declaration has no abi_version")?;
+    if version != PLUGIN_ABI_VERSION as u64 {
+        return Err(format!(
+            "the page's plugins are for ABI version {}, but the game implements version {}",
+            version, PLUGIN_ABI_VERSION
+        ));
+    }
+    let mut ignored = Vec::new();
+    for hook in v["hooks"].as_array().into_iter().flatten() {
+        match PLUGIN_HOOKS.iter().find(|&&h| Some(h) == hook.as_str()) {
+            Some(&h) => enabled.push(h),
+            None => ignored.push(hook.to_string()),
+        }
+    }
+    Ok((enabled.clone(), ignored))
+}
+
+pub fn plugin_enabled(hook: &str) -> bool {
+    ENABLED_PLUGIN_HOOKS.lock().unwrap().contains(&hook)
+}
+
+#[cfg(target_arch = "wasm32")]
 fn plugin_movement_rule(p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut HashSet<Move>) {
+    if !plugin_enabled("movement") {
+        return;
+    }
     let piece_ptr: *const Piece = &p;
-    let placements_ptr: *const [u8; 8 + 1] = pp.as_ptr();
-    const RETVAL_LEN: usize = 3 * 8 * 8 * 95;
-    let mut retval: [u8; RETVAL_LEN] = [0; RETVAL_LEN];
+    let placements = pp.as_slice();
+    // A move to each square, as any printable piece.
+    let mut retval = vec![0u8; 3 * placements.len() * 95];
     let retval_ptr: *const u8 = retval.as_mut_ptr();
     unsafe {
         movement_plugin(
             piece_ptr as u32,
-            placements_ptr as u32,
+            placements.as_ptr() as u32,
+            pp.flags_slice().as_ptr() as u32,
+            placements.len() as u32,
+            pp.width() as u32,
+            pp.height() as u32,
             retval_ptr as u32,
-            RETVAL_LEN as u32,
+            retval.len() as u32,
         );
     }
     let mut i = 0;
-    while i < RETVAL_LEN {
+    while i < retval.len() {
         if retval[i] == 0 {
             break;
         }
         let (r, c, n) = (retval[i] as usize, retval[i + 1] as usize, retval[i + 2]);
         if std_in_bounds(r as i32, c as i32) {
-            if pp[r][c] != 0 {
+            if pp.get(r, c) != 0 {
                 hs.insert(Move::capture(r, c, n, gd));
             } else {
                 hs.insert(Move::normal(r, c, n, gd));
@@ -907,6 +1893,22 @@ fn plugin_movement_rule(p: Piece, pp: &PiecePlacements, gd: GameData, hs: &mut H
     }
 }
 
+// Parses a board drawn as 8 lines of 8 characters, rank 8 first, with '.' for empty squares.
+pub fn string_board_to_placements(board: &str) -> PiecePlacements {
+    let board = board.trim();
+    let mut placements = PiecePlacements::default();
+    for (i, line) in board.split('\n').enumerate() {
+        let r = 8 - i;
+        for (j, p) in line.trim().chars().enumerate() {
+            let c = j + 1;
+            if p != '.' {
+                placements.set(r, c, p as u8);
+            }
+        }
+    }
+    placements
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1548,6 +2550,77 @@ mod tests {
         assert_moves_allowed_eq(board, piece, &allowed);
     }
 
+    #[test]
+    fn test_square_flags() {
+        let rules = Rules::defaults();
+        let gd = GameData { ply: 1, mask: 0 };
+        let mut pp = rules.initial_placements();
+        let pawn = Piece {
+            row: 2,
+            col: 5,
+            name: b'P',
+        };
+        assert_eq!(rules.allowed_moves(pawn, &pp, gd).len(), 2);
+
+        // A pawn back on its first square after moving can't step two.
+        pp.set_flags(2, 5, PF_MOVED);
+        assert!(pp.has_moved(2, 5));
+        assert_eq!(rules.allowed_moves(pawn, &pp, gd).len(), 1);
+        pp.set_flags(2, 5, PF_FROZEN);
+        assert!(rules.allowed_moves(pawn, &pp, gd).is_empty());
+
+        // Flags move with the piece, and a move sets PF_MOVED.
+        pp.set_flags(2, 5, 3 << PF_CHARGES_SHIFT);
+        let before = pp;
+        let m = Move::normal(4, 5, b'P', gd);
+        Rules::make_move(pawn, m, &mut pp);
+        assert_eq!(pp.flags(2, 5), 0);
+        assert_eq!(pp.flags(4, 5), 3 << PF_CHARGES_SHIFT | PF_MOVED);
+        Rules::unmake_move(pawn, m, &before, &mut pp);
+        assert_eq!(pp, before);
+
+        // Castling moves the rook's flags too.
+        let mut pp = string_board_to_placements(
+            "
+            ....k...
+            ........
+            ........
+            ........
+            ........
+            ........
+            ........
+            ....K..R
+        ",
+        );
+        pp.set_flags(1, 8, 1 << PF_CHARGES_SHIFT);
+        let king = Piece {
+            row: 1,
+            col: 5,
+            name: b'K',
+        };
+        let castle = *rules
+            .allowed_moves(king, &pp, gd)
+            .iter()
+            .find(|m| m.dst.col == 7)
+            .unwrap();
+        Rules::make_move(king, castle, &mut pp);
+        assert_eq!(pp.flags(1, 6), 1 << PF_CHARGES_SHIFT | PF_MOVED);
+        assert_eq!(pp.flags(1, 8), 0);
+
+        // Only flags other than PF_MOVED tell positions apart.
+        let mut moved = pp;
+        moved.set_flags(1, 7, PF_MOVED);
+        assert_eq!(
+            Rules::position_hash(&moved, gd),
+            Rules::position_hash(&pp, gd)
+        );
+        moved.set_flags(1, 7, PF_FROZEN);
+        assert_ne!(
+            Rules::position_hash(&moved, gd),
+            Rules::position_hash(&pp, gd)
+        );
+    }
+
     #[test]
     fn test_castles_queenside() {
         let board = "
@@ -1744,6 +2817,458 @@ mod tests {
         assert_moves_allowed_eq(board, piece, &Vec::new());
     }
 
+    #[test]
+    fn test_en_passant() {
+        let board = "
+            ....k...
+            ........
+            ........
+            ...pP...
+            ........
+            ........
+            ........
+            ....K...
+        ";
+        let piece = Piece {
+            row: 5,
+            col: 5,
+            name: 'P' as u8,
+        };
+        let forward = Piece {
+            row: 6,
+            col: 5,
+            name: 'P' as u8,
+        };
+        let ep = Piece {
+            row: 6,
+            col: 4,
+            name: 'P' as u8,
+        };
+        // Right after d7d5.
+        let gd = GameData {
+            ply: 3,
+            mask: 4 << GD_EN_PASSANT_SHIFT,
+        };
+        assert_moves_allowed_eq_with_gd(board, piece, &vec![forward, ep], gd);
+        // Any later move.
+        let gd = GameData { ply: 5, mask: 0 };
+        assert_moves_allowed_eq_with_gd(board, piece, &vec![forward], gd);
+
+        // The captured pawn is removed, and the next move can't capture en passant.
+        let rules = Rules::defaults();
+        let mut pp = string_board_to_placements(board);
+        let m = rules
+            .allowed_moves(
+                piece,
+                &pp,
+                GameData {
+                    ply: 3,
+                    mask: 4 << GD_EN_PASSANT_SHIFT,
+                },
+            )
+            .into_iter()
+            .find(|m| m.dst == ep)
+            .unwrap();
+        Rules::make_move(piece, m, &mut pp);
+        assert_eq!((pp.get(5, 4), pp.get(6, 4)), (0, 'P' as u8));
+        assert_eq!(m.game_data.mask & GD_EN_PASSANT, 0);
+    }
+
+    #[test]
+    fn test_pawns_on_last_rank() {
+        // Not reachable in a game, but a custom setup or FEN could put them there.
+        let board = "
+            P...k...
+            ........
+            ........
+            ........
+            ........
+            ........
+            ........
+            ....K..p
+        ";
+        for (row, col, name) in [(8, 1, 'P'), (1, 8, 'p')] {
+            let piece = Piece {
+                row,
+                col,
+                name: name as u8,
+            };
+            assert_moves_allowed_eq(board, piece, &Vec::new());
+        }
+    }
+
+    #[test]
+    fn test_promotions() {
+        let board = "
+            .r..k...
+            P.......
+            ........
+            ........
+            ........
+            ........
+            ........
+            ....K...
+        ";
+        let piece = Piece {
+            row: 7,
+            col: 1,
+            name: 'P' as u8,
+        };
+        let mut allowed = Vec::new();
+        for col in [1, 2] {
+            for name in "QRBN".bytes() {
+                allowed.push(Piece { row: 8, col, name });
+            }
+        }
+        assert_moves_allowed_eq(board, piece, &allowed);
+    }
+
+    #[test]
+    fn test_promotion_rules() {
+        let board = "
+            ....k...
+            ........
+            P.......
+            ........
+            ........
+            ........
+            ........
+            .N.QK...
+        ";
+        let pp = string_board_to_placements(board);
+        let gd = GameData { ply: 1, mask: 0 };
+        let piece = Piece {
+            row: 6,
+            col: 1,
+            name: 'P' as u8,
+        };
+        let mut rules = Rules::defaults();
+        let promoted = |rules: &Rules| {
+            let mut names: Vec<u8> = rules
+                .allowed_moves(piece, &pp, gd)
+                .iter()
+                .map(|m| m.dst.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(promoted(&rules), b"P");
+        // Promotes a rank early, and only to what was captured: a knight, but not the queen.
+        rules.promotions.insert(
+            b'p',
+            Promotion {
+                rank: 7,
+                pieces: b"qn".to_vec(),
+                captured_only: true,
+            },
+        );
+        assert_eq!(promoted(&rules), b"N");
+        assert_eq!(rules.promotion_order(piece, b'Q'), 0);
+        assert_eq!(rules.promotion_order(piece, b'N'), 1);
+        // Nothing to promote to, so the pawn can't move.
+        rules.promotions.get_mut(&b'p').unwrap().pieces = b"q".to_vec();
+        assert_eq!(promoted(&rules), b"");
+        // Pawns that don't promote stay pawns.
+        rules.promotions.clear();
+        assert_eq!(promoted(&rules), b"P");
+    }
+
+    #[test]
+    fn test_constraint_toggles() {
+        let board = "
+            ....k...
+            ........
+            ....K...
+            r.......
+            ....p...
+            ........
+            ..Q.....
+            ........
+        ";
+        let pp = string_board_to_placements(board);
+        let gd = GameData { ply: 1, mask: 0 };
+        let king = Piece {
+            row: 6,
+            col: 5,
+            name: 'K' as u8,
+        };
+        let mut rules = Rules::defaults();
+        let can_move_to = |rules: &Rules, p: Piece, r: u8, c: u8| {
+            rules
+                .allowed_moves(p, &pp, gd)
+                .iter()
+                .any(|m| (m.dst.row, m.dst.col) == (r, c))
+        };
+        // e5 is attacked by the rook, and f7 by the king.
+        assert!(!can_move_to(&rules, king, 5, 5));
+        assert!(!can_move_to(&rules, king, 7, 6));
+        rules
+            .move_constraint_rules
+            .get_mut("resolve-check")
+            .unwrap()
+            .active = false;
+        assert!(can_move_to(&rules, king, 5, 5));
+        assert!(can_move_to(&rules, king, 7, 6));
+        rules
+            .move_constraint_rules
+            .get_mut("kings-may-not-touch")
+            .unwrap()
+            .active = true;
+        assert!(can_move_to(&rules, king, 5, 5));
+        assert!(!can_move_to(&rules, king, 7, 6));
+
+        // The queen can take the pawn, so nothing else can move.
+        let queen = Piece {
+            row: 2,
+            col: 3,
+            name: 'Q' as u8,
+        };
+        assert!(can_move_to(&rules, queen, 1, 3));
+        rules
+            .move_constraint_rules
+            .get_mut("forced-capture")
+            .unwrap()
+            .active = true;
+        let moves = rules.legal_moves(0, &pp, gd);
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].1.dst.row, moves[0].1.dst.col), (4, 5));
+    }
+
+    #[test]
+    fn test_explain_moves() {
+        let board = "
+            ....k...
+            ........
+            ........
+            ........
+            ........
+            ....p...
+            ........
+            ...K....
+        ";
+        let pp = string_board_to_placements(board);
+        let gd = GameData { ply: 0, mask: 0 };
+        let king = Piece {
+            row: 1,
+            col: 4,
+            name: b'K',
+        };
+        let rules = Rules::defaults();
+        let candidates = rules.explain_moves(king, &pp, gd);
+        let allowed = rules.allowed_moves(king, &pp, gd);
+        assert_eq!(candidates.len(), 5);
+        for c in &candidates {
+            assert!(!c.produced_by.is_empty());
+            assert_eq!(c.rejected_by.is_empty(), allowed.contains(&c.m));
+        }
+        // The pawn covers d2 and f2.
+        let d2 = candidates
+            .iter()
+            .find(|c| (c.m.dst.row, c.m.dst.col) == (2, 4))
+            .unwrap();
+        assert_eq!(d2.rejected_by, vec!["resolve-check"]);
+    }
+
+    #[test]
+    fn test_double_move_turns() {
+        let mut rules = Rules::defaults();
+        assert_eq!({ rules.initial_game_data().mask }, 0);
+        rules.double_move = true;
+        let gd = rules.initial_game_data();
+        let players: Vec<usize> = (1..=7)
+            .map(|ply| GameData { ply, ..gd }.player_to_move())
+            .collect();
+        assert_eq!(players, [0, 1, 1, 0, 0, 1, 1]);
+        // Black moves again at ply 3, and white can't.
+        let pp = rules.initial_placements();
+        let second = GameData { ply: 3, ..gd };
+        assert_eq!(rules.legal_moves(1, &pp, second).len(), 20);
+        assert!(rules.legal_moves(0, &pp, second).is_empty());
+        assert!(rules
+            .rule_toggles()
+            .any(|(n, active)| n == "double-move" && active));
+    }
+
+    #[test]
+    fn test_duck_phases() {
+        let mut rules = Rules::defaults();
+        rules.phases = vec![Phase::Move, Phase::Duck];
+        let gd = rules.initial_game_data();
+        let turns: Vec<_> = (1..=5)
+            .map(|ply| {
+                let gd = GameData { ply, ..gd };
+                (gd.player_to_move(), gd.phase())
+            })
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                (0, Phase::Move),
+                (0, Phase::Duck),
+                (1, Phase::Move),
+                (1, Phase::Duck),
+                (0, Phase::Move)
+            ]
+        );
+
+        // After 1. e4, white puts the duck on any of the 32 empty squares, and can't move a piece.
+        let mut pp = rules.initial_placements();
+        let (p, m) = crate::notation::parse_san(&rules, &pp, gd, "e4").unwrap();
+        Rules::make_move(p, m, &mut pp);
+        let gd = GameData {
+            ply: 2,
+            ..m.game_data
+        };
+        assert_eq!(rules.placement(&pp, gd), Some(DUCK));
+        let moves = rules.legal_moves(0, &pp, gd);
+        assert_eq!(moves.len(), 32);
+        assert!(moves
+            .iter()
+            .all(|(p, m)| p.name == DUCK && m.dst.row == p.row));
+        let (p, m) = crate::notation::parse_san(&rules, &pp, gd, "@e5").unwrap();
+        Rules::make_move(p, m, &mut pp);
+        assert_eq!(pp.get(5, 5), DUCK);
+        // The pawn can still be taken en passant after the duck moves.
+        assert_ne!(m.game_data.mask & GD_EN_PASSANT, 0);
+
+        // Black can't take the duck or move it, and it blocks the e-pawn.
+        let gd = GameData {
+            ply: 3,
+            ..m.game_data
+        };
+        assert_eq!(rules.placement(&pp, gd), None);
+        let moves = rules.legal_moves(1, &pp, gd);
+        assert!(moves.iter().all(|(p, m)| p.name != DUCK && !m.is_capture()));
+        let e_pawn = moves.iter().filter(|(p, _)| (p.row, p.col) == (7, 5));
+        assert_eq!(e_pawn.count(), 1);
+        // Then black moves it, anywhere but where it is.
+        let gd = GameData { ply: 4, ..gd };
+        let moves = rules.legal_moves(1, &pp, gd);
+        assert_eq!(moves.len(), 31);
+        assert!(moves.iter().all(|(p, _)| (p.row, p.col) == (5, 5)));
+    }
+
+    #[test]
+    fn test_pass() {
+        let mut rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = rules.initial_game_data();
+        assert_eq!(rules.pass_move(0, &pp, gd), None);
+        rules.pass = true;
+        let (king, pass) = rules.pass_move(0, &pp, gd).unwrap();
+        assert_eq!((king.name, king.row, king.col), (b'K', 1, 5));
+        assert_eq!(pass.typ, MoveType::Pass);
+        assert_eq!(rules.legal_moves(0, &pp, gd).len(), 21);
+        // It's black's turn, so white can't pass.
+        assert_eq!(rules.pass_move(0, &pp, GameData { ply: 2, ..gd }), None);
+        let mut after = pp;
+        Rules::make_move(king, pass, &mut after);
+        assert_eq!(after, pp);
+
+        // A player in check can't pass.
+        let (pp, gd) = crate::notation::parse_fen("4k3/8/8/8/8/8/8/r3K3 w - - 0 1").unwrap();
+        assert_eq!(rules.pass_move(0, &pp, gd), None);
+        assert!(rules.pass_move(1, &pp, GameData { ply: 2, ..gd }).is_some());
+    }
+
+    #[test]
+    fn test_enable_plugins() {
+        let declaration = |version: u32, hooks: &str| {
+            format!(r#"{{"abi_version": {}, "hooks": {}}}"#, version, hooks)
+        };
+        assert_eq!(
+            enable_plugins(&declaration(PLUGIN_ABI_VERSION, r#"["movement", "chat"]"#)),
+            Ok((vec!["movement"], vec![r#""chat""#.to_string()]))
+        );
+        assert!(plugin_enabled("movement"));
+        // An old page's plugins would misread the arguments.
+        let old = enable_plugins(&declaration(1, r#"["movement"]"#));
+        assert!(old.unwrap_err().contains("version 1"));
+        assert!(!plugin_enabled("movement"));
+        assert!(enable_plugins(r#"{"hooks": ["movement"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_board() {
+        let mut board = Board::new(10, 8);
+        board.set(1, 1, b'R');
+        board.set(8, 10, b'k');
+        assert_eq!(
+            (board.get(1, 1), board.get(8, 10), board.get(2, 2)),
+            (b'R', b'k', 0)
+        );
+        // Off the board, including the row and column 0 the old layout had.
+        assert_eq!(
+            (board.get(0, 1), board.get(9, 1), board.get(1, 11)),
+            (0, 0, 0)
+        );
+        let flat = board.as_slice();
+        assert_eq!(flat.len(), 80);
+        assert_eq!((flat[0], flat[79]), (b'R', b'k'));
+        let pieces: Vec<_> = board.squares().filter(|&(_, _, n)| n != 0).collect();
+        assert_eq!(pieces, [(1, 1, b'R'), (8, 10, b'k')]);
+    }
+
+    #[test]
+    fn test_insufficient_material() {
+        let rules = Rules::defaults();
+        assert!(!Rules::insufficient_material(&rules.initial_placements()));
+        let cases = [
+            ("....k.......K...", true),
+            ("....k..n....K...", true),
+            ("....k..b....K.B.", true),
+            ("..b.k.......KB..", true),
+            ("..b.k..b....K...", false),
+            ("....k.n.....K.N.", false),
+            ("....k..p....K...", false),
+            ("....k.......K..R", false),
+        ];
+        for (squares, expected) in cases {
+            // The 8th rank, the 1st rank, and nothing in between.
+            let (eighth, first) = squares.split_at(8);
+            let board = format!("{}\n{}{}", eighth, "........\n".repeat(6), first);
+            let placements = string_board_to_placements(&board);
+            assert_eq!(
+                Rules::insufficient_material(&placements),
+                expected,
+                "{}",
+                squares
+            );
+        }
+    }
+
+    // Replays the games in testdata/golden_games.pgn.
+    #[test]
+    fn test_golden_games() {
+        use crate::notation::{fen, parse_pgn, parse_san};
+
+        let rules = Rules::defaults();
+        let games = parse_pgn(include_str!("../testdata/golden_games.pgn")).unwrap();
+        assert!(!games.is_empty());
+        for game in games {
+            let tag = |name: &str| {
+                game.tags
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+            let title = format!("{} - {}, {}", tag("White"), tag("Black"), tag("Event"));
+            let mut pp = rules.initial_placements();
+            let mut gd = GameData { ply: 1, mask: 0 };
+            for s in &game.moves {
+                let (p, m) = parse_san(&rules, &pp, gd, s)
+                    .unwrap_or_else(|| panic!("{}: {} isn't legal in {}", title, s, fen(&pp, gd)));
+                Rules::make_move(p, m, &mut pp);
+                gd = GameData {
+                    ply: m.game_data.ply + 1,
+                    ..m.game_data
+                };
+            }
+            assert_eq!(fen(&pp, gd), tag("FinalFEN"), "{}", title);
+        }
+    }
+
     fn assert_moves_allowed_eq_with_gd(
         board: &str,
         piece: Piece,
@@ -1764,19 +3289,129 @@ mod tests {
     fn assert_moves_allowed_eq(board: &str, piece: Piece, expect_allowed: &Vec<Piece>) {
         assert_moves_allowed_eq_with_gd(board, piece, expect_allowed, GameData { ply: 1, mask: 0 });
     }
+}
+
+// Invariants of the move generator, checked in positions reached by random playouts.
+#[cfg(test)]
+mod properties {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::notation::{fen, parse_fen};
+
+    // Plays from the initial position, each choice picking one of the legal moves, until the
+    // choices run out or the game ends.
+    fn playout(rules: &Rules, choices: &[usize]) -> (PiecePlacements, GameData) {
+        let mut pp = rules.initial_placements();
+        let mut gd = GameData { ply: 1, mask: 0 };
+        for &i in choices {
+            let moves = rules.legal_moves(gd.player_to_move(), &pp, gd);
+            if moves.is_empty() {
+                break;
+            }
+            let (p, m) = moves[i % moves.len()];
+            Rules::make_move(p, m, &mut pp);
+            gd = next(m);
+        }
+        (pp, gd)
+    }
+
+    fn next(m: Move) -> GameData {
+        GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        }
+    }
 
-    fn string_board_to_placements(board: &str) -> PiecePlacements {
-        let board = board.trim();
-        let mut placements = [[0; 8 + 1]; 8 + 1];
-        for (i, line) in board.split('\n').enumerate() {
-            let r = 8 - i;
-            for (j, p) in line.trim().chars().enumerate() {
-                let c = j + 1;
-                if p != '.' {
-                    placements[r][c] = p as u8;
+    // The number of move sequences of the given length.
+    fn perft(rules: &Rules, pp: &PiecePlacements, gd: GameData, depth: u32) -> usize {
+        let moves = rules.legal_moves(gd.player_to_move(), pp, gd);
+        if depth <= 1 {
+            return if depth == 1 { moves.len() } else { 1 };
+        }
+        moves
+            .into_iter()
+            .map(|(p, m)| {
+                let mut after = *pp;
+                Rules::make_move(p, m, &mut after);
+                perft(rules, &after, next(m), depth - 1)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_perft_initial_position() {
+        let rules = Rules::defaults();
+        let pp = rules.initial_placements();
+        let gd = GameData { ply: 1, mask: 0 };
+        assert_eq!(perft(&rules, &pp, gd, 1), 20);
+        assert_eq!(perft(&rules, &pp, gd, 2), 400);
+        assert_eq!(perft(&rules, &pp, gd, 3), 8902);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_moves_dont_leave_king_attacked(choices in vec(any::<usize>(), 0..80)) {
+            let rules = Rules::defaults();
+            let (pp, gd) = playout(&rules, &choices);
+            let white = gd.player_to_move() == 0;
+            for (p, m) in rules.legal_moves(gd.player_to_move(), &pp, gd) {
+                let mut after = pp;
+                Rules::make_move(p, m, &mut after);
+                prop_assert!(!Rules::in_check(white, &after, gd), "{:?} {:?}", p, m);
+                if let MoveType::Secondary { .. } = m.typ {
+                    // Castling: the king can't be in check, or pass through an attacked square.
+                    prop_assert!(!Rules::in_check(white, &pp, gd), "{:?}", m);
+                    let passed = Piece {
+                        col: (p.col + m.dst.col) / 2,
+                        ..p
+                    };
+                    prop_assert!(!piece_attacked(passed, &pp, gd), "{:?}", m);
                 }
             }
         }
-        placements
+
+        #[test]
+        fn test_unmake_restores_position(choices in vec(any::<usize>(), 0..80)) {
+            let rules = Rules::defaults();
+            let (pp, gd) = playout(&rules, &choices);
+            let hash = Rules::position_hash(&pp, gd);
+            let mut board = pp;
+            for (p, m) in rules.legal_moves(gd.player_to_move(), &pp, gd) {
+                Rules::make_move(p, m, &mut board);
+                prop_assert_ne!(Rules::position_hash(&board, next(m)), hash);
+                Rules::unmake_move(p, m, &pp, &mut board);
+                prop_assert_eq!(board, pp);
+                prop_assert_eq!(Rules::position_hash(&board, gd), hash);
+            }
+        }
+
+        #[test]
+        fn test_perft_is_sum_of_children(choices in vec(any::<usize>(), 0..60)) {
+            let rules = Rules::defaults();
+            let (pp, gd) = playout(&rules, &choices);
+            let children: usize = rules
+                .legal_moves(gd.player_to_move(), &pp, gd)
+                .into_iter()
+                .map(|(p, m)| {
+                    let mut after = pp;
+                    Rules::make_move(p, m, &mut after);
+                    perft(&rules, &after, next(m), 1)
+                })
+                .sum();
+            prop_assert_eq!(perft(&rules, &pp, gd, 2), children);
+        }
+
+        #[test]
+        fn test_fen_round_trip(choices in vec(any::<usize>(), 0..80)) {
+            let rules = Rules::defaults();
+            let (pp, gd) = playout(&rules, &choices);
+            let s = fen(&pp, gd);
+            // FEN has no square flags, only the pieces.
+            let pieces = |(pp, gd): (PiecePlacements, GameData)| (pp.as_slice().to_vec(), gd);
+            prop_assert_eq!(parse_fen(&s).map(pieces), Ok(pieces((pp, gd))), "{}", s);
+        }
     }
 }
@@ -0,0 +1,421 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::{json, Map, Value};
+
+use crate::prelude::*;
+
+// A rule set is the shareable part of the rules: which game they're for (see Rules::profile),
+// which movement and constraint rules are toggled on, the phases of a turn, how pieces promote,
+// the board's zones, the board size, a name and the seed of the random numbers, if it's fixed.
+// It's serialized as JSON so it can be passed through JS, the server and links.
+// Bump this if the format changes in a way older clients can't read.
+const RULESET_VERSION: u64 = 1;
+
+impl<'a> Rules<'a> {
+    pub fn export_ruleset(&self) -> String {
+        let board = PiecePlacements::default();
+        let mut toggles = Map::new();
+        for (n, active) in self.rule_toggles() {
+            toggles.insert(n.to_string(), Value::Bool(active));
+        }
+        let mut promotions = Map::new();
+        for (&n, p) in self.promotions.iter() {
+            promotions.insert(
+                (n as char).to_string(),
+                json!({
+                    "rank": p.rank,
+                    "pieces": String::from_utf8_lossy(&p.pieces),
+                    "captured_only": p.captured_only,
+                }),
+            );
+        }
+        json!({
+            "version": RULESET_VERSION,
+            "profile": self.profile,
+            "name": self.name,
+            "board": { "rows": board.height(), "cols": board.width() },
+            "rules": toggles,
+            "promotions": promotions,
+            "phases": self.phases.iter().map(|&p| phase_name(p)).collect::<Vec<_>>(),
+            "zones": self.zones.to_json(),
+            "seed": self.seed,
+        })
+        .to_string()
+    }
+
+    // Applies a rule set produced by export_ruleset. Rules the rule set doesn't mention are left
+    // alone, and rules we don't know about (e.g. from a newer client) are ignored.
+    pub fn import_ruleset(&mut self, s: &str) -> Result<(), String> {
+        let v: Value = serde_json::from_str(s).map_err(|e| format!("invalid ruleset: {}", e))?;
+        let version = v["version"]
+            .as_u64()
+            .ok_or("ruleset is missing a version")?;
+        if version > RULESET_VERSION {
+            return Err(format!("unsupported ruleset version: {}", version));
+        }
+        // Another game's rules start from that game's defaults.
+        if let Some(profile) = v["profile"].as_str().filter(|&p| p != self.profile) {
+            *self = Rules::profile(profile)
+                .ok_or_else(|| format!("unsupported profile: {}", profile))?;
+        }
+        // Every game is played on the default board, so a rule set for another size can't be.
+        let (board, size) = (PiecePlacements::default(), &v["board"]);
+        if !size.is_null() && (size["rows"] != board.height() || size["cols"] != board.width()) {
+            return Err(format!("unsupported board size: {}", size));
+        }
+        if let Some(toggles) = v["rules"].as_object() {
+            for (n, active) in self.rule_toggles_mut() {
+                if let Some(a) = toggles.get(n).and_then(Value::as_bool) {
+                    *active = a;
+                }
+            }
+        }
+        // Replaces all of them, so a rule set can take promotion away from pawns.
+        if let Some(promotions) = v["promotions"].as_object() {
+            let mut imported = HashMap::new();
+            for (n, p) in promotions {
+                let invalid = || format!("invalid promotion for {}: {}", n, p);
+                let name = match n.as_bytes() {
+                    &[name] if name.is_ascii_lowercase() => name,
+                    _ => return Err(invalid()),
+                };
+                let rank = p["rank"]
+                    .as_u64()
+                    .filter(|r| (1..=8).contains(r))
+                    .ok_or_else(invalid)?;
+                let pieces = p["pieces"]
+                    .as_str()
+                    .filter(|s| s.bytes().all(|b| b.is_ascii_lowercase()))
+                    .ok_or_else(invalid)?;
+                imported.insert(
+                    name,
+                    Promotion {
+                        rank: rank as u8,
+                        pieces: pieces.as_bytes().to_vec(),
+                        captured_only: p["captured_only"].as_bool().unwrap_or(false),
+                    },
+                );
+            }
+            self.promotions = imported;
+        }
+        if let Some(phases) = v["phases"].as_array() {
+            let invalid = || format!("invalid phases: {}", v["phases"]);
+            if !(1..=MAX_PHASES).contains(&phases.len()) {
+                return Err(invalid());
+            }
+            self.phases = phases
+                .iter()
+                .map(|p| p.as_str().and_then(parse_phase).ok_or_else(invalid))
+                .collect::<Result<_, _>>()?;
+        }
+        // Replaces all of them too, so a rule set only has the zones it names.
+        if !v["zones"].is_null() {
+            self.zones = Zones::from_json(&v["zones"])?;
+        }
+        if let Some(name) = v["name"].as_str() {
+            self.name = name.to_string();
+        }
+        if !v["seed"].is_null() {
+            let seed = v["seed"].as_u64();
+            self.seed = Some(seed.ok_or_else(|| format!("invalid seed: {}", v["seed"]))?);
+        }
+        Ok(())
+    }
+
+    // Checks the rules make a game that can be played, e.g. after a rule set is imported or a
+    // plugin registers, rather than finding out mid-game: every piece has a movement rule, the
+    // setup is on the board, pieces promote to pieces that exist, the zones rules use are there,
+    // and unless a player with no moves loses, each side has a king to checkmate. Returns what's
+    // wrong, each with what to do about it, or nothing if it's all fine.
+    pub fn check_ruleset(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // The board the setup rules put the pieces on (see Rules::initial_placements).
+        let board = PiecePlacements::default();
+        // Lowercase names of the pieces that can be on the board.
+        let mut pieces = BTreeSet::new();
+        let mut kings = [0, 0];
+        let mut setup: Vec<_> = self.setup_rules.iter().collect();
+        setup.sort_by_key(|&(&n, _)| n);
+        for (n, rule) in setup {
+            for p in rule() {
+                if board.contains(p.row as usize, p.col as usize) {
+                    pieces.insert(p.name.to_ascii_lowercase());
+                    match p.name {
+                        b'K' => kings[0] += 1,
+                        b'k' => kings[1] += 1,
+                        _ => {}
+                    }
+                } else {
+                    problems.push(format!(
+                        "The {} setup rule puts {} on row {}, column {}, off the {} by {} board",
+                        n,
+                        p.name as char,
+                        p.row,
+                        p.col,
+                        board.height(),
+                        board.width()
+                    ));
+                }
+            }
+        }
+        let mut promotions: Vec<_> = self.promotions.iter().collect();
+        promotions.sort_by_key(|&(&n, _)| n);
+        for (&n, promotion) in promotions {
+            for &target in &promotion.pieces {
+                if self.piece_name_to_offsets.contains_key(&target) {
+                    pieces.insert(target);
+                } else {
+                    problems.push(format!(
+                        "{} promotes to {}, which isn't a piece in this game: remove it from the promotions",
+                        n as char, target as char
+                    ));
+                }
+            }
+        }
+        for n in pieces {
+            if self.moves_piece(n) {
+                continue;
+            }
+            let mut off: Vec<_> = self
+                .movement_rules
+                .iter()
+                .filter(|(_, r)| r.piece_constrait == Some(n as char))
+                .map(|(&name, _)| format!("\"{}\"", name))
+                .collect();
+            off.sort();
+            problems.push(if off.is_empty() {
+                format!(
+                    "No movement rule moves {}: register a movement plugin for it",
+                    n as char
+                )
+            } else {
+                format!(
+                    "No movement rule moves {}: turn on {}",
+                    n as char,
+                    off.join(" or ")
+                )
+            });
+        }
+        if !self.phases.contains(&Phase::Move) {
+            problems.push("No phase of a turn moves a piece: add a \"move\" phase".to_string());
+        }
+        if self.phases.len() > 1 && self.double_move {
+            problems.push(
+                "\"double-move\" doesn't work with several phases a turn: turn one of them off"
+                    .to_string(),
+            );
+        }
+        if self.phases.contains(&Phase::Duck) && !self.moves_piece(DUCK) {
+            problems.push(
+                "There's a duck phase, but the duck can't move: turn on \"duck\"".to_string(),
+            );
+        }
+        let in_camp = self.move_constraint_rules.get("kings-stay-in-camp");
+        if in_camp.is_some_and(|r| r.active) {
+            for camp in ["white-camp", "black-camp"] {
+                if self.zones.get(camp).is_none() {
+                    problems.push(format!(
+                        "\"kings-stay-in-camp\" keeps kings in the {} zone, which there isn't: add it to the zones",
+                        camp
+                    ));
+                }
+            }
+        }
+        if !self.no_moves_loses {
+            for (kings, side) in kings.into_iter().zip(["White", "Black"]) {
+                if kings != 1 {
+                    problems.push(format!(
+                        "{} starts with {} kings, but checkmate needs one: fix the kings setup rule",
+                        side, kings
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    // Whether an active movement rule applies to the piece with the lowercase name n. The JS
+    // plugin's applies to every piece, once the page has registered it.
+    fn moves_piece(&self, n: u8) -> bool {
+        self.movement_rules
+            .iter()
+            .any(|(&name, r)| match r.piece_constrait {
+                _ if !r.active => false,
+                Some(p) => p.to_ascii_lowercase() == n as char,
+                None => name != "js-plugin" || plugin_enabled("movement"),
+            })
+    }
+}
+
+fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Move => "move",
+        Phase::Duck => "duck",
+    }
+}
+
+fn parse_phase(name: &str) -> Option<Phase> {
+    match name {
+        "move" => Some(Phase::Move),
+        "duck" => Some(Phase::Duck),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset_round_trip() {
+        let mut rules = Rules::defaults();
+        rules.name = "No knights".to_string();
+        rules.movement_rules.get_mut("knight").unwrap().active = false;
+        rules
+            .move_constraint_rules
+            .get_mut("forced-capture")
+            .unwrap()
+            .active = true;
+        rules.promotions.get_mut(&b'p').unwrap().captured_only = true;
+        rules.phases = vec![Phase::Move, Phase::Duck];
+        rules.seed = Some(1234);
+        rules.zones.insert(
+            "hill",
+            Zone {
+                squares: square_bit(4, 4),
+                shade: true,
+            },
+        );
+        let exported = rules.export_ruleset();
+        let v: Value = serde_json::from_str(&exported).unwrap();
+        assert_eq!(v["board"], json!({"rows": 8, "cols": 8}));
+
+        let mut imported = Rules::defaults();
+        imported.import_ruleset(&exported).unwrap();
+        assert_eq!(imported.name, "No knights");
+        for (n, r) in imported.movement_rules.iter() {
+            assert_eq!(r.active, *n != "knight", "{}", n);
+        }
+        assert!(imported.move_constraint_rules["forced-capture"].active);
+        assert_eq!(imported.promotions, rules.promotions);
+        assert_eq!(imported.zones, rules.zones);
+        assert_eq!(imported.phases, rules.phases);
+        assert_eq!(imported.seed, Some(1234));
+        assert_eq!(imported.export_ruleset(), exported);
+        // Without a seed, the random numbers stay random.
+        let exported = Rules::defaults().export_ruleset();
+        let mut imported = Rules::defaults();
+        imported.import_ruleset(&exported).unwrap();
+        assert_eq!(imported.seed, None);
+    }
+
+    #[test]
+    fn test_ruleset_profile() {
+        let mut rules = Rules::defaults();
+        rules
+            .import_ruleset(&Rules::draughts().export_ruleset())
+            .unwrap();
+        assert_eq!(rules.profile, "draughts");
+        assert_eq!(rules.name, "Draughts");
+        assert!(rules.movement_rules.contains_key("jump"));
+        assert!(rules.move_constraint_rules["forced-capture"].active);
+        rules
+            .import_ruleset(r#"{"version": 1, "profile": "chess", "name": "Chess"}"#)
+            .unwrap();
+        assert_eq!(rules.profile, "chess");
+        assert!(rules.movement_rules.contains_key("knight"));
+    }
+
+    #[test]
+    fn test_ruleset_partial_and_unknown_rules() {
+        let mut rules = Rules::defaults();
+        rules
+            .import_ruleset(r#"{"version": 1, "rules": {"rook": false, "no-such-rule": true}}"#)
+            .unwrap();
+        assert!(!rules.movement_rules["rook"].active);
+        assert!(rules.movement_rules["bishop"].active);
+        assert_eq!(rules.name, "Standard");
+        assert_eq!(rules.promotions, Rules::default_promotions());
+    }
+
+    #[test]
+    fn test_check_ruleset() {
+        assert!(Rules::defaults().check_ruleset().is_empty());
+        assert!(Rules::draughts().check_ruleset().is_empty());
+
+        let mut rules = Rules::defaults();
+        rules.movement_rules.get_mut("knight").unwrap().active = false;
+        rules.promotions.get_mut(&b'p').unwrap().pieces = b"qx".to_vec();
+        rules.setup_rules.remove("kings");
+        rules
+            .move_constraint_rules
+            .get_mut("kings-stay-in-camp")
+            .unwrap()
+            .active = true;
+        rules.zones = Zones::default();
+        rules.phases = vec![Phase::Duck];
+        rules.movement_rules.get_mut("duck").unwrap().active = false;
+        rules.setup_rules.insert(
+            "extra",
+            Box::new(|| {
+                vec![Piece {
+                    row: 9,
+                    col: 1,
+                    name: b'R',
+                }]
+            }),
+        );
+        assert_eq!(
+            rules.check_ruleset(),
+            [
+                "The extra setup rule puts R on row 9, column 1, off the 8 by 8 board",
+                "p promotes to x, which isn't a piece in this game: remove it from the promotions",
+                "No movement rule moves n: turn on \"knight\"",
+                "No phase of a turn moves a piece: add a \"move\" phase",
+                "There's a duck phase, but the duck can't move: turn on \"duck\"",
+                "\"kings-stay-in-camp\" keeps kings in the white-camp zone, which there isn't: add it to the zones",
+                "\"kings-stay-in-camp\" keeps kings in the black-camp zone, which there isn't: add it to the zones",
+                "White starts with 0 kings, but checkmate needs one: fix the kings setup rule",
+                "Black starts with 0 kings, but checkmate needs one: fix the kings setup rule",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ruleset_rejected() {
+        let mut rules = Rules::defaults();
+        assert!(rules.import_ruleset("not json").is_err());
+        assert!(rules.import_ruleset(r#"{"rules": {}}"#).is_err());
+        assert!(rules.import_ruleset(r#"{"version": 99}"#).is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "profile": "go"}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "board": {"rows": 10, "cols": 8}}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "board": {"rows": 8, "cols": 10}}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "board": {"rows": 8, "cols": 8}}"#)
+            .is_ok());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "promotions": {"p": {"rank": 9, "pieces": "q"}}}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "promotions": {"P": {"rank": 8, "pieces": "q"}}}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "zones": {"hill": {"squares": ["z9"]}}}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "phases": ["move", "drop"]}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "phases": []}"#)
+            .is_err());
+        assert!(rules
+            .import_ruleset(r#"{"version": 1, "seed": -1}"#)
+            .is_err());
+    }
+}
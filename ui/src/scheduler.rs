@@ -0,0 +1,112 @@
+// Spreads CPU heavy work over frames, so the board keeps drawing at 60fps however much there is to
+// do, on the desktop and in the browser alike. Each frame gets a budget, and the tasks run in order
+// of priority until they've all done what they can or the budget is spent. Whatever's left picks
+// up on the next frame. A task's step is the smallest piece of work it can stop after, e.g. one
+// root move of a search, so a frame can run over when a step is slow.
+
+use macroquad::miniquad::date;
+
+// How long the tasks can run each frame, in seconds. A 60fps frame is about 16ms, which leaves
+// most of it for drawing and the browser.
+pub const FRAME_BUDGET: f64 = 0.004;
+
+// Highest priority first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Task {
+    // The moves the player at the board can make, worked out before they pick up a piece.
+    LegalMoves,
+    // The moves the player at the board can make after each of the other player's replies, worked
+    // out while the other player thinks.
+    Lookahead,
+    // The computer opponent's next move.
+    Engine,
+    // The analysis of the position shown on the board.
+    Analysis,
+    // The computer opponent searching the position it expects after the player's reply, on the
+    // player's time.
+    Ponder,
+}
+
+const TASKS: [Task; 5] = [
+    Task::LegalMoves,
+    Task::Lookahead,
+    Task::Engine,
+    Task::Analysis,
+    Task::Ponder,
+];
+
+pub trait Tasks {
+    // Does some of the task's work, stopping at the first chance after the time until (see
+    // date::now) has passed. Returns whether there's more to do.
+    fn step(&mut self, task: Task, until: f64) -> bool;
+}
+
+// Call once per frame.
+pub fn run_frame(tasks: &mut impl Tasks) {
+    run(tasks, FRAME_BUDGET, date::now);
+}
+
+fn run(tasks: &mut impl Tasks, budget: f64, now: impl Fn() -> f64) {
+    let until = now() + budget;
+    for task in TASKS {
+        if now() >= until {
+            break;
+        }
+        while tasks.step(task, until) && now() < until {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // Each step takes a millisecond of pretend time.
+    struct Fake<'a> {
+        clock: &'a Cell<f64>,
+        // Steps left for each task.
+        left: [u32; 5],
+        ran: Vec<Task>,
+    }
+
+    impl Tasks for Fake<'_> {
+        fn step(&mut self, task: Task, _until: f64) -> bool {
+            let i = TASKS.iter().position(|&t| t == task).unwrap();
+            if self.left[i] == 0 {
+                return false;
+            }
+            self.clock.set(self.clock.get() + 0.001);
+            self.left[i] -= 1;
+            self.ran.push(task);
+            self.left[i] > 0
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let clock = Cell::new(0.0);
+        let mut tasks = Fake {
+            clock: &clock,
+            left: [1, 1, 6, 2, 2],
+            ran: Vec::new(),
+        };
+        let budget = 0.0045;
+        run(&mut tasks, budget, || clock.get());
+        use Task::*;
+        // Higher priority tasks go first, and the frame ends once the budget is spent.
+        assert_eq!(tasks.ran, [LegalMoves, Lookahead, Engine, Engine, Engine]);
+        tasks.ran.clear();
+        run(&mut tasks, budget, || clock.get());
+        assert_eq!(tasks.ran, [Engine, Engine, Engine, Analysis, Analysis]);
+        tasks.ran.clear();
+        run(&mut tasks, budget, || clock.get());
+        assert_eq!(tasks.ran, [Ponder, Ponder]);
+        // Nothing to do.
+        tasks.ran.clear();
+        let start = clock.get();
+        run(&mut tasks, budget, || clock.get());
+        assert!(tasks.ran.is_empty());
+        assert_eq!(clock.get(), start);
+    }
+}
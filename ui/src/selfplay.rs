@@ -0,0 +1,364 @@
+// Engine vs engine matches, for checking whether a change to the engine or rules made things
+// better or worse. Run the desktop binary with --selfplay (see USAGE). Games are written as PGN,
+// and a summary of the results to stderr. Each opening is played twice, so each engine gets to
+// play both sides of it. The positions the engines moved in can also be written with their games'
+// results, to fit the evaluation to (see tuning.rs).
+
+use std::fs;
+
+use macroquad::{miniquad::date, rand};
+
+use chess_ui::notation::{fen, parse_long_algebraic, pgn, san};
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    prelude::*,
+    tuning::{load_tables, training_line},
+};
+
+const USAGE: &str = "\
+Usage: chess-ui --selfplay [options]
+
+Options:
+  --games N          Number of games to play (default 10)
+  --engine-a CONFIG  First engine (default 3)
+  --engine-b CONFIG  Second engine (default 3)
+  --openings FILE    Start positions, one per line as moves from the initial position in long
+                     algebraic notation, e.g. \"e2e4 e7e5\" (default: a few common openings)
+  --ruleset FILE     Rule set to play, as exported by the ui (default: standard chess)
+  --max-plies N      Call the game a draw after this many plies (default 300)
+  --pgn FILE         Write the games here instead of to stdout
+  --training FILE    Write the positions the engines moved in, with their games' results, for
+                     --tune
+
+CONFIG is a strength from 1 to 5, optionally followed by overrides, e.g.
+\"4,depth=3,time=0.5,blunder=0,contempt=20,quiescence=4,extensions=1,pst=tables.json\", where
+pst is piece-square tables written by --tune. nullmove=false and lmr=false turn off null-move
+pruning and late move reductions.";
+
+// The options in USAGE, which all take a value.
+const FLAGS: [&str; 8] = [
+    "--games",
+    "--engine-a",
+    "--engine-b",
+    "--openings",
+    "--ruleset",
+    "--max-plies",
+    "--pgn",
+    "--training",
+];
+
+const OPENINGS: [&str; 8] = [
+    "e2e4 e7e5 g1f3 b8c6",
+    "e2e4 c7c5",
+    "e2e4 e7e6 d2d4 d7d5",
+    "e2e4 c7c6 d2d4 d7d5",
+    "d2d4 d7d5 c2c4",
+    "d2d4 g8f6 c2c4 e7e6",
+    "c2c4 e7e5",
+    "g1f3 d7d5",
+];
+
+struct Options {
+    games: usize,
+    // The text each engine was configured with, to label it in the games.
+    engines: [(String, EngineConfig); 2],
+    openings: Vec<String>,
+    ruleset: Option<String>,
+    max_plies: u16,
+    pgn: Option<String>,
+    training: Option<String>,
+}
+
+pub struct GameRecord {
+    pub moves: Vec<String>,
+    // In FEN, each before an engine's move.
+    pub positions: Vec<String>,
+    pub result: &'static str,
+    pub termination: &'static str,
+}
+
+pub fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    rand::srand((date::now() * 1000.0) as u64);
+    let mut rules = Rules::defaults();
+    if let Some(Err(e)) = options.ruleset.as_ref().map(|r| rules.import_ruleset(r)) {
+        eprintln!("Couldn't import ruleset: {}", e);
+        std::process::exit(1);
+    }
+    let [(a, config_a), (b, config_b)] = options.engines.clone();
+    let engines = [
+        (format!("Engine A ({})", a), Engine { config: config_a }),
+        (format!("Engine B ({})", b), Engine { config: config_b }),
+    ];
+    // Points for engine A, and its wins, draws and losses.
+    let mut score = 0.0;
+    let mut tally = [0; 3];
+    let mut out = String::new();
+    let mut training = String::new();
+    for i in 0..options.games {
+        let opening = &options.openings[(i / 2) % options.openings.len()];
+        // Engine A plays white in even games.
+        let (white, black) = if i % 2 == 0 {
+            (&engines[0], &engines[1])
+        } else {
+            (&engines[1], &engines[0])
+        };
+        let game = match play(&rules, &white.1, &black.1, opening, options.max_plies) {
+            Ok(g) => g,
+            Err(e) => {
+                eprintln!("Skipping opening \"{}\": {}", opening, e);
+                continue;
+            }
+        };
+        let a_points = match (game.result, i % 2 == 0) {
+            ("1-0", true) | ("0-1", false) => 1.0,
+            ("1/2-1/2", _) => 0.5,
+            _ => 0.0,
+        };
+        score += a_points;
+        tally[(2.0 - a_points * 2.0) as usize] += 1;
+        let tags = [
+            ("Event", "Self-play".to_string()),
+            ("Site", "?".to_string()),
+            ("Date", "????.??.??".to_string()),
+            ("Round", (i + 1).to_string()),
+            ("White", white.0.clone()),
+            ("Black", black.0.clone()),
+            ("Result", game.result.to_string()),
+            ("Variant", rules.name.clone()),
+            ("Opening", opening.clone()),
+            ("Termination", game.termination.to_string()),
+        ];
+        out.push_str(&pgn(&tags, &game.moves, game.result));
+        out.push('\n');
+        for fen in &game.positions {
+            training.push_str(&training_line(fen, game.result));
+        }
+        eprintln!(
+            "Game {}: {} vs {}: {} ({})",
+            i + 1,
+            white.0,
+            black.0,
+            game.result,
+            game.termination
+        );
+    }
+    match &options.pgn {
+        Some(path) => {
+            if let Err(e) = fs::write(path, &out) {
+                eprintln!("Couldn't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", out),
+    }
+    let written = options.training.as_ref().map(|path| {
+        fs::write(path, &training).map_err(|e| format!("Couldn't write {}: {}", path, e))
+    });
+    if let Some(Err(e)) = written {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let played: i32 = tally.iter().sum();
+    eprintln!(
+        "{} vs {}: +{} ={} -{}, {:.1}/{}",
+        engines[0].0, engines[1].0, tally[0], tally[1], tally[2], score, played
+    );
+}
+
+// Plays the opening moves, then lets the engines play until the game ends or max_plies is reached.
+pub fn play(
+    rules: &Rules,
+    white: &Engine,
+    black: &Engine,
+    opening: &str,
+    max_plies: u16,
+) -> Result<GameRecord, String> {
+    let mut pp = rules.initial_placements();
+    let mut gd = rules.initial_game_data();
+    let mut moves = Vec::new();
+    let mut positions = Vec::new();
+    for s in opening.split_whitespace() {
+        let (p, m) = parse_long_algebraic(rules, &pp, gd, s)
+            .ok_or_else(|| format!("{} isn't a legal move", s))?;
+        moves.push(san(rules, &pp, gd, p, m));
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+    }
+    loop {
+        if gd.ply > max_plies {
+            return Ok(GameRecord {
+                moves,
+                positions,
+                result: "1/2-1/2",
+                termination: "move limit",
+            });
+        }
+        let white_to_move = gd.player_to_move() == 0;
+        let engine = if white_to_move { white } else { black };
+        positions.push(fen(&pp, gd));
+        let (p, m) = match engine.best_move(rules, &pp, gd) {
+            Some(pm) => pm,
+            None if rules.lost_without_moves(white_to_move, &pp, gd) => {
+                return Ok(GameRecord {
+                    moves,
+                    positions,
+                    result: if white_to_move { "0-1" } else { "1-0" },
+                    termination: "checkmate",
+                });
+            }
+            None => {
+                return Ok(GameRecord {
+                    moves,
+                    positions,
+                    result: "1/2-1/2",
+                    termination: "stalemate",
+                });
+            }
+        };
+        moves.push(san(rules, &pp, gd, p, m));
+        Rules::make_move(p, m, &mut pp);
+        gd = GameData {
+            ply: m.game_data.ply + 1,
+            ..m.game_data
+        };
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        games: 10,
+        engines: [
+            ("3".to_string(), EngineConfig::level(3)),
+            ("3".to_string(), EngineConfig::level(3)),
+        ],
+        openings: OPENINGS.iter().map(|o| o.to_string()).collect(),
+        ruleset: None,
+        max_plies: 300,
+        pgn: None,
+        training: None,
+    };
+    while let Some(a) = args.next() {
+        if a == "--selfplay" {
+            continue;
+        }
+        if !FLAGS.contains(&a.as_str()) {
+            return Err(format!("Unknown argument: {}", a));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", a))?;
+        match a.as_str() {
+            "--games" => options.games = parse_number(&a, &value)?,
+            "--engine-a" => options.engines[0] = (value.clone(), parse_engine(&value)?),
+            "--engine-b" => options.engines[1] = (value.clone(), parse_engine(&value)?),
+            "--openings" => {
+                let s = read(&value)?;
+                options.openings = s
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if options.openings.is_empty() {
+                    return Err(format!("No openings in {}", value));
+                }
+            }
+            "--ruleset" => options.ruleset = Some(read(&value)?),
+            "--max-plies" => options.max_plies = parse_number(&a, &value)?,
+            "--pgn" => options.pgn = Some(value),
+            "--training" => options.training = Some(value),
+            _ => unreachable!(),
+        }
+    }
+    Ok(options)
+}
+
+// A strength, then comma separated overrides. See USAGE.
+pub fn parse_engine(s: &str) -> Result<EngineConfig, String> {
+    let mut parts = s.split(',');
+    let strength = parse_number("strength", parts.next().unwrap_or(""))?;
+    let mut config = EngineConfig::level(strength);
+    for part in parts {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value, got {}", part))?;
+        match key {
+            "depth" => config.depth = parse_number(key, value)?,
+            "time" => config.time_limit = parse_number(key, value)?,
+            "blunder" => config.blunder_chance = parse_number(key, value)?,
+            "contempt" => config.contempt = parse_number(key, value)?,
+            "quiescence" => config.quiescence_depth = parse_number(key, value)?,
+            "extensions" => config.check_extensions = parse_number(key, value)?,
+            "nullmove" => config.null_move = parse_number(key, value)?,
+            "lmr" => config.late_move_reductions = parse_number(key, value)?,
+            "pst" => config.pst = Some(load_tables(value)?),
+            _ => return Err(format!("Unknown engine setting: {}", key)),
+        }
+    }
+    Ok(config)
+}
+
+pub fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
+
+pub fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_engine() {
+        let config = parse_engine("4,depth=2,time=0.5,blunder=0").unwrap();
+        assert_eq!(config.depth, 2);
+        assert_eq!(config.time_limit, 0.5);
+        assert_eq!(config.blunder_chance, 0.0);
+        assert_eq!(
+            config.quiescence_depth,
+            EngineConfig::level(4).quiescence_depth
+        );
+        let config = parse_engine("3,nullmove=false").unwrap();
+        assert!(!config.null_move && config.late_move_reductions);
+        assert!(parse_engine("4,speed=3").is_err());
+        assert!(parse_engine("strong").is_err());
+    }
+
+    #[test]
+    fn test_play() {
+        let rules = Rules::defaults();
+        let engine = Engine {
+            config: parse_engine("1,blunder=0").unwrap(),
+        };
+        // Fool's mate, after which white has no moves.
+        let game = play(&rules, &engine, &engine, "f2f3 e7e5 g2g4 d8h4", 300).unwrap();
+        assert_eq!(game.moves, ["f3", "e5", "g4", "Qh4#"]);
+        assert_eq!((game.result, game.termination), ("0-1", "checkmate"));
+        // The opening was all the moves, so white's turn to move when mated is the only position
+        // the engines saw.
+        assert_eq!(
+            game.positions,
+            ["rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 0 3"]
+        );
+
+        let game = play(&rules, &engine, &engine, "e2e4", 10).unwrap();
+        assert_eq!(game.moves.len(), 10);
+        assert_eq!(game.result, "1/2-1/2");
+        assert!(play(&rules, &engine, &engine, "e2e5", 10).is_err());
+    }
+}
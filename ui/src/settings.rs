@@ -0,0 +1,337 @@
+// The player's preferences, applied as soon as they change without restarting the game. The
+// desktop binary keeps them in a JSON file: CHESS_SETTINGS, or chess-ui/settings.json in the
+// user's config directory. In the browser the page keeps them, e.g. in localStorage, and gives
+// them back to the game with set_settings when it loads. Either way they're a JSON object:
+//   {"theme": "classic", "sounds": true, "auto_queen": true, "coordinates": false,
+//    "animation_speed": 1.0, "input_mode": "drag", "ponder": false, "blunder_check": false,
+//    "bindings": {...}}
+// where bindings are the keys for playing with the keyboard (see controls.rs).
+// Fields that are left out keep their values, so an update can change just one of them.
+
+use macroquad::prelude::*;
+use serde_json::{json, Value};
+
+pub use chess_ui::core::InputMode;
+
+use crate::controls::Bindings;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Theme {
+    Classic,
+    Wood,
+    Gray,
+    // Black and white squares, in the accessible style (see Palette).
+    HighContrast,
+    // Blue and cream squares, colors that look different with any kind of color blindness, in the
+    // accessible style.
+    ColorBlind,
+}
+
+// How a theme draws the board. The accessible themes don't rely on color alone: dark squares are
+// hatched, the squares a moving piece is on are outlined rather than tinted, and the markers are
+// bigger, with a black edge so they stand out on light squares too. A test checks their colors
+// meet WCAG's contrast ratios.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub light: Color,
+    pub dark: Color,
+    // Diagonal lines over the dark squares.
+    pub hatching: Option<Color>,
+    // Marks the squares a moving piece is on or has stopped on.
+    pub highlight: Color,
+    // 0 tints the squares with highlight, otherwise it's an outline this thick.
+    pub outline: f32,
+    // Marks where a piece can go.
+    pub marker: Color,
+    // The marker's radius, as a fraction of a square.
+    pub marker_size: f32,
+    // Drawn around markers and outlines.
+    pub edge: Option<Color>,
+    // The color of the effects drawn when pieces are captured, promoted and so on (see
+    // effects.rs), and how many particles a capture's burst has.
+    pub effect: Color,
+    pub particles: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub theme: Theme,
+    // For the page, which plays any sounds. The game doesn't make any itself.
+    pub sounds: bool,
+    // Promote pawns to a queen when the rules allow one, rather than to the rules' first choice.
+    pub auto_queen: bool,
+    // Files and ranks along the board's edges.
+    pub coordinates: bool,
+    // How fast animations play, 1 being normal and 0 turning them off.
+    pub animation_speed: f32,
+    pub input_mode: InputMode,
+    // Let the computer opponent think on the player's time, about the reply it expects. Off by
+    // default, since it keeps the CPU busy while the player thinks.
+    pub ponder: bool,
+    // Ask the player before playing a move that loses material or allows mate (see blunder.rs).
+    // Rated online games go without it.
+    pub blunder_check: bool,
+    pub bindings: Bindings,
+}
+
+const THEMES: [(Theme, &str); 5] = [
+    (Theme::Classic, "classic"),
+    (Theme::Wood, "wood"),
+    (Theme::Gray, "gray"),
+    (Theme::HighContrast, "high_contrast"),
+    (Theme::ColorBlind, "color_blind"),
+];
+const INPUT_MODES: [(InputMode, &str); 2] =
+    [(InputMode::Drag, "drag"), (InputMode::Click, "click")];
+
+impl Theme {
+    // The theme after this one, and after the last one, the first.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn next(self) -> Theme {
+        let i = THEMES.iter().position(|&(t, _)| t == self).unwrap();
+        THEMES[(i + 1) % THEMES.len()].0
+    }
+
+    pub fn palette(self) -> Palette {
+        let plain = |light, dark| Palette {
+            light,
+            dark,
+            hatching: None,
+            highlight: Color::new(1.0, 0.85, 0.2, 0.5),
+            outline: 0.0,
+            marker: Color::new(0.1, 0.4, 0.1, 0.5),
+            marker_size: 1.0 / 6.0,
+            edge: None,
+            effect: Color::new(1.0, 0.75, 0.3, 0.9),
+            particles: 12,
+        };
+        let accessible = |light, dark, hatching, highlight, marker| Palette {
+            light,
+            dark,
+            hatching: Some(hatching),
+            highlight,
+            outline: 5.0,
+            marker,
+            marker_size: 0.25,
+            edge: Some(BLACK),
+            // Fewer, in the highlight's color, so they're as easy to see.
+            effect: highlight,
+            particles: 8,
+        };
+        match self {
+            Theme::Classic => plain(
+                Color::new(0.93, 1.0, 0.98, 1.0),
+                Color::new(0.4, 0.7, 0.7, 1.0),
+            ),
+            Theme::Wood => plain(
+                Color::new(0.94, 0.85, 0.71, 1.0),
+                Color::new(0.71, 0.53, 0.39, 1.0),
+            ),
+            Theme::Gray => plain(
+                Color::new(0.85, 0.85, 0.85, 1.0),
+                Color::new(0.55, 0.55, 0.55, 1.0),
+            ),
+            Theme::HighContrast => accessible(
+                Color::new(0.95, 0.95, 0.95, 1.0),
+                Color::new(0.3, 0.3, 0.3, 1.0),
+                Color::new(0.0, 0.0, 0.0, 0.5),
+                Color::new(1.0, 0.9, 0.0, 1.0),
+                Color::new(0.0, 0.9, 1.0, 1.0),
+            ),
+            Theme::ColorBlind => accessible(
+                Color::new(0.95, 0.9, 0.8, 1.0),
+                Color::new(0.0, 0.35, 0.6, 1.0),
+                Color::new(0.0, 0.0, 0.0, 0.35),
+                Color::new(1.0, 0.65, 0.0, 1.0),
+                Color::new(0.95, 0.9, 0.25, 1.0),
+            ),
+        }
+    }
+}
+
+impl Settings {
+    pub const DEFAULT: Settings = Settings {
+        theme: Theme::Classic,
+        sounds: true,
+        auto_queen: true,
+        coordinates: false,
+        animation_speed: 1.0,
+        input_mode: InputMode::Drag,
+        ponder: false,
+        blunder_check: false,
+        bindings: Bindings::DEFAULT,
+    };
+
+    pub fn to_json(self) -> String {
+        json!({
+            "theme": name(&THEMES, self.theme),
+            "sounds": self.sounds,
+            "auto_queen": self.auto_queen,
+            "coordinates": self.coordinates,
+            "animation_speed": self.animation_speed,
+            "input_mode": name(&INPUT_MODES, self.input_mode),
+            "ponder": self.ponder,
+            "blunder_check": self.blunder_check,
+            "bindings": self.bindings.to_json(),
+        })
+        .to_string()
+    }
+
+    // Changes the settings in the JSON object s, leaving the others as they are. Fields this
+    // version doesn't know are ignored, so settings saved by a later version still load. Nothing
+    // changes if any of the fields is invalid.
+    pub fn update(&mut self, s: &str) -> Result<(), String> {
+        let v: Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        let fields = v.as_object().ok_or("Settings must be a JSON object")?;
+        let mut new = *self;
+        for (k, v) in fields {
+            let invalid = || format!("Invalid {}: {}", k, v);
+            match k.as_str() {
+                "theme" => new.theme = named(&THEMES, v).ok_or_else(invalid)?,
+                "sounds" => new.sounds = v.as_bool().ok_or_else(invalid)?,
+                "auto_queen" => new.auto_queen = v.as_bool().ok_or_else(invalid)?,
+                "coordinates" => new.coordinates = v.as_bool().ok_or_else(invalid)?,
+                "animation_speed" => {
+                    new.animation_speed = v
+                        .as_f64()
+                        .filter(|&s| (0.0..=10.0).contains(&s))
+                        .ok_or_else(invalid)? as f32
+                }
+                "input_mode" => new.input_mode = named(&INPUT_MODES, v).ok_or_else(invalid)?,
+                "ponder" => new.ponder = v.as_bool().ok_or_else(invalid)?,
+                "blunder_check" => new.blunder_check = v.as_bool().ok_or_else(invalid)?,
+                "bindings" => new.bindings.update(v)?,
+                _ => {}
+            }
+        }
+        *self = new;
+        Ok(())
+    }
+
+    // Reads the settings file, falling back on the defaults for anything it doesn't say.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Settings {
+        let mut settings = Settings::DEFAULT;
+        let Some(path) = path() else {
+            return settings;
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(s) => {
+                if let Err(e) = settings.update(&s) {
+                    warn!("Ignoring {}: {}", path.display(), e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Couldn't read {}: {}", path.display(), e),
+        }
+        settings
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(self) {
+        let Some(path) = path() else {
+            warn!("Not saving settings: set CHESS_SETTINGS or HOME");
+            return;
+        };
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, self.to_json()));
+        if let Err(e) = saved {
+            warn!("Couldn't save settings to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn name<T: PartialEq>(names: &[(T, &'static str)], t: T) -> &'static str {
+    names.iter().find(|(n, _)| *n == t).unwrap().1
+}
+
+fn named<T: Copy>(names: &[(T, &str)], v: &Value) -> Option<T> {
+    let s = v.as_str()?;
+    names.iter().find(|(_, n)| *n == s).map(|&(t, _)| t)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path() -> Option<std::path::PathBuf> {
+    config_path("CHESS_SETTINGS", "settings.json")
+}
+
+// The file the environment variable var names, or else the one called name in chess-ui's directory
+// in the user's config directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn config_path(var: &str, name: &str) -> Option<std::path::PathBuf> {
+    use std::{env, path::PathBuf};
+
+    if let Ok(p) = env::var(var) {
+        return Some(PathBuf::from(p));
+    }
+    let config = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .ok()?;
+    Some(config.join("chess-ui").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let mut s = Settings::DEFAULT;
+        s.update(r#"{"theme": "wood", "coordinates": true, "future_setting": 3}"#)
+            .unwrap();
+        assert_eq!(s.theme, Theme::Wood);
+        assert!(s.coordinates);
+        assert_eq!(s.input_mode, InputMode::Drag);
+
+        // All or nothing.
+        let before = s;
+        assert!(s
+            .update(r#"{"input_mode": "click", "animation_speed": -1}"#)
+            .is_err());
+        assert!(s.update(r#"{"sounds": "yes"}"#).is_err());
+        assert!(s.update(r#"{"bindings": {"flip": "T"}}"#).is_err());
+        assert!(s.update("[]").is_err());
+        assert_eq!(s, before);
+
+        let mut loaded = Settings::DEFAULT;
+        loaded.update(&s.to_json()).unwrap();
+        assert_eq!(loaded, s);
+    }
+
+    // WCAG's contrast ratio, from 1 to 21.
+    fn contrast(a: Color, b: Color) -> f32 {
+        let luminance = |c: Color| {
+            let linear = |v: f32| {
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            };
+            0.2126 * linear(c.r) + 0.7152 * linear(c.g) + 0.0722 * linear(c.b)
+        };
+        let (a, b) = (luminance(a), luminance(b));
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    #[test]
+    fn test_accessible_contrast() {
+        for (theme, squares) in [(Theme::HighContrast, 7.0), (Theme::ColorBlind, 4.5)] {
+            let p = theme.palette();
+            assert!(contrast(p.light, p.dark) >= squares, "{:?}", theme);
+            // What's drawn on the board stands out from both kinds of square by at least 3:1,
+            // WCAG's ratio for graphics: the color itself on dark squares, and its edge on light
+            // ones.
+            for c in [p.highlight, p.marker] {
+                assert!(contrast(c, p.dark) >= 3.0, "{:?} {:?}", theme, c);
+            }
+            assert!(contrast(p.edge.unwrap(), p.light) >= 3.0, "{:?}", theme);
+            assert!(p.outline > 0.0 && p.marker_size > Theme::Classic.palette().marker_size);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// A compact binary form of a game: the position on the board, and the game's history (see
+// history.rs), its moves with a checkpoint position every so often. It's much smaller than FEN or
+// JSON for a long game, and simpler to read than PGN, so it's what the page keeps, e.g. in
+// IndexedDB, with get_snapshot and load_snapshot (see main.rs). A snapshot is:
+//   "CHSN", then the format version as a byte
+//   sections, each a tag byte, its length as a varint (LEB128) and that many bytes
+// with the sections:
+//   POSITION     the position on the board, required
+//   MOVES        a varint count, then each move in long algebraic notation, as a varint length and
+//                ASCII
+//   CHECKPOINTS  a varint count, then the positions after every CHECKPOINT_INTERVAL moves, from
+//                the start of the game
+// Version 1 had every position of the game instead of checkpoints, in a HISTORY section laid out
+// the same way, which is still read. A position is its width and height as bytes, the squares row
+// by row from a1 (see Board), a varint count of the squares with flags followed by each one's
+// index as a varint and its flags as a byte, and then the GameData's ply and mask, as
+// little-endian u16s.
+// Sections a reader doesn't know are skipped, so new ones can be added without breaking older
+// readers. VERSION only changes when a snapshot can't be read without knowing what changed, e.g.
+// CHECKPOINT_INTERVAL, and snapshots newer than that are rejected.
+
+use crate::{history::History, prelude::*};
+
+const MAGIC: &[u8] = b"CHSN";
+const VERSION: u8 = 2;
+
+const POSITION: u8 = 1;
+const HISTORY: u8 = 2;
+const MOVES: u8 = 3;
+const CHECKPOINTS: u8 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub board: PiecePlacements,
+    pub game_data: GameData,
+    pub history: History,
+}
+
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+
+    let mut position = Vec::new();
+    write_position(&mut position, &snapshot.board, snapshot.game_data);
+    write_section(&mut out, POSITION, &position);
+
+    let mut moves = Vec::new();
+    write_varint(&mut moves, snapshot.history.moves().len());
+    for m in snapshot.history.moves() {
+        write_varint(&mut moves, m.len());
+        moves.extend_from_slice(m.as_bytes());
+    }
+    write_section(&mut out, MOVES, &moves);
+
+    let mut checkpoints = Vec::new();
+    write_varint(&mut checkpoints, snapshot.history.checkpoints().len());
+    for (pp, gd) in snapshot.history.checkpoints() {
+        write_position(&mut checkpoints, pp, *gd);
+    }
+    write_section(&mut out, CHECKPOINTS, &checkpoints);
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Snapshot, String> {
+    let mut r = Reader(bytes);
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err("not a snapshot".to_string());
+    }
+    let version = r.byte()?;
+    if version > VERSION {
+        return Err(format!(
+            "snapshot version {} is newer than this version of the game reads ({})",
+            version, VERSION
+        ));
+    }
+    let mut position = None;
+    let mut positions = Vec::new();
+    let mut moves = Vec::new();
+    let mut checkpoints = Vec::new();
+    while !r.0.is_empty() {
+        let tag = r.byte()?;
+        let len = r.varint()?;
+        let mut section = Reader(r.take(len)?);
+        match tag {
+            POSITION => position = Some(section.position()?),
+            HISTORY => positions = section.positions()?,
+            CHECKPOINTS => checkpoints = section.positions()?,
+            MOVES => {
+                moves = (0..section.varint()?)
+                    .map(|_| {
+                        let len = section.varint()?;
+                        String::from_utf8(section.take(len)?.to_vec())
+                            .map_err(|_| "invalid move in snapshot".to_string())
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => {}
+        }
+    }
+    let (board, game_data) = position.ok_or("no position in snapshot")?;
+    let history = if !checkpoints.is_empty() {
+        History::from_checkpoints(moves, checkpoints)
+            .map_err(|e| format!("invalid snapshot: {}", e))?
+    } else if !positions.is_empty() {
+        if moves.len() + 1 != positions.len() {
+            return Err(format!(
+                "snapshot has {} moves for {} positions",
+                moves.len(),
+                positions.len()
+            ));
+        }
+        History::from_positions(&positions, &moves)
+    } else if moves.is_empty() {
+        History::new((board, game_data))
+    } else {
+        return Err("snapshot has moves but no history".to_string());
+    };
+    Ok(Snapshot {
+        board,
+        game_data,
+        history,
+    })
+}
+
+fn write_section(out: &mut Vec<u8>, tag: u8, section: &[u8]) {
+    out.push(tag);
+    write_varint(out, section.len());
+    out.extend_from_slice(section);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_position(out: &mut Vec<u8>, pp: &PiecePlacements, gd: GameData) {
+    out.push(pp.width() as u8);
+    out.push(pp.height() as u8);
+    out.extend_from_slice(pp.as_slice());
+    let flags = pp.flags_slice();
+    write_varint(out, flags.iter().filter(|&&f| f != 0).count());
+    for (i, &f) in flags.iter().enumerate().filter(|&(_, &f)| f != 0) {
+        write_varint(out, i);
+        out.push(f);
+    }
+    let GameData { ply, mask } = gd;
+    out.extend_from_slice(&ply.to_le_bytes());
+    out.extend_from_slice(&mask.to_le_bytes());
+}
+
+// What's left to read of a snapshot, or of a section of it.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.0.len() {
+            return Err("snapshot is cut short".to_string());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn varint(&mut self) -> Result<usize, String> {
+        let mut n = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err("invalid number in snapshot".to_string())
+    }
+
+    // A varint count, then that many positions.
+    fn positions(&mut self) -> Result<Vec<(PiecePlacements, GameData)>, String> {
+        (0..self.varint()?).map(|_| self.position()).collect()
+    }
+
+    fn position(&mut self) -> Result<(PiecePlacements, GameData), String> {
+        let (width, height) = (self.byte()? as usize, self.byte()? as usize);
+        if !(1..=MAX_BOARD_SIZE).contains(&width) || !(1..=MAX_BOARD_SIZE).contains(&height) {
+            return Err(format!(
+                "invalid board size in snapshot: {}x{}",
+                width, height
+            ));
+        }
+        let mut pp = PiecePlacements::new(width, height);
+        for (i, &name) in self.take(width * height)?.iter().enumerate() {
+            pp.set(i / width + 1, i % width + 1, name);
+        }
+        for _ in 0..self.varint()? {
+            let i = self.varint()?;
+            if i >= width * height {
+                return Err("invalid square in snapshot".to_string());
+            }
+            pp.set_flags(i / width + 1, i % width + 1, self.byte()?);
+        }
+        let (ply, mask) = (self.u16()?, self.u16()?);
+        Ok((pp, GameData { ply, mask }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notation::parse_fen;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let rules = Rules::defaults();
+        let start = (rules.initial_placements(), rules.initial_game_data());
+        let mut history = History::new(start);
+        let mut positions = vec![start];
+        let (mut pp, gd) =
+            parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        pp.set_flags(4, 5, PF_MOVED);
+        history.push("e2e4".to_string(), (pp, gd));
+        positions.push((pp, gd));
+        let snapshot = Snapshot {
+            board: pp,
+            game_data: gd,
+            history: history.clone(),
+        };
+        let bytes = encode(&snapshot);
+        // A fraction of the boards' size in memory.
+        assert!(bytes.len() < 200, "{} bytes", bytes.len());
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert_eq!(decoded.board.flags(4, 5), PF_MOVED);
+
+        // Sections from newer versions are skipped.
+        let mut newer = bytes.clone();
+        write_section(&mut newer, 99, b"clocks");
+        assert_eq!(decode(&newer).unwrap(), snapshot);
+        let mut incompatible = bytes.clone();
+        incompatible[MAGIC.len()] = VERSION + 1;
+        assert!(decode(&incompatible).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(b"{\"fen\": \"\"}").is_err());
+
+        // Version 1, with every position.
+        let mut v1 = MAGIC.to_vec();
+        v1.push(1);
+        let mut position = Vec::new();
+        write_position(&mut position, &pp, gd);
+        write_section(&mut v1, POSITION, &position);
+        let mut all = Vec::new();
+        write_varint(&mut all, positions.len());
+        for (pp, gd) in &positions {
+            write_position(&mut all, pp, *gd);
+        }
+        write_section(&mut v1, HISTORY, &all);
+        write_section(&mut v1, MOVES, &[1, 4, b'e', b'2', b'e', b'4']);
+        assert_eq!(decode(&v1).unwrap(), snapshot);
+    }
+}
@@ -0,0 +1,65 @@
+// The teaching preset, for playing with a beginner at the board or against the computer at its
+// weakest: the aids that are usually off, all on. Where a piece can go is marked while it's
+// dragged too, not just once it's clicked. After a move that leaves one of the player's pieces to
+// be won, by a capture static exchange evaluation (see engine::see) says gains material, the status
+// line warns about it. And when moving by clicks, clicking another of the player's pieces picks it
+// up instead of only putting the first back. Takebacks need nothing more: away from online games
+// the player can always take back as many moves as they like. The browser picks it with
+// set_teaching, and the desktop binary's menu has it against the computer at strength 1.
+
+use crate::{
+    engine::{piece_value, see},
+    prelude::*,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aids {
+    // Mark where the piece being dragged can go.
+    pub hints: bool,
+    // Warn about pieces left to be won.
+    pub hanging: bool,
+    // Clicking another piece picks it up instead (see GameCore::change_piece).
+    pub change_piece: bool,
+}
+
+impl Aids {
+    pub const TEACHING: Aids = Aids {
+        hints: true,
+        hanging: true,
+        change_piece: true,
+    };
+}
+
+// The most valuable of the player's pieces the other player, whose turn it is, can win by taking
+// it, and its square.
+pub fn hanging(rules: &Rules, pp: &PiecePlacements, gd: GameData) -> Option<(u8, (usize, usize))> {
+    rules
+        .legal_moves(gd.player_to_move(), pp, gd)
+        .into_iter()
+        .filter_map(|(p, m)| match m.typ {
+            MoveType::Capture { row, col } if see(rules, pp, p, m) > 0 => {
+                let (row, col) = (row as usize, col as usize);
+                Some((pp.get(row, col), (row, col)))
+            }
+            _ => None,
+        })
+        .max_by_key(|&(name, _)| piece_value(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::parse_fen;
+
+    use super::*;
+
+    #[test]
+    fn test_hanging() {
+        let rules = Rules::defaults();
+        // Black to move can take the knight on f3 for nothing, and the pawn on e4 too.
+        let (pp, gd) = parse_fen("4k3/8/8/8/4P1q1/5N2/8/4K3 b - - 0 1").unwrap();
+        assert_eq!(hanging(&rules, &pp, gd), Some((b'N', (3, 6))));
+        // Defended by the pawn, taking the knight loses the queen.
+        let (pp, gd) = parse_fen("4k3/8/8/8/6q1/5N2/4P3/4K3 b - - 0 1").unwrap();
+        assert_eq!(hanging(&rules, &pp, gd), None);
+    }
+}
@@ -0,0 +1,275 @@
+// Fits the piece-square tables the engine's evaluation can add to the material (see
+// PieceSquareTables) to the results of games, with Texel's method: the evaluation of each position,
+// turned into white's expected score, should be as close as possible to how the game it's from
+// ended. Collect positions with --selfplay --training FILE, fit the tables with --tune FILE (see
+// USAGE), then see whether they help with --selfplay --engine-a 3,pst=TABLES.
+
+use std::fs;
+
+use serde_json::{json, Map, Value};
+
+use chess_ui::notation::parse_fen;
+
+use crate::engine::{piece_value, PieceSquareTables, PST_PIECES};
+
+const USAGE: &str = "\
+Usage: chess-ui --tune FILE [options]
+
+FILE has a position per line in FEN, then a comma and how the game it's from ended for white: 1,
+0.5 or 0. --selfplay --training FILE writes them.
+
+Options:
+  --iterations N  Stop after this many steps, if the tables haven't stopped improving by then
+                  (default 500)
+  --out FILE      Write the tables here instead of to stdout";
+
+// A position to fit the tables to.
+struct Sample {
+    // White's material minus black's, in centipawns.
+    material: i32,
+    // The table entries for the pieces on the board, as (table, index, count), where count is the
+    // number of white pieces on the square minus the number of black pieces on the mirrored one.
+    features: Vec<(usize, usize, i32)>,
+    // For white: 1 for a win, 0.5 for a draw and 0 for a loss.
+    result: f64,
+}
+
+type Tables = [[i32; 64]; 6];
+
+pub fn main() {
+    let mut args = std::env::args().skip_while(|a| a != "--tune").skip(1);
+    let mut path = None;
+    let mut iterations = 500;
+    let mut out = None;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--iterations" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => iterations = n,
+                None => usage(),
+            },
+            "--out" => out = Some(args.next().unwrap_or_else(|| usage())),
+            _ if path.is_none() => path = Some(a),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let input = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let mut samples = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_sample(line) {
+            Ok(s) => samples.push(s),
+            Err(e) => eprintln!("Skipping line {}: {}", i + 1, e),
+        }
+    }
+    if samples.is_empty() {
+        eprintln!("No positions in {}", path);
+        std::process::exit(1);
+    }
+    let before = error(&samples, &[[0; 64]; 6]);
+    let (tables, steps) = tune(&samples, iterations);
+    eprintln!(
+        "Fitted to {} positions in {} steps: error {:.5} -> {:.5}",
+        samples.len(),
+        steps,
+        before,
+        error(&samples, &tables)
+    );
+    let json = format!("{}\n", to_json(&PieceSquareTables(tables)));
+    match out {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, json) {
+                eprintln!("Couldn't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", json),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+// A line of a training file, e.g. for --selfplay --training.
+pub fn training_line(fen: &str, result: &str) -> String {
+    let score = match result {
+        "1-0" => "1",
+        "0-1" => "0",
+        _ => "0.5",
+    };
+    format!("{},{}\n", fen, score)
+}
+
+// Reads tables written by --tune, for the rest of the run. Pieces left out have no bonuses.
+pub fn load_tables(path: &str) -> Result<&'static PieceSquareTables, String> {
+    let s = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    Ok(Box::leak(Box::new(parse_tables(&s)?)))
+}
+
+fn parse_tables(s: &str) -> Result<PieceSquareTables, String> {
+    let v: Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+    let fields = v.as_object().ok_or("Tables must be a JSON object")?;
+    let mut tables = [[0; 64]; 6];
+    for (table, &name) in tables.iter_mut().zip(PST_PIECES.iter()) {
+        let Some(values) = fields.get(&(name as char).to_string()) else {
+            continue;
+        };
+        let invalid = || format!("Invalid table for {}", name as char);
+        let values = values
+            .as_array()
+            .filter(|a| a.len() == 64)
+            .ok_or_else(invalid)?;
+        for (t, v) in table.iter_mut().zip(values) {
+            *t = v.as_i64().ok_or_else(invalid)? as i32;
+        }
+    }
+    Ok(PieceSquareTables(tables))
+}
+
+fn to_json(tables: &PieceSquareTables) -> Value {
+    let fields: Map<String, Value> = PST_PIECES
+        .iter()
+        .zip(tables.0.iter())
+        .map(|(&name, table)| ((name as char).to_string(), json!(table.to_vec())))
+        .collect();
+    Value::Object(fields)
+}
+
+fn parse_sample(line: &str) -> Result<Sample, String> {
+    let (fen, result) = line.rsplit_once(',').ok_or("Expected FEN,RESULT")?;
+    let result: f64 = result
+        .trim()
+        .parse()
+        .ok()
+        .filter(|r| (0.0..=1.0).contains(r))
+        .ok_or_else(|| format!("Invalid result: {}", result))?;
+    let (pp, _) = parse_fen(fen.trim())?;
+    if (pp.width(), pp.height()) != (8, 8) {
+        return Err("Piece-square tables are for 8x8 boards".to_string());
+    }
+    let mut material = 0;
+    let mut features: Vec<(usize, usize, i32)> = Vec::new();
+    for (r, c, n) in pp.squares() {
+        if n == 0 {
+            continue;
+        }
+        let sign = if n.is_ascii_uppercase() { 1 } else { -1 };
+        material += sign * piece_value(n);
+        let Some((t, i)) = PieceSquareTables::index(n, r, c) else {
+            continue;
+        };
+        match features.iter_mut().find(|f| (f.0, f.1) == (t, i)) {
+            Some(f) => f.2 += sign,
+            None => features.push((t, i, sign)),
+        }
+    }
+    Ok(Sample {
+        material,
+        features,
+        result,
+    })
+}
+
+// White's expected score for the evaluation, from 0 to 1, where a pawn ahead is about 64%.
+fn win_chance(eval: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-eval as f64 / 400.0))
+}
+
+fn evaluate(sample: &Sample, tables: &Tables) -> i32 {
+    let bonuses: i32 = sample
+        .features
+        .iter()
+        .map(|&(t, i, count)| count * tables[t][i])
+        .sum();
+    sample.material + bonuses
+}
+
+// The mean squared difference between the results and the expected scores.
+fn error(samples: &[Sample], tables: &Tables) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|s| (s.result - win_chance(evaluate(s, tables))).powi(2))
+        .sum();
+    total / samples.len() as f64
+}
+
+// Starting with no bonuses, moves every entry a centipawn at a time in whichever direction lowers
+// the error, until it stops going down or after iterations steps. Returns the tables and the
+// number of steps taken.
+fn tune(samples: &[Sample], iterations: usize) -> (Tables, usize) {
+    let mut tables = [[0; 64]; 6];
+    let mut best = error(samples, &tables);
+    for step in 0..iterations {
+        // Each entry's share of the error's gradient, up to a positive factor, which doesn't
+        // change which way it points.
+        let mut gradient = [[0.0; 64]; 6];
+        for s in samples {
+            let p = win_chance(evaluate(s, &tables));
+            let d = (p - s.result) * p * (1.0 - p);
+            for &(t, i, count) in &s.features {
+                gradient[t][i] += d * count as f64;
+            }
+        }
+        let mut next = tables;
+        for (row, g) in next.iter_mut().zip(gradient.iter()) {
+            for (v, &g) in row.iter_mut().zip(g.iter()) {
+                if g > 0.0 {
+                    *v -= 1;
+                } else if g < 0.0 {
+                    *v += 1;
+                }
+            }
+        }
+        let e = error(samples, &next);
+        if e >= best {
+            return (tables, step);
+        }
+        (tables, best) = (next, e);
+    }
+    (tables, iterations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tune() {
+        // A knight in the center wins, and one in the corner loses.
+        let center = "4k3/8/8/8/4N3/8/8/4K3 w - - 0 1";
+        let corner = "4k3/8/8/8/8/8/8/N3K3 w - - 0 1";
+        let lines = [training_line(center, "1-0"), training_line(corner, "0-1")];
+        assert_eq!(lines[1], format!("{},0\n", corner));
+        let samples: Vec<_> = lines
+            .iter()
+            .map(|l| parse_sample(l.trim()).unwrap())
+            .collect();
+        assert_eq!(samples[0].material, 320);
+
+        let (tables, steps) = tune(&samples, 50);
+        assert_eq!(steps, 50);
+        let (knight, e4) = PieceSquareTables::index(b'N', 4, 5).unwrap();
+        let (_, a1) = PieceSquareTables::index(b'N', 1, 1).unwrap();
+        assert_eq!((tables[knight][e4], tables[knight][a1]), (50, -50));
+        // Both kings are on their starting squares, which cancel out.
+        assert_eq!(tables[PST_PIECES.len() - 1], [0; 64]);
+        assert!(error(&samples, &tables) < error(&samples, &[[0; 64]; 6]));
+
+        let json = to_json(&PieceSquareTables(tables)).to_string();
+        assert_eq!(parse_tables(&json).unwrap().0, tables);
+        assert_eq!(parse_tables("{}").unwrap().0, [[0; 64]; 6]);
+        assert!(parse_tables(r#"{"n": [1, 2]}"#).is_err());
+        assert!(parse_sample("4k3/8/8/8/8/8/8/4K3 w - - 0 1,2").is_err());
+    }
+}
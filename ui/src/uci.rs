@@ -0,0 +1,333 @@
+// Speaks the Universal Chess Interface, so chess GUIs and tools like cutechess-cli can play and
+// analyze with the engine: run the desktop binary with --uci. Commands are read from stdin and
+// answers written to stdout, including why a position couldn't be set up, as an info string. While
+// searching, an info line is written for each completed depth, with the counters from SearchInfo.
+// There's no hash table, so info lines have no hashfull.
+
+use std::{
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use macroquad::miniquad::date;
+
+use chess_ui::notation::{long_algebraic, parse_fen, parse_long_algebraic};
+
+use crate::{
+    engine::{mate_in, Analysis, AnalysisInfo, SearchInfo},
+    prelude::*,
+};
+
+// How long a search runs between checking for commands, in seconds.
+const POLL_INTERVAL: f64 = 0.1;
+// When the GUI gives the remaining time instead of a time per move, how many more moves to expect.
+const MOVES_TO_GO: f64 = 30.0;
+
+// The stop flag of the search in progress, if any.
+type CurrentStop = Arc<Mutex<Option<Arc<AtomicBool>>>>;
+
+struct Uci<'a> {
+    rules: Rules<'a>,
+    pp: PiecePlacements,
+    gd: GameData,
+    // Shared with the thread reading stdin, which sets it on stop or quit, since a root move can
+    // take longer to search than a GUI waits.
+    stop: CurrentStop,
+}
+
+// What go asked for.
+#[derive(Debug, PartialEq)]
+struct Limits {
+    depth: u32,
+    // In seconds.
+    time: Option<f64>,
+    // Keep searching until stop, even once there's nothing left to search.
+    infinite: bool,
+}
+
+pub fn main() {
+    let stop = CurrentStop::default();
+    let reader_stop = stop.clone();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            let stopping = matches!(line.trim(), "stop" | "quit");
+            if let Some(stop) = reader_stop.lock().unwrap().as_ref().filter(|_| stopping) {
+                stop.store(true, Ordering::Relaxed);
+            }
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut uci = Uci::new(Rules::defaults(), stop);
+    if let Err(e) = uci.run(&rx, &mut io::stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+impl<'a> Uci<'a> {
+    fn new(rules: Rules<'a>, stop: CurrentStop) -> Self {
+        Self {
+            pp: rules.initial_placements(),
+            gd: rules.initial_game_data(),
+            rules,
+            stop,
+        }
+    }
+
+    // Handles lines until quit or the end of input.
+    fn run(&mut self, lines: &Receiver<String>, out: &mut impl Write) -> io::Result<()> {
+        while let Ok(line) = lines.recv() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["uci"] => {
+                    writeln!(out, "id name chess-ui")?;
+                    writeln!(out, "id author d5h")?;
+                    writeln!(out, "uciok")?;
+                }
+                ["isready"] => writeln!(out, "readyok")?,
+                ["ucinewgame"] => {
+                    self.pp = self.rules.initial_placements();
+                    self.gd = self.rules.initial_game_data();
+                }
+                ["position", args @ ..] => {
+                    // The GUI may not show stderr, so it's told too.
+                    if let Err(e) = self.set_position(args) {
+                        eprintln!("{}", e);
+                        writeln!(out, "info string {}", e)?;
+                    }
+                }
+                ["go", args @ ..] => {
+                    let white = self.gd.player_to_move() == 0;
+                    if !self.go(limits(args, white), lines, out)? {
+                        break;
+                    }
+                }
+                ["quit"] => break,
+                // Stop when not searching, and anything else.
+                _ => eprintln!("Ignoring: {}", line),
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    // args are "startpos" or "fen" and a FEN, then optionally "moves" and moves in long algebraic
+    // notation.
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args
+            .iter()
+            .position(|&a| a == "moves")
+            .unwrap_or(args.len());
+        let (mut pp, mut gd) = match &args[..moves_at] {
+            ["startpos"] => (
+                self.rules.initial_placements(),
+                self.rules.initial_game_data(),
+            ),
+            ["fen", fen @ ..] => parse_fen(&fen.join(" "))?,
+            _ => return Err(format!("Invalid position: {}", args.join(" "))),
+        };
+        for s in args.iter().skip(moves_at + 1) {
+            let (p, m) = parse_long_algebraic(&self.rules, &pp, gd, s)
+                .ok_or_else(|| format!("Illegal move: {}", s))?;
+            Rules::make_move(p, m, &mut pp);
+            gd = GameData {
+                ply: m.game_data.ply + 1,
+                ..m.game_data
+            };
+        }
+        (self.pp, self.gd) = (pp, gd);
+        Ok(())
+    }
+
+    // Searches the position until the limits are reached or the GUI says stop, then writes the
+    // best move. Returns false if the GUI said quit meanwhile.
+    fn go(
+        &mut self,
+        limits: Limits,
+        lines: &Receiver<String>,
+        out: &mut impl Write,
+    ) -> io::Result<bool> {
+        let stop = Arc::new(AtomicBool::new(false));
+        *self.stop.lock().unwrap() = Some(stop.clone());
+        if let Some(time) = limits.time {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_secs_f64(time));
+                stop.store(true, Ordering::Relaxed);
+            });
+        }
+        let white = self.gd.player_to_move() == 0;
+        let mut analysis = Analysis::new(self.pp, self.gd, 1);
+        analysis.max_depth = limits.depth;
+        let mut quit = false;
+        loop {
+            // With nothing left to search, an infinite search waits for stop.
+            let waiting = limits.infinite && analysis.done();
+            let line = if waiting {
+                lines.recv().ok()
+            } else {
+                lines.try_recv().ok()
+            };
+            match line.as_deref().map(str::trim) {
+                Some("isready") => writeln!(out, "readyok")?,
+                Some("stop") => stop.store(true, Ordering::Relaxed),
+                Some("quit") => {
+                    stop.store(true, Ordering::Relaxed);
+                    quit = true;
+                }
+                // The end of input.
+                None if waiting => stop.store(true, Ordering::Relaxed),
+                _ => {}
+            }
+            if stop.load(Ordering::Relaxed) || (analysis.done() && !limits.infinite) {
+                break;
+            }
+            if waiting {
+                continue;
+            }
+            let until = date::now() + POLL_INTERVAL;
+            let updated = analysis.step(&self.rules, until, Some(&stop));
+            // A position without moves has nothing to report.
+            if let Some(info) = analysis
+                .info
+                .as_ref()
+                .filter(|i| updated && !i.lines.is_empty())
+            {
+                writeln!(out, "{}", info_line(info, &analysis.search_info(), white))?;
+                out.flush()?;
+            }
+        }
+        *self.stop.lock().unwrap() = None;
+        match analysis.best_move() {
+            Some((p, m)) => writeln!(out, "bestmove {}", long_algebraic(p, m))?,
+            // The game is over.
+            None => writeln!(out, "bestmove 0000")?,
+        }
+        Ok(!quit)
+    }
+}
+
+// The limits in go's arguments, for white or black to move. Unknown arguments are ignored.
+fn limits(args: &[&str], white: bool) -> Limits {
+    let mut limits = Limits {
+        depth: Analysis::MAX_DEPTH,
+        time: None,
+        infinite: false,
+    };
+    let (mut remaining, mut increment, mut moves_to_go) = (None, 0.0, MOVES_TO_GO);
+    let (time, inc) = if white {
+        ("wtime", "winc")
+    } else {
+        ("btime", "binc")
+    };
+    let mut args = args.iter();
+    while let Some(&a) = args.next() {
+        if a == "infinite" {
+            limits.infinite = true;
+            continue;
+        }
+        let Some(value) = args.next().and_then(|v| v.parse::<f64>().ok()) else {
+            continue;
+        };
+        match a {
+            "depth" => limits.depth = (value as u32).clamp(1, Analysis::MAX_DEPTH),
+            "movetime" => limits.time = Some(value / 1000.0),
+            "movestogo" => moves_to_go = value.max(1.0),
+            _ if a == time => remaining = Some(value / 1000.0),
+            _ if a == inc => increment = value / 1000.0,
+            _ => {}
+        }
+    }
+    if let (None, Some(remaining)) = (limits.time, remaining) {
+        // Leave some time for the GUI, in case the search overshoots.
+        limits.time = Some((remaining / moves_to_go + increment / 2.0).min(remaining * 0.8));
+    }
+    if limits.infinite {
+        limits.time = None;
+    }
+    limits
+}
+
+// E.g. "info depth 4 seldepth 9 nodes 5120 nps 20480 time 250 score cp 35 pv e2e4 e7e5".
+fn info_line(info: &AnalysisInfo, search: &SearchInfo, white: bool) -> String {
+    // From the point of view of the player to move.
+    let score = if white { info.score } else { -info.score };
+    let score = match mate_in(score) {
+        Some(n) => format!("mate {}", n),
+        None => format!("cp {}", score),
+    };
+    let pv: Vec<String> = search
+        .pv
+        .iter()
+        .map(|&(p, m)| long_algebraic(p, m))
+        .collect();
+    format!(
+        "info depth {} seldepth {} nodes {} nps {} time {} score {} pv {}",
+        info.depth,
+        search.seldepth,
+        search.nodes,
+        search.nps(),
+        (search.elapsed * 1000.0) as u64,
+        score,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_ui::notation::fen;
+
+    use super::*;
+
+    #[test]
+    fn test_uci() {
+        let (tx, rx) = mpsc::channel();
+        for line in [
+            "uci",
+            "isready",
+            "position startpos moves e2e4 e7e5",
+            "position fen not a fen",
+            "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "go depth 2",
+        ] {
+            tx.send(line.to_string()).unwrap();
+        }
+        drop(tx);
+        let mut uci = Uci::new(Rules::defaults(), CurrentStop::default());
+        let mut out = Vec::new();
+        uci.run(&rx, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[..4],
+            ["id name chess-ui", "id author d5h", "uciok", "readyok"]
+        );
+        assert!(lines[4].starts_with("info string "));
+        assert!(lines[5].starts_with("info depth 1 "));
+        assert!(lines[6].starts_with("info depth 2 "));
+        assert!(lines[6].contains(" score mate 1 pv a1a8"));
+        assert_eq!(lines[7..], ["bestmove a1a8"]);
+
+        let mut uci = Uci::new(Rules::defaults(), CurrentStop::default());
+        uci.set_position(&["startpos", "moves", "e2e4", "e7e5"])
+            .unwrap();
+        assert_eq!(
+            fen(&uci.pp, uci.gd),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+        assert!(uci.set_position(&["startpos", "moves", "e2e5"]).is_err());
+
+        let limits = limits(&["wtime", "60000", "btime", "1000", "winc", "2000"], true);
+        assert_eq!(limits.time, Some(60.0 / MOVES_TO_GO + 1.0));
+    }
+}
@@ -0,0 +1,192 @@
+// Variations played out in analysis, from earlier positions of the game. They're kept in the game's
+// notes (see notation::Notes), with the ones from a loaded PGN, so they're listed and exported the
+// same way, and can have variations of their own. A variation is found by its path from the
+// game's moves: the index of the move it's an alternative to and which of that move's variations
+// it is, then the same in that variation, and so on.
+//
+// The board shows a variation after the moves leading to it: the game's moves up to the first
+// move the path replaces, then the first variation's up to the next, and so on, then all of the
+// last one's.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use chess_ui::notation::{glyph, Notes};
+
+pub type Path = Vec<(usize, usize)>;
+
+// The moves of the variation at path, which isn't empty, in SAN.
+pub fn line<'a>(
+    notes: &'a BTreeMap<usize, Notes>,
+    path: &[(usize, usize)],
+) -> Option<&'a Vec<(String, Notes)>> {
+    let (&(at, v), rest) = path.split_first()?;
+    let mut line = notes.get(&at)?.variations.get(v)?;
+    for &(at, v) in rest {
+        line = line.get(at)?.1.variations.get(v)?;
+    }
+    Some(line)
+}
+
+fn line_mut<'a>(
+    notes: &'a mut BTreeMap<usize, Notes>,
+    path: &[(usize, usize)],
+) -> Option<&'a mut Vec<(String, Notes)>> {
+    let (&(at, v), rest) = path.split_first()?;
+    let mut line = notes.get_mut(&at)?.variations.get_mut(v)?;
+    for &(at, v) in rest {
+        line = line.get_mut(at)?.1.variations.get_mut(v)?;
+    }
+    Some(line)
+}
+
+// The notes of move i of the variation at path, or of the game's move i if path is empty.
+pub fn notes_mut<'a>(
+    notes: &'a mut BTreeMap<usize, Notes>,
+    path: &[(usize, usize)],
+    i: usize,
+) -> Option<&'a mut Notes> {
+    if path.is_empty() {
+        return Some(notes.entry(i).or_default());
+    }
+    line_mut(notes, path)?.get_mut(i).map(|(_, n)| n)
+}
+
+// Where the board's move i is, with the variation at path shown: in which of the lines leading to
+// it, 0 for the game's moves and n for the variation at path[..n], and at what index in that line.
+pub fn locate(path: &[(usize, usize)], mut i: usize) -> (usize, usize) {
+    for (level, &(at, _)) in path.iter().enumerate() {
+        if i < at {
+            return (level, i);
+        }
+        i -= at;
+    }
+    (path.len(), i)
+}
+
+// How many moves of the board come before the first move of the variation at path.
+pub fn start(path: &[(usize, usize)]) -> usize {
+    path.iter().map(|&(at, _)| at).sum()
+}
+
+// Plays san instead of move i of the line at path: finds the variation of that move starting with
+// it, or adds one, and returns its path.
+pub fn branch(
+    notes: &mut BTreeMap<usize, Notes>,
+    path: &[(usize, usize)],
+    i: usize,
+    san: &str,
+) -> Option<Path> {
+    let variations = &mut notes_mut(notes, path, i)?.variations;
+    let v = match variations
+        .iter()
+        .position(|v| v.first().is_some_and(|(m, _)| same_move(m, san)))
+    {
+        Some(v) => v,
+        None => {
+            variations.push(vec![(san.to_string(), Notes::default())]);
+            variations.len() - 1
+        }
+    };
+    let mut path = path.to_vec();
+    path.push((i, v));
+    Some(path)
+}
+
+// Adds san to the end of the variation at path.
+pub fn extend(notes: &mut BTreeMap<usize, Notes>, path: &[(usize, usize)], san: &str) {
+    if let Some(line) = line_mut(notes, path) {
+        line.push((san.to_string(), Notes::default()));
+    }
+}
+
+// The moves of the line at path, the game's if it's empty, for get_move_list. start is how many
+// moves of the board come before the line's first.
+pub fn move_list(line: &[(String, Notes)], path: &[(usize, usize)], start: usize) -> Vec<Value> {
+    let mut list = Vec::new();
+    for (i, (san, notes)) in line.iter().enumerate() {
+        let glyphs: String = notes
+            .nags
+            .iter()
+            .map(|&n| glyph(n).map_or(format!("${}", n), String::from))
+            .collect();
+        let variations: Vec<_> = notes
+            .variations
+            .iter()
+            .enumerate()
+            .map(|(v, moves)| {
+                let mut path = path.to_vec();
+                path.push((i, v));
+                move_list(moves, &path, start + i)
+            })
+            .collect();
+        list.push(json!({
+            "path": path,
+            "view": start + i + 1,
+            "san": san,
+            "nags": notes.nags,
+            "glyphs": glyphs,
+            "before": notes.before,
+            "comment": notes.comment,
+            "variations": variations,
+        }));
+    }
+    list
+}
+
+// SAN from a PGN may leave out check marks or write castling with zeros.
+fn same_move(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| s.trim_end_matches(['+', '#']).replace('0', "O");
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variations() {
+        let mut notes = BTreeMap::new();
+        // 1. e4 e5 2. Nf3, with 1... c5 instead of e5, then 2. c3 instead of 2. Nf3 in it.
+        let c5 = branch(&mut notes, &[], 1, "c5").unwrap();
+        assert_eq!(c5, [(1, 0)]);
+        extend(&mut notes, &c5, "Nf3");
+        let c3 = branch(&mut notes, &c5, 1, "c3").unwrap();
+        assert_eq!(c3, [(1, 0), (1, 0)]);
+        assert_eq!(line(&notes, &c5).unwrap().len(), 2);
+        assert_eq!(line(&notes, &c3).unwrap()[0].0, "c3");
+
+        // Playing a variation's first move again follows it.
+        assert_eq!(branch(&mut notes, &[], 1, "c5+"), Some(c5.clone()));
+        assert_eq!(branch(&mut notes, &[], 1, "e6").unwrap(), [(1, 1)]);
+        assert_eq!(notes[&1].variations.len(), 2);
+
+        // The board for c3 is e4, c5, c3.
+        assert_eq!(start(&c3), 2);
+        assert_eq!(locate(&c3, 0), (0, 0));
+        assert_eq!(locate(&c3, 1), (1, 0));
+        assert_eq!(locate(&c3, 2), (2, 0));
+        assert_eq!(locate(&c3, 3), (2, 1));
+
+        notes_mut(&mut notes, &c3, 0).unwrap().comment = Some("Alapin".to_string());
+        assert_eq!(
+            line(&notes, &c3).unwrap()[0].1.comment.as_deref(),
+            Some("Alapin")
+        );
+        assert!(notes_mut(&mut notes, &c3, 5).is_none());
+        assert!(line(&notes, &[(0, 0)]).is_none());
+
+        let game = [
+            ("e4".to_string(), Notes::default()),
+            ("e5".to_string(), notes[&1].clone()),
+        ];
+        let list = move_list(&game, &[], 0);
+        assert_eq!(list[1]["view"], 2);
+        let c3 = &list[1]["variations"][0][1]["variations"][0][0];
+        assert_eq!(c3["san"], "c3");
+        assert_eq!(c3["path"], json!([[1, 0], [1, 0]]));
+        assert_eq!(c3["view"], 3);
+        assert_eq!(c3["comment"], "Alapin");
+    }
+}
@@ -0,0 +1,133 @@
+// Panning and zooming the board, for when it's bigger than the window: on a small screen, or for
+// variants on bigger boards. The mouse wheel zooms in and out around the pointer, and dragging a
+// piece near an edge of the window scrolls the board that way. The board snaps to the window's
+// edges, so it's never scrolled or zoomed out past them and no space is wasted showing nothing. The
+// board is drawn through camera, so input only has to turn screen positions into board positions
+// with to_board.
+
+use macroquad::prelude::*;
+
+// How close to an edge, in screen pixels, a dragged piece has to be to scroll the board.
+const EDGE: f32 = 40.0;
+// Screen pixels per second.
+const SCROLL_SPEED: f32 = 600.0;
+const MAX_ZOOM: f32 = 3.0;
+const ZOOM_STEP: f32 = 1.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    // Screen pixels per board pixel.
+    pub zoom: f32,
+    // The point of the board at the window's top left corner.
+    pub offset: Vec2,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            zoom: 1.0,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+impl Viewport {
+    // Where the screen position pos is on the board.
+    pub fn to_board(self, pos: (f32, f32)) -> (f32, f32) {
+        (
+            self.offset.x + pos.0 / self.zoom,
+            self.offset.y + pos.1 / self.zoom,
+        )
+    }
+
+    // The camera showing the board on a screen of the given size.
+    pub fn camera(&self, screen: Vec2) -> Camera2D {
+        let size = screen / self.zoom;
+        Camera2D::from_display_rect(Rect::new(self.offset.x, self.offset.y, size.x, size.y))
+    }
+
+    // Zooms in one step for wheel > 0 and out for wheel < 0, keeping the point under the screen
+    // position at where it is.
+    pub fn zoom_at(&mut self, wheel: f32, at: (f32, f32), screen: Vec2, board: f32) {
+        if wheel == 0.0 {
+            return;
+        }
+        let (x, y) = self.to_board(at);
+        let step = if wheel > 0.0 {
+            ZOOM_STEP
+        } else {
+            1.0 / ZOOM_STEP
+        };
+        self.zoom = (self.zoom * step).clamp(min_zoom(screen, board), MAX_ZOOM);
+        self.offset = vec2(x - at.0 / self.zoom, y - at.1 / self.zoom);
+        self.snap(screen, board);
+    }
+
+    // Scrolls towards the edges the screen position pos is near, for dt seconds.
+    pub fn auto_scroll(&mut self, pos: (f32, f32), screen: Vec2, board: f32, dt: f32) {
+        let direction = |p: f32, size: f32| {
+            if p < EDGE {
+                -1.0
+            } else if p > size - EDGE {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        let step = SCROLL_SPEED * dt / self.zoom;
+        self.offset.x += direction(pos.0, screen.x) * step;
+        self.offset.y += direction(pos.1, screen.y) * step;
+        self.snap(screen, board);
+    }
+
+    // Keeps the board, board pixels square, covering as much of the screen as it can: zoomed in
+    // enough to fill it, and not scrolled past its edges.
+    pub fn snap(&mut self, screen: Vec2, board: f32) {
+        self.zoom = self.zoom.clamp(min_zoom(screen, board), MAX_ZOOM);
+        let visible = screen / self.zoom;
+        self.offset.x = self.offset.x.clamp(0.0, (board - visible.x).max(0.0));
+        self.offset.y = self.offset.y.clamp(0.0, (board - visible.y).max(0.0));
+    }
+}
+
+// Zoomed out any further, the board would leave part of the screen empty. A board that's smaller
+// than the screen at its own size isn't blown up to fill it, though.
+fn min_zoom(screen: Vec2, board: f32) -> f32 {
+    (screen.x.max(screen.y) / board).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewport() {
+        let (screen, board) = (vec2(400.0, 300.0), 720.0);
+        let mut v = Viewport::default();
+        v.snap(screen, board);
+        assert_eq!(v, Viewport::default());
+
+        // Zooming keeps the point under the pointer still.
+        let at = (100.0, 50.0);
+        let before = v.to_board(at);
+        v.zoom_at(1.0, at, screen, board);
+        assert_eq!(v.zoom, ZOOM_STEP);
+        let after = v.to_board(at);
+        assert!((before.0 - after.0).abs() < 1e-3 && (before.1 - after.1).abs() < 1e-3);
+
+        // Zooming out stops where the board still fills the screen, and snaps to its edges.
+        for _ in 0..20 {
+            v.zoom_at(-1.0, at, screen, board);
+        }
+        assert_eq!(v.zoom, 400.0 / 720.0);
+        assert_eq!(v.offset, Vec2::ZERO);
+
+        // Dragging near the bottom right corner scrolls down and right, up to the board's edges.
+        let mut v = Viewport::default();
+        v.auto_scroll((390.0, 290.0), screen, board, 0.1);
+        assert_eq!(v.offset, vec2(60.0, 60.0));
+        v.auto_scroll((390.0, 150.0), screen, board, 10.0);
+        assert_eq!(v.offset, vec2(320.0, 60.0));
+        assert_eq!(v.to_board((400.0, 0.0)), (720.0, 60.0));
+    }
+}
@@ -0,0 +1,186 @@
+// Named regions of the board, e.g. "center" or "white-camp", so rules can depend on where a piece
+// is without hardcoding squares: turn and movement rules are given the rules' zones, constraint
+// rules have them as rules.zones, and JS plugins find them in the rule set. A zone can also be
+// shaded on the board, for variants where players need to see it, like a hill to reach.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::{notation::square_name, prelude::*};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Zone {
+    // A bit per square, as square_bit has them.
+    pub squares: u64,
+    // Whether the board shows it.
+    pub shade: bool,
+}
+
+impl Zone {
+    pub fn contains(&self, r: usize, c: usize) -> bool {
+        std_in_bounds(r as i32, c as i32) && self.squares & square_bit(r, c) != 0
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Zones(BTreeMap<String, Zone>);
+
+impl Zones {
+    // The center and each side's half of the board, none of them shaded.
+    pub fn defaults() -> Zones {
+        let ranks = |ranks: std::ops::RangeInclusive<usize>| {
+            ranks
+                .flat_map(|r| (1..=8).map(move |c| square_bit(r, c)))
+                .sum()
+        };
+        let mut zones = Zones::default();
+        let center = [(4, 4), (4, 5), (5, 4), (5, 5)];
+        zones.insert(
+            "center",
+            Zone {
+                squares: center.iter().map(|&(r, c)| square_bit(r, c)).sum(),
+                shade: false,
+            },
+        );
+        for (name, squares) in [("white-camp", ranks(1..=4)), ("black-camp", ranks(5..=8))] {
+            zones.insert(
+                name,
+                Zone {
+                    squares,
+                    shade: false,
+                },
+            );
+        }
+        zones
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Zone> {
+        self.0.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, zone: Zone) {
+        self.0.insert(name.to_string(), zone);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Zone)> {
+        self.0.iter().map(|(n, z)| (n.as_str(), z))
+    }
+
+    // Whether the square is in the zone named name. No square is in a zone the rules don't have.
+    pub fn contains(&self, name: &str, r: usize, c: usize) -> bool {
+        self.get(name).is_some_and(|z| z.contains(r, c))
+    }
+
+    // The names of the zones the square is in.
+    pub fn at(&self, r: usize, c: usize) -> impl Iterator<Item = &str> {
+        self.iter()
+            .filter(move |(_, z)| z.contains(r, c))
+            .map(|(n, _)| n)
+    }
+
+    // The squares of the zones the board shows.
+    pub fn shaded(&self) -> u64 {
+        self.0
+            .values()
+            .filter(|z| z.shade)
+            .fold(0, |squares, z| squares | z.squares)
+    }
+
+    // For rule sets: {"center": {"squares": ["d4", "e4", "d5", "e5"], "shade": false}, ...}
+    pub fn to_json(&self) -> Value {
+        let mut zones = Map::new();
+        for (n, z) in self.iter() {
+            let squares: Vec<_> = bit_squares(z.squares)
+                .map(|(r, c)| square_name(r as u8, c as u8))
+                .collect();
+            zones.insert(
+                n.to_string(),
+                json!({ "squares": squares, "shade": z.shade }),
+            );
+        }
+        Value::Object(zones)
+    }
+
+    pub fn from_json(v: &Value) -> Result<Zones, String> {
+        let zones = v
+            .as_object()
+            .ok_or_else(|| format!("invalid zones: {}", v))?;
+        let mut imported = Zones::default();
+        for (n, z) in zones {
+            let invalid = || format!("invalid zone {}: {}", n, z);
+            let mut squares = 0;
+            for s in z["squares"].as_array().ok_or_else(invalid)? {
+                let (r, c) = s.as_str().and_then(parse_square).ok_or_else(invalid)?;
+                squares |= square_bit(r, c);
+            }
+            let shade = z["shade"].as_bool().unwrap_or(false);
+            imported.insert(n, Zone { squares, shade });
+        }
+        Ok(imported)
+    }
+}
+
+// E.g. (4, 5) for "e4", if it's on the board.
+fn parse_square(s: &str) -> Option<(usize, usize)> {
+    match s.as_bytes() {
+        &[f @ b'a'..=b'h', r @ b'1'..=b'8'] => Some(((r - b'0') as usize, (f - b'a' + 1) as usize)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zones() {
+        let mut zones = Zones::defaults();
+        assert!(zones.contains("center", 4, 5));
+        assert!(!zones.contains("center", 3, 5));
+        assert!(zones.contains("white-camp", 4, 1));
+        assert!(!zones.contains("white-camp", 5, 1));
+        assert!(!zones.contains("hill", 4, 5));
+        assert!(!zones.contains("center", 0, 9));
+        assert_eq!(zones.at(5, 4).collect::<Vec<_>>(), ["black-camp", "center"]);
+        assert_eq!(zones.shaded(), 0);
+
+        let hill = Zone {
+            squares: square_bit(4, 4) | square_bit(5, 5),
+            shade: true,
+        };
+        zones.insert("hill", hill);
+        assert_eq!(zones.shaded(), hill.squares);
+        let json = zones.to_json();
+        assert_eq!(
+            json["hill"],
+            json!({"squares": ["d4", "e5"], "shade": true})
+        );
+        assert_eq!(Zones::from_json(&json), Ok(zones));
+
+        assert!(Zones::from_json(&json!({"hill": {"squares": ["i1"]}})).is_err());
+        assert!(Zones::from_json(&json!({"hill": {}})).is_err());
+        assert!(Zones::from_json(&json!(["d4"])).is_err());
+
+        // A king on the edge of its camp can only move along it.
+        let mut rules = Rules::defaults();
+        rules
+            .move_constraint_rules
+            .get_mut("kings-stay-in-camp")
+            .unwrap()
+            .active = true;
+        let (pp, gd) = crate::notation::parse_fen("4k3/8/8/8/4K3/8/8/8 w - - 0 1").unwrap();
+        let king = Piece {
+            row: 4,
+            col: 5,
+            name: b'K',
+        };
+        let rows: Vec<_> = rules
+            .allowed_moves(king, &pp, gd)
+            .iter()
+            .map(|m| m.dst.row)
+            .collect();
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().all(|&r| r <= 4));
+    }
+}